@@ -0,0 +1,69 @@
+//! Merge and aggregation throughput on synthetic files of increasing size.
+//!
+//! ```bash
+//! cargo bench --bench analysis
+//! ```
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use spaa::analysis::CallTree;
+use spaa::merge::{MergeOptions, merge};
+use spaa::stats::compute_stats;
+use spaa_parse::SpaaFile;
+use spaa_parse::synth::{SymbolPool, SynthConfig, generate};
+use std::hint::black_box;
+use std::io::Cursor;
+
+/// Stack counts for the small, medium and large inputs.
+const SIZES: &[usize] = &[1_000, 10_000, 100_000];
+
+fn profile(stacks: usize, seed: u64) -> SpaaFile {
+    generate(&SynthConfig {
+        seed,
+        stacks,
+        symbols: SymbolPool::synthetic(stacks.max(500) / 2, 8),
+        ..Default::default()
+    })
+}
+
+fn merge_files(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge");
+    for &stacks in SIZES {
+        let a = profile(stacks, 1);
+        let b = profile(stacks, 2);
+        group.throughput(Throughput::Elements(2 * stacks as u64));
+        group.bench_function(BenchmarkId::from_parameter(stacks), |bench| {
+            bench.iter(|| {
+                merge(&[("a", black_box(&a)), ("b", &b)], MergeOptions::default()).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn call_tree(c: &mut Criterion) {
+    let mut group = c.benchmark_group("call_tree");
+    for &stacks in SIZES {
+        let file = profile(stacks, 1);
+        group.throughput(Throughput::Elements(stacks as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(stacks), &file, |b, file| {
+            b.iter(|| CallTree::build(black_box(file), "cycles", "period"))
+        });
+    }
+    group.finish();
+}
+
+fn stats(c: &mut Criterion) {
+    let mut group = c.benchmark_group("stats");
+    for &stacks in SIZES {
+        let mut data = Vec::new();
+        profile(stacks, 1).write(&mut data).unwrap();
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(stacks), &data, |b, data| {
+            b.iter(|| compute_stats(Cursor::new(black_box(data))).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, merge_files, call_tree, stats);
+criterion_main!(benches);
@@ -0,0 +1,224 @@
+//! Allocation rate over time, from the windows of an allocation event.
+//!
+//! Heap timelines converted by `chrome_to_spaa` carry one window per
+//! interval between timeline samples. Following a stack across those
+//! windows shows whether it allocates once and stops or keeps allocating
+//! objects that survive, the usual shape of a leak.
+
+use serde::Serialize;
+use spaa_parse::{FrameOrder, SpaaFile, Window};
+use std::collections::HashMap;
+
+/// Allocations of one stack during one window.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RatePoint {
+    pub start: f64,
+    pub end: f64,
+    /// Amount of the metric allocated in the window.
+    pub value: u64,
+    /// `value` per window time unit, or 0 for an empty window.
+    pub rate: f64,
+}
+
+/// Allocation rate of one stack across every window.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AllocationRate {
+    pub stack_id: String,
+    /// Function names from the outermost caller down to the allocation
+    /// site.
+    pub functions: Vec<String>,
+    /// Amount of the metric allocated across all windows.
+    pub total: u64,
+    /// One point per window, in time order.
+    pub points: Vec<RatePoint>,
+    /// Least-squares slope of the running total against time, per window
+    /// time unit.
+    pub slope: f64,
+    /// How steadily the stack keeps allocating, from 0 to 1: the share of
+    /// windows it allocated in, times the R² of a line fitted to its
+    /// running total. Steady growth scores near 1 and a one-off burst near
+    /// 0.
+    pub growth_score: f64,
+}
+
+/// The `n` stacks of `event` that allocated the most `metric` across the
+/// file's windows, heaviest first.
+///
+/// Every stack gets a point for every window, so the series of different
+/// stacks line up.
+pub fn allocation_rates(
+    file: &SpaaFile,
+    event: &str,
+    metric: &str,
+    n: usize,
+) -> Vec<AllocationRate> {
+    let mut windows: Vec<&Window> = file.windows.iter().collect();
+    windows.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+    let mut series: HashMap<&str, Vec<u64>> = HashMap::new();
+    for (i, window) in windows.iter().enumerate() {
+        for entry in &window.by_stack {
+            let in_event = file
+                .stacks
+                .get(&entry.stack_id)
+                .is_some_and(|s| s.context.event == event);
+            let value = entry
+                .weights
+                .iter()
+                .find(|w| w.metric == metric)
+                .map_or(0, |w| w.value);
+            if in_event && value > 0 {
+                series
+                    .entry(&entry.stack_id)
+                    .or_insert_with(|| vec![0; windows.len()])[i] += value;
+            }
+        }
+    }
+
+    let mut ranked: Vec<(&str, Vec<u64>, u64)> = series
+        .into_iter()
+        .map(|(id, values)| {
+            let total = values.iter().sum();
+            (id, values, total)
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(b.0)));
+    ranked.truncate(n);
+
+    ranked
+        .into_iter()
+        .map(|(stack_id, values, total)| {
+            let points: Vec<RatePoint> = windows
+                .iter()
+                .zip(&values)
+                .map(|(window, &value)| {
+                    let duration = window.end - window.start;
+                    RatePoint {
+                        start: window.start,
+                        end: window.end,
+                        value,
+                        rate: if duration > 0.0 {
+                            value as f64 / duration
+                        } else {
+                            0.0
+                        },
+                    }
+                })
+                .collect();
+            let (slope, r_squared) = fit_running_total(&points);
+            let active = values.iter().filter(|&&v| v > 0).count() as f64 / values.len() as f64;
+            AllocationRate {
+                stack_id: stack_id.to_string(),
+                functions: functions(file, stack_id),
+                total,
+                points,
+                slope,
+                growth_score: active * r_squared,
+            }
+        })
+        .collect()
+}
+
+/// Slope and R² of a least-squares line through the running total at the
+/// end of each window.
+fn fit_running_total(points: &[RatePoint]) -> (f64, f64) {
+    if points.len() < 2 {
+        return (0.0, 0.0);
+    }
+    let mut running = 0.0;
+    let xy: Vec<(f64, f64)> = points
+        .iter()
+        .map(|p| {
+            running += p.value as f64;
+            (p.end, running)
+        })
+        .collect();
+    let len = xy.len() as f64;
+    let mean_x = xy.iter().map(|(x, _)| x).sum::<f64>() / len;
+    let mean_y = xy.iter().map(|(_, y)| y).sum::<f64>() / len;
+    let (mut sxx, mut sxy, mut syy) = (0.0, 0.0, 0.0);
+    for (x, y) in &xy {
+        sxx += (x - mean_x) * (x - mean_x);
+        sxy += (x - mean_x) * (y - mean_y);
+        syy += (y - mean_y) * (y - mean_y);
+    }
+    if sxx == 0.0 || syy == 0.0 {
+        return (0.0, 0.0);
+    }
+    (sxy / sxx, sxy * sxy / (sxx * syy))
+}
+
+fn functions(file: &SpaaFile, stack_id: &str) -> Vec<String> {
+    let Some(stack) = file.stacks.get(stack_id) else {
+        return Vec::new();
+    };
+    let mut functions: Vec<String> = stack
+        .frames
+        .iter()
+        .map(|id| {
+            file.resolve_frame(*id)
+                .map_or_else(|| format!("<frame {}>", id), |f| f.func.clone())
+        })
+        .collect();
+    if file.header.frame_order == FrameOrder::LeafToRoot {
+        functions.reverse();
+    }
+    functions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// `leak` allocates 100 bytes in each of four windows; `burst`
+    /// allocates 400 bytes in the first one only.
+    fn sample_file() -> SpaaFile {
+        let mut lines = vec![
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"chrome-heaptimeline","frame_order":"leaf_to_root","events":[{"name":"allocation","kind":"allocation","sampling":{"mode":"event","primary_metric":"alloc_bytes"}}]}"#.to_string(),
+            r#"{"type":"dso","id":1,"name":"app.js","is_kernel":false}"#.to_string(),
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#.to_string(),
+            r#"{"type":"frame","id":2,"func":"leak","dso":1}"#.to_string(),
+            r#"{"type":"frame","id":3,"func":"burst","dso":1}"#.to_string(),
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"allocation"},"weights":[{"metric":"alloc_bytes","value":400}]}"#.to_string(),
+            r#"{"type":"stack","id":"0x2","frames":[3,1],"context":{"event":"allocation"},"weights":[{"metric":"alloc_bytes","value":400}]}"#.to_string(),
+        ];
+        for i in 0..4 {
+            let burst = if i == 0 {
+                r#",{"stack_id":"0x2","weights":[{"metric":"alloc_bytes","value":400}]}"#
+            } else {
+                ""
+            };
+            lines.push(format!(
+                r#"{{"type":"window","id":"w{}","start":{},"end":{},"unit":"seconds","by_stack":[{{"stack_id":"0x1","weights":[{{"metric":"alloc_bytes","value":100}}]}}{}]}}"#,
+                i + 1,
+                i,
+                i + 1,
+                burst
+            ));
+        }
+        SpaaFile::parse(Cursor::new(lines.join("\n"))).unwrap()
+    }
+
+    #[test]
+    fn steady_allocation_scores_higher_than_a_burst() {
+        let rates = allocation_rates(&sample_file(), "allocation", "alloc_bytes", 10);
+
+        let leak = rates.iter().find(|r| r.stack_id == "0x1").unwrap();
+        let burst = rates.iter().find(|r| r.stack_id == "0x2").unwrap();
+        assert_eq!(leak.functions, vec!["main", "leak"]);
+        assert!((leak.slope - 100.0).abs() < 1e-9);
+        assert!((leak.growth_score - 1.0).abs() < 1e-9);
+        assert!(burst.growth_score < 0.25);
+    }
+
+    #[test]
+    fn every_stack_has_a_point_per_window() {
+        let rates = allocation_rates(&sample_file(), "allocation", "alloc_bytes", 10);
+
+        let burst = rates.iter().find(|r| r.stack_id == "0x2").unwrap();
+        let values: Vec<u64> = burst.points.iter().map(|p| p.value).collect();
+        assert_eq!(values, vec![400, 0, 0, 0]);
+        assert_eq!(burst.points[0].rate, 400.0);
+    }
+}
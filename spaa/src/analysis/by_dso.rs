@@ -0,0 +1,219 @@
+//! Weight rolled up per DSO (executable or shared library).
+
+use super::stack_weight;
+use serde::Serialize;
+use spaa_parse::{FrameOrder, SpaaFile};
+use std::collections::{HashMap, HashSet};
+
+/// Functions listed per DSO in a [`DsoRollup`].
+pub const TOP_FUNCTIONS_PER_DSO: usize = 5;
+
+/// Name used for frames whose DSO is not in the dictionary.
+pub const UNKNOWN_DSO: &str = "[unknown]";
+
+/// One DSO's share of an event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DsoWeight {
+    pub dso: String,
+    pub is_kernel: bool,
+    /// Weight of stacks whose leaf frame is in the DSO.
+    pub self_weight: u64,
+    /// `self_weight` as a fraction of the event's total weight.
+    pub self_share: f64,
+    /// Weight of stacks with any frame in the DSO.
+    pub total_weight: u64,
+    /// `total_weight` as a fraction of the event's total weight.
+    pub total_share: f64,
+    /// The DSO's heaviest functions by self weight, then total weight.
+    pub top_functions: Vec<DsoFunction>,
+}
+
+/// A function's weight within its DSO.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DsoFunction {
+    pub function: String,
+    pub self_weight: u64,
+    pub total_weight: u64,
+}
+
+/// Weight per DSO for one event, from [`by_dso`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DsoRollup {
+    pub event: String,
+    pub metric: String,
+    /// Total weight of the event.
+    pub total: u64,
+    /// Weight of stacks whose leaf frame is in a kernel DSO.
+    pub kernel_weight: u64,
+    /// Weight of stacks whose leaf frame is in a user-space DSO, or is
+    /// unknown.
+    pub user_weight: u64,
+    /// DSOs by descending self weight, then total weight.
+    pub dsos: Vec<DsoWeight>,
+}
+
+#[derive(Default)]
+struct Tally<'a> {
+    is_kernel: bool,
+    self_weight: u64,
+    total_weight: u64,
+    /// Self and total weight per function.
+    functions: HashMap<&'a str, (u64, u64)>,
+}
+
+/// Roll up `event`'s weight, by `metric`, per DSO.
+///
+/// DSOs are matched by name, so a library that appears under several IDs
+/// (as in merged files) is counted once. A stack counts toward a DSO's and
+/// a function's total weight once, however many of its frames they have.
+pub fn by_dso(file: &SpaaFile, event: &str, metric: &str) -> DsoRollup {
+    let mut tallies: HashMap<&str, Tally> = HashMap::new();
+    let mut total = 0;
+    let mut kernel_weight = 0;
+    for stack in file.stacks_for_event(event) {
+        let weight = stack_weight(stack, metric);
+        if weight == 0 {
+            continue;
+        }
+        total += weight;
+
+        let frame_dso = |id: u64| {
+            let frame = file.resolve_frame(id)?;
+            let dso = file.dsos.get(&frame.dso);
+            Some((
+                frame.func.as_str(),
+                dso.map_or(UNKNOWN_DSO, |d| d.name.as_str()),
+                dso.is_some_and(|d| d.is_kernel),
+            ))
+        };
+        let leaf = match file.header.frame_order {
+            FrameOrder::LeafToRoot => stack.frames.first(),
+            FrameOrder::RootToLeaf => stack.frames.last(),
+        };
+        if let Some((func, dso, is_kernel)) = leaf.and_then(|&id| frame_dso(id)) {
+            let tally = tallies.entry(dso).or_default();
+            tally.self_weight += weight;
+            tally.functions.entry(func).or_default().0 += weight;
+            if is_kernel {
+                kernel_weight += weight;
+            }
+        }
+
+        let mut seen_dsos = HashSet::new();
+        let mut seen_functions = HashSet::new();
+        for (func, dso, is_kernel) in stack.frames.iter().filter_map(|&id| frame_dso(id)) {
+            let tally = tallies.entry(dso).or_default();
+            tally.is_kernel |= is_kernel;
+            if seen_dsos.insert(dso) {
+                tally.total_weight += weight;
+            }
+            if seen_functions.insert((dso, func)) {
+                tally.functions.entry(func).or_default().1 += weight;
+            }
+        }
+    }
+
+    let share = |w: u64| w as f64 / total.max(1) as f64;
+    let mut dsos: Vec<DsoWeight> = tallies
+        .into_iter()
+        .map(|(dso, tally)| {
+            let mut functions: Vec<DsoFunction> = tally
+                .functions
+                .into_iter()
+                .map(|(function, (self_weight, total_weight))| DsoFunction {
+                    function: function.to_string(),
+                    self_weight,
+                    total_weight,
+                })
+                .collect();
+            functions.sort_by(|a, b| {
+                (b.self_weight, b.total_weight)
+                    .cmp(&(a.self_weight, a.total_weight))
+                    .then(a.function.cmp(&b.function))
+            });
+            functions.truncate(TOP_FUNCTIONS_PER_DSO);
+            DsoWeight {
+                dso: dso.to_string(),
+                is_kernel: tally.is_kernel,
+                self_weight: tally.self_weight,
+                self_share: share(tally.self_weight),
+                total_weight: tally.total_weight,
+                total_share: share(tally.total_weight),
+                top_functions: functions,
+            }
+        })
+        .collect();
+    dsos.sort_by(|a, b| {
+        (b.self_weight, b.total_weight)
+            .cmp(&(a.self_weight, a.total_weight))
+            .then(a.dso.cmp(&b.dso))
+    });
+
+    DsoRollup {
+        event: event.to_string(),
+        metric: metric.to_string(),
+        total,
+        kernel_weight,
+        user_weight: total - kernel_weight,
+        dsos,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"dso","id":2,"name":"/lib/libc.so.6","is_kernel":false}"#,
+            r#"{"type":"dso","id":3,"name":"[kernel.kallsyms]","is_kernel":true}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"memcpy","dso":2}"#,
+            r#"{"type":"frame","id":4,"func":"write","dso":2}"#,
+            r#"{"type":"frame","id":5,"func":"sys_write","dso":3}"#,
+            r#"{"type":"stack","id":"0x1","frames":[3,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":50}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":30}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[5,4,3,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":20}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn weights_roll_up_per_dso_with_a_kernel_split() {
+        let rollup = by_dso(&sample_file(), "cycles", "period");
+
+        assert_eq!(rollup.total, 100);
+        assert_eq!((rollup.kernel_weight, rollup.user_weight), (20, 80));
+        let dsos: Vec<_> = rollup
+            .dsos
+            .iter()
+            .map(|d| (d.dso.as_str(), d.self_weight, d.total_weight))
+            .collect();
+        assert_eq!(
+            dsos,
+            vec![
+                ("/lib/libc.so.6", 50, 70),
+                ("/usr/bin/app", 30, 100),
+                ("[kernel.kallsyms]", 20, 20),
+            ]
+        );
+        assert!(rollup.dsos[2].is_kernel);
+    }
+
+    #[test]
+    fn each_dso_lists_its_top_functions() {
+        let rollup = by_dso(&sample_file(), "cycles", "period");
+
+        let libc: Vec<_> = rollup.dsos[0]
+            .top_functions
+            .iter()
+            .map(|f| (f.function.as_str(), f.self_weight, f.total_weight))
+            .collect();
+        assert_eq!(libc, vec![("memcpy", 50, 70), ("write", 0, 20)]);
+    }
+}
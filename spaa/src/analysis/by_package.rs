@@ -0,0 +1,104 @@
+//! Weight rolled up per package, crate or module.
+
+use super::roll_up;
+use crate::packages::PackageMapper;
+use serde::Serialize;
+use spaa_parse::SpaaFile;
+use std::collections::HashMap;
+
+/// One package's share of an event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PackageWeight {
+    pub package: String,
+    /// Weight of stacks whose leaf frame is in the package.
+    pub self_weight: u64,
+    /// `self_weight` as a fraction of the event's total weight.
+    pub self_share: f64,
+    /// Weight of stacks with any frame in the package.
+    pub total_weight: u64,
+    /// `total_weight` as a fraction of the event's total weight.
+    pub total_share: f64,
+}
+
+/// Weight per package for one event, from [`by_package`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PackageRollup {
+    pub event: String,
+    pub metric: String,
+    /// Total weight of the event.
+    pub total: u64,
+    /// Packages by descending self weight, then total weight.
+    pub packages: Vec<PackageWeight>,
+}
+
+/// Roll up `event`'s weight, by `metric`, per package as attributed by
+/// `mapper`.
+///
+/// A stack counts toward a package's total weight once, however many of
+/// its frames are in it.
+pub fn by_package(
+    file: &SpaaFile,
+    event: &str,
+    metric: &str,
+    mapper: &PackageMapper,
+) -> PackageRollup {
+    let packages: HashMap<u64, String> = file
+        .frames
+        .values()
+        .map(|frame| (frame.id, mapper.package(file, frame)))
+        .collect();
+    let package_of = |id: u64| packages.get(&id).map_or("[unknown]", String::as_str);
+
+    let (total, weights) = roll_up(file, event, metric, package_of);
+    let share = |w: u64| w as f64 / total.max(1) as f64;
+    let packages = weights
+        .into_iter()
+        .map(|(package, self_weight, total_weight)| PackageWeight {
+            package: package.to_string(),
+            self_weight,
+            self_share: share(self_weight),
+            total_weight,
+            total_share: share(total_weight),
+        })
+        .collect();
+
+    PackageRollup {
+        event: event.to_string(),
+        metric: metric.to_string(),
+        total,
+        packages,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packages::PackageMap;
+    use std::io::Cursor;
+
+    #[test]
+    fn weights_roll_up_per_package() {
+        let data = [
+            r#"{"type":"header","format":"perf","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"app::main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"serde_json::de::from_str","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"serde_json::read::parse_str","dso":1}"#,
+            r#"{"type":"frame","id":4,"func":"memcpy","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[3,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":70}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[4,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":30}]}"#,
+        ]
+        .join("\n");
+        let file = SpaaFile::parse(Cursor::new(data)).unwrap();
+        let mapper = PackageMapper::new(&PackageMap::default()).unwrap();
+        let rollup = by_package(&file, "cycles", "period", &mapper);
+
+        let packages: Vec<_> = rollup
+            .packages
+            .iter()
+            .map(|p| (p.package.as_str(), p.self_weight, p.total_weight))
+            .collect();
+        // `memcpy` has no package of its own, so it counts toward its DSO.
+        assert_eq!(packages, vec![("serde_json", 70, 70), ("app", 30, 100)]);
+    }
+}
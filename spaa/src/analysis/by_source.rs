@@ -0,0 +1,149 @@
+//! Weight rolled up per source file or directory.
+
+use super::roll_up;
+use serde::Serialize;
+use spaa_parse::SpaaFile;
+use spaa_parse::srcline::SrcLine;
+use std::collections::HashMap;
+
+/// Name used for frames without a known source file.
+pub const UNKNOWN_SOURCE: &str = "[unknown]";
+
+/// What [`by_source`] rolls weight up to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SourceKey {
+    /// The source file.
+    #[default]
+    File,
+    /// The directory holding the source file, cut to its first `depth`
+    /// components when given (`crates/core` for depth 2), so cost can be
+    /// attributed to packages and modules in a monorepo.
+    Directory { depth: Option<usize> },
+}
+
+/// One file's or directory's share of an event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceWeight {
+    pub path: String,
+    /// Weight of stacks whose leaf frame is in the path.
+    pub self_weight: u64,
+    /// `self_weight` as a fraction of the event's total weight.
+    pub self_share: f64,
+    /// Weight of stacks with any frame in the path.
+    pub total_weight: u64,
+    /// `total_weight` as a fraction of the event's total weight.
+    pub total_share: f64,
+}
+
+/// Weight per source file or directory for one event, from [`by_source`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceRollup {
+    pub event: String,
+    pub metric: String,
+    /// Total weight of the event.
+    pub total: u64,
+    /// Paths by descending self weight, then total weight.
+    pub sources: Vec<SourceWeight>,
+}
+
+/// Roll up `event`'s weight, by `metric`, per source file or directory of
+/// each frame's `srcline`.
+///
+/// Frames without a srcline, or with an unknown file, count toward
+/// [`UNKNOWN_SOURCE`]. A stack counts toward a path's total weight once,
+/// however many of its frames are in it.
+pub fn by_source(file: &SpaaFile, event: &str, metric: &str, key: SourceKey) -> SourceRollup {
+    let paths: HashMap<u64, String> = file
+        .frames
+        .values()
+        .filter_map(|frame| {
+            let src = SrcLine::parse(frame.srcline.as_deref()?)?;
+            let path = match key {
+                SourceKey::File => src.file.to_string(),
+                SourceKey::Directory { depth } => src.directory(depth),
+            };
+            Some((frame.id, path))
+        })
+        .collect();
+    let path_of = |id: u64| paths.get(&id).map_or(UNKNOWN_SOURCE, String::as_str);
+
+    let (total, weights) = roll_up(file, event, metric, path_of);
+    let share = |w: u64| w as f64 / total.max(1) as f64;
+    let sources = weights
+        .into_iter()
+        .map(|(path, self_weight, total_weight)| SourceWeight {
+            path: path.to_string(),
+            self_weight,
+            self_share: share(self_weight),
+            total_weight,
+            total_share: share(total_weight),
+        })
+        .collect();
+
+    SourceRollup {
+        event: event.to_string(),
+        metric: metric.to_string(),
+        total,
+        sources,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1,"srcline":"crates/app/src/main.rs:3"}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1,"srcline":"crates/core/src/parse.rs:10:5"}"#,
+            r#"{"type":"frame","id":3,"func":"lex","dso":1,"srcline":"crates/core/src/lex.rs:7"}"#,
+            r#"{"type":"frame","id":4,"func":"memcpy","dso":1,"srcline":"??:0"}"#,
+            r#"{"type":"stack","id":"0x1","frames":[3,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":50}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":30}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[4,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":20}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    fn weights(rollup: &SourceRollup) -> Vec<(&str, u64, u64)> {
+        rollup
+            .sources
+            .iter()
+            .map(|s| (s.path.as_str(), s.self_weight, s.total_weight))
+            .collect()
+    }
+
+    #[test]
+    fn weights_roll_up_per_file() {
+        let rollup = by_source(&sample_file(), "cycles", "period", SourceKey::File);
+
+        assert_eq!(
+            weights(&rollup),
+            vec![
+                ("crates/core/src/lex.rs", 50, 50),
+                ("crates/core/src/parse.rs", 30, 80),
+                (UNKNOWN_SOURCE, 20, 20),
+                ("crates/app/src/main.rs", 0, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn directory_prefixes_merge_files_once_per_stack() {
+        let key = SourceKey::Directory { depth: Some(2) };
+        let rollup = by_source(&sample_file(), "cycles", "period", key);
+
+        assert_eq!(
+            weights(&rollup),
+            vec![
+                ("crates/core", 80, 80),
+                (UNKNOWN_SOURCE, 20, 20),
+                ("crates/app", 0, 100),
+            ]
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! Top-down and bottom-up call tree aggregation.
+
+use super::stack_weight;
+use crate::limits::{LimitError, Limits};
+use spaa_parse::{FrameOrder, SpaaFile, Stack};
+use std::collections::HashMap;
+
+/// Direction a [`CallTree`] aggregates stacks in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TreeMode {
+    /// Callers above callees: the root's children are the outermost frames.
+    #[default]
+    TopDown,
+    /// Callees above callers: the root's children are the leaf frames,
+    /// weighted by their self weight, and each node's children are its
+    /// callers. Answers "who calls `memcpy`" directly.
+    BottomUp,
+}
+
+/// A node in a [`CallTree`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallTreeNode {
+    /// Frame ID for this node, or `None` for the synthetic root.
+    pub frame: Option<u64>,
+    /// Index of the parent node, or `None` for the root.
+    pub parent: Option<usize>,
+    /// Weight of all stacks passing through this node. In a bottom-up tree,
+    /// the weight of stacks whose leaf-most frames are the path to it.
+    pub inclusive: u64,
+    /// Weight of stacks ending at this node: at their leaf in a top-down
+    /// tree, at their outermost frame in a bottom-up one.
+    pub exclusive: u64,
+    /// Indices of child nodes.
+    pub children: Vec<usize>,
+}
+
+/// A call tree aggregated from the stacks of one event.
+///
+/// Node 0 is a synthetic root. In a [top-down](TreeMode::TopDown) tree its
+/// children are the outermost frames of each stack and stacks are walked
+/// from root to leaf; in a [bottom-up](TreeMode::BottomUp) one they are the
+/// leaf frames and stacks are walked from leaf to root. Either way the
+/// file's `frame_order` is taken into account.
+#[derive(Debug, Clone)]
+pub struct CallTree {
+    nodes: Vec<CallTreeNode>,
+    mode: TreeMode,
+}
+
+impl CallTree {
+    /// Build a top-down call tree from every stack of `event`, weighted by `metric`.
+    pub fn build(file: &SpaaFile, event: &str, metric: &str) -> Self {
+        Self::build_filtered(file, event, metric, |_| true)
+    }
+
+    /// Build a bottom-up (callee-rooted) call tree from every stack of
+    /// `event`, weighted by `metric`.
+    pub fn build_bottom_up(file: &SpaaFile, event: &str, metric: &str) -> Self {
+        Self::build_with(file, event, metric, TreeMode::BottomUp, |_| true)
+    }
+
+    /// Like [`CallTree::build`], but only include stacks accepted by `filter`
+    /// (e.g. a single thread).
+    pub fn build_filtered(
+        file: &SpaaFile,
+        event: &str,
+        metric: &str,
+        filter: impl Fn(&Stack) -> bool,
+    ) -> Self {
+        Self::build_with(file, event, metric, TreeMode::TopDown, filter)
+    }
+
+    /// Build a call tree in `mode` from the stacks of `event` accepted by
+    /// `filter`, weighted by `metric`.
+    pub fn build_with(
+        file: &SpaaFile,
+        event: &str,
+        metric: &str,
+        mode: TreeMode,
+        filter: impl Fn(&Stack) -> bool,
+    ) -> Self {
+        Self::build_limited(file, event, metric, mode, filter, &Limits::default())
+            .expect("default limits are never reached")
+    }
+
+    /// Like [`CallTree::build_with`], checking `limits` before each stack.
+    ///
+    /// A stack is added whole or not at all, so when the memory budget runs
+    /// out the error carries a consistent tree of the stacks added so far.
+    pub fn build_limited(
+        file: &SpaaFile,
+        event: &str,
+        metric: &str,
+        mode: TreeMode,
+        filter: impl Fn(&Stack) -> bool,
+        limits: &Limits,
+    ) -> Result<Self, LimitError<CallTree>> {
+        // A node, its entry in `child_index` and its parent's reference.
+        const NODE_BYTES: usize = std::mem::size_of::<CallTreeNode>()
+            + std::mem::size_of::<((usize, u64), usize)>()
+            + std::mem::size_of::<usize>();
+        let mut meter = limits.meter();
+
+        let mut tree = CallTree {
+            nodes: vec![CallTreeNode {
+                frame: None,
+                parent: None,
+                inclusive: 0,
+                exclusive: 0,
+                children: Vec::new(),
+            }],
+            mode,
+        };
+        let mut child_index: HashMap<(usize, u64), usize> = HashMap::new();
+
+        // Visit stacks in ID order so node numbering is deterministic.
+        let mut stacks: Vec<_> = file.stacks_for_event(event).filter(|s| filter(s)).collect();
+        stacks.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let in_order = matches!(
+            (file.header.frame_order, mode),
+            (FrameOrder::RootToLeaf, TreeMode::TopDown)
+                | (FrameOrder::LeafToRoot, TreeMode::BottomUp)
+        );
+        fn frames_of(stack: &Stack, in_order: bool) -> Box<dyn Iterator<Item = &u64> + '_> {
+            if in_order {
+                Box::new(stack.frames.iter())
+            } else {
+                Box::new(stack.frames.iter().rev())
+            }
+        }
+
+        for stack in stacks {
+            limits.check()?;
+            let weight = stack_weight(stack, metric);
+            if weight == 0 {
+                continue;
+            }
+
+            // Count the nodes this stack adds before adding any of them.
+            let mut existing = Some(0);
+            let mut new_nodes = 0;
+            for &frame_id in frames_of(stack, in_order) {
+                existing = existing.and_then(|i| child_index.get(&(i, frame_id)).copied());
+                if existing.is_none() {
+                    new_nodes += 1;
+                }
+            }
+            if let Err(e) = meter.charge((new_nodes * NODE_BYTES) as u64) {
+                return Err(e.with_partial(tree).into());
+            }
+
+            let frames = frames_of(stack, in_order);
+            let mut current = 0;
+            tree.nodes[0].inclusive += weight;
+            for &frame_id in frames {
+                current = *child_index.entry((current, frame_id)).or_insert_with(|| {
+                    let idx = tree.nodes.len();
+                    tree.nodes.push(CallTreeNode {
+                        frame: Some(frame_id),
+                        parent: Some(current),
+                        inclusive: 0,
+                        exclusive: 0,
+                        children: Vec::new(),
+                    });
+                    tree.nodes[current].children.push(idx);
+                    idx
+                });
+                tree.nodes[current].inclusive += weight;
+            }
+            tree.nodes[current].exclusive += weight;
+        }
+
+        Ok(tree)
+    }
+
+    /// Direction the tree was aggregated in.
+    pub fn mode(&self) -> TreeMode {
+        self.mode
+    }
+
+    /// The synthetic root node.
+    pub fn root(&self) -> &CallTreeNode {
+        &self.nodes[0]
+    }
+
+    /// Get a node by index.
+    pub fn node(&self, idx: usize) -> &CallTreeNode {
+        &self.nodes[idx]
+    }
+
+    /// All nodes in the tree; index 0 is the root.
+    pub fn nodes(&self) -> &[CallTreeNode] {
+        &self.nodes
+    }
+
+    /// Total weight of the tree (the root's inclusive weight).
+    pub fn total(&self) -> u64 {
+        self.nodes[0].inclusive
+    }
+
+    /// Frame IDs from the root down to `idx`, excluding the synthetic root.
+    /// In a bottom-up tree this is the leaf first, then its callers.
+    pub fn path_to(&self, idx: usize) -> Vec<u64> {
+        let mut path = Vec::new();
+        let mut current = Some(idx);
+        while let Some(i) = current {
+            let node = &self.nodes[i];
+            if let Some(frame) = node.frame {
+                path.push(frame);
+            }
+            current = node.parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Index of the child of `idx` with the highest inclusive weight.
+    ///
+    /// Ties are broken by the lowest node index.
+    pub fn heaviest_child(&self, idx: usize) -> Option<usize> {
+        self.nodes[idx].children.iter().copied().max_by(|&a, &b| {
+            self.nodes[a]
+                .inclusive
+                .cmp(&self.nodes[b].inclusive)
+                .then(b.cmp(&a))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file(frame_order: &str) -> SpaaFile {
+        let data = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}\n{}",
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"FRAME_ORDER","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#
+                .replace("FRAME_ORDER", frame_order),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"render","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":60}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[3,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":40}]}"#,
+        );
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn build_aggregates_shared_prefixes() {
+        let tree = CallTree::build(&sample_file("leaf_to_root"), "cycles", "period");
+
+        assert_eq!(tree.total(), 100);
+        assert_eq!(tree.root().children.len(), 1);
+
+        let main = tree.node(tree.root().children[0]);
+        assert_eq!(main.frame, Some(1));
+        assert_eq!(main.inclusive, 100);
+        assert_eq!(main.exclusive, 0);
+        assert_eq!(main.children.len(), 2);
+    }
+
+    #[test]
+    fn build_respects_root_to_leaf_order() {
+        let tree = CallTree::build(&sample_file("root_to_leaf"), "cycles", "period");
+
+        // Frames are now read as root first, so "parse" and "render" are roots.
+        assert_eq!(tree.root().children.len(), 2);
+    }
+
+    #[test]
+    fn heaviest_child_and_path() {
+        let tree = CallTree::build(&sample_file("leaf_to_root"), "cycles", "period");
+
+        let main = tree.heaviest_child(0).unwrap();
+        let parse = tree.heaviest_child(main).unwrap();
+        assert_eq!(tree.node(parse).inclusive, 60);
+        assert_eq!(tree.path_to(parse), vec![1, 2]);
+    }
+
+    #[test]
+    fn build_filtered_skips_rejected_stacks() {
+        let tree =
+            CallTree::build_filtered(&sample_file("leaf_to_root"), "cycles", "period", |stack| {
+                stack.id != "0x2"
+            });
+
+        assert_eq!(tree.total(), 60);
+        let main = tree.node(tree.root().children[0]);
+        assert_eq!(main.children.len(), 1);
+    }
+
+    #[test]
+    fn bottom_up_roots_the_tree_at_leaf_frames() {
+        for order in ["leaf_to_root", "root_to_leaf"] {
+            let file = sample_file(order);
+            let tree = CallTree::build_bottom_up(&file, "cycles", "period");
+            let leaves: Vec<_> = tree
+                .root()
+                .children
+                .iter()
+                .map(|&i| (tree.node(i).frame, tree.node(i).inclusive))
+                .collect();
+
+            if order == "leaf_to_root" {
+                assert_eq!(leaves, vec![(Some(2), 60), (Some(3), 40)]);
+                let parse = tree.root().children[0];
+                let caller = tree.node(parse).children[0];
+                assert_eq!(tree.path_to(caller), vec![2, 1]);
+                assert_eq!(tree.node(caller).exclusive, 60);
+            } else {
+                // Read root first, "main" is every stack's leaf.
+                assert_eq!(leaves, vec![(Some(1), 100)]);
+            }
+        }
+    }
+
+    #[test]
+    fn memory_budget_returns_partial_tree() {
+        let file = sample_file("leaf_to_root");
+        let node_bytes = std::mem::size_of::<CallTreeNode>()
+            + std::mem::size_of::<((usize, u64), usize)>()
+            + std::mem::size_of::<usize>();
+        // Room for the first stack's two nodes but not the second's one.
+        let limits = Limits::with_memory(2 * node_bytes as u64);
+        let result = CallTree::build_limited(
+            &file,
+            "cycles",
+            "period",
+            TreeMode::TopDown,
+            |_| true,
+            &limits,
+        );
+
+        let Err(LimitError::BudgetExceeded(e)) = result else {
+            panic!("expected the budget to run out");
+        };
+        assert_eq!(e.partial.total(), 60);
+        assert_eq!(e.partial.nodes().len(), 3);
+        assert_eq!(e.requested, node_bytes as u64);
+    }
+
+    #[test]
+    fn unknown_event_builds_empty_tree() {
+        let tree = CallTree::build(&sample_file("leaf_to_root"), "instructions", "period");
+
+        assert_eq!(tree.total(), 0);
+        assert!(tree.root().children.is_empty());
+    }
+}
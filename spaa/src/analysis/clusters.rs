@@ -0,0 +1,276 @@
+//! Similarity clustering of stacks.
+
+use super::stack_weight;
+use crate::limits::{BudgetExceeded, LimitError, Limits};
+use serde::Serialize;
+use spaa_parse::{FrameOrder, SpaaFile, Stack};
+use std::collections::HashSet;
+
+/// Options for [`cluster_stacks`].
+#[derive(Debug, Clone)]
+pub struct ClusterOptions {
+    /// Minimum Jaccard similarity (0.0–1.0) between a stack and a cluster's
+    /// representative for the stack to join that cluster.
+    pub min_similarity: f64,
+    /// Require members to share the representative's leaf frame.
+    pub same_leaf: bool,
+}
+
+impl Default for ClusterOptions {
+    fn default() -> Self {
+        Self {
+            min_similarity: 0.7,
+            same_leaf: false,
+        }
+    }
+}
+
+/// A group of similar stacks.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StackCluster {
+    /// ID of the heaviest stack in the cluster.
+    pub representative: String,
+    /// Frame IDs of the representative, from root to leaf.
+    pub frames: Vec<u64>,
+    /// Function names for `frames`, in the same order.
+    pub functions: Vec<String>,
+    /// IDs of every stack in the cluster, including the representative.
+    pub members: Vec<String>,
+    /// Summed weight of all members.
+    pub weight: u64,
+    /// `weight` as a fraction of the event's total weight.
+    pub share: f64,
+}
+
+/// Group the stacks of `event` into clusters of similar frame sequences.
+///
+/// Similarity is the Jaccard index over adjacent caller/callee pairs of each
+/// stack (with the root and leaf edges included), so stacks that share long
+/// runs of frames score high even when they differ in depth. Stacks are
+/// visited heaviest first; each one joins the first cluster whose
+/// representative is similar enough, or starts a new cluster.
+///
+/// Clusters are returned in order of descending weight.
+pub fn cluster_stacks(
+    file: &SpaaFile,
+    event: &str,
+    metric: &str,
+    options: &ClusterOptions,
+) -> Vec<StackCluster> {
+    cluster_stacks_limited(file, event, metric, options, &Limits::default())
+        .expect("default limits are never reached")
+}
+
+/// Like [`cluster_stacks`], which compares each stack with every cluster
+/// so far, checking `limits` before each stack.
+///
+/// When the memory budget runs out the error carries the clusters of the
+/// stacks visited so far, which are the heaviest.
+pub fn cluster_stacks_limited(
+    file: &SpaaFile,
+    event: &str,
+    metric: &str,
+    options: &ClusterOptions,
+    limits: &Limits,
+) -> Result<Vec<StackCluster>, LimitError<Vec<StackCluster>>> {
+    let mut stacks: Vec<(&Stack, u64)> = file
+        .stacks_for_event(event)
+        .map(|s| (s, stack_weight(s, metric)))
+        .filter(|(_, w)| *w > 0)
+        .collect();
+    stacks.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.id.cmp(&b.0.id)));
+
+    let total: u64 = stacks.iter().map(|(_, w)| w).sum();
+    if total == 0 {
+        return Ok(Vec::new());
+    }
+
+    struct Building<'a> {
+        representative: &'a Stack,
+        root_to_leaf: Vec<u64>,
+        shingles: HashSet<(Option<u64>, Option<u64>)>,
+        members: Vec<String>,
+        weight: u64,
+    }
+
+    let mut clusters: Vec<Building> = Vec::new();
+    let mut meter = limits.meter();
+    let mut exceeded: Option<BudgetExceeded> = None;
+
+    for (stack, weight) in stacks {
+        limits.check()?;
+        let root_to_leaf = root_to_leaf(file, stack);
+        let shingles = shingles(&root_to_leaf);
+
+        let existing = clusters.iter_mut().find(|c| {
+            (!options.same_leaf || c.root_to_leaf.last() == root_to_leaf.last())
+                && jaccard(&c.shingles, &shingles) >= options.min_similarity
+        });
+
+        // A member ID, plus the representative's frames and shingles for a
+        // new cluster.
+        let mut bytes = std::mem::size_of::<String>() + stack.id.len();
+        if existing.is_none() {
+            bytes += std::mem::size_of::<Building>()
+                + root_to_leaf.len() * std::mem::size_of::<u64>()
+                + shingles.len() * std::mem::size_of::<(Option<u64>, Option<u64>)>();
+        }
+        if let Err(e) = meter.charge(bytes as u64) {
+            exceeded = Some(e);
+            break;
+        }
+
+        match existing {
+            Some(cluster) => {
+                cluster.members.push(stack.id.clone());
+                cluster.weight += weight;
+            }
+            None => clusters.push(Building {
+                representative: stack,
+                root_to_leaf,
+                shingles,
+                members: vec![stack.id.clone()],
+                weight,
+            }),
+        }
+    }
+
+    clusters.sort_by(|a, b| {
+        b.weight
+            .cmp(&a.weight)
+            .then_with(|| a.representative.id.cmp(&b.representative.id))
+    });
+
+    let clusters = clusters
+        .into_iter()
+        .map(|c| {
+            let functions = c
+                .root_to_leaf
+                .iter()
+                .map(|id| {
+                    file.resolve_frame(*id)
+                        .map_or_else(|| format!("<frame {}>", id), |f| f.func.clone())
+                })
+                .collect();
+            StackCluster {
+                representative: c.representative.id.clone(),
+                frames: c.root_to_leaf,
+                functions,
+                members: c.members,
+                weight: c.weight,
+                share: c.weight as f64 / total as f64,
+            }
+        })
+        .collect();
+    match exceeded {
+        Some(e) => Err(e.with_partial(clusters).into()),
+        None => Ok(clusters),
+    }
+}
+
+fn root_to_leaf(file: &SpaaFile, stack: &Stack) -> Vec<u64> {
+    match file.header.frame_order {
+        FrameOrder::RootToLeaf => stack.frames.clone(),
+        FrameOrder::LeafToRoot => stack.frames.iter().rev().copied().collect(),
+    }
+}
+
+/// Adjacent frame pairs, with `None` marking the edges of the stack.
+fn shingles(frames: &[u64]) -> HashSet<(Option<u64>, Option<u64>)> {
+    let mut set = HashSet::with_capacity(frames.len() + 1);
+    let mut prev = None;
+    for &frame in frames {
+        set.insert((prev, Some(frame)));
+        prev = Some(frame);
+    }
+    set.insert((prev, None));
+    set
+}
+
+fn jaccard<T: std::hash::Hash + Eq>(a: &HashSet<T>, b: &HashSet<T>) -> f64 {
+    let intersection = a.intersection(b).count();
+    let union = a.len() + b.len() - intersection;
+    if union == 0 {
+        1.0
+    } else {
+        intersection as f64 / union as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/node","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"run","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"dispatch","dso":1}"#,
+            r#"{"type":"frame","id":4,"func":"handler","dso":1}"#,
+            r#"{"type":"frame","id":5,"func":"gc","dso":1}"#,
+            r#"{"type":"frame","id":6,"func":"trampoline","dso":1}"#,
+            // Two near-identical stacks differing by one interpreter frame.
+            r#"{"type":"stack","id":"0x1","frames":[4,3,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":50}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[4,3,6,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":20}]}"#,
+            // An unrelated stack.
+            r#"{"type":"stack","id":"0x3","frames":[5],"context":{"event":"cycles"},"weights":[{"metric":"period","value":30}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn similar_stacks_share_a_cluster() {
+        let options = ClusterOptions {
+            min_similarity: 0.4,
+            ..Default::default()
+        };
+        let clusters = cluster_stacks(&sample_file(), "cycles", "period", &options);
+
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].representative, "0x1");
+        assert_eq!(clusters[0].members, vec!["0x1", "0x2"]);
+        assert_eq!(clusters[0].weight, 70);
+        assert_eq!(
+            clusters[0].functions,
+            vec!["main", "run", "dispatch", "handler"]
+        );
+        assert!((clusters[1].share - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn strict_similarity_keeps_stacks_apart() {
+        let options = ClusterOptions {
+            min_similarity: 1.0,
+            ..Default::default()
+        };
+        let clusters = cluster_stacks(&sample_file(), "cycles", "period", &options);
+
+        assert_eq!(clusters.len(), 3);
+    }
+
+    #[test]
+    fn cancelled_clustering_fails() {
+        let limits = Limits::default();
+        limits.cancel.cancel();
+        let result = cluster_stacks_limited(
+            &sample_file(),
+            "cycles",
+            "period",
+            &ClusterOptions::default(),
+            &limits,
+        );
+
+        assert!(matches!(result, Err(LimitError::Cancelled(_))));
+    }
+
+    #[test]
+    fn jaccard_of_identical_sets_is_one() {
+        let a = shingles(&[1, 2, 3]);
+        assert_eq!(jaccard(&a, &a.clone()), 1.0);
+        assert_eq!(jaccard(&a, &shingles(&[7])), 0.0);
+    }
+}
@@ -0,0 +1,212 @@
+//! Cross-event correlation (e.g. cache-misses per cycle).
+
+use super::stack_weight;
+use serde::Serialize;
+use spaa_parse::{FrameOrder, SpaaFile};
+use std::collections::{BTreeMap, HashMap};
+
+/// Options for [`correlate_with_options`].
+#[derive(Debug, Clone)]
+pub struct CorrelateOptions {
+    /// Ignore functions carrying less than this fraction of the joined
+    /// `event_a` weight; tiny denominators produce meaningless ratios.
+    pub min_share: f64,
+}
+
+impl Default for CorrelateOptions {
+    fn default() -> Self {
+        Self { min_share: 0.005 }
+    }
+}
+
+/// Per-function correlation between two events.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FunctionCorrelation {
+    pub function: String,
+    /// Joined weight of `event_a` attributed to this function.
+    pub weight_a: u64,
+    /// Joined weight of `event_b` attributed to this function.
+    pub weight_b: u64,
+    /// `weight_b / weight_a`.
+    pub ratio: f64,
+    /// `ratio` divided by the profile-wide ratio; values above 1.0 mean the
+    /// function sees more `event_b` per `event_a` than average.
+    pub enrichment: f64,
+}
+
+/// Result of [`correlate`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Correlation {
+    pub event_a: String,
+    pub event_b: String,
+    /// Number of call stacks sampled by both events.
+    pub joined_stacks: usize,
+    /// Fraction of `event_a` weight on stacks also sampled by `event_b`.
+    pub coverage_a: f64,
+    /// Fraction of `event_b` weight on stacks also sampled by `event_a`.
+    pub coverage_b: f64,
+    /// Profile-wide `event_b / event_a` over joined stacks.
+    pub overall_ratio: f64,
+    /// Functions ordered by descending enrichment.
+    pub functions: Vec<FunctionCorrelation>,
+}
+
+/// Correlate two events using [`CorrelateOptions::default`].
+pub fn correlate(file: &SpaaFile, event_a: &str, event_b: &str) -> Correlation {
+    correlate_with_options(file, event_a, event_b, &CorrelateOptions::default())
+}
+
+/// Join the stacks of two events and report functions with an unusually
+/// high ratio of `event_b` to `event_a`.
+///
+/// Stacks are joined by content: two stacks match when they have the same
+/// frame sequence, which is exactly what a content-addressable stack ID
+/// encodes. Each joined stack's weights (in each event's primary metric) are
+/// attributed to its leaf function, so the ratio describes work done in the
+/// function itself.
+pub fn correlate_with_options(
+    file: &SpaaFile,
+    event_a: &str,
+    event_b: &str,
+    options: &CorrelateOptions,
+) -> Correlation {
+    let weights_by_frames = |event: &str| -> HashMap<&[u64], u64> {
+        let metric = file.primary_metric_for_event(event).unwrap_or_default();
+        let mut map: HashMap<&[u64], u64> = HashMap::new();
+        for stack in file.stacks_for_event(event) {
+            *map.entry(stack.frames.as_slice()).or_default() += stack_weight(stack, metric);
+        }
+        map
+    };
+    let a = weights_by_frames(event_a);
+    let b = weights_by_frames(event_b);
+
+    let total_a: u64 = a.values().sum();
+    let total_b: u64 = b.values().sum();
+
+    let mut per_function: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    let mut joined_stacks = 0;
+    let (mut joined_a, mut joined_b) = (0u64, 0u64);
+    for (frames, &weight_a) in &a {
+        let Some(&weight_b) = b.get(frames) else {
+            continue;
+        };
+        joined_stacks += 1;
+        joined_a += weight_a;
+        joined_b += weight_b;
+
+        let leaf = match file.header.frame_order {
+            FrameOrder::LeafToRoot => frames.first(),
+            FrameOrder::RootToLeaf => frames.last(),
+        };
+        let function = leaf
+            .and_then(|id| file.resolve_frame(*id))
+            .map_or_else(|| "<unknown>".to_string(), |f| f.func.clone());
+        let entry = per_function.entry(function).or_default();
+        entry.0 += weight_a;
+        entry.1 += weight_b;
+    }
+
+    let overall_ratio = if joined_a > 0 {
+        joined_b as f64 / joined_a as f64
+    } else {
+        0.0
+    };
+    let min_weight = joined_a as f64 * options.min_share;
+
+    let mut functions: Vec<FunctionCorrelation> = per_function
+        .into_iter()
+        .filter(|(_, (wa, _))| *wa > 0 && *wa as f64 >= min_weight)
+        .map(|(function, (weight_a, weight_b))| {
+            let ratio = weight_b as f64 / weight_a as f64;
+            FunctionCorrelation {
+                function,
+                weight_a,
+                weight_b,
+                ratio,
+                enrichment: if overall_ratio > 0.0 {
+                    ratio / overall_ratio
+                } else {
+                    0.0
+                },
+            }
+        })
+        .collect();
+    functions.sort_by(|x, y| {
+        y.enrichment
+            .total_cmp(&x.enrichment)
+            .then_with(|| x.function.cmp(&y.function))
+    });
+
+    let share = |part: u64, total: u64| {
+        if total > 0 {
+            part as f64 / total as f64
+        } else {
+            0.0
+        }
+    };
+
+    Correlation {
+        event_a: event_a.to_string(),
+        event_b: event_b.to_string(),
+        joined_stacks,
+        coverage_a: share(joined_a, total_a),
+        coverage_b: share(joined_b, total_b),
+        overall_ratio,
+        functions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}},{"name":"cache-misses","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"hash_lookup","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"compute","dso":1}"#,
+            r#"{"type":"frame","id":4,"func":"idle","dso":1}"#,
+            r#"{"type":"stack","id":"0xa1","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":1000}]}"#,
+            r#"{"type":"stack","id":"0xa2","frames":[3,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":9000}]}"#,
+            r#"{"type":"stack","id":"0xa3","frames":[4,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":500}]}"#,
+            r#"{"type":"stack","id":"0xb1","frames":[2,1],"context":{"event":"cache-misses"},"weights":[{"metric":"period","value":500}]}"#,
+            r#"{"type":"stack","id":"0xb2","frames":[3,1],"context":{"event":"cache-misses"},"weights":[{"metric":"period","value":500}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn ranks_functions_by_enrichment() {
+        let result = correlate(&sample_file(), "cycles", "cache-misses");
+
+        assert_eq!(result.joined_stacks, 2);
+        assert!((result.overall_ratio - 0.1).abs() < 1e-9);
+        assert_eq!(result.functions[0].function, "hash_lookup");
+        assert!((result.functions[0].ratio - 0.5).abs() < 1e-9);
+        assert!((result.functions[0].enrichment - 5.0).abs() < 1e-9);
+        assert!(result.functions[1].enrichment < 1.0);
+    }
+
+    #[test]
+    fn reports_coverage_of_unjoined_stacks() {
+        let result = correlate(&sample_file(), "cycles", "cache-misses");
+
+        // "idle" only has cycles samples.
+        assert!((result.coverage_a - 10000.0 / 10500.0).abs() < 1e-9);
+        assert_eq!(result.coverage_b, 1.0);
+    }
+
+    #[test]
+    fn min_share_filters_small_functions() {
+        let options = CorrelateOptions { min_share: 0.5 };
+        let result = correlate_with_options(&sample_file(), "cycles", "cache-misses", &options);
+
+        assert_eq!(result.functions.len(), 1);
+        assert_eq!(result.functions[0].function, "compute");
+    }
+}
@@ -0,0 +1,235 @@
+//! Sampling-frequency inference and CPU-time normalization.
+//!
+//! Sample counts are only comparable between profiles captured at the same
+//! frequency. Converting them into estimated CPU time (`cpu_ns`) makes a
+//! 99 Hz profile and a 999 Hz profile directly comparable.
+
+use serde::Serialize;
+use spaa_parse::{SamplingMode, SpaaFile, Weight};
+use std::collections::BTreeMap;
+
+/// Name of the metric added by [`add_cpu_time_metric`].
+pub const CPU_TIME_METRIC: &str = "cpu_ns";
+
+/// Where a sampling frequency came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrequencySource {
+    /// `sampling.frequency_hz` in the header.
+    Declared,
+    /// Inferred from raw sample timestamps.
+    Inferred,
+}
+
+/// An event's sampling frequency.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrequencyEstimate {
+    pub event: String,
+    /// Samples per second, per CPU.
+    pub hz: f64,
+    pub source: FrequencySource,
+    /// Number of inter-sample intervals the estimate was based on (0 when
+    /// declared).
+    pub intervals: usize,
+}
+
+/// Infer the sampling frequency of `event` from raw sample timestamps.
+///
+/// Timestamps are assumed to be in seconds. Samples are grouped by CPU,
+/// since frequency-mode sampling fires independently on each CPU, and the
+/// median interval between consecutive samples is used so gaps from idle
+/// periods don't skew the estimate.
+pub fn infer_sampling_frequency(file: &SpaaFile, event: &str) -> Option<FrequencyEstimate> {
+    let mut by_cpu: BTreeMap<u32, Vec<f64>> = BTreeMap::new();
+    for sample in file.samples.iter().filter(|s| s.event == event) {
+        by_cpu.entry(sample.cpu).or_default().push(sample.timestamp);
+    }
+
+    let mut intervals: Vec<f64> = Vec::new();
+    for timestamps in by_cpu.values_mut() {
+        timestamps.sort_by(|a, b| a.total_cmp(b));
+        intervals.extend(
+            timestamps
+                .windows(2)
+                .map(|w| w[1] - w[0])
+                .filter(|d| *d > 0.0),
+        );
+    }
+    if intervals.is_empty() {
+        return None;
+    }
+
+    intervals.sort_by(|a, b| a.total_cmp(b));
+    let median = intervals[intervals.len() / 2];
+    Some(FrequencyEstimate {
+        event: event.to_string(),
+        hz: 1.0 / median,
+        source: FrequencySource::Inferred,
+        intervals: intervals.len(),
+    })
+}
+
+/// Determine the sampling frequency of `event`.
+///
+/// A frequency declared in the header wins; otherwise it is inferred from
+/// raw samples.
+pub fn sampling_frequency(file: &SpaaFile, event: &str) -> Option<FrequencyEstimate> {
+    let declared = file
+        .header
+        .events
+        .iter()
+        .find(|e| e.name == event)
+        .and_then(|e| e.sampling.frequency_hz)
+        .filter(|hz| *hz > 0);
+    if let Some(hz) = declared {
+        return Some(FrequencyEstimate {
+            event: event.to_string(),
+            hz: hz as f64,
+            source: FrequencySource::Declared,
+            intervals: 0,
+        });
+    }
+    infer_sampling_frequency(file, event)
+}
+
+/// Add an estimated `cpu_ns` weight to every stack with a `samples` weight.
+///
+/// Each sample is worth `1e9 / hz` nanoseconds. Events whose frequency can't
+/// be determined are left alone. Inferred frequencies of frequency-mode
+/// events are written back to the header's `sampling.frequency_hz`; other
+/// events keep their sampling description. Returns the frequency used for each
+/// event that was normalized.
+pub fn add_cpu_time_metric(file: &mut SpaaFile) -> Vec<FrequencyEstimate> {
+    let estimates: Vec<FrequencyEstimate> = file
+        .header
+        .events
+        .iter()
+        .filter_map(|e| sampling_frequency(file, &e.name))
+        .collect();
+
+    for estimate in &estimates {
+        let ns_per_sample = 1e9 / estimate.hz;
+        for stack in file.stacks.values_mut() {
+            if stack.context.event != estimate.event {
+                continue;
+            }
+            set_cpu_time(&mut stack.weights, ns_per_sample);
+            if let Some(exclusive) = &mut stack.exclusive {
+                set_cpu_time(&mut exclusive.weights, ns_per_sample);
+            }
+        }
+
+        if estimate.source == FrequencySource::Inferred
+            && let Some(event) = file
+                .header
+                .events
+                .iter_mut()
+                .find(|e| e.name == estimate.event)
+            && event.sampling.mode == SamplingMode::Frequency
+        {
+            event.sampling.frequency_hz = Some(estimate.hz.round() as u64);
+        }
+    }
+
+    estimates
+}
+
+fn set_cpu_time(weights: &mut Vec<Weight>, ns_per_sample: f64) {
+    let Some(samples) = weights.iter().find(|w| w.metric == "samples") else {
+        return;
+    };
+    let value = (samples.value as f64 * ns_per_sample).round() as u64;
+    match weights.iter_mut().find(|w| w.metric == CPU_TIME_METRIC) {
+        Some(existing) => existing.value = value,
+        None => weights.push(Weight {
+            metric: CPU_TIME_METRIC.to_string(),
+            value,
+            unit: Some("nanoseconds".to_string()),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::stack_weight;
+    use std::io::Cursor;
+
+    fn sample_file(frequency_hz: Option<u64>) -> SpaaFile {
+        let sampling = match frequency_hz {
+            Some(hz) => format!(
+                r#"{{"mode":"frequency","primary_metric":"samples","frequency_hz":{}}}"#,
+                hz
+            ),
+            None => r#"{"mode":"frequency","primary_metric":"samples"}"#.to_string(),
+        };
+        let mut lines = vec![
+            format!(
+                r#"{{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{{"name":"cpu-clock","kind":"timer","sampling":{}}}]}}"#,
+                sampling
+            ),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#.to_string(),
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#.to_string(),
+            r#"{"type":"stack","id":"0x1","frames":[1],"context":{"event":"cpu-clock"},"weights":[{"metric":"samples","value":100}]}"#.to_string(),
+        ];
+        // Two CPUs sampling at 100 Hz, with an idle gap on CPU 1.
+        for cpu in 0..2 {
+            for i in 0..10 {
+                let gap = if cpu == 1 && i >= 5 { 3.0 } else { 0.0 };
+                lines.push(format!(
+                    r#"{{"type":"sample","timestamp":{},"pid":1,"tid":1,"cpu":{},"event":"cpu-clock","stack_id":"0x1"}}"#,
+                    1000.0 + gap + i as f64 * 0.01,
+                    cpu
+                ));
+            }
+        }
+        SpaaFile::parse(Cursor::new(lines.join("\n"))).unwrap()
+    }
+
+    #[test]
+    fn infers_frequency_from_timestamps() {
+        let estimate = infer_sampling_frequency(&sample_file(None), "cpu-clock").unwrap();
+
+        assert!((estimate.hz - 100.0).abs() < 0.5);
+        assert_eq!(estimate.source, FrequencySource::Inferred);
+    }
+
+    #[test]
+    fn declared_frequency_takes_precedence() {
+        let estimate = sampling_frequency(&sample_file(Some(999)), "cpu-clock").unwrap();
+
+        assert_eq!(estimate.hz, 999.0);
+        assert_eq!(estimate.source, FrequencySource::Declared);
+    }
+
+    #[test]
+    fn adds_cpu_ns_metric() {
+        let mut file = sample_file(None);
+        let estimates = add_cpu_time_metric(&mut file);
+
+        assert_eq!(estimates.len(), 1);
+        // 100 samples at 100 Hz is one second of CPU time.
+        let cpu_ns = stack_weight(&file.stacks["0x1"], CPU_TIME_METRIC);
+        assert!((cpu_ns as f64 - 1e9).abs() < 1e7);
+        assert_eq!(file.header.events[0].sampling.frequency_hz, Some(100));
+    }
+
+    #[test]
+    fn period_events_keep_their_header() {
+        let mut file = sample_file(None);
+        file.header.events[0].sampling.mode = SamplingMode::Period;
+
+        assert_eq!(add_cpu_time_metric(&mut file).len(), 1);
+        assert!(stack_weight(&file.stacks["0x1"], CPU_TIME_METRIC) > 0);
+        assert_eq!(file.header.events[0].sampling.frequency_hz, None);
+    }
+
+    #[test]
+    fn unknown_frequency_leaves_file_unchanged() {
+        let mut file = sample_file(None);
+        file.samples.clear();
+
+        assert!(add_cpu_time_metric(&mut file).is_empty());
+        assert_eq!(stack_weight(&file.stacks["0x1"], CPU_TIME_METRIC), 0);
+    }
+}
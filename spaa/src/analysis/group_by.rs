@@ -0,0 +1,259 @@
+//! Aggregate weight by a context field (process, CPU, socket, container, ...).
+
+use super::stack_weight;
+use crate::topology::{CORE_KEY, NUMA_NODE_KEY, SOCKET_KEY};
+use serde::Serialize;
+use spaa_parse::{Sample, SpaaFile, StackContext};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Stack context field to group by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupKey {
+    Pid,
+    Tid,
+    Comm,
+    Cpu,
+    /// Requires [`Topology::annotate`](crate::topology::Topology::annotate).
+    Socket,
+    /// Requires [`Topology::annotate`](crate::topology::Topology::annotate).
+    Core,
+    /// Requires [`Topology::annotate`](crate::topology::Topology::annotate).
+    NumaNode,
+    Execname,
+    Zonename,
+    Cgroup,
+    ContainerId,
+    K8sPod,
+}
+
+impl GroupKey {
+    /// Field name as it appears in a stack's `context`.
+    pub fn name(self) -> &'static str {
+        match self {
+            GroupKey::Pid => "pid",
+            GroupKey::Tid => "tid",
+            GroupKey::Comm => "comm",
+            GroupKey::Cpu => "cpu",
+            GroupKey::Socket => SOCKET_KEY,
+            GroupKey::Core => CORE_KEY,
+            GroupKey::NumaNode => NUMA_NODE_KEY,
+            GroupKey::Execname => "execname",
+            GroupKey::Zonename => "zonename",
+            GroupKey::Cgroup => "cgroup",
+            GroupKey::ContainerId => "container_id",
+            GroupKey::K8sPod => "k8s_pod",
+        }
+    }
+
+    fn value(self, context: &StackContext) -> Option<String> {
+        match self {
+            GroupKey::Pid => context.pid.map(|v| v.to_string()),
+            GroupKey::Tid => context.tid.map(|v| v.to_string()),
+            GroupKey::Comm => context.comm.clone(),
+            GroupKey::Cpu => context.cpu.map(|v| v.to_string()),
+            GroupKey::Socket | GroupKey::Core | GroupKey::NumaNode => {
+                context.extra.get(self.name()).map(json_to_string)
+            }
+            GroupKey::Execname => context.execname.clone(),
+            GroupKey::Zonename => context.zonename.clone(),
+            GroupKey::Cgroup => context.cgroup.clone(),
+            GroupKey::ContainerId => context.container_id.clone(),
+            GroupKey::K8sPod => context.k8s_pod.clone(),
+        }
+    }
+
+    fn sample_value(self, sample: &Sample) -> Option<String> {
+        match self {
+            GroupKey::Pid => Some(sample.pid.to_string()),
+            GroupKey::Tid => Some(sample.tid.to_string()),
+            GroupKey::Cpu => Some(sample.cpu.to_string()),
+            _ => sample.context.get(self.name()).map(json_to_string),
+        }
+    }
+}
+
+fn json_to_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl fmt::Display for GroupKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Error returned when parsing an unknown [`GroupKey`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseGroupKeyError(String);
+
+impl fmt::Display for ParseGroupKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown group key '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseGroupKeyError {}
+
+impl FromStr for GroupKey {
+    type Err = ParseGroupKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "pid" => GroupKey::Pid,
+            "tid" => GroupKey::Tid,
+            "comm" => GroupKey::Comm,
+            "cpu" => GroupKey::Cpu,
+            "socket" => GroupKey::Socket,
+            "core" => GroupKey::Core,
+            "numa_node" | "node" => GroupKey::NumaNode,
+            "execname" => GroupKey::Execname,
+            "zonename" => GroupKey::Zonename,
+            "cgroup" => GroupKey::Cgroup,
+            "container_id" | "container" => GroupKey::ContainerId,
+            "k8s_pod" | "pod" => GroupKey::K8sPod,
+            _ => return Err(ParseGroupKeyError(s.to_string())),
+        })
+    }
+}
+
+/// Weight attributed to one value of a [`GroupKey`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GroupWeight {
+    /// Field value, or `None` for stacks that don't record it.
+    pub key: Option<String>,
+    pub weight: u64,
+    /// Fraction of the event's total weight.
+    pub share: f64,
+    /// Number of stacks in the group.
+    pub stacks: usize,
+}
+
+/// Sum `metric` over the stacks of `event`, grouped by a context field.
+///
+/// Groups are ordered by descending weight. Stacks without the field are
+/// collected in a single group with `key: None`.
+pub fn group_by(file: &SpaaFile, event: &str, metric: &str, key: GroupKey) -> Vec<GroupWeight> {
+    collect_groups(
+        file.stacks_for_event(event)
+            .map(|stack| (key.value(&stack.context), stack_weight(stack, metric))),
+    )
+}
+
+/// Group the raw samples of `event` by a sample field.
+///
+/// Each sample is weighted by its `period`, or 1 when it has none; `stacks`
+/// counts samples. Useful when stacks are aggregated across CPUs, which is
+/// the case for most converters.
+pub fn group_samples_by(file: &SpaaFile, event: &str, key: GroupKey) -> Vec<GroupWeight> {
+    collect_groups(
+        file.samples
+            .iter()
+            .filter(|s| s.event == event)
+            .map(|s| (key.sample_value(s), s.period.unwrap_or(1))),
+    )
+}
+
+fn collect_groups(items: impl Iterator<Item = (Option<String>, u64)>) -> Vec<GroupWeight> {
+    let mut groups: HashMap<Option<String>, (u64, usize)> = HashMap::new();
+    let mut total = 0u64;
+    for (key, weight) in items {
+        total += weight;
+        let entry = groups.entry(key).or_default();
+        entry.0 += weight;
+        entry.1 += 1;
+    }
+
+    let mut result: Vec<GroupWeight> = groups
+        .into_iter()
+        .map(|(key, (weight, stacks))| GroupWeight {
+            key,
+            weight,
+            share: if total > 0 {
+                weight as f64 / total as f64
+            } else {
+                0.0
+            },
+            stacks,
+        })
+        .collect();
+    result.sort_by(|a, b| b.weight.cmp(&a.weight).then_with(|| a.key.cmp(&b.key)));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"work","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[1],"context":{"event":"cycles","pid":1,"container_id":"aaa","k8s_pod":"pod-a"},"weights":[{"metric":"period","value":300}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[2,1],"context":{"event":"cycles","pid":2,"container_id":"bbb","k8s_pod":"pod-a"},"weights":[{"metric":"period","value":500}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[2],"context":{"event":"cycles","pid":3},"weights":[{"metric":"period","value":200}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn groups_by_pod() {
+        let groups = group_by(&sample_file(), "cycles", "period", GroupKey::K8sPod);
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].key.as_deref(), Some("pod-a"));
+        assert_eq!(groups[0].weight, 800);
+        assert_eq!(groups[0].stacks, 2);
+        assert!((groups[0].share - 0.8).abs() < 1e-9);
+        assert_eq!(groups[1].key, None);
+    }
+
+    #[test]
+    fn groups_by_container_in_weight_order() {
+        let groups = group_by(&sample_file(), "cycles", "period", GroupKey::ContainerId);
+        let keys: Vec<_> = groups.iter().map(|g| g.key.as_deref()).collect();
+
+        assert_eq!(keys, vec![Some("bbb"), Some("aaa"), None]);
+    }
+
+    #[test]
+    fn groups_samples_by_socket() {
+        let mut file = sample_file();
+        for (cpu, period) in [(0, 10), (1, 20), (2, 5)] {
+            let line = format!(
+                r#"{{"timestamp":1.0,"pid":1,"tid":1,"cpu":{},"event":"cycles","period":{},"stack_id":"0x1"}}"#,
+                cpu, period
+            );
+            file.samples.push(serde_json::from_str(&line).unwrap());
+        }
+        let topology = crate::topology::Topology::from_cpus([
+            (0, 0, 0, None),
+            (1, 1, 1, None),
+            (2, 1, 2, None),
+        ]);
+        topology.annotate(&mut file);
+
+        let groups = group_samples_by(&file, "cycles", GroupKey::Socket);
+        assert_eq!(groups[0].key.as_deref(), Some("1"));
+        assert_eq!(groups[0].weight, 25);
+        assert_eq!(groups[0].stacks, 2);
+        assert_eq!(groups[1].weight, 10);
+    }
+
+    #[test]
+    fn parse_group_key() {
+        assert_eq!("container".parse::<GroupKey>(), Ok(GroupKey::ContainerId));
+        assert_eq!("k8s_pod".parse::<GroupKey>(), Ok(GroupKey::K8sPod));
+        assert_eq!("node".parse::<GroupKey>(), Ok(GroupKey::NumaNode));
+        assert!("package".parse::<GroupKey>().is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Dominant-path ("hot path") extraction.
+
+use super::call_tree::CallTree;
+use serde::Serialize;
+use spaa_parse::SpaaFile;
+use std::collections::BinaryHeap;
+
+/// A dominant path through the call tree, from the outermost caller down to
+/// the frame where its samples' stacks end.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HotPath {
+    /// Frame IDs from the outermost caller down to the path's last frame.
+    pub frames: Vec<u64>,
+    /// Function names for `frames`, in the same order.
+    pub functions: Vec<String>,
+    /// Weight of samples whose stacks are exactly this path.
+    pub weight: u64,
+    /// `weight` as a fraction of the event's total weight.
+    pub share: f64,
+    /// Running total of `share` for this path and all paths ranked above it.
+    pub cumulative_share: f64,
+}
+
+/// Find the top `n` dominant paths for `event`, weighted by `metric`.
+///
+/// Starting at the root, the walk repeatedly follows the heaviest child
+/// until it reaches a leaf, or a frame whose own (exclusive) weight is larger
+/// than its heaviest child's inclusive weight. Every sibling passed over
+/// along the way becomes a candidate starting point for a later walk, as
+/// does the own weight of each frame walked through, and candidates are
+/// explored heaviest first. Exploration stops once no remaining candidate
+/// could beat the `n`th best path found so far.
+///
+/// A path's weight is the exclusive weight of its last frame, so weights
+/// never overlap and `cumulative_share` reports how much of the profile the
+/// returned paths cover, including time spent in frames that also have
+/// callees. Results are ordered by descending weight.
+pub fn hot_paths(file: &SpaaFile, event: &str, metric: &str, n: usize) -> Vec<HotPath> {
+    let tree = CallTree::build(file, event, metric);
+    let total = tree.total();
+    if n == 0 || total == 0 {
+        return Vec::new();
+    }
+
+    // (weight, last node index), kept sorted by descending weight.
+    let mut best: Vec<(u64, usize)> = Vec::new();
+    // Candidates keyed by the most weight a path from them could carry: the
+    // inclusive weight of a subtree to walk, or the exclusive weight of a
+    // node that ends a path itself. Ties favor the lower node index.
+    let mut candidates: BinaryHeap<(u64, std::cmp::Reverse<usize>, Candidate)> = BinaryHeap::new();
+    candidates.push((total, std::cmp::Reverse(0), Candidate::Subtree));
+
+    while let Some((bound, std::cmp::Reverse(start), candidate)) = candidates.pop() {
+        if best.len() == n && best[n - 1].0 >= bound {
+            break;
+        }
+
+        let mut current = start;
+        if candidate == Candidate::Subtree {
+            while let Some(heaviest) = tree.heaviest_child(current) {
+                let node = tree.node(current);
+                let stop = node.exclusive > tree.node(heaviest).inclusive;
+                for &child in &node.children {
+                    if stop || child != heaviest {
+                        candidates.push((
+                            tree.node(child).inclusive,
+                            std::cmp::Reverse(child),
+                            Candidate::Subtree,
+                        ));
+                    }
+                }
+                if stop {
+                    break;
+                }
+                if node.exclusive > 0 {
+                    candidates.push((node.exclusive, std::cmp::Reverse(current), Candidate::Own));
+                }
+                current = heaviest;
+            }
+        }
+
+        // The synthetic root has no frames and never forms a path on its own.
+        if current == 0 {
+            continue;
+        }
+
+        let weight = tree.node(current).exclusive;
+        let pos = best.partition_point(|&(w, _)| w >= weight);
+        if pos < n {
+            best.insert(pos, (weight, current));
+            best.truncate(n);
+        }
+    }
+
+    let mut cumulative = 0.0;
+    best.into_iter()
+        .map(|(weight, last)| {
+            let frames = tree.path_to(last);
+            let functions = frames
+                .iter()
+                .map(|id| {
+                    file.resolve_frame(*id)
+                        .map_or_else(|| format!("<frame {}>", id), |f| f.func.clone())
+                })
+                .collect();
+            let share = weight as f64 / total as f64;
+            cumulative += share;
+            HotPath {
+                frames,
+                functions,
+                weight,
+                share,
+                cumulative_share: cumulative,
+            }
+        })
+        .collect()
+}
+
+/// What a hot-path candidate node stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Candidate {
+    /// The node's own weight, ending a path at the node.
+    Own,
+    /// The node's subtree, to be walked down from the node.
+    Subtree,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"render","dso":1}"#,
+            r#"{"type":"frame","id":4,"func":"tokenize","dso":1}"#,
+            r#"{"type":"frame","id":5,"func":"lex","dso":1}"#,
+            // main -> parse -> tokenize: 30, main -> parse -> lex: 25
+            r#"{"type":"stack","id":"0x1","frames":[4,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":30}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[5,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":25}]}"#,
+            // main -> render: 45
+            r#"{"type":"stack","id":"0x3","frames":[3,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":45}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn hot_paths_ranks_by_path_weight() {
+        let paths = hot_paths(&sample_file(), "cycles", "period", 3);
+
+        assert_eq!(paths.len(), 3);
+        // The greedy walk visits parse (55) first, but render carries more
+        // weight through its whole path.
+        assert_eq!(paths[0].functions, vec!["main", "render"]);
+        assert_eq!(paths[0].weight, 45);
+        assert_eq!(paths[1].functions, vec!["main", "parse", "tokenize"]);
+        assert_eq!(paths[2].frames, vec![1, 2, 5]);
+    }
+
+    #[test]
+    fn hot_paths_reports_cumulative_share() {
+        let paths = hot_paths(&sample_file(), "cycles", "period", 2);
+
+        assert_eq!(paths.len(), 2);
+        assert!((paths[0].share - 0.45).abs() < 1e-9);
+        assert!((paths[1].cumulative_share - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn hot_paths_stop_at_frames_with_dominant_own_weight() {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"render","dso":1}"#,
+            r#"{"type":"frame","id":4,"func":"tokenize","dso":1}"#,
+            r#"{"type":"frame","id":5,"func":"lex","dso":1}"#,
+            // parse spends 40 itself and 40 in its callees; main spends 5.
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":40}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[4,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":30}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[5,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":10}]}"#,
+            r#"{"type":"stack","id":"0x4","frames":[3,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":20}]}"#,
+            r#"{"type":"stack","id":"0x5","frames":[1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":5}]}"#,
+        ]
+        .join("\n");
+        let file = SpaaFile::parse(Cursor::new(data)).unwrap();
+
+        let paths = hot_paths(&file, "cycles", "period", 10);
+
+        let weights: Vec<(Vec<&str>, u64)> = paths
+            .iter()
+            .map(|p| (p.functions.iter().map(String::as_str).collect(), p.weight))
+            .collect();
+        assert_eq!(
+            weights,
+            [
+                (vec!["main", "parse"], 40),
+                (vec!["main", "parse", "tokenize"], 30),
+                (vec!["main", "render"], 20),
+                (vec!["main", "parse", "lex"], 10),
+                (vec!["main"], 5),
+            ]
+        );
+        assert!((paths[4].cumulative_share - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn hot_paths_empty_for_unknown_event() {
+        assert!(hot_paths(&sample_file(), "instructions", "period", 5).is_empty());
+        assert!(hot_paths(&sample_file(), "cycles", "period", 0).is_empty());
+    }
+}
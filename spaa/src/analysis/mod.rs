@@ -0,0 +1,113 @@
+//! Analysis helpers over parsed SPAA files.
+//!
+//! These functions operate on an in-memory [`SpaaFile`]
+//! and produce compact, pre-digested views of a profile that are easier for
+//! humans and agents to consume than the raw stack records.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::analysis;
+//! use spaa_parse::SpaaFile;
+//! use std::fs::File;
+//!
+//! let spaa = SpaaFile::parse(File::open("profile.spaa").unwrap()).unwrap();
+//! for path in analysis::hot_paths(&spaa, "cycles", "period", 5) {
+//!     println!("{:.1}% {}", path.share * 100.0, path.functions.join(" -> "));
+//! }
+//! ```
+
+mod allocation_rate;
+mod by_dso;
+mod by_package;
+mod by_source;
+mod call_tree;
+mod clusters;
+mod correlate;
+mod frequency;
+mod group_by;
+mod hot_paths;
+mod outliers;
+mod syscalls;
+mod top_functions;
+
+pub use allocation_rate::{AllocationRate, RatePoint, allocation_rates};
+pub use by_dso::{DsoFunction, DsoRollup, DsoWeight, TOP_FUNCTIONS_PER_DSO, UNKNOWN_DSO, by_dso};
+pub use by_package::{PackageRollup, PackageWeight, by_package};
+pub use by_source::{SourceKey, SourceRollup, SourceWeight, UNKNOWN_SOURCE, by_source};
+pub use call_tree::{CallTree, CallTreeNode, TreeMode};
+pub use clusters::{ClusterOptions, StackCluster, cluster_stacks, cluster_stacks_limited};
+pub use correlate::{
+    CorrelateOptions, Correlation, FunctionCorrelation, correlate, correlate_with_options,
+};
+pub use frequency::{
+    CPU_TIME_METRIC, FrequencyEstimate, FrequencySource, add_cpu_time_metric,
+    infer_sampling_frequency, sampling_frequency,
+};
+pub use group_by::{GroupKey, GroupWeight, ParseGroupKeyError, group_by, group_samples_by};
+pub use hot_paths::{HotPath, hot_paths};
+pub use outliers::{
+    OutlierOptions, OutlierReport, SampleOutlier, StackOutlier, detect_outliers, exclude_outliers,
+};
+pub use syscalls::{
+    DURATION_METRIC, SYSCALL_LATENCY_EVENT, SyscallLatency, SyscallReport, pair_syscalls,
+};
+pub use top_functions::{
+    FunctionWeight, ParseRankByError, RankBy, top_functions, top_functions_limited,
+};
+
+use spaa_parse::{FrameOrder, SpaaFile, Stack};
+use std::collections::{HashMap, HashSet};
+
+/// Get the value of `metric` for a stack, or 0 if the stack doesn't record it.
+pub(crate) fn stack_weight(stack: &Stack, metric: &str) -> u64 {
+    stack
+        .weights
+        .iter()
+        .find(|w| w.metric == metric)
+        .map_or(0, |w| w.value)
+}
+
+/// Self and total weight of `event`'s stacks per key, where `key` maps a
+/// frame ID to what it rolls up to. A stack counts toward a key's total
+/// once, however many of its frames map to it.
+///
+/// Returns the event's total weight and `(key, self, total)` entries by
+/// descending self weight, then total weight, then key.
+pub(crate) fn roll_up<'a>(
+    file: &SpaaFile,
+    event: &str,
+    metric: &str,
+    key: impl Fn(u64) -> &'a str,
+) -> (u64, Vec<(&'a str, u64, u64)>) {
+    let mut weights: HashMap<&str, (u64, u64)> = HashMap::new();
+    let mut total = 0;
+    for stack in file.stacks_for_event(event) {
+        let weight = stack_weight(stack, metric);
+        if weight == 0 {
+            continue;
+        }
+        total += weight;
+        let leaf = match file.header.frame_order {
+            FrameOrder::LeafToRoot => stack.frames.first(),
+            FrameOrder::RootToLeaf => stack.frames.last(),
+        };
+        if let Some(&id) = leaf {
+            weights.entry(key(id)).or_default().0 += weight;
+        }
+        let mut seen = HashSet::new();
+        for &id in &stack.frames {
+            let k = key(id);
+            if seen.insert(k) {
+                weights.entry(k).or_default().1 += weight;
+            }
+        }
+    }
+
+    let mut entries: Vec<(&str, u64, u64)> = weights
+        .into_iter()
+        .map(|(k, (self_weight, total_weight))| (k, self_weight, total_weight))
+        .collect();
+    entries.sort_by(|a, b| (b.1, b.2).cmp(&(a.1, a.2)).then(a.0.cmp(b.0)));
+    (total, entries)
+}
@@ -0,0 +1,463 @@
+//! Detection of samples and stacks with abnormal per-sample weight.
+//!
+//! A single sample with an enormous period (for example a 2-second "sample"
+//! caused by a stalled timer) can dominate aggregated weights. These helpers
+//! find such values using a robust score based on the median absolute
+//! deviation (MAD), which is not itself skewed by the outliers it is looking
+//! for. When more than half the values are identical the MAD is zero, and
+//! values are scored by their ratio to the median instead.
+
+use super::stack_weight;
+use serde::Serialize;
+use spaa_parse::{FrameOrder, SpaaFile, Weight};
+use std::collections::{BTreeMap, HashSet};
+
+/// Options for [`detect_outliers`].
+#[derive(Debug, Clone)]
+pub struct OutlierOptions {
+    /// Robust z-score above which a value is considered an outlier. When
+    /// the MAD is zero, the ratio to the median above which it is.
+    pub threshold: f64,
+    /// Minimum number of values for an event before detection is attempted.
+    pub min_population: usize,
+}
+
+impl Default for OutlierOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 10.0,
+            min_population: 5,
+        }
+    }
+}
+
+/// A raw sample whose period is far above its event's distribution.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SampleOutlier {
+    /// Index of the sample in [`SpaaFile::samples`].
+    pub index: usize,
+    pub event: String,
+    pub stack_id: String,
+    /// The sample's period.
+    pub value: u64,
+    /// Median period for the event.
+    pub median: f64,
+    /// Robust z-score of `value`.
+    pub score: f64,
+}
+
+/// A stack whose weight per sample is far above its event's distribution.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StackOutlier {
+    pub stack_id: String,
+    pub event: String,
+    /// Primary metric divided by the stack's `samples` count when available,
+    /// otherwise the raw primary metric value.
+    pub value: f64,
+    /// Median of `value` across the event's stacks.
+    pub median: f64,
+    /// Robust z-score of `value`.
+    pub score: f64,
+}
+
+/// Outliers found by [`detect_outliers`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OutlierReport {
+    pub samples: Vec<SampleOutlier>,
+    pub stacks: Vec<StackOutlier>,
+}
+
+impl OutlierReport {
+    /// Returns true if no outliers were found.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty() && self.stacks.is_empty()
+    }
+}
+
+/// Find samples and stacks whose weight is far outside the distribution for
+/// their event.
+///
+/// Samples are scored by their `period`; samples without a period are
+/// ignored. Stacks are scored by their primary metric per sample, so a hot
+/// stack with many ordinary samples is not mistaken for an outlier. Only
+/// unusually large values are reported, since those are the ones that skew
+/// aggregation.
+pub fn detect_outliers(file: &SpaaFile, options: &OutlierOptions) -> OutlierReport {
+    let mut report = OutlierReport::default();
+
+    // Raw samples, grouped by event.
+    let mut by_event: BTreeMap<&str, Vec<(usize, u64)>> = BTreeMap::new();
+    for (index, sample) in file.samples.iter().enumerate() {
+        if let Some(period) = sample.period {
+            by_event
+                .entry(sample.event.as_str())
+                .or_default()
+                .push((index, period));
+        }
+    }
+    for (event, values) in by_event {
+        let floats: Vec<f64> = values.iter().map(|(_, v)| *v as f64).collect();
+        let Some(scorer) = RobustScorer::new(&floats, options.min_population) else {
+            continue;
+        };
+        for (index, value) in values {
+            let score = scorer.score(value as f64);
+            if score > options.threshold {
+                report.samples.push(SampleOutlier {
+                    index,
+                    event: event.to_string(),
+                    stack_id: file.samples[index].stack_id.clone(),
+                    value,
+                    median: scorer.median,
+                    score,
+                });
+            }
+        }
+    }
+
+    // Aggregated stacks, grouped by event.
+    for event in &file.header.events {
+        let metric = event.sampling.primary_metric.as_str();
+        let mut values: Vec<(&str, f64)> = file
+            .stacks_for_event(&event.name)
+            .filter_map(|stack| {
+                let weight = stack.weights.iter().find(|w| w.metric == metric)?.value;
+                let samples = if metric == "samples" {
+                    1
+                } else {
+                    stack_weight(stack, "samples").max(1)
+                };
+                Some((stack.id.as_str(), weight as f64 / samples as f64))
+            })
+            .collect();
+        values.sort_by(|a, b| a.0.cmp(b.0));
+
+        let floats: Vec<f64> = values.iter().map(|(_, v)| *v).collect();
+        let Some(scorer) = RobustScorer::new(&floats, options.min_population) else {
+            continue;
+        };
+        for (stack_id, value) in values {
+            let score = scorer.score(value);
+            if score > options.threshold {
+                report.stacks.push(StackOutlier {
+                    stack_id: stack_id.to_string(),
+                    event: event.name.clone(),
+                    value,
+                    median: scorer.median,
+                    score,
+                });
+            }
+        }
+    }
+
+    report
+}
+
+/// Remove the outliers in `report` from `file`.
+///
+/// Outlier samples are dropped, and one is subtracted from their stack's
+/// `samples` count and their period from its primary metric, unless that
+/// is `samples` too. The same is taken from the stack's exclusive weights
+/// when they are on its leaf, and from its entry in the window containing
+/// the sample. Outlier stacks are dropped along with any samples and window
+/// entries that reference them.
+pub fn exclude_outliers(file: &mut SpaaFile, report: &OutlierReport) {
+    let dropped_samples: HashSet<usize> = report.samples.iter().map(|o| o.index).collect();
+    let frame_order = file.header.frame_order;
+    for outlier in &report.samples {
+        let metric = file
+            .primary_metric_for_event(&outlier.event)
+            .map(str::to_string);
+        let subtract = |weights: &mut Vec<Weight>| {
+            for weight in weights {
+                if weight.metric == "samples" {
+                    weight.value = weight.value.saturating_sub(1);
+                } else if Some(&weight.metric) == metric.as_ref() {
+                    weight.value = weight.value.saturating_sub(outlier.value);
+                }
+            }
+        };
+        if let Some(stack) = file.stacks.get_mut(&outlier.stack_id) {
+            subtract(&mut stack.weights);
+            let leaf = match frame_order {
+                FrameOrder::LeafToRoot => stack.frames.first(),
+                FrameOrder::RootToLeaf => stack.frames.last(),
+            };
+            if let Some(exclusive) = &mut stack.exclusive
+                && leaf == Some(&exclusive.frame)
+            {
+                subtract(&mut exclusive.weights);
+            }
+        }
+        let timestamp = file.samples.get(outlier.index).map(|s| s.timestamp);
+        if let Some(entry) = file
+            .windows
+            .iter_mut()
+            .filter(|w| timestamp.is_some_and(|ts| w.start <= ts && ts <= w.end))
+            .flat_map(|w| w.by_stack.iter_mut())
+            .find(|e| e.stack_id == outlier.stack_id)
+        {
+            subtract(&mut entry.weights);
+        }
+    }
+
+    let dropped_stacks: HashSet<&str> = report.stacks.iter().map(|o| o.stack_id.as_str()).collect();
+    file.stacks
+        .retain(|id, _| !dropped_stacks.contains(id.as_str()));
+
+    let mut index = 0;
+    file.samples.retain(|sample| {
+        let keep =
+            !dropped_samples.contains(&index) && !dropped_stacks.contains(sample.stack_id.as_str());
+        index += 1;
+        keep
+    });
+
+    for window in &mut file.windows {
+        window
+            .by_stack
+            .retain(|w| !dropped_stacks.contains(w.stack_id.as_str()));
+    }
+}
+
+/// Scores values by their distance from the median in units of MAD, or by
+/// their ratio to the median when the MAD is zero.
+struct RobustScorer {
+    median: f64,
+    /// Scaled MAD, or `None` to score by ratio.
+    scale: Option<f64>,
+}
+
+impl RobustScorer {
+    /// Consistency constant so MAD estimates the standard deviation of
+    /// normally distributed data.
+    const MAD_SCALE: f64 = 1.4826;
+
+    fn new(values: &[f64], min_population: usize) -> Option<Self> {
+        if values.is_empty() || values.len() < min_population {
+            return None;
+        }
+        let median = median_of(values.to_vec());
+        let deviations: Vec<f64> = values.iter().map(|v| (v - median).abs()).collect();
+        let scale = median_of(deviations) * Self::MAD_SCALE;
+        if scale > 0.0 {
+            return Some(Self {
+                median,
+                scale: Some(scale),
+            });
+        }
+        // More than half the values are identical. Any dispersion measure
+        // is dominated by the spikes themselves, capping a lone spike's
+        // score near the population size, so compare to the median instead.
+        (median > 0.0).then_some(Self {
+            median,
+            scale: None,
+        })
+    }
+
+    fn score(&self, value: f64) -> f64 {
+        match self.scale {
+            Some(scale) => (value - self.median) / scale,
+            None => value / self.median,
+        }
+    }
+}
+
+fn median_of(mut values: Vec<f64>) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Header with one `cycles` event sampled by period.
+    fn header(primary_metric: &str) -> String {
+        format!(
+            concat!(
+                r#"{{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","#,
+                r#""frame_order":"leaf_to_root","events":[{{"name":"cycles","kind":"hardware","#,
+                r#""sampling":{{"mode":"period","primary_metric":"{}"}}}}]}}"#,
+            ),
+            primary_metric
+        )
+    }
+
+    fn weights(values: &[(&str, u64)]) -> String {
+        let weights: Vec<String> = values
+            .iter()
+            .map(|(metric, value)| format!(r#"{{"metric":"{metric}","value":{value}}}"#))
+            .collect();
+        format!("[{}]", weights.join(","))
+    }
+
+    fn stack(id: &str, frames: &str, weights: &str) -> String {
+        format!(
+            concat!(
+                r#"{{"type":"stack","id":"{}","frames":{},"context":{{"event":"cycles"}},"#,
+                r#""weights":{}}}"#,
+            ),
+            id, frames, weights
+        )
+    }
+
+    fn sample(timestamp: usize, period: u64, stack_id: &str) -> String {
+        format!(
+            concat!(
+                r#"{{"type":"sample","timestamp":{}.0,"pid":1,"tid":1,"cpu":0,"#,
+                r#""event":"cycles","period":{},"stack_id":"{}"}}"#,
+            ),
+            timestamp, period, stack_id
+        )
+    }
+
+    fn sample_file() -> SpaaFile {
+        let mut lines = vec![
+            header("period"),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#.to_string(),
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#.to_string(),
+        ];
+        // Five ordinary stacks of 10 samples x ~1000, and one stack whose
+        // single sample carries a huge period.
+        for (i, period) in [10_000, 10_100, 9_900, 10_050, 9_950].iter().enumerate() {
+            let weights = weights(&[("period", *period), ("samples", 10)]);
+            lines.push(stack(&format!("0x{}", i), "[1]", &weights));
+        }
+        let weights = weights(&[("period", 2_000_000), ("samples", 1)]);
+        lines.push(stack("0xbad", "[1]", &weights));
+        for (i, period) in [1000, 1010, 990, 1000, 1005, 2_000_000].iter().enumerate() {
+            let stack = if *period > 100_000 { "0xbad" } else { "0x0" };
+            lines.push(sample(i, *period, stack));
+        }
+        SpaaFile::parse(Cursor::new(lines.join("\n"))).unwrap()
+    }
+
+    /// One stack on `work` whose six samples include a lone 2M-period
+    /// spike, with exclusive weights and a window entry matching the stack.
+    fn spike_file(primary_metric: &str) -> SpaaFile {
+        let total = if primary_metric == "samples" {
+            weights(&[("samples", 6)])
+        } else {
+            weights(&[("period", 2_005_000), ("samples", 6)])
+        };
+        let mut lines = vec![
+            header(primary_metric),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#.to_string(),
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#.to_string(),
+            r#"{"type":"frame","id":2,"func":"work","dso":1}"#.to_string(),
+            format!(
+                concat!(
+                    r#"{{"type":"stack","id":"0x1","frames":[2,1],"context":{{"event":"cycles"}},"#,
+                    r#""weights":{0},"exclusive":{{"frame":2,"weights":{0}}}}}"#,
+                ),
+                total
+            ),
+            format!(
+                concat!(
+                    r#"{{"type":"window","id":"w1","start":0.0,"end":10.0,"unit":"seconds","#,
+                    r#""by_stack":[{{"stack_id":"0x1","weights":{}}}]}}"#,
+                ),
+                total
+            ),
+        ];
+        for (i, period) in [1000, 1000, 1000, 1000, 1000, 2_000_000].iter().enumerate() {
+            lines.push(sample(i, *period, "0x1"));
+        }
+        SpaaFile::parse(Cursor::new(lines.join("\n"))).unwrap()
+    }
+
+    #[test]
+    fn detects_sample_with_huge_period() {
+        let report = detect_outliers(&sample_file(), &OutlierOptions::default());
+
+        assert_eq!(report.samples.len(), 1);
+        assert_eq!(report.samples[0].index, 5);
+        assert_eq!(report.samples[0].value, 2_000_000);
+    }
+
+    #[test]
+    fn detects_stack_with_huge_weight_per_sample() {
+        let report = detect_outliers(&sample_file(), &OutlierOptions::default());
+
+        assert_eq!(report.stacks.len(), 1);
+        assert_eq!(report.stacks[0].stack_id, "0xbad");
+    }
+
+    #[test]
+    fn small_populations_are_skipped() {
+        let options = OutlierOptions {
+            min_population: 100,
+            ..Default::default()
+        };
+        assert!(detect_outliers(&sample_file(), &options).is_empty());
+    }
+
+    #[test]
+    fn exclude_removes_stacks_and_adjusts_weights() {
+        let mut file = sample_file();
+        let mut report = detect_outliers(&file, &OutlierOptions::default());
+        // Pretend the bad sample belonged to an ordinary stack.
+        report.stacks.clear();
+        report.samples[0].stack_id = "0x0".to_string();
+
+        exclude_outliers(&mut file, &report);
+
+        assert_eq!(file.samples.len(), 5);
+        let stack = &file.stacks["0x0"];
+        assert_eq!(stack_weight(stack, "period"), 0);
+        assert_eq!(stack_weight(stack, "samples"), 9);
+    }
+
+    #[test]
+    fn exclude_drops_outlier_stacks_and_their_samples() {
+        let mut file = sample_file();
+        let report = detect_outliers(&file, &OutlierOptions::default());
+
+        exclude_outliers(&mut file, &report);
+
+        assert!(!file.stacks.contains_key("0xbad"));
+        assert!(file.samples.iter().all(|s| s.stack_id != "0xbad"));
+    }
+
+    #[test]
+    fn lone_spike_among_identical_values_is_flagged() {
+        let mut file = spike_file("period");
+
+        let report = detect_outliers(&file, &OutlierOptions::default());
+        assert_eq!(report.samples.len(), 1);
+        assert_eq!(report.samples[0].score, 2000.0);
+
+        // The stack, its exclusive weights and its window entry all lose
+        // the spike, so the file stays valid.
+        exclude_outliers(&mut file, &report);
+        let mut out = Vec::new();
+        file.write(&mut out).unwrap();
+        let file = SpaaFile::parse_slice(&out).unwrap();
+        let stack = &file.stacks["0x1"];
+        assert_eq!(stack_weight(stack, "period"), 5000);
+        assert_eq!(stack.exclusive.as_ref().unwrap().weights[0].value, 5000);
+        assert_eq!(file.windows[0].by_stack[0].weights[0].value, 5000);
+        assert_eq!(file.windows[0].by_stack[0].weights[1].value, 5);
+    }
+
+    #[test]
+    fn excluding_from_samples_primary_events_drops_one_sample() {
+        let mut file = spike_file("samples");
+
+        let report = detect_outliers(&file, &OutlierOptions::default());
+        assert_eq!(report.samples.len(), 1);
+
+        exclude_outliers(&mut file, &report);
+        let stack = &file.stacks["0x1"];
+        assert_eq!(stack_weight(stack, "samples"), 5);
+        assert_eq!(stack.exclusive.as_ref().unwrap().weights[0].value, 5);
+        assert_eq!(file.windows[0].by_stack[0].weights[0].value, 5);
+    }
+}
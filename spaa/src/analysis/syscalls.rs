@@ -0,0 +1,309 @@
+//! Syscall latency from paired entry/exit probes.
+//!
+//! Entry and exit probes are matched per thread using raw `sample` records,
+//! so the input must carry timestamps (for example
+//! [`PerfConverter::with_samples`](crate::perf::PerfConverter::with_samples)).
+//! Recognized event names:
+//!
+//! - perf tracepoints: `syscalls:sys_enter_read` / `syscalls:sys_exit_read`
+//! - DTrace probes: `syscall::read:entry` / `syscall::read:return`
+
+use serde::Serialize;
+use spaa_parse::{
+    EventDef, EventKind, ProbeContext, Sampling, SamplingMode, SpaaFile, Stack, StackContext,
+    Weight,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+
+/// Name of the event added by [`pair_syscalls`].
+pub const SYSCALL_LATENCY_EVENT: &str = "syscall_latency";
+
+/// Primary metric of [`SYSCALL_LATENCY_EVENT`] stacks.
+pub const DURATION_METRIC: &str = "duration_ns";
+
+/// Latency distribution for one syscall.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SyscallLatency {
+    pub syscall: String,
+    pub calls: u64,
+    pub total_ns: u64,
+    pub min_ns: u64,
+    pub max_ns: u64,
+    pub p50_ns: u64,
+    pub p90_ns: u64,
+    pub p99_ns: u64,
+}
+
+/// Result of [`pair_syscalls`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SyscallReport {
+    /// Per-syscall distributions, ordered by descending total latency.
+    pub syscalls: Vec<SyscallLatency>,
+    /// Entry probes with no matching exit.
+    pub unmatched_entries: u64,
+    /// Exit probes with no matching entry.
+    pub unmatched_exits: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edge {
+    Entry,
+    Exit,
+}
+
+/// Classify an event name as a syscall entry or exit probe.
+fn classify(event: &str) -> Option<(&str, Edge)> {
+    if let Some(rest) = event.strip_prefix("syscalls:") {
+        if let Some(name) = rest.strip_prefix("sys_enter_") {
+            return Some((name, Edge::Entry));
+        }
+        if let Some(name) = rest.strip_prefix("sys_exit_") {
+            return Some((name, Edge::Exit));
+        }
+        return None;
+    }
+    if let Some(rest) = event.strip_prefix("syscall:") {
+        // provider:module:function:name, with an empty module.
+        let mut parts = rest.splitn(3, ':');
+        let (_module, function, name) = (parts.next()?, parts.next()?, parts.next()?);
+        if function.is_empty() {
+            return None;
+        }
+        return match name {
+            "entry" => Some((function, Edge::Entry)),
+            "return" => Some((function, Edge::Exit)),
+            _ => None,
+        };
+    }
+    None
+}
+
+/// Pair syscall entry/exit samples and add their latencies to `file`.
+///
+/// Each completed call is attributed to the stack recorded at entry. A
+/// [`SYSCALL_LATENCY_EVENT`] event is added to the header with one stack per
+/// (syscall, calling stack) pair, weighted by `duration_ns` and `calls`.
+/// Returns per-syscall latency distributions.
+pub fn pair_syscalls(file: &mut SpaaFile) -> SyscallReport {
+    let mut samples: Vec<_> = file
+        .samples
+        .iter()
+        .filter_map(|s| classify(&s.event).map(|(name, edge)| (s, name, edge)))
+        .collect();
+    samples.sort_by(|a, b| a.0.timestamp.total_cmp(&b.0.timestamp));
+
+    let mut report = SyscallReport::default();
+    // tid -> open calls (syscall, entry timestamp, entry stack)
+    let mut pending: HashMap<u64, Vec<(&str, f64, &str)>> = HashMap::new();
+    let mut durations: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+    // (syscall, entry stack) -> (total ns, calls)
+    let mut by_stack: BTreeMap<(String, String), (u64, u64)> = BTreeMap::new();
+
+    for (sample, name, edge) in samples {
+        let open = pending.entry(sample.tid).or_default();
+        match edge {
+            Edge::Entry => open.push((name, sample.timestamp, sample.stack_id.as_str())),
+            Edge::Exit => {
+                let Some(pos) = open.iter().rposition(|(n, _, _)| *n == name) else {
+                    report.unmatched_exits += 1;
+                    continue;
+                };
+                // Anything opened after the matching entry never returned.
+                report.unmatched_entries += (open.len() - pos - 1) as u64;
+                let (_, start, stack_id) = open[pos];
+                open.truncate(pos);
+
+                let ns = ((sample.timestamp - start).max(0.0) * 1e9).round() as u64;
+                durations.entry(name.to_string()).or_default().push(ns);
+                let entry = by_stack
+                    .entry((name.to_string(), stack_id.to_string()))
+                    .or_default();
+                entry.0 += ns;
+                entry.1 += 1;
+            }
+        }
+    }
+    report.unmatched_entries += pending.values().map(|v| v.len() as u64).sum::<u64>();
+
+    for (syscall, mut values) in durations {
+        values.sort_unstable();
+        let pct = |p: f64| values[((values.len() - 1) as f64 * p).round() as usize];
+        report.syscalls.push(SyscallLatency {
+            calls: values.len() as u64,
+            total_ns: values.iter().sum(),
+            min_ns: values[0],
+            max_ns: values[values.len() - 1],
+            p50_ns: pct(0.5),
+            p90_ns: pct(0.9),
+            p99_ns: pct(0.99),
+            syscall,
+        });
+    }
+    report
+        .syscalls
+        .sort_by(|a, b| b.total_ns.cmp(&a.total_ns).then(a.syscall.cmp(&b.syscall)));
+
+    if by_stack.is_empty() {
+        return report;
+    }
+
+    if !file
+        .header
+        .events
+        .iter()
+        .any(|e| e.name == SYSCALL_LATENCY_EVENT)
+    {
+        file.header.events.push(EventDef {
+            name: SYSCALL_LATENCY_EVENT.to_string(),
+            kind: EventKind::Probe,
+            sampling: Sampling {
+                mode: SamplingMode::Event,
+                primary_metric: DURATION_METRIC.to_string(),
+                sample_period: None,
+                frequency_hz: None,
+            },
+            allocation_tracking: None,
+            description: Some("Time between syscall entry and return".to_string()),
+            unit: Some("nanoseconds".to_string()),
+        });
+    }
+
+    for ((syscall, entry_stack), (total_ns, calls)) in by_stack {
+        let Some(entry) = file.stacks.get(&entry_stack) else {
+            continue;
+        };
+        let frames = entry.frames.clone();
+        let stack_type = entry.stack_type;
+        let mut context = StackContext {
+            event: SYSCALL_LATENCY_EVENT.to_string(),
+            probe: Some(ProbeContext {
+                provider: "syscall".to_string(),
+                module: String::new(),
+                function: syscall.clone(),
+                name: "latency".to_string(),
+            }),
+            ..entry.context.clone()
+        };
+        context.trace_fields = None;
+
+        let mut hasher = DefaultHasher::new();
+        SYSCALL_LATENCY_EVENT.hash(&mut hasher);
+        syscall.hash(&mut hasher);
+        entry_stack.hash(&mut hasher);
+        let id = format!("0x{:016x}", hasher.finish());
+
+        file.stacks.insert(
+            id.clone(),
+            Stack {
+                id,
+                frames,
+                stack_type,
+                context,
+                weights: vec![
+                    Weight {
+                        metric: DURATION_METRIC.to_string(),
+                        value: total_ns,
+                        unit: Some("nanoseconds".to_string()),
+                    },
+                    Weight {
+                        metric: "calls".to_string(),
+                        value: calls,
+                        unit: None,
+                    },
+                ],
+                exclusive: None,
+                related_stacks: None,
+            },
+        );
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::stack_weight;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let mut lines = vec![
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"syscalls:sys_enter_read","kind":"probe","sampling":{"mode":"event","primary_metric":"samples"}},{"name":"syscalls:sys_exit_read","kind":"probe","sampling":{"mode":"event","primary_metric":"samples"}}]}"#.to_string(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#.to_string(),
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#.to_string(),
+            r#"{"type":"frame","id":2,"func":"read_config","dso":1}"#.to_string(),
+            r#"{"type":"stack","id":"0xe","frames":[2,1],"context":{"event":"syscalls:sys_enter_read","tid":7},"weights":[{"metric":"samples","value":3}]}"#.to_string(),
+            r#"{"type":"stack","id":"0xx","frames":[1],"context":{"event":"syscalls:sys_exit_read","tid":7},"weights":[{"metric":"samples","value":3}]}"#.to_string(),
+        ];
+        // Three reads of 1ms, 2ms and 9ms on tid 7, plus a dangling entry.
+        for (enter, exit) in [(1.0, 1.001), (2.0, 2.002), (3.0, 3.009)] {
+            lines.push(format!(
+                r#"{{"type":"sample","timestamp":{},"pid":7,"tid":7,"cpu":0,"event":"syscalls:sys_enter_read","stack_id":"0xe"}}"#,
+                enter
+            ));
+            lines.push(format!(
+                r#"{{"type":"sample","timestamp":{},"pid":7,"tid":7,"cpu":0,"event":"syscalls:sys_exit_read","stack_id":"0xx"}}"#,
+                exit
+            ));
+        }
+        lines.push(r#"{"type":"sample","timestamp":4.0,"pid":7,"tid":7,"cpu":0,"event":"syscalls:sys_enter_read","stack_id":"0xe"}"#.to_string());
+        SpaaFile::parse(Cursor::new(lines.join("\n"))).unwrap()
+    }
+
+    #[test]
+    fn classify_perf_and_dtrace_names() {
+        assert_eq!(
+            classify("syscalls:sys_enter_openat"),
+            Some(("openat", Edge::Entry))
+        );
+        assert_eq!(
+            classify("syscalls:sys_exit_read"),
+            Some(("read", Edge::Exit))
+        );
+        assert_eq!(
+            classify("syscall::write:return"),
+            Some(("write", Edge::Exit))
+        );
+        assert_eq!(classify("syscall:::entry"), None);
+        assert_eq!(classify("cycles"), None);
+    }
+
+    #[test]
+    fn pairs_entries_with_exits() {
+        let mut file = sample_file();
+        let report = pair_syscalls(&mut file);
+
+        assert_eq!(report.syscalls.len(), 1);
+        let read = &report.syscalls[0];
+        assert_eq!(read.syscall, "read");
+        assert_eq!(read.calls, 3);
+        assert_eq!(read.min_ns, 1_000_000);
+        assert_eq!(read.max_ns, 9_000_000);
+        assert_eq!(read.p50_ns, 2_000_000);
+        assert_eq!(report.unmatched_entries, 1);
+        assert_eq!(report.unmatched_exits, 0);
+    }
+
+    #[test]
+    fn latency_is_attributed_to_entry_stack() {
+        let mut file = sample_file();
+        pair_syscalls(&mut file);
+
+        let stacks: Vec<_> = file.stacks_for_event(SYSCALL_LATENCY_EVENT).collect();
+        assert_eq!(stacks.len(), 1);
+        assert_eq!(stacks[0].frames, vec![2, 1]);
+        assert_eq!(stack_weight(stacks[0], DURATION_METRIC), 12_000_000);
+        assert_eq!(stack_weight(stacks[0], "calls"), 3);
+        assert_eq!(
+            file.primary_metric_for_event(SYSCALL_LATENCY_EVENT),
+            Some(DURATION_METRIC)
+        );
+
+        // The augmented file still validates.
+        let mut buf = Vec::new();
+        file.write(&mut buf).unwrap();
+        SpaaFile::parse(Cursor::new(buf)).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+//! Functions ranked by self or total weight.
+
+use super::stack_weight;
+use crate::limits::{BudgetExceeded, LimitError, Limits};
+use serde::{Deserialize, Serialize};
+use spaa_parse::{FrameOrder, SpaaFile};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// Which weight [`top_functions`] ranks by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RankBy {
+    /// Weight of stacks whose leaf is the function.
+    #[default]
+    #[serde(rename = "self")]
+    SelfWeight,
+    /// Weight of stacks the function appears anywhere in.
+    #[serde(rename = "total")]
+    TotalWeight,
+}
+
+impl fmt::Display for RankBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RankBy::SelfWeight => "self",
+            RankBy::TotalWeight => "total",
+        })
+    }
+}
+
+/// Error returned when parsing an unknown [`RankBy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseRankByError(String);
+
+impl fmt::Display for ParseRankByError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown ranking '{}' (expected self or total)", self.0)
+    }
+}
+
+impl std::error::Error for ParseRankByError {}
+
+impl FromStr for RankBy {
+    type Err = ParseRankByError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "self" => Ok(RankBy::SelfWeight),
+            "total" => Ok(RankBy::TotalWeight),
+            _ => Err(ParseRankByError(s.to_string())),
+        }
+    }
+}
+
+/// A function's self and total weight within one event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FunctionWeight {
+    pub function: String,
+    pub self_weight: u64,
+    /// `self_weight` as a fraction of the event's total weight.
+    pub self_share: f64,
+    pub total_weight: u64,
+    /// `total_weight` as a fraction of the event's total weight.
+    pub total_share: f64,
+}
+
+/// The top `n` functions for `event`, weighted by `metric` and ranked by
+/// `rank`.
+///
+/// Functions are matched by name, so the same function in several DSOs is
+/// counted once. Ties fall back to the other weight, then to the name.
+pub fn top_functions(
+    file: &SpaaFile,
+    event: &str,
+    metric: &str,
+    n: usize,
+    rank: RankBy,
+) -> Vec<FunctionWeight> {
+    top_functions_limited(file, event, metric, n, rank, &Limits::default())
+        .expect("default limits are never reached")
+}
+
+/// Like [`top_functions`], checking `limits` before each stack.
+///
+/// When the memory budget runs out the error carries the ranking of the
+/// stacks visited so far, with shares of their weight alone.
+pub fn top_functions_limited(
+    file: &SpaaFile,
+    event: &str,
+    metric: &str,
+    n: usize,
+    rank: RankBy,
+    limits: &Limits,
+) -> Result<Vec<FunctionWeight>, LimitError<Vec<FunctionWeight>>> {
+    const ENTRY_BYTES: u64 = std::mem::size_of::<(&str, u64)>() as u64;
+    let mut meter = limits.meter();
+    let mut exceeded: Option<BudgetExceeded> = None;
+    let mut self_weight: HashMap<&str, u64> = HashMap::new();
+    let mut total_weight: HashMap<&str, u64> = HashMap::new();
+    let mut total = 0;
+    for stack in file.stacks_for_event(event) {
+        limits.check()?;
+        let weight = stack_weight(stack, metric);
+        let leaf = match file.header.frame_order {
+            FrameOrder::LeafToRoot => stack.frames.first(),
+            FrameOrder::RootToLeaf => stack.frames.last(),
+        };
+        let leaf = leaf.and_then(|&id| file.resolve_frame(id));
+        let mut seen = HashSet::new();
+        let functions: Vec<&str> = stack
+            .frames
+            .iter()
+            .filter_map(|&id| file.resolve_frame(id))
+            .map(|f| f.func.as_str())
+            .filter(|func| seen.insert(*func))
+            .collect();
+
+        // Account for the stack's new entries before adding any of it.
+        let new_entries = leaf.is_some_and(|f| !self_weight.contains_key(f.func.as_str())) as usize
+            + functions
+                .iter()
+                .filter(|func| !total_weight.contains_key(*func))
+                .count();
+        if let Err(e) = meter.charge(new_entries as u64 * ENTRY_BYTES) {
+            exceeded = Some(e);
+            break;
+        }
+
+        total += weight;
+        if let Some(frame) = leaf {
+            *self_weight.entry(&frame.func).or_default() += weight;
+        }
+        for func in functions {
+            *total_weight.entry(func).or_default() += weight;
+        }
+    }
+
+    let share = |w: u64| w as f64 / total.max(1) as f64;
+    let mut functions: Vec<FunctionWeight> = total_weight
+        .into_iter()
+        .map(|(function, total_weight)| {
+            let self_weight = self_weight.get(function).copied().unwrap_or(0);
+            FunctionWeight {
+                function: function.to_string(),
+                self_weight,
+                self_share: share(self_weight),
+                total_weight,
+                total_share: share(total_weight),
+            }
+        })
+        .collect();
+    functions.sort_by(|a, b| {
+        let key = |f: &FunctionWeight| match rank {
+            RankBy::SelfWeight => (f.self_weight, f.total_weight),
+            RankBy::TotalWeight => (f.total_weight, f.self_weight),
+        };
+        key(b).cmp(&key(a)).then(a.function.cmp(&b.function))
+    });
+    functions.truncate(n);
+    match exceeded {
+        Some(e) => Err(e.with_partial(functions).into()),
+        None => Ok(functions),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"render","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":70}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[3,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":30}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn ranks_by_self_weight() {
+        let functions = top_functions(&sample_file(), "cycles", "period", 10, RankBy::SelfWeight);
+        let names: Vec<&str> = functions.iter().map(|f| f.function.as_str()).collect();
+
+        assert_eq!(names, vec!["parse", "render", "main"]);
+        assert_eq!(functions[0].self_share, 0.7);
+    }
+
+    #[test]
+    fn ranks_by_total_weight() {
+        let functions = top_functions(&sample_file(), "cycles", "period", 1, RankBy::TotalWeight);
+
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0].function, "main");
+        assert_eq!(functions[0].total_weight, 100);
+    }
+}
@@ -20,10 +20,12 @@
 
 use clap::Parser;
 use spaa::chrome::{CpuProfileConverter, HeapSnapshotConverter, ProfileType, detect_profile_type};
+use spaa::cli::{CliError, ErrorArgs, LogArgs};
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::PathBuf;
 use std::process::ExitCode;
+use tracing::info;
 
 #[derive(Parser, Debug)]
 #[command(name = "chrome_to_spaa")]
@@ -36,6 +38,12 @@ struct Args {
     /// Output SPAA file (defaults to input filename with .spaa extension)
     #[arg(short, long)]
     output: Option<PathBuf>,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    log: LogArgs,
 }
 
 fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
@@ -48,10 +56,9 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
 
     // Read input file
     let input_file = File::open(&args.input).map_err(|e| {
-        format!(
-            "Failed to open input file '{}': {}",
-            args.input.display(),
-            e
+        CliError::context(
+            e,
+            format!("Failed to open input file '{}'", args.input.display()),
         )
     })?;
     let mut reader = BufReader::new(input_file);
@@ -63,10 +70,9 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
 
     // Create output file
     let output_file = File::create(&output_path).map_err(|e| {
-        format!(
-            "Failed to create output file '{}': {}",
-            output_path.display(),
-            e
+        CliError::context(
+            e,
+            format!("Failed to create output file '{}'", output_path.display()),
         )
     })?;
     let mut writer = BufWriter::new(output_file);
@@ -79,7 +85,7 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
                 ProfileType::HeapTimeline => "Chrome heap timeline",
                 _ => unreachable!(),
             };
-            eprintln!("Detected: {}", type_name);
+            info!("Detected: {}", type_name);
             let mut converter = HeapSnapshotConverter::new();
             converter.parse(std::io::Cursor::new(&contents))?;
             converter.write_spaa(&mut writer)?;
@@ -90,7 +96,7 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
                 ProfileType::CpuProfile => "V8 cpuprofile",
                 _ => unreachable!(),
             };
-            eprintln!("Detected: {}", type_name);
+            info!("Detected: {}", type_name);
             let mut converter = CpuProfileConverter::new();
             converter.parse(std::io::Cursor::new(&contents))?;
             converter.write_spaa(&mut writer)?;
@@ -99,7 +105,7 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
 
     writer.flush()?;
 
-    eprintln!(
+    info!(
         "Converted '{}' -> '{}'",
         args.input.display(),
         output_path.display()
@@ -109,13 +115,15 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
 }
 
 fn main() -> ExitCode {
-    let args = Args::parse();
+    let args: Args = match spaa::cli::parse() {
+        Ok(args) => args,
+        Err(code) => return code,
+    };
+    args.log.init();
+    let error_format = args.errors.error_format;
 
     match run(args) {
         Ok(()) => ExitCode::SUCCESS,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            ExitCode::FAILURE
-        }
+        Err(e) => CliError::from(e).report(error_format),
     }
 }
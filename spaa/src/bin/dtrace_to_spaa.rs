@@ -9,14 +9,27 @@
 //! dtrace_to_spaa input.txt -o output.spaa
 //! dtrace_to_spaa input.txt --event syscall::read:entry --frequency 0
 //! dtrace_to_spaa input.txt  # outputs to input.spaa
+//! dtrace_to_spaa dumps/ --interval 10  # one window per file, to dumps.spaa
 //! ```
+//!
+//! Given a directory, each file is read as one interval of a periodic
+//! capture such as `tick-10s { printa(@); trunc(@); }`; see
+//! [`spaa::dtrace::dump_intervals`] for how intervals are timed.
+//!
+//! Defaults for `--event`, `--frequency` and `--max-stack-depth`, plus
+//! rewrite and redaction rules, are read from `spaa.toml`; see
+//! [`spaa::config`].
 
 use clap::{Parser, ValueEnum};
-use spaa::dtrace::{ConverterConfig, DtraceConverter, InputFormat};
+use spaa::cli::{CliError, ErrorArgs, LogArgs};
+use spaa::config::Config;
+use spaa::convert::ConvertOptions;
+use spaa::dtrace::{DtraceConverter, InputFormat, dump_intervals};
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use tracing::{info, warn};
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
 enum Format {
@@ -43,7 +56,7 @@ impl From<Format> for InputFormat {
 #[command(about = "Convert DTrace output to SPAA format")]
 #[command(version)]
 struct Args {
-    /// Input DTrace output file
+    /// Input DTrace output file, or a directory of periodic dumps
     input: PathBuf,
 
     /// Output SPAA file (defaults to input filename with .spaa extension)
@@ -54,13 +67,47 @@ struct Args {
     #[arg(short, long, value_enum, default_value = "aggregated")]
     format: Format,
 
-    /// Event name for the SPAA output
-    #[arg(short, long, default_value = "profile-997")]
-    event: String,
+    /// Event name for the SPAA output (default: profile-997)
+    #[arg(short, long)]
+    event: Option<String>,
 
     /// Sampling frequency in Hz (set to 0 for event/probe-based tracing)
     #[arg(short = 'z', long)]
     frequency: Option<u64>,
+
+    /// Truncate stacks deeper than this many frames
+    #[arg(long)]
+    max_stack_depth: Option<usize>,
+
+    /// Records are keyed by execname (and optionally pid), as for
+    /// @[execname, ustack()]
+    #[arg(long)]
+    execname: bool,
+
+    /// Fail on unparsable lines and stacks without a count instead of
+    /// skipping them
+    #[arg(long)]
+    strict: bool,
+
+    /// Seconds covered by each dump in a directory input (defaults to the
+    /// gap between dump timestamps)
+    #[arg(long)]
+    interval: Option<f64>,
+
+    /// Configuration file (defaults to the nearest spaa.toml, then
+    /// ~/.config/spaa/spaa.toml)
+    #[arg(long, conflicts_with = "no_config")]
+    config: Option<PathBuf>,
+
+    /// Ignore spaa.toml files
+    #[arg(long)]
+    no_config: bool,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    log: LogArgs,
 }
 
 fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
@@ -71,54 +118,71 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
         path
     });
 
-    // Build config
-    let frequency_hz = match args.frequency {
-        Some(0) => None,
-        Some(f) => Some(f),
-        None => {
-            // Infer from event name if it matches profile-N pattern
-            if let Some(freq_str) = args.event.strip_prefix("profile-") {
-                freq_str.parse().ok()
-            } else {
-                None
-            }
-        }
-    };
-
-    let config = ConverterConfig {
-        event_name: args.event,
-        frequency_hz,
+    // Build config: flags override spaa.toml
+    let mut options = if args.no_config {
+        ConvertOptions::default()
+    } else {
+        Config::resolve(args.config.as_deref())?.0.convert
     };
-
-    // Open input
-    let input_file = File::open(&args.input).map_err(|e| {
-        format!(
-            "Failed to open input file '{}': {}",
-            args.input.display(),
-            e
-        )
-    })?;
-    let reader = BufReader::new(input_file);
+    if args.event.is_some() {
+        // A new event name must not inherit a frequency meant for another
+        options.event = args.event;
+        options.frequency = None;
+    }
+    if let Some(frequency) = args.frequency {
+        options.frequency = Some(frequency);
+    }
+    if let Some(depth) = args.max_stack_depth {
+        options.max_stack_depth = Some(depth);
+    }
+    options.execname |= args.execname;
+    options.strict |= args.strict;
+    let config = options.dtrace_config();
 
     // Parse
     let mut converter = DtraceConverter::with_config(args.format.into(), config);
-    converter.parse(reader)?;
+    if args.input.is_dir() {
+        let dumps = dump_intervals(&args.input, args.interval).map_err(|e| {
+            CliError::context(
+                e,
+                format!("Failed to read directory '{}'", args.input.display()),
+            )
+        })?;
+        for (path, interval) in &dumps {
+            let reader = BufReader::new(open_input(path)?);
+            converter
+                .parse_interval(reader, *interval)
+                .map_err(|e| CliError::context(e, path.display()))?;
+        }
+        info!("Read {} dumps from '{}'", dumps.len(), args.input.display());
+    } else {
+        converter.parse(BufReader::new(open_input(&args.input)?))?;
+    }
+
+    if converter.truncated_stacks() > 0 {
+        warn!(
+            "Truncated {} stacks deeper than {} frames",
+            converter.truncated_stacks(),
+            options.max_stack_depth.unwrap_or_default()
+        );
+    }
 
     // Create output
     let output_file = File::create(&output_path).map_err(|e| {
-        format!(
-            "Failed to create output file '{}': {}",
-            output_path.display(),
-            e
+        CliError::context(
+            e,
+            format!("Failed to create output file '{}'", output_path.display()),
         )
     })?;
     let mut writer = BufWriter::new(output_file);
 
-    // Write SPAA
-    converter.write_spaa(&mut writer)?;
+    // Write SPAA, through the rewrite and redaction passes if configured
+    let mut file = converter.to_spaa_file()?;
+    options.apply(&mut file)?;
+    file.write(&mut writer)?;
     writer.flush()?;
 
-    eprintln!(
+    info!(
         "Converted '{}' -> '{}'",
         args.input.display(),
         output_path.display()
@@ -127,14 +191,22 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn open_input(path: &Path) -> Result<File, CliError> {
+    File::open(path).map_err(|e| {
+        CliError::context(e, format!("Failed to open input file '{}'", path.display()))
+    })
+}
+
 fn main() -> ExitCode {
-    let args = Args::parse();
+    let args: Args = match spaa::cli::parse() {
+        Ok(args) => args,
+        Err(code) => return code,
+    };
+    args.log.init();
+    let error_format = args.errors.error_format;
 
     match run(args) {
         Ok(()) => ExitCode::SUCCESS,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            ExitCode::FAILURE
-        }
+        Err(e) => CliError::from(e).report(error_format),
     }
 }
@@ -5,29 +5,79 @@
 //! - Object types that grew (count and size deltas)
 //! - Retention paths for new objects (what's keeping them alive)
 //!
+//! Two SPAA files with an allocation event are compared by call path
+//! instead, showing which allocation sites grew.
+//!
+//! `--leaks` ranks likely leaks instead, from a diff of two snapshots or
+//! from the allocation windows of one SPAA heap timeline.
+//!
+//! Given a single snapshot, it reports statistics instead: constructors by
+//! retained size, the largest retainers and duplicate strings.
+//!
 //! # Usage
 //!
 //! ```bash
 //! heapdiff baseline.heapsnapshot target.heapsnapshot -o diff.ndjson
+//! heapdiff baseline.heapsnapshot target.heapsnapshot --format markdown --top 5
+//! heapdiff baseline.heapsnapshot target.heapsnapshot --format dot | dot -Tsvg > paths.svg
+//! heapdiff Heap.heapsnapshot --format markdown
+//! heapdiff before.spaa after.spaa --format markdown
+//! heapdiff baseline.heapsnapshot target.heapsnapshot --leaks
+//! heapdiff timeline.spaa --leaks --format markdown
+//! heapdiff baseline.heapsnapshot target.heapsnapshot --exclude-weak
+//! heapdiff load1.heapsnapshot load2.heapsnapshot --match-by structure
 //! ```
 
-use clap::Parser;
-use spaa::heapdiff::{HeapDiff, ParsedSnapshot};
+use clap::{Parser, ValueEnum};
+use spaa::cli::{CliError, ErrorArgs, LogArgs};
+use spaa::heapdiff::{
+    AllocationDiff, HeapDiff, HeapDiffError, ObjectMatch, ParsedSnapshot, RetentionGraph,
+    SnapshotStats, WeakRefs,
+};
+use spaa::leaks::{self, LeakSuspect, suspects_from_allocations, suspects_from_diff};
+use spaa::progress::TerminalProgress;
+use spaa_parse::{EventKind, SpaaFile};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::path::PathBuf;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    /// Type growth and retained objects, one JSON record per line
+    Ndjson,
+    /// One JSON document with type growth and grouped retention paths
+    Json,
+    /// A Markdown leak report for issues and reviews
+    Markdown,
+    /// Graphviz graph of the heaviest retention paths
+    Dot,
+    /// d3-sankey JSON of the heaviest retention paths
+    Sankey,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum MatchBy {
+    /// Node ids, stable across snapshots of one page load
+    Id,
+    /// Constructor, shape and retention path, for snapshots from different
+    /// page loads
+    Structure,
+}
 
 #[derive(Parser, Debug)]
 #[command(name = "heapdiff")]
 #[command(about = "Compare heap snapshots to find memory leaks")]
 #[command(version)]
 struct Args {
-    /// Baseline heap snapshot (before the leak)
+    /// Baseline heap snapshot or SPAA allocation profile (before the
+    /// leak), or the only snapshot to analyze
     baseline: PathBuf,
 
-    /// Target heap snapshot (after the leak)
-    target: PathBuf,
+    /// Target heap snapshot or SPAA allocation profile (after the leak);
+    /// omit to report statistics for a baseline snapshot alone
+    target: Option<PathBuf>,
 
     /// Output file (defaults to stdout)
     #[arg(short, long)]
@@ -36,66 +86,268 @@ struct Args {
     /// Maximum number of retained objects to analyze
     #[arg(short = 'n', long, default_value = "100")]
     max_retained: usize,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value = "ndjson")]
+    format: Format,
+
+    /// Only report the N fastest-growing types and their retained objects,
+    /// or N rows per statistic for a single snapshot (Markdown and
+    /// statistics show 10 when omitted)
+    #[arg(long)]
+    top: Option<usize>,
+
+    /// Rank likely leaks instead of writing the diff. With SPAA input,
+    /// pass a single allocation timeline.
+    #[arg(long)]
+    leaks: bool,
+
+    /// Ignore weak references, WeakMap and WeakSet entries, and WeakRef
+    /// and FinalizationRegistry targets when finding retention paths and
+    /// retained sizes
+    #[arg(long)]
+    exclude_weak: bool,
+
+    /// How to tell new objects from ones already in the baseline
+    #[arg(long, value_enum, default_value = "id")]
+    match_by: MatchBy,
+
+    /// Maximum distinct retention paths in `dot` and `sankey` output
+    #[arg(long, default_value = "20")]
+    max_paths: usize,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    log: LogArgs,
 }
 
 fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
-    eprintln!("Loading baseline: {}", args.baseline.display());
-    let baseline_file = File::open(&args.baseline)?;
-    let baseline = ParsedSnapshot::parse(BufReader::new(baseline_file))?;
-    eprintln!(
+    let baseline_spaa = is_spaa(&args.baseline)?;
+    if let Some(target) = &args.target
+        && is_spaa(target)? != baseline_spaa
+    {
+        return Err("cannot compare a SPAA file with a heap snapshot".into());
+    }
+    if baseline_spaa {
+        return if args.leaks {
+            allocation_leaks(&args)
+        } else {
+            allocation_diff(&args)
+        };
+    }
+
+    info!("Loading baseline: {}", args.baseline.display());
+    let baseline = load_snapshot(&args.baseline)?;
+    info!(
         "  {} nodes, {} edges",
         baseline.nodes.len(),
         baseline.edges.len()
     );
 
-    eprintln!("Loading target: {}", args.target.display());
-    let target_file = File::open(&args.target)?;
-    let target = ParsedSnapshot::parse(BufReader::new(target_file))?;
-    eprintln!(
+    let Some(target_path) = &args.target else {
+        if args.leaks {
+            return Err("--leaks needs a baseline and a target snapshot".into());
+        }
+        return stats(&args, &baseline);
+    };
+    info!("Loading target: {}", target_path.display());
+    let target = load_snapshot(target_path)?;
+    info!(
         "  {} nodes, {} edges",
         target.nodes.len(),
         target.edges.len()
     );
 
-    eprintln!("Computing diff...");
-    let diff = HeapDiff::compute(
+    info!("Computing diff...");
+    let mut diff = HeapDiff::compute(
         &baseline,
         &target,
         args.baseline.to_str().unwrap_or("baseline"),
-        args.target.to_str().unwrap_or("target"),
+        target_path.to_str().unwrap_or("target"),
         args.max_retained,
+        weak_refs(&args),
+        match args.match_by {
+            MatchBy::Id => ObjectMatch::Id,
+            MatchBy::Structure => ObjectMatch::Structure,
+        },
     );
 
-    eprintln!(
+    info!(
         "Found {} growing types, {} retained objects",
         diff.type_growth.len(),
         diff.retained_objects.len()
     );
+    if args.leaks {
+        let mut suspects = suspects_from_diff(&diff);
+        suspects.truncate(args.top.unwrap_or(10));
+        return write_leaks(&args, &suspects);
+    }
+    if let Some(top) = args.top {
+        diff.truncate(top);
+    }
 
-    // Write output
-    match args.output {
-        Some(path) => {
-            let file = File::create(&path)?;
-            let writer = BufWriter::new(file);
-            diff.write_ndjson(writer)?;
-            eprintln!("Wrote diff to {}", path.display());
-        }
-        None => {
-            diff.write_ndjson(std::io::stdout())?;
-        }
+    let mut out = output(&args)?;
+    match args.format {
+        Format::Ndjson => diff.write_ndjson(&mut out)?,
+        Format::Json => diff.write_json(&mut out, args.top.unwrap_or(usize::MAX))?,
+        Format::Markdown => diff.write_markdown(&mut out, args.top.unwrap_or(10))?,
+        Format::Dot => RetentionGraph::build(&diff, args.max_paths).write_dot(&mut out)?,
+        Format::Sankey => RetentionGraph::build(&diff, args.max_paths).write_sankey(&mut out)?,
+    }
+    out.flush()?;
+    if let Some(path) = &args.output {
+        info!("Wrote diff to {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn stats(args: &Args, snapshot: &ParsedSnapshot) -> Result<(), Box<dyn std::error::Error>> {
+    if matches!(args.format, Format::Dot | Format::Sankey) {
+        return Err("dot and sankey output need a baseline and a target snapshot".into());
+    }
+    info!("Computing statistics...");
+    let stats = SnapshotStats::compute(
+        snapshot,
+        args.baseline.to_str().unwrap_or("snapshot"),
+        args.top.unwrap_or(10),
+        weak_refs(args),
+    );
+
+    let mut out = output(args)?;
+    match args.format {
+        Format::Json => stats.write_json(&mut out)?,
+        Format::Markdown => stats.write_markdown(&mut out)?,
+        _ => stats.write_ndjson(&mut out)?,
     }
+    out.flush()?;
+    if let Some(path) = &args.output {
+        info!("Wrote statistics to {}", path.display());
+    }
+    Ok(())
+}
+
+fn allocation_diff(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(target_path) = &args.target else {
+        return Err("statistics need a heap snapshot; pass a target SPAA file to diff".into());
+    };
+    if matches!(args.format, Format::Dot | Format::Sankey) {
+        return Err("dot and sankey output need heap snapshots".into());
+    }
+    info!("Loading baseline: {}", args.baseline.display());
+    let baseline = SpaaFile::open(&args.baseline)?;
+    info!("Loading target: {}", target_path.display());
+    let target = SpaaFile::open(target_path)?;
+
+    let mut diff = AllocationDiff::compute(
+        &baseline,
+        &target,
+        args.baseline.to_str().unwrap_or("baseline"),
+        target_path.to_str().unwrap_or("target"),
+    )?;
+    info!(
+        "Found {} growing call paths in '{}'",
+        diff.growth.len(),
+        diff.event
+    );
+    if let Some(top) = args.top {
+        diff.truncate(top);
+    }
+
+    let mut out = output(args)?;
+    match args.format {
+        Format::Json => diff.write_json(&mut out)?,
+        Format::Markdown => diff.write_markdown(&mut out, args.top.unwrap_or(10))?,
+        _ => diff.write_ndjson(&mut out)?,
+    }
+    out.flush()?;
+    if let Some(path) = &args.output {
+        info!("Wrote diff to {}", path.display());
+    }
+    Ok(())
+}
 
+fn allocation_leaks(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    if args.target.is_some() {
+        return Err("--leaks takes a single SPAA allocation timeline".into());
+    }
+    info!("Loading profile: {}", args.baseline.display());
+    let file = SpaaFile::open(&args.baseline)?;
+    let event = file
+        .header
+        .events
+        .iter()
+        .find(|e| e.kind == EventKind::Allocation)
+        .ok_or(HeapDiffError::NoAllocationEvent)?;
+    if file.windows.is_empty() {
+        return Err(
+            "--leaks needs a SPAA file with windows, such as a converted heap timeline".into(),
+        );
+    }
+    let suspects = suspects_from_allocations(&file, &event.name, args.top.unwrap_or(10));
+    write_leaks(args, &suspects)
+}
+
+fn write_leaks(args: &Args, suspects: &[LeakSuspect]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = output(args)?;
+    match args.format {
+        Format::Json => serde_json::to_writer_pretty(&mut out, suspects)?,
+        Format::Markdown => leaks::write_markdown(suspects, &mut out)?,
+        Format::Ndjson => leaks::write_ndjson(suspects, &mut out)?,
+        Format::Dot | Format::Sankey => {
+            return Err("--leaks supports ndjson, json and markdown output".into());
+        }
+    }
+    out.flush()?;
     Ok(())
 }
 
+/// Whether the first line of `path` is a SPAA header.
+fn weak_refs(args: &Args) -> WeakRefs {
+    if args.exclude_weak {
+        WeakRefs::Exclude
+    } else {
+        WeakRefs::Follow
+    }
+}
+
+fn is_spaa(path: &Path) -> std::io::Result<bool> {
+    let mut line = Vec::new();
+    BufReader::new(File::open(path)?)
+        .take(64 * 1024)
+        .read_until(b'\n', &mut line)?;
+    Ok(serde_json::from_slice::<serde_json::Value>(&line)
+        .is_ok_and(|header| header["type"] == "header" && header["format"] == "spaa"))
+}
+
+fn output(args: &Args) -> std::io::Result<Box<dyn Write>> {
+    Ok(match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    })
+}
+
+fn load_snapshot(path: &Path) -> Result<ParsedSnapshot, Box<dyn std::error::Error>> {
+    let file = File::open(path)?;
+    let size = file.metadata().ok().map(|m| m.len());
+    let mut progress = TerminalProgress::new();
+    let snapshot = ParsedSnapshot::parse_with_progress(BufReader::new(file), size, &mut progress)?;
+    progress.finish();
+    Ok(snapshot)
+}
+
 fn main() -> ExitCode {
-    let args = Args::parse();
+    let args: Args = match spaa::cli::parse() {
+        Ok(args) => args,
+        Err(code) => return code,
+    };
+    args.log.init();
+    let error_format = args.errors.error_format;
 
     match run(args) {
         Ok(()) => ExitCode::SUCCESS,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            ExitCode::FAILURE
-        }
+        Err(e) => CliError::from(e).report(error_format),
     }
 }
@@ -0,0 +1,149 @@
+//! `spaa bisect`: find the first profile in a series where a function
+//! regressed.
+
+use clap::{Args, ValueEnum};
+use spaa::analysis::RankBy;
+use spaa::bisect::{Bisection, Predicate, Threshold, bisect};
+use spaa::report::{ReportFormat, ReportOptions};
+use spaa_parse::SpaaFile;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct BisectArgs {
+    /// SPAA files of successive builds, oldest first
+    #[arg(required = true, num_args = 2..)]
+    inputs: Vec<PathBuf>,
+
+    /// Function to watch, matched exactly
+    #[arg(long)]
+    function: String,
+
+    /// Event to measure (defaults to the first profile's first event)
+    #[arg(long)]
+    event: Option<String>,
+
+    /// Metric to weigh stacks by (defaults to the event's primary metric)
+    #[arg(long)]
+    metric: Option<String>,
+
+    /// Measure the function's self or total weight
+    #[arg(long, value_enum, default_value = "self")]
+    by: By,
+
+    /// Regressed once the function's share of the event is at least this
+    /// fraction, e.g. 0.05
+    #[arg(long, conflicts_with = "growth", required_unless_present = "growth")]
+    share: Option<f64>,
+
+    /// Regressed once the function's share grew by at least this fraction
+    /// over the first profile's, e.g. 0.5
+    #[arg(long)]
+    growth: Option<f64>,
+
+    /// Number of changed functions and stacks to list
+    #[arg(long, default_value = "5")]
+    top: usize,
+
+    /// Emit JSON instead of a text summary
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum By {
+    /// Stacks whose leaf is the function
+    #[value(name = "self")]
+    SelfWeight,
+    /// Stacks the function appears anywhere in
+    Total,
+}
+
+pub fn run(args: BisectArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let profiles = args
+        .inputs
+        .iter()
+        .map(SpaaFile::open)
+        .collect::<Result<Vec<_>, _>>()?;
+    let event = match args.event {
+        Some(event) => event,
+        None => profiles[0]
+            .header
+            .events
+            .first()
+            .map(|e| e.name.clone())
+            .ok_or("the first profile has no events")?,
+    };
+    let threshold = match (args.share, args.growth) {
+        (Some(share), _) => Threshold::Share(share),
+        (None, Some(growth)) => Threshold::Growth(growth),
+        (None, None) => unreachable!("clap requires --share or --growth"),
+    };
+    let predicate = Predicate {
+        metric: args.metric,
+        rank: match args.by {
+            By::SelfWeight => RankBy::SelfWeight,
+            By::Total => RankBy::TotalWeight,
+        },
+        ..Predicate::new(&args.function, &event, threshold)
+    };
+    let options = ReportOptions {
+        top: args.top,
+        stacks: args.top,
+        ..Default::default()
+    };
+    let result = bisect(&profiles, &predicate, &options);
+
+    let mut out = std::io::stdout().lock();
+    if args.json {
+        serde_json::to_writer_pretty(&mut out, &result)?;
+        writeln!(out)?;
+    } else {
+        print(&mut out, &args.inputs, &result)?;
+    }
+    Ok(())
+}
+
+fn print<W: Write>(out: &mut W, inputs: &[PathBuf], result: &Bisection) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "`{}` {} share of {}:",
+        result.predicate.function, result.predicate.rank, result.predicate.event
+    )?;
+    for point in &result.points {
+        writeln!(
+            out,
+            "  {} {:>6.1}%  {}{}",
+            if point.regressed { "bad " } else { "good" },
+            point.share * 100.0,
+            inputs[point.index].display(),
+            point
+                .git_sha
+                .as_ref()
+                .map(|sha| format!(" ({sha})"))
+                .unwrap_or_default()
+        )?;
+    }
+    writeln!(out)?;
+
+    let Some(bad) = result.first_bad else {
+        return writeln!(out, "No profile matches the regression condition.");
+    };
+    writeln!(out, "First regressed profile: {}", inputs[bad].display())?;
+    let Some(good) = result.last_good else {
+        return writeln!(
+            out,
+            "The first profile already matches; there is nothing to compare it with."
+        );
+    };
+    if let (Some(good_sha), Some(bad_sha)) =
+        (&result.points[good].git_sha, &result.points[bad].git_sha)
+    {
+        writeln!(out, "  git bisect start {bad_sha} {good_sha}")?;
+    }
+    if let Some(changes) = &result.changes {
+        writeln!(out)?;
+        changes.write(&mut *out, ReportFormat::Text)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+//! `spaa bundle`: pack a profile and its analysis into one archive.
+
+use super::store::unix_now;
+use clap::{Args, ValueEnum};
+use spaa::bundle::{BundleFormat, BundleOptions, write_bundle};
+use spaa::report::ReportOptions;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Args, Debug)]
+pub struct BundleArgs {
+    /// Input SPAA file
+    input: PathBuf,
+
+    /// Archive format (defaults to the output's extension, else tar.gz)
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+
+    /// Fidelity report to include (defaults to the input with a
+    /// .report.json extension, if it exists)
+    #[arg(long)]
+    fidelity: Option<PathBuf>,
+
+    /// Number of hotspots listed per event in the report
+    #[arg(long, default_value = "10")]
+    top: usize,
+
+    /// Output file (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    /// A gzipped tarball
+    #[value(name = "tar.gz")]
+    TarGz,
+    /// A zip archive
+    Zip,
+}
+
+pub fn run(args: BundleArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read(&args.input)?;
+    let format = match args.format {
+        Some(Format::TarGz) => BundleFormat::TarGz,
+        Some(Format::Zip) => BundleFormat::Zip,
+        None => args
+            .output
+            .as_deref()
+            .and_then(BundleFormat::from_path)
+            .unwrap_or_default(),
+    };
+    let fidelity = match &args.fidelity {
+        Some(path) => Some(std::fs::read(path)?),
+        None => std::fs::read(args.input.with_extension("report.json")).ok(),
+    };
+    let options = BundleOptions {
+        format,
+        report: ReportOptions {
+            top: args.top,
+            ..Default::default()
+        },
+        fidelity,
+        created: unix_now(),
+    };
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let manifest = write_bundle(&data, &options, &mut out)?;
+    out.flush()?;
+    if let Some(path) = &args.output {
+        info!(
+            "Wrote {} files to {}",
+            manifest.files.len() + 1,
+            path.display()
+        );
+    }
+    Ok(())
+}
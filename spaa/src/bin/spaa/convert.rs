@@ -0,0 +1,473 @@
+//! `spaa convert`: convert profiler output to SPAA, one file or a batch.
+
+use clap::Args;
+use spaa::Converter;
+use spaa::cli::{CliError, ErrorKind};
+use spaa::config::{Config, ConfigError};
+use spaa::convert::{
+    BatchConverter, ConvertOptions, FidelityReport, FileStatus, SourceFormat, WindowPolicy,
+    convert_with, convert_with_report,
+};
+use spaa::perf::PerfConverter;
+use spaa::plugin::{self, Plugin};
+use spaa::store::{AddOptions, Store};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    /// Profiler output to convert; quoted glob patterns such as
+    /// 'profiles/*.cpuprofile' are expanded
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Input format: perf, dtrace, chrome, turbopack or a plugin name
+    /// (inferred from the file name or contents when omitted)
+    #[arg(short, long)]
+    format: Option<String>,
+
+    /// Output file (defaults to the input with a .spaa extension)
+    #[arg(short, long, conflicts_with = "out_dir")]
+    output: Option<PathBuf>,
+
+    /// Convert every input into this directory
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+
+    /// Worker threads for --out-dir (defaults to the number of CPUs)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// Reconvert inputs whose output in --out-dir is already up to date
+    #[arg(long)]
+    force: bool,
+
+    /// Cache conversions in this store, skipping inputs already converted
+    /// by the same converter version
+    #[arg(long, conflicts_with = "out_dir")]
+    store: Option<PathBuf>,
+
+    /// Event name for DTrace input
+    #[arg(long)]
+    event: Option<String>,
+
+    /// Sampling frequency in Hz for DTrace input (0 for probe-based tracing)
+    #[arg(long)]
+    frequency: Option<u64>,
+
+    /// Truncate perf and DTrace stacks deeper than this many frames
+    #[arg(long)]
+    max_stack_depth: Option<usize>,
+
+    /// Stream perf input, writing a window of aggregated stacks every this
+    /// many seconds; `-` reads standard input
+    #[arg(long, conflicts_with_all = ["out_dir", "store"])]
+    window: Option<f64>,
+
+    /// Stream perf input, writing a window every this many samples
+    #[arg(long, conflicts_with_all = ["out_dir", "store"])]
+    window_samples: Option<u64>,
+
+    /// Stream perf input tracking at most this many stacks, so memory stays
+    /// bounded; totals of the stacks kept become estimates
+    #[arg(long, conflicts_with_all = ["out_dir", "store"])]
+    max_stacks: Option<usize>,
+
+    /// DTrace records are keyed by execname (and optionally pid), as for
+    /// @[execname, ustack()]
+    #[arg(long)]
+    execname: bool,
+
+    /// Normalize function names so they match across builds
+    #[arg(long)]
+    normalize_symbols: bool,
+
+    /// Rebase timestamps to Unix time, when the input records how its clock
+    /// relates to wall-clock time (perf record -k with perf script --header)
+    #[arg(long)]
+    wall_clock: bool,
+
+    /// Record this machine's hostname, OS, CPU and memory when the input
+    /// does not say which host it was recorded on
+    #[arg(long)]
+    system_info: bool,
+
+    /// Fail on perf and DTrace input that would otherwise be skipped, such
+    /// as unparsable frame lines and stacks without a count
+    #[arg(long)]
+    strict: bool,
+
+    /// Write a JSON fidelity report of records read, samples dropped and
+    /// unparsed lines (defaults to the output with a .report.json
+    /// extension)
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        conflicts_with_all = ["out_dir", "store", "window", "window_samples", "max_stacks"]
+    )]
+    report: Option<Option<PathBuf>>,
+
+    /// Configuration file (defaults to the nearest spaa.toml, then
+    /// ~/.config/spaa/spaa.toml)
+    #[arg(long, conflicts_with = "no_config")]
+    config: Option<PathBuf>,
+
+    /// Ignore spaa.toml files
+    #[arg(long)]
+    no_config: bool,
+}
+
+/// The converter chosen for an input.
+enum Format {
+    Builtin(SourceFormat),
+    Plugin(Box<Plugin>),
+}
+
+impl ConvertArgs {
+    /// The configuration file, with its convert options overridden by flags.
+    fn config(&self) -> Result<Config, ConfigError> {
+        let mut config = if self.no_config {
+            Config::default()
+        } else {
+            Config::resolve(self.config.as_deref())?.0
+        };
+        let options = &mut config.convert;
+        if let Some(event) = &self.event {
+            options.event = Some(event.clone());
+        }
+        if let Some(frequency) = self.frequency {
+            options.frequency = Some(frequency);
+        }
+        if let Some(depth) = self.max_stack_depth {
+            options.max_stack_depth = Some(depth);
+        }
+        options.execname |= self.execname;
+        options.normalize_symbols |= self.normalize_symbols;
+        options.strict |= self.strict;
+        options.wall_clock |= self.wall_clock;
+        options.system_info |= self.system_info;
+        Ok(config)
+    }
+
+    /// The format named by `--format`, or else the one `input` looks like,
+    /// trying plugins when no built-in converter recognizes it.
+    fn format(
+        &self,
+        input: &Path,
+        data: &[u8],
+        plugins: Vec<Plugin>,
+    ) -> Result<Format, Box<dyn std::error::Error>> {
+        if let Some(name) = &self.format {
+            if let Ok(format) = name.parse() {
+                return Ok(Format::Builtin(format));
+            }
+            let names: Vec<String> = plugins.iter().map(|p| p.name().to_string()).collect();
+            return plugins
+                .into_iter()
+                .find(|p| p.name() == name)
+                .map(|p| Format::Plugin(Box::new(p)))
+                .ok_or_else(|| {
+                    let mut expected = vec!["perf", "dtrace", "chrome", "turbopack"];
+                    expected.extend(names.iter().map(String::as_str));
+                    CliError::new(
+                        ErrorKind::Usage,
+                        format!(
+                            "unknown format '{}' (expected {})",
+                            name,
+                            expected.join(", ")
+                        ),
+                    )
+                    .into()
+                });
+        }
+        match SourceFormat::infer(input, data) {
+            Ok(format) => Ok(Format::Builtin(format)),
+            Err(e) => plugins
+                .into_iter()
+                .filter_map(|p| Some((p.detect(data)?, p)))
+                .reduce(|best, p| if p.0 > best.0 { p } else { best })
+                .map(|(_, p)| Format::Plugin(Box::new(p)))
+                .ok_or_else(|| e.into()),
+        }
+    }
+}
+
+pub fn run(args: ConvertArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let inputs = expand(&args.inputs)?;
+    let config = args.config()?;
+    let options = config.convert;
+    if let Some(out_dir) = &args.out_dir {
+        return run_batch(&args, options, out_dir, &inputs);
+    }
+    if args.window.is_some() || args.window_samples.is_some() || args.max_stacks.is_some() {
+        return run_stream(&args, &options, &inputs);
+    }
+    let [input] = inputs.as_slice() else {
+        return Err(format!(
+            "{} inputs given; use --out-dir to convert more than one",
+            inputs.len()
+        )
+        .into());
+    };
+    let output = args.output.clone().unwrap_or_else(|| {
+        let mut path = input.clone();
+        path.set_extension("spaa");
+        path
+    });
+    let data = std::fs::read(input)
+        .map_err(|e| CliError::context(e, format!("Failed to read '{}'", input.display())))?;
+    let format = args.format(input, &data, plugin::discover(&config.plugins))?;
+
+    let plugin = match format {
+        Format::Builtin(format) => {
+            return convert_builtin(&args, format, &data, &options, input, &output);
+        }
+        Format::Plugin(plugin) => plugin,
+    };
+    convert_plugin(&args, *plugin, &data, &options, input, &output)
+}
+
+fn convert_builtin(
+    args: &ConvertArgs,
+    format: SourceFormat,
+    data: &[u8],
+    options: &ConvertOptions,
+    input: &Path,
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match &args.store {
+        Some(root) => {
+            let mut store = Store::open(root)?;
+            let converted = store.convert(format, data, options, add_options(input))?;
+            std::fs::copy(store.path(&converted.entry), output)?;
+            info!(
+                "{} {} -> {}{}",
+                &converted.entry.id[..12],
+                input.display(),
+                output.display(),
+                if converted.cached { " (cached)" } else { "" }
+            );
+        }
+        None if args.report.is_some() => {
+            let (spaa, report) = convert_with_report(format, data, options)?;
+            std::fs::write(output, spaa)?;
+            info!("Wrote {}", output.display());
+            write_report(args, output, &report)?;
+        }
+        None => {
+            std::fs::write(output, convert_with(format, data, options)?)?;
+            info!("Wrote {}", output.display());
+        }
+    }
+    Ok(())
+}
+
+/// Convert with a plugin. Plugin output is not versioned, so `--store`
+/// adds the result without caching the conversion.
+fn convert_plugin(
+    args: &ConvertArgs,
+    mut plugin: Plugin,
+    mut data: &[u8],
+    options: &ConvertOptions,
+    input: &Path,
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!(
+        "Converting {} with plugin '{}' ({})",
+        input.display(),
+        plugin.name(),
+        plugin.program().display()
+    );
+    let input_bytes = data.len() as u64;
+    plugin.parse(&mut data)?;
+    let mut file = plugin.to_spaa_file()?;
+    options.apply(&mut file)?;
+    let report = FidelityReport::new(plugin.name(), input_bytes, plugin.fidelity(), &file);
+    let mut spaa = Vec::new();
+    file.write(&mut spaa)?;
+    match &args.store {
+        Some(root) => {
+            let mut store = Store::open(root)?;
+            let added = store.add(&spaa, add_options(input))?;
+            std::fs::copy(store.path(&added.entry), output)?;
+            info!(
+                "{} {} -> {}",
+                &added.entry.id[..12],
+                input.display(),
+                output.display()
+            );
+        }
+        None => {
+            std::fs::write(output, spaa)?;
+            info!("Wrote {}", output.display());
+        }
+    }
+    if args.report.is_some() {
+        write_report(args, output, &report)?;
+    }
+    Ok(())
+}
+
+/// Write the `--report` sidecar for the conversion to `output`, warning if
+/// anything was lost.
+fn write_report(
+    args: &ConvertArgs,
+    output: &Path,
+    report: &FidelityReport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = match &args.report {
+        Some(Some(path)) => path.clone(),
+        _ => output.with_extension("report.json"),
+    };
+    let mut out = BufWriter::new(File::create(&path)?);
+    serde_json::to_writer_pretty(&mut out, report)?;
+    writeln!(out)?;
+    out.flush()?;
+    if let Some(fidelity) = report.fidelity.as_ref().filter(|_| report.is_lossy()) {
+        warn!(
+            "{} of {} samples dropped, {} lines unparsed, {} events lost",
+            fidelity.samples_dropped,
+            fidelity.samples_read,
+            fidelity.unparsed_lines,
+            fidelity.lost_events
+        );
+    }
+    info!("Wrote fidelity report {}", path.display());
+    Ok(())
+}
+
+fn add_options(input: &Path) -> AddOptions {
+    AddOptions {
+        name: input.file_name().map(|n| n.to_string_lossy().into_owned()),
+        ..Default::default()
+    }
+}
+
+/// Convert perf output as it arrives, for inputs that may never end.
+fn run_stream(
+    args: &ConvertArgs,
+    options: &ConvertOptions,
+    inputs: &[PathBuf],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = |message: &str| CliError::new(ErrorKind::Usage, message.to_string());
+    if args.format.as_deref().is_some_and(|f| f != "perf") {
+        return Err(
+            usage("--window, --window-samples and --max-stacks only stream perf input").into(),
+        );
+    }
+    if options.has_transforms() {
+        return Err(usage(
+            "symbol normalization, wall-clock rebasing, rewrite and redaction rules cannot be applied to streamed output",
+        )
+        .into());
+    }
+    let [input] = inputs else {
+        return Err(usage("streaming takes exactly one input").into());
+    };
+    let stdin = input.as_os_str() == "-";
+    let reader: Box<dyn Read> =
+        if stdin {
+            Box::new(std::io::stdin().lock())
+        } else {
+            Box::new(File::open(input).map_err(|e| {
+                CliError::context(e, format!("Failed to read '{}'", input.display()))
+            })?)
+        };
+    let output = match (&args.output, stdin) {
+        (Some(path), _) => Some(path.clone()),
+        (None, true) => None,
+        (None, false) => Some(input.with_extension("spaa")),
+    };
+    let writer: Box<dyn Write> = match &output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+
+    let mut converter = PerfConverter::new();
+    if let Some(depth) = options.max_stack_depth {
+        converter = converter.with_max_stack_depth(depth);
+    }
+    if options.strict {
+        converter = converter.with_strict();
+    }
+    let policy = WindowPolicy {
+        seconds: args.window,
+        samples: args.window_samples,
+        max_stacks: args.max_stacks,
+    };
+    converter.stream(reader, writer, policy)?;
+    if let Some(path) = output {
+        info!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+fn run_batch(
+    args: &ConvertArgs,
+    options: ConvertOptions,
+    out_dir: &Path,
+    inputs: &[PathBuf],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut converter = BatchConverter::new(out_dir);
+    converter.format = match &args.format {
+        Some(name) => Some(name.parse().map_err(|e| {
+            CliError::new(
+                ErrorKind::Usage,
+                format!("{} (plugins cannot be used with --out-dir)", e),
+            )
+        })?),
+        None => None,
+    };
+    converter.options = options;
+    converter.force = args.force;
+    if let Some(jobs) = args.jobs {
+        converter.jobs = jobs;
+    }
+    let summary = converter.run(inputs)?;
+
+    for file in &summary.files {
+        match &file.status {
+            FileStatus::Converted => {
+                println!(
+                    "converted {} -> {}",
+                    file.input.display(),
+                    file.output.display()
+                )
+            }
+            FileStatus::Skipped => println!("skipped   {} (up to date)", file.input.display()),
+            FileStatus::Failed(e) => println!("failed    {}: {}", file.input.display(), e),
+        }
+    }
+    info!(
+        "{} converted, {} skipped, {} failed",
+        summary.converted(),
+        summary.skipped(),
+        summary.failed()
+    );
+    if summary.failed() > 0 {
+        return Err(format!("{} of {} inputs failed", summary.failed(), inputs.len()).into());
+    }
+    Ok(())
+}
+
+/// Expand glob patterns; other arguments are taken as paths.
+fn expand(patterns: &[String]) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut inputs = Vec::new();
+    for pattern in patterns {
+        if !pattern.contains(['*', '?', '[']) {
+            inputs.push(PathBuf::from(pattern));
+            continue;
+        }
+        let matched = glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+        if matched.is_empty() {
+            return Err(
+                CliError::new(ErrorKind::Io, format!("no files match '{}'", pattern)).into(),
+            );
+        }
+        inputs.extend(matched);
+    }
+    Ok(inputs)
+}
@@ -0,0 +1,100 @@
+//! `spaa detect`: flag known performance pathologies in a profile.
+
+use clap::Args;
+use spaa::cli::CliError;
+use spaa::detectors::{DetectOptions, Detector, Finding, builtin_detectors, detect, load_rules};
+use spaa_parse::SpaaFile;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct DetectArgs {
+    /// Input SPAA file
+    input: PathBuf,
+
+    /// Rule file (TOML, or JSON with a `.json` extension); may be repeated
+    #[arg(long)]
+    rules: Vec<PathBuf>,
+
+    /// Run only the rules from `--rules`
+    #[arg(long)]
+    no_builtin: bool,
+
+    /// Emit JSON instead of a text summary
+    #[arg(long)]
+    json: bool,
+
+    /// Evidence stacks to show per finding
+    #[arg(long, default_value = "3")]
+    evidence: usize,
+
+    /// Output file (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub fn run(args: DetectArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let file = SpaaFile::open(&args.input)?;
+    let options = DetectOptions {
+        max_evidence: args.evidence,
+    };
+    let mut detectors: Vec<Box<dyn Detector>> = if args.no_builtin {
+        Vec::new()
+    } else {
+        builtin_detectors()
+    };
+    for path in &args.rules {
+        let rules = load_rules(path).map_err(|e| CliError::context(e, path.display()))?;
+        detectors.extend(rules.into_iter().map(|r| Box::new(r) as Box<dyn Detector>));
+    }
+    let findings = detect(&file, &detectors, &options);
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    if args.json {
+        serde_json::to_writer_pretty(&mut out, &findings)?;
+        writeln!(out)?;
+    } else {
+        print_text(&mut out, &findings)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn print_text<W: Write>(out: &mut W, findings: &[Finding]) -> std::io::Result<()> {
+    if findings.is_empty() {
+        return writeln!(out, "No known issues found.");
+    }
+    for (i, finding) in findings.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(
+            out,
+            "[{}] {} ({}): {:.1}% of {} {} across {} stacks",
+            finding.severity,
+            finding.title,
+            finding.rule,
+            finding.share * 100.0,
+            finding.event,
+            finding.metric,
+            finding.stacks
+        )?;
+        if !finding.advice.is_empty() {
+            writeln!(out, "  {}", finding.advice)?;
+        }
+        for evidence in &finding.evidence {
+            writeln!(
+                out,
+                "  {:>5.1}%  {}  {}",
+                evidence.share * 100.0,
+                evidence.stack_id,
+                evidence.functions.join(" -> ")
+            )?;
+        }
+    }
+    Ok(())
+}
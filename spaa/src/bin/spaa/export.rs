@@ -0,0 +1,127 @@
+//! `spaa export`: render a profile for viewing outside the terminal.
+
+use clap::{Args, Subcommand, ValueEnum};
+use spaa::export::compare::{CompareMode, CompareOptions, write_compare_svg};
+use spaa::export::html::{HtmlOptions, write_html};
+use spaa_parse::SpaaFile;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    #[command(subcommand)]
+    format: ExportFormat,
+}
+
+#[derive(Subcommand, Debug)]
+enum ExportFormat {
+    /// A self-contained HTML report with a flame graph, top functions,
+    /// threads and a timeline
+    Html(HtmlArgs),
+    /// An SVG comparing two events, as an overlay colored by their ratio
+    /// or as paired flame graphs
+    Compare(CompareArgs),
+}
+
+#[derive(Args, Debug)]
+struct HtmlArgs {
+    /// Input SPAA file
+    input: PathBuf,
+
+    /// Page title (defaults to the source tool)
+    #[arg(long)]
+    title: Option<String>,
+
+    /// Number of functions to list per event
+    #[arg(long, default_value = "20")]
+    top: usize,
+
+    /// Only include this event; may be repeated
+    #[arg(long = "event")]
+    events: Vec<String>,
+
+    /// Output file (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct CompareArgs {
+    /// Input SPAA file
+    input: PathBuf,
+
+    /// Event the flame graph is shaped by, e.g. cycles
+    #[arg(long)]
+    base: String,
+
+    /// Event compared against it, e.g. cache-misses
+    #[arg(long)]
+    other: String,
+
+    /// How to draw the two events
+    #[arg(long, value_enum, default_value = "overlay")]
+    mode: Mode,
+
+    /// Title (defaults to "<base> vs <other>")
+    #[arg(long)]
+    title: Option<String>,
+
+    /// Output file (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Mode {
+    /// One flame graph colored red where the other event is
+    /// over-represented and blue where it is under-represented
+    Overlay,
+    /// Both flame graphs, base on top
+    Paired,
+}
+
+pub fn run(args: ExportArgs) -> Result<(), Box<dyn std::error::Error>> {
+    match args.format {
+        ExportFormat::Html(args) => html(args),
+        ExportFormat::Compare(args) => compare(args),
+    }
+}
+
+fn html(args: HtmlArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let file = SpaaFile::open(&args.input)?;
+    let options = HtmlOptions {
+        title: args.title,
+        top: args.top,
+        events: args.events,
+    };
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    write_html(&file, &options, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn compare(args: CompareArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let file = SpaaFile::open(&args.input)?;
+    let options = CompareOptions {
+        base: args.base,
+        other: args.other,
+        mode: match args.mode {
+            Mode::Overlay => CompareMode::Overlay,
+            Mode::Paired => CompareMode::Paired,
+        },
+        title: args.title,
+    };
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    write_compare_svg(&file, &options, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
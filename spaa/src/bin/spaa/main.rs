@@ -0,0 +1,147 @@
+//! Unified command-line interface for working with SPAA files.
+//!
+//! # Usage
+//!
+//! ```bash
+//! spaa bisect nightly/*.spaa --function parse_request --growth 0.5
+//! spaa bundle profile.spaa -o profile.tar.gz
+//! spaa convert perf.txt --format perf --store .spaa-store
+//! spaa convert 'profiles/*.cpuprofile' --out-dir spaa/
+//! spaa detect profile.spaa --json
+//! spaa export html profile.spaa -o profile.html
+//! spaa export compare profile.spaa --base cycles --other cache-misses -o compare.svg
+//! spaa merge a.spaa b.spaa -o merged.spaa
+//! spaa note add profile.spaa 'memcpy here is the JSON re-encode' --function memcpy -o profile.spaa
+//! spaa report profile.spaa --format markdown
+//! spaa rollup profile.spaa --by dso --top 20
+//! spaa schema --record stack
+//! spaa serve --listen 127.0.0.1:7878   # requires the `http` feature
+//! spaa split profile.spaa --by event
+//! spaa stamp profile.spaa --from-env --set service_version=1.4.2 -o profile.spaa
+//! spaa stats profile.spaa --json
+//! spaa store add nightly.spaa --label branch=main
+//! spaa synth --stacks 10000 --event cycles=3 --event cache-misses -o synth.spaa
+//! spaa trim profile.spaa --budget 50000-tokens -o trimmed.spaa
+//! spaa view profile.spaa          # requires the `tui` feature
+//! ```
+
+mod bisect;
+mod bundle;
+mod convert;
+mod detect;
+mod export;
+mod merge;
+mod note;
+mod report;
+mod rollup;
+mod schema;
+#[cfg(feature = "http")]
+mod serve;
+mod split;
+mod stamp;
+mod stats;
+mod store;
+mod synth;
+mod trim;
+#[cfg(feature = "tui")]
+mod view;
+
+use clap::{Parser, Subcommand};
+use spaa::cli::{CliError, ErrorArgs, LogArgs};
+use std::process::ExitCode;
+
+#[derive(Parser, Debug)]
+#[command(name = "spaa")]
+#[command(about = "Work with SPAA (Stack Profile for Agentic Analysis) files")]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Find the first profile in a series where a function regressed
+    Bisect(bisect::BisectArgs),
+    /// Pack a profile, its report, flame graphs and validation into an archive
+    Bundle(bundle::BundleArgs),
+    /// Convert profiler output to SPAA
+    Convert(convert::ConvertArgs),
+    /// Flag known performance pathologies
+    Detect(detect::DetectArgs),
+    /// Export a profile as an HTML report or an SVG event comparison
+    Export(export::ExportArgs),
+    /// Merge several SPAA files into one
+    Merge(merge::MergeArgs),
+    /// Add or list investigation notes in a profile
+    Note(note::NoteArgs),
+    /// Write a Markdown or plain-text report of a profile
+    Report(report::ReportArgs),
+    /// Roll weight up per DSO, source file, directory or package
+    Rollup(rollup::RollupArgs),
+    /// Print the JSON Schema for SPAA records
+    Schema(schema::SchemaArgs),
+    /// Serve the profile analysis REST API
+    #[cfg(feature = "http")]
+    Serve(serve::ServeArgs),
+    /// Split a SPAA file by event, process or time window
+    Split(split::SplitArgs),
+    /// Attach git, CI and version metadata to a profile
+    Stamp(stamp::StampArgs),
+    /// Summarize record counts, weights and dictionary usage
+    Stats(stats::StatsArgs),
+    /// Manage a catalog of stored profiles
+    Store(store::StoreArgs),
+    /// Generate a deterministic synthetic profile
+    Synth(synth::SynthArgs),
+    /// Drop the lightest stacks until a profile fits a token budget
+    Trim(trim::TrimArgs),
+    /// Browse a SPAA file interactively in the terminal
+    #[cfg(feature = "tui")]
+    View(view::ViewArgs),
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    match cli.command {
+        Command::Bisect(args) => bisect::run(args),
+        Command::Bundle(args) => bundle::run(args),
+        Command::Convert(args) => convert::run(args),
+        Command::Detect(args) => detect::run(args),
+        Command::Export(args) => export::run(args),
+        Command::Merge(args) => merge::run(args),
+        Command::Note(args) => note::run(args),
+        Command::Report(args) => report::run(args),
+        Command::Rollup(args) => rollup::run(args),
+        Command::Schema(args) => schema::run(args),
+        #[cfg(feature = "http")]
+        Command::Serve(args) => serve::run(args),
+        Command::Split(args) => split::run(args),
+        Command::Stamp(args) => stamp::run(args),
+        Command::Stats(args) => stats::run(args),
+        Command::Store(args) => store::run(args),
+        Command::Synth(args) => synth::run(args),
+        Command::Trim(args) => trim::run(args),
+        #[cfg(feature = "tui")]
+        Command::View(args) => view::run(args),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli: Cli = match spaa::cli::parse() {
+        Ok(cli) => cli,
+        Err(code) => return code,
+    };
+    cli.log.init();
+    let error_format = cli.errors.error_format;
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => CliError::from(e).report(error_format),
+    }
+}
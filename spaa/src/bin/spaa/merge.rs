@@ -0,0 +1,150 @@
+//! `spaa merge`: combine shards from distributed capture.
+
+use clap::{Args, ValueEnum};
+use spaa::merge::{ConflictPolicy, IdRemapStrategy, InputOptions, MergeOptions, Merger};
+use spaa::progress::TerminalProgress;
+use spaa_parse::{ParseOptions, SpaaFile};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Conflict {
+    /// Fail when two inputs define an event with different primary metrics
+    Error,
+    /// Keep both, renaming the later event to `<event>@<input index>`
+    Rename,
+    /// Drop the later event and its records
+    Skip,
+}
+
+impl From<Conflict> for ConflictPolicy {
+    fn from(c: Conflict) -> Self {
+        match c {
+            Conflict::Error => ConflictPolicy::Error,
+            Conflict::Rename => ConflictPolicy::Rename,
+            Conflict::Skip => ConflictPolicy::Skip,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum IdStrategy {
+    /// Sequential IDs (0x1, 0x2, ...)
+    Sequential,
+    /// Input position plus original ID (1:0xabc)
+    SourcePrefix,
+}
+
+impl From<IdStrategy> for IdRemapStrategy {
+    fn from(s: IdStrategy) -> Self {
+        match s {
+            IdStrategy::Sequential => IdRemapStrategy::Sequential,
+            IdStrategy::SourcePrefix => IdRemapStrategy::SourcePrefix,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct MergeArgs {
+    /// Input SPAA files, merged in order
+    #[arg(required = true, num_args = 1..)]
+    inputs: Vec<PathBuf>,
+
+    /// Output file (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Weight scale factor, given once for all inputs or once per input in
+    /// order
+    #[arg(long, value_name = "FACTOR")]
+    scale: Vec<f64>,
+
+    /// Prefix each input's event names with its file stem (`shard1/cycles`)
+    #[arg(long)]
+    prefix_event: bool,
+
+    /// What to do when inputs define the same event with different primary
+    /// metrics
+    #[arg(long, value_enum, default_value = "error")]
+    conflict: Conflict,
+
+    /// How to mint IDs for stacks and windows that must be remapped
+    #[arg(long, value_enum, default_value = "sequential")]
+    id_strategy: IdStrategy,
+
+    /// Write the original-to-merged ID audit map (NDJSON) to this file
+    #[arg(long)]
+    audit: Option<PathBuf>,
+}
+
+pub fn run(args: MergeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let scales = match args.scale.len() {
+        0 => vec![1.0; args.inputs.len()],
+        1 => vec![args.scale[0]; args.inputs.len()],
+        n if n == args.inputs.len() => args.scale.clone(),
+        n => {
+            return Err(format!(
+                "--scale given {} times for {} inputs; pass it once or once per input",
+                n,
+                args.inputs.len()
+            )
+            .into());
+        }
+    };
+
+    let mut merger = Merger::new(MergeOptions {
+        id_strategy: args.id_strategy.into(),
+        conflicts: args.conflict.into(),
+    });
+
+    for (path, scale) in args.inputs.iter().zip(scales) {
+        info!("Reading {}", path.display());
+        let mut progress = TerminalProgress::new();
+        let (file, _) =
+            SpaaFile::open_with_progress(path, &ParseOptions::default(), &mut progress)?;
+        progress.finish();
+        let event_prefix = args.prefix_event.then(|| {
+            let stem = path.file_stem().unwrap_or(path.as_os_str());
+            format!("{}/", stem.to_string_lossy())
+        });
+        let source = path.display().to_string();
+        merger.add_with(
+            &source,
+            &file,
+            &InputOptions {
+                scale,
+                event_prefix,
+            },
+        )?;
+    }
+
+    let output = merger.finish()?;
+    info!(
+        "Merged {} inputs: {} stacks, {} frames, {} events",
+        args.inputs.len(),
+        output.file.stacks.len(),
+        output.file.frames.len(),
+        output.file.header.events.len()
+    );
+
+    match &args.output {
+        Some(path) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            output.file.write(&mut writer)?;
+            writer.flush()?;
+            info!("Wrote {}", path.display());
+        }
+        None => output.file.write(std::io::stdout().lock())?,
+    }
+
+    if let Some(path) = &args.audit {
+        let mut writer = BufWriter::new(File::create(path)?);
+        output.audit.write_ndjson(&mut writer)?;
+        writer.flush()?;
+        info!("Wrote ID audit map to {}", path.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,159 @@
+//! `spaa note`: attach investigation notes to a profile.
+
+use super::store::{format_age, unix_now};
+use clap::{Args, Subcommand};
+use spaa_parse::{Note, SpaaFile};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct NoteArgs {
+    #[command(subcommand)]
+    command: NoteCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum NoteCommand {
+    /// Add a note to a profile
+    Add(AddArgs),
+    /// List a profile's notes
+    List(ListArgs),
+}
+
+#[derive(Args, Debug)]
+struct AddArgs {
+    /// Input SPAA file
+    input: PathBuf,
+
+    /// Text of the note
+    text: String,
+
+    /// Who wrote the note (defaults to $USER)
+    #[arg(long)]
+    author: Option<String>,
+
+    /// Stack the note is about
+    #[arg(long)]
+    stack: Option<String>,
+
+    /// Frame the note is about
+    #[arg(long, conflicts_with = "function")]
+    frame: Option<u64>,
+
+    /// Function the note is about, resolved to its frame
+    #[arg(long)]
+    function: Option<String>,
+
+    /// Output file, which may be the input (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ListArgs {
+    /// Input SPAA file
+    input: PathBuf,
+
+    /// Emit JSON instead of text
+    #[arg(long)]
+    json: bool,
+}
+
+pub fn run(args: NoteArgs) -> Result<(), Box<dyn std::error::Error>> {
+    match args.command {
+        NoteCommand::Add(add) => {
+            let mut file = SpaaFile::open(&add.input)?;
+            if let Some(stack) = &add.stack
+                && !file.stacks.contains_key(stack)
+            {
+                return Err(format!("no stack with ID {stack}").into());
+            }
+            let frame_id = match (add.frame, &add.function) {
+                (Some(id), _) if !file.frames.contains_key(&id) => {
+                    return Err(format!("no frame with ID {id}").into());
+                }
+                (Some(id), _) => Some(id),
+                (None, Some(function)) => Some(frame_of(&file, function)?),
+                (None, None) => None,
+            };
+            let author = add
+                .author
+                .or_else(|| std::env::var("USER").ok())
+                .unwrap_or_else(|| "unknown".to_string());
+            file.notes.push(Note {
+                author,
+                timestamp: unix_now(),
+                text: add.text,
+                stack_id: add.stack,
+                frame_id,
+            });
+
+            match &add.output {
+                Some(path) => {
+                    let mut writer = BufWriter::new(File::create(path)?);
+                    file.write(&mut writer)?;
+                    writer.flush()?;
+                }
+                None => file.write(std::io::stdout().lock())?,
+            }
+        }
+        NoteCommand::List(list) => {
+            let file = SpaaFile::open(&list.input)?;
+            let mut out = std::io::stdout().lock();
+            if list.json {
+                serde_json::to_writer_pretty(&mut out, &file.notes)?;
+                writeln!(out)?;
+                return Ok(());
+            }
+            let now = unix_now();
+            for note in &file.notes {
+                let mut about = Vec::new();
+                if let Some(stack) = &note.stack_id {
+                    about.push(format!("stack {stack}"));
+                }
+                if let Some(frame) = note.frame_id.and_then(|id| file.resolve_frame(id)) {
+                    about.push(frame.func.clone());
+                }
+                writeln!(
+                    out,
+                    "{} ({}){}",
+                    note.author,
+                    format_age(now.saturating_sub(note.timestamp)),
+                    if about.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" on {}", about.join(", "))
+                    }
+                )?;
+                for line in note.text.lines() {
+                    writeln!(out, "  {line}")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The ID of the only frame named `function`.
+fn frame_of(file: &SpaaFile, function: &str) -> Result<u64, String> {
+    let mut ids: Vec<u64> = file
+        .frames
+        .values()
+        .filter(|f| f.func == function)
+        .map(|f| f.id)
+        .collect();
+    ids.sort_unstable();
+    match ids.as_slice() {
+        [id] => Ok(*id),
+        [] => Err(format!("no frame for function '{function}'")),
+        _ => Err(format!(
+            "function '{function}' has {} frames; pick one with --frame ({})",
+            ids.len(),
+            ids.iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
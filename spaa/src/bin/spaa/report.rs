@@ -0,0 +1,53 @@
+//! `spaa report`: write a narrative summary of a profile, or of how it
+//! changed from a baseline.
+
+use clap::Args;
+use spaa::report::{ReportFormat, ReportOptions, build_comparison, build_report};
+use spaa_parse::SpaaFile;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct ReportArgs {
+    /// Input SPAA file
+    input: PathBuf,
+
+    /// Output format: markdown or text
+    #[arg(long, default_value_t = ReportFormat::Markdown)]
+    format: ReportFormat,
+
+    /// Baseline profile to compare against
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+
+    /// Number of hotspots (or changed functions) to list per event
+    #[arg(long, default_value = "10")]
+    top: usize,
+
+    /// Output file (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub fn run(args: ReportArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let file = SpaaFile::open(&args.input)?;
+    let options = ReportOptions {
+        top: args.top,
+        ..Default::default()
+    };
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match &args.baseline {
+        Some(baseline) => {
+            let baseline = SpaaFile::open(baseline)?;
+            build_comparison(&baseline, &file, &options).write(&mut out, args.format)?
+        }
+        None => build_report(&file, &options).write(&mut out, args.format)?,
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,199 @@
+//! `spaa rollup`: weight per DSO, source file, directory or package.
+
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use spaa::analysis::{
+    DsoRollup, PackageRollup, SourceKey, SourceRollup, by_dso, by_package, by_source,
+};
+use spaa::config::Config;
+use spaa::packages::PackageMapper;
+use spaa_parse::{EventDef, SpaaFile};
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct RollupArgs {
+    /// Input SPAA file
+    input: PathBuf,
+
+    /// What to roll weight up to
+    #[arg(long, value_enum, default_value = "dso")]
+    by: RollupBy,
+
+    /// Keep only the first N directory components with `--by dir`, e.g. 2
+    /// for `crates/core`
+    #[arg(long)]
+    depth: Option<usize>,
+
+    /// Configuration file with `[packages]` rules for `--by package`
+    /// (defaults to the nearest spaa.toml, then ~/.config/spaa/spaa.toml)
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Only include this event; may be repeated
+    #[arg(long = "event")]
+    events: Vec<String>,
+
+    /// Number of entries to list per event
+    #[arg(long, default_value = "10")]
+    top: usize,
+
+    /// Emit JSON instead of a text summary
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum RollupBy {
+    /// Executable or shared library, with a kernel/user split
+    Dso,
+    /// Source file of each frame's srcline
+    File,
+    /// Directory of each frame's srcline
+    Dir,
+    /// Package, crate or module, from `[packages]` rules and heuristics
+    Package,
+}
+
+pub fn run(args: RollupArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let file = SpaaFile::open(&args.input)?;
+    let events: Vec<&EventDef> = file
+        .header
+        .events
+        .iter()
+        .filter(|e| args.events.is_empty() || args.events.contains(&e.name))
+        .collect();
+
+    let mut out = std::io::stdout().lock();
+    match args.by {
+        RollupBy::Dso => {
+            let rollups: Vec<DsoRollup> = events
+                .iter()
+                .map(|e| {
+                    let mut rollup = by_dso(&file, &e.name, &e.sampling.primary_metric);
+                    rollup.dsos.truncate(args.top);
+                    rollup
+                })
+                .collect();
+            write(&mut out, &rollups, args.json, print_dsos)?;
+        }
+        RollupBy::File | RollupBy::Dir => {
+            let key = match args.by {
+                RollupBy::Dir => SourceKey::Directory { depth: args.depth },
+                _ => SourceKey::File,
+            };
+            let rollups: Vec<SourceRollup> = events
+                .iter()
+                .map(|e| {
+                    let mut rollup = by_source(&file, &e.name, &e.sampling.primary_metric, key);
+                    rollup.sources.truncate(args.top);
+                    rollup
+                })
+                .collect();
+            write(&mut out, &rollups, args.json, print_sources)?;
+        }
+        RollupBy::Package => {
+            let config = Config::resolve(args.config.as_deref())?.0;
+            let mapper = PackageMapper::new(&config.packages)?;
+            let rollups: Vec<PackageRollup> = events
+                .iter()
+                .map(|e| {
+                    let mut rollup =
+                        by_package(&file, &e.name, &e.sampling.primary_metric, &mapper);
+                    rollup.packages.truncate(args.top);
+                    rollup
+                })
+                .collect();
+            write(&mut out, &rollups, args.json, print_packages)?;
+        }
+    }
+    Ok(())
+}
+
+fn write<W: Write, T: Serialize>(
+    out: &mut W,
+    rollups: &[T],
+    json: bool,
+    print: fn(&mut W, &T) -> std::io::Result<()>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if json {
+        serde_json::to_writer_pretty(&mut *out, rollups)?;
+        writeln!(out)?;
+    } else {
+        for rollup in rollups {
+            print(out, rollup)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_dsos<W: Write>(out: &mut W, rollup: &DsoRollup) -> std::io::Result<()> {
+    let percent = |w: u64| w as f64 * 100.0 / rollup.total.max(1) as f64;
+    writeln!(
+        out,
+        "{} ({}): total {}, {:.1}% kernel, {:.1}% user",
+        rollup.event,
+        rollup.metric,
+        rollup.total,
+        percent(rollup.kernel_weight),
+        percent(rollup.user_weight)
+    )?;
+    writeln!(out, "  {:>6}  {:>6}  DSO", "self%", "total%")?;
+    for dso in &rollup.dsos {
+        writeln!(
+            out,
+            "  {:>6.1}  {:>6.1}  {}",
+            dso.self_share * 100.0,
+            dso.total_share * 100.0,
+            dso.dso
+        )?;
+        for function in dso.top_functions.iter().filter(|f| f.self_weight > 0) {
+            writeln!(
+                out,
+                "  {:>6.1}  {:>6.1}    {}",
+                percent(function.self_weight),
+                percent(function.total_weight),
+                function.function
+            )?;
+        }
+    }
+    writeln!(out)
+}
+
+fn print_sources<W: Write>(out: &mut W, rollup: &SourceRollup) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{} ({}): total {}",
+        rollup.event, rollup.metric, rollup.total
+    )?;
+    writeln!(out, "  {:>6}  {:>6}  Path", "self%", "total%")?;
+    for source in &rollup.sources {
+        writeln!(
+            out,
+            "  {:>6.1}  {:>6.1}  {}",
+            source.self_share * 100.0,
+            source.total_share * 100.0,
+            source.path
+        )?;
+    }
+    writeln!(out)
+}
+
+fn print_packages<W: Write>(out: &mut W, rollup: &PackageRollup) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{} ({}): total {}",
+        rollup.event, rollup.metric, rollup.total
+    )?;
+    writeln!(out, "  {:>6}  {:>6}  Package", "self%", "total%")?;
+    for package in &rollup.packages {
+        writeln!(
+            out,
+            "  {:>6.1}  {:>6.1}  {}",
+            package.self_share * 100.0,
+            package.total_share * 100.0,
+            package.package
+        )?;
+    }
+    writeln!(out)
+}
@@ -0,0 +1,35 @@
+//! `spaa schema`: print the JSON Schema for SPAA records.
+
+use clap::Args;
+use spaa_parse::schema::{RecordType, line_schema, record_schema};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct SchemaArgs {
+    /// Record type to describe: header, dso, frame, thread, stack, sample,
+    /// samples, window, state or footer (defaults to a schema matching any record)
+    #[arg(long)]
+    record: Option<RecordType>,
+
+    /// Output file (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub fn run(args: SchemaArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let schema = match args.record {
+        Some(record) => record_schema(record),
+        None => line_schema(),
+    };
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    serde_json::to_writer_pretty(&mut out, &schema)?;
+    writeln!(out)?;
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,25 @@
+//! `spaa serve`: run the profile analysis REST API.
+
+use clap::Args;
+use spaa::service::Service;
+use spaa::service::http::serve;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::info;
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    listen: SocketAddr,
+}
+
+pub fn run(args: ServeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(args.listen).await?;
+        info!("Listening on http://{}", listener.local_addr()?);
+        serve(listener, Arc::new(Service::new())).await
+    })?;
+    Ok(())
+}
@@ -0,0 +1,92 @@
+//! `spaa split`: break a profile into smaller files along one dimension.
+
+use clap::{Args, ValueEnum};
+use spaa::split::{SplitBy, split};
+use spaa_parse::SpaaFile;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum By {
+    /// One file per event
+    Event,
+    /// One file per process
+    Pid,
+    /// One file per time window record
+    Window,
+}
+
+impl From<By> for SplitBy {
+    fn from(b: By) -> Self {
+        match b {
+            By::Event => SplitBy::Event,
+            By::Pid => SplitBy::Pid,
+            By::Window => SplitBy::Window,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct SplitArgs {
+    /// Input SPAA file
+    input: PathBuf,
+
+    /// Dimension to split along
+    #[arg(long, value_enum)]
+    by: By,
+
+    /// Output directory (defaults to the input's directory)
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+}
+
+pub fn run(args: SplitArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let file = SpaaFile::open(&args.input)?;
+    let parts = split(&file, args.by.into());
+    if parts.is_empty() {
+        return Err(format!("nothing to split by {}", SplitBy::from(args.by)).into());
+    }
+
+    let dir = match args.output_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(&dir)?;
+            dir
+        }
+        None => args.input.parent().map(PathBuf::from).unwrap_or_default(),
+    };
+    let stem = args
+        .input
+        .file_stem()
+        .map_or_else(|| "profile".into(), |s| s.to_string_lossy());
+
+    for part in parts {
+        let path = dir.join(format!("{}.{}.spaa", stem, file_safe(&part.key)));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        part.file.write(&mut writer)?;
+        writer.flush()?;
+        info!(
+            "Wrote {} ({} stacks, {} frames)",
+            path.display(),
+            part.file.stacks.len(),
+            part.file.frames.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Replace characters that are awkward in file names (`sched:sched_switch`,
+/// `cpu/cycles/`).
+fn file_safe(key: &str) -> String {
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,60 @@
+//! `spaa stamp`: attach build metadata to a profile.
+
+use clap::Args;
+use spaa::stamp::{from_env, stamp};
+use spaa_parse::SpaaFile;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct StampArgs {
+    /// Input SPAA file
+    input: PathBuf,
+
+    /// Build metadata as KEY=VALUE, e.g. `service_version=1.4.2`; may be
+    /// repeated
+    #[arg(long = "set", value_parser = parse_entry)]
+    entries: Vec<(String, String)>,
+
+    /// Also record the git commit, branch, CI job and run from GitHub
+    /// Actions, GitLab CI, Buildkite, CircleCI or Jenkins variables
+    #[arg(long)]
+    from_env: bool,
+
+    /// Output file, which may be the input (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub fn run(args: StampArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = SpaaFile::open(&args.input)?;
+    let mut metadata = if args.from_env {
+        from_env()
+    } else {
+        BTreeMap::new()
+    };
+    metadata.extend(args.entries);
+    if metadata.is_empty() {
+        return Err("nothing to stamp: pass --set KEY=VALUE or --from-env".into());
+    }
+    stamp(&mut file, metadata);
+
+    match &args.output {
+        Some(path) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            file.write(&mut writer)?;
+            writer.flush()?;
+        }
+        None => file.write(std::io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+fn parse_entry(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((k, v)) if !k.is_empty() => Ok((k.to_string(), v.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", s)),
+    }
+}
@@ -0,0 +1,108 @@
+//! `spaa stats`: summarize a SPAA file without loading it into memory.
+
+use clap::Args;
+use spaa::stats::{ProfileStats, StatsOptions, compute_stats_with_options};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    /// Input SPAA file (`-` for stdin)
+    input: PathBuf,
+
+    /// Emit JSON instead of a text summary
+    #[arg(long)]
+    json: bool,
+
+    /// Number of DSOs to list
+    #[arg(long, default_value = "10")]
+    top: usize,
+}
+
+pub fn run(args: StatsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let reader: Box<dyn BufRead> = if args.input.as_os_str() == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(&args.input)?))
+    };
+    let stats = compute_stats_with_options(reader, &StatsOptions { top_dsos: args.top })?;
+
+    let mut out = std::io::stdout().lock();
+    if args.json {
+        serde_json::to_writer_pretty(&mut out, &stats)?;
+        writeln!(out)?;
+    } else {
+        print_text(&mut out, &stats)?;
+    }
+    Ok(())
+}
+
+fn print_text<W: Write>(out: &mut W, stats: &ProfileStats) -> std::io::Result<()> {
+    writeln!(out, "Source tool:     {}", stats.source_tool)?;
+    writeln!(out, "Size:            {} bytes", stats.bytes)?;
+
+    let r = &stats.records;
+    writeln!(out, "\nRecords:")?;
+    for (name, count) in [
+        ("dso", r.dsos),
+        ("frame", r.frames),
+        ("thread", r.threads),
+        ("stack", r.stacks),
+        ("sample", r.samples),
+        ("window", r.windows),
+        ("state", r.states),
+        ("note", r.notes),
+    ] {
+        if count > 0 {
+            writeln!(out, "  {:<8} {:>12}", name, count)?;
+        }
+    }
+    writeln!(out, "Unique symbols:  {}", stats.unique_symbols)?;
+
+    writeln!(out, "\nWeights:")?;
+    for w in &stats.weights {
+        writeln!(
+            out,
+            "  {:<24} {:<16} {:>16}{}",
+            w.event,
+            w.metric,
+            w.total,
+            if w.primary { "  (primary)" } else { "" }
+        )?;
+    }
+
+    if !stats.top_dsos.is_empty() {
+        writeln!(out, "\nTop DSOs (by stack references):")?;
+        for dso in &stats.top_dsos {
+            writeln!(
+                out,
+                "  {:>10} refs {:>8} leaf {:>8} frames  {}",
+                dso.stack_references, dso.leaf_stacks, dso.frames, dso.name
+            )?;
+        }
+    }
+
+    writeln!(out, "\nStack depth (max {}):", stats.max_depth)?;
+    for bucket in stats.depth_histogram.iter().filter(|b| b.stacks > 0) {
+        let range = match bucket.max {
+            Some(max) if max == bucket.min => format!("{}", max),
+            Some(max) => format!("{}-{}", bucket.min, max),
+            None => format!("{}+", bucket.min),
+        };
+        writeln!(out, "  {:>8} {:>12}", range, bucket.stacks)?;
+    }
+
+    let d = &stats.dictionary;
+    writeln!(out, "\nDictionaries:")?;
+    writeln!(
+        out,
+        "  frames referenced  {} of {} ({:.1}%)",
+        d.frames_referenced,
+        r.frames,
+        d.frame_hit_rate * 100.0
+    )?;
+    writeln!(out, "  frame reuse        {:.2} refs/frame", d.frame_reuse)?;
+    writeln!(out, "  dsos referenced    {:.1}%", d.dso_hit_rate * 100.0)?;
+    Ok(())
+}
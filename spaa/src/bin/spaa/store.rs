@@ -0,0 +1,255 @@
+//! `spaa store`: manage a catalog of SPAA profiles.
+
+use clap::{Args, Subcommand};
+use spaa::cli::CliError;
+use spaa::store::{AddOptions, Entry, Filter, RetentionPolicy, Store};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+#[derive(Args, Debug)]
+pub struct StoreArgs {
+    /// Store directory
+    #[arg(long, default_value = ".spaa-store")]
+    store: PathBuf,
+
+    #[command(subcommand)]
+    command: StoreCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum StoreCommand {
+    /// Add profiles to the store
+    Add(AddArgs),
+    /// List stored profiles
+    List(ListArgs),
+    /// Write a stored profile out
+    Get(GetArgs),
+    /// Remove profiles according to a retention policy
+    Prune(PruneArgs),
+}
+
+#[derive(Args, Debug)]
+struct AddArgs {
+    /// SPAA files to add
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Host the profiles were recorded on
+    #[arg(long)]
+    host: Option<String>,
+
+    /// Label as KEY=VALUE; may be repeated
+    #[arg(long = "label", value_parser = parse_label)]
+    labels: Vec<(String, String)>,
+}
+
+#[derive(Args, Debug)]
+struct ListArgs {
+    /// Only profiles from this source tool
+    #[arg(long)]
+    tool: Option<String>,
+
+    /// Only profiles from this host
+    #[arg(long)]
+    host: Option<String>,
+
+    /// Only profiles with this KEY=VALUE label; may be repeated
+    #[arg(long = "label", value_parser = parse_label)]
+    labels: Vec<(String, String)>,
+
+    /// Emit JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args, Debug)]
+struct GetArgs {
+    /// Profile ID or unique prefix
+    id: String,
+
+    /// Output file (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct PruneArgs {
+    /// Remove profiles older than this, e.g. `30d`, `12h` (units: s, m, h, d, w)
+    #[arg(long, value_parser = parse_age)]
+    max_age: Option<Duration>,
+
+    /// Keep at most this many of the newest profiles
+    #[arg(long)]
+    max_count: Option<usize>,
+
+    /// Remove the oldest profiles until the rest fit in this many bytes
+    #[arg(long)]
+    max_bytes: Option<u64>,
+
+    /// Show what would be removed without removing it
+    #[arg(long)]
+    dry_run: bool,
+}
+
+pub fn run(args: StoreArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::open(&args.store)?;
+    match args.command {
+        StoreCommand::Add(add) => {
+            let labels: BTreeMap<String, String> = add.labels.into_iter().collect();
+            for input in &add.inputs {
+                let data = std::fs::read(input)?;
+                let options = AddOptions {
+                    name: input.file_name().map(|n| n.to_string_lossy().into_owned()),
+                    host: add.host.clone(),
+                    labels: labels.clone(),
+                    added: None,
+                };
+                let added = store
+                    .add(&data, options)
+                    .map_err(|e| CliError::context(e, input.display()))?;
+                println!(
+                    "{} {}{}",
+                    short_id(&added.entry),
+                    input.display(),
+                    if added.duplicate {
+                        " (already stored)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        StoreCommand::List(list) => {
+            let filter = Filter {
+                tool: list.tool,
+                host: list.host,
+                labels: list.labels,
+            };
+            let entries: Vec<&Entry> = store
+                .entries()
+                .iter()
+                .filter(|e| filter.matches(e))
+                .collect();
+            let mut out = std::io::stdout().lock();
+            if list.json {
+                serde_json::to_writer_pretty(&mut out, &entries)?;
+                writeln!(out)?;
+            } else {
+                let now = unix_now();
+                for entry in entries {
+                    let labels: Vec<String> = entry
+                        .labels
+                        .iter()
+                        .map(|(k, v)| format!("{}={}", k, v))
+                        .collect();
+                    writeln!(
+                        out,
+                        "{}  {:>8}  {:<10} {:<12} {:>10}  {}  {}",
+                        short_id(entry),
+                        format_age(now.saturating_sub(entry.added)),
+                        entry.source_tool,
+                        entry.host.as_deref().unwrap_or("-"),
+                        entry.bytes,
+                        entry.name.as_deref().unwrap_or("-"),
+                        labels.join(",")
+                    )?;
+                }
+            }
+        }
+        StoreCommand::Get(get) => {
+            let data = store.read(&get.id)?;
+            let mut out: Box<dyn Write> = match &get.output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            out.write_all(&data)?;
+            out.flush()?;
+        }
+        StoreCommand::Prune(prune) => {
+            let policy = RetentionPolicy {
+                max_age: prune.max_age,
+                max_count: prune.max_count,
+                max_bytes: prune.max_bytes,
+            };
+            let now = SystemTime::now();
+            let removed: Vec<Entry> = if prune.dry_run {
+                store.expired(&policy, now).into_iter().cloned().collect()
+            } else {
+                store.prune(&policy, now)?
+            };
+            for entry in &removed {
+                println!(
+                    "{} {}",
+                    if prune.dry_run {
+                        "would remove"
+                    } else {
+                        "removed"
+                    },
+                    short_id(entry)
+                );
+            }
+            info!(
+                "{} profiles {}",
+                removed.len(),
+                if prune.dry_run {
+                    "would be removed"
+                } else {
+                    "removed"
+                }
+            );
+        }
+    }
+    Ok(())
+}
+
+fn short_id(entry: &Entry) -> &str {
+    &entry.id[..12.min(entry.id.len())]
+}
+
+pub(super) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+pub(super) fn format_age(secs: u64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s ago", s),
+        s if s < 3600 => format!("{}m ago", s / 60),
+        s if s < 86400 => format!("{}h ago", s / 3600),
+        s => format!("{}d ago", s / 86400),
+    }
+}
+
+fn parse_label(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((k, v)) if !k.is_empty() => Ok((k.to_string(), v.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", s)),
+    }
+}
+
+fn parse_age(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (n, unit) = s.split_at(split);
+    let n: u64 = n
+        .parse()
+        .map_err(|_| format!("expected a number with a unit, got '{}'", s))?;
+    let scale = match unit {
+        "s" | "" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => {
+            return Err(format!(
+                "unknown unit '{}' (expected s, m, h, d or w)",
+                unit
+            ));
+        }
+    };
+    Ok(Duration::from_secs(n * scale))
+}
@@ -0,0 +1,93 @@
+//! `spaa synth`: generate a deterministic synthetic profile.
+
+use clap::Args;
+use spaa::synth::{DepthDistribution, EventWeight, ProfileGenerator, SymbolPool};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Args, Debug)]
+pub struct SynthArgs {
+    /// Output file (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Random seed; the same seed and options give the same file
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    /// Number of distinct stacks
+    #[arg(long, default_value = "1000")]
+    stacks: usize,
+
+    /// Stack depth distribution: fixed:N, uniform:MIN-MAX or geometric:MEAN
+    #[arg(long, default_value = "uniform:1-32")]
+    depth: DepthDistribution,
+
+    /// Number of generated function names
+    #[arg(long, default_value = "500")]
+    functions: usize,
+
+    /// Number of generated DSOs
+    #[arg(long, default_value = "8")]
+    dsos: usize,
+
+    /// File of function names, one per line, to use instead of generated names
+    #[arg(long, conflicts_with_all = ["functions", "dsos"])]
+    symbols: Option<PathBuf>,
+
+    /// Event and relative share, as NAME or NAME=SHARE (repeatable)
+    #[arg(long = "event", default_value = "cycles")]
+    events: Vec<EventWeight>,
+
+    /// Number of threads
+    #[arg(long, default_value = "4")]
+    threads: usize,
+
+    /// Raw sample records per stack
+    #[arg(long, default_value = "0")]
+    samples_per_stack: usize,
+
+    /// Seconds the samples are spread over; recorded as the time range
+    #[arg(long)]
+    duration: Option<f64>,
+}
+
+pub fn run(args: SynthArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let symbols = match &args.symbols {
+        Some(path) => {
+            let names = std::fs::read_to_string(path)?;
+            let pool =
+                SymbolPool::from_names(names.lines().map(str::trim).filter(|l| !l.is_empty()));
+            if pool.functions.is_empty() {
+                return Err(format!("no function names in {}", path.display()).into());
+            }
+            pool
+        }
+        None => SymbolPool::synthetic(args.functions, args.dsos),
+    };
+
+    let generator = ProfileGenerator {
+        seed: args.seed,
+        stacks: args.stacks,
+        depth: args.depth,
+        symbols,
+        events: args.events,
+        threads: args.threads,
+        samples_per_stack: args.samples_per_stack,
+        time_range: args.duration.map(|d| (0.0, d)),
+    };
+    let file = generator.generate();
+
+    match &args.output {
+        Some(path) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            file.write(&mut writer)?;
+            writer.flush()?;
+            info!("Wrote {} stacks to {}", file.stacks.len(), path.display());
+        }
+        None => file.write(std::io::stdout().lock())?,
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! `spaa trim`: shrink a profile to fit an LLM context budget.
+
+use clap::Args;
+use spaa::trim::{Budget, trim};
+use spaa_parse::SpaaFile;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Args, Debug)]
+pub struct TrimArgs {
+    /// Input SPAA file
+    input: PathBuf,
+
+    /// Size limit: `N-tokens` or `N-bytes`, e.g. `50000-tokens` or `200k-bytes`
+    #[arg(long)]
+    budget: Budget,
+
+    /// Output file (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub fn run(args: TrimArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let file = SpaaFile::open(&args.input)?;
+    let trimmed = trim(&file, args.budget);
+
+    match &args.output {
+        Some(path) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            trimmed.file.write(&mut writer)?;
+            writer.flush()?;
+        }
+        None => trimmed.file.write(std::io::stdout().lock())?,
+    }
+
+    info!(
+        "Kept {} of {} stacks (~{} tokens, was ~{}); dropped {} samples",
+        file.stacks.len() - trimmed.dropped_stacks,
+        file.stacks.len(),
+        trimmed.file.estimate_tokens(),
+        file.estimate_tokens(),
+        trimmed.dropped_samples,
+    );
+    if !trimmed.fits {
+        return Err(format!("cannot fit {} within {}", args.input.display(), args.budget).into());
+    }
+    Ok(())
+}
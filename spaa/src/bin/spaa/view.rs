@@ -0,0 +1,17 @@
+//! `spaa view`: interactive call-tree and flame viewer.
+
+use clap::Args;
+use spaa_parse::SpaaFile;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct ViewArgs {
+    /// Input SPAA file
+    input: PathBuf,
+}
+
+pub fn run(args: ViewArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let file = SpaaFile::open(&args.input)?;
+    spaa::view::run(&file)?;
+    Ok(())
+}
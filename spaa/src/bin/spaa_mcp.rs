@@ -0,0 +1,42 @@
+//! MCP server exposing SPAA profiles to coding agents.
+//!
+//! Speaks the Model Context Protocol over stdio. Register it with an MCP
+//! client, for example:
+//!
+//! ```json
+//! { "mcpServers": { "spaa": { "command": "spaa-mcp" } } }
+//! ```
+//!
+//! Build with `cargo install spaa --features mcp`.
+
+use clap::Parser;
+use spaa::cli::{CliError, ErrorArgs, LogArgs};
+use spaa::mcp::{Server, serve};
+use std::process::ExitCode;
+
+#[derive(Parser, Debug)]
+#[command(name = "spaa-mcp")]
+#[command(about = "Model Context Protocol server for SPAA profiles")]
+#[command(version)]
+struct Args {
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+fn main() -> ExitCode {
+    let args: Args = match spaa::cli::parse() {
+        Ok(args) => args,
+        Err(code) => return code,
+    };
+    args.log.init();
+    let stdin = std::io::stdin().lock();
+    let stdout = std::io::stdout().lock();
+
+    match serve(&mut Server::new(), stdin, stdout) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => CliError::from_error(&e).report(args.errors.error_format),
+    }
+}
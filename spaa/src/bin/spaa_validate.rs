@@ -0,0 +1,69 @@
+//! Check that a file is a well-formed SPAA profile.
+//!
+//! Prints a summary of the file's records on success. Failures are reported
+//! with the shared exit codes and `--error-format` of the other SPAA tools.
+//!
+//! # Usage
+//!
+//! ```bash
+//! spaa_validate profile.spaa
+//! spaa_validate profile.spaa --error-format json
+//! ```
+
+use clap::Parser;
+use spaa::cli::{CliError, ErrorArgs, LogArgs};
+use spaa_parse::SpaaFile;
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser, Debug)]
+#[command(name = "spaa_validate")]
+#[command(about = "Check that a file is a well-formed SPAA profile")]
+#[command(version)]
+struct Args {
+    /// SPAA file to validate
+    input: PathBuf,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+fn run(args: &Args) -> Result<(), CliError> {
+    let path = args.input.display();
+    let file = File::open(&args.input)
+        .map_err(|e| CliError::context(e, format!("Error opening '{}'", path)))?;
+    let spaa = SpaaFile::parse(file)
+        .map_err(|e| CliError::context(e, format!("Invalid SPAA file '{}'", path)))?;
+
+    println!("Valid SPAA file: {}", path);
+    println!("  Format version: {}", spaa.header.version);
+    println!("  Source tool: {}", spaa.header.source_tool);
+    println!("  Events: {}", spaa.header.events.len());
+    println!("  DSOs: {}", spaa.dsos.len());
+    println!("  Frames: {}", spaa.frames.len());
+    println!("  Stacks: {}", spaa.stacks.len());
+    if !spaa.samples.is_empty() {
+        println!("  Samples: {}", spaa.samples.len());
+    }
+    if !spaa.windows.is_empty() {
+        println!("  Windows: {}", spaa.windows.len());
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Args = match spaa::cli::parse() {
+        Ok(args) => args,
+        Err(code) => return code,
+    };
+    args.log.init();
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => e.report(args.errors.error_format),
+    }
+}
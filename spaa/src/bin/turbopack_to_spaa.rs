@@ -11,11 +11,13 @@
 //! ```
 
 use clap::Parser;
+use spaa::cli::{CliError, ErrorArgs, LogArgs};
 use spaa::turbopack::TurbopackConverter;
 use std::fs::File;
 use std::io::{self, BufWriter, Write};
 use std::path::PathBuf;
 use std::process::ExitCode;
+use tracing::info;
 
 #[derive(Parser, Debug)]
 #[command(name = "turbopack_to_spaa")]
@@ -28,15 +30,21 @@ struct Args {
     /// Output SPAA file (defaults to stdout)
     #[arg(short, long)]
     output: Option<PathBuf>,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    log: LogArgs,
 }
 
 fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
-    eprintln!("Opening trace file: {}", args.input.display());
+    info!("Opening trace file: {}", args.input.display());
 
     let mut converter = TurbopackConverter::new();
     converter.parse_file(&args.input)?;
 
-    eprintln!(
+    info!(
         "Parsed {} events, {} spans",
         converter.row_count(),
         converter.span_count()
@@ -52,21 +60,24 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
     out.flush()?;
 
     if converter.has_allocations() {
-        eprintln!("Wrote SPAA output (includes allocation data)");
+        info!("Wrote SPAA output (includes allocation data)");
     } else {
-        eprintln!("Wrote SPAA output");
+        info!("Wrote SPAA output");
     }
 
     Ok(())
 }
 
 fn main() -> ExitCode {
-    let args = Args::parse();
+    let args: Args = match spaa::cli::parse() {
+        Ok(args) => args,
+        Err(code) => return code,
+    };
+    args.log.init();
+    let error_format = args.errors.error_format;
+
     match run(args) {
         Ok(()) => ExitCode::SUCCESS,
-        Err(e) => {
-            eprintln!("Error: {e}");
-            ExitCode::FAILURE
-        }
+        Err(e) => CliError::from(e).report(error_format),
     }
 }
@@ -0,0 +1,243 @@
+//! Find where a function regressed in an ordered series of profiles.
+//!
+//! Given profiles of successive builds, oldest first, [`bisect`] measures
+//! one function's share of an event in each and reports the first profile
+//! where a [`Predicate`] holds, together with a
+//! [`Comparison`] against the profile before
+//! it. When the profiles were stamped with `git_sha` (see
+//! [`stamp`](crate::stamp)), the result names the commits to hand to
+//! `git bisect good` and `git bisect bad`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::bisect::{Predicate, Threshold, bisect};
+//! use spaa::report::ReportOptions;
+//! use spaa_parse::SpaaFile;
+//!
+//! let profiles: Vec<SpaaFile> = ["a.spaa", "b.spaa", "c.spaa"]
+//!     .iter()
+//!     .map(|p| SpaaFile::open(p).unwrap())
+//!     .collect();
+//! let predicate = Predicate::new("serde_json::de::from_str", "cycles", Threshold::Growth(0.5));
+//! let result = bisect(&profiles, &predicate, &ReportOptions::default());
+//! if let Some(bad) = result.first_bad {
+//!     println!("regressed in profile {bad}");
+//! }
+//! ```
+
+use crate::analysis::{RankBy, stack_weight};
+use crate::report::{Comparison, ReportOptions, build_comparison};
+use serde::Serialize;
+use spaa_parse::{FrameOrder, SpaaFile};
+
+/// When a function's share counts as regressed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Threshold {
+    /// The share is at least this fraction of the event.
+    Share(f64),
+    /// The share grew by at least this fraction over the first profile's,
+    /// e.g. `0.5` for half as much again.
+    Growth(f64),
+}
+
+/// The function and condition [`bisect`] looks for.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Predicate {
+    /// Function name, matched exactly.
+    pub function: String,
+    pub event: String,
+    /// Metric to weigh stacks by; defaults to each profile's primary
+    /// metric for the event.
+    pub metric: Option<String>,
+    /// Whether the function's self or total weight is measured.
+    pub rank: RankBy,
+    pub threshold: Threshold,
+}
+
+impl Predicate {
+    /// A predicate on `function`'s self weight in `event`.
+    pub fn new(function: &str, event: &str, threshold: Threshold) -> Self {
+        Self {
+            function: function.to_string(),
+            event: event.to_string(),
+            metric: None,
+            rank: RankBy::SelfWeight,
+            threshold,
+        }
+    }
+}
+
+/// The function's weight in one profile of the series.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Point {
+    /// Position in the series.
+    pub index: usize,
+    /// `source.build.git_sha` of the profile, if stamped.
+    pub git_sha: Option<String>,
+    pub weight: u64,
+    /// `weight` as a fraction of the event's total weight.
+    pub share: f64,
+    /// Whether the predicate holds.
+    pub regressed: bool,
+}
+
+/// Result of [`bisect`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bisection {
+    pub predicate: Predicate,
+    /// One point per profile, in series order.
+    pub points: Vec<Point>,
+    /// First profile where the predicate holds.
+    pub first_bad: Option<usize>,
+    /// Profile just before `first_bad`, if there is one.
+    pub last_good: Option<usize>,
+    /// Changes from `last_good` to `first_bad`.
+    pub changes: Option<Comparison>,
+}
+
+/// Measure `predicate.function` across `profiles`, oldest first, and find
+/// the first profile where it regressed.
+///
+/// `options` limits the functions and stacks listed in the comparison.
+pub fn bisect(profiles: &[SpaaFile], predicate: &Predicate, options: &ReportOptions) -> Bisection {
+    let measured: Vec<(u64, f64)> = profiles.iter().map(|p| measure(p, predicate)).collect();
+    let baseline = measured.first().map_or(0.0, |&(_, share)| share);
+    let points: Vec<Point> = profiles
+        .iter()
+        .zip(&measured)
+        .enumerate()
+        .map(|(index, (profile, &(weight, share)))| Point {
+            index,
+            git_sha: profile
+                .header
+                .source
+                .as_ref()
+                .and_then(|s| s.build.get("git_sha"))
+                .cloned(),
+            weight,
+            share,
+            regressed: match predicate.threshold {
+                Threshold::Share(min) => share >= min,
+                Threshold::Growth(growth) => {
+                    share > 0.0 && share >= baseline * (1.0 + growth) && index > 0
+                }
+            },
+        })
+        .collect();
+
+    let first_bad = points.iter().position(|p| p.regressed);
+    let last_good = first_bad.and_then(|i| i.checked_sub(1));
+    let changes = first_bad
+        .zip(last_good)
+        .map(|(bad, good)| build_comparison(&profiles[good], &profiles[bad], options));
+
+    Bisection {
+        predicate: predicate.clone(),
+        points,
+        first_bad,
+        last_good,
+        changes,
+    }
+}
+
+/// The function's weight and share of the event in `file`.
+fn measure(file: &SpaaFile, predicate: &Predicate) -> (u64, f64) {
+    let Some(metric) = predicate
+        .metric
+        .as_deref()
+        .or_else(|| file.primary_metric_for_event(&predicate.event))
+    else {
+        return (0, 0.0);
+    };
+    let mut total = 0;
+    let mut weight = 0;
+    for stack in file.stacks_for_event(&predicate.event) {
+        let w = stack_weight(stack, metric);
+        total += w;
+        let matches = match predicate.rank {
+            RankBy::SelfWeight => {
+                let leaf = match file.header.frame_order {
+                    FrameOrder::LeafToRoot => stack.frames.first(),
+                    FrameOrder::RootToLeaf => stack.frames.last(),
+                };
+                leaf.and_then(|&id| file.resolve_frame(id))
+                    .is_some_and(|f| f.func == predicate.function)
+            }
+            RankBy::TotalWeight => stack
+                .frames
+                .iter()
+                .filter_map(|&id| file.resolve_frame(id))
+                .any(|f| f.func == predicate.function),
+        };
+        if matches {
+            weight += w;
+        }
+    }
+    (weight, weight as f64 / total.max(1) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A profile where `parse` has `parse` weight out of 100, stamped with
+    /// `sha`.
+    fn profile(parse: u64, sha: &str) -> SpaaFile {
+        let data = [
+            format!(
+                r#"{{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{{"name":"cycles","kind":"hardware","sampling":{{"mode":"period","primary_metric":"period"}}}}],"source":{{"tool":"perf","build":{{"git_sha":"{sha}"}}}}}}"#
+            ),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#.to_string(),
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#.to_string(),
+            r#"{"type":"frame","id":2,"func":"parse","dso":1}"#.to_string(),
+            r#"{"type":"frame","id":3,"func":"render","dso":1}"#.to_string(),
+            format!(
+                r#"{{"type":"stack","id":"0x1","frames":[2,1],"context":{{"event":"cycles"}},"weights":[{{"metric":"period","value":{parse}}}]}}"#
+            ),
+            format!(
+                r#"{{"type":"stack","id":"0x2","frames":[3,1],"context":{{"event":"cycles"}},"weights":[{{"metric":"period","value":{}}}]}}"#,
+                100 - parse
+            ),
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn first_regressed_profile_is_found() {
+        let series = [
+            profile(10, "a1"),
+            profile(12, "b2"),
+            profile(30, "c3"),
+            profile(31, "d4"),
+        ];
+        let predicate = Predicate::new("parse", "cycles", Threshold::Growth(0.5));
+        let result = bisect(&series, &predicate, &ReportOptions::default());
+
+        assert_eq!(result.first_bad, Some(2));
+        assert_eq!(result.last_good, Some(1));
+        assert_eq!(result.points[2].git_sha.as_deref(), Some("c3"));
+        assert!((result.points[2].share - 0.3).abs() < 1e-9);
+        let changes = result.changes.unwrap();
+        let increase = changes.events[0].largest_increase.as_ref().unwrap();
+        assert_eq!(increase.function, "parse");
+    }
+
+    #[test]
+    fn total_weight_and_absolute_shares_are_supported() {
+        let series = [profile(10, "a1"), profile(20, "b2")];
+        let mut predicate = Predicate::new("main", "cycles", Threshold::Share(0.5));
+        let result = bisect(&series, &predicate, &ReportOptions::default());
+        assert_eq!(result.first_bad, None);
+
+        predicate.rank = RankBy::TotalWeight;
+        let result = bisect(&series, &predicate, &ReportOptions::default());
+        // Already regressed in the first profile: nothing to compare with.
+        assert_eq!(result.first_bad, Some(0));
+        assert_eq!(result.last_good, None);
+        assert!(result.changes.is_none());
+    }
+}
@@ -0,0 +1,549 @@
+//! Self-contained analysis bundles for attaching to tickets.
+//!
+//! [`write_bundle`] packs a profile and what can be worked out from it into
+//! one archive:
+//!
+//! - `manifest.json` - the [`Manifest`]: what else is in the bundle, with
+//!   sizes and SHA-256 digests
+//! - `profile.spaa` - the profile, unchanged
+//! - `report.md` - the Markdown [report](crate::report)
+//! - `flamegraph-<event>.svg` - a flame graph of each event with weight
+//! - `validation.json` - the [`Validation`] of the profile
+//! - `fidelity.json` - the `spaa convert --report` fidelity report, when
+//!   given
+//!
+//! Bundles are gzipped tarballs or zip archives, written front to back so
+//! they can be sent to a pipe.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::bundle::{BundleOptions, write_bundle};
+//! use std::fs::File;
+//!
+//! let data = std::fs::read("profile.spaa").unwrap();
+//! let out = File::create("profile.tar.gz").unwrap();
+//! let manifest = write_bundle(&data, &BundleOptions::default(), out).unwrap();
+//! println!("bundled {} files", manifest.files.len());
+//! ```
+
+use crate::export::html::render_flame_svg;
+use crate::report::{ReportFormat, ReportOptions, build_report};
+use flate2::Compression;
+use flate2::write::{DeflateEncoder, GzEncoder};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use spaa_parse::{ParseError, ParseOptions, SpaaFile};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+use std::path::Path;
+use thiserror::Error;
+
+/// Version of the manifest layout.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Longest event name kept in a flame graph's file name.
+const MAX_EVENT_NAME: usize = 64;
+
+#[derive(Error, Debug)]
+pub enum BundleError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("failed to parse profile: {0}")]
+    Parse(#[from] ParseError),
+
+    #[error("failed to encode {0}: {1}")]
+    Json(&'static str, serde_json::Error),
+
+    #[error("{0} is too large for a zip archive, which is limited to 4 GiB; use a tarball")]
+    TooLargeForZip(String),
+}
+
+/// Archive format of a bundle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BundleFormat {
+    /// A gzipped ustar archive.
+    #[default]
+    TarGz,
+    /// A zip archive with deflated entries.
+    Zip,
+}
+
+impl BundleFormat {
+    /// The format named by `path`'s extension, if it names one.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else {
+            None
+        }
+    }
+
+    /// Conventional file extension, without the leading dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::TarGz => "tar.gz",
+            Self::Zip => "zip",
+        }
+    }
+}
+
+/// Options for [`write_bundle`].
+#[derive(Debug, Clone, Default)]
+pub struct BundleOptions {
+    pub format: BundleFormat,
+    /// Detail of `report.md`.
+    pub report: ReportOptions,
+    /// A `spaa convert --report` fidelity report, bundled as-is.
+    pub fidelity: Option<Vec<u8>>,
+    /// Creation time of the bundle and its entries, in Unix seconds.
+    pub created: u64,
+}
+
+/// Contents of `manifest.json`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Manifest {
+    /// Always [`MANIFEST_VERSION`].
+    pub version: u32,
+    /// The `spaa` version that wrote the bundle.
+    pub generator: String,
+    /// Creation time, in Unix seconds.
+    pub created: u64,
+    /// Source tool of the profile.
+    pub source_tool: String,
+    /// `source.build` stamps of the profile, such as `git_sha`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub build: BTreeMap<String, String>,
+    /// Every other file in the bundle, in archive order.
+    pub files: Vec<ManifestEntry>,
+}
+
+/// One file of a bundle.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ManifestEntry {
+    /// Path within the archive.
+    pub name: String,
+    /// What the file holds.
+    pub description: String,
+    pub bytes: u64,
+    /// Hex SHA-256 of the contents.
+    pub sha256: String,
+}
+
+/// Contents of `validation.json`: how the profile fared when parsed
+/// leniently.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Validation {
+    /// Whether every record parsed and checked out.
+    pub valid: bool,
+    /// Whether the profile ends with a footer, so it was written to
+    /// completion.
+    pub has_footer: bool,
+    /// Records that were skipped, in the order they were found.
+    pub warnings: Vec<ValidationWarning>,
+}
+
+/// A record skipped while validating.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidationWarning {
+    /// Line of the record, when known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Bundle the SPAA profile `data` with its report, flame graphs and
+/// validation, and write the archive to `writer`.
+///
+/// The profile is parsed leniently, so records that fail to parse are
+/// reported in `validation.json` rather than failing the bundle. Returns
+/// the manifest written.
+pub fn write_bundle<W: Write>(
+    data: &[u8],
+    options: &BundleOptions,
+    writer: W,
+) -> Result<Manifest, BundleError> {
+    let parse = ParseOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    let (file, diagnostics) = SpaaFile::parse_with_options(data, &parse)?;
+
+    let mut entries: Vec<(String, String, Vec<u8>)> = Vec::new();
+    let mut add = |name: String, description: &str, contents: Vec<u8>| {
+        entries.push((name, description.to_string(), contents));
+    };
+    add(
+        "profile.spaa".to_string(),
+        "The profile, unchanged",
+        data.to_vec(),
+    );
+
+    let mut report = Vec::new();
+    build_report(&file, &options.report).write(&mut report, ReportFormat::Markdown)?;
+    add("report.md".to_string(), "Markdown report", report);
+
+    // Distinct events can share a sanitized name, such as `a:b` and `a_b`.
+    let mut flame_names = HashSet::new();
+    for event in &file.header.events {
+        if let Some(svg) = render_flame_svg(&file, &event.name, &event.sampling.primary_metric) {
+            let part = file_name_part(&event.name);
+            let mut name = format!("flamegraph-{part}.svg");
+            for n in 2.. {
+                if flame_names.insert(name.clone()) {
+                    break;
+                }
+                name = format!("flamegraph-{part}-{n}.svg");
+            }
+            add(
+                name,
+                &format!(
+                    "Flame graph of {} by {}",
+                    event.name, event.sampling.primary_metric
+                ),
+                svg.into_bytes(),
+            );
+        }
+    }
+
+    let validation = Validation {
+        valid: diagnostics.warnings.is_empty(),
+        has_footer: diagnostics.has_footer,
+        warnings: diagnostics
+            .warnings
+            .iter()
+            .map(|w| ValidationWarning {
+                line: w.location.map(|l| l.line),
+                message: w.message.clone(),
+            })
+            .collect(),
+    };
+    let validation = serde_json::to_vec_pretty(&validation)
+        .map_err(|e| BundleError::Json("validation.json", e))?;
+    add(
+        "validation.json".to_string(),
+        "Lenient parse of the profile",
+        validation,
+    );
+
+    if let Some(fidelity) = &options.fidelity {
+        add(
+            "fidelity.json".to_string(),
+            "Conversion fidelity report",
+            fidelity.clone(),
+        );
+    }
+
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        generator: format!("spaa {}", env!("CARGO_PKG_VERSION")),
+        created: options.created,
+        source_tool: file.header.source_tool.clone(),
+        build: file
+            .header
+            .source
+            .as_ref()
+            .map(|s| s.build.clone())
+            .unwrap_or_default(),
+        files: entries
+            .iter()
+            .map(|(name, description, contents)| ManifestEntry {
+                name: name.clone(),
+                description: description.clone(),
+                bytes: contents.len() as u64,
+                sha256: format!("{:x}", Sha256::digest(contents)),
+            })
+            .collect(),
+    };
+    let manifest_json =
+        serde_json::to_vec_pretty(&manifest).map_err(|e| BundleError::Json("manifest.json", e))?;
+
+    let mut files: Vec<(&str, &[u8])> = vec![("manifest.json", &manifest_json)];
+    files.extend(
+        entries
+            .iter()
+            .map(|(name, _, data)| (name.as_str(), &data[..])),
+    );
+    match options.format {
+        BundleFormat::TarGz => {
+            let mut gz = GzEncoder::new(writer, Compression::default());
+            write_tar(&mut gz, &files, options.created)?;
+            gz.finish()?.flush()?;
+        }
+        BundleFormat::Zip => {
+            let mut writer = writer;
+            write_zip(&mut writer, &files, options.created)?;
+            writer.flush()?;
+        }
+    }
+    Ok(manifest)
+}
+
+/// `name` with anything but ASCII letters, digits, `.`, `-` and `_`
+/// replaced, so it is safe in any archive tool.
+fn file_name_part(name: &str) -> String {
+    name.chars()
+        .take(MAX_EVENT_NAME)
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Write `files` as a ustar archive. Names must be under 100 bytes.
+fn write_tar<W: Write>(out: &mut W, files: &[(&str, &[u8])], mtime: u64) -> io::Result<()> {
+    for (name, data) in files {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[108..116].copy_from_slice(b"0000000\0");
+        header[116..124].copy_from_slice(b"0000000\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+        header[136..148].copy_from_slice(format!("{:011o}\0", mtime).as_bytes());
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        // The checksum is computed with its own field as spaces.
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+        out.write_all(&header)?;
+        out.write_all(data)?;
+        out.write_all(&[0; 512][..(512 - data.len() % 512) % 512])?;
+    }
+    out.write_all(&[0; 1024])
+}
+
+/// Write `files` as a zip archive of deflated entries.
+///
+/// Zip64 records are not written, so the archive fails with
+/// [`BundleError::TooLargeForZip`] if an entry or the archive itself
+/// reaches 4 GiB.
+fn write_zip<W: Write>(
+    out: &mut W,
+    files: &[(&str, &[u8])],
+    mtime: u64,
+) -> Result<(), BundleError> {
+    const VERSION: u16 = 20;
+    const UTF8_NAMES: u16 = 1 << 11;
+    const DEFLATE: u16 = 8;
+
+    let (time, date) = dos_time(mtime);
+    let mut offset = 0u32;
+    let mut central = Vec::new();
+    for (name, data) in files {
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+        let mut deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(data)?;
+        let compressed = deflate.finish()?;
+        let too_large = || BundleError::TooLargeForZip(name.to_string());
+        let compressed_len = u32::try_from(compressed.len()).map_err(|_| too_large())?;
+        let data_len = u32::try_from(data.len()).map_err(|_| too_large())?;
+
+        let mut local = Vec::with_capacity(30 + name.len());
+        local.extend(0x0403_4b50u32.to_le_bytes());
+        local.extend(VERSION.to_le_bytes());
+        local.extend(UTF8_NAMES.to_le_bytes());
+        local.extend(DEFLATE.to_le_bytes());
+        local.extend(time.to_le_bytes());
+        local.extend(date.to_le_bytes());
+        local.extend(crc.sum().to_le_bytes());
+        local.extend(compressed_len.to_le_bytes());
+        local.extend(data_len.to_le_bytes());
+        local.extend((name.len() as u16).to_le_bytes());
+        local.extend(0u16.to_le_bytes());
+        local.extend(name.as_bytes());
+
+        central.extend(0x0201_4b50u32.to_le_bytes());
+        // Made by Unix, so the mode in the external attributes is used.
+        central.extend(((3 << 8) | VERSION).to_le_bytes());
+        central.extend(&local[4..28]);
+        central.extend([0; 8]);
+        central.extend((0o100644u32 << 16).to_le_bytes());
+        central.extend(offset.to_le_bytes());
+        central.extend(name.as_bytes());
+
+        out.write_all(&local)?;
+        out.write_all(&compressed)?;
+        // The next entry's offset must fit as well.
+        offset = u32::try_from(local.len() + compressed.len())
+            .ok()
+            .and_then(|len| offset.checked_add(len))
+            .ok_or_else(too_large)?;
+    }
+    let central_len = u32::try_from(central.len())
+        .ok()
+        .filter(|len| offset.checked_add(*len).is_some())
+        .ok_or_else(|| BundleError::TooLargeForZip("the archive".to_string()))?;
+    out.write_all(&central)?;
+
+    let mut end = Vec::with_capacity(22);
+    end.extend(0x0605_4b50u32.to_le_bytes());
+    end.extend([0; 4]);
+    end.extend((files.len() as u16).to_le_bytes());
+    end.extend((files.len() as u16).to_le_bytes());
+    end.extend(central_len.to_le_bytes());
+    end.extend(offset.to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    out.write_all(&end)?;
+    Ok(())
+}
+
+/// MS-DOS time and date fields for Unix time `secs`, in UTC and no earlier
+/// than 1980, the start of DOS time.
+fn dos_time(secs: u64) -> (u16, u16) {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = ((rem / 3600) << 11) | ((rem % 3600 / 60) << 5) | (rem % 60 / 2);
+    let date = ((year - 1980).min(127) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::{DeflateDecoder, GzDecoder};
+    use std::io::Read;
+
+    const PROFILE: &str = concat!(
+        r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"sched:sched_switch","kind":"probe","sampling":{"mode":"event","primary_metric":"events"}}],"source":{"tool":"perf","build":{"git_sha":"abc123"}}}"#,
+        "\n",
+        r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+        "\n",
+        r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+        "\n",
+        r#"{"type":"frame","id":2,"func":"poll","dso":1}"#,
+        "\n",
+        r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"sched:sched_switch"},"weights":[{"metric":"events","value":10}]}"#,
+        "\n",
+    );
+
+    /// Names and contents of the entries of an uncompressed tar archive.
+    fn untar(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut files = Vec::new();
+        let mut pos = 0;
+        while data[pos] != 0 {
+            let header = &data[pos..pos + 512];
+            let name = String::from_utf8(header[..100].split(|&b| b == 0).next().unwrap().to_vec());
+            let size = std::str::from_utf8(&header[124..135]).unwrap();
+            let size = usize::from_str_radix(size, 8).unwrap();
+            pos += 512;
+            files.push((name.unwrap(), data[pos..pos + size].to_vec()));
+            pos += size.div_ceil(512) * 512;
+        }
+        files
+    }
+
+    #[test]
+    fn tarball_holds_the_manifested_files() {
+        let data = format!("{PROFILE}{{\"type\":\"stack\",\"id\":\"0x2\"}}\n");
+        let options = BundleOptions {
+            fidelity: Some(b"{}".to_vec()),
+            created: 1_700_000_000,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let manifest = write_bundle(data.as_bytes(), &options, &mut out).unwrap();
+
+        let mut tar = Vec::new();
+        GzDecoder::new(&out[..]).read_to_end(&mut tar).unwrap();
+        let files = untar(&tar);
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "manifest.json",
+                "profile.spaa",
+                "report.md",
+                "flamegraph-sched_sched_switch.svg",
+                "validation.json",
+                "fidelity.json",
+            ]
+        );
+        assert_eq!(manifest.build["git_sha"], "abc123");
+        for (entry, (name, contents)) in manifest.files.iter().zip(&files[1..]) {
+            assert_eq!(&entry.name, name);
+            assert_eq!(entry.sha256, format!("{:x}", Sha256::digest(contents)));
+        }
+        assert_eq!(files[1].1, data.as_bytes());
+
+        let validation: serde_json::Value = serde_json::from_slice(&files[4].1).unwrap();
+        assert_eq!(validation["valid"], false);
+        assert_eq!(validation["warnings"][0]["line"], 6);
+    }
+
+    #[test]
+    fn zip_entries_inflate_to_their_contents() {
+        let options = BundleOptions {
+            format: BundleFormat::Zip,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let manifest = write_bundle(PROFILE.as_bytes(), &options, &mut out).unwrap();
+
+        let end = &out[out.len() - 22..];
+        assert_eq!(end[..4], 0x0605_4b50u32.to_le_bytes());
+        assert_eq!(
+            u16::from_le_bytes([end[10], end[11]]) as usize,
+            1 + manifest.files.len()
+        );
+
+        // The first entry after the manifest is the profile.
+        let manifest_len = u32::from_le_bytes(out[18..22].try_into().unwrap()) as usize;
+        let second = 30 + "manifest.json".len() + manifest_len;
+        let local = &out[second..];
+        let name_len = u16::from_le_bytes([local[26], local[27]]) as usize;
+        assert_eq!(&local[30..30 + name_len], b"profile.spaa");
+        let compressed_len = u32::from_le_bytes(local[18..22].try_into().unwrap()) as usize;
+        let mut profile = String::new();
+        DeflateDecoder::new(&local[30 + name_len..30 + name_len + compressed_len])
+            .read_to_string(&mut profile)
+            .unwrap();
+        assert_eq!(profile, PROFILE);
+    }
+
+    #[test]
+    fn flame_graph_names_are_unique() {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"a:b","kind":"probe","sampling":{"mode":"event","primary_metric":"events"}},{"name":"a_b","kind":"probe","sampling":{"mode":"event","primary_metric":"events"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[1],"context":{"event":"a:b"},"weights":[{"metric":"events","value":1}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[1],"context":{"event":"a_b"},"weights":[{"metric":"events","value":1}]}"#,
+        ]
+        .join("\n");
+        let manifest =
+            write_bundle(data.as_bytes(), &BundleOptions::default(), Vec::new()).unwrap();
+
+        let flame_graphs: Vec<&str> = manifest
+            .files
+            .iter()
+            .map(|f| f.name.as_str())
+            .filter(|name| name.starts_with("flamegraph-"))
+            .collect();
+        assert_eq!(flame_graphs, ["flamegraph-a_b.svg", "flamegraph-a_b-2.svg"]);
+    }
+}
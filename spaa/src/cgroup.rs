@@ -0,0 +1,103 @@
+//! Container attribution from cgroup paths.
+//!
+//! Container runtimes place each container in a cgroup whose path embeds
+//! the container ID, and Kubernetes adds the pod UID. For example:
+//!
+//! - Docker: `/system.slice/docker-<id>.scope` or `/docker/<id>`
+//! - Kubernetes (cgroupfs): `/kubepods/burstable/pod<uid>/<id>`
+//! - Kubernetes (systemd): `/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod<uid>.slice/cri-containerd-<id>.scope`
+//!
+//! # Example
+//!
+//! ```
+//! use spaa::cgroup::container_info;
+//!
+//! let info = container_info("/kubepods/burstable/pod6f1c2a3b-0000-4000-8000-000000000001/3f4e5d6c7b8a9f0e1d2c3b4a5f6e7d8c9b0a1f2e3d4c5b6a7f8e9d0c1b2a3f4e");
+//! assert_eq!(info.k8s_pod.as_deref(), Some("6f1c2a3b-0000-4000-8000-000000000001"));
+//! assert!(info.container_id.is_some());
+//! ```
+
+/// Container identity derived from a cgroup path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContainerInfo {
+    pub container_id: Option<String>,
+    pub k8s_pod: Option<String>,
+}
+
+/// Extract container and pod identifiers from a cgroup path.
+pub fn container_info(cgroup: &str) -> ContainerInfo {
+    let mut info = ContainerInfo::default();
+
+    for segment in cgroup.split('/').filter(|s| !s.is_empty()) {
+        // Strip systemd unit suffixes and runtime prefixes:
+        // `cri-containerd-<id>.scope`, `docker-<id>.scope`, `crio-<id>.scope`
+        let unit = segment
+            .strip_suffix(".scope")
+            .or_else(|| segment.strip_suffix(".slice"))
+            .unwrap_or(segment);
+
+        if let Some(pos) = unit.rfind("pod") {
+            let uid = &unit[pos + 3..];
+            if looks_like_uid(uid) {
+                // systemd escapes '-' in unit names as '_'.
+                info.k8s_pod = Some(uid.replace('_', "-"));
+                continue;
+            }
+        }
+
+        let candidate = unit.rsplit('-').next().unwrap_or(unit);
+        if is_container_id(candidate) {
+            info.container_id = Some(candidate.to_string());
+        }
+    }
+
+    info
+}
+
+/// Container IDs are 64 lowercase hex digits.
+fn is_container_id(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Pod UIDs are RFC 4122 UUIDs, possibly with '-' escaped as '_'.
+fn looks_like_uid(s: &str) -> bool {
+    s.len() == 36
+        && s.bytes()
+            .all(|b| b.is_ascii_hexdigit() || b == b'-' || b == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3f4e5d6c7b8a9f0e1d2c3b4a5f6e7d8c9b0a1f2e3d4c5b6a7f8e9d0c1b2a3f4e";
+
+    #[test]
+    fn docker_systemd_scope() {
+        let info = container_info(&format!("/system.slice/docker-{}.scope", ID));
+        assert_eq!(info.container_id.as_deref(), Some(ID));
+        assert_eq!(info.k8s_pod, None);
+    }
+
+    #[test]
+    fn kubernetes_systemd_slices() {
+        let path = format!(
+            "/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod6f1c2a3b_0000_4000_8000_000000000001.slice/cri-containerd-{}.scope",
+            ID
+        );
+        let info = container_info(&path);
+        assert_eq!(info.container_id.as_deref(), Some(ID));
+        assert_eq!(
+            info.k8s_pod.as_deref(),
+            Some("6f1c2a3b-0000-4000-8000-000000000001")
+        );
+    }
+
+    #[test]
+    fn plain_cgroup_has_no_container() {
+        assert_eq!(
+            container_info("/user.slice/user-1000.slice/session-2.scope"),
+            ContainerInfo::default()
+        );
+    }
+}
@@ -49,12 +49,13 @@
 //! converter.write_spaa(output).unwrap();
 //! ```
 
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use spaa_parse::{
-    EventDef, EventKind, ExclusiveWeights, FrameKind, FrameOrder, Header, Sampling, SamplingMode,
-    StackContext, StackIdMode, StackType, Weight,
+    ClockInfo, ClockSource, Dso, EventDef, EventKind, ExclusiveWeights, Frame, FrameKind,
+    FrameOrder, Header, Sampling, SamplingMode, SpaaFile, Stack, StackContext, StackIdMode,
+    StackType, Weight, Window, WindowStackWeight, WriteError,
 };
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::io::{Read, Write};
 use thiserror::Error;
@@ -68,6 +69,9 @@ pub enum ConvertError {
     #[error("JSON parse error: {0}")]
     Json(#[from] serde_json::Error),
 
+    #[error("SPAA write error: {0}")]
+    Write(#[from] WriteError),
+
     #[error("invalid profile: {0}")]
     InvalidProfile(String),
 
@@ -270,6 +274,30 @@ pub struct ProfileChunkCpuProfile {
 // Converter
 // ============================================================================
 
+/// Relative difference between sample and hit totals above which
+/// [`HitReconciliation::agrees`] fails.
+const HIT_TOLERANCE: f64 = 0.05;
+
+/// Per-node sample counts from the `samples` array checked against the
+/// nodes' own `hitCount`s, which V8 derives from the same ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HitReconciliation {
+    /// Entries in the `samples` array.
+    pub samples: u64,
+    /// Sum of node hit counts.
+    pub hits: u64,
+    /// Nodes whose hit count differs from the number of samples naming them.
+    pub mismatched_nodes: usize,
+}
+
+impl HitReconciliation {
+    /// Whether the two totals are within 5% of each other.
+    pub fn agrees(&self) -> bool {
+        let larger = self.samples.max(self.hits);
+        larger == 0 || self.samples.abs_diff(self.hits) as f64 / larger as f64 <= HIT_TOLERANCE
+    }
+}
+
 /// Converter from Chrome cpuprofile to SPAA format.
 pub struct CpuProfileConverter {
     profile: Option<CpuProfile>,
@@ -355,29 +383,27 @@ impl CpuProfileConverter {
             match event.name.as_str() {
                 "Profile" => {
                     // Extract start time from Profile event
-                    if let Some(data) = event.args.get("data") {
-                        if let Ok(profile_data) =
+                    if let Some(data) = event.args.get("data")
+                        && let Ok(profile_data) =
                             serde_json::from_value::<ProfileEventData>(data.clone())
-                        {
-                            profile_start_time = Some(profile_data.start_time);
-                        }
+                    {
+                        profile_start_time = Some(profile_data.start_time);
                     }
                 }
                 "ProfileChunk" => {
                     // Extract nodes, samples, and timeDeltas from ProfileChunk
-                    if let Some(data) = event.args.get("data") {
-                        if let Ok(chunk_data) =
+                    if let Some(data) = event.args.get("data")
+                        && let Ok(chunk_data) =
                             serde_json::from_value::<ProfileChunkData>(data.clone())
-                        {
-                            // Add nodes from this chunk
-                            if let Some(cpu_profile) = chunk_data.cpu_profile {
-                                all_nodes.extend(cpu_profile.nodes);
-                                all_samples.extend(cpu_profile.samples);
-                            }
-                            // Add time deltas
-                            all_time_deltas.extend(chunk_data.time_deltas);
-                            last_ts = event.ts;
+                    {
+                        // Add nodes from this chunk
+                        if let Some(cpu_profile) = chunk_data.cpu_profile {
+                            all_nodes.extend(cpu_profile.nodes);
+                            all_samples.extend(cpu_profile.samples);
                         }
+                        // Add time deltas
+                        all_time_deltas.extend(chunk_data.time_deltas);
+                        last_ts = event.ts;
                     }
                 }
                 _ => {}
@@ -417,6 +443,39 @@ impl CpuProfileConverter {
         Ok(())
     }
 
+    /// Compare the `samples` array with node hit counts, or `None` if
+    /// nothing is parsed or no node records a hit count.
+    pub fn reconcile_hits(&self) -> Option<HitReconciliation> {
+        let profile = self.profile.as_ref()?;
+        let hits: u64 = profile.nodes.iter().map(Self::node_hits).sum();
+        if hits == 0 {
+            return None;
+        }
+        let mut sampled: HashMap<u64, u64> = HashMap::new();
+        for &node_id in &profile.samples {
+            *sampled.entry(node_id).or_default() += 1;
+        }
+        let mismatched_nodes = profile
+            .nodes
+            .iter()
+            .filter(|n| Self::node_hits(n) != sampled.get(&n.id).copied().unwrap_or(0))
+            .count();
+        Some(HitReconciliation {
+            samples: profile.samples.len() as u64,
+            hits,
+            mismatched_nodes,
+        })
+    }
+
+    /// A node's hit count, falling back to its position ticks.
+    fn node_hits(node: &ProfileNode) -> u64 {
+        if node.hit_count > 0 {
+            node.hit_count
+        } else {
+            node.position_ticks.iter().map(|t| t.ticks).sum()
+        }
+    }
+
     /// Get the stack trace for a node by walking up to the root.
     /// Returns frames in leaf-to-root order.
     fn get_stack_for_node(&self, node_id: u64) -> Vec<u64> {
@@ -435,28 +494,46 @@ impl CpuProfileConverter {
         stack
     }
 
-    /// Write the parsed data as SPAA format to a writer.
-    pub fn write_spaa<W: Write>(&self, mut writer: W) -> Result<()> {
+    /// Build the SPAA profile in memory.
+    pub fn to_spaa_file(&self) -> Result<SpaaFile> {
         let profile = self
             .profile
             .as_ref()
             .ok_or_else(|| ConvertError::InvalidProfile("no profile parsed".into()))?;
 
-        if profile.samples.is_empty() {
+        let reconciliation = self.reconcile_hits();
+        let ticks = self.ticks(profile);
+        if ticks.is_empty() {
             return Err(ConvertError::NoSamples);
         }
+        if profile.samples.is_empty() {
+            tracing::warn!("cpuprofile has no samples; weighting stacks by node hit counts");
+        } else if let Some(r) = reconciliation
+            && !r.agrees()
+        {
+            tracing::warn!(
+                "cpuprofile hit counts total {} but {} samples were recorded ({} nodes disagree)",
+                r.hits,
+                r.samples,
+                r.mismatched_nodes
+            );
+        }
 
         // Build dictionaries
         // For cpuprofile, the "DSO" is the script URL
         let mut dso_map: HashMap<&str, u64> = HashMap::new();
         let mut frame_map: HashMap<u64, u64> = HashMap::new(); // node_id -> frame_id
 
-        // Collect unique DSOs (scripts) and frames from all nodes used in stacks
-        let mut used_nodes: std::collections::HashSet<u64> = std::collections::HashSet::new();
-        for &sample_node_id in &profile.samples {
-            let stack = self.get_stack_for_node(sample_node_id);
+        // Collect unique DSOs (scripts) and frames from all nodes used in
+        // stacks, in order of first appearance so IDs are reproducible
+        let mut seen_nodes: HashSet<u64> = HashSet::new();
+        let mut used_nodes: Vec<u64> = Vec::new();
+        for tick in &ticks {
+            let stack = self.get_stack_for_node(tick.node_id);
             for node_id in stack {
-                used_nodes.insert(node_id);
+                if seen_nodes.insert(node_id) {
+                    used_nodes.push(node_id);
+                }
             }
         }
 
@@ -483,24 +560,27 @@ impl CpuProfileConverter {
         }
 
         // Aggregate stacks from samples
-        let aggregated = self.aggregate_stacks(profile, &frame_map);
-
-        // Write header
-        let header = self.build_header(profile);
-        self.write_record(&mut writer, "header", &header)?;
-
-        // Write DSO dictionary
-        for (url, dso_id) in &dso_map {
-            let dso = DsoRecord {
-                id: *dso_id,
-                name: (*url).to_string(),
-                build_id: None,
-                is_kernel: false,
-            };
-            self.write_record(&mut writer, "dso", &dso)?;
-        }
+        let aggregated = self.aggregate_stacks(profile, &ticks, &frame_map);
+
+        let sample_count = ticks.iter().map(|t| t.count).sum();
+        let header = self.build_header(profile, sample_count);
 
-        // Write frame dictionary
+        // DSO dictionary
+        let dsos = dso_map
+            .iter()
+            .map(|(url, &id)| {
+                let dso = Dso {
+                    id,
+                    name: (*url).to_string(),
+                    build_id: None,
+                    is_kernel: false,
+                };
+                (id, dso)
+            })
+            .collect();
+
+        // Frame dictionary
+        let mut frames = HashMap::new();
         for (&node_id, &frame_id) in &frame_map {
             if let Some(&node_idx) = self.node_map.get(&node_id) {
                 let node = &profile.nodes[node_idx];
@@ -534,24 +614,47 @@ impl CpuProfileConverter {
                     node.call_frame.function_name.clone()
                 };
 
-                let frame = FrameRecord {
+                let frame = Frame {
                     id: frame_id,
                     func: func_name,
-                    func_resolved: true,
                     dso: dso_id,
+                    func_resolved: true,
                     ip: None,
                     symoff: None,
                     srcline,
+                    srcline_resolved: true,
                     inlined: false,
+                    inline_depth: None,
                     kind: FrameKind::User,
                 };
-                self.write_record(&mut writer, "frame", &frame)?;
+                frames.insert(frame_id, frame);
             }
         }
 
-        // Write stacks
+        // Stacks
+        let mut stacks = HashMap::new();
         for (stack_key, stack_data) in &aggregated {
-            let stack = StackRecord {
+            let mut weights = vec![
+                Weight {
+                    metric: "samples".to_string(),
+                    value: stack_data.sample_count,
+                    unit: None,
+                },
+                Weight {
+                    metric: "time_us".to_string(),
+                    value: stack_data.total_time_us,
+                    unit: Some("microseconds".to_string()),
+                },
+            ];
+            // Each stack ends at a single node, so its hits are that node's
+            if reconciliation.is_some() {
+                weights.push(Weight {
+                    metric: "hits".to_string(),
+                    value: stack_data.hits,
+                    unit: None,
+                });
+            }
+            let stack = Stack {
                 id: stack_key.id.clone(),
                 frames: stack_key.frame_ids.clone(),
                 stack_type: StackType::User,
@@ -565,47 +668,44 @@ impl CpuProfileConverter {
                     execname: None,
                     uid: None,
                     zonename: None,
+                    cgroup: None,
+                    container_id: None,
+                    k8s_pod: None,
                     trace_fields: None,
                     extra: HashMap::new(),
                 },
-                weights: vec![
-                    Weight {
-                        metric: "samples".to_string(),
-                        value: stack_data.sample_count,
-                        unit: None,
-                    },
-                    Weight {
-                        metric: "time_us".to_string(),
-                        value: stack_data.total_time_us,
-                        unit: Some("microseconds".to_string()),
-                    },
-                ],
                 exclusive: stack_key.frame_ids.first().map(|&leaf| ExclusiveWeights {
                     frame: leaf,
-                    weights: vec![
-                        Weight {
-                            metric: "samples".to_string(),
-                            value: stack_data.sample_count,
-                            unit: None,
-                        },
-                        Weight {
-                            metric: "time_us".to_string(),
-                            value: stack_data.total_time_us,
-                            unit: Some("microseconds".to_string()),
-                        },
-                    ],
+                    weights: weights.clone(),
                 }),
+                weights,
                 related_stacks: None,
             };
-            self.write_record(&mut writer, "stack", &stack)?;
+            stacks.insert(stack.id.clone(), stack);
         }
 
+        Ok(SpaaFile {
+            header,
+            system: None,
+            dsos,
+            frames,
+            threads: HashMap::new(),
+            stacks,
+            samples: Vec::new(),
+            windows: Vec::new(),
+            states: Vec::new(),
+            notes: Vec::new(),
+        })
+    }
+
+    /// Write the parsed data as SPAA format to a writer.
+    pub fn write_spaa<W: Write>(&self, writer: W) -> Result<()> {
+        self.to_spaa_file()?.write(writer)?;
         Ok(())
     }
 
-    fn build_header(&self, profile: &CpuProfile) -> Header {
+    fn build_header(&self, profile: &CpuProfile, sample_count: u64) -> Header {
         let duration_us = profile.end_time.saturating_sub(profile.start_time);
-        let sample_count = profile.samples.len() as u64;
         let frequency_hz = if duration_us > 0 && sample_count > 0 {
             // Estimate sampling frequency
             Some((sample_count * 1_000_000) / duration_us)
@@ -625,6 +725,8 @@ impl CpuProfileConverter {
             kind: EventKind::Timer,
             sampling,
             allocation_tracking: None,
+            description: None,
+            unit: None,
         };
 
         Header {
@@ -642,21 +744,63 @@ impl CpuProfileConverter {
                 tool: "chrome-devtools".to_string(),
                 command: None,
                 tool_version: None,
+                data_loss: None,
+                build: BTreeMap::new(),
             }),
             stack_id_mode: StackIdMode::ContentAddressable,
+            clock: Some(ClockInfo::new(ClockSource::Monotonic)),
+            approximation: None,
+        }
+    }
+
+    /// Weighted leaf nodes to build stacks from: one per entry of the
+    /// `samples` array, or, for profiles that only record hit counts, one
+    /// per node with hits, spreading the profile duration evenly over them.
+    fn ticks(&self, profile: &CpuProfile) -> Vec<Tick> {
+        let duration_us = profile.end_time.saturating_sub(profile.start_time);
+        if profile.samples.is_empty() {
+            let total: u64 = profile.nodes.iter().map(Self::node_hits).sum();
+            return profile
+                .nodes
+                .iter()
+                .filter_map(|node| {
+                    let hits = Self::node_hits(node);
+                    (hits > 0).then(|| Tick {
+                        node_id: node.id,
+                        count: hits,
+                        time_us: duration_us * hits / total,
+                    })
+                })
+                .collect();
         }
+
+        profile
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(sample_idx, &node_id)| Tick {
+                node_id,
+                count: 1,
+                // Use the sample's time delta, or estimate from the duration
+                time_us: profile.time_deltas.get(sample_idx).map_or_else(
+                    || duration_us / profile.samples.len() as u64,
+                    |delta| delta.unsigned_abs(),
+                ),
+            })
+            .collect()
     }
 
     fn aggregate_stacks(
         &self,
         profile: &CpuProfile,
+        ticks: &[Tick],
         frame_map: &HashMap<u64, u64>,
-    ) -> HashMap<StackKey, StackData> {
-        let mut aggregated: HashMap<StackKey, StackData> = HashMap::new();
+    ) -> BTreeMap<StackKey, StackData> {
+        let mut aggregated: BTreeMap<StackKey, StackData> = BTreeMap::new();
 
-        for (sample_idx, &sample_node_id) in profile.samples.iter().enumerate() {
+        for tick in ticks {
             // Get the stack for this sample
-            let node_stack = self.get_stack_for_node(sample_node_id);
+            let node_stack = self.get_stack_for_node(tick.node_id);
 
             // Convert node IDs to frame IDs
             let frame_ids: Vec<u64> = node_stack
@@ -668,31 +812,23 @@ impl CpuProfileConverter {
                 continue;
             }
 
-            // Get time delta for this sample (or estimate if not available)
-            let time_us = if sample_idx < profile.time_deltas.len() {
-                profile.time_deltas[sample_idx].unsigned_abs()
-            } else {
-                // If no time deltas, estimate based on total duration
-                if !profile.samples.is_empty() {
-                    let duration_us = profile.end_time.saturating_sub(profile.start_time);
-                    duration_us / profile.samples.len() as u64
-                } else {
-                    0
-                }
-            };
-
             let stack_id = Self::compute_stack_id(&frame_ids);
             let key = StackKey {
                 id: stack_id,
                 frame_ids,
             };
 
+            let hits = self
+                .node_map
+                .get(&tick.node_id)
+                .map_or(0, |&idx| Self::node_hits(&profile.nodes[idx]));
             let data = aggregated.entry(key).or_insert(StackData {
                 sample_count: 0,
                 total_time_us: 0,
+                hits,
             });
-            data.sample_count += 1;
-            data.total_time_us += time_us;
+            data.sample_count += tick.count;
+            data.total_time_us += tick.time_us;
         }
 
         aggregated
@@ -704,23 +840,6 @@ impl CpuProfileConverter {
         frame_ids.hash(&mut hasher);
         format!("0x{:016x}", hasher.finish())
     }
-
-    fn write_record<W: Write, T: Serialize>(
-        &self,
-        writer: &mut W,
-        record_type: &str,
-        data: &T,
-    ) -> Result<()> {
-        let mut map = serde_json::to_value(data)?;
-        if let serde_json::Value::Object(ref mut obj) = map {
-            obj.insert(
-                "type".to_string(),
-                serde_json::Value::String(record_type.to_string()),
-            );
-        }
-        writeln!(writer, "{}", serde_json::to_string(&map)?)?;
-        Ok(())
-    }
 }
 
 impl Default for CpuProfileConverter {
@@ -729,6 +848,61 @@ impl Default for CpuProfileConverter {
     }
 }
 
+impl crate::convert::Converter for CpuProfileConverter {
+    fn name(&self) -> &str {
+        "cpuprofile"
+    }
+
+    /// A `.cpuprofile` has `nodes` with `callFrame`s; a Performance trace
+    /// has `traceEvents`, which hold a CPU profile if they include
+    /// `ProfileChunk` events.
+    fn detect(&self, data: &[u8]) -> Option<crate::convert::Confidence> {
+        use crate::convert::Confidence;
+        let text = crate::convert::text_prefix(data);
+        if !text.trim_start().starts_with('{') || text.contains("\"snapshot\"") {
+            return None;
+        }
+        if text.contains("\"nodes\"") && text.contains("\"callFrame\"") {
+            Some(Confidence::High)
+        } else if text.contains("\"traceEvents\"") {
+            Some(if text.contains("ProfileChunk") {
+                Confidence::High
+            } else {
+                Confidence::Medium
+            })
+        } else {
+            None
+        }
+    }
+
+    fn parse(
+        &mut self,
+        reader: &mut dyn Read,
+    ) -> std::result::Result<(), crate::convert::ConvertError> {
+        Ok(CpuProfileConverter::parse(self, reader)?)
+    }
+
+    fn to_spaa_file(&self) -> std::result::Result<SpaaFile, crate::convert::ConvertError> {
+        Ok(CpuProfileConverter::to_spaa_file(self)?)
+    }
+
+    /// Samples referring to a node the profile does not define are
+    /// dropped.
+    fn fidelity(&self) -> Option<crate::convert::Fidelity> {
+        let profile = self.profile.as_ref()?;
+        Some(crate::convert::Fidelity {
+            records_read: profile.nodes.len() as u64,
+            samples_read: profile.samples.len() as u64,
+            samples_dropped: profile
+                .samples
+                .iter()
+                .filter(|id| !self.node_map.contains_key(id))
+                .count() as u64,
+            ..Default::default()
+        })
+    }
+}
+
 // ============================================================================
 // Chrome Heap Snapshot format types
 // ============================================================================
@@ -813,7 +987,6 @@ struct FunctionInfo {
 /// Parsed trace tree node.
 #[derive(Debug, Clone)]
 struct ParsedTraceNode {
-    #[allow(dead_code)]
     id: u64,
     function_info_index: usize,
     count: u64,
@@ -827,7 +1000,6 @@ struct HeapTimelineSample {
     /// Timestamp in microseconds.
     timestamp_us: u64,
     /// Last assigned object ID at this sample point.
-    #[allow(dead_code)]
     last_assigned_id: u64,
 }
 
@@ -1038,11 +1210,11 @@ impl HeapSnapshotConverter {
                 });
 
                 // Recursively parse grandchildren
-                if let Some(gc_arr) = grandchildren.as_array() {
-                    if !gc_arr.is_empty() {
-                        let gc_indices = self.parse_children_array(gc_arr, nodes);
-                        nodes[child_idx].children = gc_indices;
-                    }
+                if let Some(gc_arr) = grandchildren.as_array()
+                    && !gc_arr.is_empty()
+                {
+                    let gc_indices = self.parse_children_array(gc_arr, nodes);
+                    nodes[child_idx].children = gc_indices;
                 }
 
                 i += 5;
@@ -1080,11 +1252,11 @@ impl HeapSnapshotConverter {
             });
 
             // Recursively parse grandchildren
-            if let Some(gc_arr) = grandchildren.as_array() {
-                if !gc_arr.is_empty() {
-                    let gc_indices = self.parse_children_array(gc_arr, nodes);
-                    nodes[child_idx].children = gc_indices;
-                }
+            if let Some(gc_arr) = grandchildren.as_array()
+                && !gc_arr.is_empty()
+            {
+                let gc_indices = self.parse_children_array(gc_arr, nodes);
+                nodes[child_idx].children = gc_indices;
             }
 
             i += 5;
@@ -1093,9 +1265,9 @@ impl HeapSnapshotConverter {
         child_indices
     }
 
-    /// Write the parsed heap snapshot as SPAA format.
-    pub fn write_spaa<W: Write>(&self, mut writer: W) -> Result<()> {
-        let _snapshot = self
+    /// Build the SPAA profile in memory.
+    pub fn to_spaa_file(&self) -> Result<SpaaFile> {
+        let snapshot = self
             .snapshot
             .as_ref()
             .ok_or_else(|| ConvertError::InvalidProfile("no snapshot parsed".into()))?;
@@ -1105,7 +1277,8 @@ impl HeapSnapshotConverter {
         }
 
         // Build stacks by walking the trace tree
-        let mut stacks: Vec<(Vec<usize>, u64, u64)> = Vec::new(); // (function_info_indices, count, size)
+        // (function_info_indices, count, size, trace_node_id)
+        let mut stacks: Vec<(Vec<usize>, u64, u64, u64)> = Vec::new();
         self.collect_stacks(0, &mut Vec::new(), &mut stacks);
 
         if stacks.is_empty() {
@@ -1115,7 +1288,7 @@ impl HeapSnapshotConverter {
         // Build DSO map (script_name -> dso_id)
         let mut dso_map: HashMap<&str, u64> = HashMap::new();
         // Collect all unique DSOs and frames
-        for (stack, _, _) in &stacks {
+        for (stack, _, _, _) in &stacks {
             for &func_idx in stack {
                 if func_idx < self.function_infos.len() {
                     let func = &self.function_infos[func_idx];
@@ -1133,26 +1306,25 @@ impl HeapSnapshotConverter {
             }
         }
 
-        // Write header
-        let header = self.build_header();
-        self.write_record(&mut writer, "header", &header)?;
-
-        // Write DSOs
-        for (script, dso_id) in &dso_map {
-            let dso = DsoRecord {
-                id: *dso_id,
-                name: (*script).to_string(),
-                build_id: None,
-                is_kernel: false,
-            };
-            self.write_record(&mut writer, "dso", &dso)?;
-        }
+        let dsos = dso_map
+            .iter()
+            .map(|(script, &id)| {
+                let dso = Dso {
+                    id,
+                    name: (*script).to_string(),
+                    build_id: None,
+                    is_kernel: false,
+                };
+                (id, dso)
+            })
+            .collect();
 
-        // Assign frame IDs and write frames
+        // Assign frame IDs and build frames
+        let mut frames = HashMap::new();
         let mut frame_id_counter: u64 = 1;
         let mut func_to_frame: HashMap<usize, u64> = HashMap::new();
 
-        for (stack, _, _) in &stacks {
+        for (stack, _, _, _) in &stacks {
             for &func_idx in stack {
                 if !func_to_frame.contains_key(&func_idx) && func_idx < self.function_infos.len() {
                     let func = &self.function_infos[func_idx];
@@ -1179,18 +1351,20 @@ impl HeapSnapshotConverter {
                         func.name.clone()
                     };
 
-                    let frame = FrameRecord {
+                    let frame = Frame {
                         id: frame_id_counter,
                         func: func_name,
-                        func_resolved: true,
                         dso: dso_id,
+                        func_resolved: true,
                         ip: None,
                         symoff: None,
                         srcline,
+                        srcline_resolved: true,
                         inlined: false,
+                        inline_depth: None,
                         kind: FrameKind::User,
                     };
-                    self.write_record(&mut writer, "frame", &frame)?;
+                    frames.insert(frame_id_counter, frame);
 
                     func_to_frame.insert(func_idx, frame_id_counter);
                     frame_id_counter += 1;
@@ -1198,8 +1372,10 @@ impl HeapSnapshotConverter {
             }
         }
 
-        // Write stacks
-        for (stack, count, size) in &stacks {
+        // Stacks
+        let mut stack_records = HashMap::new();
+        let mut trace_stacks: HashMap<u64, String> = HashMap::new();
+        for (stack, count, size, trace_node_id) in &stacks {
             if *count == 0 && *size == 0 {
                 continue; // Skip empty stacks
             }
@@ -1216,9 +1392,10 @@ impl HeapSnapshotConverter {
             }
 
             let stack_id = Self::compute_stack_id(&frame_ids);
+            trace_stacks.insert(*trace_node_id, stack_id.clone());
 
-            let stack_record = StackRecord {
-                id: stack_id,
+            let stack_record = Stack {
+                id: stack_id.clone(),
                 frames: frame_ids.clone(),
                 stack_type: StackType::User,
                 context: StackContext {
@@ -1231,6 +1408,9 @@ impl HeapSnapshotConverter {
                     execname: None,
                     uid: None,
                     zonename: None,
+                    cgroup: None,
+                    container_id: None,
+                    k8s_pod: None,
                     trace_fields: None,
                     extra: HashMap::new(),
                 },
@@ -1263,18 +1443,106 @@ impl HeapSnapshotConverter {
                 }),
                 related_stacks: None,
             };
-            self.write_record(&mut writer, "stack", &stack_record)?;
+            stack_records.insert(stack_id, stack_record);
         }
 
+        Ok(SpaaFile {
+            header: self.build_header(),
+            system: None,
+            dsos,
+            frames,
+            threads: HashMap::new(),
+            stacks: stack_records,
+            samples: Vec::new(),
+            windows: self.build_windows(snapshot, &trace_stacks),
+            states: Vec::new(),
+            notes: Vec::new(),
+        })
+    }
+
+    /// Write the parsed heap snapshot as SPAA format.
+    pub fn write_spaa<W: Write>(&self, writer: W) -> Result<()> {
+        self.to_spaa_file()?.write(writer)?;
         Ok(())
     }
 
+    /// One window per interval between timeline samples, weighting each
+    /// stack by the objects it allocated in that interval.
+    ///
+    /// V8 assigns object IDs in increasing order, so an object was
+    /// allocated in the interval whose samples bracket its ID. Objects
+    /// collected before the snapshot was taken are not in it, so windows
+    /// count surviving allocations only. Empty windows are kept so the
+    /// timeline has no gaps.
+    fn build_windows(
+        &self,
+        snapshot: &HeapSnapshot,
+        trace_stacks: &HashMap<u64, String>,
+    ) -> Vec<Window> {
+        let fields = &snapshot.snapshot.meta.node_fields;
+        let field = |name: &str| fields.iter().position(|f| f == name);
+        let (Some(id_idx), Some(size_idx), Some(trace_idx)) =
+            (field("id"), field("self_size"), field("trace_node_id"))
+        else {
+            return Vec::new();
+        };
+        let samples = &self.timeline_samples;
+        if samples.len() < 2 {
+            return Vec::new();
+        }
+
+        // (bytes, count) by stack, for each interval
+        let mut intervals: Vec<BTreeMap<&str, (u64, u64)>> =
+            vec![BTreeMap::new(); samples.len() - 1];
+        for node in snapshot.nodes.chunks_exact(fields.len()) {
+            let Some(stack_id) = trace_stacks.get(&node[trace_idx]) else {
+                continue;
+            };
+            let sample = samples.partition_point(|s| s.last_assigned_id < node[id_idx]);
+            if sample == 0 || sample == samples.len() {
+                continue;
+            }
+            let entry = intervals[sample - 1].entry(stack_id).or_default();
+            entry.0 += node[size_idx];
+            entry.1 += 1;
+        }
+
+        intervals
+            .into_iter()
+            .enumerate()
+            .map(|(index, by_stack)| Window {
+                id: format!("w{}", index + 1),
+                start: samples[index].timestamp_us as f64 / 1_000_000.0,
+                end: samples[index + 1].timestamp_us as f64 / 1_000_000.0,
+                unit: "seconds".to_string(),
+                by_stack: by_stack
+                    .into_iter()
+                    .map(|(stack_id, (bytes, count))| WindowStackWeight {
+                        stack_id: stack_id.to_string(),
+                        weights: vec![
+                            Weight {
+                                metric: "alloc_bytes".to_string(),
+                                value: bytes,
+                                unit: Some("bytes".to_string()),
+                            },
+                            Weight {
+                                metric: "alloc_count".to_string(),
+                                value: count,
+                                unit: None,
+                            },
+                        ],
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
     /// Recursively collect stacks from the trace tree.
     fn collect_stacks(
         &self,
         node_idx: usize,
         current_stack: &mut Vec<usize>,
-        stacks: &mut Vec<(Vec<usize>, u64, u64)>,
+        stacks: &mut Vec<(Vec<usize>, u64, u64, u64)>,
     ) {
         if node_idx >= self.trace_nodes.len() {
             return;
@@ -1289,7 +1557,7 @@ impl HeapSnapshotConverter {
 
         // If this node has allocations, record the stack
         if node.count > 0 || node.size > 0 {
-            stacks.push((current_stack.clone(), node.count, node.size));
+            stacks.push((current_stack.clone(), node.count, node.size, node.id));
         }
 
         // Recurse into children
@@ -1319,6 +1587,8 @@ impl HeapSnapshotConverter {
                 tracks_frees: false,
                 has_timestamps: self.is_timeline,
             }),
+            description: None,
+            unit: None,
         };
 
         // Compute time range from timeline samples if available
@@ -1356,13 +1626,19 @@ impl HeapSnapshotConverter {
             source_tool: source_tool.to_string(),
             frame_order: FrameOrder::LeafToRoot,
             events: vec![event],
+            clock: time_range
+                .is_some()
+                .then(|| ClockInfo::new(ClockSource::Monotonic)),
             time_range,
             source: Some(spaa_parse::SourceInfo {
                 tool: "chrome-devtools".to_string(),
                 command: None,
                 tool_version: None,
+                data_loss: None,
+                build: BTreeMap::new(),
             }),
             stack_id_mode: StackIdMode::ContentAddressable,
+            approximation: None,
         }
     }
 
@@ -1372,23 +1648,6 @@ impl HeapSnapshotConverter {
         frame_ids.hash(&mut hasher);
         format!("0x{:016x}", hasher.finish())
     }
-
-    fn write_record<W: Write, T: Serialize>(
-        &self,
-        writer: &mut W,
-        record_type: &str,
-        data: &T,
-    ) -> Result<()> {
-        let mut map = serde_json::to_value(data)?;
-        if let serde_json::Value::Object(ref mut obj) = map {
-            obj.insert(
-                "type".to_string(),
-                serde_json::Value::String(record_type.to_string()),
-            );
-        }
-        writeln!(writer, "{}", serde_json::to_string(&map)?)?;
-        Ok(())
-    }
 }
 
 impl Default for HeapSnapshotConverter {
@@ -1397,6 +1656,38 @@ impl Default for HeapSnapshotConverter {
     }
 }
 
+impl crate::convert::Converter for HeapSnapshotConverter {
+    fn name(&self) -> &str {
+        "heapsnapshot"
+    }
+
+    /// Heap snapshots open with a `snapshot` object; those recorded with
+    /// allocation tracking also carry `trace_function_infos`.
+    fn detect(&self, data: &[u8]) -> Option<crate::convert::Confidence> {
+        use crate::convert::Confidence;
+        let text = crate::convert::text_prefix(data);
+        if !text.trim_start().starts_with('{') || !text.contains("\"snapshot\"") {
+            return None;
+        }
+        Some(if text.contains("\"trace_function_infos\"") {
+            Confidence::High
+        } else {
+            Confidence::Medium
+        })
+    }
+
+    fn parse(
+        &mut self,
+        reader: &mut dyn Read,
+    ) -> std::result::Result<(), crate::convert::ConvertError> {
+        Ok(HeapSnapshotConverter::parse(self, reader)?)
+    }
+
+    fn to_spaa_file(&self) -> std::result::Result<SpaaFile, crate::convert::ConvertError> {
+        Ok(HeapSnapshotConverter::to_spaa_file(self)?)
+    }
+}
+
 // ============================================================================
 // Unified converter for auto-detection
 // ============================================================================
@@ -1421,18 +1712,16 @@ pub fn detect_profile_type(contents: &str) -> Result<ProfileType> {
     if value.get("snapshot").is_some() && value.get("nodes").is_some() {
         // Both heap snapshot and heap timeline have "snapshot" and "nodes".
         // Heap timeline has a non-empty "samples" array with timestamp data.
-        if let Some(samples) = value.get("samples") {
-            if let Some(arr) = samples.as_array() {
-                if !arr.is_empty() {
-                    // Check if snapshot.meta has sample_fields (heap timeline indicator)
-                    if let Some(snapshot) = value.get("snapshot") {
-                        if let Some(meta) = snapshot.get("meta") {
-                            if meta.get("sample_fields").is_some() {
-                                return Ok(ProfileType::HeapTimeline);
-                            }
-                        }
-                    }
-                }
+        if let Some(samples) = value.get("samples")
+            && let Some(arr) = samples.as_array()
+            && !arr.is_empty()
+        {
+            // Check if snapshot.meta has sample_fields (heap timeline indicator)
+            if let Some(snapshot) = value.get("snapshot")
+                && let Some(meta) = snapshot.get("meta")
+                && meta.get("sample_fields").is_some()
+            {
+                return Ok(ProfileType::HeapTimeline);
             }
         }
         Ok(ProfileType::HeapSnapshot)
@@ -1449,57 +1738,28 @@ pub fn detect_profile_type(contents: &str) -> Result<ProfileType> {
 }
 
 // ============================================================================
-// Serialization records
+// Aggregation
 // ============================================================================
 
-#[derive(Serialize)]
-struct DsoRecord {
-    id: u64,
-    name: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    build_id: Option<String>,
-    is_kernel: bool,
-}
-
-#[derive(Serialize)]
-struct FrameRecord {
-    id: u64,
-    func: String,
-    func_resolved: bool,
-    dso: u64,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    ip: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    symoff: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    srcline: Option<String>,
-    inlined: bool,
-    kind: FrameKind,
-}
-
-#[derive(Serialize)]
-struct StackRecord {
-    id: String,
-    frames: Vec<u64>,
-    stack_type: StackType,
-    context: StackContext,
-    weights: Vec<Weight>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    exclusive: Option<ExclusiveWeights>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    related_stacks: Option<Vec<String>>,
-}
-
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct StackKey {
     id: String,
     frame_ids: Vec<u64>,
 }
 
+/// A leaf node and the weight it contributes.
+#[derive(Debug, Clone, Copy)]
+struct Tick {
+    node_id: u64,
+    count: u64,
+    time_us: u64,
+}
+
 #[derive(Debug, Clone)]
 struct StackData {
     sample_count: u64,
     total_time_us: u64,
+    hits: u64,
 }
 
 #[cfg(test)]
@@ -1752,6 +2012,19 @@ mod tests {
         assert_eq!(stack, vec![5, 3, 2, 1]);
     }
 
+    #[test]
+    fn output_is_reproducible() {
+        let convert = || {
+            let mut converter = CpuProfileConverter::new();
+            converter.parse(Cursor::new(sample_cpuprofile())).unwrap();
+            let mut output = Vec::new();
+            converter.write_spaa(&mut output).unwrap();
+            output
+        };
+
+        assert_eq!(convert(), convert());
+    }
+
     #[test]
     fn convert_to_spaa() {
         let cursor = Cursor::new(sample_cpuprofile());
@@ -1808,6 +2081,30 @@ mod tests {
         assert!(matches!(result, Err(ConvertError::NoSamples)));
     }
 
+    #[test]
+    fn hit_counts_stand_in_for_missing_samples() {
+        let profile = sample_cpuprofile()
+            .replace("[3, 4, 4, 4, 5, 5, 4, 4, 3, 5]", "[]")
+            .replace(
+                "[100000, 100000, 100000, 100000, 100000, 100000, 100000, 100000, 100000, 100000]",
+                "[]",
+            );
+        let mut converter = CpuProfileConverter::new();
+        converter.parse(Cursor::new(profile)).unwrap();
+        let mut output = Vec::new();
+        converter.write_spaa(&mut output).unwrap();
+
+        let spaa = spaa_parse::SpaaFile::parse(Cursor::new(output)).unwrap();
+        let mut samples: Vec<(u64, u64)> = spaa
+            .stacks
+            .values()
+            .map(|s| (s.weights[0].value, s.weights[1].value))
+            .collect();
+        samples.sort();
+        // 1s of profile spread over 10 hits
+        assert_eq!(samples, vec![(2, 200000), (3, 300000), (5, 500000)]);
+    }
+
     #[test]
     fn handles_anonymous_functions() {
         let profile = r#"{
@@ -1946,6 +2243,47 @@ mod tests {
         assert_eq!(main_frame.srcline, Some("app.js:11:1".to_string()));
     }
 
+    #[test]
+    fn hit_counts_agree_with_samples() {
+        let mut converter = CpuProfileConverter::new();
+        converter.parse(Cursor::new(sample_cpuprofile())).unwrap();
+
+        let reconciliation = converter.reconcile_hits().unwrap();
+        assert_eq!(reconciliation.samples, 10);
+        assert_eq!(reconciliation.hits, 10);
+        assert_eq!(reconciliation.mismatched_nodes, 0);
+        assert!(reconciliation.agrees());
+    }
+
+    #[test]
+    fn hit_count_disagreement_is_detected() {
+        let profile = sample_cpuprofile().replace(r#""hitCount": 5"#, r#""hitCount": 50"#);
+        let mut converter = CpuProfileConverter::new();
+        converter.parse(Cursor::new(profile)).unwrap();
+
+        let reconciliation = converter.reconcile_hits().unwrap();
+        assert_eq!(reconciliation.mismatched_nodes, 1);
+        assert!(!reconciliation.agrees());
+    }
+
+    #[test]
+    fn stacks_carry_hits_metric() {
+        let mut converter = CpuProfileConverter::new();
+        converter.parse(Cursor::new(sample_cpuprofile())).unwrap();
+        let mut output = Vec::new();
+        converter.write_spaa(&mut output).unwrap();
+
+        let spaa = spaa_parse::SpaaFile::parse(Cursor::new(output)).unwrap();
+        let mut hits: Vec<u64> = spaa
+            .stacks
+            .values()
+            .filter_map(|s| s.weights.iter().find(|w| w.metric == "hits"))
+            .map(|w| w.value)
+            .collect();
+        hits.sort();
+        assert_eq!(hits, vec![2, 3, 5]);
+    }
+
     // ========================================================================
     // Heap Snapshot tests
     // ========================================================================
@@ -2189,6 +2527,29 @@ mod tests {
         assert!(allocation_tracking.has_timestamps);
     }
 
+    #[test]
+    fn heap_timeline_windows_attribute_objects_by_id() {
+        // Objects 2 and 3 were assigned IDs between the first and second,
+        // and second and third, samples.
+        let timeline = sample_heap_timeline().replace(
+            "[1000000, 100, 2000000, 150, 3000000, 200, 4000000, 250]",
+            "[1000000, 1, 2000000, 2, 3000000, 3]",
+        );
+        let mut converter = HeapSnapshotConverter::new();
+        converter.parse(Cursor::new(timeline)).unwrap();
+        let spaa = converter.to_spaa_file().unwrap();
+
+        let windows: Vec<(f64, f64, Vec<u64>)> = spaa
+            .windows
+            .iter()
+            .map(|w| {
+                let bytes = w.by_stack.iter().map(|s| s.weights[0].value).collect();
+                (w.start, w.end, bytes)
+            })
+            .collect();
+        assert_eq!(windows, vec![(1.0, 2.0, vec![200]), (2.0, 3.0, vec![300])]);
+    }
+
     #[test]
     fn heap_snapshot_does_not_have_timestamps_flag() {
         let cursor = Cursor::new(sample_heap_snapshot());
@@ -0,0 +1,547 @@
+//! Exit codes, error reporting and logging shared by the SPAA command-line
+//! tools.
+//!
+//! Every binary exits with an [`ErrorKind`] code on failure, so scripts and
+//! agents can tell a missing file from a malformed profile without parsing
+//! messages:
+//!
+//! | Code | Kind | Meaning |
+//! |------|------|---------|
+//! | 0 | | Success |
+//! | 1 | `failure` | Any other error |
+//! | 2 | `usage` | Invalid command-line arguments |
+//! | 3 | `io` | A file could not be read or written |
+//! | 4 | `parse` | Input is not well-formed |
+//! | 5 | `validation` | Input is well-formed but inconsistent |
+//! | 6 | `unsupported_format` | Input format is not recognized |
+//!
+//! With `--error-format json`, the error is written to stderr as a single
+//! line, `{"error":{"kind":"parse","code":4,"message":"..."}}`.
+//!
+//! The library reports progress through `tracing` rather than printing.
+//! [`LogArgs`] adds `-v`, `-q` and `--log-format json` to a binary and
+//! installs a subscriber that writes those events to stderr.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use clap::Parser;
+//! use spaa::cli::{CliError, ErrorArgs, LogArgs};
+//! use std::process::ExitCode;
+//!
+//! #[derive(Parser)]
+//! struct Args {
+//!     input: std::path::PathBuf,
+//!     #[command(flatten)]
+//!     errors: ErrorArgs,
+//!     #[command(flatten)]
+//!     log: LogArgs,
+//! }
+//!
+//! fn main() -> ExitCode {
+//!     let args: Args = match spaa::cli::parse() {
+//!         Ok(args) => args,
+//!         Err(code) => return code,
+//!     };
+//!     args.log.init();
+//!     match spaa_parse::SpaaFile::open(&args.input) {
+//!         Ok(_) => ExitCode::SUCCESS,
+//!         Err(e) => CliError::from_error(&e).report(args.errors.error_format),
+//!     }
+//! }
+//! ```
+
+use crate::config::ConfigError;
+use crate::convert::{ConversionError, ParseSourceFormatError};
+use crate::detectors::RuleError;
+use crate::heapdiff::HeapDiffError;
+use crate::merge::MergeError;
+use crate::store::StoreError;
+pub use spaa_parse::ErrorKind;
+use spaa_parse::{ParseError, WriteError};
+use std::error::Error;
+use std::fmt;
+use std::io::IsTerminal;
+use std::process::ExitCode;
+use std::str::FromStr;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// How errors are written to stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorFormat {
+    /// `Error: message`.
+    #[default]
+    Text,
+    /// One JSON object per error.
+    Json,
+}
+
+impl fmt::Display for ErrorFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorFormat::Text => "text",
+            ErrorFormat::Json => "json",
+        })
+    }
+}
+
+/// Error returned when parsing an unknown [`ErrorFormat`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseErrorFormatError(String);
+
+impl fmt::Display for ParseErrorFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown error format '{}' (expected text or json)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseErrorFormatError {}
+
+impl FromStr for ErrorFormat {
+    type Err = ParseErrorFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(ParseErrorFormatError(s.to_string())),
+        }
+    }
+}
+
+/// The `--error-format` option, for flattening into a binary's arguments.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct ErrorArgs {
+    /// How to report errors: text or json
+    #[arg(long, global = true, default_value_t = ErrorFormat::Text)]
+    pub error_format: ErrorFormat,
+}
+
+/// How log events are written to stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Plain messages, prefixed with the level unless it is `INFO`.
+    #[default]
+    Text,
+    /// One JSON object per event, with its fields and spans.
+    Json,
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        })
+    }
+}
+
+/// Error returned when parsing an unknown [`LogFormat`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseLogFormatError(String);
+
+impl fmt::Display for ParseLogFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown log format '{}' (expected text or json)", self.0)
+    }
+}
+
+impl std::error::Error for ParseLogFormatError {}
+
+impl FromStr for LogFormat {
+    type Err = ParseLogFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(ParseLogFormatError(s.to_string())),
+        }
+    }
+}
+
+/// The `-v`, `-q` and `--log-format` options, for flattening into a
+/// binary's arguments.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct LogArgs {
+    /// Log more; repeat for more detail
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Log less; repeat to silence warnings too
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub quiet: u8,
+
+    /// How to write log messages: text or json
+    #[arg(long, global = true, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+}
+
+impl LogArgs {
+    /// Most detailed level to log: `INFO` by default, raised by each `-v`
+    /// and lowered by each `-q`.
+    pub fn level(&self) -> LevelFilter {
+        match i16::from(self.verbose) - i16::from(self.quiet) {
+            i16::MIN..=-3 => LevelFilter::OFF,
+            -2 => LevelFilter::ERROR,
+            -1 => LevelFilter::WARN,
+            0 => LevelFilter::INFO,
+            1 => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
+        }
+    }
+
+    /// Install a global subscriber writing to stderr. Does nothing if one
+    /// is already installed.
+    pub fn init(&self) {
+        let builder = tracing_subscriber::fmt()
+            .with_max_level(self.level())
+            .with_ansi(std::io::stderr().is_terminal())
+            .with_writer(std::io::stderr);
+        let _ = match self.log_format {
+            LogFormat::Text => builder.event_format(TextFormat).try_init(),
+            LogFormat::Json => builder.json().try_init(),
+        };
+    }
+}
+
+/// Bare messages for `INFO`, so default output reads like plain progress
+/// lines; other levels are prefixed, e.g. `warning: ...`.
+struct TextFormat;
+
+impl<S, N> FormatEvent<S, N> for TextFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        match *event.metadata().level() {
+            Level::INFO => {}
+            Level::WARN => write!(writer, "warning: ")?,
+            Level::ERROR => write!(writer, "error: ")?,
+            Level::DEBUG => write!(writer, "debug: ")?,
+            Level::TRACE => write!(writer, "trace: ")?,
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// An error classified for reporting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CliError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl CliError {
+    /// An error of `kind` with `message`.
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Classify `error` by the first error in its source chain with a known
+    /// kind; anything unrecognized is a [`ErrorKind::Failure`].
+    pub fn from_error(error: &(dyn Error + 'static)) -> Self {
+        Self::new(classify(error), error.to_string())
+    }
+
+    /// Like [`from_error`](Self::from_error), with `context` (usually a
+    /// path) prefixed to the message.
+    pub fn context<E: Error + 'static>(error: E, context: impl fmt::Display) -> Self {
+        Self::new(classify(&error), format!("{}: {}", context, error))
+    }
+
+    /// Write the error to stderr in `format` and return the exit code.
+    pub fn report(&self, format: ErrorFormat) -> ExitCode {
+        match format {
+            ErrorFormat::Text => eprintln!("Error: {}", self.message),
+            ErrorFormat::Json => eprintln!("{}", self.to_json()),
+        }
+        ExitCode::from(self.kind.code())
+    }
+
+    /// The JSON form written by [`report`](Self::report).
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "error": {
+                "kind": self.kind.to_string(),
+                "code": self.kind.code(),
+                "message": self.message,
+            }
+        })
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for CliError {}
+
+impl From<Box<dyn Error>> for CliError {
+    fn from(error: Box<dyn Error>) -> Self {
+        Self::from_error(error.as_ref())
+    }
+}
+
+/// Parse the process arguments into `P`.
+///
+/// Help and version requests print and exit as usual. Other argument errors
+/// are reported as [`ErrorKind::Usage`], or [`ErrorKind::UnsupportedFormat`]
+/// for an unknown `--format`, honouring `--error-format` even though parsing
+/// failed.
+pub fn parse<P: clap::Parser>() -> Result<P, ExitCode> {
+    P::try_parse().map_err(|e| {
+        use clap::error::ErrorKind as ClapKind;
+        if matches!(
+            e.kind(),
+            ClapKind::DisplayHelp
+                | ClapKind::DisplayVersion
+                | ClapKind::DisplayHelpOnMissingArgumentOrSubcommand
+        ) {
+            e.exit();
+        }
+        let kind = match e.source().map(classify) {
+            Some(ErrorKind::UnsupportedFormat) => ErrorKind::UnsupportedFormat,
+            _ => ErrorKind::Usage,
+        };
+        match requested_format(std::env::args()) {
+            ErrorFormat::Text => {
+                let _ = e.print();
+                ExitCode::from(kind.code())
+            }
+            ErrorFormat::Json => {
+                let message = e.to_string();
+                let message = message.trim().trim_start_matches("error: ");
+                CliError::new(kind, message.lines().next().unwrap_or_default())
+                    .report(ErrorFormat::Json)
+            }
+        }
+    })
+}
+
+/// `--error-format` from raw arguments, for errors raised before clap has
+/// produced a value.
+fn requested_format(args: impl IntoIterator<Item = String>) -> ErrorFormat {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix("--error-format") {
+            Some("") => args.next(),
+            Some(rest) => rest.strip_prefix('=').map(str::to_string),
+            None => continue,
+        };
+        return value.and_then(|v| v.parse().ok()).unwrap_or_default();
+    }
+    ErrorFormat::Text
+}
+
+fn classify(error: &(dyn Error + 'static)) -> ErrorKind {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(kind) = kind_of(error) {
+            return kind;
+        }
+        current = error.source();
+    }
+    ErrorKind::Failure
+}
+
+/// Kind of `error` itself, or `None` to look at its source.
+fn kind_of(error: &(dyn Error + 'static)) -> Option<ErrorKind> {
+    if let Some(e) = error.downcast_ref::<CliError>() {
+        return Some(e.kind);
+    }
+    if error.is::<std::io::Error>() {
+        return Some(ErrorKind::Io);
+    }
+    if error.is::<serde_json::Error>() || error.is::<postcard::Error>() {
+        return Some(ErrorKind::Parse);
+    }
+    if error.is::<ParseSourceFormatError>() {
+        return Some(ErrorKind::UnsupportedFormat);
+    }
+    if error.is::<ConversionError>() {
+        return Some(ErrorKind::Parse);
+    }
+    if let Some(e) = error.downcast_ref::<ParseError>() {
+        return Some(e.kind());
+    }
+    if error.is::<WriteError>() {
+        return None;
+    }
+    if let Some(e) = error.downcast_ref::<crate::perf::ConvertError>() {
+        use crate::perf::ConvertError;
+        return match e {
+            ConvertError::Parse { .. } | ConvertError::NoSamples => Some(ErrorKind::Parse),
+            ConvertError::TooManyFrames => Some(ErrorKind::Failure),
+            _ => None,
+        };
+    }
+    if let Some(e) = error.downcast_ref::<crate::dtrace::ConvertError>() {
+        use crate::dtrace::ConvertError;
+        return match e {
+            ConvertError::Parse { .. } | ConvertError::NoStacks => Some(ErrorKind::Parse),
+            ConvertError::UnsupportedFormat => Some(ErrorKind::UnsupportedFormat),
+            _ => None,
+        };
+    }
+    if let Some(e) = error.downcast_ref::<crate::chrome::ConvertError>() {
+        use crate::chrome::ConvertError;
+        return match e {
+            ConvertError::Io(_) | ConvertError::Json(_) | ConvertError::Write(_) => None,
+            _ => Some(ErrorKind::Parse),
+        };
+    }
+    if let Some(e) = error.downcast_ref::<crate::turbopack::ConvertError>() {
+        use crate::turbopack::ConvertError;
+        return match e {
+            ConvertError::NoSpans => Some(ErrorKind::Parse),
+            _ => None,
+        };
+    }
+    if let Some(e) = error.downcast_ref::<crate::convert::ConvertError>() {
+        use crate::convert::ConvertError;
+        return match e {
+            ConvertError::Invalid(_) | ConvertError::Empty(_) => Some(ErrorKind::Parse),
+            ConvertError::Unsupported(_) => Some(ErrorKind::UnsupportedFormat),
+            ConvertError::Io(_) | ConvertError::Write(_) => None,
+        };
+    }
+    if let Some(e) = error.downcast_ref::<HeapDiffError>() {
+        return match e {
+            HeapDiffError::InvalidSnapshot(_) => Some(ErrorKind::Parse),
+            HeapDiffError::NoAllocationEvent => Some(ErrorKind::Validation),
+            HeapDiffError::Cancelled(_) | HeapDiffError::BudgetExceeded(_) => {
+                Some(ErrorKind::Failure)
+            }
+            _ => None,
+        };
+    }
+    if let Some(e) = error.downcast_ref::<MergeError>() {
+        return match e {
+            MergeError::NoInputs | MergeError::InvalidScale { .. } => Some(ErrorKind::Usage),
+            MergeError::ConflictingPrimaryMetric { .. } => Some(ErrorKind::Validation),
+            MergeError::Cancelled(_) => Some(ErrorKind::Failure),
+            _ => None,
+        };
+    }
+    if let Some(e) = error.downcast_ref::<RuleError>() {
+        return match e {
+            RuleError::Pattern { .. } | RuleError::Invalid { .. } => Some(ErrorKind::Validation),
+            RuleError::Toml(_) => Some(ErrorKind::Parse),
+            _ => None,
+        };
+    }
+    if let Some(e) = error.downcast_ref::<ConfigError>() {
+        return match e {
+            ConfigError::Toml(_) => Some(ErrorKind::Parse),
+            ConfigError::Pattern(_) => Some(ErrorKind::Validation),
+            ConfigError::File { source, .. } => Some(classify(source.as_ref())),
+            ConfigError::Io(_) => None,
+        };
+    }
+    if let Some(e) = error.downcast_ref::<crate::bundle::BundleError>() {
+        use crate::bundle::BundleError;
+        return match e {
+            BundleError::TooLargeForZip(_) => Some(ErrorKind::Failure),
+            _ => None,
+        };
+    }
+    if let Some(e) = error.downcast_ref::<StoreError>() {
+        return match e {
+            StoreError::NotFound(_) | StoreError::Ambiguous(_) => Some(ErrorKind::Failure),
+            StoreError::Convert(_) => Some(ErrorKind::Parse),
+            _ => None,
+        };
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_error_is_classified_through_source_chain() {
+        let error = StoreError::Io(std::io::Error::from(std::io::ErrorKind::NotFound));
+
+        assert_eq!(CliError::from_error(&error).kind, ErrorKind::Io);
+    }
+
+    #[test]
+    fn reference_errors_are_validation_errors() {
+        let error = ParseError::InvalidStackReference {
+            record: "sample",
+            stack_id: "0x1".to_string(),
+            location: Default::default(),
+        };
+
+        assert_eq!(CliError::from_error(&error).kind.code(), 5);
+    }
+
+    #[test]
+    fn unknown_errors_are_failures() {
+        let error: Box<dyn Error> = "something went wrong".into();
+
+        assert_eq!(CliError::from(error).kind, ErrorKind::Failure);
+    }
+
+    #[test]
+    fn json_form_carries_kind_and_code() {
+        let json = CliError::new(ErrorKind::UnsupportedFormat, "unknown format").to_json();
+
+        assert_eq!(json["error"]["kind"], "unsupported_format");
+        assert_eq!(json["error"]["code"], 6);
+    }
+
+    #[test]
+    fn verbosity_flags_adjust_log_level() {
+        let log = |verbose, quiet| LogArgs {
+            verbose,
+            quiet,
+            ..Default::default()
+        };
+
+        assert_eq!(log(0, 0).level(), LevelFilter::INFO);
+        assert_eq!(log(2, 0).level(), LevelFilter::TRACE);
+        assert_eq!(log(0, 1).level(), LevelFilter::WARN);
+        assert_eq!(log(0, 5).level(), LevelFilter::OFF);
+    }
+
+    #[test]
+    fn error_format_is_read_from_raw_arguments() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            requested_format(args(&["spaa", "--error-format", "json", "bogus"])),
+            ErrorFormat::Json
+        );
+        assert_eq!(
+            requested_format(args(&["spaa", "--error-format=json"])),
+            ErrorFormat::Json
+        );
+        assert_eq!(
+            requested_format(args(&["spaa", "stats"])),
+            ErrorFormat::Text
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! `spaa.toml` configuration for converter defaults.
+//!
+//! Teams that convert with the same flags every time can put them in a
+//! `spaa.toml` at the project root, or in `~/.config/spaa/spaa.toml` for
+//! every project. Command-line flags override the file.
+//!
+//! ```toml
+//! [convert]
+//! event = "syscall::read:entry"   # DTrace event name
+//! frequency = 0                   # probe-based, not sampled
+//! max_stack_depth = 256
+//! normalize_symbols = true
+//!
+//! [[convert.rewrite]]
+//! pattern = '^acme_internal::'
+//! replace = 'acme::'
+//!
+//! [convert.redact]
+//! functions = ['^acme::license::']
+//! dsos = ['^/home/']
+//! command = true
+//!
+//! [plugins]
+//! acme = "/opt/acme/bin/acme-to-spaa"
+//!
+//! [[packages.rule]]               # see spaa::packages
+//! name = "acme-core"
+//! functions = ['^acme_core::']
+//! ```
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::config::Config;
+//! use spaa::convert::{SourceFormat, convert_with};
+//!
+//! let (config, _path) = Config::resolve(None).unwrap();
+//! let data = std::fs::read("perf.txt").unwrap();
+//! let spaa = convert_with(SourceFormat::Perf, &data, &config.convert).unwrap();
+//! ```
+
+use crate::convert::ConvertOptions;
+use crate::packages::{PackageMap, PackageMapper};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Name of the configuration file.
+pub const CONFIG_FILE: &str = "spaa.toml";
+
+/// Errors that can occur while loading a configuration file.
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("{0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("invalid pattern: {0}")]
+    Pattern(#[from] regex::Error),
+
+    #[error("{}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        #[source]
+        source: Box<ConfigError>,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, ConfigError>;
+
+/// Contents of a `spaa.toml`. Every section is optional.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Defaults for `spaa convert` and the `*_to_spaa` converters.
+    pub convert: ConvertOptions,
+    /// Converter plugins by format name, as paths to their executables;
+    /// see [`crate::plugin`].
+    pub plugins: BTreeMap<String, PathBuf>,
+    /// Rules attributing frames to packages; see [`crate::packages`].
+    pub packages: PackageMap,
+}
+
+impl Config {
+    /// Parse a configuration from TOML text, checking its patterns.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let config: Config = toml::from_str(text)?;
+        config.convert.validate()?;
+        PackageMapper::new(&config.packages)?;
+        Ok(config)
+    }
+
+    /// Load the configuration file at `path`. Errors name the file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        fs::read_to_string(path)
+            .map_err(ConfigError::from)
+            .and_then(|text| Self::from_toml(&text))
+            .map_err(|e| ConfigError::File {
+                path: path.to_path_buf(),
+                source: Box::new(e),
+            })
+    }
+
+    /// The configuration file that applies in `dir`: the nearest
+    /// `spaa.toml` in `dir` or its ancestors, else the user's
+    /// `$XDG_CONFIG_HOME/spaa/spaa.toml` (`~/.config/spaa/spaa.toml`) if it
+    /// exists. Files are not merged; the first one found wins.
+    pub fn discover(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|d| d.join(CONFIG_FILE))
+            .find(|p| p.is_file())
+            .or_else(|| user_config_path().filter(|p| p.is_file()))
+    }
+
+    /// Load `explicit` if given, otherwise the file [`discover`](Self::discover)
+    /// finds from the current directory, otherwise the defaults. Returns the
+    /// path that was loaded, if any.
+    pub fn resolve(explicit: Option<&Path>) -> Result<(Self, Option<PathBuf>)> {
+        let path = match explicit {
+            Some(path) => Some(path.to_path_buf()),
+            None => Self::discover(&std::env::current_dir()?),
+        };
+        match path {
+            Some(path) => {
+                tracing::debug!(path = %path.display(), "loading configuration");
+                Ok((Self::load(&path)?, Some(path)))
+            }
+            None => Ok((Self::default(), None)),
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/spaa/spaa.toml`, falling back to `~/.config`.
+fn user_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(base.join("spaa").join(CONFIG_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_convert_section() {
+        let config = Config::from_toml(
+            r#"
+            [convert]
+            event = "syscall::read:entry"
+            frequency = 0
+
+            [[convert.rewrite]]
+            pattern = "^a::"
+            replace = "b::"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.convert.event.as_deref(), Some("syscall::read:entry"));
+        assert_eq!(config.convert.rewrite[0].replace, "b::");
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let err = Config::from_toml("[convert]\nevnet = \"cycles\"\n").unwrap_err();
+
+        assert!(matches!(err, ConfigError::Toml(_)));
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        let err = Config::from_toml("[convert.redact]\nfunctions = [\"(\"]\n").unwrap_err();
+
+        assert!(matches!(err, ConfigError::Pattern(_)));
+    }
+
+    #[test]
+    fn packages_section_is_parsed() {
+        let config = Config::from_toml(
+            "[packages]\nheuristics = false\n[[packages.rule]]\nname = \"acme\"\nfunctions = [\"^acme::\"]\n",
+        )
+        .unwrap();
+
+        assert!(!config.packages.heuristics);
+        assert_eq!(config.packages.rules[0].name, "acme");
+    }
+
+    #[test]
+    fn nearest_project_file_is_discovered() {
+        let root = std::env::temp_dir().join(format!("spaa-config-{}", std::process::id()));
+        let nested = root.join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join(CONFIG_FILE), "").unwrap();
+
+        assert_eq!(Config::discover(&nested), Some(root.join(CONFIG_FILE)));
+    }
+}
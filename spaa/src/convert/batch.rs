@@ -0,0 +1,261 @@
+//! Convert many files at once on a pool of worker threads.
+
+use super::{ConvertOptions, SourceFormat, convert_with};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// What happened to one input of a batch.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", content = "error", rename_all = "lowercase")]
+pub enum FileStatus {
+    Converted,
+    /// The output already existed and was newer than the input.
+    Skipped,
+    /// The input could not be read, converted or written.
+    Failed(String),
+}
+
+/// Outcome for one input.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileResult {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    #[serde(flatten)]
+    pub status: FileStatus,
+}
+
+/// Per-file outcomes of [`BatchConverter::run`], in input order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BatchSummary {
+    pub files: Vec<FileResult>,
+}
+
+impl BatchSummary {
+    /// Number of inputs converted.
+    pub fn converted(&self) -> usize {
+        self.count(|s| matches!(s, FileStatus::Converted))
+    }
+
+    /// Number of inputs skipped as up to date.
+    pub fn skipped(&self) -> usize {
+        self.count(|s| matches!(s, FileStatus::Skipped))
+    }
+
+    /// Number of inputs that failed.
+    pub fn failed(&self) -> usize {
+        self.count(|s| matches!(s, FileStatus::Failed(_)))
+    }
+
+    fn count(&self, f: impl Fn(&FileStatus) -> bool) -> usize {
+        self.files.iter().filter(|r| f(&r.status)).count()
+    }
+}
+
+/// Converts a list of inputs into `out_dir`, one `.spaa` file per input.
+///
+/// Each output is named after its input with the extension replaced, so
+/// `profiles/a.cpuprofile` becomes `out_dir/a.spaa`. Inputs whose output is
+/// already newer are skipped unless `force` is set. A failed input does not
+/// stop the rest of the batch.
+#[derive(Debug, Clone)]
+pub struct BatchConverter {
+    pub out_dir: PathBuf,
+    /// Format of every input; when unset it is inferred per file with
+    /// [`SourceFormat::infer`].
+    pub format: Option<SourceFormat>,
+    pub options: ConvertOptions,
+    /// Number of worker threads.
+    pub jobs: usize,
+    /// Convert inputs even when their output is up to date.
+    pub force: bool,
+}
+
+impl BatchConverter {
+    /// A converter writing to `out_dir` with one worker per available CPU.
+    pub fn new<P: Into<PathBuf>>(out_dir: P) -> Self {
+        Self {
+            out_dir: out_dir.into(),
+            format: None,
+            options: ConvertOptions::default(),
+            jobs: thread::available_parallelism().map_or(1, |n| n.get()),
+            force: false,
+        }
+    }
+
+    /// Where the output for `input` is written.
+    pub fn output_path(&self, input: &Path) -> PathBuf {
+        let name = input.file_name().map_or_else(PathBuf::new, PathBuf::from);
+        self.out_dir.join(name.with_extension("spaa"))
+    }
+
+    /// Convert every input, creating `out_dir` if needed.
+    ///
+    /// Only failing to create `out_dir` is an error; per-file failures are
+    /// reported in the summary. Inputs that map to the same output as an
+    /// earlier input fail rather than overwrite it.
+    pub fn run(&self, inputs: &[PathBuf]) -> std::io::Result<BatchSummary> {
+        fs::create_dir_all(&self.out_dir)?;
+
+        let mut claimed: HashMap<PathBuf, &Path> = HashMap::new();
+        let slots: Vec<Mutex<Option<FileResult>>> = inputs
+            .iter()
+            .map(|input| {
+                let output = self.output_path(input);
+                let collision = claimed.get(&output).map(|first| FileResult {
+                    input: input.clone(),
+                    output: output.clone(),
+                    status: FileStatus::Failed(format!("output collides with {}", first.display())),
+                });
+                claimed.entry(output).or_insert(input);
+                Mutex::new(collision)
+            })
+            .collect();
+
+        let next = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..self.jobs.clamp(1, inputs.len().max(1)) {
+                scope.spawn(|| {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(input) = inputs.get(i) else { break };
+                        let mut slot = slots[i].lock().expect("result slot poisoned");
+                        if slot.is_none() {
+                            let output = self.output_path(input);
+                            let status = self.convert_one(input, &output);
+                            tracing::debug!(input = %input.display(), ?status, "batch file done");
+                            *slot = Some(FileResult {
+                                input: input.clone(),
+                                output,
+                                status,
+                            });
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(BatchSummary {
+            files: slots
+                .into_iter()
+                .map(|slot| {
+                    slot.into_inner()
+                        .expect("result slot poisoned")
+                        .expect("every input is processed")
+                })
+                .collect(),
+        })
+    }
+
+    fn convert_one(&self, input: &Path, output: &Path) -> FileStatus {
+        if !self.force && is_up_to_date(input, output) {
+            return FileStatus::Skipped;
+        }
+        let result = fs::read(input)
+            .map_err(|e| e.to_string())
+            .and_then(|data| {
+                let format = match self.format {
+                    Some(format) => format,
+                    None => SourceFormat::infer(input, &data).map_err(|e| e.to_string())?,
+                };
+                convert_with(format, &data, &self.options).map_err(|e| e.to_string())
+            })
+            .and_then(|spaa| fs::write(output, spaa).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => FileStatus::Converted,
+            Err(e) => FileStatus::Failed(e),
+        }
+    }
+}
+
+/// Whether `output` exists and was modified no earlier than `input`.
+fn is_up_to_date(input: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(input), modified(output)) {
+        (Some(input), Some(output)) => output >= input,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERF_SCRIPT: &str =
+        "app  1234 [000] 12345.678901:     100000 cycles:\n\t401234 main+0x54 (/usr/bin/app)\n\n";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("spaa-batch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn converter(dir: &Path) -> BatchConverter {
+        BatchConverter {
+            format: Some(SourceFormat::Perf),
+            jobs: 2,
+            ..BatchConverter::new(dir.join("out"))
+        }
+    }
+
+    #[test]
+    fn converts_every_input() {
+        let dir = temp_dir("all");
+        let inputs: Vec<PathBuf> = ["a.txt", "b.txt", "c.txt"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        for input in &inputs {
+            fs::write(input, PERF_SCRIPT).unwrap();
+        }
+
+        let summary = converter(&dir).run(&inputs).unwrap();
+        assert_eq!(summary.converted(), 3);
+        assert!(dir.join("out/b.spaa").exists());
+    }
+
+    #[test]
+    fn up_to_date_output_is_skipped() {
+        let dir = temp_dir("skip");
+        let input = dir.join("a.txt");
+        fs::write(&input, PERF_SCRIPT).unwrap();
+        let converter = converter(&dir);
+        converter.run(std::slice::from_ref(&input)).unwrap();
+
+        let summary = converter.run(&[input]).unwrap();
+        assert_eq!(summary.skipped(), 1);
+    }
+
+    #[test]
+    fn failed_input_does_not_stop_batch() {
+        let dir = temp_dir("fail");
+        let good = dir.join("good.txt");
+        fs::write(&good, PERF_SCRIPT).unwrap();
+
+        let summary = converter(&dir)
+            .run(&[dir.join("missing.txt"), good])
+            .unwrap();
+        assert!(matches!(summary.files[0].status, FileStatus::Failed(_)));
+        assert_eq!(summary.files[1].status, FileStatus::Converted);
+    }
+
+    #[test]
+    fn colliding_outputs_fail() {
+        let dir = temp_dir("collide");
+        fs::create_dir_all(dir.join("x")).unwrap();
+        fs::create_dir_all(dir.join("y")).unwrap();
+        let inputs = vec![dir.join("x/a.txt"), dir.join("y/a.txt")];
+        for input in &inputs {
+            fs::write(input, PERF_SCRIPT).unwrap();
+        }
+
+        let summary = converter(&dir).run(&inputs).unwrap();
+        assert_eq!(summary.converted(), 1);
+        assert_eq!(summary.failed(), 1);
+    }
+}
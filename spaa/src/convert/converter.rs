@@ -0,0 +1,203 @@
+//! The [`Converter`] trait shared by every profiler format, and the
+//! [`ConvertError`] it reports.
+//!
+//! Each converter module keeps its own error type for its inherent API;
+//! those convert into [`ConvertError`], so code written against the trait
+//! handles one error type whatever the format.
+
+use super::Fidelity;
+use spaa_parse::{SpaaFile, WriteError};
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+use thiserror::Error;
+
+/// Bytes of input [`Converter::detect`] implementations look at.
+pub const DETECT_PREFIX: usize = 64 * 1024;
+
+/// How sure a [`Converter::detect`] match is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    /// The input could be this format, e.g. it is compressed or generic JSON.
+    Low,
+    /// The input has features of this format but lacks a distinctive one.
+    Medium,
+    /// The input has a marker only this format produces.
+    High,
+}
+
+/// Errors from any [`Converter`].
+#[derive(Error, Debug)]
+pub enum ConvertError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    /// The input is malformed.
+    #[error("invalid input: {0}")]
+    Invalid(String),
+
+    /// The input is well formed but holds nothing to convert.
+    #[error("{0}")]
+    Empty(String),
+
+    /// The input uses a variant of the format this converter cannot read.
+    #[error("unsupported input: {0}")]
+    Unsupported(String),
+
+    #[error("SPAA write error: {0}")]
+    Write(#[from] WriteError),
+}
+
+pub type Result<T> = std::result::Result<T, ConvertError>;
+
+/// A profiler output format that converts to SPAA.
+///
+/// The trait is object safe, so converters for different formats can be
+/// kept together as `Box<dyn Converter>`.
+pub trait Converter {
+    /// Short format name, e.g. `perf`.
+    fn name(&self) -> &str;
+
+    /// How likely it is that an input starting with `data` is in this
+    /// format, or `None` if it is not. Callers pass at most
+    /// [`DETECT_PREFIX`] bytes.
+    fn detect(&self, data: &[u8]) -> Option<Confidence>;
+
+    /// Read an input in this format.
+    fn parse(&mut self, reader: &mut dyn Read) -> Result<()>;
+
+    /// Build the SPAA profile of the parsed input.
+    fn to_spaa_file(&self) -> Result<SpaaFile>;
+
+    /// What was read and lost while parsing, for converters that track it.
+    fn fidelity(&self) -> Option<Fidelity> {
+        None
+    }
+
+    /// Write the SPAA profile of the parsed input as NDJSON.
+    fn write_spaa(&self, writer: &mut dyn Write) -> Result<()> {
+        self.to_spaa_file()?.write(writer)?;
+        Ok(())
+    }
+}
+
+/// The first [`DETECT_PREFIX`] bytes of `data` as text, for sniffing.
+pub(crate) fn text_prefix(data: &[u8]) -> Cow<'_, str> {
+    String::from_utf8_lossy(&data[..data.len().min(DETECT_PREFIX)])
+}
+
+impl From<crate::perf::ConvertError> for ConvertError {
+    fn from(e: crate::perf::ConvertError) -> Self {
+        use crate::perf::ConvertError as E;
+        match e {
+            E::Io(e) => Self::Io(e),
+            E::Write(e) => Self::Write(e),
+            E::NoSamples => Self::Empty(e.to_string()),
+            E::Json(_) | E::Parse { .. } | E::TooManyFrames => Self::Invalid(e.to_string()),
+        }
+    }
+}
+
+impl From<crate::dtrace::ConvertError> for ConvertError {
+    fn from(e: crate::dtrace::ConvertError) -> Self {
+        use crate::dtrace::ConvertError as E;
+        match e {
+            E::Io(e) => Self::Io(e),
+            E::Write(e) => Self::Write(e),
+            E::NoStacks => Self::Empty(e.to_string()),
+            E::UnsupportedFormat => Self::Unsupported(e.to_string()),
+            E::Json(_) | E::Parse { .. } => Self::Invalid(e.to_string()),
+        }
+    }
+}
+
+impl From<crate::chrome::ConvertError> for ConvertError {
+    fn from(e: crate::chrome::ConvertError) -> Self {
+        use crate::chrome::ConvertError as E;
+        match e {
+            E::Io(e) => Self::Io(e),
+            E::Write(e) => Self::Write(e),
+            E::NoSamples | E::NoCpuProfileInTrace | E::NoAllocationTraceData => {
+                Self::Empty(e.to_string())
+            }
+            E::Json(_) | E::InvalidProfile(_) => Self::Invalid(e.to_string()),
+        }
+    }
+}
+
+impl From<crate::turbopack::ConvertError> for ConvertError {
+    fn from(e: crate::turbopack::ConvertError) -> Self {
+        use crate::turbopack::ConvertError as E;
+        match e {
+            E::Io(e) => Self::Io(e),
+            E::Write(e) => Self::Write(e),
+            E::NoSpans => Self::Empty(e.to_string()),
+            E::Postcard(_) => Self::Invalid(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chrome::{CpuProfileConverter, HeapSnapshotConverter};
+    use crate::dtrace::{DtraceConverter, InputFormat};
+    use crate::perf::PerfConverter;
+    use crate::turbopack::TurbopackConverter;
+
+    fn converters() -> Vec<Box<dyn Converter>> {
+        vec![
+            Box::new(PerfConverter::new()),
+            Box::new(DtraceConverter::new(InputFormat::AggregatedStack)),
+            Box::new(CpuProfileConverter::new()),
+            Box::new(HeapSnapshotConverter::new()),
+            Box::new(TurbopackConverter::new()),
+        ]
+    }
+
+    fn best_match(data: &[u8]) -> Option<String> {
+        converters()
+            .iter()
+            .filter_map(|c| Some((c.detect(data)?, c.name().to_string())))
+            .max()
+            .map(|(_, name)| name)
+    }
+
+    #[test]
+    fn detect_recognizes_each_format() {
+        let perf = "myapp 1234 1.000: 1 cycles:\n\t 401234 main+0x54 (/usr/bin/myapp)\n";
+        let dtrace = "\n              libc.so.1`malloc+0x8\n              myapp`main+0x10\n               42\n";
+        let cpuprofile = r#"{"nodes":[{"id":1,"callFrame":{"functionName":"(root)"}}]}"#;
+        let heap = r#"{"snapshot":{"meta":{}},"trace_function_infos":[]}"#;
+
+        assert_eq!(best_match(perf.as_bytes()).as_deref(), Some("perf"));
+        assert_eq!(best_match(dtrace.as_bytes()).as_deref(), Some("dtrace"));
+        assert_eq!(
+            best_match(cpuprofile.as_bytes()).as_deref(),
+            Some("cpuprofile")
+        );
+        assert_eq!(best_match(heap.as_bytes()).as_deref(), Some("heapsnapshot"));
+        assert_eq!(best_match(b"TRACEv0\x00").as_deref(), Some("turbopack"));
+        assert_eq!(best_match(b"hello world\n"), None);
+    }
+
+    #[test]
+    fn trait_objects_convert() {
+        let mut converter: Box<dyn Converter> = Box::new(PerfConverter::new());
+        let input = "myapp 1234 1.000: 1 cycles:\n\t 401234 main+0x54 (/usr/bin/myapp)\n\n";
+        converter.parse(&mut input.as_bytes()).unwrap();
+
+        let mut out = Vec::new();
+        converter.write_spaa(&mut out).unwrap();
+        let file = SpaaFile::parse_slice(&out).unwrap();
+        assert_eq!(file.stacks.len(), 1);
+    }
+
+    #[test]
+    fn module_errors_map_to_unified_kinds() {
+        let empty: ConvertError = crate::perf::ConvertError::NoSamples.into();
+        let unsupported: ConvertError = crate::dtrace::ConvertError::UnsupportedFormat.into();
+
+        assert!(matches!(empty, ConvertError::Empty(_)));
+        assert!(matches!(unsupported, ConvertError::Unsupported(_)));
+    }
+}
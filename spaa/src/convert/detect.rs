@@ -0,0 +1,264 @@
+//! Recognizing profiler output from its contents.
+//!
+//! [`detect_format`] asks every [`Converter`] whether it recognizes the
+//! input, and adds checks for formats spaa can identify but not convert, so
+//! that the error can name them. Compressed input is identified by what it
+//! decompresses to.
+
+use super::{Confidence, Converter, DETECT_PREFIX, SourceFormat, text_prefix};
+use crate::chrome::{CpuProfileConverter, HeapSnapshotConverter};
+use crate::dtrace::{DtraceConverter, InputFormat};
+use crate::perf::PerfConverter;
+use crate::turbopack::TurbopackConverter;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Lines of text [`detect_format`] checks for folded stacks.
+const FOLDED_LINES: usize = 100;
+
+/// A format [`detect_format`] recognizes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectedFormat {
+    /// `perf script` text output.
+    Perf,
+    /// DTrace aggregated stack output.
+    Dtrace,
+    /// Folded stacks (`a;b;c 42`), as written by `stackcollapse` scripts.
+    Folded,
+    /// Standalone V8 `.cpuprofile`.
+    CpuProfile,
+    /// Chrome Performance trace.
+    Trace,
+    /// Chrome heap snapshot or heap timeline.
+    HeapSnapshot,
+    /// Turbopack trace file.
+    Turbopack,
+    /// pprof protobuf profile, optionally gzipped.
+    Pprof,
+}
+
+impl DetectedFormat {
+    /// Every detected format, in the order ties are broken.
+    pub const ALL: [DetectedFormat; 8] = [
+        DetectedFormat::Perf,
+        DetectedFormat::Dtrace,
+        DetectedFormat::Folded,
+        DetectedFormat::CpuProfile,
+        DetectedFormat::Trace,
+        DetectedFormat::HeapSnapshot,
+        DetectedFormat::Turbopack,
+        DetectedFormat::Pprof,
+    ];
+
+    /// The [`SourceFormat`] that converts this format, or `None` if spaa
+    /// cannot convert it.
+    pub fn source_format(self) -> Option<SourceFormat> {
+        match self {
+            DetectedFormat::Perf => Some(SourceFormat::Perf),
+            DetectedFormat::Dtrace => Some(SourceFormat::Dtrace),
+            DetectedFormat::CpuProfile | DetectedFormat::Trace | DetectedFormat::HeapSnapshot => {
+                Some(SourceFormat::Chrome)
+            }
+            DetectedFormat::Turbopack => Some(SourceFormat::Turbopack),
+            DetectedFormat::Folded | DetectedFormat::Pprof => None,
+        }
+    }
+
+    fn detect(self, data: &[u8]) -> Option<Confidence> {
+        match self {
+            DetectedFormat::Perf => PerfConverter::new().detect(data),
+            DetectedFormat::Dtrace => {
+                DtraceConverter::new(InputFormat::AggregatedStack).detect(data)
+            }
+            DetectedFormat::Folded => detect_folded(data),
+            DetectedFormat::CpuProfile => CpuProfileConverter::new()
+                .detect(data)
+                .filter(|_| !text_prefix(data).contains("\"traceEvents\"")),
+            DetectedFormat::Trace => CpuProfileConverter::new()
+                .detect(data)
+                .filter(|_| text_prefix(data).contains("\"traceEvents\"")),
+            DetectedFormat::HeapSnapshot => HeapSnapshotConverter::new().detect(data),
+            DetectedFormat::Turbopack => TurbopackConverter::new().detect(data),
+            DetectedFormat::Pprof => detect_pprof(data),
+        }
+    }
+}
+
+impl fmt::Display for DetectedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DetectedFormat::Perf => "perf script output",
+            DetectedFormat::Dtrace => "DTrace output",
+            DetectedFormat::Folded => "folded stacks",
+            DetectedFormat::CpuProfile => "a V8 cpuprofile",
+            DetectedFormat::Trace => "a Chrome trace",
+            DetectedFormat::HeapSnapshot => "a Chrome heap snapshot",
+            DetectedFormat::Turbopack => "a Turbopack trace",
+            DetectedFormat::Pprof => "a pprof profile",
+        })
+    }
+}
+
+/// The result of [`detect_format`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Detection {
+    pub format: DetectedFormat,
+    pub confidence: Confidence,
+}
+
+/// Identify the format of profiler output from its first
+/// [`DETECT_PREFIX`] bytes, or `None` if no format matches.
+///
+/// The most confident match wins; ties go to the earliest format in
+/// [`DetectedFormat::ALL`]. zstd and gzip input is identified by its
+/// decompressed contents.
+pub fn detect_format(data: &[u8]) -> Option<Detection> {
+    let data = &data[..data.len().min(DETECT_PREFIX)];
+    if let Some(inner) = decompressed_prefix(data)
+        && let Some(detection) = best_match(&inner)
+    {
+        return Some(detection);
+    }
+    best_match(data)
+}
+
+/// Like [`detect_format`], reading the start of the file at `path`.
+pub fn detect_file_format(path: &Path) -> io::Result<Option<Detection>> {
+    let mut data = Vec::with_capacity(DETECT_PREFIX);
+    File::open(path)?
+        .take(DETECT_PREFIX as u64)
+        .read_to_end(&mut data)?;
+    Ok(detect_format(&data))
+}
+
+fn best_match(data: &[u8]) -> Option<Detection> {
+    DetectedFormat::ALL
+        .into_iter()
+        .filter_map(|format| {
+            Some(Detection {
+                format,
+                confidence: format.detect(data)?,
+            })
+        })
+        .reduce(|best, d| {
+            if d.confidence > best.confidence {
+                d
+            } else {
+                best
+            }
+        })
+}
+
+/// The start of zstd or gzip compressed `data`, decompressed as far as the
+/// prefix allows.
+fn decompressed_prefix(data: &[u8]) -> Option<Vec<u8>> {
+    let reader: Box<dyn Read + '_> = if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(zstd::Decoder::new(data).ok()?)
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        Box::new(flate2::read::GzDecoder::new(data))
+    } else {
+        return None;
+    };
+    let mut inner = Vec::new();
+    // A truncated stream ends in an error after yielding what it could.
+    let _ = reader.take(DETECT_PREFIX as u64).read_to_end(&mut inner);
+    (!inner.is_empty()).then_some(inner)
+}
+
+/// Folded stacks are unindented `frame;frame;... count` lines.
+fn detect_folded(data: &[u8]) -> Option<Confidence> {
+    let text = text_prefix(data);
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+    // The prefix may end mid-line.
+    if data.len() >= DETECT_PREFIX {
+        lines.next_back();
+    }
+    let mut nested = false;
+    let mut count = 0;
+    for line in lines.take(FOLDED_LINES) {
+        let (stack, weight) = line.rsplit_once(' ')?;
+        if line.starts_with(char::is_whitespace) || stack.is_empty() {
+            return None;
+        }
+        weight.parse::<u64>().ok()?;
+        nested |= stack.contains(';');
+        count += 1;
+    }
+    match (count, nested) {
+        (0, _) => None,
+        (_, true) => Some(Confidence::High),
+        (_, false) => Some(Confidence::Low),
+    }
+}
+
+/// A pprof `Profile` message starts with its first `sample_type`, a
+/// length-delimited field 1 holding a `ValueType` whose own first field is
+/// the varint `type`.
+fn detect_pprof(data: &[u8]) -> Option<Confidence> {
+    match data {
+        [0x0a, len, 0x08, ..] if *len < 0x80 => Some(Confidence::Medium),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn detected(data: &[u8]) -> Option<DetectedFormat> {
+        detect_format(data).map(|d| d.format)
+    }
+
+    #[test]
+    fn chrome_variants_are_told_apart() {
+        let cpuprofile = r#"{"nodes":[{"id":1,"callFrame":{"functionName":"(root)"}}]}"#;
+        let trace = r#"{"traceEvents":[{"name":"ProfileChunk","args":{"data":{}}}]}"#;
+
+        assert_eq!(
+            detected(cpuprofile.as_bytes()),
+            Some(DetectedFormat::CpuProfile)
+        );
+        assert_eq!(detected(trace.as_bytes()), Some(DetectedFormat::Trace));
+    }
+
+    #[test]
+    fn folded_stacks_are_detected_but_not_convertible() {
+        let folded = "main;parse;lex 12\nmain;render 3\n";
+        let detection = detect_format(folded.as_bytes()).unwrap();
+
+        assert_eq!(detection.format, DetectedFormat::Folded);
+        assert_eq!(detection.format.source_format(), None);
+    }
+
+    #[test]
+    fn compressed_input_is_detected_by_its_contents() {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(b"TRACEv0\x00\x01").unwrap();
+        let data = gz.finish().unwrap();
+
+        assert_eq!(
+            detect_format(&data),
+            Some(Detection {
+                format: DetectedFormat::Turbopack,
+                confidence: Confidence::High,
+            })
+        );
+    }
+
+    #[test]
+    fn infer_prefers_file_name_then_contents() {
+        let perf = b"myapp 1234 1.000: 1 cycles:\n\t 401234 main+0x54 (/usr/bin/myapp)\n";
+
+        assert_eq!(
+            SourceFormat::infer(Path::new("perf.txt"), perf).unwrap(),
+            SourceFormat::Perf
+        );
+        assert!(matches!(
+            SourceFormat::infer(Path::new("stacks.txt"), b"a;b 1\n"),
+            Err(crate::convert::ConvertError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,129 @@
+//! Conversion fidelity reports.
+//!
+//! A converter that tracks what it reads reports a [`Fidelity`]: how many
+//! input records and samples it saw, and what it dropped or could not
+//! parse. [`FidelityReport`] adds what can be read off the converted
+//! profile, such as the share of frames without a symbol, and is written
+//! next to the output by `spaa convert --report`.
+
+use serde::Serialize;
+use spaa_parse::SpaaFile;
+
+/// Unparsed lines kept as examples in a [`Fidelity`].
+pub const MAX_UNPARSED_EXAMPLES: usize = 5;
+
+/// Longest example line kept, in bytes.
+const MAX_EXAMPLE_LEN: usize = 200;
+
+/// What a converter read from its input and what it lost on the way.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Fidelity {
+    /// Input records read: lines for text formats, profile nodes for JSON
+    /// ones.
+    pub records_read: u64,
+    /// Samples (or, for aggregated input, stacks) found in the input.
+    pub samples_read: u64,
+    /// Samples that contribute nothing to the output, such as samples
+    /// without a stack or referring to an unknown node.
+    pub samples_dropped: u64,
+    /// Events the profiler itself reported as lost.
+    pub lost_events: u64,
+    /// Lines skipped because they could not be parsed.
+    pub unparsed_lines: u64,
+    /// The first [`MAX_UNPARSED_EXAMPLES`] unparsed lines.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unparsed_examples: Vec<UnparsedLine>,
+}
+
+/// A line a converter skipped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UnparsedLine {
+    /// Line number, 1-indexed.
+    pub line: usize,
+    /// The line, cut to a couple of hundred bytes.
+    pub text: String,
+}
+
+impl Fidelity {
+    /// Count line `line` with contents `text` as unparsed.
+    pub fn unparsed(&mut self, line: usize, text: &str) {
+        self.unparsed_lines += 1;
+        if self.unparsed_examples.len() < MAX_UNPARSED_EXAMPLES {
+            let mut end = text.len().min(MAX_EXAMPLE_LEN);
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.unparsed_examples.push(UnparsedLine {
+                line,
+                text: text[..end].to_string(),
+            });
+        }
+    }
+}
+
+/// The fidelity report for one conversion.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FidelityReport {
+    /// Name of the converter, e.g. `perf`.
+    pub format: String,
+    /// Size of the input in bytes.
+    pub input_bytes: u64,
+    /// What the converter tracked, if it tracks anything.
+    #[serde(flatten)]
+    pub fidelity: Option<Fidelity>,
+    /// Frames in the output.
+    pub frames: u64,
+    /// Frames in the output whose function name is not a resolved symbol.
+    pub unresolved_frames: u64,
+    /// `unresolved_frames / frames`, or 0 without frames.
+    pub unresolved_ratio: f64,
+}
+
+impl FidelityReport {
+    /// The report for converting `input_bytes` of `format` into `file`.
+    pub fn new(
+        format: impl Into<String>,
+        input_bytes: u64,
+        fidelity: Option<Fidelity>,
+        file: &SpaaFile,
+    ) -> Self {
+        let frames = file.frames.len() as u64;
+        let unresolved_frames = file.frames.values().filter(|f| !f.func_resolved).count() as u64;
+        Self {
+            format: format.into(),
+            input_bytes,
+            fidelity,
+            frames,
+            unresolved_frames,
+            unresolved_ratio: if frames == 0 {
+                0.0
+            } else {
+                unresolved_frames as f64 / frames as f64
+            },
+        }
+    }
+
+    /// Whether anything was dropped, left unparsed or reported lost.
+    pub fn is_lossy(&self) -> bool {
+        self.fidelity
+            .as_ref()
+            .is_some_and(|f| f.samples_dropped > 0 || f.lost_events > 0 || f.unparsed_lines > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_first_unparsed_lines_are_kept_as_examples() {
+        let mut fidelity = Fidelity::default();
+        for line in 1..=8 {
+            fidelity.unparsed(line, &"x".repeat(300));
+        }
+
+        assert_eq!(fidelity.unparsed_lines, 8);
+        assert_eq!(fidelity.unparsed_examples.len(), MAX_UNPARSED_EXAMPLES);
+        assert_eq!(fidelity.unparsed_examples[0].text.len(), MAX_EXAMPLE_LEN);
+    }
+}
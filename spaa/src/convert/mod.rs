@@ -0,0 +1,259 @@
+//! Conversion of profiler output to SPAA, dispatched by source format.
+//!
+//! [`convert`] runs the converter for a [`SourceFormat`] over an in-memory
+//! buffer. [`converter_version`] identifies the code that produced a
+//! conversion, so caches such as [`Store::convert`](crate::store::Store::convert)
+//! can tell when a stored result is stale. [`BatchConverter`] converts many
+//! files into a directory in parallel. [`ConvertOptions`] carries converter
+//! defaults and the rewrite and redaction passes, usually loaded from
+//! `spaa.toml` by [`crate::config`]. Each format implements the
+//! [`Converter`] trait, which reports errors as [`ConvertError`], and
+//! [`detect_format`] recognizes a format from its contents.
+//! [`WindowedWriter`] writes aggregated output window by window for inputs
+//! that never end. [`convert_with_report`] also returns a
+//! [`FidelityReport`] of what the conversion read and lost.
+
+mod batch;
+mod converter;
+mod detect;
+mod fidelity;
+mod options;
+mod stream;
+
+pub use batch::{BatchConverter, BatchSummary, FileResult, FileStatus};
+pub(crate) use converter::text_prefix;
+pub use converter::{Confidence, ConvertError, Converter, DETECT_PREFIX};
+pub use detect::{DetectedFormat, Detection, detect_file_format, detect_format};
+pub use fidelity::{Fidelity, FidelityReport, MAX_UNPARSED_EXAMPLES, UnparsedLine};
+pub use options::{ConvertOptions, REDACTED, Redaction, RewriteRule};
+pub use stream::{StreamFrame, StreamSample, WindowPolicy, WindowedWriter};
+
+use crate::chrome::{CpuProfileConverter, HeapSnapshotConverter, ProfileType, detect_profile_type};
+use crate::dtrace::{DtraceConverter, InputFormat};
+use crate::perf::PerfConverter;
+use crate::turbopack::TurbopackConverter;
+use std::fmt;
+use std::io::Cursor;
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Profiler output formats [`convert`] accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceFormat {
+    /// `perf script` text output.
+    Perf,
+    /// DTrace aggregated stack output.
+    Dtrace,
+    /// Chrome trace, V8 cpuprofile, heap snapshot or heap timeline;
+    /// the kind is detected from the contents.
+    Chrome,
+    /// Turbopack trace file.
+    Turbopack,
+}
+
+impl SourceFormat {
+    /// Guess the format from a file name.
+    ///
+    /// `.cpuprofile`, `.heapsnapshot`, `.heaptimeline` and `.json` files are
+    /// Chrome, and names starting with `trace-turbopack` are Turbopack. perf
+    /// and DTrace text output have no conventional name, so they are never
+    /// inferred.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        if name.starts_with("trace-turbopack") {
+            return Some(SourceFormat::Turbopack);
+        }
+        match path.extension()?.to_str()? {
+            "cpuprofile" | "heapsnapshot" | "heaptimeline" | "json" => Some(SourceFormat::Chrome),
+            _ => None,
+        }
+    }
+
+    /// The format of `data`, read from `path`: inferred from the file name
+    /// when possible, otherwise detected from the contents with
+    /// [`detect_format`].
+    pub fn infer(path: &Path, data: &[u8]) -> Result<Self, ConvertError> {
+        if let Some(format) = Self::from_path(path) {
+            return Ok(format);
+        }
+        let detection = detect_format(data).ok_or_else(|| {
+            ConvertError::Unsupported(format!(
+                "cannot infer the format of '{}'; pass --format",
+                path.display()
+            ))
+        })?;
+        detection.format.source_format().ok_or_else(|| {
+            ConvertError::Unsupported(format!(
+                "'{}' looks like {}, which spaa cannot convert",
+                path.display(),
+                detection.format
+            ))
+        })
+    }
+}
+
+impl fmt::Display for SourceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SourceFormat::Perf => "perf",
+            SourceFormat::Dtrace => "dtrace",
+            SourceFormat::Chrome => "chrome",
+            SourceFormat::Turbopack => "turbopack",
+        })
+    }
+}
+
+/// Error returned when parsing an unknown [`SourceFormat`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSourceFormatError(String);
+
+impl fmt::Display for ParseSourceFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown source format '{}' (expected perf, dtrace, chrome or turbopack)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseSourceFormatError {}
+
+impl FromStr for SourceFormat {
+    type Err = ParseSourceFormatError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "perf" => Ok(SourceFormat::Perf),
+            "dtrace" => Ok(SourceFormat::Dtrace),
+            "chrome" => Ok(SourceFormat::Chrome),
+            "turbopack" => Ok(SourceFormat::Turbopack),
+            _ => Err(ParseSourceFormatError(s.to_string())),
+        }
+    }
+}
+
+/// Error returned when a converter rejects its input.
+#[derive(Error, Debug)]
+#[error("{format} conversion failed: {message}")]
+pub struct ConversionError {
+    pub format: SourceFormat,
+    pub message: String,
+}
+
+/// Identifies the converter for `format` in this build, e.g. `perf@0.1.0`.
+///
+/// The output of a conversion only depends on the input bytes and this
+/// string, so it is the cache key alongside the input digest.
+pub fn converter_version(format: SourceFormat) -> String {
+    format!("{}@{}", format, env!("CARGO_PKG_VERSION"))
+}
+
+/// Convert profiler output to SPAA NDJSON.
+pub fn convert(format: SourceFormat, data: &[u8]) -> Result<Vec<u8>, ConversionError> {
+    convert_with(format, data, &ConvertOptions::default())
+}
+
+/// Like [`convert`], with converter settings and post-conversion passes
+/// from `options`.
+pub fn convert_with(
+    format: SourceFormat,
+    data: &[u8],
+    options: &ConvertOptions,
+) -> Result<Vec<u8>, ConversionError> {
+    convert_with_report(format, data, options).map(|(out, _)| out)
+}
+
+/// Like [`convert_with`], also returning a [`FidelityReport`] of the
+/// conversion.
+pub fn convert_with_report(
+    format: SourceFormat,
+    data: &[u8],
+    options: &ConvertOptions,
+) -> Result<(Vec<u8>, FidelityReport), ConversionError> {
+    let _span = tracing::debug_span!("convert", %format, bytes = data.len()).entered();
+    let fail = |e: &dyn fmt::Display| ConversionError {
+        format,
+        message: e.to_string(),
+    };
+    let (mut file, fidelity) = match format {
+        SourceFormat::Perf => {
+            let mut converter = PerfConverter::new();
+            if let Some(depth) = options.max_stack_depth {
+                converter = converter.with_max_stack_depth(depth);
+            }
+            if options.strict {
+                converter = converter.with_strict();
+            }
+            converter.parse(data).map_err(|e| fail(&e))?;
+            (
+                converter.to_spaa_file().map_err(|e| fail(&e))?,
+                converter.fidelity(),
+            )
+        }
+        SourceFormat::Dtrace => {
+            let mut converter =
+                DtraceConverter::with_config(InputFormat::AggregatedStack, options.dtrace_config());
+            converter.parse(data).map_err(|e| fail(&e))?;
+            (
+                converter.to_spaa_file().map_err(|e| fail(&e))?,
+                converter.fidelity(),
+            )
+        }
+        SourceFormat::Chrome => {
+            let text = std::str::from_utf8(data).map_err(|e| fail(&e))?;
+            match detect_profile_type(text).map_err(|e| fail(&e))? {
+                ProfileType::HeapSnapshot | ProfileType::HeapTimeline => {
+                    let mut converter = HeapSnapshotConverter::new();
+                    converter.parse(data).map_err(|e| fail(&e))?;
+                    (
+                        converter.to_spaa_file().map_err(|e| fail(&e))?,
+                        converter.fidelity(),
+                    )
+                }
+                ProfileType::PerformanceTrace | ProfileType::CpuProfile => {
+                    let mut converter = CpuProfileConverter::new();
+                    converter.parse(data).map_err(|e| fail(&e))?;
+                    (
+                        converter.to_spaa_file().map_err(|e| fail(&e))?,
+                        converter.fidelity(),
+                    )
+                }
+            }
+        }
+        SourceFormat::Turbopack => {
+            let mut converter = TurbopackConverter::new();
+            converter
+                .parse_reader(Cursor::new(data))
+                .map_err(|e| fail(&e))?;
+            (
+                converter.to_spaa_file().map_err(|e| fail(&e))?,
+                converter.fidelity(),
+            )
+        }
+    };
+    options.apply(&mut file).map_err(|e| fail(&e))?;
+    let report = FidelityReport::new(format.to_string(), data.len() as u64, fidelity, &file);
+    let mut out = Vec::new();
+    file.write(&mut out).map_err(|e| fail(&e))?;
+    tracing::debug!(output_bytes = out.len(), "converted");
+    Ok((out, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_is_inferred_from_file_name() {
+        let infer = |name: &str| SourceFormat::from_path(Path::new(name));
+
+        assert_eq!(infer("profiles/app.cpuprofile"), Some(SourceFormat::Chrome));
+        assert_eq!(
+            infer(".next/trace-turbopack"),
+            Some(SourceFormat::Turbopack)
+        );
+        assert_eq!(infer("perf.txt"), None);
+    }
+}
@@ -0,0 +1,269 @@
+//! Conversion settings: DTrace defaults, stack depth, and the rewrite and
+//! redaction passes applied to the converted profile.
+
+use crate::dtrace::ConverterConfig;
+use crate::symbols::SymbolNormalizer;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use spaa_parse::SpaaFile;
+
+/// Replacement for redacted names.
+pub const REDACTED: &str = "[redacted]";
+
+/// A regex substitution applied to every function name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewriteRule {
+    /// Regular expression matched against function names.
+    pub pattern: String,
+    /// Replacement; `$1` and `${name}` refer to capture groups.
+    pub replace: String,
+}
+
+/// Names to hide before a profile leaves the machine it was recorded on.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Redaction {
+    /// Function names matching any of these patterns become [`REDACTED`].
+    pub functions: Vec<String>,
+    /// DSO paths matching any of these patterns become [`REDACTED`].
+    pub dsos: Vec<String>,
+    /// Drop the recorded command line from the header.
+    pub command: bool,
+}
+
+impl Redaction {
+    fn is_empty(&self) -> bool {
+        self.functions.is_empty() && self.dsos.is_empty() && !self.command
+    }
+}
+
+/// Settings for [`convert_with`](super::convert_with).
+///
+/// The passes run in order: symbol normalization, rewrite rules, then
+/// redaction, so redaction patterns see the final names.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConvertOptions {
+    /// Event name for DTrace output, which does not record it; defaults to
+    /// `profile-997`.
+    pub event: Option<String>,
+    /// Sampling frequency in Hz for DTrace output, or 0 for probe-based
+    /// tracing. Inferred from `profile-N` event names when unset.
+    pub frequency: Option<u64>,
+    /// Truncate perf and DTrace stacks deeper than this many frames.
+    pub max_stack_depth: Option<usize>,
+    /// DTrace records start with an `execname [pid]` key, as printed for
+    /// `@[execname, ustack()]`; each process gets its own stacks.
+    pub execname: bool,
+    /// Normalize function names with the default [`SymbolNormalizer`].
+    pub normalize_symbols: bool,
+    /// Rebase timestamps to Unix time when the converter knows the clock's
+    /// offset; see [`SpaaFile::rebase_to_wall_clock`].
+    pub wall_clock: bool,
+    /// Describe the converting machine with [`crate::system::local`] when
+    /// the input does not say which host it was recorded on.
+    pub system_info: bool,
+    /// Fail on perf and DTrace input the converter would otherwise skip,
+    /// such as unparsable frame lines and stacks without a count.
+    pub strict: bool,
+    pub rewrite: Vec<RewriteRule>,
+    pub redact: Redaction,
+}
+
+impl ConvertOptions {
+    /// DTrace converter settings derived from these options.
+    pub fn dtrace_config(&self) -> ConverterConfig {
+        let event_name = self
+            .event
+            .clone()
+            .unwrap_or_else(|| ConverterConfig::default().event_name);
+        let frequency_hz = match self.frequency {
+            Some(0) => None,
+            Some(f) => Some(f),
+            None => event_name
+                .strip_prefix("profile-")
+                .and_then(|f| f.parse().ok()),
+        };
+        ConverterConfig {
+            event_name,
+            frequency_hz,
+            max_stack_depth: self.max_stack_depth,
+            execname_key: self.execname,
+            strict: self.strict,
+        }
+    }
+
+    /// Check that every pattern compiles.
+    pub fn validate(&self) -> Result<(), regex::Error> {
+        self.compile().map(|_| ())
+    }
+
+    /// Whether [`apply`](Self::apply) would change anything.
+    pub fn has_transforms(&self) -> bool {
+        self.normalize_symbols
+            || self.wall_clock
+            || self.system_info
+            || !self.rewrite.is_empty()
+            || !self.redact.is_empty()
+    }
+
+    /// Run the normalization, rewrite and redaction passes over `file`,
+    /// after rebasing it to wall-clock time and filling in the host if
+    /// asked to.
+    pub fn apply(&self, file: &mut SpaaFile) -> Result<(), regex::Error> {
+        let compiled = self.compile()?;
+        if self.system_info {
+            file.system.get_or_insert_with(crate::system::local);
+        }
+        if self.wall_clock && !file.rebase_to_wall_clock() {
+            tracing::warn!("clock offset unknown; timestamps left as recorded");
+        }
+        if self.normalize_symbols {
+            SymbolNormalizer::default().normalize_file(file);
+        }
+        for frame in file.frames.values_mut() {
+            for (pattern, replace) in &compiled.rewrites {
+                if let std::borrow::Cow::Owned(name) = pattern.replace_all(&frame.func, *replace) {
+                    frame.func = name;
+                }
+            }
+            if compiled.functions.iter().any(|p| p.is_match(&frame.func)) {
+                frame.func = REDACTED.to_string();
+            }
+        }
+        for dso in file.dsos.values_mut() {
+            if compiled.dsos.iter().any(|p| p.is_match(&dso.name)) {
+                dso.name = REDACTED.to_string();
+            }
+        }
+        if self.redact.command
+            && let Some(source) = &mut file.header.source
+        {
+            source.command = None;
+        }
+        Ok(())
+    }
+
+    /// Key identifying conversions of `format` with these options, for
+    /// caches: [`converter_version`](super::converter_version), plus a digest
+    /// of the options when they differ from the defaults.
+    pub fn cache_key(&self, format: super::SourceFormat) -> String {
+        let version = super::converter_version(format);
+        if *self == Self::default() {
+            return version;
+        }
+        let options = serde_json::to_vec(self).expect("options serialize to JSON");
+        let digest = format!("{:x}", Sha256::digest(options));
+        format!("{}+{}", version, &digest[..16])
+    }
+
+    fn compile(&self) -> Result<Compiled<'_>, regex::Error> {
+        let rewrites = self
+            .rewrite
+            .iter()
+            .map(|r| Ok((Regex::new(&r.pattern)?, r.replace.as_str())))
+            .collect::<Result<_, regex::Error>>()?;
+        let compile_all = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| Regex::new(p))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Compiled {
+            rewrites,
+            functions: compile_all(&self.redact.functions)?,
+            dsos: compile_all(&self.redact.dsos)?,
+        })
+    }
+}
+
+/// Compiled patterns of a [`ConvertOptions`].
+struct Compiled<'a> {
+    rewrites: Vec<(Regex, &'a str)>,
+    functions: Vec<Regex>,
+    dsos: Vec<Regex>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::SourceFormat;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}],"source":{"tool":"perf","command":"perf record --token=abc"}}"#,
+            r#"{"type":"dso","id":1,"name":"/home/alice/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"acme_internal::secret_sauce","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"acme_internal::parse","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[1,2],"context":{"event":"cycles"},"weights":[{"metric":"period","value":10}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse_slice(data.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn rewrite_runs_before_redaction() {
+        let options = ConvertOptions {
+            rewrite: vec![RewriteRule {
+                pattern: "^acme_internal::".to_string(),
+                replace: "acme::".to_string(),
+            }],
+            redact: Redaction {
+                functions: vec!["^acme::secret".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut file = sample_file();
+        options.apply(&mut file).unwrap();
+
+        assert_eq!(file.frames[&1].func, REDACTED);
+        assert_eq!(file.frames[&2].func, "acme::parse");
+    }
+
+    #[test]
+    fn redaction_covers_dsos_and_command() {
+        let options = ConvertOptions {
+            redact: Redaction {
+                dsos: vec!["^/home/".to_string()],
+                command: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut file = sample_file();
+        options.apply(&mut file).unwrap();
+
+        assert_eq!(file.dsos[&1].name, REDACTED);
+        assert_eq!(file.header.source.unwrap().command, None);
+    }
+
+    #[test]
+    fn dtrace_frequency_is_inferred_from_event() {
+        let options = ConvertOptions {
+            event: Some("profile-4999".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(options.dtrace_config().frequency_hz, Some(4999));
+    }
+
+    #[test]
+    fn cache_key_changes_with_options() {
+        let options = ConvertOptions {
+            normalize_symbols: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ConvertOptions::default().cache_key(SourceFormat::Perf),
+            crate::convert::converter_version(SourceFormat::Perf)
+        );
+        assert_ne!(
+            options.cache_key(SourceFormat::Perf),
+            ConvertOptions::default().cache_key(SourceFormat::Perf)
+        );
+    }
+}
@@ -0,0 +1,671 @@
+//! Windowed streaming output for inputs that never end.
+//!
+//! A [`WindowedWriter`] aggregates samples into the current window and, when
+//! the window closes, writes the dictionary and stack records it introduced
+//! followed by a `window` record with its per-stack weights. Only the
+//! current window's weights and one record per distinct frame and stack are
+//! kept, so memory does not grow with the length of the input.
+//!
+//! Stack records are written when a stack first appears, carrying its
+//! weights so far. When the stream is [finished](WindowedWriter::finish),
+//! stacks that gained weight later are written again with their totals;
+//! SPAA readers keep the last record for each stack ID. A stream cut short
+//! still parses, with stack totals as of their first window and exact
+//! per-window weights.
+//!
+//! With [`WindowPolicy::max_stacks`] set, at most that many stacks are
+//! tracked, using the Space-Saving algorithm: a new stack arriving when the
+//! table is full replaces the lightest one and inherits its weight, so
+//! memory stays bounded however many distinct stacks the input has, and
+//! every stack carrying more than `1 / max_stacks` of the total is kept.
+//! Stack totals become upper-bound estimates and the header is marked with
+//! an [`Approximation`]; per-window weights stay exact. Stacks are ranked
+//! by their event's primary metric, or by a `samples` count for events
+//! first seen after the header was written.
+
+use spaa_parse::{
+    Approximation, Dso, EventDef, ExclusiveWeights, Frame, FrameKind, FrameOrder, Header,
+    SPACE_SAVING, SpaaWriter, Stack, StackContext, StackType, Thread, Weight, Window,
+    WindowStackWeight, WriteResult,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Write;
+
+/// When a [`WindowedWriter`] closes its window. A window closes as soon as
+/// either limit is reached; with neither set, there is a single window.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WindowPolicy {
+    /// Close windows every this many seconds of sample time.
+    pub seconds: Option<f64>,
+    /// Close windows after this many samples.
+    pub samples: Option<u64>,
+    /// Track at most this many stacks, approximating their totals.
+    pub max_stacks: Option<usize>,
+}
+
+/// A frame of a [`StreamSample`], before it has a dictionary ID.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StreamFrame {
+    pub func: String,
+    /// DSO name.
+    pub dso: String,
+    pub kind: FrameKind,
+    pub ip: Option<String>,
+    pub symoff: Option<String>,
+    pub srcline: Option<String>,
+}
+
+/// One sample fed to a [`WindowedWriter`].
+#[derive(Debug, Clone)]
+pub struct StreamSample {
+    /// Sample time in seconds; samples without one never close a window by
+    /// time.
+    pub timestamp: Option<f64>,
+    /// Frames in the header's frame order.
+    pub frames: Vec<StreamFrame>,
+    pub context: StackContext,
+    pub weights: Vec<Weight>,
+}
+
+/// A stack and whether it gained weight after its record was written.
+struct StreamStack {
+    stack: Stack,
+    stale: bool,
+    /// Space-Saving count, including weight inherited on admission.
+    count: u64,
+}
+
+/// Writes SPAA incrementally, one window at a time.
+///
+/// Stack records are written when a stack first appears and again by
+/// [`finish`](Self::finish) if they gained weight since; readers keep the
+/// last record for each stack ID.
+pub struct WindowedWriter<W: Write> {
+    writer: SpaaWriter<W>,
+    /// The header, until the first window is written.
+    header: Option<Header>,
+    frame_order: FrameOrder,
+    policy: WindowPolicy,
+    dsos: HashMap<String, u64>,
+    frames: HashMap<StreamFrame, u64>,
+    threads: HashSet<(u64, u64)>,
+    stacks: HashMap<String, StreamStack>,
+    /// Primary metric of each declared event, ranking stacks for eviction.
+    primary_metrics: HashMap<String, String>,
+    /// Tracked stacks by count, when `max_stacks` is set.
+    by_count: BTreeSet<(u64, String)>,
+    /// Evicted stacks whose latest weights have not been written.
+    evicted: Vec<Stack>,
+    /// Records introduced since the last window, in order.
+    pending_dsos: Vec<Dso>,
+    pending_frames: Vec<Frame>,
+    pending_threads: Vec<Thread>,
+    pending_stacks: Vec<String>,
+    /// Weights per stack in the current window.
+    window: BTreeMap<String, Vec<Weight>>,
+    window_start: Option<f64>,
+    window_end: Option<f64>,
+    window_samples: u64,
+    windows_written: u64,
+}
+
+impl<W: Write> WindowedWriter<W> {
+    /// A writer that writes `header` before its first window.
+    pub fn new(writer: W, mut header: Header, policy: WindowPolicy) -> Self {
+        if let Some(capacity) = policy.max_stacks {
+            header.approximation = Some(Approximation {
+                algorithm: SPACE_SAVING.to_string(),
+                capacity: capacity as u64,
+            });
+        }
+        let primary_metrics = header
+            .events
+            .iter()
+            .map(|e| (e.name.clone(), e.sampling.primary_metric.clone()))
+            .collect();
+        Self {
+            writer: SpaaWriter::new(writer),
+            frame_order: header.frame_order,
+            header: Some(header),
+            policy,
+            dsos: HashMap::new(),
+            frames: HashMap::new(),
+            threads: HashSet::new(),
+            stacks: HashMap::new(),
+            primary_metrics,
+            by_count: BTreeSet::new(),
+            evicted: Vec::new(),
+            pending_dsos: Vec::new(),
+            pending_frames: Vec::new(),
+            pending_threads: Vec::new(),
+            pending_stacks: Vec::new(),
+            window: BTreeMap::new(),
+            window_start: None,
+            window_end: None,
+            window_samples: 0,
+            windows_written: 0,
+        }
+    }
+
+    /// Declare an event in the header. Events first seen after the header
+    /// was written cannot be declared and are only logged.
+    pub fn declare_event(&mut self, event: EventDef) {
+        match &mut self.header {
+            Some(header) => {
+                if !header.events.iter().any(|e| e.name == event.name) {
+                    self.primary_metrics
+                        .insert(event.name.clone(), event.sampling.primary_metric.clone());
+                    header.events.push(event);
+                }
+            }
+            None => tracing::warn!(
+                event = %event.name,
+                "event first seen after the header was written; it is not declared"
+            ),
+        }
+    }
+
+    /// Number of windows written so far.
+    pub fn windows_written(&self) -> u64 {
+        self.windows_written
+    }
+
+    /// Add a sample, closing the current window first if the sample falls
+    /// past its time limit.
+    pub fn push(&mut self, sample: StreamSample) -> WriteResult<()> {
+        if let (Some(seconds), Some(start), Some(ts)) =
+            (self.policy.seconds, self.window_start, sample.timestamp)
+            && ts >= start + seconds
+        {
+            self.flush_window()?;
+            // Windows start on multiples of the window length after the first,
+            // skipping any that would be empty.
+            self.window_start = Some(start + ((ts - start) / seconds).floor() * seconds);
+        }
+        if let Some(ts) = sample.timestamp {
+            self.window_start = Some(self.window_start.map_or(ts, |s| s.min(ts)));
+            self.window_end = Some(self.window_end.map_or(ts, |e| e.max(ts)));
+        }
+
+        let frame_ids: Vec<u64> = sample.frames.into_iter().map(|f| self.intern(f)).collect();
+        if let (Some(pid), Some(tid)) = (sample.context.pid, sample.context.tid)
+            && self.threads.insert((pid, tid))
+        {
+            self.pending_threads.push(Thread {
+                pid,
+                tid,
+                comm: sample.context.comm.clone(),
+            });
+        }
+
+        let id = stack_id(&frame_ids, &sample.context);
+        // Stacks are ranked by their event's primary metric, or for
+        // undeclared events by a `samples` count. When the sample has none,
+        // the stack totals count it so the weight they inherit has somewhere
+        // to go; the window keeps the sample's own weights.
+        let (ranked_by, counted) = match self.primary_metrics.get(&sample.context.event) {
+            Some(metric) => (metric.clone(), None),
+            None => {
+                let counted = (self.policy.max_stacks.is_some()
+                    && !sample.weights.iter().any(|w| w.metric == "samples"))
+                .then(|| Weight {
+                    metric: "samples".to_string(),
+                    value: 1,
+                    unit: None,
+                });
+                ("samples".to_string(), counted)
+            }
+        };
+        let rank = sample
+            .weights
+            .iter()
+            .chain(&counted)
+            .find(|w| w.metric == ranked_by)
+            .map_or(0, |w| w.value);
+        let inherited = if self.stacks.contains_key(&id) {
+            0
+        } else {
+            self.make_room()
+        };
+        let entry = self.stacks.entry(id.clone()).or_insert_with(|| {
+            self.pending_stacks.push(id.clone());
+            let leaf = match self.frame_order {
+                FrameOrder::LeafToRoot => frame_ids.first(),
+                FrameOrder::RootToLeaf => frame_ids.last(),
+            };
+            StreamStack {
+                stack: Stack {
+                    id: id.clone(),
+                    exclusive: leaf.map(|&frame| ExclusiveWeights {
+                        frame,
+                        weights: Vec::new(),
+                    }),
+                    frames: frame_ids,
+                    stack_type: StackType::Unified,
+                    context: sample.context,
+                    weights: Vec::new(),
+                    related_stacks: None,
+                },
+                stale: false,
+                count: 0,
+            }
+        });
+        if inherited > 0 {
+            let inherited = [Weight {
+                metric: ranked_by,
+                value: inherited,
+                unit: None,
+            }];
+            add_weights(&mut entry.stack.weights, &inherited);
+            if let Some(exclusive) = &mut entry.stack.exclusive {
+                add_weights(&mut exclusive.weights, &inherited);
+            }
+        }
+        let counted = counted.as_slice();
+        add_weights(&mut entry.stack.weights, &sample.weights);
+        add_weights(&mut entry.stack.weights, counted);
+        if let Some(exclusive) = &mut entry.stack.exclusive {
+            add_weights(&mut exclusive.weights, &sample.weights);
+            add_weights(&mut exclusive.weights, counted);
+        }
+        entry.stale = true;
+        if self.policy.max_stacks.is_some() {
+            self.by_count.remove(&(entry.count, id.clone()));
+            entry.count = entry.count.max(inherited).saturating_add(rank);
+            self.by_count.insert((entry.count, id.clone()));
+        }
+        add_weights(self.window.entry(id).or_default(), &sample.weights);
+
+        self.window_samples += 1;
+        if self
+            .policy
+            .samples
+            .is_some_and(|limit| self.window_samples >= limit)
+        {
+            self.flush_window()?;
+        }
+        Ok(())
+    }
+
+    /// Write the current window, if it has any samples.
+    pub fn flush_window(&mut self) -> WriteResult<()> {
+        if self.window.is_empty() {
+            return Ok(());
+        }
+        self.write_header()?;
+        for dso in self.pending_dsos.drain(..) {
+            self.writer.write_dso(&dso)?;
+        }
+        for frame in self.pending_frames.drain(..) {
+            self.writer.write_frame(&frame)?;
+        }
+        for thread in self.pending_threads.drain(..) {
+            self.writer.write_thread(&thread)?;
+        }
+        for stack in self.evicted.drain(..) {
+            self.writer.write_stack(&stack)?;
+        }
+        for id in self.pending_stacks.drain(..) {
+            let entry = self.stacks.get_mut(&id).expect("pending stacks are known");
+            self.writer.write_stack(&entry.stack)?;
+            entry.stale = false;
+        }
+
+        self.windows_written += 1;
+        let start = self.window_start.unwrap_or(0.0);
+        let end = match (self.policy.seconds, self.window_start) {
+            (Some(seconds), Some(start)) => start + seconds,
+            _ => self.window_end.unwrap_or(start),
+        };
+        let window = Window {
+            id: format!("w{}", self.windows_written),
+            start,
+            end,
+            unit: "seconds".to_string(),
+            by_stack: std::mem::take(&mut self.window)
+                .into_iter()
+                .map(|(stack_id, weights)| WindowStackWeight { stack_id, weights })
+                .collect(),
+        };
+        self.writer.write_window(&window)?;
+        self.writer.get_mut().flush()?;
+
+        self.window_start = None;
+        self.window_end = None;
+        self.window_samples = 0;
+        Ok(())
+    }
+
+    /// Write the last window, then the final totals of stacks that gained
+    /// weight after they were written, and return the underlying writer.
+    pub fn finish(mut self) -> WriteResult<W> {
+        self.flush_window()?;
+        self.write_header()?;
+        for stack in self.evicted.drain(..) {
+            self.writer.write_stack(&stack)?;
+        }
+        let mut stale: Vec<&Stack> = self
+            .stacks
+            .values()
+            .filter(|s| s.stale)
+            .map(|s| &s.stack)
+            .collect();
+        stale.sort_by(|a, b| a.id.cmp(&b.id));
+        for stack in stale {
+            self.writer.write_stack(stack)?;
+        }
+        let mut writer = self.writer.into_inner();
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    /// Evict the lightest stack if the table is full, returning its count
+    /// for the stack taking its place to inherit.
+    fn make_room(&mut self) -> u64 {
+        let Some(capacity) = self.policy.max_stacks else {
+            return 0;
+        };
+        if self.stacks.len() < capacity.max(1) {
+            return 0;
+        }
+        let Some((count, id)) = self.by_count.pop_first() else {
+            return 0;
+        };
+        let evicted = self.stacks.remove(&id).expect("counted stacks are known");
+        let pending = match self.pending_stacks.iter().position(|p| *p == id) {
+            Some(i) => {
+                self.pending_stacks.remove(i);
+                true
+            }
+            None => false,
+        };
+        // Keep a record for the stack, which the current window may refer
+        // to, carrying its latest estimate.
+        if pending || evicted.stale {
+            self.evicted.push(evicted.stack);
+        }
+        count
+    }
+
+    fn write_header(&mut self) -> WriteResult<()> {
+        if let Some(header) = self.header.take() {
+            self.writer.write_header(&header)?;
+        }
+        Ok(())
+    }
+
+    /// The frame ID for `frame`, queueing dictionary records for new ones.
+    fn intern(&mut self, frame: StreamFrame) -> u64 {
+        if let Some(&id) = self.frames.get(&frame) {
+            return id;
+        }
+        let dso = match self.dsos.get(&frame.dso) {
+            Some(&id) => id,
+            None => {
+                let id = self.dsos.len() as u64 + 1;
+                self.dsos.insert(frame.dso.clone(), id);
+                self.pending_dsos.push(Dso {
+                    id,
+                    name: frame.dso.clone(),
+                    build_id: None,
+                    is_kernel: frame.kind == FrameKind::Kernel,
+                });
+                id
+            }
+        };
+        let id = self.frames.len() as u64 + 1;
+        self.pending_frames.push(Frame {
+            id,
+            func_resolved: !frame.func.starts_with("0x"),
+            func: frame.func.clone(),
+            dso,
+            ip: frame.ip.clone(),
+            symoff: frame.symoff.clone(),
+            srcline: frame.srcline.clone(),
+            srcline_resolved: true,
+            inlined: false,
+            inline_depth: None,
+            kind: frame.kind,
+        });
+        self.frames.insert(frame, id);
+        id
+    }
+}
+
+/// Stack ID from the frames and context, so that the same frames sampled
+/// in different threads or events stay apart.
+fn stack_id(frame_ids: &[u64], context: &StackContext) -> String {
+    let mut hasher = DefaultHasher::new();
+    frame_ids.hash(&mut hasher);
+    // `Value` maps are sorted, so the key does not depend on HashMap order.
+    serde_json::to_value(context)
+        .map(|v| v.to_string())
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    format!("0x{:016x}", hasher.finish())
+}
+
+/// Add `weights` into `into` by metric.
+fn add_weights(into: &mut Vec<Weight>, weights: &[Weight]) {
+    for weight in weights {
+        match into.iter_mut().find(|w| w.metric == weight.metric) {
+            Some(existing) => existing.value = existing.value.saturating_add(weight.value),
+            None => into.push(weight.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spaa_parse::{SpaaFile, StackIdMode};
+
+    fn header() -> Header {
+        Header {
+            format: "spaa".to_string(),
+            version: "1.0".to_string(),
+            source_tool: "test".to_string(),
+            frame_order: FrameOrder::LeafToRoot,
+            events: Vec::new(),
+            time_range: None,
+            source: None,
+            stack_id_mode: StackIdMode::ContentAddressable,
+            clock: None,
+            approximation: None,
+        }
+    }
+
+    fn sample(timestamp: f64, funcs: &[&str], period: u64) -> StreamSample {
+        StreamSample {
+            timestamp: Some(timestamp),
+            frames: funcs
+                .iter()
+                .map(|f| StreamFrame {
+                    func: f.to_string(),
+                    dso: "app".to_string(),
+                    kind: FrameKind::User,
+                    ip: None,
+                    symoff: None,
+                    srcline: None,
+                })
+                .collect(),
+            context: StackContext {
+                event: "cycles".to_string(),
+                pid: None,
+                tid: None,
+                cpu: None,
+                comm: None,
+                probe: None,
+                execname: None,
+                uid: None,
+                zonename: None,
+                cgroup: None,
+                container_id: None,
+                k8s_pod: None,
+                trace_fields: None,
+                extra: HashMap::new(),
+            },
+            weights: vec![Weight {
+                metric: "period".to_string(),
+                value: period,
+                unit: None,
+            }],
+        }
+    }
+
+    fn total(file: &SpaaFile) -> u64 {
+        file.stacks.values().map(|s| s.weights[0].value).sum()
+    }
+
+    #[test]
+    fn windows_close_by_time() {
+        let mut writer = WindowedWriter::new(
+            Vec::new(),
+            header(),
+            WindowPolicy {
+                seconds: Some(1.0),
+                samples: None,
+                max_stacks: None,
+            },
+        );
+        writer.push(sample(10.0, &["a", "main"], 5)).unwrap();
+        writer.push(sample(10.5, &["b", "main"], 3)).unwrap();
+        writer.push(sample(12.2, &["a", "main"], 7)).unwrap();
+        let file = SpaaFile::parse_slice(&writer.finish().unwrap()).unwrap();
+
+        let spans: Vec<(f64, f64, usize)> = file
+            .windows
+            .iter()
+            .map(|w| (w.start, w.end, w.by_stack.len()))
+            .collect();
+        assert_eq!(spans, [(10.0, 11.0, 2), (12.0, 13.0, 1)]);
+    }
+
+    #[test]
+    fn final_totals_replace_first_window_weights() {
+        let mut writer = WindowedWriter::new(
+            Vec::new(),
+            header(),
+            WindowPolicy {
+                seconds: None,
+                samples: Some(1),
+                max_stacks: None,
+            },
+        );
+        writer.push(sample(1.0, &["a", "main"], 5)).unwrap();
+        writer.push(sample(2.0, &["a", "main"], 7)).unwrap();
+        let file = SpaaFile::parse_slice(&writer.finish().unwrap()).unwrap();
+
+        assert_eq!(file.windows.len(), 2);
+        assert_eq!(file.stacks.len(), 1);
+        assert_eq!(total(&file), 12);
+    }
+
+    #[test]
+    fn output_is_readable_before_finish() {
+        let mut writer = WindowedWriter::new(
+            Vec::new(),
+            header(),
+            WindowPolicy {
+                seconds: None,
+                samples: Some(2),
+                max_stacks: None,
+            },
+        );
+        writer.push(sample(1.0, &["a", "main"], 5)).unwrap();
+        writer.push(sample(1.5, &["b", "main"], 1)).unwrap();
+        writer.push(sample(2.0, &["c", "main"], 2)).unwrap();
+        let file = SpaaFile::parse_slice(writer.writer.get_ref()).unwrap();
+
+        assert_eq!(file.windows.len(), 1);
+        assert_eq!(total(&file), 6);
+    }
+
+    #[test]
+    fn max_stacks_keeps_heavy_stacks_within_capacity() {
+        let mut writer = WindowedWriter::new(
+            Vec::new(),
+            header(),
+            WindowPolicy {
+                seconds: None,
+                samples: Some(4),
+                max_stacks: Some(2),
+            },
+        );
+        writer.declare_event(
+            serde_json::from_str(
+                r#"{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}"#,
+            )
+            .unwrap(),
+        );
+        for (i, func) in ["a", "b", "a", "c", "a", "d", "a", "e"].iter().enumerate() {
+            let period = if *func == "a" { 10 } else { 1 };
+            writer
+                .push(sample(i as f64, &[func, "main"], period))
+                .unwrap();
+            assert!(writer.stacks.len() <= 2);
+        }
+        let file = SpaaFile::parse_slice(&writer.finish().unwrap()).unwrap();
+
+        assert_eq!(
+            file.header.approximation,
+            Some(Approximation {
+                algorithm: SPACE_SAVING.to_string(),
+                capacity: 2,
+            })
+        );
+        // The heavy stack survives with at least its true weight, and every
+        // window still refers to written stacks with exact weights.
+        let heavy = file
+            .stacks
+            .values()
+            .find(|s| file.frames[&s.frames[0]].func == "a")
+            .unwrap();
+        assert!(heavy.weights[0].value >= 40);
+        let windowed: u64 = file
+            .windows
+            .iter()
+            .flat_map(|w| &w.by_stack)
+            .map(|s| {
+                assert!(file.stacks.contains_key(&s.stack_id));
+                s.weights[0].value
+            })
+            .sum();
+        assert_eq!(windowed, 44);
+    }
+
+    #[test]
+    fn undeclared_events_inherit_into_samples() {
+        let mut writer = WindowedWriter::new(
+            Vec::new(),
+            header(),
+            WindowPolicy {
+                seconds: None,
+                samples: None,
+                max_stacks: Some(1),
+            },
+        );
+        writer.push(sample(1.0, &["a", "main"], 5)).unwrap();
+        writer.push(sample(2.0, &["b", "main"], 7)).unwrap();
+        let file = SpaaFile::parse_slice(&writer.finish().unwrap()).unwrap();
+
+        // "b" replaced "a" and took over its one sample, so its count is an
+        // upper bound on its own.
+        let b = file
+            .stacks
+            .values()
+            .find(|s| file.frames[&s.frames[0]].func == "b")
+            .unwrap();
+        let samples = b.weights.iter().find(|w| w.metric == "samples").unwrap();
+        assert_eq!(samples.value, 2);
+        // Windows only carry the metrics the samples had.
+        assert!(!file.windows.is_empty());
+        assert!(
+            file.windows
+                .iter()
+                .flat_map(|w| &w.by_stack)
+                .all(|e| e.weights.iter().all(|w| w.metric != "samples"))
+        );
+    }
+}
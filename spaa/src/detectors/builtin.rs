@@ -0,0 +1,99 @@
+//! Built-in detector rules.
+
+use super::{Detector, MatchScope, Rule};
+use regex::Regex;
+
+/// (id, title, advice, pattern, scope, min_share, critical_share)
+type RuleSpec = (
+    &'static str,
+    &'static str,
+    &'static str,
+    &'static str,
+    MatchScope,
+    f64,
+    f64,
+);
+
+const BUILTIN: &[RuleSpec] = &[
+    (
+        "spin-loop",
+        "Busy-wait spin loop",
+        "Threads are burning CPU waiting for a lock or flag; check lock contention or switch to a blocking wait.",
+        r"(?i)spin_?(lock|loop|wait|until)|pthread_spin|cpu_relax|sched_yield|_mm_pause|osq_lock|busy_?wait|SpinWait",
+        MatchScope::Leaf,
+        0.05,
+        0.25,
+    ),
+    (
+        "memcpy",
+        "Excessive memory copying",
+        "A large share of time is spent copying memory; look for avoidable clones, buffer resizes or copies across the user/kernel boundary.",
+        r"(^|_)mem(cpy|move)|copy_(user|to_user|from_user)",
+        MatchScope::Leaf,
+        0.1,
+        0.3,
+    ),
+    (
+        "allocator",
+        "Allocator-heavy hot path",
+        "Hot paths spend much of their time in the allocator; reuse buffers, reserve capacity up front or use an arena.",
+        r"^(__libc_)?(malloc|calloc|realloc|free|cfree|memalign|posix_memalign|aligned_alloc)$|^_int_(malloc|free|realloc|memalign)$|^malloc_consolidate$|^(je|tc|mi)_(malloc|calloc|realloc|free)|^__rust_(alloc|dealloc|realloc|alloc_zeroed)$|^operator (new|delete)",
+        MatchScope::Any,
+        0.1,
+        0.3,
+    ),
+    (
+        "page-faults",
+        "Page-fault storm",
+        "Much of the time is spent servicing page faults; pre-fault or reuse memory, or check for memory pressure and huge page settings.",
+        r"^(asm_)?exc_page_fault$|^do_(user_addr_fault|page_fault|anonymous_page|huge_pmd_anonymous_page)$|^_?_?handle_mm_fault$|^page_fault$|^filemap_fault$",
+        MatchScope::Any,
+        0.05,
+        0.2,
+    ),
+    (
+        "regex-backtracking",
+        "Regex backtracking",
+        "A backtracking regex engine is hot; simplify the pattern, anchor it, or switch to a linear-time engine.",
+        r"(?i)backtrack|^pcre2?_(match|exec|dfa_match)|^pcre_exec$|^onig_(search|match)|std::__detail::_Executor|java\.util\.regex\.Pattern|RegExpImpl|irregexp",
+        MatchScope::Any,
+        0.05,
+        0.2,
+    ),
+    (
+        "logging",
+        "Logging on a hot path",
+        "Log formatting and output show up in hot paths; lower the log level, guard expensive messages or log asynchronously.",
+        r"^(__)?v?f?printf(_chk)?$|^v?syslog$|^__android_log_|(^|::)(log|tracing|tracing_core|env_logger|slog|spdlog|glog)::|^google::LogMessage|log4j|org\.slf4j|ch\.qos\.logback|(^|\.)logging\.|^console\.log$",
+        MatchScope::Any,
+        0.05,
+        0.2,
+    ),
+];
+
+/// Rules for common pathologies, in a fixed order.
+pub fn builtin_rules() -> Vec<Rule> {
+    BUILTIN
+        .iter()
+        .map(
+            |&(id, title, advice, pattern, scope, min_share, critical_share)| Rule {
+                id: id.to_string(),
+                title: title.to_string(),
+                advice: advice.to_string(),
+                pattern: Regex::new(pattern).expect("built-in patterns are valid"),
+                scope,
+                min_share,
+                critical_share,
+                events: None,
+            },
+        )
+        .collect()
+}
+
+/// [`builtin_rules`] as detectors, ready for [`detect`](super::detect).
+pub fn builtin_detectors() -> Vec<Box<dyn Detector>> {
+    builtin_rules()
+        .into_iter()
+        .map(|rule| Box::new(rule) as Box<dyn Detector>)
+        .collect()
+}
@@ -0,0 +1,464 @@
+//! Known-issue detectors.
+//!
+//! A [`Detector`] inspects one event of a profile and reports
+//! [`Finding`]s. The stock implementation is [`Rule`], which matches function names against a regular expression and
+//! reports a [`Finding`] when the matching stacks carry more than a
+//! threshold share of an event's weight. Each finding names the rule, the
+//! share it measured and the heaviest matching stacks as evidence, so a
+//! reader can jump straight to the code paths involved.
+//!
+//! [`builtin_rules`] covers common pathologies: spin loops, excessive
+//! `memcpy`/`memmove`, allocator-heavy paths, page-fault storms, regex
+//! backtracking and logging on hot paths. Organization-specific rules can
+//! be written in TOML or JSON and loaded with [`load_rules`]; anything a
+//! pattern cannot express can implement [`Detector`] directly.
+//!
+//! Rules skip allocation events: every stack in them is an allocator call,
+//! so frame-share rules would fire on every profile.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::detectors::{DetectOptions, builtin_detectors, detect};
+//! use spaa_parse::SpaaFile;
+//!
+//! let spaa = SpaaFile::open("profile.spaa").unwrap();
+//! for finding in detect(&spaa, &builtin_detectors(), &DetectOptions::default()) {
+//!     println!("[{}] {}: {:.1}%", finding.severity, finding.title, finding.share * 100.0);
+//! }
+//! ```
+
+mod builtin;
+mod rules;
+
+pub use builtin::{builtin_detectors, builtin_rules};
+pub use rules::{RuleDef, RuleError, load_rules, rules_from_json, rules_from_toml};
+
+use crate::analysis::stack_weight;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use spaa_parse::{EventDef, EventKind, FrameOrder, SpaaFile, Stack};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Which frames of a stack a [`Rule`] looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchScope {
+    /// Only the innermost frame: time spent in the matching function itself.
+    Leaf,
+    /// Any frame: time spent in or below the matching function.
+    Any,
+}
+
+/// How urgent a [`Finding`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        })
+    }
+}
+
+/// Something that can spot a known issue in a profile.
+///
+/// [`detect`] calls [`detect`](Detector::detect) once per header event,
+/// including allocation events; implementations decide which events they
+/// apply to.
+pub trait Detector {
+    /// Identifier reported as [`Finding::rule`].
+    fn id(&self) -> &str;
+
+    /// Inspect `event` and return any findings.
+    fn detect(&self, file: &SpaaFile, event: &EventDef, options: &DetectOptions) -> Vec<Finding>;
+}
+
+/// A frame-pattern rule.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// Short kebab-case identifier, e.g. `spin-loop`.
+    pub id: String,
+    /// One-line description of the pathology.
+    pub title: String,
+    /// What to look at when the rule fires.
+    pub advice: String,
+    /// Matched against each frame's function name.
+    pub pattern: Regex,
+    pub scope: MatchScope,
+    /// Share of an event's weight at which the rule fires.
+    pub min_share: f64,
+    /// Share at which the finding is [`Severity::Critical`].
+    pub critical_share: f64,
+    /// Restricts the rule to events whose name matches.
+    pub events: Option<Regex>,
+}
+
+impl Rule {
+    /// Evaluate the rule against one event, returning a finding if the
+    /// matching share reaches [`min_share`](Self::min_share).
+    pub fn evaluate(
+        &self,
+        file: &SpaaFile,
+        event: &str,
+        metric: &str,
+        options: &DetectOptions,
+    ) -> Option<Finding> {
+        let mut matches: HashMap<u64, bool> = HashMap::new();
+        let mut total = 0;
+        let mut hits: Vec<(&Stack, String, u64)> = Vec::new();
+
+        for stack in file.stacks_for_event(event) {
+            let weight = stack_weight(stack, metric);
+            total += weight;
+            if weight == 0 {
+                continue;
+            }
+            let candidates: &[u64] = match self.scope {
+                MatchScope::Any => &stack.frames,
+                MatchScope::Leaf => match file.header.frame_order {
+                    FrameOrder::LeafToRoot => &stack.frames[..stack.frames.len().min(1)],
+                    FrameOrder::RootToLeaf => &stack.frames[stack.frames.len().saturating_sub(1)..],
+                },
+            };
+            let matched = candidates.iter().find_map(|&id| {
+                let frame = file.resolve_frame(id)?;
+                let hit = *matches
+                    .entry(id)
+                    .or_insert_with(|| self.pattern.is_match(&frame.func));
+                hit.then(|| frame.func.clone())
+            });
+            if let Some(func) = matched {
+                hits.push((stack, func, weight));
+            }
+        }
+
+        let weight: u64 = hits.iter().map(|(_, _, w)| w).sum();
+        if total == 0 || weight == 0 {
+            return None;
+        }
+        let share = weight as f64 / total as f64;
+        if share < self.min_share {
+            return None;
+        }
+
+        let mut by_function: HashMap<&str, u64> = HashMap::new();
+        for (_, func, w) in &hits {
+            *by_function.entry(func).or_default() += w;
+        }
+        let mut by_function: Vec<(&str, u64)> = by_function.into_iter().collect();
+        by_function.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let functions = by_function
+            .into_iter()
+            .take(options.max_evidence)
+            .map(|(f, _)| f.to_string())
+            .collect();
+
+        let stacks = hits.len();
+        hits.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.id.cmp(&b.0.id)));
+        let evidence = hits
+            .into_iter()
+            .take(options.max_evidence)
+            .map(|(stack, matched, weight)| Evidence {
+                stack_id: stack.id.clone(),
+                functions: root_to_leaf(file, stack),
+                matched,
+                weight,
+                share: weight as f64 / total as f64,
+            })
+            .collect();
+
+        Some(Finding {
+            rule: self.id.clone(),
+            title: self.title.clone(),
+            advice: self.advice.clone(),
+            severity: if share >= self.critical_share {
+                Severity::Critical
+            } else {
+                Severity::Warning
+            },
+            event: event.to_string(),
+            metric: metric.to_string(),
+            weight,
+            share,
+            stacks,
+            functions,
+            evidence,
+        })
+    }
+}
+
+impl Detector for Rule {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn detect(&self, file: &SpaaFile, event: &EventDef, options: &DetectOptions) -> Vec<Finding> {
+        if event.kind == EventKind::Allocation || event.allocation_tracking.is_some() {
+            return Vec::new();
+        }
+        if self
+            .events
+            .as_ref()
+            .is_some_and(|e| !e.is_match(&event.name))
+        {
+            return Vec::new();
+        }
+        self.evaluate(file, &event.name, &event.sampling.primary_metric, options)
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Options for [`detect`].
+#[derive(Debug, Clone)]
+pub struct DetectOptions {
+    /// Evidence stacks (and matched functions) kept per finding.
+    pub max_evidence: usize,
+}
+
+impl Default for DetectOptions {
+    fn default() -> Self {
+        Self { max_evidence: 3 }
+    }
+}
+
+/// A rule that fired on one event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    /// [`Rule::id`] of the rule that fired.
+    pub rule: String,
+    pub title: String,
+    pub advice: String,
+    pub severity: Severity,
+    pub event: String,
+    pub metric: String,
+    /// Weight of the matching stacks.
+    pub weight: u64,
+    /// `weight` as a fraction of the event's total weight.
+    pub share: f64,
+    /// Number of matching stacks.
+    pub stacks: usize,
+    /// Matched functions, heaviest first.
+    pub functions: Vec<String>,
+    /// Heaviest matching stacks.
+    pub evidence: Vec<Evidence>,
+}
+
+/// A stack supporting a [`Finding`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Evidence {
+    pub stack_id: String,
+    /// Function names from the outermost caller down to the leaf.
+    pub functions: Vec<String>,
+    /// Function that matched the rule's pattern.
+    pub matched: String,
+    pub weight: u64,
+    /// `weight` as a fraction of the event's total weight.
+    pub share: f64,
+}
+
+/// Run `detectors` against every event in `file`.
+///
+/// Findings are ordered by severity, then by descending share.
+pub fn detect(
+    file: &SpaaFile,
+    detectors: &[Box<dyn Detector>],
+    options: &DetectOptions,
+) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = file
+        .header
+        .events
+        .iter()
+        .flat_map(|event| {
+            detectors
+                .iter()
+                .flat_map(move |detector| detector.detect(file, event, options))
+        })
+        .collect();
+    findings.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then(b.share.total_cmp(&a.share))
+            .then(a.rule.cmp(&b.rule))
+    });
+    findings
+}
+
+/// Function names of `stack`, outermost caller first.
+fn root_to_leaf(file: &SpaaFile, stack: &Stack) -> Vec<String> {
+    let mut functions: Vec<String> = stack
+        .frames
+        .iter()
+        .map(|&id| {
+            file.resolve_frame(id)
+                .map_or_else(|| format!("<frame {}>", id), |f| f.func.clone())
+        })
+        .collect();
+    if file.header.frame_order == FrameOrder::LeafToRoot {
+        functions.reverse();
+    }
+    functions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}},{"name":"malloc","kind":"allocation","sampling":{"mode":"event","primary_metric":"alloc_bytes"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"worker","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"pthread_spin_lock","dso":1}"#,
+            r#"{"type":"frame","id":4,"func":"__memcpy_avx_unaligned_erms","dso":1}"#,
+            r#"{"type":"frame","id":5,"func":"compute","dso":1}"#,
+            r#"{"type":"frame","id":6,"func":"malloc","dso":1}"#,
+            // Spinning: 50 of 100.
+            r#"{"type":"stack","id":"0x1","frames":[3,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":40}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[3,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":10}]}"#,
+            // Copying: 8 of 100.
+            r#"{"type":"stack","id":"0x3","frames":[4,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":8}]}"#,
+            r#"{"type":"stack","id":"0x4","frames":[5,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":42}]}"#,
+            r#"{"type":"stack","id":"0x5","frames":[6,2,1],"context":{"event":"malloc"},"weights":[{"metric":"alloc_bytes","value":4096}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    fn findings() -> Vec<Finding> {
+        detect(
+            &sample_file(),
+            &builtin_detectors(),
+            &DetectOptions::default(),
+        )
+    }
+
+    #[test]
+    fn spin_loop_reports_share_and_evidence() {
+        let findings = findings();
+        let spin = findings.iter().find(|f| f.rule == "spin-loop").unwrap();
+
+        assert_eq!(spin.event, "cycles");
+        assert_eq!(spin.weight, 50);
+        assert_eq!(spin.stacks, 2);
+        assert_eq!(spin.severity, Severity::Critical);
+        assert_eq!(spin.functions, vec!["pthread_spin_lock"]);
+        assert_eq!(spin.evidence[0].stack_id, "0x1");
+        assert_eq!(
+            spin.evidence[0].functions,
+            vec!["main", "worker", "pthread_spin_lock"]
+        );
+    }
+
+    #[test]
+    fn share_below_threshold_is_not_reported() {
+        // memcpy sits at 8%, under the 10% threshold.
+        assert!(findings().iter().all(|f| f.rule != "memcpy"));
+    }
+
+    #[test]
+    fn allocation_events_are_skipped() {
+        assert!(findings().iter().all(|f| f.event != "malloc"));
+    }
+
+    #[test]
+    fn leaf_scope_ignores_callers() {
+        let rule = Rule {
+            id: "worker".to_string(),
+            title: "Worker".to_string(),
+            advice: String::new(),
+            pattern: Regex::new("^worker$").unwrap(),
+            scope: MatchScope::Leaf,
+            min_share: 0.0,
+            critical_share: 1.0,
+            events: None,
+        };
+        let file = sample_file();
+
+        assert!(
+            rule.evaluate(&file, "cycles", "period", &DetectOptions::default())
+                .is_none()
+        );
+        let any = Rule {
+            scope: MatchScope::Any,
+            ..rule
+        };
+        let finding = any
+            .evaluate(&file, "cycles", "period", &DetectOptions::default())
+            .unwrap();
+        assert_eq!(finding.weight, 90);
+    }
+
+    #[test]
+    fn custom_detectors_see_every_event() {
+        struct PerEvent;
+        impl Detector for PerEvent {
+            fn id(&self) -> &str {
+                "per-event"
+            }
+            fn detect(&self, _: &SpaaFile, event: &EventDef, _: &DetectOptions) -> Vec<Finding> {
+                vec![Finding {
+                    rule: self.id().to_string(),
+                    title: String::new(),
+                    advice: String::new(),
+                    severity: Severity::Warning,
+                    event: event.name.clone(),
+                    metric: event.sampling.primary_metric.clone(),
+                    weight: 0,
+                    share: 0.0,
+                    stacks: 0,
+                    functions: Vec::new(),
+                    evidence: Vec::new(),
+                }]
+            }
+        }
+
+        let detectors: Vec<Box<dyn Detector>> = vec![Box::new(PerEvent)];
+        let findings = detect(&sample_file(), &detectors, &DetectOptions::default());
+        let events: Vec<&str> = findings.iter().map(|f| f.event.as_str()).collect();
+        assert_eq!(events, vec!["cycles", "malloc"]);
+    }
+
+    #[test]
+    fn event_filter_restricts_rule() {
+        let mut rules = builtin_rules();
+        rules.retain(|r| r.id == "spin-loop");
+        rules[0].events = Some(Regex::new("^cpu-clock$").unwrap());
+        let detectors: Vec<Box<dyn Detector>> =
+            rules.into_iter().map(|r| Box::new(r) as _).collect();
+
+        assert!(detect(&sample_file(), &detectors, &DetectOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn builtin_patterns_match_known_symbols() {
+        let rules = builtin_rules();
+        let matches = |id: &str, func: &str| {
+            rules
+                .iter()
+                .find(|r| r.id == id)
+                .unwrap()
+                .pattern
+                .is_match(func)
+        };
+
+        assert!(matches("spin-loop", "native_queued_spin_lock_slowpath"));
+        assert!(matches("memcpy", "__memmove_avx_unaligned_erms"));
+        assert!(matches("allocator", "_int_malloc"));
+        assert!(matches("page-faults", "asm_exc_page_fault"));
+        assert!(matches("regex-backtracking", "pcre2_match_8"));
+        assert!(matches("logging", "log::__private_api::log"));
+        assert!(!matches("logging", "login_user"));
+        assert!(!matches("allocator", "malloc_stats_report"));
+    }
+}
@@ -0,0 +1,209 @@
+//! Rule definitions loaded from TOML or JSON.
+//!
+//! A rule file holds a list of `rule` entries:
+//!
+//! ```toml
+//! [[rule]]
+//! id = "protobuf-encode"
+//! title = "Protobuf encoding on a hot path"
+//! advice = "Cache encoded messages or move encoding off the request path."
+//! pattern = "^prost::encoding::"
+//! scope = "any"          # or "leaf"; defaults to "any"
+//! min_share = 0.05
+//! critical_share = 0.2   # defaults to three times min_share
+//! events = "^(cycles|cpu-clock)$"
+//! ```
+//!
+//! The JSON form is the same object: `{"rule": [{"id": ..., ...}]}`.
+
+use super::{MatchScope, Rule};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+use thiserror::Error;
+
+/// Errors from loading rule definitions.
+#[derive(Error, Debug)]
+pub enum RuleError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("TOML parse error: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("JSON parse error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("invalid pattern in rule '{id}': {source}")]
+    Pattern { id: String, source: regex::Error },
+
+    #[error("invalid rule '{id}': {message}")]
+    Invalid { id: String, message: String },
+}
+
+/// One rule as written in a rule file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleDef {
+    pub id: String,
+    /// Defaults to `id`.
+    pub title: Option<String>,
+    #[serde(default)]
+    pub advice: String,
+    /// Regular expression matched against function names.
+    pub pattern: String,
+    #[serde(default = "default_scope")]
+    pub scope: MatchScope,
+    pub min_share: f64,
+    /// Defaults to three times `min_share`.
+    pub critical_share: Option<f64>,
+    /// Regular expression restricting the rule to matching event names.
+    pub events: Option<String>,
+}
+
+fn default_scope() -> MatchScope {
+    MatchScope::Any
+}
+
+impl RuleDef {
+    /// Validate the thresholds and compile the patterns.
+    pub fn compile(self) -> Result<Rule, RuleError> {
+        let invalid = |message: &str| RuleError::Invalid {
+            id: self.id.clone(),
+            message: message.to_string(),
+        };
+        if self.id.is_empty() {
+            return Err(invalid("id is empty"));
+        }
+        if !(0.0..=1.0).contains(&self.min_share) {
+            return Err(invalid("min_share must be between 0 and 1"));
+        }
+        let critical_share = self.critical_share.unwrap_or(self.min_share * 3.0);
+        if critical_share < self.min_share {
+            return Err(invalid("critical_share is below min_share"));
+        }
+
+        let compile = |pattern: &str| {
+            Regex::new(pattern).map_err(|source| RuleError::Pattern {
+                id: self.id.clone(),
+                source,
+            })
+        };
+        let pattern = compile(&self.pattern)?;
+        let events = self.events.as_deref().map(compile).transpose()?;
+        Ok(Rule {
+            title: self.title.unwrap_or_else(|| self.id.clone()),
+            id: self.id,
+            advice: self.advice,
+            pattern,
+            scope: self.scope,
+            min_share: self.min_share,
+            critical_share,
+            events,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    #[serde(default)]
+    rule: Vec<RuleDef>,
+}
+
+/// Parse and compile rules from TOML.
+pub fn rules_from_toml(s: &str) -> Result<Vec<Rule>, RuleError> {
+    compile_all(toml::from_str::<RuleFile>(s)?.rule)
+}
+
+/// Parse and compile rules from JSON.
+pub fn rules_from_json(s: &str) -> Result<Vec<Rule>, RuleError> {
+    compile_all(serde_json::from_str::<RuleFile>(s)?.rule)
+}
+
+/// Load rules from a file: JSON if the extension is `.json`, TOML otherwise.
+pub fn load_rules<P: AsRef<Path>>(path: P) -> Result<Vec<Rule>, RuleError> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)?;
+    if path.extension().is_some_and(|e| e == "json") {
+        rules_from_json(&text)
+    } else {
+        rules_from_toml(&text)
+    }
+}
+
+fn compile_all(defs: Vec<RuleDef>) -> Result<Vec<Rule>, RuleError> {
+    let mut seen = HashSet::new();
+    defs.into_iter()
+        .map(|def| {
+            if !seen.insert(def.id.clone()) {
+                return Err(RuleError::Invalid {
+                    id: def.id,
+                    message: "defined more than once".to_string(),
+                });
+            }
+            def.compile()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toml_rules_compile_with_defaults() {
+        let rules = rules_from_toml(
+            r#"
+            [[rule]]
+            id = "protobuf"
+            pattern = "^prost::"
+            min_share = 0.05
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].title, "protobuf");
+        assert_eq!(rules[0].scope, MatchScope::Any);
+        assert!((rules[0].critical_share - 0.15).abs() < 1e-9);
+        assert!(rules[0].pattern.is_match("prost::encoding::encode"));
+    }
+
+    #[test]
+    fn json_rules_accept_the_same_fields() {
+        let rules = rules_from_json(
+            r#"{"rule":[{"id":"gc","pattern":"GC","scope":"leaf","min_share":0.1,"critical_share":0.5,"events":"^cpu"}]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(rules[0].scope, MatchScope::Leaf);
+        assert!(rules[0].events.as_ref().unwrap().is_match("cpu-clock"));
+    }
+
+    #[test]
+    fn invalid_pattern_names_the_rule() {
+        let err = rules_from_toml(
+            r#"
+            [[rule]]
+            id = "broken"
+            pattern = "(unclosed"
+            min_share = 0.1
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, RuleError::Pattern { ref id, .. } if id == "broken"));
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let err = rules_from_json(
+            r#"{"rule":[{"id":"a","pattern":"x","min_share":0.1},{"id":"a","pattern":"y","min_share":0.1}]}"#,
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, RuleError::Invalid { .. }));
+    }
+}
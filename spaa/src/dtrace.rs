@@ -8,6 +8,16 @@
 //! Currently supported:
 //! - Aggregated stacks: `@[ustack()] = count();` or `@[stack()] = count();`
 //!
+//! A periodic capture such as `tick-10s { printa(@); trunc(@); }` written
+//! to one file per interval converts to a single profile with a `window`
+//! record per interval; see [`dump_intervals`] and
+//! [`DtraceConverter::parse_interval`].
+//!
+//! System-wide captures keyed by process, such as
+//! `@[execname, ustack()] = count();` or `@[execname, pid, ustack()]`, keep
+//! each process's stacks apart when [`ConverterConfig::execname_key`] is
+//! set: the key line becomes the stack's `execname` and `pid` context.
+//!
 //! Planned:
 //! - Split user/kernel stacks: `@[ustack(), stack()] = count();`
 //! - Per-probe output with timestamps
@@ -27,14 +37,18 @@
 //! converter.write_spaa(output).unwrap();
 //! ```
 
-use serde::Serialize;
+use crate::convert::Fidelity;
 use spaa_parse::{
-    EventDef, EventKind, ExclusiveWeights, FrameKind, FrameOrder, Header, Sampling, SamplingMode,
-    StackContext, StackIdMode, StackType, Weight,
+    ClockInfo, ClockSource, Dso, EventDef, EventKind, ExclusiveWeights, Frame, FrameKind,
+    FrameOrder, Header, Sampling, SamplingMode, SpaaFile, Stack, StackContext, StackIdMode,
+    StackType, TRUNCATED_FRAME_NAME, TimeRange, Weight, Window, WindowStackWeight, WriteError,
+    truncate_frames,
 };
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::hash::{Hash, Hasher};
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 use thiserror::Error;
 
 /// Errors that can occur during DTrace output parsing.
@@ -46,6 +60,9 @@ pub enum ConvertError {
     #[error("JSON serialization error: {0}")]
     Json(#[from] serde_json::Error),
 
+    #[error("SPAA write error: {0}")]
+    Write(#[from] WriteError),
+
     #[error("parse error at line {line}: {message}")]
     Parse { line: usize, message: String },
 
@@ -89,6 +106,11 @@ struct DtraceStack {
     frames: Vec<DtraceFrame>,
     count: u64,
     kind: StackKind,
+    /// Process name and ID from the record's key line, if keyed.
+    execname: Option<String>,
+    pid: Option<u64>,
+    /// Index of the interval the stack was captured in, if any.
+    window: Option<usize>,
     /// For split stacks, the related stack (user/kernel pair).
     /// Reserved for future SplitStacks format support.
     #[allow(dead_code)]
@@ -103,6 +125,14 @@ struct DtraceFrame {
     offset: Option<String>,
 }
 
+/// Time span covered by one dump of a periodic capture, in seconds since
+/// the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub start: f64,
+    pub end: f64,
+}
+
 /// Configuration for the converter.
 #[derive(Debug, Clone)]
 pub struct ConverterConfig {
@@ -110,6 +140,15 @@ pub struct ConverterConfig {
     pub event_name: String,
     /// Sampling frequency if known (for profile-N provider).
     pub frequency_hz: Option<u64>,
+    /// Maximum number of frames to keep per stack. Deeper stacks keep their
+    /// leaf-most frames followed by a synthetic `[truncated]` frame.
+    pub max_stack_depth: Option<usize>,
+    /// Each record starts with an `execname` key line, optionally followed
+    /// by a pid, as printed for `@[execname, ustack()]` aggregations.
+    pub execname_key: bool,
+    /// Fail on unparsable lines and on stacks or counts missing their
+    /// other half, instead of skipping them.
+    pub strict: bool,
 }
 
 impl Default for ConverterConfig {
@@ -117,6 +156,9 @@ impl Default for ConverterConfig {
         Self {
             event_name: "profile-997".to_string(),
             frequency_hz: Some(997),
+            max_stack_depth: None,
+            execname_key: false,
+            strict: false,
         }
     }
 }
@@ -126,6 +168,9 @@ pub struct DtraceConverter {
     format: InputFormat,
     config: ConverterConfig,
     stacks: Vec<DtraceStack>,
+    intervals: Vec<Interval>,
+    truncated_stacks: u64,
+    fidelity: Fidelity,
 }
 
 impl DtraceConverter {
@@ -135,6 +180,9 @@ impl DtraceConverter {
             format,
             config: ConverterConfig::default(),
             stacks: Vec::new(),
+            intervals: Vec::new(),
+            truncated_stacks: 0,
+            fidelity: Fidelity::default(),
         }
     }
 
@@ -144,9 +192,17 @@ impl DtraceConverter {
             format,
             config,
             stacks: Vec::new(),
+            intervals: Vec::new(),
+            truncated_stacks: 0,
+            fidelity: Fidelity::default(),
         }
     }
 
+    /// Number of stacks truncated because of `ConverterConfig::max_stack_depth`.
+    pub fn truncated_stacks(&self) -> u64 {
+        self.truncated_stacks
+    }
+
     /// Parse DTrace output from a reader.
     pub fn parse<R: Read>(&mut self, reader: R) -> Result<()> {
         match self.format {
@@ -156,14 +212,31 @@ impl DtraceConverter {
         }
     }
 
+    /// Parse one dump of a periodic capture. Each interval becomes a
+    /// `window` record in the output, and stacks are also aggregated across
+    /// intervals as usual.
+    pub fn parse_interval<R: Read>(&mut self, reader: R, interval: Interval) -> Result<()> {
+        let first = self.stacks.len();
+        self.parse(reader)?;
+        let window = self.intervals.len();
+        self.intervals.push(interval);
+        for stack in &mut self.stacks[first..] {
+            stack.window = Some(window);
+        }
+        Ok(())
+    }
+
     /// Parse aggregated stack format.
     fn parse_aggregated<R: Read>(&mut self, reader: R) -> Result<()> {
         let buf_reader = BufReader::new(reader);
         let mut current_frames: Vec<DtraceFrame> = Vec::new();
+        let mut current_key: Option<(String, Option<u64>)> = None;
+        let mut last_line = 0;
 
-        for line_result in buf_reader.lines() {
+        for (line_idx, line_result) in buf_reader.lines().enumerate() {
             let line = line_result?;
             let trimmed = line.trim();
+            self.fidelity.records_read += 1;
 
             // Skip empty lines - they separate stacks
             if trimmed.is_empty() {
@@ -173,14 +246,37 @@ impl DtraceConverter {
 
             // Check if this is a count line (just a number)
             if let Ok(count) = trimmed.parse::<u64>() {
-                if !current_frames.is_empty() {
+                let key = current_key.take();
+                self.fidelity.samples_read += 1;
+                if current_frames.is_empty() {
+                    self.skip(line_idx + 1, "count without a stack")?;
+                } else {
                     // Determine stack kind from frames
                     let kind = Self::infer_stack_kind(&current_frames);
 
+                    if let Some(max_depth) = self.config.max_stack_depth {
+                        let sentinel = DtraceFrame {
+                            module: TRUNCATED_FRAME_NAME.to_string(),
+                            symbol: TRUNCATED_FRAME_NAME.to_string(),
+                            offset: None,
+                        };
+                        if truncate_frames(
+                            &mut current_frames,
+                            max_depth,
+                            FrameOrder::LeafToRoot,
+                            sentinel,
+                        ) {
+                            self.truncated_stacks += 1;
+                        }
+                    }
+
                     self.stacks.push(DtraceStack {
                         frames: std::mem::take(&mut current_frames),
                         count,
                         kind,
+                        execname: key.as_ref().map(|(name, _)| name.clone()),
+                        pid: key.and_then(|(_, pid)| pid),
+                        window: None,
                         related: None,
                     });
                 }
@@ -196,21 +292,58 @@ impl DtraceConverter {
                 continue;
             }
 
+            // The first line of a keyed record names the process
+            if self.config.execname_key && current_key.is_none() && current_frames.is_empty() {
+                current_key = Some(Self::parse_key(trimmed));
+                continue;
+            }
+
             // Parse as a frame
-            if let Some(frame) = Self::parse_frame(trimmed) {
-                current_frames.push(frame);
+            match Self::parse_frame(trimmed) {
+                Some(frame) => current_frames.push(frame),
+                None if self.config.strict => {
+                    return Err(ConvertError::Parse {
+                        line: line_idx + 1,
+                        message: format!("unparsable line '{}'", trimmed),
+                    });
+                }
+                None => self.fidelity.unparsed(line_idx + 1, &line),
             }
+            last_line = line_idx + 1;
         }
 
-        // Handle case where last stack doesn't have trailing newline
+        // A last stack without a count shouldn't happen in well-formed
+        // output, but we'll skip it rather than error
         if !current_frames.is_empty() {
-            // No count found - this shouldn't happen in well-formed output
-            // but we'll skip it rather than error
+            self.fidelity.samples_read += 1;
+            self.skip(last_line, "stack without a count")?;
         }
 
         Ok(())
     }
 
+    /// Drop a sample at `line`, or fail with `reason` in strict mode.
+    fn skip(&mut self, line: usize, reason: &str) -> Result<()> {
+        if self.config.strict {
+            return Err(ConvertError::Parse {
+                line,
+                message: reason.to_string(),
+            });
+        }
+        self.fidelity.samples_dropped += 1;
+        Ok(())
+    }
+
+    /// Parse an `execname [pid]` key line.
+    fn parse_key(line: &str) -> (String, Option<u64>) {
+        if let Some((name, pid)) = line.rsplit_once(char::is_whitespace)
+            && let Ok(pid) = pid.parse()
+        {
+            return (name.trim_end().to_string(), Some(pid));
+        }
+        (line.to_string(), None)
+    }
+
     /// Parse a single frame line.
     /// Format: `module`symbol+offset` or `module`symbol` or just `symbol+offset`
     fn parse_frame(line: &str) -> Option<DtraceFrame> {
@@ -280,8 +413,8 @@ impl DtraceConverter {
         StackKind::Unknown
     }
 
-    /// Write the parsed data as SPAA format to a writer.
-    pub fn write_spaa<W: Write>(&self, mut writer: W) -> Result<()> {
+    /// Build the SPAA profile in memory.
+    pub fn to_spaa_file(&self) -> Result<SpaaFile> {
         if self.stacks.is_empty() {
             return Err(ConvertError::NoStacks);
         }
@@ -289,61 +422,59 @@ impl DtraceConverter {
         // Build dictionaries
         let mut dso_map: HashMap<&str, u64> = HashMap::new();
         let mut frame_map: HashMap<&DtraceFrame, u64> = HashMap::new();
+        let mut dsos = HashMap::new();
+        let mut frames = HashMap::new();
 
-        // Collect unique DSOs and frames
+        // Collect unique DSOs and frames; IDs follow first appearance
         for stack in &self.stacks {
             for frame in &stack.frames {
-                if !dso_map.contains_key(frame.module.as_str()) {
-                    let id = dso_map.len() as u64 + 1;
-                    dso_map.insert(&frame.module, id);
-                }
+                let dso_id = match dso_map.get(frame.module.as_str()) {
+                    Some(&id) => id,
+                    None => {
+                        let id = dso_map.len() as u64 + 1;
+                        dso_map.insert(&frame.module, id);
+                        dsos.insert(
+                            id,
+                            Dso {
+                                id,
+                                name: frame.module.clone(),
+                                build_id: None,
+                                is_kernel: Self::is_kernel_module(&frame.module),
+                            },
+                        );
+                        id
+                    }
+                };
                 if !frame_map.contains_key(frame) {
                     let id = frame_map.len() as u64 + 1;
                     frame_map.insert(frame, id);
+                    frames.insert(
+                        id,
+                        Frame {
+                            id,
+                            func: frame.symbol.clone(),
+                            dso: dso_id,
+                            func_resolved: !frame.symbol.starts_with("0x"),
+                            ip: None,
+                            symoff: frame.offset.clone(),
+                            srcline: None,
+                            srcline_resolved: true,
+                            inlined: false,
+                            inline_depth: None,
+                            kind: if Self::is_kernel_module(&frame.module) {
+                                FrameKind::Kernel
+                            } else {
+                                FrameKind::User
+                            },
+                        },
+                    );
                 }
             }
         }
 
-        // Write header
-        let header = self.build_header();
-        self.write_record(&mut writer, "header", &header)?;
-
-        // Write DSO dictionary
-        for (dso_name, dso_id) in &dso_map {
-            let is_kernel = Self::is_kernel_module(dso_name);
-            let dso = DsoRecord {
-                id: *dso_id,
-                name: (*dso_name).to_string(),
-                build_id: None,
-                is_kernel,
-            };
-            self.write_record(&mut writer, "dso", &dso)?;
-        }
-
-        // Write frame dictionary
-        for (dtrace_frame, frame_id) in &frame_map {
-            let dso_id = dso_map[dtrace_frame.module.as_str()];
-            let is_kernel = Self::is_kernel_module(&dtrace_frame.module);
-            let frame = FrameRecord {
-                id: *frame_id,
-                func: dtrace_frame.symbol.clone(),
-                func_resolved: !dtrace_frame.symbol.starts_with("0x"),
-                dso: dso_id,
-                ip: None,
-                symoff: dtrace_frame.offset.clone(),
-                srcline: None,
-                inlined: false,
-                kind: if is_kernel {
-                    FrameKind::Kernel
-                } else {
-                    FrameKind::User
-                },
-            };
-            self.write_record(&mut writer, "frame", &frame)?;
-        }
-
-        // Write stacks (aggregated - each unique stack becomes one record)
+        // Aggregated stacks - each unique stack becomes one record
         let aggregated = self.aggregate_stacks(&frame_map);
+        let mut stacks = HashMap::new();
         for (stack_key, stack_data) in &aggregated {
             let stack_type = match stack_data.kind {
                 StackKind::User => StackType::User,
@@ -351,35 +482,27 @@ impl DtraceConverter {
                 StackKind::Unknown => StackType::Unified,
             };
 
-            let stack = StackRecord {
+            let stack = Stack {
                 id: stack_key.id.clone(),
                 frames: stack_key.frame_ids.clone(),
                 stack_type,
                 context: StackContext {
                     event: self.config.event_name.clone(),
-                    pid: None,
+                    pid: stack_key.pid,
                     tid: None,
                     cpu: None,
                     comm: None,
                     probe: None,
-                    execname: None,
+                    execname: stack_key.execname.clone(),
                     uid: None,
                     zonename: None,
+                    cgroup: None,
+                    container_id: None,
+                    k8s_pod: None,
                     trace_fields: None,
                     extra: HashMap::new(),
                 },
-                weights: vec![
-                    Weight {
-                        metric: "samples".to_string(),
-                        value: stack_data.total_count,
-                        unit: None,
-                    },
-                    Weight {
-                        metric: "count".to_string(),
-                        value: stack_data.total_count,
-                        unit: None,
-                    },
-                ],
+                weights: Self::count_weights(stack_data.total_count),
                 exclusive: stack_key.frame_ids.first().map(|&leaf| ExclusiveWeights {
                     frame: leaf,
                     weights: vec![Weight {
@@ -390,12 +513,67 @@ impl DtraceConverter {
                 }),
                 related_stacks: None,
             };
-            self.write_record(&mut writer, "stack", &stack)?;
+            stacks.insert(stack.id.clone(), stack);
         }
 
+        // One window per interval of a periodic capture
+        let windows = self
+            .intervals
+            .iter()
+            .enumerate()
+            .map(|(index, interval)| Window {
+                id: format!("w{}", index + 1),
+                start: interval.start,
+                end: interval.end,
+                unit: "seconds".to_string(),
+                by_stack: aggregated
+                    .iter()
+                    .filter_map(|(key, data)| {
+                        Some(WindowStackWeight {
+                            stack_id: key.id.clone(),
+                            weights: Self::count_weights(*data.by_window.get(&index)?),
+                        })
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Ok(SpaaFile {
+            header: self.build_header(),
+            system: None,
+            dsos,
+            frames,
+            threads: HashMap::new(),
+            stacks,
+            samples: Vec::new(),
+            windows,
+            states: Vec::new(),
+            notes: Vec::new(),
+        })
+    }
+
+    /// Write the parsed data as SPAA format to a writer.
+    pub fn write_spaa<W: Write>(&self, writer: W) -> Result<()> {
+        self.to_spaa_file()?.write(writer)?;
         Ok(())
     }
 
+    /// `samples` and `count` weights of `count` probe firings.
+    fn count_weights(count: u64) -> Vec<Weight> {
+        vec![
+            Weight {
+                metric: "samples".to_string(),
+                value: count,
+                unit: None,
+            },
+            Weight {
+                metric: "count".to_string(),
+                value: count,
+                unit: None,
+            },
+        ]
+    }
+
     fn is_kernel_module(module: &str) -> bool {
         let module_lower = module.to_lowercase();
         module_lower.contains("kernel")
@@ -430,6 +608,8 @@ impl DtraceConverter {
             },
             sampling,
             allocation_tracking: None,
+            description: None,
+            unit: None,
         };
 
         Header {
@@ -438,21 +618,37 @@ impl DtraceConverter {
             source_tool: "dtrace".to_string(),
             frame_order: FrameOrder::LeafToRoot,
             events: vec![event],
-            time_range: None,
+            time_range: self.time_range(),
             source: Some(spaa_parse::SourceInfo {
                 tool: "dtrace".to_string(),
                 command: None,
                 tool_version: None,
+                data_loss: None,
+                build: BTreeMap::new(),
             }),
             stack_id_mode: StackIdMode::ContentAddressable,
+            // Dump timestamps are Unix time
+            clock: (!self.intervals.is_empty()).then(|| ClockInfo::new(ClockSource::Wall)),
+            approximation: None,
         }
     }
 
+    /// Span of all parsed intervals, if any.
+    fn time_range(&self) -> Option<TimeRange> {
+        let start = self.intervals.iter().map(|i| i.start).reduce(f64::min)?;
+        let end = self.intervals.iter().map(|i| i.end).reduce(f64::max)?;
+        Some(TimeRange {
+            start,
+            end,
+            unit: "seconds".to_string(),
+        })
+    }
+
     fn aggregate_stacks(
         &self,
         frame_map: &HashMap<&DtraceFrame, u64>,
-    ) -> HashMap<StackKey, StackData> {
-        let mut aggregated: HashMap<StackKey, StackData> = HashMap::new();
+    ) -> BTreeMap<StackKey, StackData> {
+        let mut aggregated: BTreeMap<StackKey, StackData> = BTreeMap::new();
 
         for stack in &self.stacks {
             let frame_ids: Vec<u64> = stack.frames.iter().map(|f| frame_map[f]).collect();
@@ -461,96 +657,145 @@ impl DtraceConverter {
                 continue;
             }
 
-            let stack_id = Self::compute_stack_id(&frame_ids);
+            let stack_id = Self::compute_stack_id(&frame_ids, stack);
             let key = StackKey {
                 id: stack_id,
                 frame_ids,
+                execname: stack.execname.clone(),
+                pid: stack.pid,
             };
 
             let data = aggregated.entry(key).or_insert(StackData {
                 total_count: 0,
                 kind: stack.kind,
+                by_window: BTreeMap::new(),
             });
             data.total_count += stack.count;
+            if let Some(window) = stack.window {
+                *data.by_window.entry(window).or_default() += stack.count;
+            }
         }
 
         aggregated
     }
 
-    fn compute_stack_id(frame_ids: &[u64]) -> String {
+    fn compute_stack_id(frame_ids: &[u64], stack: &DtraceStack) -> String {
         use std::collections::hash_map::DefaultHasher;
         let mut hasher = DefaultHasher::new();
         frame_ids.hash(&mut hasher);
+        // Unkeyed stacks keep the IDs they had before keys were supported
+        if stack.execname.is_some() {
+            stack.execname.hash(&mut hasher);
+            stack.pid.hash(&mut hasher);
+        }
         format!("0x{:016x}", hasher.finish())
     }
+}
 
-    fn write_record<W: Write, T: Serialize>(
-        &self,
-        writer: &mut W,
-        record_type: &str,
-        data: &T,
-    ) -> Result<()> {
-        let mut map = serde_json::to_value(data)?;
-        if let serde_json::Value::Object(ref mut obj) = map {
-            obj.insert(
-                "type".to_string(),
-                serde_json::Value::String(record_type.to_string()),
-            );
+impl crate::convert::Converter for DtraceConverter {
+    fn name(&self) -> &str {
+        "dtrace"
+    }
+
+    /// DTrace stacks are indented `module`function` frames, each stack
+    /// followed by an indented count.
+    fn detect(&self, data: &[u8]) -> Option<crate::convert::Confidence> {
+        use crate::convert::Confidence;
+        let text = crate::convert::text_prefix(data);
+        let indented = || text.lines().filter(|l| l.starts_with([' ', '\t']));
+        if !indented().any(|l| l.contains('`')) {
+            return None;
         }
-        writeln!(writer, "{}", serde_json::to_string(&map)?)?;
-        Ok(())
+        let counted = indented().any(|l| l.trim().parse::<u64>().is_ok());
+        Some(if counted {
+            Confidence::High
+        } else {
+            Confidence::Medium
+        })
     }
-}
 
-// Serialization records
-#[derive(Serialize)]
-struct DsoRecord {
-    id: u64,
-    name: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    build_id: Option<String>,
-    is_kernel: bool,
+    fn parse(
+        &mut self,
+        reader: &mut dyn Read,
+    ) -> std::result::Result<(), crate::convert::ConvertError> {
+        Ok(DtraceConverter::parse(self, reader)?)
+    }
+
+    fn to_spaa_file(&self) -> std::result::Result<SpaaFile, crate::convert::ConvertError> {
+        Ok(DtraceConverter::to_spaa_file(self)?)
+    }
+
+    /// Stacks without a count, and counts without a stack, are dropped.
+    fn fidelity(&self) -> Option<Fidelity> {
+        Some(self.fidelity.clone())
+    }
 }
 
-#[derive(Serialize)]
-struct FrameRecord {
-    id: u64,
-    func: String,
-    func_resolved: bool,
-    dso: u64,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    ip: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    symoff: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    srcline: Option<String>,
-    inlined: bool,
-    kind: FrameKind,
+/// List the dumps of a periodic capture in `dir`, oldest first, with the
+/// interval each one covers.
+///
+/// A dump's timestamp is the first run of at least nine digits in its file
+/// name, read as Unix seconds (as in `dtrace-1700000000.out`), or else its
+/// modification time. Each dump covers the `interval` seconds before its
+/// timestamp if given, otherwise the time since the previous dump, with the
+/// first assumed as long as the second. Hidden files and subdirectories are
+/// ignored.
+pub fn dump_intervals(dir: &Path, interval: Option<f64>) -> io::Result<Vec<(PathBuf, Interval)>> {
+    let mut dumps = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type()?.is_file() || entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let timestamp = match name_timestamp(&path) {
+            Some(t) => t,
+            None => entry
+                .metadata()?
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |d| d.as_secs_f64()),
+        };
+        dumps.push((path, timestamp));
+    }
+    dumps.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+
+    let first_length = interval
+        .or_else(|| dumps.get(1).map(|d| d.1 - dumps[0].1))
+        .unwrap_or(0.0);
+    let mut previous = dumps.first().map_or(0.0, |d| d.1 - first_length);
+    Ok(dumps
+        .into_iter()
+        .map(|(path, end)| {
+            let start = interval.map_or(previous, |length| end - length);
+            previous = end;
+            (path, Interval { start, end })
+        })
+        .collect())
 }
 
-#[derive(Serialize)]
-struct StackRecord {
-    id: String,
-    frames: Vec<u64>,
-    stack_type: StackType,
-    context: StackContext,
-    weights: Vec<Weight>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    exclusive: Option<ExclusiveWeights>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    related_stacks: Option<Vec<String>>,
+/// Unix timestamp embedded in a dump's file name.
+fn name_timestamp(path: &Path) -> Option<f64> {
+    let name = path.file_name()?.to_str()?;
+    name.split(|c: char| !c.is_ascii_digit())
+        .find(|run| run.len() >= 9)
+        .and_then(|run| run.parse().ok())
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct StackKey {
     id: String,
     frame_ids: Vec<u64>,
+    execname: Option<String>,
+    pid: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
 struct StackData {
     total_count: u64,
     kind: StackKind,
+    /// Count per interval index, for periodic captures.
+    by_window: BTreeMap<usize, u64>,
 }
 
 #[cfg(test)]
@@ -610,6 +855,30 @@ mod tests {
         assert_eq!(converter.stacks[1].frames.len(), 3);
     }
 
+    #[test]
+    fn output_is_reproducible() {
+        let convert = || {
+            let mut converter = DtraceConverter::new(InputFormat::AggregatedStack);
+            converter.parse(Cursor::new(SAMPLE_DTRACE_OUTPUT)).unwrap();
+            let mut output = Vec::new();
+            converter.write_spaa(&mut output).unwrap();
+            output
+        };
+
+        assert_eq!(convert(), convert());
+    }
+
+    #[test]
+    fn in_memory_file_matches_written_output() {
+        let mut converter = DtraceConverter::new(InputFormat::AggregatedStack);
+        converter.parse(Cursor::new(SAMPLE_DTRACE_OUTPUT)).unwrap();
+        let mut output = Vec::new();
+        converter.write_spaa(&mut output).unwrap();
+
+        let written = spaa_parse::SpaaFile::parse_slice(&output).unwrap();
+        assert_eq!(converter.to_spaa_file().unwrap(), written);
+    }
+
     #[test]
     fn convert_to_spaa() {
         let cursor = Cursor::new(SAMPLE_DTRACE_OUTPUT);
@@ -717,6 +986,9 @@ mod tests {
         let config = ConverterConfig {
             event_name: "syscall::read:entry".to_string(),
             frequency_hz: None,
+            max_stack_depth: None,
+            execname_key: false,
+            strict: false,
         };
 
         let cursor = Cursor::new(SAMPLE_DTRACE_OUTPUT);
@@ -757,4 +1029,124 @@ CPU     ID                    FUNCTION:NAME
 
         assert!(matches!(result, Err(ConvertError::UnsupportedFormat)));
     }
+
+    #[test]
+    fn strict_mode_fails_on_a_stack_without_a_count() {
+        let input = "
+              libc`malloc+0x10
+              5
+
+              libc`free+0x4
+              app`main+0x20
+";
+        let config = ConverterConfig {
+            strict: true,
+            ..ConverterConfig::default()
+        };
+        let mut converter = DtraceConverter::with_config(InputFormat::AggregatedStack, config);
+
+        assert!(matches!(
+            converter.parse(Cursor::new(input)),
+            Err(ConvertError::Parse { line: 6, .. })
+        ));
+    }
+
+    #[test]
+    fn max_stack_depth_truncates_deep_stacks() {
+        let config = ConverterConfig {
+            max_stack_depth: Some(1),
+            ..ConverterConfig::default()
+        };
+        let cursor = Cursor::new(SAMPLE_DTRACE_OUTPUT);
+        let mut converter = DtraceConverter::with_config(InputFormat::AggregatedStack, config);
+        converter.parse(cursor).unwrap();
+
+        assert_eq!(converter.truncated_stacks(), 2);
+        assert_eq!(converter.stacks[0].frames.len(), 2);
+        assert_eq!(converter.stacks[0].frames[0].symbol, "malloc");
+        assert_eq!(converter.stacks[0].frames[1].symbol, TRUNCATED_FRAME_NAME);
+    }
+
+    #[test]
+    fn intervals_become_windows() {
+        let mut converter = DtraceConverter::new(InputFormat::AggregatedStack);
+        let first = Interval {
+            start: 0.0,
+            end: 10.0,
+        };
+        let second = Interval {
+            start: 10.0,
+            end: 20.0,
+        };
+        converter
+            .parse_interval(Cursor::new(SAMPLE_DTRACE_OUTPUT), first)
+            .unwrap();
+        converter
+            .parse_interval(Cursor::new("libc`malloc+0x10\n7\n"), second)
+            .unwrap();
+
+        let mut output = Vec::new();
+        converter.write_spaa(&mut output).unwrap();
+        let spaa = spaa_parse::SpaaFile::parse(Cursor::new(output)).unwrap();
+
+        assert_eq!(spaa.windows.len(), 2);
+        assert_eq!(spaa.windows[0].by_stack.len(), 2);
+        assert_eq!(spaa.windows[1].by_stack[0].weights[0].value, 7);
+        assert_eq!(spaa.header.time_range.unwrap().end, 20.0);
+    }
+
+    #[test]
+    fn dump_intervals_orders_by_name_timestamp() {
+        let dir = std::env::temp_dir().join(format!("spaa-dtrace-dumps-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["out-1700000020.txt", "out-1700000010.txt", ".hidden"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let dumps = dump_intervals(&dir, None).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(dumps.len(), 2);
+        assert!(dumps[0].0.ends_with("out-1700000010.txt"));
+        assert_eq!(
+            dumps[0].1,
+            Interval {
+                start: 1700000000.0,
+                end: 1700000010.0
+            }
+        );
+        assert_eq!(dumps[1].1.start, 1700000010.0);
+    }
+
+    #[test]
+    fn execname_keys_split_stacks_by_process() {
+        let input = r#"
+  myapp                                              1234
+              libc`malloc+0x10
+              40
+
+  other
+              libc`malloc+0x10
+              2
+"#;
+        let config = ConverterConfig {
+            execname_key: true,
+            ..ConverterConfig::default()
+        };
+        let mut converter = DtraceConverter::with_config(InputFormat::AggregatedStack, config);
+        converter.parse(Cursor::new(input)).unwrap();
+
+        let mut output = Vec::new();
+        converter.write_spaa(&mut output).unwrap();
+        let spaa = spaa_parse::SpaaFile::parse(Cursor::new(output)).unwrap();
+
+        assert_eq!(spaa.stacks.len(), 2);
+        let myapp = spaa
+            .stacks
+            .values()
+            .find(|s| s.context.execname.as_deref() == Some("myapp"))
+            .unwrap();
+        assert_eq!(myapp.context.pid, Some(1234));
+        assert_eq!(myapp.weights[0].value, 40);
+    }
 }
@@ -0,0 +1,314 @@
+//! Two-event flame graph comparison as a standalone SVG.
+//!
+//! [`write_compare_svg`] draws two events of one profile, such as `cycles`
+//! and `cache-misses`, so they can be compared without external tooling:
+//!
+//! - [`CompareMode::Overlay`] lays out one flame graph by the base event and
+//!   colors each frame by how its share of the other event compares to its
+//!   share of the base: red where the other event is over-represented, blue
+//!   where it is under-represented. Frames the other event only records
+//!   outside the base event's stacks are not drawn.
+//! - [`CompareMode::Paired`] stacks both flame graphs, base on top, with
+//!   each function in the same color in both.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::export::compare::{CompareOptions, write_compare_svg};
+//! use spaa_parse::SpaaFile;
+//! use std::fs::File;
+//!
+//! let spaa = SpaaFile::open("profile.spaa").unwrap();
+//! let options = CompareOptions::new("cycles", "cache-misses");
+//! write_compare_svg(&spaa, &options, File::create("compare.svg").unwrap()).unwrap();
+//! ```
+
+use super::d3_flamegraph::{FlameNode, flame_graph};
+use super::html::{
+    CHAR_WIDTH, CHART_WIDTH, MIN_FRAME_WIDTH, ROW_HEIGHT, depth, escape, flame_node, truncate,
+};
+use spaa_parse::SpaaFile;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use thiserror::Error;
+
+/// Height of the title and graph label rows.
+const LABEL_HEIGHT: f64 = 24.0;
+
+/// Ratios at or beyond this factor either way get the strongest color.
+const MAX_RATIO: f64 = 8.0;
+
+const STYLE: &str = "text{font:11px ui-monospace,monospace;pointer-events:none}\
+.label{font:bold 13px system-ui,sans-serif}rect{stroke:#fff;stroke-width:.5}";
+
+#[derive(Error, Debug)]
+pub enum CompareError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("event '{0}' not found in the header")]
+    UnknownEvent(String),
+
+    #[error("event '{0}' has no weight")]
+    NoWeight(String),
+}
+
+/// How the two events are drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompareMode {
+    /// One flame graph shaped by the base event, colored by the ratio of
+    /// the two events' shares.
+    #[default]
+    Overlay,
+    /// Both flame graphs, one above the other.
+    Paired,
+}
+
+/// Options for [`write_compare_svg`].
+#[derive(Debug, Clone, Default)]
+pub struct CompareOptions {
+    /// Event the overlay is shaped by, drawn first when paired.
+    pub base: String,
+    /// Event compared against the base.
+    pub other: String,
+    pub mode: CompareMode,
+    /// Title; defaults to `<base> vs <other>`.
+    pub title: Option<String>,
+}
+
+impl CompareOptions {
+    /// An overlay of `other` on `base`.
+    pub fn new(base: impl Into<String>, other: impl Into<String>) -> Self {
+        Self {
+            base: base.into(),
+            other: other.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Write the comparison of two events in `file` as an SVG document.
+pub fn write_compare_svg<W: Write>(
+    file: &SpaaFile,
+    options: &CompareOptions,
+    mut writer: W,
+) -> Result<(), CompareError> {
+    writer.write_all(render(file, options)?.as_bytes())?;
+    Ok(())
+}
+
+/// The SVG comparison of two events in `file`.
+pub fn render(file: &SpaaFile, options: &CompareOptions) -> Result<String, CompareError> {
+    let base = event_tree(file, &options.base)?;
+    let other = event_tree(file, &options.other)?;
+    let title = options
+        .title
+        .clone()
+        .unwrap_or_else(|| format!("{} vs {}", options.base, options.other));
+
+    let base_height = (depth(&base) as f64 + 1.0) * ROW_HEIGHT;
+    let height = match options.mode {
+        CompareMode::Overlay => LABEL_HEIGHT * 2.0 + base_height,
+        CompareMode::Paired => {
+            LABEL_HEIGHT * 3.0 + base_height + (depth(&other) as f64 + 1.0) * ROW_HEIGHT
+        }
+    };
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg viewBox=\"0 0 {} {}\" xmlns=\"http://www.w3.org/2000/svg\">\n<style>{}</style>",
+        CHART_WIDTH, height, STYLE
+    );
+    label(&mut svg, 0.0, &title);
+
+    match options.mode {
+        CompareMode::Overlay => {
+            label(
+                &mut svg,
+                LABEL_HEIGHT,
+                &format!(
+                    "width: {}; red: more {} than {}, blue: less",
+                    options.base, options.other, options.base
+                ),
+            );
+            let scale = CHART_WIDTH / base.value as f64;
+            let y = LABEL_HEIGHT * 2.0 + base_height - ROW_HEIGHT;
+            overlay_node(
+                &mut svg,
+                &base,
+                Some(&other),
+                (&base, &other),
+                0.0,
+                y,
+                scale,
+            );
+        }
+        CompareMode::Paired => {
+            let mut top = LABEL_HEIGHT;
+            for tree in [&base, &other] {
+                label(&mut svg, top, &format!("{} ({})", tree.name, tree.value));
+                let graph_height = (depth(tree) as f64 + 1.0) * ROW_HEIGHT;
+                let scale = CHART_WIDTH / tree.value as f64;
+                let y = top + LABEL_HEIGHT + graph_height - ROW_HEIGHT;
+                flame_node(&mut svg, tree, tree.value, 0.0, y, scale);
+                top += LABEL_HEIGHT + graph_height;
+            }
+        }
+    }
+    svg.push_str("</svg>\n");
+    Ok(svg)
+}
+
+/// The flame graph of `event`, weighted by its primary metric.
+fn event_tree(file: &SpaaFile, event: &str) -> Result<FlameNode, CompareError> {
+    let def = file
+        .header
+        .events
+        .iter()
+        .find(|e| e.name == event)
+        .ok_or_else(|| CompareError::UnknownEvent(event.to_string()))?;
+    let tree = flame_graph(file, event, &def.sampling.primary_metric);
+    if tree.value == 0 {
+        return Err(CompareError::NoWeight(event.to_string()));
+    }
+    Ok(tree)
+}
+
+fn label(svg: &mut String, top: f64, text: &str) {
+    let _ = writeln!(
+        svg,
+        "<text class=\"label\" x=\"3\" y=\"{:.1}\">{}</text>",
+        top + LABEL_HEIGHT - 7.0,
+        escape(text)
+    );
+}
+
+/// Draw `node` of the base tree and its children, colored against the
+/// matching node of the other tree. `roots` are the two trees' roots, for
+/// their totals.
+fn overlay_node(
+    svg: &mut String,
+    node: &FlameNode,
+    other: Option<&FlameNode>,
+    roots: (&FlameNode, &FlameNode),
+    x: f64,
+    y: f64,
+    scale: f64,
+) {
+    let width = node.value as f64 * scale;
+    if width < MIN_FRAME_WIDTH {
+        return;
+    }
+    let other_value = other.map_or(0, |o| o.value);
+    let base_share = node.value as f64 / roots.0.value as f64;
+    let other_share = other_value as f64 / roots.1.value as f64;
+    let ratio = other_share / base_share;
+    let _ = write!(
+        svg,
+        "<g><title>{} ({} {}, {} {}, ratio {:.2})</title><rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{}\" fill=\"{}\"/>",
+        escape(&node.name),
+        node.value,
+        escape(&roots.0.name),
+        other_value,
+        escape(&roots.1.name),
+        ratio,
+        x,
+        y,
+        width,
+        ROW_HEIGHT,
+        ratio_color(ratio)
+    );
+    let chars = ((width - 6.0) / CHAR_WIDTH) as usize;
+    if chars >= 3 {
+        let _ = write!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+            x + 3.0,
+            y + ROW_HEIGHT - 5.0,
+            escape(&truncate(&node.name, chars))
+        );
+    }
+    svg.push_str("</g>\n");
+
+    let mut child_x = x;
+    for child in &node.children {
+        // Children are sorted by name.
+        let matching = other.and_then(|o| {
+            o.children
+                .binary_search_by(|c| c.name.cmp(&child.name))
+                .ok()
+                .map(|i| &o.children[i])
+        });
+        overlay_node(svg, child, matching, roots, child_x, y - ROW_HEIGHT, scale);
+        child_x += child.value as f64 * scale;
+    }
+}
+
+/// Red for ratios above 1, blue below, near-white around 1, on a log
+/// scale that saturates at [`MAX_RATIO`].
+fn ratio_color(ratio: f64) -> String {
+    let t = (ratio.log2() / MAX_RATIO.log2()).clamp(-1.0, 1.0);
+    let (hue, t) = if t >= 0.0 { (0, t) } else { (220, -t) };
+    format!("hsl({},80%,{:.0}%)", hue, 95.0 - 45.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}},{"name":"cache-misses","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"compute","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"memcpy","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":900}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[3,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":100}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[2,1],"context":{"event":"cache-misses"},"weights":[{"metric":"period","value":10}]}"#,
+            r#"{"type":"stack","id":"0x4","frames":[3,1],"context":{"event":"cache-misses"},"weights":[{"metric":"period","value":90}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    fn fill_of(svg: &str, func: &str) -> String {
+        let start = svg.find(&format!("<title>{} (", func)).unwrap();
+        let fill = &svg[start..][svg[start..].find("fill=\"").unwrap() + 6..];
+        fill[..fill.find('"').unwrap()].to_string()
+    }
+
+    #[test]
+    fn overlay_colors_frames_by_their_share_of_the_other_event() {
+        let svg = render(
+            &sample_file(),
+            &CompareOptions::new("cycles", "cache-misses"),
+        )
+        .unwrap();
+
+        assert_eq!(fill_of(&svg, "memcpy"), ratio_color(9.0));
+        assert!(fill_of(&svg, "memcpy").starts_with("hsl(0,"));
+        assert!(fill_of(&svg, "compute").starts_with("hsl(220,"));
+        assert_eq!(fill_of(&svg, "main"), "hsl(0,80%,95%)");
+    }
+
+    #[test]
+    fn paired_mode_draws_both_events() {
+        let options = CompareOptions {
+            mode: CompareMode::Paired,
+            ..CompareOptions::new("cycles", "cache-misses")
+        };
+        let svg = render(&sample_file(), &options).unwrap();
+
+        assert!(svg.contains(">cycles (1000)</text>"));
+        assert!(svg.contains(">cache-misses (100)</text>"));
+        assert_eq!(svg.matches("<title>memcpy (").count(), 2);
+    }
+
+    #[test]
+    fn unknown_events_are_rejected() {
+        let err = render(&sample_file(), &CompareOptions::new("cycles", "branches")).unwrap_err();
+        assert!(matches!(err, CompareError::UnknownEvent(name) if name == "branches"));
+    }
+}
@@ -0,0 +1,127 @@
+//! d3-flame-graph JSON export.
+//!
+//! Emits the nested tree the [d3-flame-graph] component renders: each node
+//! has a `name`, an inclusive `value` and its `children`.
+//!
+//! ```json
+//! {"name":"cycles","value":450,"children":[{"name":"main","value":450,"children":[...]}]}
+//! ```
+//!
+//! The root is named after the event and holds its total weight. Children
+//! are sorted by name, and subtrees with no weight are left out.
+//!
+//! [d3-flame-graph]: https://github.com/spiermar/d3-flame-graph
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::export::d3_flamegraph::write_flame_graph;
+//! use spaa_parse::SpaaFile;
+//! use std::fs::File;
+//!
+//! let spaa = SpaaFile::parse(File::open("profile.spaa").unwrap()).unwrap();
+//! let out = File::create("cycles.json").unwrap();
+//! write_flame_graph(&spaa, "cycles", "period", out).unwrap();
+//! ```
+
+use crate::analysis::CallTree;
+use serde::Serialize;
+use spaa_parse::SpaaFile;
+use std::io::{self, Write};
+
+/// A node of a d3-flame-graph tree.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlameNode {
+    pub name: String,
+    /// Weight of every stack passing through this node.
+    pub value: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<FlameNode>,
+}
+
+/// The flame graph of `event`'s stacks, weighted by `metric`.
+pub fn flame_graph(file: &SpaaFile, event: &str, metric: &str) -> FlameNode {
+    let tree = CallTree::build(file, event, metric);
+    let mut root = node(file, &tree, 0);
+    root.name = event.to_string();
+    root
+}
+
+/// Write the flame graph of `event`'s stacks, weighted by `metric`, as
+/// JSON.
+pub fn write_flame_graph<W: Write>(
+    file: &SpaaFile,
+    event: &str,
+    metric: &str,
+    writer: W,
+) -> io::Result<()> {
+    serde_json::to_writer(writer, &flame_graph(file, event, metric))?;
+    Ok(())
+}
+
+fn node(file: &SpaaFile, tree: &CallTree, idx: usize) -> FlameNode {
+    let call_node = tree.node(idx);
+    let name = call_node.frame.map_or_else(String::new, |id| {
+        file.resolve_frame(id)
+            .map_or_else(|| format!("<frame {}>", id), |f| f.func.clone())
+    });
+    let mut children: Vec<FlameNode> = call_node
+        .children
+        .iter()
+        .map(|&child| node(file, tree, child))
+        .collect();
+    children.sort_by(|a, b| a.name.cmp(&b.name));
+    FlameNode {
+        name,
+        value: call_node.inclusive,
+        children,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"emit","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":300}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[3,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":100}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":50}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn tree_is_rooted_at_the_event_with_sorted_children() {
+        let root = flame_graph(&sample_file(), "cycles", "period");
+
+        assert_eq!(root.name, "cycles");
+        assert_eq!(root.value, 450);
+        let main = &root.children[0];
+        assert_eq!(main.name, "main");
+        let children: Vec<_> = main
+            .children
+            .iter()
+            .map(|c| (c.name.as_str(), c.value))
+            .collect();
+        assert_eq!(children, vec![("emit", 100), ("parse", 300)]);
+    }
+
+    #[test]
+    fn leaves_are_written_without_children() {
+        let mut out = Vec::new();
+        write_flame_graph(&sample_file(), "cycles", "period", &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        let leaf = &json["children"][0]["children"][0];
+        assert_eq!(leaf["name"], "emit");
+        assert!(leaf.get("children").is_none());
+    }
+}
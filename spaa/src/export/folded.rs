@@ -0,0 +1,407 @@
+//! Folded stack export.
+//!
+//! Each output line is a semicolon-separated call path from root to leaf
+//! followed by a space and a value:
+//!
+//! ```text
+//! main;parse;tokenize 1234
+//! ```
+//!
+//! Any metric can be emitted, as can the ratio of two metrics (for example
+//! `cache-misses/instructions`). A ratio operand that names another event
+//! rather than a metric of the event being folded stands for that event's
+//! primary metric on the same call path, so the ratio of two separately
+//! sampled events can be folded too. [`fold`] computes every requested
+//! value for every event in a single pass over the stacks.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::export::folded::{FoldedValue, fold};
+//! use spaa_parse::SpaaFile;
+//! use std::fs::File;
+//!
+//! let spaa = SpaaFile::parse(File::open("profile.spaa").unwrap()).unwrap();
+//! let values: Vec<FoldedValue> = ["period", "cache-misses/instructions"]
+//!     .iter()
+//!     .map(|s| s.parse().unwrap())
+//!     .collect();
+//!
+//! for profile in fold(&spaa, &values) {
+//!     let path = format!("{}.{}.folded", profile.event, profile.value.label());
+//!     profile.write(File::create(path).unwrap()).unwrap();
+//! }
+//! ```
+
+use spaa_parse::{FrameOrder, SpaaFile};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// The value emitted for each folded stack.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FoldedValue {
+    /// The summed value of a single metric.
+    Metric(String),
+    /// `numerator / denominator * scale`, computed from the summed metrics
+    /// of each path. Paths where the denominator is zero are omitted.
+    ///
+    /// An operand naming a header event whose stacks do not carry it as a
+    /// metric refers to that event's primary metric, joined on the folded
+    /// path.
+    Ratio {
+        numerator: String,
+        denominator: String,
+        scale: f64,
+    },
+}
+
+impl FoldedValue {
+    /// A single metric.
+    pub fn metric(name: impl Into<String>) -> Self {
+        FoldedValue::Metric(name.into())
+    }
+
+    /// The ratio of two metrics.
+    pub fn ratio(numerator: impl Into<String>, denominator: impl Into<String>) -> Self {
+        FoldedValue::Ratio {
+            numerator: numerator.into(),
+            denominator: denominator.into(),
+            scale: 1.0,
+        }
+    }
+
+    /// A file-name-safe label, such as `period` or `cache-misses_per_instructions`.
+    pub fn label(&self) -> String {
+        match self {
+            FoldedValue::Metric(name) => name.clone(),
+            FoldedValue::Ratio {
+                numerator,
+                denominator,
+                ..
+            } => format!("{}_per_{}", numerator, denominator),
+        }
+    }
+
+    fn metrics(&self) -> impl Iterator<Item = &str> {
+        let (a, b) = match self {
+            FoldedValue::Metric(name) => (name.as_str(), None),
+            FoldedValue::Ratio {
+                numerator,
+                denominator,
+                ..
+            } => (numerator.as_str(), Some(denominator.as_str())),
+        };
+        std::iter::once(a).chain(b)
+    }
+
+    fn evaluate(&self, get: impl Fn(&str) -> u64) -> Option<f64> {
+        match self {
+            FoldedValue::Metric(name) => Some(get(name) as f64),
+            FoldedValue::Ratio {
+                numerator,
+                denominator,
+                scale,
+            } => {
+                let den = get(denominator);
+                (den > 0).then(|| get(numerator) as f64 / den as f64 * scale)
+            }
+        }
+    }
+}
+
+/// Error returned when parsing a [`FoldedValue`] from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFoldedValueError(String);
+
+impl fmt::Display for ParseFoldedValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid folded value '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseFoldedValueError {}
+
+impl FromStr for FoldedValue {
+    type Err = ParseFoldedValueError;
+
+    /// Parse `metric`, `numerator/denominator` or
+    /// `numerator/denominator*scale`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseFoldedValueError(s.to_string());
+        let Some((numerator, rest)) = s.split_once('/') else {
+            if s.is_empty() {
+                return Err(err());
+            }
+            return Ok(FoldedValue::metric(s));
+        };
+        let (denominator, scale) = match rest.split_once('*') {
+            Some((den, scale)) => (den, scale.parse().map_err(|_| err())?),
+            None => (rest, 1.0),
+        };
+        if numerator.is_empty() || denominator.is_empty() {
+            return Err(err());
+        }
+        Ok(FoldedValue::Ratio {
+            numerator: numerator.to_string(),
+            denominator: denominator.to_string(),
+            scale,
+        })
+    }
+}
+
+/// Folded stacks for one event and one value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoldedProfile {
+    pub event: String,
+    pub value: FoldedValue,
+    /// Folded paths and their values, sorted by path.
+    pub stacks: Vec<(String, f64)>,
+}
+
+impl FoldedProfile {
+    /// Write the profile in folded format.
+    ///
+    /// Whole numbers are written without a fractional part; other values
+    /// are written with up to six decimal places.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for (path, value) in &self.stacks {
+            writeln!(writer, "{} {}", path, format_value(*value))?;
+        }
+        Ok(())
+    }
+
+    /// Sum of all values in the profile.
+    pub fn total(&self) -> f64 {
+        self.stacks.iter().map(|(_, v)| v).sum()
+    }
+}
+
+/// Fold every event in `file` for each of `values` in a single pass.
+///
+/// Returns one [`FoldedProfile`] per event and value, in header event order
+/// then `values` order. Events with no non-zero paths for a value produce an
+/// empty profile, as do events other than the numerator's for a ratio whose
+/// numerator names an event, so each cross-event ratio is emitted once.
+pub fn fold(file: &SpaaFile, values: &[FoldedValue]) -> Vec<FoldedProfile> {
+    let primaries: HashMap<&str, &str> = file
+        .header
+        .events
+        .iter()
+        .map(|e| (e.name.as_str(), e.sampling.primary_metric.as_str()))
+        .collect();
+    let metrics: BTreeSet<&str> = values
+        .iter()
+        .flat_map(|v| v.metrics())
+        .chain(primaries.values().copied())
+        .collect();
+
+    // event -> path -> metric -> sum
+    let mut sums: HashMap<&str, BTreeMap<String, HashMap<&str, u64>>> = HashMap::new();
+    // event -> metrics its stacks carry
+    let mut carried: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    let mut names: HashMap<u64, String> = HashMap::new();
+
+    for stack in file.stacks.values() {
+        let path = folded_path(file, &stack.frames, &mut names);
+        let event = stack.context.event.as_str();
+        let per_path = sums.entry(event).or_default().entry(path).or_default();
+        for weight in &stack.weights {
+            if let Some(metric) = metrics.get(weight.metric.as_str()) {
+                *per_path.entry(metric).or_default() += weight.value;
+                carried.entry(event).or_default().insert(metric);
+            }
+        }
+    }
+
+    // Where an operand's value comes from when folding `event`: a metric of
+    // some event, summed on the same path.
+    let resolve = |event: &str, operand: &str| -> (String, String) {
+        let carries = carried.get(event).is_some_and(|m| m.contains(operand));
+        match primaries.get(operand) {
+            Some(primary) if !carries => (operand.to_string(), primary.to_string()),
+            _ => (event.to_string(), operand.to_string()),
+        }
+    };
+    let lookup = |source: &(String, String), path: &str| {
+        sums.get(source.0.as_str())
+            .and_then(|paths| paths.get(path))
+            .and_then(|metrics| metrics.get(source.1.as_str()))
+            .copied()
+            .unwrap_or(0)
+    };
+
+    let mut profiles = Vec::with_capacity(file.header.events.len() * values.len());
+    for event in &file.header.events {
+        let paths = sums.get(event.name.as_str());
+        for value in values {
+            let sources: HashMap<&str, (String, String)> = value
+                .metrics()
+                .map(|m| (m, resolve(&event.name, m)))
+                .collect();
+            let numerator = value.metrics().next().expect("values have a metric");
+            let stacks = if sources[numerator].0 != event.name {
+                Vec::new()
+            } else {
+                paths
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|(path, _)| {
+                        value
+                            .evaluate(|m| lookup(&sources[m], path))
+                            .filter(|v| *v != 0.0)
+                            .map(|v| (path.clone(), v))
+                    })
+                    .collect()
+            };
+            profiles.push(FoldedProfile {
+                event: event.name.clone(),
+                value: value.clone(),
+                stacks,
+            });
+        }
+    }
+    profiles
+}
+
+/// Write folded stacks for a single event and value.
+pub fn write_folded<W: Write>(
+    file: &SpaaFile,
+    event: &str,
+    value: &FoldedValue,
+    writer: W,
+) -> io::Result<()> {
+    let profile = fold(file, std::slice::from_ref(value))
+        .into_iter()
+        .find(|p| p.event == event);
+    match profile {
+        Some(profile) => profile.write(writer),
+        None => Ok(()),
+    }
+}
+
+/// Build the `root;...;leaf` path for a stack, caching frame names.
+fn folded_path(file: &SpaaFile, frames: &[u64], names: &mut HashMap<u64, String>) -> String {
+    let mut parts: Vec<&str> = Vec::with_capacity(frames.len());
+    for id in frames {
+        if !names.contains_key(id) {
+            let name = file
+                .resolve_frame(*id)
+                .map_or_else(|| format!("<frame {}>", id), |f| f.func.replace(';', ":"));
+            names.insert(*id, name);
+        }
+    }
+    for id in frames {
+        parts.push(&names[id]);
+    }
+    if file.header.frame_order == FrameOrder::LeafToRoot {
+        parts.reverse();
+    }
+    parts.join(";")
+}
+
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        let s = format!("{:.6}", value);
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}},{"name":"cache-misses","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles","tid":1},"weights":[{"metric":"period","value":300},{"metric":"instructions","value":400},{"metric":"cache-misses","value":10}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[2,1],"context":{"event":"cycles","tid":2},"weights":[{"metric":"period","value":100},{"metric":"instructions","value":600},{"metric":"cache-misses","value":40}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":50}]}"#,
+            r#"{"type":"stack","id":"0x4","frames":[1],"context":{"event":"cache-misses"},"weights":[{"metric":"period","value":7}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn folds_selected_metric_root_first() {
+        let mut out = Vec::new();
+        write_folded(
+            &sample_file(),
+            "cycles",
+            &FoldedValue::metric("period"),
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "main 50\nmain;parse 400\n");
+    }
+
+    #[test]
+    fn ratios_use_summed_metrics_per_path() {
+        let value: FoldedValue = "cache-misses/instructions*1000".parse().unwrap();
+        let profiles = fold(&sample_file(), &[value]);
+
+        // (10 + 40) / (400 + 600) * 1000; "main" has no instructions.
+        assert_eq!(profiles[0].stacks, vec![("main;parse".to_string(), 50.0)]);
+    }
+
+    #[test]
+    fn ratios_join_events_on_path() {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cache-misses","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}},{"name":"instructions","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cache-misses","tid":1},"weights":[{"metric":"period","value":30}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[2,1],"context":{"event":"cache-misses","tid":2},"weights":[{"metric":"period","value":20}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[2,1],"context":{"event":"instructions"},"weights":[{"metric":"period","value":1000}]}"#,
+            r#"{"type":"stack","id":"0x4","frames":[1],"context":{"event":"cache-misses"},"weights":[{"metric":"period","value":5}]}"#,
+        ]
+        .join("\n");
+        let file = SpaaFile::parse(Cursor::new(data)).unwrap();
+        let value: FoldedValue = "cache-misses/instructions*1000".parse().unwrap();
+        let profiles = fold(&file, &[value]);
+
+        // (30 + 20) / 1000 * 1000; "main" has no instructions. The ratio is
+        // emitted under the numerator's event only.
+        assert_eq!(profiles[0].event, "cache-misses");
+        assert_eq!(profiles[0].stacks, vec![("main;parse".to_string(), 50.0)]);
+        assert!(profiles[1].stacks.is_empty());
+    }
+
+    #[test]
+    fn fold_emits_every_event_and_value() {
+        let values = [
+            FoldedValue::metric("period"),
+            FoldedValue::metric("samples"),
+        ];
+        let profiles = fold(&sample_file(), &values);
+
+        assert_eq!(profiles.len(), 4);
+        assert_eq!(profiles[2].event, "cache-misses");
+        assert_eq!(profiles[2].total(), 7.0);
+        assert!(profiles[1].stacks.is_empty());
+    }
+
+    #[test]
+    fn parse_folded_value() {
+        assert_eq!("period".parse(), Ok(FoldedValue::metric("period")));
+        assert_eq!("a/b".parse(), Ok(FoldedValue::ratio("a", "b")));
+        assert!("a/".parse::<FoldedValue>().is_err());
+        assert!("a/b*x".parse::<FoldedValue>().is_err());
+    }
+
+    #[test]
+    fn format_value_trims_fractions() {
+        assert_eq!(format_value(3.0), "3");
+        assert_eq!(format_value(0.125), "0.125");
+    }
+}
@@ -0,0 +1,455 @@
+//! Self-contained HTML reports.
+//!
+//! [`write_html`] renders a profile as a single HTML page with no scripts
+//! or external resources, so it can be attached to a pull request or an
+//! incident and opened offline. For each event the page has:
+//!
+//! - a flame graph, as inline SVG with a tooltip per frame
+//! - the top functions by self weight
+//! - the weight per thread
+//! - the weight over time, from window records or, failing those, samples
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::export::html::{HtmlOptions, write_html};
+//! use spaa_parse::SpaaFile;
+//! use std::fs::File;
+//!
+//! let spaa = SpaaFile::open("profile.spaa").unwrap();
+//! let out = File::create("profile.html").unwrap();
+//! write_html(&spaa, &HtmlOptions::default(), out).unwrap();
+//! ```
+
+use super::d3_flamegraph::{FlameNode, flame_graph};
+use crate::analysis::{GroupKey, RankBy, group_by, top_functions};
+use spaa_parse::SpaaFile;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// Width of the SVG charts, in user units.
+pub(super) const CHART_WIDTH: f64 = 1200.0;
+
+/// Height of one flame graph row.
+pub(super) const ROW_HEIGHT: f64 = 17.0;
+
+/// Frames narrower than this are left out of the flame graph.
+pub(super) const MIN_FRAME_WIDTH: f64 = 0.5;
+
+/// Approximate width of a character of frame label text.
+pub(super) const CHAR_WIDTH: f64 = 7.0;
+
+/// Height of the time chart.
+const TIME_CHART_HEIGHT: f64 = 120.0;
+
+/// Buckets samples are counted into when a profile has no windows.
+const TIME_BUCKETS: usize = 60;
+
+const STYLE: &str = "\
+body{font:14px system-ui,sans-serif;margin:2em auto;max-width:1240px;color:#222}\
+h1{font-size:1.6em}h2{margin-top:2em;border-bottom:1px solid #ccc}\
+table{border-collapse:collapse;margin:.5em 0}\
+td,th{padding:2px 10px;text-align:right}td:first-child,th:first-child{text-align:left}\
+tr:nth-child(even){background:#f4f4f4}\
+code{font:12px ui-monospace,monospace}\
+.bar{background:#e0703a;height:10px;display:inline-block}\
+svg{width:100%;height:auto}svg text{font:11px ui-monospace,monospace;pointer-events:none}\
+svg rect{stroke:#fff;stroke-width:.5}\
+.meta{color:#666}";
+
+/// Style for flame graphs outside of a page.
+const SVG_STYLE: &str = "text{font:11px ui-monospace,monospace;pointer-events:none}\
+rect{stroke:#fff;stroke-width:.5}";
+
+/// Options for [`write_html`].
+#[derive(Debug, Clone)]
+pub struct HtmlOptions {
+    /// Page title; defaults to the source tool.
+    pub title: Option<String>,
+    /// Functions listed per event.
+    pub top: usize,
+    /// Events to include; empty for every event.
+    pub events: Vec<String>,
+}
+
+impl Default for HtmlOptions {
+    fn default() -> Self {
+        Self {
+            title: None,
+            top: 20,
+            events: Vec::new(),
+        }
+    }
+}
+
+/// Write `file` as a self-contained HTML report.
+pub fn write_html<W: Write>(
+    file: &SpaaFile,
+    options: &HtmlOptions,
+    mut writer: W,
+) -> io::Result<()> {
+    writer.write_all(render(file, options).as_bytes())
+}
+
+/// The HTML report of `file`.
+pub fn render(file: &SpaaFile, options: &HtmlOptions) -> String {
+    let title = options
+        .title
+        .clone()
+        .unwrap_or_else(|| format!("{} profile", file.header.source_tool));
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        escape(&title),
+        STYLE,
+        escape(&title)
+    );
+    let mut meta = format!(
+        "{} stacks, {} frames, {} threads",
+        file.stacks.len(),
+        file.frames.len(),
+        file.threads.len()
+    );
+    if let Some(range) = &file.header.time_range {
+        let _ = write!(
+            meta,
+            ", {:.3} to {:.3} {}",
+            range.start, range.end, range.unit
+        );
+    }
+    let _ = writeln!(html, "<p class=\"meta\">{}</p>", escape(&meta));
+
+    for event in &file.header.events {
+        if !options.events.is_empty() && !options.events.contains(&event.name) {
+            continue;
+        }
+        let metric = event.sampling.primary_metric.as_str();
+        let _ = writeln!(
+            html,
+            "<section>\n<h2>{} <span class=\"meta\">by {}</span></h2>",
+            escape(&event.name),
+            escape(metric)
+        );
+        let tree = flame_graph(file, &event.name, metric);
+        if tree.value == 0 {
+            html.push_str("<p>No weight recorded.</p>\n</section>\n");
+            continue;
+        }
+        html.push_str("<h3>Flame graph</h3>\n");
+        flame_svg(&mut html, &tree, None);
+        html.push_str("<h3>Top functions</h3>\n");
+        top_table(&mut html, file, &event.name, metric, options.top);
+        html.push_str("<h3>Threads</h3>\n");
+        thread_table(&mut html, file, &event.name, metric);
+        let buckets = time_buckets(file, &event.name, metric);
+        if !buckets.is_empty() {
+            html.push_str("<h3>Over time</h3>\n");
+            time_svg(&mut html, &buckets);
+        }
+        html.push_str("</section>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// The flame graph of `event`, weighted by `metric`, as a standalone SVG
+/// document, or `None` if the event has no weight.
+pub fn render_flame_svg(file: &SpaaFile, event: &str, metric: &str) -> Option<String> {
+    let tree = flame_graph(file, event, metric);
+    if tree.value == 0 {
+        return None;
+    }
+    let mut svg = String::new();
+    flame_svg(&mut svg, &tree, Some(SVG_STYLE));
+    Some(svg)
+}
+
+/// Render `tree` as a flame graph, root at the bottom, with its own
+/// `style` sheet when it is not part of a page.
+fn flame_svg(html: &mut String, tree: &FlameNode, style: Option<&str>) {
+    let height = (depth(tree) as f64 + 1.0) * ROW_HEIGHT;
+    let _ = writeln!(
+        html,
+        "<svg viewBox=\"0 0 {} {}\" xmlns=\"http://www.w3.org/2000/svg\">",
+        CHART_WIDTH, height
+    );
+    if let Some(style) = style {
+        let _ = writeln!(html, "<style>{}</style>", style);
+    }
+    let scale = CHART_WIDTH / tree.value as f64;
+    flame_node(html, tree, tree.value, 0.0, height - ROW_HEIGHT, scale);
+    html.push_str("</svg>\n");
+}
+
+pub(super) fn flame_node(
+    html: &mut String,
+    node: &FlameNode,
+    total: u64,
+    x: f64,
+    y: f64,
+    scale: f64,
+) {
+    let width = node.value as f64 * scale;
+    if width < MIN_FRAME_WIDTH {
+        return;
+    }
+    let share = node.value as f64 * 100.0 / total as f64;
+    let _ = write!(
+        html,
+        "<g><title>{} ({}, {:.2}%)</title><rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{}\" fill=\"{}\"/>",
+        escape(&node.name),
+        node.value,
+        share,
+        x,
+        y,
+        width,
+        ROW_HEIGHT,
+        color(&node.name)
+    );
+    let chars = ((width - 6.0) / CHAR_WIDTH) as usize;
+    if chars >= 3 {
+        let _ = write!(
+            html,
+            "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+            x + 3.0,
+            y + ROW_HEIGHT - 5.0,
+            escape(&truncate(&node.name, chars))
+        );
+    }
+    html.push_str("</g>\n");
+
+    let mut child_x = x;
+    for child in &node.children {
+        flame_node(html, child, total, child_x, y - ROW_HEIGHT, scale);
+        child_x += child.value as f64 * scale;
+    }
+}
+
+pub(super) fn depth(node: &FlameNode) -> usize {
+    node.children
+        .iter()
+        .map(|c| depth(c) + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// A warm color derived from `name`, so a function keeps its color across
+/// graphs.
+fn color(name: &str) -> String {
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    let hue = hash % 50;
+    let lightness = 50 + (hash >> 8) % 20;
+    format!("hsl({},85%,{}%)", hue, lightness)
+}
+
+fn top_table(html: &mut String, file: &SpaaFile, event: &str, metric: &str, n: usize) {
+    html.push_str(
+        "<table>\n<tr><th>Function</th><th>Self</th><th>Self %</th><th>Total</th><th>Total %</th></tr>\n",
+    );
+    for function in top_functions(file, event, metric, n, RankBy::SelfWeight) {
+        let _ = writeln!(
+            html,
+            "<tr><td><code>{}</code></td><td>{}</td><td>{:.1}</td><td>{}</td><td>{:.1}</td></tr>",
+            escape(&function.function),
+            function.self_weight,
+            function.self_share * 100.0,
+            function.total_weight,
+            function.total_share * 100.0
+        );
+    }
+    html.push_str("</table>\n");
+}
+
+fn thread_table(html: &mut String, file: &SpaaFile, event: &str, metric: &str) {
+    // Thread records name threads; many converters only put the name in
+    // stack contexts.
+    let mut comms: HashMap<String, &str> = HashMap::new();
+    for context in file.stacks_for_event(event).map(|s| &s.context) {
+        if let (Some(tid), Some(comm)) = (context.tid, context.comm.as_deref()) {
+            comms.insert(tid.to_string(), comm);
+        }
+    }
+    for thread in file.threads.values() {
+        if let Some(comm) = thread.comm.as_deref() {
+            comms.insert(thread.tid.to_string(), comm);
+        }
+    }
+    html.push_str("<table>\n<tr><th>Thread</th><th>Weight</th><th>%</th><th></th></tr>\n");
+    for group in group_by(file, event, metric, GroupKey::Tid) {
+        let label = match &group.key {
+            Some(tid) => match comms.get(tid) {
+                Some(comm) => format!("{} ({})", comm, tid),
+                None => tid.clone(),
+            },
+            None => "unknown".to_string(),
+        };
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{:.1}</td><td><span class=\"bar\" style=\"width:{:.0}px\"></span></td></tr>",
+            escape(&label),
+            group.weight,
+            group.share * 100.0,
+            group.share * 300.0
+        );
+    }
+    html.push_str("</table>\n");
+}
+
+/// `(start, end, weight)` of `event` over time, from windows if the file
+/// has them and from samples otherwise. Samples are weighted by their
+/// `period`, or 1 when they have none, whatever `metric` is.
+fn time_buckets(file: &SpaaFile, event: &str, metric: &str) -> Vec<(f64, f64, u64)> {
+    if !file.windows.is_empty() {
+        let mut windows: Vec<(f64, f64, u64)> = file
+            .windows
+            .iter()
+            .map(|window| {
+                let weight = window
+                    .by_stack
+                    .iter()
+                    .filter(|e| {
+                        file.stacks
+                            .get(&e.stack_id)
+                            .is_some_and(|s| s.context.event == event)
+                    })
+                    .flat_map(|e| &e.weights)
+                    .filter(|w| w.metric == metric)
+                    .map(|w| w.value)
+                    .sum();
+                (window.start, window.end, weight)
+            })
+            .collect();
+        windows.sort_by(|a, b| a.0.total_cmp(&b.0));
+        return windows;
+    }
+
+    let samples: Vec<(f64, u64)> = file
+        .samples
+        .iter()
+        .filter(|s| s.event == event)
+        .map(|s| (s.timestamp, s.period.unwrap_or(1)))
+        .collect();
+    let Some((start, end)) = samples.iter().fold(None, |range, &(t, _)| match range {
+        None => Some((t, t)),
+        Some((lo, hi)) => Some((f64::min(lo, t), f64::max(hi, t))),
+    }) else {
+        return Vec::new();
+    };
+    let span = (end - start).max(f64::EPSILON);
+    let step = span / TIME_BUCKETS as f64;
+    let mut buckets: Vec<(f64, f64, u64)> = (0..TIME_BUCKETS)
+        .map(|i| (start + i as f64 * step, start + (i + 1) as f64 * step, 0))
+        .collect();
+    for (t, weight) in samples {
+        let i = (((t - start) / step) as usize).min(TIME_BUCKETS - 1);
+        buckets[i].2 += weight;
+    }
+    buckets
+}
+
+fn time_svg(html: &mut String, buckets: &[(f64, f64, u64)]) {
+    let max = buckets.iter().map(|b| b.2).max().unwrap_or(0).max(1);
+    let bar_width = CHART_WIDTH / buckets.len() as f64;
+    let _ = writeln!(
+        html,
+        "<svg viewBox=\"0 0 {} {}\" xmlns=\"http://www.w3.org/2000/svg\">",
+        CHART_WIDTH, TIME_CHART_HEIGHT
+    );
+    for (i, &(start, end, weight)) in buckets.iter().enumerate() {
+        let height = weight as f64 / max as f64 * TIME_CHART_HEIGHT;
+        let _ = writeln!(
+            html,
+            "<g><title>{:.3} to {:.3}: {}</title><rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#4a7fc1\"/></g>",
+            start,
+            end,
+            weight,
+            i as f64 * bar_width,
+            TIME_CHART_HEIGHT - height,
+            bar_width,
+            height
+        );
+    }
+    html.push_str("</svg>\n");
+}
+
+/// `s` cut to at most `chars` characters, ending in `..` if cut.
+pub(super) fn truncate(s: &str, chars: usize) -> String {
+    if s.chars().count() <= chars {
+        return s.to_string();
+    }
+    let mut cut: String = s.chars().take(chars.saturating_sub(2)).collect();
+    cut.push_str("..");
+    cut
+}
+
+/// Escape text for HTML element content and attribute values.
+pub(super) fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"Vec<T>::push","dso":1}"#,
+            r#"{"type":"thread","pid":10,"tid":11,"comm":"worker"}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles","tid":11},"weights":[{"metric":"period","value":300}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[1],"context":{"event":"cycles","tid":11},"weights":[{"metric":"period","value":100}]}"#,
+            r#"{"type":"sample","timestamp":1.0,"pid":10,"tid":11,"cpu":0,"event":"cycles","period":300,"stack_id":"0x1"}"#,
+            r#"{"type":"sample","timestamp":2.0,"pid":10,"tid":11,"cpu":0,"event":"cycles","period":100,"stack_id":"0x2"}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn report_has_every_section_and_no_external_resources() {
+        let html = render(&sample_file(), &HtmlOptions::default());
+
+        for heading in ["Flame graph", "Top functions", "Threads", "Over time"] {
+            assert!(html.contains(&format!("<h3>{}</h3>", heading)), "{heading}");
+        }
+        assert!(html.contains("worker (11)"));
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("src=") && !html.contains("href="));
+    }
+
+    #[test]
+    fn frame_names_are_escaped() {
+        let html = render(&sample_file(), &HtmlOptions::default());
+
+        assert!(html.contains("Vec&lt;T&gt;::push"));
+        assert!(!html.contains("Vec<T>"));
+    }
+
+    #[test]
+    fn samples_are_bucketed_over_time() {
+        let buckets = time_buckets(&sample_file(), "cycles", "period");
+
+        assert_eq!(buckets.len(), TIME_BUCKETS);
+        assert_eq!(buckets[0].2, 300);
+        assert_eq!(buckets[TIME_BUCKETS - 1].2, 100);
+    }
+}
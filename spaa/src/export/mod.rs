@@ -0,0 +1,14 @@
+//! Export SPAA profiles to other visualization formats.
+//!
+//! - [`folded`] - Brendan Gregg's folded stack format, as consumed by
+//!   `flamegraph.pl`, inferno and speedscope
+//! - [`d3_flamegraph`] - nested JSON trees for embedding the d3-flame-graph
+//!   component in web pages
+//! - [`html`] - self-contained HTML reports with a flame graph, top
+//!   functions, threads and a timeline
+//! - [`compare`] - SVG comparisons of two events, overlaid or side by side
+
+pub mod compare;
+pub mod d3_flamegraph;
+pub mod folded;
+pub mod html;
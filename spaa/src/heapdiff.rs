@@ -2,8 +2,14 @@
 //!
 //! This module compares two Chrome heap snapshots and produces an
 //! agent-friendly diff showing what objects grew and their retention paths.
+//! [`AllocationDiff`] compares the allocation stacks of two SPAA files
+//! instead.
 
+use crate::analysis::stack_weight;
+use crate::limits::{BudgetExceeded, Limits, Meter};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use spaa_parse::{CancelToken, Cancelled, EventKind, FrameOrder, ProgressSink, SpaaFile};
 use std::collections::HashMap;
 use std::io::{Read, Write};
 use thiserror::Error;
@@ -18,6 +24,15 @@ pub enum HeapDiffError {
 
     #[error("invalid snapshot: {0}")]
     InvalidSnapshot(String),
+
+    #[error("no allocation event is recorded in both SPAA files")]
+    NoAllocationEvent,
+
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+
+    #[error(transparent)]
+    BudgetExceeded(#[from] BudgetExceeded),
 }
 
 pub type Result<T> = std::result::Result<T, HeapDiffError>;
@@ -56,6 +71,35 @@ pub struct FieldMeta {
     pub trace_function_info_fields: Vec<String>,
 }
 
+/// Bytes read between progress updates.
+const PROGRESS_INTERVAL: u64 = 1 << 20;
+
+/// Reader that reports bytes read, failing with an I/O error once the sink
+/// cancels so the JSON parser stops early.
+struct ProgressReader<'a, R> {
+    inner: R,
+    read: u64,
+    next_report: u64,
+    total: Option<u64>,
+    sink: &'a mut dyn ProgressSink,
+    cancelled: bool,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.read >= self.next_report {
+            if self.sink.update("snapshot", self.read, self.total).is_err() {
+                self.cancelled = true;
+                return Err(std::io::Error::other(Cancelled));
+            }
+            self.next_report = self.read + PROGRESS_INTERVAL;
+        }
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
 // ============================================================================
 // Parsed snapshot representation
 // ============================================================================
@@ -97,6 +141,32 @@ impl ParsedSnapshot {
         Self::from_raw(raw)
     }
 
+    /// Parse a snapshot, reporting bytes read to `progress` (stage
+    /// `"snapshot"`). `size_hint` is the snapshot size, if known.
+    ///
+    /// Fails with [`HeapDiffError::Cancelled`] if the sink cancels.
+    pub fn parse_with_progress<R: Read>(
+        reader: R,
+        size_hint: Option<u64>,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<Self> {
+        let mut reader = ProgressReader {
+            inner: reader,
+            read: 0,
+            next_report: 0,
+            total: size_hint,
+            sink: progress,
+            cancelled: false,
+        };
+        let raw: RawHeapSnapshot = match serde_json::from_reader(&mut reader) {
+            Ok(raw) => raw,
+            Err(_) if reader.cancelled => return Err(Cancelled.into()),
+            Err(e) => return Err(e.into()),
+        };
+        reader.sink.update("snapshot", reader.read, size_hint)?;
+        Self::from_raw(raw)
+    }
+
     fn from_raw(raw: RawHeapSnapshot) -> Result<Self> {
         let meta = &raw.snapshot.meta;
 
@@ -279,8 +349,29 @@ pub struct TypeGrowth {
     pub size_before: u64,
     pub size_after: u64,
     pub size_delta: i64,
+    /// Growing shapes of the type's objects, heaviest first, so that
+    /// growth of a common constructor such as `Object` can be told apart.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub shapes: Vec<ShapeGrowth>,
+}
+
+/// Growth of the objects of one type that share a shape: the same set of
+/// property names.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShapeGrowth {
+    /// Sorted, distinct property edge names; the shape's fingerprint.
+    pub properties: Vec<String>,
+    pub count_before: u64,
+    pub count_after: u64,
+    pub count_delta: i64,
+    pub size_before: u64,
+    pub size_after: u64,
+    pub size_delta: i64,
 }
 
+/// Most shapes listed per type in [`TypeGrowth::shapes`].
+const MAX_SHAPES: usize = 10;
+
 /// A retained object with its retention path.
 #[derive(Debug, Clone, Serialize)]
 pub struct RetainedObject {
@@ -289,6 +380,16 @@ pub struct RetainedObject {
     pub retention_path: Vec<String>,
 }
 
+/// Retained objects of one constructor that share a retention path, once
+/// array indices are folded into `[]`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RetentionPathTotal {
+    pub constructor: String,
+    pub retention_path: Vec<String>,
+    pub objects: u64,
+    pub size: u64,
+}
+
 /// Heap diff result.
 pub struct HeapDiff {
     pub baseline_path: String,
@@ -297,35 +398,145 @@ pub struct HeapDiff {
     pub retained_objects: Vec<RetainedObject>,
 }
 
-/// Reverse edge map: node_idx -> [(from_node_idx, edge_name)]
-type ReverseEdgeMap = HashMap<usize, Vec<(usize, String)>>;
+/// Incoming edges of every node, as `(from_node_idx, edge_idx)` pairs
+/// grouped by target node.
+struct ReverseEdgeMap {
+    /// `offsets[n]..offsets[n + 1]` indexes `entries` for node `n`.
+    offsets: Vec<usize>,
+    entries: Vec<(usize, usize)>,
+}
+
+impl ReverseEdgeMap {
+    fn predecessors(&self, node_idx: usize) -> &[(usize, usize)] {
+        &self.entries[self.offsets[node_idx]..self.offsets[node_idx + 1]]
+    }
+}
+
+/// How retention paths and retained sizes treat weak references.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WeakRefs {
+    /// Retention paths may pass through any edge, and only edges of type
+    /// `weak` are left out of retained sizes.
+    #[default]
+    Follow,
+    /// Leave out every weak reference, including WeakMap and WeakSet
+    /// entries and WeakRef and FinalizationRegistry targets. Objects
+    /// reachable only through them have no retention path, so they are not
+    /// reported as retained, and they retain only themselves.
+    Exclude,
+}
+
+impl WeakRefs {
+    /// Whether `edge`, from `from`, keeps its target alive.
+    fn retains(self, from: &HeapNode, edge: &HeapEdge) -> bool {
+        match self {
+            WeakRefs::Follow => true,
+            WeakRefs::Exclude => !is_weak_reference(from, edge),
+        }
+    }
+}
+
+/// Whether `edge`, from `from`, only holds its target while something else
+/// does: a `weak` edge, an entry of a WeakMap or WeakSet backing table, or
+/// the target of a WeakRef or FinalizationRegistry cell.
+fn is_weak_reference(from: &HeapNode, edge: &HeapEdge) -> bool {
+    edge.edge_type == "weak"
+        || from.name.contains("EphemeronHashTable")
+        || edge.name_or_index.contains("pair in WeakMap")
+        || (edge.name_or_index == "target"
+            && matches!(
+                from.name.as_str(),
+                "WeakRef" | "WeakCell" | "system / WeakCell"
+            ))
+}
+
+/// How objects in the target snapshot are matched with the baseline to
+/// tell which ones are new.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ObjectMatch {
+    /// By node id, which V8 keeps stable across snapshots of one page
+    /// load.
+    #[default]
+    Id,
+    /// By constructor, shape and retention path from the root, for
+    /// snapshots whose ids are unrelated, such as ones from different page
+    /// loads. Objects with the same fingerprint are matched one-to-one, so
+    /// only those beyond the baseline's count are new.
+    Structure,
+}
+
+/// The constructor name of objects and closures, the node type otherwise.
+fn constructor_name(node: &HeapNode) -> &str {
+    if (node.node_type == "object" || node.node_type == "closure") && !node.name.is_empty() {
+        &node.name
+    } else {
+        &node.node_type
+    }
+}
 
 impl HeapDiff {
     /// Compute diff between two snapshots.
+    ///
+    /// Type statistics, the reverse edge map and retention path searches
+    /// run on the rayon thread pool. `weak_refs` decides whether retention
+    /// paths may pass through weak references, and `matching` how new
+    /// objects are told from ones already in the baseline.
     pub fn compute(
         baseline: &ParsedSnapshot,
         target: &ParsedSnapshot,
         baseline_path: &str,
         target_path: &str,
         max_retained_objects: usize,
+        weak_refs: WeakRefs,
+        matching: ObjectMatch,
     ) -> Self {
-        // Compute type stats for baseline
-        let baseline_stats = Self::compute_type_stats(baseline);
+        Self::compute_limited(
+            baseline,
+            target,
+            baseline_path,
+            target_path,
+            max_retained_objects,
+            weak_refs,
+            matching,
+            &Limits::default(),
+        )
+        .expect("default limits are never reached")
+    }
 
-        // Compute type stats for target
-        let target_stats = Self::compute_type_stats(target);
+    /// Like [`HeapDiff::compute`], checking `limits` between phases and
+    /// while matching objects and searching retention paths.
+    ///
+    /// Fails with [`HeapDiffError::Cancelled`] once the token is cancelled,
+    /// and with [`HeapDiffError::BudgetExceeded`] if the reverse edge map
+    /// or structural matching would exceed the memory budget.
+    #[allow(clippy::too_many_arguments)]
+    pub fn compute_limited(
+        baseline: &ParsedSnapshot,
+        target: &ParsedSnapshot,
+        baseline_path: &str,
+        target_path: &str,
+        max_retained_objects: usize,
+        weak_refs: WeakRefs,
+        matching: ObjectMatch,
+        limits: &Limits,
+    ) -> Result<Self> {
+        let cancel = &limits.cancel;
+        let mut meter = limits.meter();
+        let _span = tracing::info_span!("heapdiff", baseline = baseline_path, target = target_path)
+            .entered();
+        let (baseline_stats, target_stats) = rayon::join(
+            || Self::compute_type_stats(baseline),
+            || Self::compute_type_stats(target),
+        );
+        cancel.check()?;
 
         // Compute growth
         let mut type_growth: Vec<TypeGrowth> = Vec::new();
 
         // Get all type names
         let mut all_types: std::collections::HashSet<&str> = std::collections::HashSet::new();
-        for key in baseline_stats.keys() {
-            all_types.insert(key);
-        }
-        for key in target_stats.keys() {
-            all_types.insert(key);
-        }
+        all_types.extend(baseline_stats.keys());
+        all_types.extend(target_stats.keys());
 
         for type_name in all_types {
             let before = baseline_stats.get(type_name).cloned().unwrap_or_default();
@@ -344,110 +555,299 @@ impl HeapDiff {
                     size_before: before.total_size,
                     size_after: after.total_size,
                     size_delta,
+                    shapes: Vec::new(),
                 });
             }
         }
 
         // Sort by size delta descending
-        type_growth.sort_by(|a, b| b.size_delta.cmp(&a.size_delta));
+        type_growth.sort_by(|a, b| {
+            b.size_delta
+                .cmp(&a.size_delta)
+                .then_with(|| a.constructor.cmp(&b.constructor))
+        });
+
+        let growing_types: std::collections::HashSet<&str> =
+            type_growth.iter().map(|g| g.constructor.as_str()).collect();
+        let (baseline_shapes, target_shapes) = rayon::join(
+            || Self::compute_shape_stats(baseline, &growing_types),
+            || Self::compute_shape_stats(target, &growing_types),
+        );
+        cancel.check()?;
+        let mut shapes: HashMap<&str, Vec<ShapeGrowth>> = HashMap::new();
+        let all_shapes: std::collections::HashSet<&(&str, Vec<&str>)> =
+            baseline_shapes.keys().chain(target_shapes.keys()).collect();
+        for key in all_shapes {
+            let before = baseline_shapes.get(key).cloned().unwrap_or_default();
+            let after = target_shapes.get(key).cloned().unwrap_or_default();
+            let count_delta = after.count as i64 - before.count as i64;
+            let size_delta = after.total_size as i64 - before.total_size as i64;
+            if count_delta > 0 || size_delta > 0 {
+                shapes.entry(key.0).or_default().push(ShapeGrowth {
+                    properties: key.1.iter().map(|p| p.to_string()).collect(),
+                    count_before: before.count,
+                    count_after: after.count,
+                    count_delta,
+                    size_before: before.total_size,
+                    size_after: after.total_size,
+                    size_delta,
+                });
+            }
+        }
+        for growth in &mut type_growth {
+            if let Some(mut type_shapes) = shapes.remove(growth.constructor.as_str()) {
+                type_shapes.sort_by(|a, b| {
+                    b.size_delta
+                        .cmp(&a.size_delta)
+                        .then_with(|| a.properties.cmp(&b.properties))
+                });
+                type_shapes.truncate(MAX_SHAPES);
+                growth.shapes = type_shapes;
+            }
+        }
 
         // Find objects that are new in target (not in baseline)
-        let mut retained_objects = Vec::new();
-        let top_growing_types: Vec<String> = type_growth
+        let top_growing_types: std::collections::HashSet<&str> = type_growth
             .iter()
             .take(10)
-            .map(|g| g.constructor.clone())
+            .map(|g| g.constructor.as_str())
             .collect();
 
         // Build reverse edge map once (this is expensive but only done once)
-        eprintln!("  Building reverse edge map ({} edges)...", target.edges.len());
-        let reverse_edges = Self::build_reverse_edge_map(target);
-        eprintln!("  Analyzing retained objects...");
-
-        // Find new objects of top growing types and get their retention paths
-        for (node_idx, node) in target.nodes.iter().enumerate() {
-            if retained_objects.len() >= max_retained_objects {
-                break;
-            }
-
-            // Check if this object is new (not in baseline)
-            if baseline.id_to_idx.contains_key(&node.id) {
-                continue;
-            }
-
-            // Get the constructor name (for objects, it's the name field)
-            let constructor = if node.node_type == "object" || node.node_type == "closure" {
-                if node.name.is_empty() {
-                    node.node_type.clone()
-                } else {
-                    node.name.clone()
-                }
-            } else {
-                node.node_type.clone()
-            };
-
-            // Only analyze top growing types
-            if !top_growing_types.contains(&constructor) {
-                continue;
+        tracing::info!(
+            "  Building reverse edge map ({} edges)...",
+            target.edges.len()
+        );
+        let reverse_edges = Self::build_reverse_edge_map(target, &mut meter)?;
+        cancel.check()?;
+        tracing::info!("  Analyzing retained objects...");
+
+        // New objects of top growing types, in node order
+        let unmatched = match matching {
+            ObjectMatch::Structure => {
+                // A fingerprint, a visited flag and a queue slot per node
+                // of both snapshots, and a flag per target node.
+                let nodes = baseline.nodes.len() + target.nodes.len();
+                meter.charge_items::<(u64, bool, usize)>(nodes)?;
+                meter.charge_items::<bool>(target.nodes.len())?;
+                Some(Self::unmatched_by_structure(baseline, target, cancel)?)
             }
+            ObjectMatch::Id => None,
+        };
+        let candidates: Vec<usize> = (0..target.nodes.len())
+            .into_par_iter()
+            .filter(|&idx| {
+                let node = &target.nodes[idx];
+                let is_new = match &unmatched {
+                    Some(unmatched) => unmatched[idx],
+                    None => !baseline.id_to_idx.contains_key(&node.id),
+                };
+                is_new && top_growing_types.contains(constructor_name(node))
+            })
+            .collect();
 
-            // Get retention path
-            let retention_path = Self::find_retention_path(target, node_idx, &reverse_edges);
-
-            if !retention_path.is_empty() {
-                retained_objects.push(RetainedObject {
-                    constructor,
-                    size: node.self_size,
-                    retention_path,
-                });
-            }
+        // Search retention paths a batch at a time, so that objects without
+        // a path do not count towards the limit and the result matches a
+        // sequential scan.
+        let mut retained_objects = Vec::new();
+        let mut remaining = candidates.as_slice();
+        while retained_objects.len() < max_retained_objects && !remaining.is_empty() {
+            let batch_len = (max_retained_objects - retained_objects.len()).min(remaining.len());
+            let (batch, rest) = remaining.split_at(batch_len);
+            remaining = rest;
+            let found: Vec<RetainedObject> = batch
+                .par_iter()
+                .filter_map(|&node_idx| {
+                    if cancel.is_cancelled() {
+                        return None;
+                    }
+                    let node = &target.nodes[node_idx];
+                    let retention_path =
+                        Self::find_retention_path(target, node_idx, &reverse_edges, weak_refs);
+                    (!retention_path.is_empty()).then(|| RetainedObject {
+                        constructor: constructor_name(node).to_string(),
+                        size: node.self_size,
+                        retention_path,
+                    })
+                })
+                .collect();
+            cancel.check()?;
+            retained_objects.extend(found);
         }
 
-        HeapDiff {
+        Ok(HeapDiff {
             baseline_path: baseline_path.to_string(),
             target_path: target_path.to_string(),
             type_growth,
             retained_objects,
-        }
+        })
     }
 
-    fn compute_type_stats(snapshot: &ParsedSnapshot) -> HashMap<String, TypeStats> {
-        let mut stats: HashMap<String, TypeStats> = HashMap::new();
+    fn compute_type_stats(snapshot: &ParsedSnapshot) -> HashMap<&str, TypeStats> {
+        snapshot
+            .nodes
+            .par_iter()
+            .fold(HashMap::new, |mut stats: HashMap<&str, TypeStats>, node| {
+                let entry = stats.entry(constructor_name(node)).or_default();
+                entry.count += 1;
+                entry.total_size += node.self_size;
+                stats
+            })
+            .reduce(HashMap::new, |mut a, b| {
+                for (key, stat) in b {
+                    let entry = a.entry(key).or_default();
+                    entry.count += stat.count;
+                    entry.total_size += stat.total_size;
+                }
+                a
+            })
+    }
 
-        for node in &snapshot.nodes {
-            // Use name for objects (constructor name), node_type otherwise
-            let key = if node.node_type == "object" || node.node_type == "closure" {
-                if node.name.is_empty() {
-                    node.node_type.clone()
-                } else {
-                    node.name.clone()
+    /// Whether each target node is left over once target and baseline
+    /// nodes with the same structural fingerprint are paired off, first
+    /// nodes first.
+    fn unmatched_by_structure(
+        baseline: &ParsedSnapshot,
+        target: &ParsedSnapshot,
+        cancel: &CancelToken,
+    ) -> std::result::Result<Vec<bool>, Cancelled> {
+        let (baseline_prints, target_prints) = rayon::join(
+            || Self::structural_fingerprints(baseline, cancel),
+            || Self::structural_fingerprints(target, cancel),
+        );
+        let (baseline_prints, target_prints) = (baseline_prints?, target_prints?);
+        let mut counts: HashMap<u64, usize> = HashMap::new();
+        for print in baseline_prints {
+            *counts.entry(print).or_default() += 1;
+        }
+        Ok(target_prints
+            .into_iter()
+            .map(|print| match counts.get_mut(&print) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    false
                 }
-            } else {
-                node.node_type.clone()
-            };
+                _ => true,
+            })
+            .collect())
+    }
 
-            let entry = stats.entry(key).or_default();
-            entry.count += 1;
-            entry.total_size += node.self_size;
+    /// A hash of each node's constructor, shape and breadth-first path of
+    /// edge names from node 0 over non-weak edges, with array indices
+    /// folded so the elements of one array share a path. Unreachable nodes
+    /// get an empty path.
+    fn structural_fingerprints(
+        snapshot: &ParsedSnapshot,
+        cancel: &CancelToken,
+    ) -> std::result::Result<Vec<u64>, Cancelled> {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+
+        let len = snapshot.nodes.len();
+        let mut paths = vec![0u64; len];
+        let mut seen = vec![false; len];
+        let mut queue = std::collections::VecDeque::new();
+        if len > 0 {
+            seen[0] = true;
+            queue.push_back(0);
+        }
+        while let Some(node) = queue.pop_front() {
+            cancel.check()?;
+            for edge in snapshot.edges_for_node(node) {
+                let to = edge.to_node_idx;
+                if edge.edge_type == "weak" || to >= len || seen[to] {
+                    continue;
+                }
+                seen[to] = true;
+                let mut hasher = DefaultHasher::new();
+                paths[node].hash(&mut hasher);
+                fold_index(&edge.name_or_index).hash(&mut hasher);
+                paths[to] = hasher.finish();
+                queue.push_back(to);
+            }
         }
 
-        stats
+        Ok((0..len)
+            .into_par_iter()
+            .map(|idx| {
+                let mut hasher = DefaultHasher::new();
+                constructor_name(&snapshot.nodes[idx]).hash(&mut hasher);
+                shape(snapshot, idx).hash(&mut hasher);
+                paths[idx].hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect())
+    }
+
+    /// Statistics of the objects of `types` by constructor and shape.
+    fn compute_shape_stats<'a>(
+        snapshot: &'a ParsedSnapshot,
+        types: &std::collections::HashSet<&str>,
+    ) -> HashMap<(&'a str, Vec<&'a str>), TypeStats> {
+        (0..snapshot.nodes.len())
+            .into_par_iter()
+            .filter(|&idx| {
+                let node = &snapshot.nodes[idx];
+                node.node_type == "object" && types.contains(constructor_name(node))
+            })
+            .fold(HashMap::new, |mut stats: HashMap<_, TypeStats>, idx| {
+                let node = &snapshot.nodes[idx];
+                let entry = stats
+                    .entry((constructor_name(node), shape(snapshot, idx)))
+                    .or_default();
+                entry.count += 1;
+                entry.total_size += node.self_size;
+                stats
+            })
+            .reduce(HashMap::new, |mut a, b| {
+                for (key, stat) in b {
+                    let entry = a.entry(key).or_default();
+                    entry.count += stat.count;
+                    entry.total_size += stat.total_size;
+                }
+                a
+            })
     }
 
     /// Build reverse edge map: for each node, which nodes point to it.
-    fn build_reverse_edge_map(snapshot: &ParsedSnapshot) -> ReverseEdgeMap {
-        let mut reverse_edges: ReverseEdgeMap = HashMap::new();
-
-        for (from_idx, _node) in snapshot.nodes.iter().enumerate() {
-            for edge in snapshot.edges_for_node(from_idx) {
-                reverse_edges
-                    .entry(edge.to_node_idx)
-                    .or_default()
-                    .push((from_idx, edge.name_or_index.clone()));
-            }
-        }
+    ///
+    /// Charges `meter` for the map and the sorted edge list it is built
+    /// from before allocating either.
+    fn build_reverse_edge_map(
+        snapshot: &ParsedSnapshot,
+        meter: &mut Meter,
+    ) -> std::result::Result<ReverseEdgeMap, BudgetExceeded> {
+        meter.charge_items::<(usize, usize, usize)>(snapshot.edges.len())?;
+        meter.charge_items::<(usize, usize)>(snapshot.edges.len())?;
+        meter.charge_items::<usize>(snapshot.nodes.len() + 1)?;
+
+        // (to, from, edge), sorted so each node's predecessors are in
+        // source node and edge order
+        let mut edges: Vec<(usize, usize, usize)> = (0..snapshot.nodes.len())
+            .into_par_iter()
+            .flat_map_iter(|from_idx| {
+                let start = snapshot.nodes[from_idx].edges_start;
+                snapshot
+                    .edges_for_node(from_idx)
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, edge)| (edge.to_node_idx, from_idx, start + i))
+            })
+            .filter(|&(to, _, _)| to < snapshot.nodes.len())
+            .collect();
+        edges.par_sort_unstable();
 
-        reverse_edges
+        let mut offsets = vec![0; snapshot.nodes.len() + 1];
+        for &(to, _, _) in &edges {
+            offsets[to + 1] += 1;
+        }
+        for i in 1..offsets.len() {
+            offsets[i] += offsets[i - 1];
+        }
+        let entries = edges
+            .into_iter()
+            .map(|(_, from, edge)| (from, edge))
+            .collect();
+        Ok(ReverseEdgeMap { offsets, entries })
     }
 
     /// Find retention path from GC roots to a node (BFS from node backwards to root).
@@ -456,14 +856,17 @@ impl HeapDiff {
         snapshot: &ParsedSnapshot,
         target_idx: usize,
         reverse_edges: &ReverseEdgeMap,
+        weak_refs: WeakRefs,
     ) -> Vec<String> {
-        // BFS from target back to root (with iteration limit to avoid very long searches)
-        let mut visited: HashMap<usize, (usize, String)> = HashMap::new();
+        // BFS from target back to root (with iteration limit to avoid very long searches).
+        // `visited` maps each node to the node it points to on the way to the
+        // target and the edge index it points through.
+        let mut visited: HashMap<usize, (usize, usize)> = HashMap::new();
         let mut queue: std::collections::VecDeque<usize> = std::collections::VecDeque::new();
         const MAX_BFS_ITERATIONS: usize = 10_000;
 
         queue.push_back(target_idx);
-        visited.insert(target_idx, (usize::MAX, String::new()));
+        visited.insert(target_idx, (usize::MAX, usize::MAX));
 
         let mut root_idx: Option<usize> = None;
         let mut iterations = 0;
@@ -487,13 +890,14 @@ impl HeapDiff {
             }
 
             // Add predecessors
-            if let Some(predecessors) = reverse_edges.get(&current) {
-                for (pred_idx, edge_name) in predecessors {
-                    if !visited.contains_key(pred_idx) {
-                        visited.insert(*pred_idx, (current, edge_name.clone()));
-                        queue.push_back(*pred_idx);
-                    }
+            for &(pred_idx, edge_idx) in reverse_edges.predecessors(current) {
+                if !weak_refs.retains(&snapshot.nodes[pred_idx], &snapshot.edges[edge_idx]) {
+                    continue;
                 }
+                visited.entry(pred_idx).or_insert_with(|| {
+                    queue.push_back(pred_idx);
+                    (current, edge_idx)
+                });
             }
         }
 
@@ -505,23 +909,14 @@ impl HeapDiff {
             path.push(snapshot.nodes[current].name.clone());
 
             while current != target_idx {
-                // Find next in path
-                let mut found = false;
-                for (node_idx, (prev, edge_name)) in &visited {
-                    if *prev == current {
-                        if edge_name.is_empty() {
-                            path.push(snapshot.nodes[*node_idx].name.clone());
-                        } else {
-                            path.push(edge_name.clone());
-                        }
-                        current = *node_idx;
-                        found = true;
-                        break;
-                    }
-                }
-                if !found {
-                    break;
+                let (next, edge_idx) = visited[&current];
+                let edge_name = &snapshot.edges[edge_idx].name_or_index;
+                if edge_name.is_empty() {
+                    path.push(snapshot.nodes[next].name.clone());
+                } else {
+                    path.push(edge_name.clone());
                 }
+                current = next;
                 // Limit path length
                 if path.len() > 20 {
                     path.push("...".to_string());
@@ -533,6 +928,162 @@ impl HeapDiff {
         path
     }
 
+    /// Retained objects grouped by constructor and retention path,
+    /// heaviest first. Indices such as `[42]` are folded into `[]` so the
+    /// elements of one array group together.
+    pub fn retention_paths(&self) -> Vec<RetentionPathTotal> {
+        let mut groups: HashMap<(&str, Vec<String>), (u64, u64)> = HashMap::new();
+        for obj in &self.retained_objects {
+            let path = obj
+                .retention_path
+                .iter()
+                .map(|segment| fold_index(segment))
+                .collect();
+            let entry = groups.entry((&obj.constructor, path)).or_default();
+            entry.0 += 1;
+            entry.1 += obj.size;
+        }
+        let mut totals: Vec<RetentionPathTotal> = groups
+            .into_iter()
+            .map(
+                |((constructor, retention_path), (objects, size))| RetentionPathTotal {
+                    constructor: constructor.to_string(),
+                    retention_path,
+                    objects,
+                    size,
+                },
+            )
+            .collect();
+        totals.sort_by(|a, b| {
+            b.size
+                .cmp(&a.size)
+                .then_with(|| a.retention_path.cmp(&b.retention_path))
+                .then_with(|| a.constructor.cmp(&b.constructor))
+        });
+        totals
+    }
+
+    /// Keep the `top` fastest-growing types and the retained objects of
+    /// those types.
+    pub fn truncate(&mut self, top: usize) {
+        self.type_growth.truncate(top);
+        let kept: std::collections::HashSet<&str> = self
+            .type_growth
+            .iter()
+            .map(|g| g.constructor.as_str())
+            .collect();
+        self.retained_objects
+            .retain(|obj| kept.contains(obj.constructor.as_str()));
+    }
+
+    /// Write a Markdown leak report: totals, a table of growing types and
+    /// the heaviest retention paths, at most `top` rows each.
+    pub fn write_markdown<W: Write>(&self, mut writer: W, top: usize) -> Result<()> {
+        let size_delta: i64 = self.type_growth.iter().map(|g| g.size_delta).sum();
+        let count_delta: i64 = self.type_growth.iter().map(|g| g.count_delta).sum();
+
+        writeln!(writer, "# Heap diff\n")?;
+        writeln!(writer, "- Baseline: {}", md_code(&self.baseline_path))?;
+        writeln!(writer, "- Target: {}", md_code(&self.target_path))?;
+        writeln!(
+            writer,
+            "- {} types grew by {:+} objects and {:+} bytes",
+            self.type_growth.len(),
+            count_delta,
+            size_delta
+        )?;
+        writeln!(writer)?;
+
+        writeln!(writer, "## Growing types\n")?;
+        if self.type_growth.is_empty() {
+            writeln!(writer, "No type grew.\n")?;
+        } else {
+            writeln!(
+                writer,
+                "| # | Constructor | Objects | Δ objects | Bytes | Δ bytes |"
+            )?;
+            writeln!(writer, "|---|---|---|---|---|---|")?;
+            for (i, g) in self.type_growth.iter().take(top).enumerate() {
+                writeln!(
+                    writer,
+                    "| {} | {} | {} | {:+} | {} | {:+} |",
+                    i + 1,
+                    md_cell(&md_code(&g.constructor)),
+                    g.count_after,
+                    g.count_delta,
+                    g.size_after,
+                    g.size_delta
+                )?;
+            }
+            writeln!(writer)?;
+        }
+
+        let shapes: Vec<(&str, &ShapeGrowth)> = self
+            .type_growth
+            .iter()
+            .take(top)
+            .flat_map(|g| g.shapes.iter().map(move |s| (g.constructor.as_str(), s)))
+            .take(top)
+            .collect();
+        if !shapes.is_empty() {
+            writeln!(writer, "## Object shapes\n")?;
+            writeln!(
+                writer,
+                "| Constructor | Properties | Objects | Δ objects | Δ bytes |"
+            )?;
+            writeln!(writer, "|---|---|---|---|---|")?;
+            for (constructor, shape) in shapes {
+                writeln!(
+                    writer,
+                    "| {} | {} | {} | {:+} | {:+} |",
+                    md_cell(&md_code(constructor)),
+                    md_cell(&md_code(&format!("{{{}}}", shape.properties.join(", ")))),
+                    shape.count_after,
+                    shape.count_delta,
+                    shape.size_delta
+                )?;
+            }
+            writeln!(writer)?;
+        }
+
+        let paths = self.retention_paths();
+        if !paths.is_empty() {
+            writeln!(writer, "## Retention paths\n")?;
+            writeln!(writer, "| # | Constructor | Objects | Bytes | Path |")?;
+            writeln!(writer, "|---|---|---|---|---|")?;
+            for (i, p) in paths.iter().take(top).enumerate() {
+                writeln!(
+                    writer,
+                    "| {} | {} | {} | {} | {} |",
+                    i + 1,
+                    md_cell(&md_code(&p.constructor)),
+                    p.objects,
+                    p.size,
+                    md_cell(&md_code(&p.retention_path.join(" -> ")))
+                )?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Write a single JSON document with the `top` growing types and
+    /// retention paths.
+    pub fn write_json<W: Write>(&self, writer: W, top: usize) -> Result<()> {
+        let mut paths = self.retention_paths();
+        paths.truncate(top);
+        let report = serde_json::json!({
+            "format": "heap-diff",
+            "version": "0.1",
+            "baseline": self.baseline_path,
+            "target": self.target_path,
+            "type_growth": &self.type_growth[..top.min(self.type_growth.len())],
+            "retention_paths": paths,
+        });
+        serde_json::to_writer_pretty(writer, &report)?;
+        Ok(())
+    }
+
     /// Write diff as NDJSON.
     pub fn write_ndjson<W: Write>(&self, mut writer: W) -> Result<()> {
         // Write header
@@ -547,7 +1098,7 @@ impl HeapDiff {
 
         // Write type growth records
         for growth in &self.type_growth {
-            let record = serde_json::json!({
+            let mut record = serde_json::json!({
                 "type": "growth",
                 "constructor": growth.constructor,
                 "count_before": growth.count_before,
@@ -557,6 +1108,9 @@ impl HeapDiff {
                 "size_after": growth.size_after,
                 "size_delta": growth.size_delta
             });
+            if !growth.shapes.is_empty() {
+                record["shapes"] = serde_json::to_value(&growth.shapes)?;
+            }
             writeln!(writer, "{}", serde_json::to_string(&record)?)?;
         }
 
@@ -574,3 +1128,1371 @@ impl HeapDiff {
         Ok(())
     }
 }
+
+// ============================================================================
+// Single snapshot statistics
+// ============================================================================
+
+/// Longest string value listed in [`SnapshotStats::duplicate_strings`].
+const MAX_STRING_CHARS: usize = 100;
+
+/// Shallow and retained size of one constructor.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConstructorStats {
+    pub constructor: String,
+    pub count: u64,
+    /// Sum of the objects' own sizes.
+    pub shallow_size: u64,
+    /// Bytes freed if every object of this constructor were collected.
+    /// Objects dominated by another object of the same constructor are
+    /// counted once.
+    pub retained_size: u64,
+}
+
+/// An object that retains a large part of the heap.
+#[derive(Debug, Clone, Serialize)]
+pub struct Retainer {
+    pub id: u64,
+    pub constructor: String,
+    pub shallow_size: u64,
+    pub retained_size: u64,
+    pub retention_path: Vec<String>,
+}
+
+/// A string value stored more than once.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateString {
+    /// The value, cut to 100 characters.
+    pub value: String,
+    pub count: u64,
+    /// Bytes taken by every copy but the largest.
+    pub wasted_size: u64,
+}
+
+/// Triage statistics for a single snapshot, for when there is no baseline
+/// to diff against.
+///
+/// Retained sizes come from the dominator tree rooted at the snapshot's
+/// first node, V8's synthetic root, ignoring weak edges, and also every
+/// other weak reference with [`WeakRefs::Exclude`]. Unreachable objects
+/// retain only themselves.
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotStats {
+    pub snapshot_path: String,
+    pub node_count: usize,
+    pub total_size: u64,
+    /// Heaviest constructors by retained size.
+    pub constructors: Vec<ConstructorStats>,
+    /// Objects with the largest retained size, other than synthetic roots.
+    pub largest_retainers: Vec<Retainer>,
+    /// Strings with the most bytes in duplicate copies.
+    pub duplicate_strings: Vec<DuplicateString>,
+}
+
+impl SnapshotStats {
+    /// Compute statistics for `snapshot`, keeping `top` rows per table.
+    pub fn compute(
+        snapshot: &ParsedSnapshot,
+        snapshot_path: &str,
+        top: usize,
+        weak_refs: WeakRefs,
+    ) -> Self {
+        Self::compute_limited(snapshot, snapshot_path, top, weak_refs, &Limits::default())
+            .expect("default limits are never reached")
+    }
+
+    /// Like [`SnapshotStats::compute`], checking `limits` while building
+    /// the dominator tree.
+    ///
+    /// Fails with [`HeapDiffError::Cancelled`] once the token is cancelled,
+    /// and with [`HeapDiffError::BudgetExceeded`] if the reverse edge map
+    /// and dominator tree would exceed the memory budget.
+    pub fn compute_limited(
+        snapshot: &ParsedSnapshot,
+        snapshot_path: &str,
+        top: usize,
+        weak_refs: WeakRefs,
+        limits: &Limits,
+    ) -> Result<Self> {
+        let _span = tracing::info_span!("heapstats", snapshot = snapshot_path).entered();
+        let mut meter = limits.meter();
+        let reverse_edges = HeapDiff::build_reverse_edge_map(snapshot, &mut meter)?;
+        limits.check()?;
+        // Dominators, postorder and its numbering, visited flags, DFS stack
+        // and retained sizes, per node.
+        meter.charge_items::<(usize, usize, usize, bool, (usize, usize), u64)>(
+            snapshot.nodes.len(),
+        )?;
+        let (idom, postorder) = dominators(snapshot, &reverse_edges, weak_refs, &limits.cancel)?;
+
+        let mut retained: Vec<u64> = snapshot.nodes.iter().map(|n| n.self_size).collect();
+        for &node in &postorder {
+            if node != 0 {
+                retained[idom[node]] += retained[node];
+            }
+        }
+
+        let mut retainers: Vec<usize> = (1..snapshot.nodes.len())
+            .filter(|&idx| snapshot.nodes[idx].node_type != "synthetic")
+            .collect();
+        retainers.par_sort_unstable_by(|&a, &b| retained[b].cmp(&retained[a]).then(a.cmp(&b)));
+        retainers.truncate(top);
+        let largest_retainers = retainers
+            .par_iter()
+            .map(|&idx| {
+                let node = &snapshot.nodes[idx];
+                Retainer {
+                    id: node.id,
+                    constructor: constructor_name(node).to_string(),
+                    shallow_size: node.self_size,
+                    retained_size: retained[idx],
+                    retention_path: HeapDiff::find_retention_path(
+                        snapshot,
+                        idx,
+                        &reverse_edges,
+                        weak_refs,
+                    ),
+                }
+            })
+            .collect();
+
+        Ok(SnapshotStats {
+            snapshot_path: snapshot_path.to_string(),
+            node_count: snapshot.nodes.len(),
+            total_size: snapshot.nodes.iter().map(|n| n.self_size).sum(),
+            constructors: Self::constructor_stats(snapshot, &idom, &retained, top),
+            largest_retainers,
+            duplicate_strings: Self::duplicate_strings(snapshot, top),
+        })
+    }
+
+    fn constructor_stats(
+        snapshot: &ParsedSnapshot,
+        idom: &[usize],
+        retained: &[u64],
+        top: usize,
+    ) -> Vec<ConstructorStats> {
+        let mut stats: HashMap<&str, ConstructorStats> = HashMap::new();
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); snapshot.nodes.len()];
+        for (idx, node) in snapshot.nodes.iter().enumerate() {
+            let name = constructor_name(node);
+            let stat = stats.entry(name).or_insert_with(|| ConstructorStats {
+                constructor: name.to_string(),
+                count: 0,
+                shallow_size: 0,
+                retained_size: 0,
+            });
+            stat.count += 1;
+            stat.shallow_size += node.self_size;
+            match idom[idx] {
+                usize::MAX => stat.retained_size += node.self_size,
+                parent if parent != idx => children[parent].push(idx),
+                _ => {}
+            }
+        }
+
+        // Walk the dominator tree top-down, counting an object's retained
+        // size unless an object of the same constructor dominates it.
+        // Synthetic nodes cannot be collected, so they retain nothing.
+        let mut active: HashMap<&str, usize> = HashMap::new();
+        let mut stack: Vec<(usize, bool)> = Vec::new();
+        if !snapshot.nodes.is_empty() {
+            stack.push((0, true));
+        }
+        while let Some((idx, enter)) = stack.pop() {
+            let node = &snapshot.nodes[idx];
+            let name = constructor_name(node);
+            let depth = active.entry(name).or_default();
+            if !enter {
+                *depth -= 1;
+                continue;
+            }
+            if *depth == 0
+                && idx != 0
+                && node.node_type != "synthetic"
+                && let Some(stat) = stats.get_mut(name)
+            {
+                stat.retained_size += retained[idx];
+            }
+            *depth += 1;
+            stack.push((idx, false));
+            stack.extend(children[idx].iter().map(|&child| (child, true)));
+        }
+
+        let mut stats: Vec<ConstructorStats> = stats.into_values().collect();
+        stats.sort_by(|a, b| {
+            b.retained_size
+                .cmp(&a.retained_size)
+                .then_with(|| a.constructor.cmp(&b.constructor))
+        });
+        stats.truncate(top);
+        stats
+    }
+
+    fn duplicate_strings(snapshot: &ParsedSnapshot, top: usize) -> Vec<DuplicateString> {
+        // value -> (copies, total size, largest copy)
+        let mut groups: HashMap<&str, (u64, u64, u64)> = HashMap::new();
+        for node in snapshot.nodes.iter().filter(|n| n.node_type == "string") {
+            let group = groups.entry(&node.name).or_default();
+            group.0 += 1;
+            group.1 += node.self_size;
+            group.2 = group.2.max(node.self_size);
+        }
+        let mut duplicates: Vec<(&str, u64, u64)> = groups
+            .into_iter()
+            .filter(|(_, (count, _, _))| *count > 1)
+            .map(|(value, (count, total, largest))| (value, count, total - largest))
+            .collect();
+        duplicates.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(b.0)));
+        duplicates
+            .into_iter()
+            .take(top)
+            .map(|(value, count, wasted_size)| DuplicateString {
+                value: value.chars().take(MAX_STRING_CHARS).collect(),
+                count,
+                wasted_size,
+            })
+            .collect()
+    }
+
+    /// Write the statistics as NDJSON: a header, then `constructor`,
+    /// `retainer` and `duplicate_string` records.
+    pub fn write_ndjson<W: Write>(&self, mut writer: W) -> Result<()> {
+        let header = serde_json::json!({
+            "type": "header",
+            "format": "heap-stats",
+            "version": "0.1",
+            "snapshot": self.snapshot_path,
+            "node_count": self.node_count,
+            "total_size": self.total_size
+        });
+        writeln!(writer, "{}", serde_json::to_string(&header)?)?;
+        let mut record = |kind: &str, value: serde_json::Value| -> Result<()> {
+            let mut line = serde_json::json!({ "type": kind });
+            if let (Some(line), serde_json::Value::Object(fields)) = (line.as_object_mut(), value) {
+                line.extend(fields);
+            }
+            writeln!(writer, "{}", serde_json::to_string(&line)?)?;
+            Ok(())
+        };
+        for stat in &self.constructors {
+            record("constructor", serde_json::to_value(stat)?)?;
+        }
+        for retainer in &self.largest_retainers {
+            record("retainer", serde_json::to_value(retainer)?)?;
+        }
+        for duplicate in &self.duplicate_strings {
+            record("duplicate_string", serde_json::to_value(duplicate)?)?;
+        }
+        Ok(())
+    }
+
+    /// Write the statistics as one JSON document.
+    pub fn write_json<W: Write>(&self, writer: W) -> Result<()> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Write a Markdown triage report with a table per statistic.
+    pub fn write_markdown<W: Write>(&self, mut writer: W) -> Result<()> {
+        writeln!(writer, "# Heap snapshot\n")?;
+        writeln!(writer, "- Snapshot: {}", md_code(&self.snapshot_path))?;
+        writeln!(
+            writer,
+            "- {} objects, {} bytes\n",
+            self.node_count, self.total_size
+        )?;
+
+        writeln!(writer, "## Constructors by retained size\n")?;
+        writeln!(
+            writer,
+            "| # | Constructor | Objects | Shallow bytes | Retained bytes |"
+        )?;
+        writeln!(writer, "|---|---|---|---|---|")?;
+        for (i, stat) in self.constructors.iter().enumerate() {
+            writeln!(
+                writer,
+                "| {} | {} | {} | {} | {} |",
+                i + 1,
+                md_cell(&md_code(&stat.constructor)),
+                stat.count,
+                stat.shallow_size,
+                stat.retained_size
+            )?;
+        }
+        writeln!(writer)?;
+
+        writeln!(writer, "## Largest retainers\n")?;
+        writeln!(
+            writer,
+            "| # | Object | Shallow bytes | Retained bytes | Path |"
+        )?;
+        writeln!(writer, "|---|---|---|---|---|")?;
+        for (i, retainer) in self.largest_retainers.iter().enumerate() {
+            writeln!(
+                writer,
+                "| {} | {} | {} | {} | {} |",
+                i + 1,
+                md_cell(&md_code(&format!(
+                    "{} @{}",
+                    retainer.constructor, retainer.id
+                ))),
+                retainer.shallow_size,
+                retainer.retained_size,
+                md_cell(&md_code(&retainer.retention_path.join(" -> ")))
+            )?;
+        }
+        writeln!(writer)?;
+
+        if !self.duplicate_strings.is_empty() {
+            writeln!(writer, "## Duplicate strings\n")?;
+            writeln!(writer, "| # | Value | Copies | Wasted bytes |")?;
+            writeln!(writer, "|---|---|---|---|")?;
+            for (i, duplicate) in self.duplicate_strings.iter().enumerate() {
+                let value: String = duplicate
+                    .value
+                    .chars()
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .collect();
+                writeln!(
+                    writer,
+                    "| {} | {} | {} | {} |",
+                    i + 1,
+                    md_cell(&md_code(&value)),
+                    duplicate.count,
+                    duplicate.wasted_size
+                )?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Immediate dominators over non-weak edges from node 0, V8's synthetic
+/// root, also skipping the weak references [`WeakRefs::Exclude`] names, using the Cooper-Harvey-Kennedy iteration. Also returns the
+/// reachable nodes in DFS postorder, root last.
+///
+/// The root is its own dominator; unreachable nodes have `usize::MAX`.
+/// Fails once `cancel` is cancelled.
+fn dominators(
+    snapshot: &ParsedSnapshot,
+    reverse_edges: &ReverseEdgeMap,
+    weak_refs: WeakRefs,
+    cancel: &CancelToken,
+) -> std::result::Result<(Vec<usize>, Vec<usize>), Cancelled> {
+    let len = snapshot.nodes.len();
+    let mut idom = vec![usize::MAX; len];
+    let mut postorder = Vec::new();
+    if len == 0 {
+        return Ok((idom, postorder));
+    }
+    let strong = |from: usize, edge: &HeapEdge| {
+        edge.edge_type != "weak"
+            && edge.to_node_idx < len
+            && weak_refs.retains(&snapshot.nodes[from], edge)
+    };
+
+    // Postorder numbers, by node
+    let mut order = vec![usize::MAX; len];
+    let mut seen = vec![false; len];
+    let mut stack: Vec<(usize, usize)> = vec![(0, 0)];
+    seen[0] = true;
+    while let Some(top) = stack.last_mut() {
+        let (node, next) = *top;
+        let edges = snapshot.edges_for_node(node);
+        if let Some(edge) = edges.get(next) {
+            top.1 += 1;
+            if strong(node, edge) && !seen[edge.to_node_idx] {
+                seen[edge.to_node_idx] = true;
+                stack.push((edge.to_node_idx, 0));
+            }
+        } else {
+            cancel.check()?;
+            order[node] = postorder.len();
+            postorder.push(node);
+            stack.pop();
+        }
+    }
+
+    let intersect = |idom: &[usize], mut a: usize, mut b: usize| {
+        while a != b {
+            while order[a] < order[b] {
+                a = idom[a];
+            }
+            while order[b] < order[a] {
+                b = idom[b];
+            }
+        }
+        a
+    };
+
+    idom[0] = 0;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in postorder.iter().rev().skip(1) {
+            cancel.check()?;
+            let mut new_idom = usize::MAX;
+            for &(pred, edge_idx) in reverse_edges.predecessors(node) {
+                if idom[pred] == usize::MAX || !strong(pred, &snapshot.edges[edge_idx]) {
+                    continue;
+                }
+                new_idom = if new_idom == usize::MAX {
+                    pred
+                } else {
+                    intersect(&idom, pred, new_idom)
+                };
+            }
+            if idom[node] != new_idom {
+                idom[node] = new_idom;
+                changed = true;
+            }
+        }
+    }
+
+    Ok((idom, postorder))
+}
+
+// ============================================================================
+// SPAA allocation diff
+// ============================================================================
+
+/// Change in the allocations attributed to one call path.
+#[derive(Debug, Clone, Serialize)]
+pub struct AllocationGrowth {
+    /// Function names from the outermost caller down to the allocation
+    /// site.
+    pub stack: Vec<String>,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub bytes_delta: i64,
+    pub count_before: u64,
+    pub count_after: u64,
+    pub count_delta: i64,
+}
+
+/// Diff of the allocation event in two SPAA files, such as those written by
+/// [`crate::chrome::HeapSnapshotConverter`].
+///
+/// Stacks are matched by function names rather than IDs, so files from
+/// different converter runs compare cleanly. Bytes are the event's primary
+/// metric and counts its `alloc_count` weight, 0 when absent.
+#[derive(Debug, Clone, Serialize)]
+pub struct AllocationDiff {
+    pub baseline_path: String,
+    pub target_path: String,
+    pub event: String,
+    /// Call paths whose bytes or count grew, largest byte growth first.
+    pub growth: Vec<AllocationGrowth>,
+}
+
+/// `alloc_count` weight of allocation events.
+const ALLOC_COUNT_METRIC: &str = "alloc_count";
+
+impl AllocationDiff {
+    /// Compare the first allocation event of `target` that `baseline` also
+    /// records.
+    ///
+    /// Fails with [`HeapDiffError::NoAllocationEvent`] if there is none.
+    pub fn compute(
+        baseline: &SpaaFile,
+        target: &SpaaFile,
+        baseline_path: &str,
+        target_path: &str,
+    ) -> Result<Self> {
+        let event = target
+            .header
+            .events
+            .iter()
+            .find(|e| {
+                e.kind == EventKind::Allocation
+                    && baseline.header.events.iter().any(|b| b.name == e.name)
+            })
+            .ok_or(HeapDiffError::NoAllocationEvent)?;
+        let before = Self::by_stack(baseline, &event.name);
+        let after = Self::by_stack(target, &event.name);
+
+        let mut stacks: Vec<&Vec<&str>> = before.keys().chain(after.keys()).collect();
+        stacks.sort();
+        stacks.dedup();
+        let mut growth: Vec<AllocationGrowth> = stacks
+            .into_iter()
+            .filter_map(|stack| {
+                let (bytes_before, count_before) = before.get(stack).copied().unwrap_or_default();
+                let (bytes_after, count_after) = after.get(stack).copied().unwrap_or_default();
+                let bytes_delta = bytes_after as i64 - bytes_before as i64;
+                let count_delta = count_after as i64 - count_before as i64;
+                (bytes_delta > 0 || count_delta > 0).then(|| AllocationGrowth {
+                    stack: stack.iter().map(|f| f.to_string()).collect(),
+                    bytes_before,
+                    bytes_after,
+                    bytes_delta,
+                    count_before,
+                    count_after,
+                    count_delta,
+                })
+            })
+            .collect();
+        growth.sort_by_key(|g| std::cmp::Reverse(g.bytes_delta));
+
+        Ok(AllocationDiff {
+            baseline_path: baseline_path.to_string(),
+            target_path: target_path.to_string(),
+            event: event.name.clone(),
+            growth,
+        })
+    }
+
+    /// `(bytes, count)` of `event` by root-to-leaf function names.
+    fn by_stack<'a>(file: &'a SpaaFile, event: &str) -> HashMap<Vec<&'a str>, (u64, u64)> {
+        let metric = file
+            .primary_metric_for_event(event)
+            .unwrap_or("alloc_bytes");
+        let mut stacks: HashMap<Vec<&str>, (u64, u64)> = HashMap::new();
+        for stack in file.stacks_for_event(event) {
+            let mut path: Vec<&str> = stack
+                .frames
+                .iter()
+                .filter_map(|&id| file.resolve_frame(id))
+                .map(|f| f.func.as_str())
+                .collect();
+            if file.header.frame_order == FrameOrder::LeafToRoot {
+                path.reverse();
+            }
+            let entry = stacks.entry(path).or_default();
+            entry.0 += stack_weight(stack, metric);
+            entry.1 += stack_weight(stack, ALLOC_COUNT_METRIC);
+        }
+        stacks
+    }
+
+    /// Keep the `top` call paths with the largest byte growth.
+    pub fn truncate(&mut self, top: usize) {
+        self.growth.truncate(top);
+    }
+
+    /// Write the diff as NDJSON: a header, then one `allocation_growth`
+    /// record per call path.
+    pub fn write_ndjson<W: Write>(&self, mut writer: W) -> Result<()> {
+        let header = serde_json::json!({
+            "type": "header",
+            "format": "allocation-diff",
+            "version": "0.1",
+            "baseline": self.baseline_path,
+            "target": self.target_path,
+            "event": self.event
+        });
+        writeln!(writer, "{}", serde_json::to_string(&header)?)?;
+        for growth in &self.growth {
+            let record = serde_json::json!({
+                "type": "allocation_growth",
+                "stack": growth.stack,
+                "bytes_before": growth.bytes_before,
+                "bytes_after": growth.bytes_after,
+                "bytes_delta": growth.bytes_delta,
+                "count_before": growth.count_before,
+                "count_after": growth.count_after,
+                "count_delta": growth.count_delta
+            });
+            writeln!(writer, "{}", serde_json::to_string(&record)?)?;
+        }
+        Ok(())
+    }
+
+    /// Write the diff as one JSON document.
+    pub fn write_json<W: Write>(&self, writer: W) -> Result<()> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Write a Markdown report of the `top` call paths with the largest
+    /// byte growth.
+    pub fn write_markdown<W: Write>(&self, mut writer: W, top: usize) -> Result<()> {
+        let bytes_delta: i64 = self.growth.iter().map(|g| g.bytes_delta).sum();
+        let count_delta: i64 = self.growth.iter().map(|g| g.count_delta).sum();
+
+        writeln!(writer, "# Allocation diff: {}\n", self.event)?;
+        writeln!(writer, "- Baseline: {}", md_code(&self.baseline_path))?;
+        writeln!(writer, "- Target: {}", md_code(&self.target_path))?;
+        writeln!(
+            writer,
+            "- {} call paths grew by {:+} allocations and {:+} bytes\n",
+            self.growth.len(),
+            count_delta,
+            bytes_delta
+        )?;
+
+        if self.growth.is_empty() {
+            writeln!(writer, "No call path allocates more.\n")?;
+            return Ok(());
+        }
+        writeln!(
+            writer,
+            "| # | Allocation site | Δ allocations | Bytes | Δ bytes | Path |"
+        )?;
+        writeln!(writer, "|---|---|---|---|---|---|")?;
+        for (i, g) in self.growth.iter().take(top).enumerate() {
+            writeln!(
+                writer,
+                "| {} | {} | {:+} | {} | {:+} | {} |",
+                i + 1,
+                md_cell(&md_code(g.stack.last().map_or("", String::as_str))),
+                g.count_delta,
+                g.bytes_after,
+                g.bytes_delta,
+                md_cell(&md_code(&g.stack.join(" -> ")))
+            )?;
+        }
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Retention graph export
+// ============================================================================
+
+/// Retention paths of a diff's retained objects merged into a tree, each
+/// link weighted by the bytes retained through it.
+///
+/// Paths that share a prefix share its nodes, and array indices such as
+/// `[42]` are folded into `[]` so the elements of one array merge. The
+/// object's constructor ends each path. Render the tree with
+/// [`RetentionGraph::write_dot`] for Graphviz or
+/// [`RetentionGraph::write_sankey`] for d3-sankey.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetentionGraph {
+    pub nodes: Vec<RetentionNode>,
+    pub links: Vec<RetentionLink>,
+}
+
+/// A node of a [`RetentionGraph`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RetentionNode {
+    pub name: String,
+}
+
+/// A link of a [`RetentionGraph`], by node index.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RetentionLink {
+    pub source: usize,
+    pub target: usize,
+    /// Bytes retained through this link.
+    pub value: u64,
+}
+
+impl RetentionGraph {
+    /// Merge the `max_paths` heaviest distinct retention paths of `diff`.
+    pub fn build(diff: &HeapDiff, max_paths: usize) -> Self {
+        let paths: Vec<(Vec<String>, u64)> = diff
+            .retention_paths()
+            .into_iter()
+            .take(max_paths)
+            .map(|total| {
+                let mut path = total.retention_path;
+                path.push(total.constructor);
+                (path, total.size)
+            })
+            .collect();
+        let mut graph = RetentionGraph::default();
+        let mut children: HashMap<(Option<usize>, &str), usize> = HashMap::new();
+        // Index of the link into each node; roots have none.
+        let mut incoming: Vec<Option<usize>> = Vec::new();
+        for (path, bytes) in &paths {
+            let mut parent = None;
+            for segment in path {
+                let node = *children
+                    .entry((parent, segment.as_str()))
+                    .or_insert_with(|| {
+                        graph.nodes.push(RetentionNode {
+                            name: segment.clone(),
+                        });
+                        incoming.push(parent.map(|source| {
+                            graph.links.push(RetentionLink {
+                                source,
+                                target: graph.nodes.len() - 1,
+                                value: 0,
+                            });
+                            graph.links.len() - 1
+                        }));
+                        graph.nodes.len() - 1
+                    });
+                if let Some(link) = incoming[node] {
+                    graph.links[link].value += bytes;
+                }
+                parent = Some(node);
+            }
+        }
+        graph
+    }
+
+    /// Write the graph in Graphviz DOT, with pen widths scaled by bytes.
+    pub fn write_dot<W: Write>(&self, mut writer: W) -> Result<()> {
+        let max = self.links.iter().map(|l| l.value).max().unwrap_or(0).max(1);
+        writeln!(writer, "digraph retention {{")?;
+        writeln!(writer, "  rankdir=LR;")?;
+        writeln!(writer, "  node [shape=box, fontname=\"monospace\"];")?;
+        for (idx, node) in self.nodes.iter().enumerate() {
+            writeln!(writer, "  n{} [label=\"{}\"];", idx, escape_dot(&node.name))?;
+        }
+        for link in &self.links {
+            let width = 1.0 + 7.0 * link.value as f64 / max as f64;
+            writeln!(
+                writer,
+                "  n{} -> n{} [label=\"{} B\", penwidth={:.1}];",
+                link.source, link.target, link.value, width
+            )?;
+        }
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+
+    /// Write the graph as d3-sankey JSON: `{"nodes":[{"name"}],"links":[{"source","target","value"}]}`.
+    pub fn write_sankey<W: Write>(&self, writer: W) -> Result<()> {
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
+}
+
+/// A Markdown table cell.
+fn md_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+/// Markdown inline code.
+fn md_code(text: &str) -> String {
+    format!("`{}`", text.replace('`', "'"))
+}
+
+/// `[42]` becomes `[]`; other segments are unchanged.
+/// The sorted, distinct property edge names of node `idx`.
+fn shape(snapshot: &ParsedSnapshot, idx: usize) -> Vec<&str> {
+    let mut properties: Vec<&str> = snapshot
+        .edges_for_node(idx)
+        .iter()
+        .filter(|edge| edge.edge_type == "property")
+        .map(|edge| edge.name_or_index.as_str())
+        .collect();
+    properties.sort_unstable();
+    properties.dedup();
+    properties
+}
+
+fn fold_index(segment: &str) -> String {
+    match segment.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        Some(index) if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) => {
+            "[]".to_string()
+        }
+        _ => segment.to_string(),
+    }
+}
+
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A snapshot of `(type, name, self_size)` nodes, with ids `2 * idx + 1`,
+    /// and `(from, type, name_or_index, to)` edges.
+    fn build(nodes: &[(&str, &str, u64)], edges: &[(usize, &str, &str, usize)]) -> ParsedSnapshot {
+        const NODE_TYPES: [&str; 6] = [
+            "hidden",
+            "array",
+            "string",
+            "object",
+            "closure",
+            "synthetic",
+        ];
+        const EDGE_TYPES: [&str; 6] = [
+            "context", "element", "property", "internal", "hidden", "weak",
+        ];
+        let mut strings: Vec<String> = Vec::new();
+        let mut intern = |s: &str| match strings.iter().position(|x| x == s) {
+            Some(idx) => idx as i64,
+            None => {
+                strings.push(s.to_string());
+                strings.len() as i64 - 1
+            }
+        };
+        let mut raw_nodes = Vec::new();
+        for (idx, &(node_type, name, size)) in nodes.iter().enumerate() {
+            let edge_count = edges.iter().filter(|e| e.0 == idx).count();
+            let type_id = NODE_TYPES.iter().position(|t| *t == node_type).unwrap();
+            raw_nodes.extend([
+                type_id as i64,
+                intern(name),
+                2 * idx as i64 + 1,
+                size as i64,
+                edge_count as i64,
+            ]);
+        }
+        let mut sorted = edges.to_vec();
+        sorted.sort_by_key(|e| e.0);
+        let mut raw_edges = Vec::new();
+        for (_, edge_type, name, to) in sorted {
+            let name = if edge_type == "element" {
+                name.parse().unwrap()
+            } else {
+                intern(name)
+            };
+            let type_id = EDGE_TYPES.iter().position(|t| *t == edge_type).unwrap();
+            raw_edges.extend([type_id as i64, name, 5 * to as i64]);
+        }
+        let json = serde_json::json!({
+            "snapshot": {
+                "meta": {
+                    "node_fields": ["type", "name", "id", "self_size", "edge_count"],
+                    "node_types": [NODE_TYPES],
+                    "edge_fields": ["type", "name_or_index", "to_node"],
+                    "edge_types": [EDGE_TYPES]
+                },
+                "node_count": nodes.len(),
+                "edge_count": edges.len()
+            },
+            "nodes": raw_nodes,
+            "edges": raw_edges,
+            "strings": strings
+        });
+        ParsedSnapshot::parse(json.to_string().as_bytes()).unwrap()
+    }
+
+    /// `Window.cache` is an array of `Item`s of the given sizes.
+    fn snapshot(items: &[u64]) -> ParsedSnapshot {
+        let mut nodes = vec![("object", "Window", 10), ("array", "", 16)];
+        let mut edges = vec![(0, "property", "cache", 1)];
+        let indices: Vec<String> = (0..items.len()).map(|i| i.to_string()).collect();
+        for (i, &size) in items.iter().enumerate() {
+            nodes.push(("object", "Item", size));
+            edges.push((1, "element", indices[i].as_str(), i + 2));
+        }
+        build(&nodes, &edges)
+    }
+
+    #[test]
+    fn compute_finds_growth_and_retention_paths() {
+        let baseline = snapshot(&[]);
+        let target = snapshot(&[100, 50]);
+        let diff = HeapDiff::compute(
+            &baseline,
+            &target,
+            "a",
+            "b",
+            10,
+            WeakRefs::Follow,
+            ObjectMatch::Id,
+        );
+
+        assert_eq!(diff.type_growth.len(), 1);
+        assert_eq!(diff.type_growth[0].constructor, "Item");
+        assert_eq!(diff.type_growth[0].size_delta, 150);
+        let paths: Vec<(u64, Vec<String>)> = diff
+            .retained_objects
+            .iter()
+            .map(|o| (o.size, o.retention_path.clone()))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                (100, vec!["Window".into(), "cache".into(), "[0]".into()]),
+                (50, vec!["Window".into(), "cache".into(), "[1]".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn compute_stops_at_max_retained_objects() {
+        let baseline = snapshot(&[]);
+        let target = snapshot(&[100, 50, 25]);
+        let diff = HeapDiff::compute(
+            &baseline,
+            &target,
+            "a",
+            "b",
+            2,
+            WeakRefs::Follow,
+            ObjectMatch::Id,
+        );
+
+        let sizes: Vec<u64> = diff.retained_objects.iter().map(|o| o.size).collect();
+        assert_eq!(sizes, vec![100, 50]);
+    }
+
+    #[test]
+    fn structural_matching_finds_new_objects_when_ids_differ() {
+        // An extra node shifts every baseline id after `Window`
+        let baseline = build(
+            &[
+                ("object", "Window", 10),
+                ("hidden", "", 0),
+                ("array", "", 16),
+                ("object", "Item", 100),
+                ("object", "Item", 50),
+            ],
+            &[
+                (0, "property", "cache", 2),
+                (2, "element", "0", 3),
+                (2, "element", "1", 4),
+            ],
+        );
+        let target = snapshot(&[100, 50, 25]);
+        let sizes = |matching| {
+            HeapDiff::compute(&baseline, &target, "a", "b", 10, WeakRefs::Follow, matching)
+                .retained_objects
+                .iter()
+                .map(|o| o.size)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sizes(ObjectMatch::Id), Vec::<u64>::new());
+        assert_eq!(sizes(ObjectMatch::Structure), vec![25]);
+    }
+
+    #[test]
+    fn compute_splits_type_growth_by_object_shape() {
+        let baseline = build(&[("object", "Window", 10)], &[]);
+        // Window holds two Objects with `x` and `y` and one with `z`
+        let target = build(
+            &[
+                ("object", "Window", 10),
+                ("object", "Object", 20),
+                ("object", "Object", 20),
+                ("object", "Object", 50),
+                ("hidden", "", 0),
+            ],
+            &[
+                (0, "property", "a", 1),
+                (0, "property", "b", 2),
+                (0, "property", "c", 3),
+                (1, "property", "y", 4),
+                (1, "property", "x", 4),
+                (2, "property", "x", 4),
+                (2, "property", "y", 4),
+                (3, "property", "z", 4),
+            ],
+        );
+        let diff = HeapDiff::compute(
+            &baseline,
+            &target,
+            "a",
+            "b",
+            10,
+            WeakRefs::Follow,
+            ObjectMatch::Id,
+        );
+
+        let object = diff
+            .type_growth
+            .iter()
+            .find(|g| g.constructor == "Object")
+            .unwrap();
+        let shapes: Vec<(Vec<String>, i64, i64)> = object
+            .shapes
+            .iter()
+            .map(|s| (s.properties.clone(), s.count_delta, s.size_delta))
+            .collect();
+        assert_eq!(
+            shapes,
+            vec![
+                (vec!["z".to_string()], 1, 50),
+                (vec!["x".to_string(), "y".to_string()], 2, 40),
+            ]
+        );
+    }
+
+    /// `Window.cache` holds one `Item` and `Window.map`, a WeakMap, holds
+    /// another as a value.
+    fn weak_map_snapshot() -> ParsedSnapshot {
+        build(
+            &[
+                ("object", "Window", 10),
+                ("array", "", 16),
+                ("object", "Item", 100),
+                ("object", "WeakMap", 24),
+                ("hidden", "system / EphemeronHashTable", 32),
+                ("object", "Item", 50),
+            ],
+            &[
+                (0, "property", "cache", 1),
+                (1, "element", "0", 2),
+                (0, "property", "map", 3),
+                (3, "internal", "table", 4),
+                (
+                    4,
+                    "internal",
+                    "part of key (Object @3) -> value (Item @11) pair in WeakMap (table @9)",
+                    5,
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn excluding_weak_refs_drops_objects_only_a_weak_map_retains() {
+        let baseline = build(&[("object", "Window", 10)], &[]);
+        let target = weak_map_snapshot();
+
+        let follow = HeapDiff::compute(
+            &baseline,
+            &target,
+            "a",
+            "b",
+            10,
+            WeakRefs::Follow,
+            ObjectMatch::Id,
+        );
+        let exclude = HeapDiff::compute(
+            &baseline,
+            &target,
+            "a",
+            "b",
+            10,
+            WeakRefs::Exclude,
+            ObjectMatch::Id,
+        );
+
+        let items = |diff: &HeapDiff| {
+            diff.retained_objects
+                .iter()
+                .filter(|o| o.constructor == "Item")
+                .map(|o| o.size)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(items(&follow), vec![100, 50]);
+        assert_eq!(items(&exclude), vec![100]);
+    }
+
+    fn retained(constructor: &str, size: u64, path: &[&str]) -> RetainedObject {
+        RetainedObject {
+            constructor: constructor.to_string(),
+            size,
+            retention_path: path.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn growth(constructor: &str, count_delta: i64, size_delta: i64) -> TypeGrowth {
+        TypeGrowth {
+            constructor: constructor.to_string(),
+            count_before: 0,
+            count_after: count_delta as u64,
+            count_delta,
+            size_before: 0,
+            size_after: size_delta as u64,
+            size_delta,
+            shapes: Vec::new(),
+        }
+    }
+
+    fn diff() -> HeapDiff {
+        HeapDiff {
+            baseline_path: "a.heapsnapshot".to_string(),
+            target_path: "b.heapsnapshot".to_string(),
+            type_growth: vec![growth("Item", 2, 150), growth("Listener", 1, 30)],
+            retained_objects: vec![
+                retained("Item", 100, &["Window", "cache", "[0]"]),
+                retained("Item", 50, &["Window", "cache", "[1]"]),
+                retained("Listener", 30, &["Window", "handlers"]),
+            ],
+        }
+    }
+
+    #[test]
+    fn shared_prefixes_merge_and_sum_bytes() {
+        let graph = RetentionGraph::build(&diff(), 10);
+
+        let names: Vec<&str> = graph.nodes.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["Window", "cache", "[]", "Item", "handlers", "Listener"]
+        );
+        let links: Vec<(usize, usize, u64)> = graph
+            .links
+            .iter()
+            .map(|l| (l.source, l.target, l.value))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                (0, 1, 150),
+                (1, 2, 150),
+                (2, 3, 150),
+                (0, 4, 30),
+                (4, 5, 30)
+            ]
+        );
+    }
+
+    #[test]
+    fn only_the_heaviest_paths_are_kept() {
+        let graph = RetentionGraph::build(&diff(), 1);
+
+        assert_eq!(graph.nodes.len(), 4);
+        assert!(graph.nodes.iter().all(|n| n.name != "handlers"));
+    }
+
+    #[test]
+    fn dot_output_labels_links_with_bytes() {
+        let mut out = Vec::new();
+        RetentionGraph::build(&diff(), 10)
+            .write_dot(&mut out)
+            .unwrap();
+        let dot = String::from_utf8(out).unwrap();
+
+        assert!(dot.starts_with("digraph retention {"));
+        assert!(dot.contains("n0 -> n1 [label=\"150 B\", penwidth=8.0];"));
+        assert!(dot.contains("n0 -> n4 [label=\"30 B\", penwidth=2.4];"));
+    }
+
+    #[test]
+    fn retention_paths_group_array_elements() {
+        let paths = diff().retention_paths();
+
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0].constructor, "Item");
+        assert_eq!(paths[0].retention_path, vec!["Window", "cache", "[]"]);
+        assert_eq!((paths[0].objects, paths[0].size), (2, 150));
+    }
+
+    #[test]
+    fn truncate_drops_objects_of_dropped_types() {
+        let mut diff = diff();
+        diff.truncate(1);
+
+        assert_eq!(diff.type_growth.len(), 1);
+        assert!(
+            diff.retained_objects
+                .iter()
+                .all(|o| o.constructor == "Item")
+        );
+    }
+
+    #[test]
+    fn markdown_report_has_growth_and_path_tables() {
+        let mut out = Vec::new();
+        diff().write_markdown(&mut out, 10).unwrap();
+        let markdown = String::from_utf8(out).unwrap();
+
+        assert!(markdown.starts_with("# Heap diff\n"));
+        assert!(markdown.contains("- 2 types grew by +3 objects and +180 bytes"));
+        assert!(markdown.contains("| 1 | `Item` | 2 | +2 | 150 | +150 |"));
+        assert!(markdown.contains("| 1 | `Item` | 2 | 150 | `Window -> cache -> []` |"));
+    }
+
+    #[test]
+    fn cancelled_token_stops_stats_and_diffs() {
+        let limits = Limits::default();
+        limits.cancel.cancel();
+        let stats = SnapshotStats::compute_limited(
+            &snapshot(&[100]),
+            "heap",
+            10,
+            WeakRefs::Follow,
+            &limits,
+        );
+        assert!(matches!(stats, Err(HeapDiffError::Cancelled(_))));
+
+        let diff = HeapDiff::compute_limited(
+            &snapshot(&[]),
+            &snapshot(&[100, 50]),
+            "a",
+            "b",
+            10,
+            WeakRefs::Follow,
+            ObjectMatch::Structure,
+            &limits,
+        );
+        assert!(matches!(diff, Err(HeapDiffError::Cancelled(_))));
+    }
+
+    #[test]
+    fn memory_budget_bounds_reverse_edge_map() {
+        let target = snapshot(&[100, 50]);
+        let limits = Limits::with_memory(64);
+        let stats = SnapshotStats::compute_limited(&target, "heap", 10, WeakRefs::Follow, &limits);
+        assert!(matches!(
+            stats,
+            Err(HeapDiffError::BudgetExceeded(BudgetExceeded {
+                limit: 64,
+                ..
+            }))
+        ));
+
+        let limits = Limits::with_memory(1 << 20);
+        let stats = SnapshotStats::compute_limited(&target, "heap", 10, WeakRefs::Follow, &limits);
+        assert_eq!(stats.unwrap().total_size, 176);
+    }
+
+    #[test]
+    fn stats_rank_constructors_by_retained_size() {
+        let stats = SnapshotStats::compute(&snapshot(&[100, 50]), "heap", 10, WeakRefs::Follow);
+
+        assert_eq!(stats.total_size, 176);
+        let constructors: Vec<(&str, u64, u64)> = stats
+            .constructors
+            .iter()
+            .map(|c| (c.constructor.as_str(), c.shallow_size, c.retained_size))
+            .collect();
+        assert_eq!(
+            constructors,
+            vec![("array", 16, 166), ("Item", 150, 150), ("Window", 10, 0)]
+        );
+        assert_eq!(stats.largest_retainers[0].id, 3);
+        assert_eq!(
+            stats.largest_retainers[0].retention_path,
+            vec!["Window", "cache"]
+        );
+    }
+
+    #[test]
+    fn stats_share_objects_reachable_from_two_parents_with_the_dominator() {
+        // (root) -> a -> shared, (root) -> b -> shared, a -weak-> c
+        let snapshot = build(
+            &[
+                ("synthetic", "(root)", 0),
+                ("object", "A", 10),
+                ("object", "B", 10),
+                ("object", "Shared", 100),
+                ("object", "C", 5),
+            ],
+            &[
+                (0, "property", "a", 1),
+                (0, "property", "b", 2),
+                (1, "property", "shared", 3),
+                (2, "property", "shared", 3),
+                (1, "weak", "c", 4),
+            ],
+        );
+        let stats = SnapshotStats::compute(&snapshot, "heap", 10, WeakRefs::Follow);
+
+        let retained = |name: &str| {
+            stats
+                .constructors
+                .iter()
+                .find(|c| c.constructor == name)
+                .unwrap()
+                .retained_size
+        };
+        assert_eq!(retained("A"), 10);
+        assert_eq!(retained("Shared"), 100);
+        assert_eq!(retained("C"), 5);
+    }
+
+    #[test]
+    fn stats_excluding_weak_refs_leave_weak_map_values_out_of_retained_size() {
+        // (root) -> Window -> WeakMap -> table -> Value
+        let snapshot = build(
+            &[
+                ("synthetic", "(root)", 0),
+                ("object", "Window", 10),
+                ("object", "WeakMap", 10),
+                ("hidden", "system / EphemeronHashTable", 32),
+                ("object", "Value", 10),
+            ],
+            &[
+                (0, "property", "window", 1),
+                (1, "property", "map", 2),
+                (2, "internal", "table", 3),
+                (3, "internal", "part of key -> value pair in WeakMap", 4),
+            ],
+        );
+        let retained = |weak_refs| {
+            SnapshotStats::compute(&snapshot, "heap", 10, weak_refs)
+                .constructors
+                .into_iter()
+                .find(|c| c.constructor == "WeakMap")
+                .unwrap()
+                .retained_size
+        };
+
+        assert_eq!(retained(WeakRefs::Follow), 52);
+        assert_eq!(retained(WeakRefs::Exclude), 42);
+    }
+
+    #[test]
+    fn stats_sum_wasted_bytes_of_duplicate_strings() {
+        let snapshot = build(
+            &[
+                ("synthetic", "(root)", 0),
+                ("string", "hello", 24),
+                ("string", "hello", 24),
+                ("string", "hello", 24),
+                ("string", "unique", 32),
+            ],
+            &[],
+        );
+        let stats = SnapshotStats::compute(&snapshot, "heap", 10, WeakRefs::Follow);
+
+        assert_eq!(stats.duplicate_strings.len(), 1);
+        let hello = &stats.duplicate_strings[0];
+        assert_eq!(
+            (hello.value.as_str(), hello.count, hello.wasted_size),
+            ("hello", 3, 48)
+        );
+    }
+
+    fn allocation_file(frames: &[&str], stacks: &[(&[u64], u64, u64)]) -> SpaaFile {
+        let mut lines = vec![
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"chrome","frame_order":"root_to_leaf","events":[{"name":"heap","kind":"allocation","sampling":{"mode":"event","primary_metric":"alloc_bytes"}}]}"#.to_string(),
+            r#"{"type":"dso","id":1,"name":"app.js","is_kernel":false}"#.to_string(),
+        ];
+        for (i, func) in frames.iter().enumerate() {
+            lines.push(format!(
+                r#"{{"type":"frame","id":{},"func":"{}","dso":1}}"#,
+                i + 1,
+                func
+            ));
+        }
+        for (i, (ids, bytes, count)) in stacks.iter().enumerate() {
+            lines.push(format!(
+                r#"{{"type":"stack","id":"0x{}","frames":{:?},"context":{{"event":"heap"}},"weights":[{{"metric":"alloc_bytes","value":{}}},{{"metric":"alloc_count","value":{}}}]}}"#,
+                i + 1,
+                ids,
+                bytes,
+                count
+            ));
+        }
+        SpaaFile::parse(lines.join("\n").as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn allocation_diff_matches_stacks_by_function_name() {
+        let baseline = allocation_file(&["main", "load"], &[(&[1, 2], 100, 1)]);
+        // Same functions under different frame IDs, plus a new call path
+        let target = allocation_file(
+            &["load", "main", "render"],
+            &[(&[2, 1], 300, 3), (&[2, 3], 50, 1)],
+        );
+        let diff = AllocationDiff::compute(&baseline, &target, "a.spaa", "b.spaa").unwrap();
+
+        assert_eq!(diff.event, "heap");
+        let growth: Vec<(String, i64, i64)> = diff
+            .growth
+            .iter()
+            .map(|g| (g.stack.join(";"), g.bytes_delta, g.count_delta))
+            .collect();
+        assert_eq!(
+            growth,
+            vec![
+                ("main;load".to_string(), 200, 2),
+                ("main;render".to_string(), 50, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn allocation_diff_needs_a_shared_allocation_event() {
+        let target = allocation_file(&["main"], &[(&[1], 100, 1)]);
+        let cpu = SpaaFile::parse(
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#.as_bytes(),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            AllocationDiff::compute(&cpu, &target, "a.spaa", "b.spaa"),
+            Err(HeapDiffError::NoAllocationEvent)
+        ));
+    }
+}
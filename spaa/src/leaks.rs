@@ -0,0 +1,351 @@
+//! Rank likely leaks from heap diffs or allocation timelines.
+//!
+//! Growth tables say what grew, not what leaks. A leak usually grows a lot,
+//! grows steadily, and is held by one retention path or allocated from one
+//! call path. Each [`LeakSuspect`] scores those three signals from 0 to 1
+//! and combines them with a geometric mean, so a suspect must do well on
+//! all three to score high:
+//!
+//! | Signal | [`HeapDiff`] | Allocation windows |
+//! |---|---|---|
+//! | `size` | Byte growth relative to the largest suspect | Bytes allocated relative to the largest suspect |
+//! | `steadiness` | Share of the type's objects that are new | [`AllocationRate::growth_score`] |
+//! | `path_stability` | Share of retained bytes on the dominant retention path | Share of the allocation site's bytes from this call path |
+//!
+//! `score = (size * steadiness * path_stability)^(1/3)`. A type whose
+//! retained objects were not analyzed gets a neutral `path_stability` of
+//! 0.5.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::leaks::suspects_from_allocations;
+//! use spaa_parse::SpaaFile;
+//!
+//! let spaa = SpaaFile::open("timeline.spaa").unwrap();
+//! for suspect in suspects_from_allocations(&spaa, "allocation", 5) {
+//!     println!("{:?} {:.2} {}", suspect.confidence, suspect.score, suspect.name);
+//! }
+//! ```
+
+use crate::analysis::{AllocationRate, allocation_rates};
+use crate::heapdiff::HeapDiff;
+use serde::Serialize;
+use spaa_parse::SpaaFile;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+/// `path_stability` of a type without analyzed retention paths.
+const UNKNOWN_PATH_STABILITY: f64 = 0.5;
+
+/// How strongly a suspect's signals point to a leak.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    /// Score below 0.4.
+    Low,
+    /// Score from 0.4 to 0.7.
+    Medium,
+    /// Score of 0.7 or more.
+    High,
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        })
+    }
+}
+
+impl Confidence {
+    fn from_score(score: f64) -> Self {
+        if score >= 0.7 {
+            Confidence::High
+        } else if score >= 0.4 {
+            Confidence::Medium
+        } else {
+            Confidence::Low
+        }
+    }
+}
+
+/// A constructor or allocation call path that may be leaking.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LeakSuspect {
+    /// Constructor name, or the allocation site's function.
+    pub name: String,
+    /// Dominant retention path, or the call path from the outermost caller
+    /// to the allocation site.
+    pub path: Vec<String>,
+    /// Bytes the suspect grew by or allocated.
+    pub bytes: u64,
+    pub size: f64,
+    pub steadiness: f64,
+    pub path_stability: f64,
+    pub score: f64,
+    pub confidence: Confidence,
+}
+
+impl LeakSuspect {
+    fn new(
+        name: String,
+        path: Vec<String>,
+        bytes: u64,
+        size: f64,
+        steadiness: f64,
+        path_stability: f64,
+    ) -> Self {
+        let score = (size * steadiness * path_stability).cbrt();
+        LeakSuspect {
+            name,
+            path,
+            bytes,
+            size,
+            steadiness,
+            path_stability,
+            score,
+            confidence: Confidence::from_score(score),
+        }
+    }
+}
+
+/// Score every growing type of `diff`, most likely leak first.
+pub fn suspects_from_diff(diff: &HeapDiff) -> Vec<LeakSuspect> {
+    let largest = diff
+        .type_growth
+        .iter()
+        .map(|g| g.size_delta)
+        .max()
+        .unwrap_or(0)
+        .max(1) as f64;
+    let paths = diff.retention_paths();
+    let mut retained: HashMap<&str, u64> = HashMap::new();
+    for path in &paths {
+        *retained.entry(&path.constructor).or_default() += path.size;
+    }
+
+    let suspects = diff
+        .type_growth
+        .iter()
+        .filter(|g| g.size_delta > 0)
+        .map(|g| {
+            // Paths are heaviest first, so the first one is dominant.
+            let dominant = paths.iter().find(|p| p.constructor == g.constructor);
+            let path_stability = match (dominant, retained.get(g.constructor.as_str())) {
+                (Some(path), Some(&total)) if total > 0 => path.size as f64 / total as f64,
+                _ => UNKNOWN_PATH_STABILITY,
+            };
+            let steadiness = if g.count_after > 0 {
+                (g.count_delta.max(0) as f64 / g.count_after as f64).min(1.0)
+            } else {
+                0.0
+            };
+            LeakSuspect::new(
+                g.constructor.clone(),
+                dominant
+                    .map(|p| p.retention_path.clone())
+                    .unwrap_or_default(),
+                g.size_delta as u64,
+                g.size_delta as f64 / largest,
+                steadiness,
+                path_stability,
+            )
+        })
+        .collect();
+    ranked(suspects)
+}
+
+/// Score the `n` call paths of allocation `event` that allocated the most
+/// across `file`'s windows, most likely leak first.
+///
+/// Bytes are the event's primary metric. Files without windows have no
+/// suspects.
+pub fn suspects_from_allocations(file: &SpaaFile, event: &str, n: usize) -> Vec<LeakSuspect> {
+    let metric = file
+        .primary_metric_for_event(event)
+        .unwrap_or("alloc_bytes");
+    let rates = allocation_rates(file, event, metric, usize::MAX);
+    let largest = rates.iter().map(|r| r.total).max().unwrap_or(0).max(1) as f64;
+    let mut by_site: HashMap<&str, u64> = HashMap::new();
+    for rate in &rates {
+        *by_site.entry(site(rate)).or_default() += rate.total;
+    }
+
+    let mut suspects: Vec<LeakSuspect> = rates
+        .iter()
+        .map(|rate| {
+            let site_total = by_site[site(rate)].max(1) as f64;
+            LeakSuspect::new(
+                site(rate).to_string(),
+                rate.functions.clone(),
+                rate.total,
+                rate.total as f64 / largest,
+                rate.growth_score,
+                rate.total as f64 / site_total,
+            )
+        })
+        .collect();
+    suspects = ranked(suspects);
+    suspects.truncate(n);
+    suspects
+}
+
+/// Write `suspects` as NDJSON, one `leak_suspect` record per line.
+pub fn write_ndjson<W: Write>(suspects: &[LeakSuspect], mut writer: W) -> io::Result<()> {
+    for suspect in suspects {
+        let mut record = serde_json::json!({ "type": "leak_suspect" });
+        if let (Some(record), Ok(serde_json::Value::Object(fields))) =
+            (record.as_object_mut(), serde_json::to_value(suspect))
+        {
+            record.extend(fields);
+        }
+        writeln!(writer, "{}", record)?;
+    }
+    Ok(())
+}
+
+/// Write `suspects` as a Markdown table with each signal's score.
+pub fn write_markdown<W: Write>(suspects: &[LeakSuspect], mut writer: W) -> io::Result<()> {
+    writeln!(writer, "# Likely leaks\n")?;
+    if suspects.is_empty() {
+        writeln!(writer, "No suspects.")?;
+        return Ok(());
+    }
+    writeln!(
+        writer,
+        "| # | Suspect | Score | Confidence | Bytes | Size | Steadiness | Path stability | Path |"
+    )?;
+    writeln!(writer, "|---|---|---|---|---|---|---|---|---|")?;
+    for (i, s) in suspects.iter().enumerate() {
+        writeln!(
+            writer,
+            "| {} | `{}` | {:.2} | {} | {} | {:.2} | {:.2} | {:.2} | `{}` |",
+            i + 1,
+            s.name.replace('|', "\\|").replace('`', "'"),
+            s.score,
+            s.confidence,
+            s.bytes,
+            s.size,
+            s.steadiness,
+            s.path_stability,
+            s.path.join(" -> ").replace('|', "\\|").replace('`', "'")
+        )?;
+    }
+    writeln!(writer)
+}
+
+fn site(rate: &AllocationRate) -> &str {
+    rate.functions.last().map_or("", String::as_str)
+}
+
+fn ranked(mut suspects: Vec<LeakSuspect>) -> Vec<LeakSuspect> {
+    suspects.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| b.bytes.cmp(&a.bytes))
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    suspects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heapdiff::{RetainedObject, TypeGrowth};
+    use std::io::Cursor;
+
+    fn growth(
+        constructor: &str,
+        count_before: u64,
+        count_after: u64,
+        size_delta: i64,
+    ) -> TypeGrowth {
+        TypeGrowth {
+            constructor: constructor.to_string(),
+            count_before,
+            count_after,
+            count_delta: count_after as i64 - count_before as i64,
+            size_before: 0,
+            size_after: size_delta as u64,
+            size_delta,
+            shapes: Vec::new(),
+        }
+    }
+
+    fn retained(constructor: &str, size: u64, path: &[&str]) -> RetainedObject {
+        RetainedObject {
+            constructor: constructor.to_string(),
+            size,
+            retention_path: path.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn new_objects_on_one_path_rank_above_scattered_churn() {
+        let diff = HeapDiff {
+            baseline_path: "a".to_string(),
+            target_path: "b".to_string(),
+            type_growth: vec![
+                // Every Item is new and held by the cache
+                growth("Item", 0, 100, 1000),
+                // Strings barely grew in number and are held all over
+                growth("string", 900, 1000, 1000),
+            ],
+            retained_objects: vec![
+                retained("Item", 500, &["Window", "cache", "[1]"]),
+                retained("Item", 500, &["Window", "cache", "[2]"]),
+                retained("string", 500, &["Window", "a"]),
+                retained("string", 500, &["Window", "b"]),
+            ],
+        };
+        let suspects = suspects_from_diff(&diff);
+
+        assert_eq!(suspects[0].name, "Item");
+        assert_eq!(suspects[0].path, vec!["Window", "cache", "[]"]);
+        assert_eq!(suspects[0].confidence, Confidence::High);
+        assert!((suspects[0].score - 1.0).abs() < 1e-9);
+        assert_eq!(suspects[1].name, "string");
+        assert!(suspects[1].score < suspects[0].score);
+    }
+
+    #[test]
+    fn steady_allocation_ranks_above_a_burst() {
+        let mut lines = vec![
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"chrome-heaptimeline","frame_order":"leaf_to_root","events":[{"name":"allocation","kind":"allocation","sampling":{"mode":"event","primary_metric":"alloc_bytes"}}]}"#.to_string(),
+            r#"{"type":"dso","id":1,"name":"app.js","is_kernel":false}"#.to_string(),
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#.to_string(),
+            r#"{"type":"frame","id":2,"func":"leak","dso":1}"#.to_string(),
+            r#"{"type":"frame","id":3,"func":"burst","dso":1}"#.to_string(),
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"allocation"},"weights":[{"metric":"alloc_bytes","value":400}]}"#.to_string(),
+            r#"{"type":"stack","id":"0x2","frames":[3,1],"context":{"event":"allocation"},"weights":[{"metric":"alloc_bytes","value":400}]}"#.to_string(),
+        ];
+        for i in 0..4 {
+            let burst = if i == 0 {
+                r#",{"stack_id":"0x2","weights":[{"metric":"alloc_bytes","value":400}]}"#
+            } else {
+                ""
+            };
+            lines.push(format!(
+                r#"{{"type":"window","id":"w{}","start":{},"end":{},"unit":"seconds","by_stack":[{{"stack_id":"0x1","weights":[{{"metric":"alloc_bytes","value":100}}]}}{}]}}"#,
+                i + 1,
+                i,
+                i + 1,
+                burst
+            ));
+        }
+        let file = SpaaFile::parse(Cursor::new(lines.join("\n"))).unwrap();
+        let suspects = suspects_from_allocations(&file, "allocation", 10);
+
+        assert_eq!(suspects.len(), 2);
+        assert_eq!(suspects[0].name, "leak");
+        assert_eq!(suspects[0].path, vec!["main", "leak"]);
+        assert_eq!(suspects[0].confidence, Confidence::High);
+        assert_eq!(suspects[1].name, "burst");
+        assert!(suspects[1].confidence < Confidence::High);
+    }
+}
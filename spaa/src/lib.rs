@@ -8,10 +8,40 @@
 //! - [`dtrace`] - Convert DTrace output to SPAA
 //! - [`perf`] - Convert Linux `perf script` output to SPAA
 //! - [`chrome`] - Convert Chrome DevTools profiles to SPAA
+//! - [`convert`] - Run any of the above by source format, on an in-memory buffer
+//! - [`config`] - `spaa.toml` defaults for converters: event names, frequency, rewrite and redaction rules
+//! - [`plugin`] - Converters for other formats, run as `spaa-convert-<name>` subprocesses
 //!
 //! # Analysis Tools
 //!
+//! - [`analysis`] - Call trees, hot paths, clustering, outliers, group-by and metric normalization
+//! - [`bisect`] - Find the first profile in a series where a function regressed
+//! - [`bundle`] - Pack a profile, report, flame graphs and validation into one archive for tickets
+//! - [`cgroup`] - Derive container and Kubernetes pod IDs from cgroup paths
+//! - [`cli`] - Exit codes and `--error-format json` reporting shared by the command-line tools
+//! - [`detectors`] - Flag known pathologies such as spin loops, memcpy, allocator and logging hot paths
+//! - [`export`] - Export profiles as folded stacks or d3-flame-graph JSON
 //! - [`heapdiff`] - Compare heap snapshots for memory leak analysis
+//! - [`leaks`] - Rank likely leaks from heap diffs or allocation timelines, with a confidence
+//! - [`limits`] - Cancellation and approximate memory budgets for call trees, heap diffs and aggregation
+//! - [`mcp`] - Model Context Protocol server for coding agents (`mcp` feature)
+//! - [`merge`] - Merge SPAA files with collision-safe ID remapping
+//! - [`packages`] - Attribute frames to Rust crates, npm packages and Java packages, with configurable rules
+//! - [`pmu`] - Descriptions and units for common perf events
+//! - [`progress`] - Progress reporting and cancellation for long-running operations
+//! - [`report`] - Markdown or plain-text narrative reports: hotspots, threads, allocations and next steps
+//! - [`service`] - Upload, convert, validate, summarize and query operations for a profile API, with an axum front end (`http` feature)
+//! - [`split`] - Split SPAA files by event, process or time window
+//! - [`stamp`] - Attach git, CI and version metadata to the header for regression tracking
+//! - [`stats`] - Record counts, weight totals and dictionary usage, computed in one streaming pass
+//! - [`store`] - Content-addressed profile catalog with metadata, labels and retention
+//! - [`synth`] - Deterministic synthetic profiles with configurable depth, symbols and event mix
+//! - [`symbols`] - Normalize symbol names so functions match across builds
+//! - [`system`] - Describe the local machine for the `system` record
+//! - [`testing`] - Semantic SPAA comparison and golden-corpus checks for converter tests
+//! - [`trim`] - Fit a profile to an LLM token budget, folding dropped stacks into `[other]`
+//! - [`topology`] - Annotate CPUs with socket, core and NUMA node from `lscpu`
+//! - [`view`] - Call-tree and flame navigation state for the `spaa view` TUI (`tui` feature)
 //!
 //! # Example
 //!
@@ -27,12 +57,52 @@
 //! converter.parse(input).unwrap();
 //! converter.write_spaa(output).unwrap();
 //! ```
+//!
+//! Every converter also has `to_spaa_file()`, which returns the profile as a
+//! [`spaa_parse::SpaaFile`] for analysis without a write and re-parse.
+//!
+//! All of them implement the [`Converter`] trait, whose `detect` method
+//! recognizes a format from the start of its input and whose errors share
+//! one [`convert::ConvertError`] type. [`detect_format`] asks every
+//! converter and picks the best match.
 
+pub mod analysis;
+pub mod bisect;
+pub mod bundle;
+pub mod cgroup;
 pub mod chrome;
+pub mod cli;
+pub mod config;
+pub mod convert;
+pub mod detectors;
 pub mod dtrace;
+pub mod export;
 pub mod heapdiff;
+pub mod leaks;
+pub mod limits;
+#[cfg(feature = "mcp")]
+pub mod mcp;
+pub mod merge;
+pub mod packages;
 pub mod perf;
+pub mod plugin;
+pub mod pmu;
+pub mod progress;
+pub mod report;
+pub mod service;
+pub mod split;
+pub mod stamp;
+pub mod stats;
+pub mod store;
+pub mod symbols;
+pub mod synth;
+pub mod system;
+pub mod testing;
+pub mod topology;
+pub mod trim;
 pub mod turbopack;
+pub mod view;
 
+pub use convert::{Confidence, Converter, detect_format};
 // Re-export spaa_parse for convenience
 pub use spaa_parse;
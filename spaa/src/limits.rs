@@ -0,0 +1,148 @@
+//! Cancellation and memory budgets for analyses run on behalf of others.
+//!
+//! Services embedding the crate pass [`Limits`] to the `_limited` variants
+//! of the expensive analyses, such as
+//! [`CallTree::build_limited`](crate::analysis::CallTree::build_limited)
+//! and [`HeapDiff::compute_limited`](crate::heapdiff::HeapDiff::compute_limited).
+//! They stop when the [`CancelToken`] is cancelled, failing with
+//! [`Cancelled`], or when their working state would grow past the memory
+//! budget, failing with [`BudgetExceeded`].
+//!
+//! Memory is accounted approximately, from the number and size of the
+//! entries a function adds to its maps, trees and tables, and covers only
+//! that function's working state, not the input profile or snapshot. Where
+//! a partial result is meaningful, such as a call tree of the stacks added
+//! before the budget ran out, the error carries it.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::analysis::{CallTree, TreeMode};
+//! use spaa::limits::{LimitError, Limits};
+//! use spaa_parse::SpaaFile;
+//!
+//! let spaa = SpaaFile::open("profile.spaa").unwrap();
+//! let limits = Limits::with_memory(64 << 20);
+//! let tree = match CallTree::build_limited(&spaa, "cycles", "period", TreeMode::TopDown, |_| true, &limits) {
+//!     Ok(tree) => tree,
+//!     Err(LimitError::BudgetExceeded(e)) => e.partial,
+//!     Err(LimitError::Cancelled(e)) => panic!("{e}"),
+//! };
+//! ```
+
+pub use spaa_parse::{CancelToken, Cancelled};
+use thiserror::Error;
+
+/// What an analysis may use: a token to cancel it with and a memory budget.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// Checked between units of work, such as before each stack.
+    pub cancel: CancelToken,
+    /// Approximate bytes of working state the analysis may allocate; no
+    /// limit when `None`.
+    pub memory: Option<u64>,
+}
+
+impl Limits {
+    /// Limits allowing `bytes` of working state, with a fresh token.
+    pub fn with_memory(bytes: u64) -> Self {
+        Self {
+            memory: Some(bytes),
+            ..Default::default()
+        }
+    }
+
+    /// Fail with [`Cancelled`] if the token has been cancelled.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        self.cancel.check()
+    }
+
+    /// A meter for one analysis run under these limits.
+    pub(crate) fn meter(&self) -> Meter {
+        Meter {
+            limit: self.memory,
+            used: 0,
+        }
+    }
+}
+
+impl From<CancelToken> for Limits {
+    fn from(cancel: CancelToken) -> Self {
+        Self {
+            cancel,
+            memory: None,
+        }
+    }
+}
+
+/// Error returned when an analysis would exceed its memory budget.
+///
+/// `partial` is what the analysis computed before it stopped, or `()` when
+/// nothing useful can be returned.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error(
+    "memory budget of {limit} bytes exceeded: {requested} more bytes needed with {used} in use"
+)]
+pub struct BudgetExceeded<T = ()> {
+    /// The budget, in bytes.
+    pub limit: u64,
+    /// Bytes accounted before the request that failed.
+    pub used: u64,
+    /// Bytes the analysis tried to add.
+    pub requested: u64,
+    /// What the analysis computed before it stopped.
+    pub partial: T,
+}
+
+impl BudgetExceeded {
+    /// Attach a partial result.
+    pub fn with_partial<T>(self, partial: T) -> BudgetExceeded<T> {
+        BudgetExceeded {
+            limit: self.limit,
+            used: self.used,
+            requested: self.requested,
+            partial,
+        }
+    }
+}
+
+/// Why a `_limited` analysis stopped.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum LimitError<T = ()> {
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+
+    #[error(transparent)]
+    BudgetExceeded(#[from] BudgetExceeded<T>),
+}
+
+/// Running account of one analysis' working state.
+#[derive(Debug, Clone)]
+pub(crate) struct Meter {
+    limit: Option<u64>,
+    used: u64,
+}
+
+impl Meter {
+    /// Account `bytes` more, failing without accounting them if that
+    /// exceeds the budget.
+    pub(crate) fn charge(&mut self, bytes: u64) -> Result<(), BudgetExceeded> {
+        match self.limit {
+            Some(limit) if self.used.saturating_add(bytes) > limit => Err(BudgetExceeded {
+                limit,
+                used: self.used,
+                requested: bytes,
+                partial: (),
+            }),
+            _ => {
+                self.used += bytes;
+                Ok(())
+            }
+        }
+    }
+
+    /// Account `count` more items of type `T`.
+    pub(crate) fn charge_items<T>(&mut self, count: usize) -> Result<(), BudgetExceeded> {
+        self.charge((count * std::mem::size_of::<T>()) as u64)
+    }
+}
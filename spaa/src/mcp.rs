@@ -0,0 +1,594 @@
+//! Model Context Protocol server (requires the `mcp` feature).
+//!
+//! [`Server`] answers MCP requests so coding agents can interrogate
+//! profiles interactively instead of reading whole files into their
+//! context. [`serve`] runs it over newline-delimited JSON-RPC, the MCP stdio
+//! transport; the `spaa-mcp` binary is a thin wrapper around it.
+//!
+//! Tools:
+//!
+//! - `load_profile` - Load a SPAA file under a name
+//! - `top_functions` - Functions ranked by self or total weight for an event
+//! - `get_stack` - One stack with its frames resolved
+//! - `diff_profiles` - Markdown comparison of two loaded profiles
+//! - `detect_issues` - Known-issue findings from the built-in detectors
+//! - `heap_diff` - Type growth and retained objects between two Chrome heap snapshots
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::mcp::{Server, serve};
+//!
+//! let stdin = std::io::stdin().lock();
+//! let stdout = std::io::stdout().lock();
+//! serve(&mut Server::new(), stdin, stdout).unwrap();
+//! ```
+
+use crate::analysis::{RankBy, stack_weight, top_functions};
+use crate::detectors::{DetectOptions, builtin_detectors, detect};
+use crate::heapdiff::{HeapDiff, ObjectMatch, ParsedSnapshot, WeakRefs};
+use crate::report::{ReportFormat, ReportOptions, build_comparison};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use spaa_parse::SpaaFile;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+/// Protocol versions the server can speak, newest first.
+pub const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// MCP server state: the profiles loaded so far, by name.
+#[derive(Debug, Default)]
+pub struct Server {
+    profiles: BTreeMap<String, SpaaFile>,
+}
+
+impl Server {
+    /// A server with no profiles loaded.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make an already-parsed profile available to tools under `name`.
+    pub fn insert(&mut self, name: impl Into<String>, file: SpaaFile) {
+        self.profiles.insert(name.into(), file);
+    }
+
+    /// Handle one JSON-RPC message, returning the response to send back.
+    ///
+    /// Notifications (messages without an `id`) never get a response.
+    pub fn handle(&mut self, message: &Value) -> Option<Value> {
+        let id = message.get("id").cloned();
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            return id.map(|id| error_response(id, INVALID_REQUEST, "missing method"));
+        };
+        let params = message.get("params").cloned().unwrap_or_else(|| json!({}));
+
+        let result = match method {
+            "initialize" => Ok(initialize(&params)),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": tool_definitions() })),
+            "tools/call" => self.call_tool(&params),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+        };
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => error_response(id, code, &message),
+        })
+    }
+
+    fn call_tool(&mut self, params: &Value) -> Result<Value, (i64, String)> {
+        let name = params
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or((INVALID_PARAMS, "missing tool name".to_string()))?;
+        let arguments = params
+            .get("arguments")
+            .cloned()
+            .unwrap_or_else(|| json!({}));
+
+        let output = match name {
+            "load_profile" => args(arguments).and_then(|a| self.load_profile(a)),
+            "top_functions" => args(arguments).and_then(|a| self.top_functions(a)),
+            "get_stack" => args(arguments).and_then(|a| self.get_stack(a)),
+            "diff_profiles" => args(arguments).and_then(|a| self.diff_profiles(a)),
+            "detect_issues" => args(arguments).and_then(|a| self.detect_issues(a)),
+            "heap_diff" => args(arguments).and_then(heap_diff),
+            _ => return Err((INVALID_PARAMS, format!("unknown tool '{}'", name))),
+        };
+        Ok(match output {
+            Ok(Value::String(text)) => json!({
+                "content": [{ "type": "text", "text": text }],
+                "isError": false,
+            }),
+            Ok(value) => json!({
+                "content": [{ "type": "text", "text": value.to_string() }],
+                "structuredContent": value,
+                "isError": false,
+            }),
+            Err(message) => json!({
+                "content": [{ "type": "text", "text": message }],
+                "isError": true,
+            }),
+        })
+    }
+
+    fn profile(&self, name: &str) -> Result<&SpaaFile, String> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| format!("profile '{}' is not loaded; call load_profile first", name))
+    }
+
+    fn load_profile(&mut self, args: LoadProfileArgs) -> Result<Value, String> {
+        let file = SpaaFile::open(&args.path).map_err(|e| format!("{}: {}", args.path, e))?;
+        let name = args.name.unwrap_or_else(|| {
+            Path::new(&args.path)
+                .file_stem()
+                .map_or_else(|| args.path.clone(), |s| s.to_string_lossy().into_owned())
+        });
+
+        let events: Vec<Value> = file
+            .header
+            .events
+            .iter()
+            .map(|event| {
+                let metric = &event.sampling.primary_metric;
+                let stacks: Vec<_> = file.stacks_for_event(&event.name).collect();
+                json!({
+                    "name": event.name,
+                    "metric": metric,
+                    "total": stacks.iter().map(|s| stack_weight(s, metric)).sum::<u64>(),
+                    "stacks": stacks.len(),
+                })
+            })
+            .collect();
+        let summary = json!({
+            "name": name,
+            "source_tool": file.header.source_tool,
+            "events": events,
+            "frames": file.frames.len(),
+            "stacks": file.stacks.len(),
+        });
+        self.profiles.insert(name, file);
+        Ok(summary)
+    }
+
+    fn top_functions(&self, args: TopFunctionsArgs) -> Result<Value, String> {
+        let file = self.profile(&args.profile)?;
+        let (event, metric) = event_and_metric(file, args.event, args.metric)?;
+
+        let total: u64 = file
+            .stacks_for_event(&event)
+            .map(|s| stack_weight(s, &metric))
+            .sum();
+        let functions = top_functions(file, &event, &metric, args.n, args.order);
+
+        Ok(json!({
+            "event": event,
+            "metric": metric,
+            "total": total,
+            "functions": functions,
+        }))
+    }
+
+    fn get_stack(&self, args: GetStackArgs) -> Result<Value, String> {
+        let file = self.profile(&args.profile)?;
+        let stack = file
+            .stacks
+            .get(&args.stack_id)
+            .ok_or_else(|| format!("no stack '{}' in '{}'", args.stack_id, args.profile))?;
+        let frames: Vec<Value> = stack
+            .frames
+            .iter()
+            .map(|&id| match file.resolve_frame(id) {
+                Some(frame) => json!({
+                    "id": id,
+                    "func": frame.func,
+                    "dso": file.resolve_dso(frame.dso).map(|d| &d.name),
+                    "srcline": frame.srcline,
+                    "inlined": frame.inlined,
+                }),
+                None => json!({ "id": id }),
+            })
+            .collect();
+        Ok(json!({
+            "frame_order": file.header.frame_order,
+            "stack": stack,
+            "frames": frames,
+        }))
+    }
+
+    fn diff_profiles(&self, args: DiffProfilesArgs) -> Result<Value, String> {
+        let baseline = self.profile(&args.baseline)?;
+        let current = self.profile(&args.current)?;
+        let options = ReportOptions {
+            top: args.top,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        build_comparison(baseline, current, &options)
+            .write(&mut out, ReportFormat::Markdown)
+            .map_err(|e| e.to_string())?;
+        Ok(Value::String(String::from_utf8_lossy(&out).into_owned()))
+    }
+
+    fn detect_issues(&self, args: DetectIssuesArgs) -> Result<Value, String> {
+        let file = self.profile(&args.profile)?;
+        let findings = detect(
+            file,
+            &builtin_detectors(),
+            &DetectOptions {
+                max_evidence: args.evidence,
+            },
+        );
+        Ok(json!({ "findings": findings }))
+    }
+}
+
+/// Serve MCP over newline-delimited JSON-RPC until `reader` reaches EOF.
+pub fn serve<R: BufRead, W: Write>(
+    server: &mut Server,
+    reader: R,
+    mut writer: W,
+) -> io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => server.handle(&message),
+            Err(e) => Some(error_response(Value::Null, PARSE_ERROR, &e.to_string())),
+        };
+        if let Some(response) = response {
+            writeln!(writer, "{}", response)?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+fn initialize(params: &Value) -> Value {
+    let requested = params.get("protocolVersion").and_then(Value::as_str);
+    let version = PROTOCOL_VERSIONS
+        .iter()
+        .find(|&&v| Some(v) == requested)
+        .unwrap_or(&PROTOCOL_VERSIONS[0]);
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": { "listChanged": false } },
+        "serverInfo": { "name": "spaa-mcp", "version": env!("CARGO_PKG_VERSION") },
+        "instructions": "Call load_profile with the path to a .spaa file, then query it by name.",
+    })
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+fn args<T: DeserializeOwned>(arguments: Value) -> Result<T, String> {
+    serde_json::from_value(arguments).map_err(|e| format!("invalid arguments: {}", e))
+}
+
+/// Resolve the event (default: the first in the header) and metric
+/// (default: the event's primary metric).
+fn event_and_metric(
+    file: &SpaaFile,
+    event: Option<String>,
+    metric: Option<String>,
+) -> Result<(String, String), String> {
+    let def = match &event {
+        Some(name) => file.header.events.iter().find(|e| &e.name == name),
+        None => file.header.events.first(),
+    }
+    .ok_or_else(|| format!("no event '{}'", event.unwrap_or_default()))?;
+    let metric = metric.unwrap_or_else(|| def.sampling.primary_metric.clone());
+    Ok((def.name.clone(), metric))
+}
+
+fn heap_diff(args: HeapDiffArgs) -> Result<Value, String> {
+    let load = |path: &str| {
+        let reader = BufReader::new(File::open(path).map_err(|e| format!("{}: {}", path, e))?);
+        ParsedSnapshot::parse(reader).map_err(|e| format!("{}: {}", path, e))
+    };
+    let baseline = load(&args.baseline)?;
+    let target = load(&args.target)?;
+    let mut diff = HeapDiff::compute(
+        &baseline,
+        &target,
+        &args.baseline,
+        &args.target,
+        args.max_retained,
+        if args.exclude_weak {
+            WeakRefs::Exclude
+        } else {
+            WeakRefs::Follow
+        },
+        ObjectMatch::Id,
+    );
+    diff.type_growth.truncate(args.top);
+    Ok(json!({
+        "type_growth": diff.type_growth,
+        "retained_objects": diff.retained_objects,
+    }))
+}
+
+#[derive(Deserialize)]
+struct LoadProfileArgs {
+    path: String,
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TopFunctionsArgs {
+    profile: String,
+    event: Option<String>,
+    metric: Option<String>,
+    #[serde(default = "default_top")]
+    n: usize,
+    #[serde(default)]
+    order: RankBy,
+}
+
+#[derive(Deserialize)]
+struct GetStackArgs {
+    profile: String,
+    stack_id: String,
+}
+
+#[derive(Deserialize)]
+struct DiffProfilesArgs {
+    baseline: String,
+    current: String,
+    #[serde(default = "default_top")]
+    top: usize,
+}
+
+#[derive(Deserialize)]
+struct DetectIssuesArgs {
+    profile: String,
+    #[serde(default = "default_evidence")]
+    evidence: usize,
+}
+
+#[derive(Deserialize)]
+struct HeapDiffArgs {
+    baseline: String,
+    target: String,
+    #[serde(default = "default_top")]
+    top: usize,
+    #[serde(default = "default_top")]
+    max_retained: usize,
+    #[serde(default)]
+    exclude_weak: bool,
+}
+
+fn default_top() -> usize {
+    10
+}
+
+fn default_evidence() -> usize {
+    3
+}
+
+/// `tools/list` entries, with JSON Schemas for each tool's arguments.
+fn tool_definitions() -> Value {
+    json!([
+        {
+            "name": "load_profile",
+            "description": "Load a SPAA profile from disk and summarize its events. Later tools refer to it by name.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "Path to a .spaa file" },
+                    "name": { "type": "string", "description": "Name to load it under (defaults to the file stem)" }
+                },
+                "required": ["path"]
+            }
+        },
+        {
+            "name": "top_functions",
+            "description": "Functions ranked by self or total weight for one event of a loaded profile.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "profile": { "type": "string" },
+                    "event": { "type": "string", "description": "Defaults to the first event" },
+                    "metric": { "type": "string", "description": "Defaults to the event's primary metric" },
+                    "n": { "type": "integer", "minimum": 0, "default": 10 },
+                    "order": { "type": "string", "enum": ["self", "total"], "default": "self" }
+                },
+                "required": ["profile"]
+            }
+        },
+        {
+            "name": "get_stack",
+            "description": "One stack of a loaded profile with its frames resolved to functions, DSOs and source lines.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "profile": { "type": "string" },
+                    "stack_id": { "type": "string" }
+                },
+                "required": ["profile", "stack_id"]
+            }
+        },
+        {
+            "name": "diff_profiles",
+            "description": "Markdown report of what changed between two loaded profiles.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "baseline": { "type": "string" },
+                    "current": { "type": "string" },
+                    "top": { "type": "integer", "minimum": 0, "default": 10 }
+                },
+                "required": ["baseline", "current"]
+            }
+        },
+        {
+            "name": "detect_issues",
+            "description": "Known performance pathologies in a loaded profile, with evidence stacks.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "profile": { "type": "string" },
+                    "evidence": { "type": "integer", "minimum": 0, "default": 3 }
+                },
+                "required": ["profile"]
+            }
+        },
+        {
+            "name": "heap_diff",
+            "description": "Compare two Chrome heap snapshots: object types that grew and what retains new objects.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "baseline": { "type": "string", "description": "Path to the earlier .heapsnapshot" },
+                    "target": { "type": "string", "description": "Path to the later .heapsnapshot" },
+                    "top": { "type": "integer", "minimum": 0, "default": 10 },
+                    "max_retained": { "type": "integer", "minimum": 0, "default": 10 },
+                    "exclude_weak": { "type": "boolean", "default": false, "description": "Ignore WeakMap, WeakSet, WeakRef and FinalizationRegistry references" }
+                },
+                "required": ["baseline", "target"]
+            }
+        }
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn server() -> Server {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1,"srcline":"parse.c:10"}"#,
+            r#"{"type":"frame","id":3,"func":"render","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":70}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[3,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":30}]}"#,
+        ]
+        .join("\n");
+        let mut server = Server::new();
+        server.insert("app", SpaaFile::parse(Cursor::new(data)).unwrap());
+        server
+    }
+
+    fn call(server: &mut Server, tool: &str, arguments: Value) -> Value {
+        let response = server
+            .handle(&json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": tool, "arguments": arguments },
+            }))
+            .unwrap();
+        response["result"].clone()
+    }
+
+    #[test]
+    fn initialize_echoes_supported_version() {
+        let response = Server::new()
+            .handle(&json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": "initialize",
+                "params": { "protocolVersion": "2024-11-05", "capabilities": {} },
+            }))
+            .unwrap();
+
+        assert_eq!(response["id"], 0);
+        assert_eq!(response["result"]["protocolVersion"], "2024-11-05");
+        assert!(response["result"]["capabilities"]["tools"].is_object());
+    }
+
+    #[test]
+    fn notifications_get_no_response() {
+        let response = Server::new().handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/initialized",
+        }));
+        assert!(response.is_none());
+    }
+
+    #[test]
+    fn unknown_method_is_an_error() {
+        let response = Server::new()
+            .handle(&json!({ "jsonrpc": "2.0", "id": 7, "method": "resources/list" }))
+            .unwrap();
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn top_functions_ranks_by_self_weight() {
+        let result = call(&mut server(), "top_functions", json!({ "profile": "app" }));
+        let functions = &result["structuredContent"]["functions"];
+
+        assert_eq!(functions[0]["function"], "parse");
+        assert_eq!(functions[0]["self_weight"], 70);
+        assert_eq!(functions[2]["function"], "main");
+        assert_eq!(functions[2]["total_share"], 1.0);
+    }
+
+    #[test]
+    fn get_stack_resolves_frames() {
+        let result = call(
+            &mut server(),
+            "get_stack",
+            json!({ "profile": "app", "stack_id": "0x1" }),
+        );
+        let frames = &result["structuredContent"]["frames"];
+
+        assert_eq!(frames[0]["func"], "parse");
+        assert_eq!(frames[0]["dso"], "/usr/bin/app");
+        assert_eq!(frames[0]["srcline"], "parse.c:10");
+    }
+
+    #[test]
+    fn unloaded_profile_is_a_tool_error() {
+        let result = call(
+            &mut server(),
+            "detect_issues",
+            json!({ "profile": "other" }),
+        );
+        assert_eq!(result["isError"], true);
+    }
+
+    #[test]
+    fn serve_answers_each_request_line() {
+        let input = concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+            "\n",
+            "not json\n",
+        );
+        let mut out = Vec::new();
+        serve(&mut Server::new(), Cursor::new(input), &mut out).unwrap();
+        let lines: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["result"], json!({}));
+        assert_eq!(lines[1]["error"]["code"], PARSE_ERROR);
+    }
+}
@@ -0,0 +1,1101 @@
+//! Merge multiple SPAA files into one.
+//!
+//! Merging remaps every dictionary and stack ID so references stay valid in
+//! the combined file:
+//!
+//! - DSOs and frames are deduplicated by content and renumbered.
+//! - Threads are deduplicated by `(pid, tid)`. A thread whose tid is already
+//!   taken by another process is given the next free tid, and its samples,
+//!   stack contexts and thread states follow.
+//! - In `content_addressable` mode, stacks with the same ID and identical
+//!   content are combined by summing their weights, exclusive weights
+//!   included. An ID that collides with a stack of different content is
+//!   given a fresh suffix.
+//! - In `local` mode (if any input uses it), IDs carry no meaning across
+//!   files, so every stack receives a new ID according to
+//!   [`IdRemapStrategy`].
+//!
+//! Every remapping is recorded in an [`IdAuditMap`] so merged IDs can be
+//! traced back to the file and ID they came from.
+//!
+//! Each input can additionally be scaled or have its events renamed with
+//! [`InputOptions`], and [`ConflictPolicy`] decides what happens when two
+//! inputs define the same event with different primary metrics.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::merge::{MergeOptions, Merger};
+//! use spaa_parse::SpaaFile;
+//! use std::fs::File;
+//!
+//! let a = SpaaFile::parse(File::open("a.spaa").unwrap()).unwrap();
+//! let b = SpaaFile::parse(File::open("b.spaa").unwrap()).unwrap();
+//!
+//! let mut merger = Merger::new(MergeOptions::default());
+//! merger.add("a.spaa", &a).unwrap();
+//! merger.add("b.spaa", &b).unwrap();
+//! let output = merger.finish().unwrap();
+//!
+//! output.file.write(File::create("merged.spaa").unwrap()).unwrap();
+//! output.audit.write_ndjson(File::create("merged.ids.ndjson").unwrap()).unwrap();
+//! ```
+
+use serde::Serialize;
+use spaa_parse::{
+    Cancelled, Dso, Frame, Header, NoProgress, Note, ProgressSink, Sample, SpaaFile, Stack,
+    StackIdMode, SystemInfo, Thread, ThreadState, Weight, Window, WindowStackWeight,
+};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use thiserror::Error;
+
+/// Errors that can occur while merging.
+#[derive(Error, Debug)]
+pub enum MergeError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("No input files to merge")]
+    NoInputs,
+
+    #[error(
+        "Event '{event}' uses primary metric '{existing}' in one input and '{incoming}' in '{source_name}'"
+    )]
+    ConflictingPrimaryMetric {
+        event: String,
+        existing: String,
+        incoming: String,
+        source_name: String,
+    },
+
+    #[error("Invalid scale {scale} for '{source_name}': must be finite and non-negative")]
+    InvalidScale { scale: f64, source_name: String },
+
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+}
+
+pub type Result<T> = std::result::Result<T, MergeError>;
+
+/// How new IDs are minted when an ID must be remapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdRemapStrategy {
+    /// Replace IDs with sequential hex numbers (`0x1`, `0x2`, ...).
+    ///
+    /// Original IDs do not appear in the output; use the audit map to
+    /// recover them.
+    #[default]
+    Sequential,
+    /// Prefix the original ID with the input's position (`1:0xabc`).
+    SourcePrefix,
+}
+
+/// What to do when an input defines an event that already exists with a
+/// different primary metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// Fail with [`MergeError::ConflictingPrimaryMetric`].
+    #[default]
+    Error,
+    /// Rename the incoming event to `<event>@<input index>` so both are kept.
+    Rename,
+    /// Drop the incoming event and every record that belongs to it.
+    Skip,
+}
+
+/// Options controlling a merge.
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Strategy for minting stack and window IDs that need remapping.
+    pub id_strategy: IdRemapStrategy,
+    /// Handling of conflicting event definitions.
+    pub conflicts: ConflictPolicy,
+}
+
+/// Per-input adjustments applied before merging.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputOptions {
+    /// Factor applied to every stack, exclusive and window weight (rounded
+    /// to the nearest integer). Useful for weighting shards that sampled a
+    /// different fraction of the fleet.
+    pub scale: f64,
+    /// Prefix prepended to every event name of this input, keeping its
+    /// events apart from identically named events in other inputs.
+    pub event_prefix: Option<String>,
+}
+
+impl Default for InputOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            event_prefix: None,
+        }
+    }
+}
+
+/// The kind of record an [`IdMapping`] refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdKind {
+    Dso,
+    Frame,
+    Thread,
+    Stack,
+    Window,
+}
+
+/// One original-to-merged ID mapping.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IdMapping {
+    /// Name of the input the record came from.
+    pub source: String,
+    pub kind: IdKind,
+    /// ID in the input file.
+    pub original: String,
+    /// ID in the merged file.
+    pub merged: String,
+}
+
+/// Record of every ID remapping performed by a merge.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct IdAuditMap {
+    pub entries: Vec<IdMapping>,
+}
+
+impl IdAuditMap {
+    /// All input records that ended up as `merged` in the output.
+    pub fn trace<'a>(
+        &'a self,
+        kind: IdKind,
+        merged: &'a str,
+    ) -> impl Iterator<Item = &'a IdMapping> + 'a {
+        self.entries
+            .iter()
+            .filter(move |m| m.kind == kind && m.merged == merged)
+    }
+
+    /// The merged ID for `original` from `source`, if it was mapped.
+    pub fn lookup(&self, source: &str, kind: IdKind, original: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|m| m.source == source && m.kind == kind && m.original == original)
+            .map(|m| m.merged.as_str())
+    }
+
+    /// Write the audit map as newline-delimited JSON, one mapping per line.
+    pub fn write_ndjson<W: Write>(&self, mut writer: W) -> Result<()> {
+        for entry in &self.entries {
+            serde_json::to_writer(&mut writer, entry)?;
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of a merge.
+#[derive(Debug, Clone)]
+pub struct MergeOutput {
+    pub file: SpaaFile,
+    pub audit: IdAuditMap,
+}
+
+/// Incrementally merges SPAA files.
+pub struct Merger {
+    options: MergeOptions,
+    header: Option<Header>,
+    system: Option<SystemInfo>,
+    dsos: HashMap<u64, Dso>,
+    dso_index: HashMap<(String, Option<String>, bool), u64>,
+    frames: HashMap<u64, Frame>,
+    frame_index: HashMap<String, u64>,
+    threads: HashMap<u64, Thread>,
+    stacks: HashMap<String, Stack>,
+    samples: Vec<Sample>,
+    windows: Vec<Window>,
+    states: Vec<ThreadState>,
+    notes: Vec<Note>,
+    audit: IdAuditMap,
+    inputs: usize,
+    next_stack_id: u64,
+    next_window_id: u64,
+}
+
+impl Merger {
+    /// Create an empty merger.
+    pub fn new(options: MergeOptions) -> Self {
+        Self {
+            options,
+            header: None,
+            system: None,
+            dsos: HashMap::new(),
+            dso_index: HashMap::new(),
+            frames: HashMap::new(),
+            frame_index: HashMap::new(),
+            threads: HashMap::new(),
+            stacks: HashMap::new(),
+            samples: Vec::new(),
+            windows: Vec::new(),
+            states: Vec::new(),
+            notes: Vec::new(),
+            audit: IdAuditMap::default(),
+            inputs: 0,
+            next_stack_id: 1,
+            next_window_id: 1,
+        }
+    }
+
+    /// Add a file to the merge.
+    ///
+    /// `source` identifies the file in the audit map (typically its path).
+    pub fn add(&mut self, source: &str, file: &SpaaFile) -> Result<()> {
+        self.add_with(source, file, &InputOptions::default())
+    }
+
+    /// Add a file to the merge, applying per-input `options` first.
+    pub fn add_with(
+        &mut self,
+        source: &str,
+        file: &SpaaFile,
+        options: &InputOptions,
+    ) -> Result<()> {
+        let input_index = self.inputs;
+        let file = self.prepare(source, input_index, file, options)?;
+        let file = file.as_ref();
+        self.merge_header(&file.header);
+        // Keep the machine only while every input was recorded on it.
+        if input_index == 0 {
+            self.system = file.system.clone();
+        } else if self.system != file.system {
+            self.system = None;
+        }
+        self.inputs += 1;
+
+        let output_order = self.header.as_ref().map(|h| h.frame_order);
+        let reverse = output_order != Some(file.header.frame_order);
+        let local = self
+            .header
+            .as_ref()
+            .is_some_and(|h| h.stack_id_mode == StackIdMode::Local);
+
+        // DSOs, deduplicated by identity.
+        let mut dso_map: HashMap<u64, u64> = HashMap::new();
+        let mut dsos: Vec<_> = file.dsos.values().collect();
+        dsos.sort_by_key(|d| d.id);
+        for dso in dsos {
+            let key = (dso.name.clone(), dso.build_id.clone(), dso.is_kernel);
+            let next_id = self.dsos.len() as u64 + 1;
+            let merged_id = *self.dso_index.entry(key).or_insert_with(|| {
+                self.dsos.insert(
+                    next_id,
+                    Dso {
+                        id: next_id,
+                        ..dso.clone()
+                    },
+                );
+                next_id
+            });
+            dso_map.insert(dso.id, merged_id);
+            self.record(source, IdKind::Dso, dso.id, merged_id);
+        }
+
+        // Frames, deduplicated by content.
+        let mut frame_map: HashMap<u64, u64> = HashMap::new();
+        let mut frames: Vec<_> = file.frames.values().collect();
+        frames.sort_by_key(|f| f.id);
+        for frame in frames {
+            let mut remapped = frame.clone();
+            remapped.id = 0;
+            remapped.dso = dso_map[&frame.dso];
+            let key = serde_json::to_string(&remapped)?;
+            let next_id = self.frames.len() as u64 + 1;
+            let merged_id = *self.frame_index.entry(key).or_insert_with(|| {
+                remapped.id = next_id;
+                self.frames.insert(next_id, remapped);
+                next_id
+            });
+            frame_map.insert(frame.id, merged_id);
+            self.record(source, IdKind::Frame, frame.id, merged_id);
+        }
+
+        // Threads, deduplicated by (pid, tid). Input tid -> (pid, merged tid)
+        // for threads whose tid was taken by another process.
+        let mut tid_map: HashMap<u64, (u64, u64)> = HashMap::new();
+        let mut threads: Vec<_> = file.threads.values().collect();
+        threads.sort_by_key(|t| t.tid);
+        for thread in threads {
+            let tid = match self.threads.get(&thread.tid) {
+                None => thread.tid,
+                Some(existing) if existing.pid == thread.pid => continue,
+                Some(_) => {
+                    let mut tid = thread.tid;
+                    while self.threads.contains_key(&tid) || file.threads.contains_key(&tid) {
+                        tid = tid.wrapping_add(1);
+                    }
+                    tid_map.insert(thread.tid, (thread.pid, tid));
+                    self.record(source, IdKind::Thread, thread.tid, tid);
+                    tid
+                }
+            };
+            self.threads.insert(
+                tid,
+                Thread {
+                    tid,
+                    ..thread.clone()
+                },
+            );
+        }
+        // A reference without a pid is taken to mean the input's thread.
+        let remap_tid = |pid: Option<u64>, tid: u64| match tid_map.get(&tid) {
+            Some(&(thread_pid, merged)) if pid.is_none_or(|pid| pid == thread_pid) => merged,
+            _ => tid,
+        };
+
+        // Stacks: assign every merged ID first so related_stacks can be
+        // rewritten in a second pass.
+        let mut stacks: Vec<_> = file.stacks.values().collect();
+        stacks.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut rewritten: Vec<Stack> = Vec::with_capacity(stacks.len());
+        for stack in &stacks {
+            let mut stack = (*stack).clone();
+            stack.frames = stack.frames.iter().map(|f| frame_map[f]).collect();
+            if reverse {
+                stack.frames.reverse();
+            }
+            if let Some(exclusive) = &mut stack.exclusive {
+                exclusive.frame = frame_map[&exclusive.frame];
+            }
+            if let Some(tid) = &mut stack.context.tid {
+                *tid = remap_tid(stack.context.pid, *tid);
+            }
+            rewritten.push(stack);
+        }
+
+        let mut stack_map: HashMap<String, String> = HashMap::new();
+        let mut combine: Vec<bool> = Vec::with_capacity(rewritten.len());
+        for stack in &rewritten {
+            let (merged_id, combined) = if local {
+                (self.mint_stack_id(input_index, &stack.id), false)
+            } else {
+                match self.stacks.get(&stack.id) {
+                    None => (stack.id.clone(), false),
+                    Some(existing) if same_content(existing, stack) => (stack.id.clone(), true),
+                    Some(_) => (self.collision_free_id(&stack.id), false),
+                }
+            };
+            stack_map.insert(stack.id.clone(), merged_id);
+            combine.push(combined);
+        }
+
+        for (mut stack, combined) in rewritten.into_iter().zip(combine) {
+            let original = std::mem::take(&mut stack.id);
+            let merged_id = stack_map[&original].clone();
+            if let Some(related) = &mut stack.related_stacks {
+                for id in related.iter_mut() {
+                    if let Some(mapped) = stack_map.get(id) {
+                        *id = mapped.clone();
+                    }
+                }
+            }
+            self.audit.entries.push(IdMapping {
+                source: source.to_string(),
+                kind: IdKind::Stack,
+                original,
+                merged: merged_id.clone(),
+            });
+
+            if combined {
+                let existing = self.stacks.get_mut(&merged_id).unwrap();
+                existing.merge_weights(&stack);
+            } else {
+                stack.id = merged_id.clone();
+                self.stacks.insert(merged_id, stack);
+            }
+        }
+
+        for sample in &file.samples {
+            let mut sample = sample.clone();
+            sample.stack_id = stack_map[&sample.stack_id].clone();
+            sample.tid = remap_tid(Some(sample.pid), sample.tid);
+            self.samples.push(sample);
+        }
+
+        for state in &file.states {
+            let mut state = state.clone();
+            state.tid = remap_tid(state.pid, state.tid);
+            if let Some(stack_id) = &mut state.stack_id
+                && let Some(mapped) = stack_map.get(stack_id)
+            {
+                *stack_id = mapped.clone();
+            }
+            self.states.push(state);
+        }
+
+        for note in &file.notes {
+            let mut note = note.clone();
+            note.frame_id = note.frame_id.and_then(|id| frame_map.get(&id).copied());
+            if let Some(stack_id) = &mut note.stack_id
+                && let Some(mapped) = stack_map.get(stack_id)
+            {
+                *stack_id = mapped.clone();
+            }
+            self.notes.push(note);
+        }
+
+        for window in &file.windows {
+            let merged_id = self.mint_window_id(input_index, &window.id);
+            self.audit.entries.push(IdMapping {
+                source: source.to_string(),
+                kind: IdKind::Window,
+                original: window.id.clone(),
+                merged: merged_id.clone(),
+            });
+            self.windows.push(Window {
+                id: merged_id,
+                // Entries for stacks the input does not define would point at
+                // whatever another input calls that ID, so they are dropped.
+                by_stack: window
+                    .by_stack
+                    .iter()
+                    .filter_map(|w| {
+                        Some(WindowStackWeight {
+                            stack_id: stack_map.get(&w.stack_id)?.clone(),
+                            weights: w.weights.clone(),
+                        })
+                    })
+                    .collect(),
+                ..window.clone()
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Finish the merge and return the combined file and audit map.
+    pub fn finish(self) -> Result<MergeOutput> {
+        let header = self.header.ok_or(MergeError::NoInputs)?;
+        Ok(MergeOutput {
+            file: SpaaFile {
+                header,
+                system: self.system,
+                dsos: self.dsos,
+                frames: self.frames,
+                threads: self.threads,
+                stacks: self.stacks,
+                samples: self.samples,
+                windows: self.windows,
+                states: self.states,
+                notes: self.notes,
+            },
+            audit: self.audit,
+        })
+    }
+
+    /// Apply `options` and the conflict policy to an input, cloning it only
+    /// when something has to change.
+    fn prepare<'a>(
+        &self,
+        source: &str,
+        input_index: usize,
+        file: &'a SpaaFile,
+        options: &InputOptions,
+    ) -> Result<Cow<'a, SpaaFile>> {
+        if !options.scale.is_finite() || options.scale < 0.0 {
+            return Err(MergeError::InvalidScale {
+                scale: options.scale,
+                source_name: source.to_string(),
+            });
+        }
+
+        let prefixed = |name: &str| match &options.event_prefix {
+            Some(prefix) => format!("{}{}", prefix, name),
+            None => name.to_string(),
+        };
+
+        let mut renames: HashMap<String, String> = HashMap::new();
+        let mut skipped: HashSet<String> = HashSet::new();
+        for event in &file.header.events {
+            let name = prefixed(&event.name);
+            let conflict = self.header.as_ref().and_then(|h| {
+                h.events.iter().find(|e| {
+                    e.name == name && e.sampling.primary_metric != event.sampling.primary_metric
+                })
+            });
+            match (conflict, self.options.conflicts) {
+                (Some(existing), ConflictPolicy::Error) => {
+                    return Err(MergeError::ConflictingPrimaryMetric {
+                        event: name,
+                        existing: existing.sampling.primary_metric.clone(),
+                        incoming: event.sampling.primary_metric.clone(),
+                        source_name: source.to_string(),
+                    });
+                }
+                (Some(_), ConflictPolicy::Rename) => {
+                    renames.insert(event.name.clone(), format!("{}@{}", name, input_index));
+                }
+                (Some(_), ConflictPolicy::Skip) => {
+                    skipped.insert(event.name.clone());
+                }
+                (None, _) if name != event.name => {
+                    renames.insert(event.name.clone(), name);
+                }
+                (None, _) => {}
+            }
+        }
+
+        if options.scale == 1.0 && renames.is_empty() && skipped.is_empty() {
+            return Ok(Cow::Borrowed(file));
+        }
+
+        let mut file = file.clone();
+        let rename = |name: &mut String| {
+            if let Some(renamed) = renames.get(name.as_str()) {
+                *name = renamed.clone();
+            }
+        };
+
+        if !skipped.is_empty() {
+            file.header.events.retain(|e| !skipped.contains(&e.name));
+            file.stacks
+                .retain(|_, stack| !skipped.contains(&stack.context.event));
+            let stacks: HashSet<String> = file.stacks.keys().cloned().collect();
+            file.samples.retain(|s| stacks.contains(&s.stack_id));
+            for stack in file.stacks.values_mut() {
+                if let Some(related) = &mut stack.related_stacks {
+                    related.retain(|id| stacks.contains(id));
+                }
+            }
+            for window in &mut file.windows {
+                window.by_stack.retain(|w| stacks.contains(&w.stack_id));
+            }
+            for state in &mut file.states {
+                if state
+                    .stack_id
+                    .as_ref()
+                    .is_some_and(|id| !stacks.contains(id))
+                {
+                    state.stack_id = None;
+                }
+            }
+            for note in &mut file.notes {
+                if note
+                    .stack_id
+                    .as_ref()
+                    .is_some_and(|id| !stacks.contains(id))
+                {
+                    note.stack_id = None;
+                }
+            }
+        }
+
+        for event in &mut file.header.events {
+            rename(&mut event.name);
+        }
+        for sample in &mut file.samples {
+            rename(&mut sample.event);
+        }
+        let scale = |weights: &mut [Weight]| {
+            for weight in weights {
+                weight.value = (weight.value as f64 * options.scale).round() as u64;
+            }
+        };
+        for stack in file.stacks.values_mut() {
+            rename(&mut stack.context.event);
+            scale(&mut stack.weights);
+            if let Some(exclusive) = &mut stack.exclusive {
+                scale(&mut exclusive.weights);
+            }
+        }
+        for window in &mut file.windows {
+            for entry in &mut window.by_stack {
+                scale(&mut entry.weights);
+            }
+        }
+
+        Ok(Cow::Owned(file))
+    }
+
+    /// Fold an input header into the merged header. Conflicting events have
+    /// already been resolved by [`Merger::prepare`].
+    fn merge_header(&mut self, incoming: &Header) {
+        let Some(header) = &mut self.header else {
+            self.header = Some(incoming.clone());
+            return;
+        };
+
+        for event in &incoming.events {
+            if !header.events.iter().any(|e| e.name == event.name) {
+                header.events.push(event.clone());
+            }
+        }
+
+        if header.source_tool != incoming.source_tool {
+            header.source_tool = "spaa-merge".to_string();
+        }
+
+        if incoming.stack_id_mode == StackIdMode::Local
+            && header.stack_id_mode != StackIdMode::Local
+        {
+            // Stacks already added kept their content-addressable IDs, which
+            // remain unique; from here on every stack is remapped.
+            header.stack_id_mode = StackIdMode::Local;
+        }
+
+        header.time_range = match (header.time_range.take(), &incoming.time_range) {
+            (Some(mut a), Some(b)) if a.unit == b.unit => {
+                a.start = a.start.min(b.start);
+                a.end = a.end.max(b.end);
+                Some(a)
+            }
+            (a, _) => a,
+        };
+    }
+
+    fn mint_stack_id(&mut self, input_index: usize, original: &str) -> String {
+        match self.options.id_strategy {
+            IdRemapStrategy::Sequential => loop {
+                let id = format!("0x{:x}", self.next_stack_id);
+                self.next_stack_id += 1;
+                if !self.stacks.contains_key(&id) {
+                    return id;
+                }
+            },
+            IdRemapStrategy::SourcePrefix => {
+                self.collision_free_id(&format!("{}:{}", input_index, original))
+            }
+        }
+    }
+
+    fn mint_window_id(&mut self, input_index: usize, original: &str) -> String {
+        match self.options.id_strategy {
+            IdRemapStrategy::Sequential => {
+                let id = format!("w{}", self.next_window_id);
+                self.next_window_id += 1;
+                id
+            }
+            IdRemapStrategy::SourcePrefix => format!("{}:{}", input_index, original),
+        }
+    }
+
+    /// `id` if unused, otherwise `id~N` for the smallest free N.
+    fn collision_free_id(&self, id: &str) -> String {
+        if !self.stacks.contains_key(id) {
+            return id.to_string();
+        }
+        (1..)
+            .map(|n| format!("{}~{}", id, n))
+            .find(|candidate| !self.stacks.contains_key(candidate))
+            .unwrap()
+    }
+
+    fn record(&mut self, source: &str, kind: IdKind, original: u64, merged: u64) {
+        self.audit.entries.push(IdMapping {
+            source: source.to_string(),
+            kind,
+            original: original.to_string(),
+            merged: merged.to_string(),
+        });
+    }
+}
+
+/// Merge `inputs` (pairs of source name and file) in order.
+pub fn merge(inputs: &[(&str, &SpaaFile)], options: MergeOptions) -> Result<MergeOutput> {
+    merge_with_progress(inputs, options, &mut NoProgress)
+}
+
+/// Like [`merge`], reporting the number of inputs merged to `progress`
+/// (stage `"merge"`).
+///
+/// Fails with [`MergeError::Cancelled`] if the sink cancels.
+pub fn merge_with_progress(
+    inputs: &[(&str, &SpaaFile)],
+    options: MergeOptions,
+    progress: &mut dyn ProgressSink,
+) -> Result<MergeOutput> {
+    let _span = tracing::debug_span!("merge", inputs = inputs.len()).entered();
+    let total = Some(inputs.len() as u64);
+    let mut merger = Merger::new(options);
+    for (i, (source, file)) in inputs.iter().enumerate() {
+        progress.update("merge", i as u64, total)?;
+        merger.add(source, file)?;
+    }
+    progress.update("merge", inputs.len() as u64, total)?;
+    merger.finish()
+}
+
+/// Stacks are the same if everything but their ID and weights matches.
+///
+/// Exclusive weights are weights too: one input may record them and another
+/// not, and [`Stack::merge_weights`] sums them per frame.
+fn same_content(a: &Stack, b: &Stack) -> bool {
+    a.frames == b.frames && a.stack_type == b.stack_type && a.context == b.context
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn local_file(func: &str, period: u64) -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","stack_id_mode":"local","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#.to_string(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#.to_string(),
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#.to_string(),
+            format!(r#"{{"type":"frame","id":2,"func":"{}","dso":1}}"#, func),
+            format!(
+                r#"{{"type":"stack","id":"1","frames":[2,1],"related_stacks":["2"],"context":{{"event":"cycles"}},"weights":[{{"metric":"period","value":{}}}]}}"#,
+                period
+            ),
+            r#"{"type":"stack","id":"2","frames":[1],"related_stacks":["1"],"context":{"event":"cycles"},"weights":[{"metric":"period","value":5}]}"#.to_string(),
+            r#"{"type":"sample","timestamp":1.0,"pid":1,"tid":1,"cpu":0,"event":"cycles","period":10,"stack_id":"1"}"#.to_string(),
+            r#"{"type":"window","id":"w","start":0.0,"end":1.0,"unit":"seconds","by_stack":[{"stack_id":"1","weights":[{"metric":"period","value":10}]}]}"#.to_string(),
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    fn content_file(period: u64) -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#.to_string(),
+            r#"{"type":"dso","id":7,"name":"/usr/bin/app","is_kernel":false}"#.to_string(),
+            r#"{"type":"frame","id":3,"func":"main","dso":7}"#.to_string(),
+            format!(
+                r#"{{"type":"stack","id":"0xabc","frames":[3],"context":{{"event":"cycles"}},"weights":[{{"metric":"period","value":{}}}]}}"#,
+                period
+            ),
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    /// A `main -> work` stack in `frame_order`, with `period` of exclusive
+    /// weight on `work` if `exclusive` is set.
+    fn leaf_file(frame_order: &str, period: u64, exclusive: bool) -> SpaaFile {
+        let frames = match frame_order {
+            "leaf_to_root" => "[4,3]",
+            _ => "[3,4]",
+        };
+        let exclusive = if exclusive {
+            format!(
+                r#","exclusive":{{"frame":4,"weights":[{{"metric":"period","value":{period}}}]}}"#
+            )
+        } else {
+            String::new()
+        };
+        let data = [
+            format!(r#"{{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"{frame_order}","events":[{{"name":"cycles","kind":"hardware","sampling":{{"mode":"period","primary_metric":"period"}}}}]}}"#),
+            r#"{"type":"dso","id":7,"name":"/usr/bin/app","is_kernel":false}"#.to_string(),
+            r#"{"type":"frame","id":3,"func":"main","dso":7}"#.to_string(),
+            r#"{"type":"frame","id":4,"func":"work","dso":7}"#.to_string(),
+            format!(
+                r#"{{"type":"stack","id":"0xabc","frames":{frames},"context":{{"event":"cycles"}},"weights":[{{"metric":"period","value":{period}}}]{exclusive}}}"#
+            ),
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn local_ids_are_remapped_without_collisions() {
+        let a = local_file("parse", 10);
+        let b = local_file("render", 20);
+        let output = merge(&[("a", &a), ("b", &b)], MergeOptions::default()).unwrap();
+
+        assert_eq!(output.file.stacks.len(), 4);
+        assert_eq!(output.file.header.stack_id_mode, StackIdMode::Local);
+        // "main" is shared; "parse" and "render" are distinct.
+        assert_eq!(output.file.frames.len(), 3);
+        assert_eq!(output.file.dsos.len(), 1);
+    }
+
+    #[test]
+    fn notes_follow_their_remapped_stacks_and_frames() {
+        let a = local_file("parse", 10);
+        let mut b = local_file("render", 20);
+        b.notes.push(Note {
+            author: "sam".to_string(),
+            timestamp: 1_700_000_000,
+            text: "render is slow".to_string(),
+            stack_id: Some("1".to_string()),
+            frame_id: Some(2),
+        });
+        let output = merge(&[("a", &a), ("b", &b)], MergeOptions::default()).unwrap();
+
+        let note = &output.file.notes[0];
+        let stack = &output.file.stacks[note.stack_id.as_ref().unwrap()];
+        let frame = &output.file.frames[&note.frame_id.unwrap()];
+        assert_eq!(frame.func, "render");
+        assert!(stack.frames.contains(&frame.id));
+    }
+
+    #[test]
+    fn merge_can_be_cancelled_between_inputs() {
+        let a = local_file("parse", 10);
+        let b = local_file("render", 20);
+        let mut seen = Vec::new();
+        let mut sink = |p: &spaa_parse::Progress| {
+            seen.push(p.done);
+            if p.done == 1 {
+                std::ops::ControlFlow::Break(())
+            } else {
+                std::ops::ControlFlow::Continue(())
+            }
+        };
+        let result =
+            merge_with_progress(&[("a", &a), ("b", &b)], MergeOptions::default(), &mut sink);
+
+        assert!(matches!(result, Err(MergeError::Cancelled(_))));
+        assert_eq!(seen, vec![0, 1]);
+    }
+
+    #[test]
+    fn audit_map_traces_stacks_to_sources() {
+        let a = local_file("parse", 10);
+        let b = local_file("render", 20);
+        let output = merge(&[("a", &a), ("b", &b)], MergeOptions::default()).unwrap();
+
+        let merged = output.audit.lookup("b", IdKind::Stack, "1").unwrap();
+        let traced: Vec<_> = output.audit.trace(IdKind::Stack, merged).collect();
+        assert_eq!(traced.len(), 1);
+        assert_eq!(traced[0].source, "b");
+        assert_eq!(traced[0].original, "1");
+        assert_eq!(output.file.stacks[merged].weights[0].value, 20);
+    }
+
+    #[test]
+    fn references_follow_remapped_ids() {
+        let a = local_file("parse", 10);
+        let b = local_file("render", 20);
+        let output = merge(&[("a", &a), ("b", &b)], MergeOptions::default()).unwrap();
+
+        let b1 = output.audit.lookup("b", IdKind::Stack, "1").unwrap();
+        let b2 = output.audit.lookup("b", IdKind::Stack, "2").unwrap();
+        assert_eq!(
+            output.file.stacks[b1].related_stacks,
+            Some(vec![b2.to_string()])
+        );
+        assert_eq!(output.file.samples[1].stack_id, b1);
+        assert_eq!(output.file.windows[1].by_stack[0].stack_id, b1);
+        assert_ne!(output.file.windows[0].id, output.file.windows[1].id);
+
+        // The merged file must still round-trip through validation.
+        let mut buf = Vec::new();
+        output.file.write(&mut buf).unwrap();
+        SpaaFile::parse(Cursor::new(buf)).unwrap();
+    }
+
+    #[test]
+    fn threads_from_different_processes_keep_their_own_tids() {
+        let thread = |pid, comm: &str| Thread {
+            pid,
+            tid: 1,
+            comm: Some(comm.to_string()),
+        };
+        let mut a = local_file("parse", 10);
+        a.threads.insert(1, thread(1, "parser"));
+        let mut b = local_file("render", 20);
+        b.threads.insert(1, thread(2, "renderer"));
+        b.samples[0].pid = 2;
+        let output = merge(&[("a", &a), ("b", &b)], MergeOptions::default()).unwrap();
+
+        assert_eq!(output.file.threads.len(), 2);
+        let tid = output.audit.lookup("b", IdKind::Thread, "1").unwrap();
+        let renderer = &output.file.threads[&tid.parse().unwrap()];
+        assert_eq!(renderer.comm.as_deref(), Some("renderer"));
+        assert_eq!(output.file.samples[0].tid, 1);
+        assert_eq!(output.file.samples[1].tid, renderer.tid);
+
+        // The same thread in another input is still deduplicated.
+        let output = merge(&[("a", &a), ("a2", &a)], MergeOptions::default()).unwrap();
+        assert_eq!(output.file.threads.len(), 1);
+    }
+
+    #[test]
+    fn window_entries_for_unknown_stacks_are_dropped() {
+        let a = local_file("parse", 10);
+        let mut b = local_file("render", 20);
+        b.windows[0].by_stack.push(WindowStackWeight {
+            stack_id: "missing".to_string(),
+            weights: Vec::new(),
+        });
+        let output = merge(&[("a", &a), ("b", &b)], MergeOptions::default()).unwrap();
+
+        assert_eq!(output.file.windows[1].by_stack.len(), 1);
+    }
+
+    #[test]
+    fn source_prefix_strategy_keeps_original_ids_visible() {
+        let a = local_file("parse", 10);
+        let b = local_file("render", 20);
+        let options = MergeOptions {
+            id_strategy: IdRemapStrategy::SourcePrefix,
+            ..Default::default()
+        };
+        let output = merge(&[("a", &a), ("b", &b)], options).unwrap();
+
+        assert!(output.file.stacks.contains_key("0:1"));
+        assert!(output.file.stacks.contains_key("1:2"));
+    }
+
+    #[test]
+    fn content_addressable_stacks_are_combined() {
+        let a = content_file(10);
+        let b = content_file(32);
+        let output = merge(&[("a", &a), ("b", &b)], MergeOptions::default()).unwrap();
+
+        assert_eq!(output.file.stacks.len(), 1);
+        assert_eq!(output.file.stacks["0xabc"].weights[0].value, 42);
+        assert_eq!(output.audit.trace(IdKind::Stack, "0xabc").count(), 2);
+    }
+
+    #[test]
+    fn exclusive_weights_are_summed_across_frame_orders() {
+        let a = leaf_file("leaf_to_root", 10, true);
+        let b = leaf_file("root_to_leaf", 32, true);
+        let output = merge(&[("a", &a), ("b", &b)], MergeOptions::default()).unwrap();
+
+        let stack = &output.file.stacks["0xabc"];
+        let work = output
+            .file
+            .frames
+            .values()
+            .find(|f| f.func == "work")
+            .unwrap();
+        assert_eq!(stack.frames[0], work.id);
+        let exclusive = stack.exclusive.as_ref().unwrap();
+        assert_eq!(exclusive.frame, work.id);
+        assert_eq!(exclusive.weights[0].value, 42);
+    }
+
+    #[test]
+    fn missing_exclusive_weights_do_not_split_a_stack() {
+        let a = leaf_file("leaf_to_root", 10, false);
+        let b = leaf_file("leaf_to_root", 32, true);
+        let output = merge(&[("a", &a), ("b", &b)], MergeOptions::default()).unwrap();
+
+        assert_eq!(output.file.stacks.len(), 1);
+        let stack = &output.file.stacks["0xabc"];
+        assert_eq!(stack.weights[0].value, 42);
+        assert_eq!(stack.exclusive.as_ref().unwrap().weights[0].value, 32);
+    }
+
+    #[test]
+    fn context_extensions_survive_merge() {
+        let extend = |file: SpaaFile| {
+            let mut file = file;
+            let context = &mut file.stacks.get_mut("0xabc").unwrap().context;
+            context.trace_fields = Some(HashMap::from([("fd".to_string(), 3.into())]));
+            context.extra.insert("numa_node".to_string(), 1.into());
+            file
+        };
+        let a = extend(content_file(10));
+        let b = extend(content_file(32));
+        let output = merge(&[("a", &a), ("b", &b)], MergeOptions::default()).unwrap();
+
+        let stack = &output.file.stacks["0xabc"];
+        assert_eq!(stack.context, a.stacks["0xabc"].context);
+        assert_eq!(stack.get_extra::<u32>("numa_node"), Some(1));
+        assert_eq!(stack.weights[0].value, 42);
+    }
+
+    #[test]
+    fn conflicting_primary_metric_is_an_error() {
+        let a = content_file(10);
+        let mut b = content_file(10);
+        b.header.events[0].sampling.primary_metric = "samples".to_string();
+
+        let err = merge(&[("a", &a), ("b", &b)], MergeOptions::default()).unwrap_err();
+        assert!(matches!(err, MergeError::ConflictingPrimaryMetric { .. }));
+    }
+
+    #[test]
+    fn conflicting_events_can_be_renamed_or_skipped() {
+        let a = content_file(10);
+        let mut b = content_file(10);
+        b.header.events[0].sampling.primary_metric = "samples".to_string();
+
+        let options = |conflicts| MergeOptions {
+            conflicts,
+            ..Default::default()
+        };
+        let renamed = merge(&[("a", &a), ("b", &b)], options(ConflictPolicy::Rename)).unwrap();
+        let events: Vec<_> = renamed
+            .file
+            .header
+            .events
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect();
+        assert_eq!(events, vec!["cycles", "cycles@1"]);
+        assert_eq!(renamed.file.stacks_for_event("cycles@1").count(), 1);
+
+        let skipped = merge(&[("a", &a), ("b", &b)], options(ConflictPolicy::Skip)).unwrap();
+        assert_eq!(skipped.file.header.events.len(), 1);
+        assert_eq!(skipped.file.stacks["0xabc"].weights[0].value, 10);
+    }
+
+    #[test]
+    fn inputs_can_be_scaled_and_prefixed() {
+        let a = content_file(10);
+        let b = content_file(10);
+
+        let mut merger = Merger::new(MergeOptions::default());
+        merger.add("a", &a).unwrap();
+        merger
+            .add_with(
+                "b",
+                &b,
+                &InputOptions {
+                    scale: 0.5,
+                    event_prefix: None,
+                },
+            )
+            .unwrap();
+        merger
+            .add_with(
+                "c",
+                &b,
+                &InputOptions {
+                    scale: 1.0,
+                    event_prefix: Some("host2/".to_string()),
+                },
+            )
+            .unwrap();
+        let output = merger.finish().unwrap();
+
+        assert_eq!(output.file.stacks["0xabc"].weights[0].value, 15);
+        let prefixed: Vec<_> = output.file.stacks_for_event("host2/cycles").collect();
+        assert_eq!(prefixed.len(), 1);
+        assert_ne!(prefixed[0].id, "0xabc");
+    }
+
+    #[test]
+    fn negative_scale_is_an_error() {
+        let a = content_file(10);
+        let mut merger = Merger::new(MergeOptions::default());
+        let err = merger
+            .add_with(
+                "a",
+                &a,
+                &InputOptions {
+                    scale: -1.0,
+                    event_prefix: None,
+                },
+            )
+            .unwrap_err();
+        assert!(matches!(err, MergeError::InvalidScale { .. }));
+    }
+
+    #[test]
+    fn no_inputs_is_an_error() {
+        assert!(matches!(
+            merge(&[], MergeOptions::default()),
+            Err(MergeError::NoInputs)
+        ));
+    }
+}
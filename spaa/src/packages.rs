@@ -0,0 +1,284 @@
+//! Attribute frames to the package, crate or module they belong to.
+//!
+//! "Which dependency is costing me" needs each frame mapped to a package.
+//! [`PackageMapper`] first tries the rules of a [`PackageMap`], then these
+//! heuristics:
+//!
+//! - Node: the package after the last `node_modules/` in the srcline,
+//!   including its `@scope/`
+//! - Rust: the crate of a Cargo registry source path
+//!   (`.../registry/src/<index>/serde_json-1.0.108/...` is `serde_json`),
+//!   else the first segment of a `crate::path::function` symbol
+//! - Java: up to the first three segments of a package-qualified method,
+//!   such as `org.apache.kafka` for
+//!   `org.apache.kafka.clients.producer.KafkaProducer.send`
+//!
+//! Frames that match nothing are attributed to their DSO's file name.
+//!
+//! Rules live in the `[packages]` table of `spaa.toml`, or in a file of
+//! the same shape:
+//!
+//! ```toml
+//! [packages]
+//! heuristics = true   # the default
+//!
+//! [[packages.rule]]
+//! name = "acme-core"
+//! functions = ['^acme_core::', '^com\.acme\.core\.']
+//! paths = ['/services/core/']
+//! ```
+//!
+//! # Example
+//!
+//! ```
+//! use spaa::packages::{PackageMap, PackageMapper};
+//!
+//! let mapper = PackageMapper::new(&PackageMap::default()).unwrap();
+//! assert_eq!(
+//!     mapper.package_of("tokio::runtime::park::Inner::park", None, "app"),
+//!     "tokio"
+//! );
+//! assert_eq!(
+//!     mapper.package_of("debounce", Some("webpack:///node_modules/@acme/ui/index.js:4:2"), "app.js"),
+//!     "@acme/ui"
+//! );
+//! ```
+
+use regex::Regex;
+use serde::Deserialize;
+use spaa_parse::srcline::SrcLine;
+use spaa_parse::{Frame, SpaaFile};
+
+/// Java package segments kept by the heuristic.
+const JAVA_PACKAGE_DEPTH: usize = 3;
+
+/// A package and the frames that belong to it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PackageRule {
+    /// Package name reported for matching frames.
+    pub name: String,
+    /// Patterns matched against function names.
+    pub functions: Vec<String>,
+    /// Patterns matched against the source file of the srcline.
+    pub paths: Vec<String>,
+    /// Patterns matched against DSO paths.
+    pub dsos: Vec<String>,
+}
+
+/// Package mapping settings: rules tried in order, then the heuristics.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PackageMap {
+    /// Fall back to the Node, Rust and Java heuristics.
+    pub heuristics: bool,
+    #[serde(rename = "rule")]
+    pub rules: Vec<PackageRule>,
+}
+
+impl Default for PackageMap {
+    fn default() -> Self {
+        Self {
+            heuristics: true,
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl PackageMap {
+    /// Read the `[packages]` table of a TOML file.
+    pub fn from_toml(text: &str) -> Result<Self, toml::de::Error> {
+        #[derive(Deserialize)]
+        struct File {
+            #[serde(default)]
+            packages: PackageMap,
+        }
+        Ok(toml::from_str::<File>(text)?.packages)
+    }
+}
+
+/// Compiled [`PackageRule`].
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    name: String,
+    functions: Vec<Regex>,
+    paths: Vec<Regex>,
+    dsos: Vec<Regex>,
+}
+
+/// Maps frames to packages with a compiled [`PackageMap`].
+#[derive(Debug, Clone)]
+pub struct PackageMapper {
+    rules: Vec<CompiledRule>,
+    heuristics: bool,
+}
+
+impl PackageMapper {
+    /// Compile `map`'s patterns.
+    pub fn new(map: &PackageMap) -> Result<Self, regex::Error> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| Regex::new(p))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let rules = map
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok(CompiledRule {
+                    name: rule.name.clone(),
+                    functions: compile(&rule.functions)?,
+                    paths: compile(&rule.paths)?,
+                    dsos: compile(&rule.dsos)?,
+                })
+            })
+            .collect::<Result<_, regex::Error>>()?;
+        Ok(Self {
+            rules,
+            heuristics: map.heuristics,
+        })
+    }
+
+    /// The package of `frame` in `file`.
+    pub fn package(&self, file: &SpaaFile, frame: &Frame) -> String {
+        let dso = file.dsos.get(&frame.dso).map_or("", |d| d.name.as_str());
+        self.package_of(&frame.func, frame.srcline.as_deref(), dso)
+    }
+
+    /// The package of a frame with function `func`, `srcline` and DSO path
+    /// `dso`.
+    pub fn package_of(&self, func: &str, srcline: Option<&str>, dso: &str) -> String {
+        let path = srcline.and_then(SrcLine::parse).map(|s| s.file);
+        for rule in &self.rules {
+            let matches = rule.functions.iter().any(|p| p.is_match(func))
+                || path.is_some_and(|path| rule.paths.iter().any(|p| p.is_match(path)))
+                || rule.dsos.iter().any(|p| p.is_match(dso));
+            if matches {
+                return rule.name.clone();
+            }
+        }
+        if self.heuristics
+            && let Some(package) = path
+                .and_then(|p| node_package(p).or_else(|| cargo_crate(p)))
+                .or_else(|| rust_crate(func))
+                .or_else(|| java_package(func))
+        {
+            return package;
+        }
+        let name = dso.rsplit(['/', '\\']).next().unwrap_or_default();
+        if name.is_empty() {
+            "[unknown]".to_string()
+        } else {
+            name.to_string()
+        }
+    }
+}
+
+/// The package after the last `node_modules/` in `path`.
+fn node_package(path: &str) -> Option<String> {
+    let (_, rest) = path.rsplit_once("node_modules/")?;
+    let mut segments = rest.split('/');
+    let first = segments.next().filter(|s| !s.is_empty())?;
+    if first.starts_with('@') {
+        Some(format!("{}/{}", first, segments.next()?))
+    } else {
+        Some(first.to_string())
+    }
+}
+
+/// The crate of a source file in Cargo's registry.
+fn cargo_crate(path: &str) -> Option<String> {
+    let path = path.replace('\\', "/");
+    let (_, rest) = path.split_once("/registry/src/")?;
+    // Skip the index directory, then strip the version from `name-1.2.3`.
+    let dir = rest.split('/').nth(1)?;
+    let name = match dir.rfind('-') {
+        Some(i) if dir[i + 1..].starts_with(|c: char| c.is_ascii_digit()) => &dir[..i],
+        _ => dir,
+    };
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// The first segment of a Rust `crate::path` symbol, looking through
+/// qualified paths like `<tokio::net::TcpStream as Read>::read`.
+fn rust_crate(func: &str) -> Option<String> {
+    let func = func.trim_start_matches(['<', '&']);
+    let func = func.strip_prefix("dyn ").unwrap_or(func);
+    let (first, _) = func.split_once("::")?;
+    let ident = !first.is_empty()
+        && first.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && first.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    ident.then(|| first.to_string())
+}
+
+/// Leading lowercase segments of a Java `package.Class.method` name (or
+/// `package/Class.method`, as async-profiler prints), up to
+/// [`JAVA_PACKAGE_DEPTH`].
+fn java_package(func: &str) -> Option<String> {
+    let func = func
+        .strip_prefix('L')
+        .filter(|f| f.contains('/'))
+        .unwrap_or(func);
+    let segments: Vec<&str> = func.split(['.', '/']).collect();
+    let package = segments
+        .iter()
+        .take_while(|s| {
+            s.starts_with(|c: char| c.is_ascii_lowercase())
+                && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+        .count();
+    // A package has at least two segments and is followed by a class.
+    let class = segments.get(package)?;
+    (package >= 2 && class.starts_with(|c: char| c.is_ascii_uppercase()))
+        .then(|| segments[..package.min(JAVA_PACKAGE_DEPTH)].join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heuristic(func: &str, srcline: Option<&str>) -> String {
+        PackageMapper::new(&PackageMap::default())
+            .unwrap()
+            .package_of(func, srcline, "/usr/lib/libfoo.so")
+    }
+
+    #[test]
+    fn heuristics_recognize_node_rust_and_java_packages() {
+        let node = Some("/srv/app/node_modules/express/node_modules/qs/lib/parse.js:3:1");
+        assert_eq!(heuristic("parse", node), "qs");
+        let cargo = Some(
+            "/home/ci/.cargo/registry/src/index.crates.io-6f17d22bba15001f/serde_json-1.0.108/src/de.rs:10",
+        );
+        assert_eq!(heuristic("from_str", cargo), "serde_json");
+        assert_eq!(
+            heuristic("<hyper::proto::h1::Conn<I> as Drop>::drop", None),
+            "hyper"
+        );
+        assert_eq!(
+            heuristic("org.apache.kafka.clients.producer.KafkaProducer.send", None),
+            "org.apache.kafka"
+        );
+        assert_eq!(heuristic("java/util/HashMap.get", None), "java.util");
+        assert_eq!(heuristic("memcpy", None), "libfoo.so");
+        assert_eq!(heuristic("memcpy.isra.0", None), "libfoo.so");
+    }
+
+    #[test]
+    fn rules_take_precedence_over_heuristics() {
+        let map = PackageMap::from_toml(
+            r#"
+            [[packages.rule]]
+            name = "acme"
+            functions = ['^tokio::']
+            "#,
+        )
+        .unwrap();
+        let mapper = PackageMapper::new(&map).unwrap();
+
+        assert_eq!(mapper.package_of("tokio::spawn", None, ""), "acme");
+        assert_eq!(mapper.package_of("hyper::serve", None, ""), "hyper");
+        assert_eq!(mapper.package_of("main", None, ""), "[unknown]");
+    }
+}
@@ -3,6 +3,15 @@
 //! This module parses the text output from `perf script` and converts it
 //! to the SPAA (Stack Profile for Agentic Analysis) format.
 //!
+//! Scheduler tracepoints (`sched:sched_switch`, `sched:sched_wakeup`,
+//! `sched:sched_wakeup_new` and `sched:sched_migrate_task`) are additionally
+//! turned into per-thread `state` records describing when each thread was
+//! running, runnable or blocked.
+//!
+//! When samples carry a cgroup path (`perf record --all-cgroups` with
+//! `perf script -F +cgroup`), stacks are split per cgroup and their context
+//! records the cgroup plus any container or Kubernetes pod ID it encodes.
+//!
 //! # Example
 //!
 //! ```no_run
@@ -18,13 +27,17 @@
 //! converter.write_spaa(output).unwrap();
 //! ```
 
-use serde::Serialize;
+use crate::cgroup::container_info;
+use crate::convert::{Fidelity, StreamFrame, StreamSample, WindowPolicy, WindowedWriter};
 use spaa_parse::{
-    EventDef, EventKind, ExclusiveWeights, FrameKind, FrameOrder, Header, Sampling, SamplingMode,
-    StackContext, StackIdMode, StackType, Weight,
+    ClockInfo, ClockSource, DataLoss, Dso, EventDef, EventKind, ExclusiveWeights, Frame, FrameKind,
+    FrameOrder, Header, Sample, Sampling, SamplingMode, SpaaFile, Stack, StackContext, StackIdMode,
+    StackType, SystemInfo, TRUNCATED_FRAME_NAME, Thread, ThreadState, ThreadStateKind, Weight,
+    WriteError, truncate_frames,
 };
-use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
 use std::io::{BufRead, BufReader, Read, Write};
 use thiserror::Error;
 
@@ -37,29 +50,54 @@ pub enum ConvertError {
     #[error("JSON serialization error: {0}")]
     Json(#[from] serde_json::Error),
 
+    #[error("SPAA write error: {0}")]
+    Write(#[from] WriteError),
+
     #[error("parse error at line {line}: {message}")]
     Parse { line: usize, message: String },
 
     #[error("no samples found in input")]
     NoSamples,
+
+    #[error("input has more than {} distinct frames", FrameHandle::MAX)]
+    TooManyFrames,
 }
 
 pub type Result<T> = std::result::Result<T, ConvertError>;
 
+/// Symbol perf prints for frames it could not resolve.
+const UNKNOWN_SYMBOL: &str = "[unknown]";
+
 /// A parsed sample from perf script output.
 #[derive(Debug, Clone)]
 struct PerfSample {
     comm: String,
     pid: u64,
     tid: u64,
-    #[allow(dead_code)] // Preserved for potential future use in per-sample output
     cpu: Option<u32>,
     timestamp: Option<f64>,
     period: u64,
     event: String,
+    /// Tracepoint payload following the event name, if any.
+    trace_args: Option<String>,
+    /// cgroup path, when recorded with `--all-cgroups` and printed with
+    /// `perf script -F +cgroup`.
+    cgroup: Option<String>,
     frames: Vec<PerfFrame>,
 }
 
+/// A thread state interval that hasn't ended yet.
+#[derive(Debug, Clone)]
+struct OpenState {
+    pid: Option<u64>,
+    state: ThreadStateKind,
+    start: f64,
+    cpu: Option<u32>,
+    reason: Option<String>,
+    /// The stack the thread stopped in.
+    stack_id: Option<String>,
+}
+
 /// A parsed stack frame from perf script output.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct PerfFrame {
@@ -70,11 +108,92 @@ struct PerfFrame {
     srcline: Option<String>,
 }
 
+/// Index of a [`PerfFrame`] in a [`FrameArena`].
+type FrameHandle = u32;
+
+/// Distinct frames seen while parsing, each stored once.
+///
+/// A capture repeats the same few thousand frames across millions of
+/// samples; stacks refer to them by handle. A frame's SPAA ID is its handle
+/// plus one.
+#[derive(Debug, Default)]
+struct FrameArena {
+    frames: Vec<PerfFrame>,
+    /// First handle of each hash chain, keyed by frame hash.
+    heads: HashMap<u64, FrameHandle>,
+    /// Next handle in the same hash chain, parallel to `frames`.
+    next: Vec<Option<FrameHandle>>,
+    hasher: BuildHasherDefault<DefaultHasher>,
+}
+
+impl FrameArena {
+    /// The handle of `frame`, adding it if it is new, or `None` if it is
+    /// new and every handle is taken.
+    fn intern(&mut self, frame: PerfFrame) -> Option<FrameHandle> {
+        let hash = self.hasher.hash_one(&frame);
+        let head = self.heads.get(&hash).copied();
+        let mut cursor = head;
+        while let Some(handle) = cursor {
+            if self.frames[handle as usize] == frame {
+                return Some(handle);
+            }
+            cursor = self.next[handle as usize];
+        }
+        let handle = FrameHandle::try_from(self.frames.len()).ok()?;
+        self.frames.push(frame);
+        self.next.push(head);
+        self.heads.insert(hash, handle);
+        Some(handle)
+    }
+
+    /// Frames with their SPAA IDs.
+    fn iter(&self) -> impl Iterator<Item = (u64, &PerfFrame)> {
+        self.frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| (i as u64 + 1, frame))
+    }
+
+    /// SPAA frame IDs of `handles`.
+    fn ids(handles: &[FrameHandle]) -> Vec<u64> {
+        handles.iter().map(|&h| u64::from(h) + 1).collect()
+    }
+}
+
 /// Converter from perf script output to SPAA format.
+///
+/// Samples are aggregated as they are parsed, so memory grows with the
+/// number of distinct frames and stacks rather than with the length of the
+/// capture.
 pub struct PerfConverter {
-    samples: Vec<PerfSample>,
-    events: HashMap<String, EventInfo>,
+    /// DSO IDs, assigned in order of first appearance.
+    dso_ids: HashMap<String, u64>,
+    /// Frames, in order of first appearance.
+    frames: FrameArena,
+    threads: BTreeSet<(u64, u64)>,
+    stacks: BTreeMap<StackKey, StackData>,
+    /// Sample records, kept only when `emit_samples` is set.
+    sample_records: Vec<Sample>,
+    sample_count: u64,
+    /// Events in order of first appearance.
+    events: Vec<EventInfo>,
     time_range: Option<(f64, f64)>,
+    max_stack_depth: Option<usize>,
+    emit_samples: bool,
+    truncated_stacks: u64,
+    lost_events: u64,
+    lost_records: u64,
+    unknown_frames: u64,
+    open_states: HashMap<u64, OpenState>,
+    closed_states: Vec<(u64, OpenState, f64)>,
+    last_sched_timestamp: Option<f64>,
+    migrations: u64,
+    /// Clock of the timestamps, from `perf script --header` when present.
+    clock: ClockInfo,
+    /// Host the capture was recorded on, from `perf script --header`.
+    system: SystemInfo,
+    strict: bool,
+    fidelity: Fidelity,
 }
 
 #[derive(Debug, Clone)]
@@ -87,40 +206,186 @@ impl PerfConverter {
     /// Create a new converter.
     pub fn new() -> Self {
         Self {
-            samples: Vec::new(),
-            events: HashMap::new(),
+            dso_ids: HashMap::new(),
+            frames: FrameArena::default(),
+            threads: BTreeSet::new(),
+            stacks: BTreeMap::new(),
+            sample_records: Vec::new(),
+            sample_count: 0,
+            events: Vec::new(),
             time_range: None,
+            max_stack_depth: None,
+            emit_samples: false,
+            truncated_stacks: 0,
+            lost_events: 0,
+            lost_records: 0,
+            unknown_frames: 0,
+            open_states: HashMap::new(),
+            closed_states: Vec::new(),
+            last_sched_timestamp: None,
+            migrations: 0,
+            clock: ClockInfo::new(ClockSource::Monotonic),
+            system: SystemInfo::default(),
+            strict: false,
+            fidelity: Fidelity::default(),
         }
     }
 
+    /// Truncate sample stacks deeper than `max_depth` frames.
+    ///
+    /// The leaf-most `max_depth` frames are kept and the rest are replaced
+    /// by a synthetic `[truncated]` frame.
+    pub fn with_max_stack_depth(mut self, max_depth: usize) -> Self {
+        self.max_stack_depth = Some(max_depth);
+        self
+    }
+
+    /// Fail on lines that would otherwise be skipped as unparsable, such
+    /// as malformed frame lines.
+    pub fn with_strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Also write a `sample` record for every parsed sample that has a
+    /// timestamp, preserving the timeline needed for temporal analysis such
+    /// as entry/exit probe pairing.
+    pub fn with_samples(mut self) -> Self {
+        self.emit_samples = true;
+        self
+    }
+
+    /// Number of sample stacks truncated because of the max stack depth.
+    pub fn truncated_stacks(&self) -> u64 {
+        self.truncated_stacks
+    }
+
+    /// Number of events perf reported as lost (`PERF_RECORD_LOST` and
+    /// `PERF_RECORD_LOST_SAMPLES` records).
+    pub fn lost_events(&self) -> u64 {
+        self.lost_events
+    }
+
+    /// Number of lost-data records and "lost N chunks" warnings seen.
+    pub fn lost_records(&self) -> u64 {
+        self.lost_records
+    }
+
+    /// Number of `[unknown]` frames across all parsed samples.
+    pub fn unknown_frames(&self) -> u64 {
+        self.unknown_frames
+    }
+
+    /// Number of `sched:sched_migrate_task` events seen.
+    pub fn migrations(&self) -> u64 {
+        self.migrations
+    }
+
+    /// Number of samples with a stack aggregated so far.
+    pub fn sample_count(&self) -> u64 {
+        self.sample_count
+    }
+
     /// Parse perf script output from a reader.
     pub fn parse<R: Read>(&mut self, reader: R) -> Result<()> {
+        self.read_samples(reader, |this, sample| this.add_sample(sample))
+    }
+
+    /// Convert perf script output as it is read, such as a live
+    /// `perf record -o - | perf script` pipe, writing a window of aggregated
+    /// stacks each time `policy` closes one. Memory stays bounded by the
+    /// number of distinct frames and stacks however long the input runs.
+    ///
+    /// Thread states and raw samples are not produced, and the header
+    /// cannot record the time range or lost events. See
+    /// [`WindowedWriter`] for how stack totals are written.
+    pub fn stream<R: Read, W: Write>(
+        &mut self,
+        reader: R,
+        writer: W,
+        policy: WindowPolicy,
+    ) -> Result<W> {
+        let mut out = WindowedWriter::new(writer, self.build_header(), policy);
+        self.read_samples(reader, |this, mut sample| {
+            this.truncate(&mut sample);
+            if sample.frames.is_empty() {
+                return Ok(());
+            }
+            let events = this.events.len();
+            this.tally(&sample);
+            if this.events.len() > events {
+                out.declare_event(Self::event_def(&this.events[events]));
+            }
+            out.push(StreamSample {
+                timestamp: sample.timestamp,
+                context: Self::stack_context(
+                    &sample.event,
+                    sample.pid,
+                    sample.tid,
+                    &sample.comm,
+                    sample.cgroup.as_deref(),
+                ),
+                weights: Self::weights(1, sample.period),
+                frames: sample
+                    .frames
+                    .into_iter()
+                    .map(|f| StreamFrame {
+                        kind: Self::frame_kind(&f.dso),
+                        func: f.symbol,
+                        dso: f.dso,
+                        ip: Some(format!("0x{}", f.ip)),
+                        symoff: f.offset,
+                        srcline: f.srcline,
+                    })
+                    .collect(),
+            })?;
+            Ok(())
+        })?;
+        Ok(out.finish()?)
+    }
+
+    /// Parse samples from `reader`, handing each to `sink`.
+    fn read_samples<R: Read>(
+        &mut self,
+        reader: R,
+        mut sink: impl FnMut(&mut Self, PerfSample) -> Result<()>,
+    ) -> Result<()> {
         let buf_reader = BufReader::new(reader);
         let mut current_sample: Option<PerfSample> = None;
-        let mut line_num = 0;
-
-        for line_result in buf_reader.lines() {
-            line_num += 1;
+        for (line_idx, line_result) in buf_reader.lines().enumerate() {
+            let line_num = line_idx + 1;
             let line = line_result?;
+            self.fidelity.records_read += 1;
+
+            if let Some(comment) = line.strip_prefix('#') {
+                self.parse_header_comment(comment);
+            }
 
             // Skip empty lines and comments
             if line.trim().is_empty() || line.starts_with('#') {
                 // If we have a current sample and hit empty line, finalize it
                 if let Some(sample) = current_sample.take() {
-                    if !sample.frames.is_empty() {
-                        self.add_sample(sample);
-                    }
+                    self.finish_sample(sample, &mut sink)?;
                 }
                 continue;
             }
 
+            // Lost-data records and warnings (from `--show-lost-events` or
+            // captured stderr) are tallied rather than parsed as samples.
+            if let Some(lost) = Self::parse_lost_line(&line) {
+                self.lost_records += 1;
+                self.lost_events += lost;
+                continue;
+            }
+            if line.starts_with("Warning:") {
+                continue;
+            }
+
             // Check if this is a sample header line or a stack frame
             if !line.starts_with('\t') && !line.starts_with(' ') {
                 // Finalize previous sample
                 if let Some(sample) = current_sample.take() {
-                    if !sample.frames.is_empty() {
-                        self.add_sample(sample);
-                    }
+                    self.finish_sample(sample, &mut sink)?;
                 }
 
                 // Parse new sample header
@@ -129,6 +394,7 @@ impl PerfConverter {
                     Err(msg) => {
                         // Could be a header line from perf script --header, skip it
                         if !line.contains(':') {
+                            self.skip_line(line_num, &line)?;
                             continue;
                         }
                         return Err(ConvertError::Parse {
@@ -139,33 +405,150 @@ impl PerfConverter {
                 }
             } else if let Some(ref mut sample) = current_sample {
                 // Parse stack frame
-                if let Some(frame) = Self::parse_frame(&line) {
-                    sample.frames.push(frame);
+                match Self::parse_frame(&line) {
+                    Some(frame) => sample.frames.push(frame),
+                    None => self.skip_line(line_num, &line)?,
                 }
+            } else {
+                self.skip_line(line_num, &line)?;
             }
         }
 
         // Finalize last sample
         if let Some(sample) = current_sample {
-            if !sample.frames.is_empty() {
-                self.add_sample(sample);
-            }
+            self.finish_sample(sample, &mut sink)?;
         }
 
         Ok(())
     }
 
-    fn add_sample(&mut self, sample: PerfSample) {
+    /// Skip an unparsable line, or fail in strict mode.
+    fn skip_line(&mut self, line_num: usize, line: &str) -> Result<()> {
+        if self.strict {
+            return Err(ConvertError::Parse {
+                line: line_num,
+                message: format!("unparsable line '{}'", line.trim()),
+            });
+        }
+        self.fidelity.unparsed(line_num, line);
+        Ok(())
+    }
+
+    /// Count `sample` for the fidelity report and hand it to `sink`.
+    fn finish_sample(
+        &mut self,
+        sample: PerfSample,
+        sink: &mut impl FnMut(&mut Self, PerfSample) -> Result<()>,
+    ) -> Result<()> {
+        self.fidelity.samples_read += 1;
+        if sample.frames.is_empty() {
+            self.fidelity.samples_dropped += 1;
+        }
+        sink(self, sample)
+    }
+
+    /// Intern the sample's frames, update thread states, and add it to the
+    /// aggregated stacks.
+    fn add_sample(&mut self, mut sample: PerfSample) -> Result<()> {
+        self.truncate(&mut sample);
+        if !sample.frames.is_empty() {
+            self.tally(&sample);
+        }
+        let frames: Vec<FrameHandle> = std::mem::take(&mut sample.frames)
+            .into_iter()
+            .map(|frame| self.intern(frame))
+            .collect::<Result<_>>()?;
+        let key = StackKey {
+            frames,
+            event: sample.event.clone(),
+            pid: sample.pid,
+            tid: sample.tid,
+            comm: std::mem::take(&mut sample.comm),
+            cgroup: sample.cgroup.take(),
+        };
+        self.track_sched(&sample, &key);
+        if key.frames.is_empty() {
+            return Ok(());
+        }
+
+        self.sample_count += 1;
+        self.threads.insert((sample.pid, sample.tid));
+        if self.emit_samples
+            && let Some(timestamp) = sample.timestamp
+        {
+            self.sample_records.push(Sample {
+                timestamp,
+                pid: sample.pid,
+                tid: sample.tid,
+                cpu: sample.cpu.unwrap_or(0),
+                event: sample.event.clone(),
+                period: Some(sample.period),
+                stack_id: Self::compute_stack_id(&key),
+                context: HashMap::new(),
+            });
+        }
+        let data = self.stacks.entry(key).or_insert(StackData {
+            sample_count: 0,
+            total_period: 0,
+        });
+        data.sample_count += 1;
+        data.total_period += sample.period;
+        Ok(())
+    }
+
+    /// The handle of `frame`, adding it (and assigning its DSO an ID) if
+    /// it is new.
+    fn intern(&mut self, frame: PerfFrame) -> Result<FrameHandle> {
+        let dso = (!self.dso_ids.contains_key(&frame.dso)).then(|| frame.dso.clone());
+        let handle = self
+            .frames
+            .intern(frame)
+            .ok_or(ConvertError::TooManyFrames)?;
+        if let Some(dso) = dso {
+            let id = self.dso_ids.len() as u64 + 1;
+            self.dso_ids.insert(dso, id);
+        }
+        Ok(handle)
+    }
+
+    /// Apply the maximum stack depth to `sample`.
+    fn truncate(&mut self, sample: &mut PerfSample) {
+        let Some(max_depth) = self.max_stack_depth else {
+            return;
+        };
+        let sentinel = PerfFrame {
+            ip: "0".to_string(),
+            symbol: TRUNCATED_FRAME_NAME.to_string(),
+            offset: None,
+            dso: TRUNCATED_FRAME_NAME.to_string(),
+            srcline: None,
+        };
+        if truncate_frames(
+            &mut sample.frames,
+            max_depth,
+            FrameOrder::LeafToRoot,
+            sentinel,
+        ) {
+            self.truncated_stacks += 1;
+        }
+    }
+
+    /// Count unknown frames and track the event and time range of a sample
+    /// with a stack.
+    fn tally(&mut self, sample: &PerfSample) {
+        self.unknown_frames += sample
+            .frames
+            .iter()
+            .filter(|f| f.symbol == UNKNOWN_SYMBOL)
+            .count() as u64;
+
         // Track event types
-        if !self.events.contains_key(&sample.event) {
+        if !self.events.iter().any(|e| e.name == sample.event) {
             let kind = Self::classify_event(&sample.event);
-            self.events.insert(
-                sample.event.clone(),
-                EventInfo {
-                    name: sample.event.clone(),
-                    kind,
-                },
-            );
+            self.events.push(EventInfo {
+                name: sample.event.clone(),
+                kind,
+            });
         }
 
         // Track time range
@@ -182,8 +565,198 @@ impl PerfConverter {
                 }
             }
         }
+    }
 
-        self.samples.push(sample);
+    /// Update thread state timelines from a scheduler tracepoint sample.
+    fn track_sched(&mut self, sample: &PerfSample, key: &StackKey) {
+        let (Some(ts), Some(args)) = (sample.timestamp, sample.trace_args.as_deref()) else {
+            return;
+        };
+        let fields = Self::parse_trace_fields(args);
+        let field = |key: &str| fields.get(key).and_then(|v| v.parse::<u64>().ok());
+
+        match sample.event.as_str() {
+            "sched:sched_switch" => {
+                let (Some(prev), Some(next)) = (field("prev_pid"), field("next_pid")) else {
+                    return;
+                };
+                let prev_state = fields.get("prev_state").copied().unwrap_or("R");
+                let state = if prev_state.starts_with('R') {
+                    ThreadStateKind::Runnable
+                } else {
+                    ThreadStateKind::Blocked
+                };
+                // The sample is recorded in the context of the outgoing
+                // thread, so its stack shows where that thread stopped.
+                let own = sample.tid == prev;
+                self.transition(
+                    prev,
+                    OpenState {
+                        pid: own.then_some(sample.pid),
+                        state,
+                        start: ts,
+                        cpu: sample.cpu,
+                        reason: Some(prev_state.to_string()),
+                        stack_id: (own && !key.frames.is_empty())
+                            .then(|| Self::compute_stack_id(key)),
+                    },
+                );
+                self.transition(
+                    next,
+                    OpenState {
+                        pid: None,
+                        state: ThreadStateKind::Running,
+                        start: ts,
+                        cpu: sample.cpu,
+                        reason: None,
+                        stack_id: None,
+                    },
+                );
+            }
+            "sched:sched_wakeup" | "sched:sched_wakeup_new" => {
+                let Some(tid) = field("pid") else {
+                    return;
+                };
+                if self
+                    .open_states
+                    .get(&tid)
+                    .is_some_and(|s| s.state != ThreadStateKind::Blocked)
+                {
+                    return;
+                }
+                self.transition(
+                    tid,
+                    OpenState {
+                        pid: None,
+                        state: ThreadStateKind::Runnable,
+                        start: ts,
+                        cpu: field("target_cpu").map(|c| c as u32),
+                        reason: None,
+                        stack_id: None,
+                    },
+                );
+            }
+            "sched:sched_migrate_task" => {
+                let Some(tid) = field("pid") else {
+                    return;
+                };
+                self.migrations += 1;
+                if let Some(current) = self.open_states.get(&tid).cloned() {
+                    self.transition(
+                        tid,
+                        OpenState {
+                            start: ts,
+                            cpu: field("dest_cpu").map(|c| c as u32),
+                            stack_id: None,
+                            ..current
+                        },
+                    );
+                }
+            }
+            _ => return,
+        }
+
+        self.last_sched_timestamp = Some(self.last_sched_timestamp.map_or(ts, |t| t.max(ts)));
+    }
+
+    /// End the thread's current state (if any) and begin `next`.
+    fn transition(&mut self, tid: u64, mut next: OpenState) {
+        // tid 0 is the per-CPU idle task.
+        if tid == 0 {
+            return;
+        }
+        if let Some(previous) = self.open_states.remove(&tid) {
+            next.pid = next.pid.or(previous.pid);
+            if next.start >= previous.start {
+                let end = next.start;
+                self.closed_states.push((tid, previous, end));
+            }
+        }
+        self.open_states.insert(tid, next);
+    }
+
+    /// Split a tracepoint payload into `key=value` fields.
+    fn parse_trace_fields(args: &str) -> HashMap<&str, &str> {
+        args.split_whitespace()
+            .filter_map(|token| token.split_once('='))
+            .collect()
+    }
+
+    /// Recognize a lost-data line and return the number of lost events.
+    ///
+    /// Examples:
+    ///   `perf  4031 [002] 12345.678901: PERF_RECORD_LOST lost 17`
+    ///   `perf  4031 [002] 12345.678901: PERF_RECORD_LOST_SAMPLES lost 5`
+    ///   `Processed 48213 events and lost 3 chunks!`
+    ///
+    /// Chunk warnings don't report an event count, so they return 0.
+    fn parse_lost_line(line: &str) -> Option<u64> {
+        let line = line.trim();
+        if let Some(pos) = line.find("PERF_RECORD_LOST") {
+            let mut tokens = line[pos..].split_whitespace();
+            while let Some(token) = tokens.next() {
+                if token == "lost" {
+                    return Some(tokens.next().and_then(|n| n.parse().ok()).unwrap_or(0));
+                }
+            }
+            return Some(0);
+        }
+        if line.starts_with("Processed ") && line.contains(" lost ") && line.contains("chunks") {
+            return Some(0);
+        }
+        None
+    }
+
+    /// Read the host and clock from `perf script --header` comments. The
+    /// clock is only listed for captures made with `perf record -k <clock>`:
+    ///
+    /// ```text
+    /// # hostname : build-07
+    /// # os release : 6.8.0-45-generic
+    /// # nrcpus online : 16
+    /// # total memory : 65536000 kB
+    /// # clockid: CLOCK_MONOTONIC (1)
+    /// # reference time: 2020-08-13 12:13:55.786 = 1597313635.786285 (TOD) = 17826.148296149 (CLOCK_MONOTONIC)
+    /// ```
+    fn parse_header_comment(&mut self, comment: &str) {
+        let comment = comment.trim();
+        if let Some((key, value)) = comment.split_once(" : ") {
+            let value = value.trim().to_string();
+            let system = &mut self.system;
+            match key.trim() {
+                "hostname" => system.hostname = Some(value),
+                "os release" => {
+                    system.os = Some("linux".to_string());
+                    system.kernel_version = Some(value);
+                }
+                "arch" => system.arch = Some(value),
+                "cpudesc" => system.cpu_model = Some(value),
+                "nrcpus online" => system.cpu_cores = value.parse().ok(),
+                "total memory" => {
+                    system.memory_bytes = value
+                        .trim_end_matches("kB")
+                        .trim()
+                        .parse::<u64>()
+                        .ok()
+                        .map(|kb| kb * 1024);
+                }
+                _ => {}
+            }
+        } else if let Some(rest) = comment.strip_prefix("clockid:") {
+            let name = rest.split_whitespace().next().unwrap_or_default();
+            self.clock.source = match name {
+                "CLOCK_REALTIME" | "CLOCK_TAI" => ClockSource::Wall,
+                _ => ClockSource::Monotonic,
+            };
+        } else if let Some(rest) = comment.strip_prefix("reference time:") {
+            let seconds = |part: &str| part.split_whitespace().next()?.parse::<f64>().ok();
+            let parts: Vec<&str> = rest.split(" = ").collect();
+            if let [_, tod, clock] = parts.as_slice()
+                && let (Some(tod), Some(clock)) = (seconds(tod), seconds(clock))
+            {
+                self.clock.epoch_offset = Some(tod - clock);
+            }
+        }
     }
 
     /// Parse a sample header line.
@@ -194,8 +767,12 @@ impl PerfConverter {
     fn parse_sample_header(line: &str) -> std::result::Result<PerfSample, String> {
         let line = line.trim();
 
-        // Find the colon that separates timestamp from period/event
-        let colon_pos = line.find(':').ok_or("no colon found")?;
+        // Find the colon that separates timestamp from period/event. Prefer
+        // ": " so that colons inside comm (e.g. `kworker/0:1`) are skipped.
+        let colon_pos = line
+            .find(": ")
+            .or_else(|| line.find(':'))
+            .ok_or("no colon found")?;
         let before_colon = &line[..colon_pos];
         let after_colon = &line[colon_pos + 1..];
 
@@ -205,14 +782,14 @@ impl PerfConverter {
             return Err("no event info after colon".into());
         }
 
-        let (period, event) = if after_parts.len() >= 2 {
-            let period = after_parts[0].parse::<u64>().unwrap_or(1);
-            let event = after_parts[1].trim_end_matches(':').to_string();
-            (period, event)
-        } else {
-            // Sometimes just the event name
-            (1, after_parts[0].trim_end_matches(':').to_string())
+        // Tracepoints usually have no period: `sched:sched_switch: prev_comm=...`
+        let (period, event_idx) = match after_parts[0].parse::<u64>() {
+            Ok(period) if after_parts.len() >= 2 => (period, 1),
+            _ => (1, 0),
         };
+        let event = after_parts[event_idx].trim_end_matches(':').to_string();
+        let trace_args =
+            (after_parts.len() > event_idx + 1).then(|| after_parts[event_idx + 1..].join(" "));
 
         // Parse the part before the colon
         let parts: Vec<&str> = before_colon.split_whitespace().collect();
@@ -226,12 +803,15 @@ impl PerfConverter {
         // Second part is pid or pid/tid
         let (pid, tid) = Self::parse_pid_tid(parts[1])?;
 
-        // Look for CPU in brackets and timestamp
+        // Look for CPU in brackets, timestamp and cgroup path
         let mut cpu = None;
         let mut timestamp = None;
+        let mut cgroup = None;
 
         for part in &parts[2..] {
-            if part.starts_with('[') && part.ends_with(']') {
+            if part.starts_with('/') {
+                cgroup = Some(part.to_string());
+            } else if part.starts_with('[') && part.ends_with(']') {
                 // CPU number
                 let cpu_str = part.trim_start_matches('[').trim_end_matches(']');
                 cpu = cpu_str.parse().ok();
@@ -249,6 +829,8 @@ impl PerfConverter {
             timestamp,
             period,
             event,
+            trace_args,
+            cgroup,
             frames: Vec::new(),
         })
     }
@@ -339,118 +921,82 @@ impl PerfConverter {
         }
     }
 
-    /// Write the parsed data as SPAA format to a writer.
-    pub fn write_spaa<W: Write>(&self, mut writer: W) -> Result<()> {
-        if self.samples.is_empty() {
+    /// Build the SPAA profile in memory.
+    pub fn to_spaa_file(&self) -> Result<SpaaFile> {
+        let states = self.thread_states();
+        if self.stacks.is_empty() && states.is_empty() {
             return Err(ConvertError::NoSamples);
         }
 
-        // Build dictionaries
-        let mut dso_map: HashMap<&str, u64> = HashMap::new();
-        let mut frame_map: HashMap<&PerfFrame, u64> = HashMap::new();
-        let mut thread_map: HashMap<(u64, u64), ()> = HashMap::new();
-
-        // First pass: collect unique DSOs, frames, and threads
-        for sample in &self.samples {
-            thread_map.insert((sample.pid, sample.tid), ());
-            for frame in &sample.frames {
-                if !dso_map.contains_key(frame.dso.as_str()) {
-                    let id = dso_map.len() as u64 + 1;
-                    dso_map.insert(&frame.dso, id);
-                }
-                if !frame_map.contains_key(frame) {
-                    let id = frame_map.len() as u64 + 1;
-                    frame_map.insert(frame, id);
-                }
-            }
-        }
-
-        // Aggregate stacks
-        let aggregated = self.aggregate_stacks(&frame_map);
-
-        // Write header
-        let header = self.build_header();
-        self.write_record(&mut writer, "header", &header)?;
-
-        // Write DSO dictionary
-        for (dso_name, dso_id) in &dso_map {
-            let is_kernel = dso_name.contains("[kernel")
-                || dso_name.contains("kallsyms")
-                || dso_name.starts_with("[k]");
-            let dso = DsoRecord {
-                id: *dso_id,
-                name: (*dso_name).to_string(),
-                build_id: None,
-                is_kernel,
-            };
-            self.write_record(&mut writer, "dso", &dso)?;
-        }
+        // DSO dictionary
+        let dsos = self
+            .dso_ids
+            .iter()
+            .map(|(dso_name, &id)| {
+                let is_kernel = dso_name.contains("[kernel")
+                    || dso_name.contains("kallsyms")
+                    || dso_name.starts_with("[k]");
+                let dso = Dso {
+                    id,
+                    name: (*dso_name).to_string(),
+                    build_id: None,
+                    is_kernel,
+                };
+                (id, dso)
+            })
+            .collect();
 
-        // Write frame dictionary
-        for (perf_frame, frame_id) in &frame_map {
-            let dso_id = dso_map[perf_frame.dso.as_str()];
-            let is_kernel =
-                perf_frame.dso.contains("[kernel") || perf_frame.dso.contains("kallsyms");
-            let frame = FrameRecord {
-                id: *frame_id,
+        // Frame dictionary
+        let mut frames = HashMap::new();
+        for (frame_id, perf_frame) in self.frames.iter() {
+            let dso_id = self.dso_ids[&perf_frame.dso];
+            let frame = Frame {
+                id: frame_id,
                 func: perf_frame.symbol.clone(),
-                func_resolved: !perf_frame.symbol.starts_with("0x"),
                 dso: dso_id,
+                func_resolved: !perf_frame.symbol.starts_with("0x"),
                 ip: Some(format!("0x{}", perf_frame.ip)),
                 symoff: perf_frame.offset.clone(),
                 srcline: perf_frame.srcline.clone(),
+                srcline_resolved: true,
                 inlined: false,
-                kind: if is_kernel {
-                    FrameKind::Kernel
-                } else {
-                    FrameKind::User
-                },
+                inline_depth: None,
+                kind: Self::frame_kind(&perf_frame.dso),
             };
-            self.write_record(&mut writer, "frame", &frame)?;
+            frames.insert(frame_id, frame);
         }
 
-        // Write thread dictionary
-        for (pid, tid) in thread_map.keys() {
-            let thread = ThreadRecord {
-                pid: *pid,
-                tid: *tid,
-                comm: None, // Could track this per-thread
-            };
-            self.write_record(&mut writer, "thread", &thread)?;
-        }
+        // Thread dictionary
+        let threads = self
+            .threads
+            .iter()
+            .map(|&(pid, tid)| {
+                let thread = Thread {
+                    pid,
+                    tid,
+                    comm: None, // Could track this per-thread
+                };
+                (tid, thread)
+            })
+            .collect();
 
-        // Write stacks
-        for (stack_key, stack_data) in &aggregated {
-            let stack = StackRecord {
-                id: stack_key.id.clone(),
-                frames: stack_key.frame_ids.clone(),
+        // Stacks
+        let mut stacks = HashMap::new();
+        for (stack_key, stack_data) in &self.stacks {
+            let frame_ids = FrameArena::ids(&stack_key.frames);
+            let stack = Stack {
+                id: Self::compute_stack_id(stack_key),
+                frames: frame_ids.clone(),
                 stack_type: StackType::Unified,
-                context: StackContext {
-                    event: stack_key.event.clone(),
-                    pid: Some(stack_key.pid),
-                    tid: Some(stack_key.tid),
-                    cpu: None,
-                    comm: Some(stack_key.comm.clone()),
-                    probe: None,
-                    execname: None,
-                    uid: None,
-                    zonename: None,
-                    trace_fields: None,
-                    extra: HashMap::new(),
-                },
-                weights: vec![
-                    Weight {
-                        metric: "samples".to_string(),
-                        value: stack_data.sample_count,
-                        unit: None,
-                    },
-                    Weight {
-                        metric: "period".to_string(),
-                        value: stack_data.total_period,
-                        unit: Some("events".to_string()),
-                    },
-                ],
-                exclusive: stack_key.frame_ids.first().map(|&leaf| ExclusiveWeights {
+                context: Self::stack_context(
+                    &stack_key.event,
+                    stack_key.pid,
+                    stack_key.tid,
+                    &stack_key.comm,
+                    stack_key.cgroup.as_deref(),
+                ),
+                weights: Self::weights(stack_data.sample_count, stack_data.total_period),
+                exclusive: frame_ids.first().map(|&leaf| ExclusiveWeights {
                     frame: leaf,
                     weights: vec![Weight {
                         metric: "period".to_string(),
@@ -460,28 +1006,140 @@ impl PerfConverter {
                 }),
                 related_stacks: None,
             };
-            self.write_record(&mut writer, "stack", &stack)?;
+            stacks.insert(stack.id.clone(), stack);
+        }
+
+        // Thread state intervals
+        let mut state_records = Vec::new();
+        for (tid, state, end) in states {
+            let stack_id = state.stack_id.clone();
+            let record = ThreadState {
+                tid,
+                pid: state.pid,
+                state: state.state,
+                start: state.start,
+                end,
+                cpu: state.cpu,
+                reason: state.reason.clone(),
+                stack_id,
+            };
+            state_records.push(record);
         }
 
+        Ok(SpaaFile {
+            header: self.build_header(),
+            system: (self.system != SystemInfo::default()).then(|| self.system.clone()),
+            dsos,
+            frames,
+            threads,
+            stacks,
+            samples: self.sample_records.clone(),
+            windows: Vec::new(),
+            states: state_records,
+            notes: Vec::new(),
+        })
+    }
+
+    /// Write the parsed data as SPAA format to a writer.
+    pub fn write_spaa<W: Write>(&self, writer: W) -> Result<()> {
+        self.to_spaa_file()?.write(writer)?;
         Ok(())
     }
 
-    fn build_header(&self) -> Header {
-        let events: Vec<EventDef> = self
-            .events
-            .values()
-            .map(|e| EventDef {
-                name: e.name.clone(),
-                kind: e.kind,
-                sampling: Sampling {
-                    mode: SamplingMode::Period,
-                    primary_metric: "period".to_string(),
-                    sample_period: None,
-                    frequency_hz: None,
-                },
-                allocation_tracking: None,
-            })
+    /// All thread state intervals, with still-open states ending at the
+    /// last timestamp seen. Sorted by start time, then thread ID.
+    fn thread_states(&self) -> Vec<(u64, &OpenState, f64)> {
+        let end = match (self.last_sched_timestamp, self.time_range) {
+            (Some(a), Some((_, b))) => a.max(b),
+            (Some(a), None) => a,
+            (None, Some((_, b))) => b,
+            (None, None) => 0.0,
+        };
+        let mut states: Vec<(u64, &OpenState, f64)> = self
+            .closed_states
+            .iter()
+            .map(|(tid, state, end)| (*tid, state, *end))
+            .chain(
+                self.open_states
+                    .iter()
+                    .map(|(tid, state)| (*tid, state, end)),
+            )
             .collect();
+        states.sort_by(|a, b| a.1.start.total_cmp(&b.1.start).then(a.0.cmp(&b.0)));
+        states
+    }
+
+    /// Context of a stack sampled by `event` in a thread, with container
+    /// details derived from its cgroup.
+    fn stack_context(
+        event: &str,
+        pid: u64,
+        tid: u64,
+        comm: &str,
+        cgroup: Option<&str>,
+    ) -> StackContext {
+        let container = cgroup.map(container_info).unwrap_or_default();
+        StackContext {
+            event: event.to_string(),
+            pid: Some(pid),
+            tid: Some(tid),
+            cpu: None,
+            comm: Some(comm.to_string()),
+            probe: None,
+            execname: None,
+            uid: None,
+            zonename: None,
+            cgroup: cgroup.map(str::to_string),
+            container_id: container.container_id,
+            k8s_pod: container.k8s_pod,
+            trace_fields: None,
+            extra: HashMap::new(),
+        }
+    }
+
+    fn weights(samples: u64, period: u64) -> Vec<Weight> {
+        vec![
+            Weight {
+                metric: "samples".to_string(),
+                value: samples,
+                unit: None,
+            },
+            Weight {
+                metric: "period".to_string(),
+                value: period,
+                unit: Some("events".to_string()),
+            },
+        ]
+    }
+
+    fn frame_kind(dso: &str) -> FrameKind {
+        if dso.contains("[kernel") || dso.contains("kallsyms") {
+            FrameKind::Kernel
+        } else {
+            FrameKind::User
+        }
+    }
+
+    fn event_def(event: &EventInfo) -> EventDef {
+        let mut def = EventDef {
+            name: event.name.clone(),
+            kind: event.kind,
+            sampling: Sampling {
+                mode: SamplingMode::Period,
+                primary_metric: "period".to_string(),
+                sample_period: None,
+                frequency_hz: None,
+            },
+            allocation_tracking: None,
+            description: None,
+            unit: None,
+        };
+        crate::pmu::describe_events(std::slice::from_mut(&mut def));
+        def
+    }
+
+    fn build_header(&self) -> Header {
+        let events = self.events.iter().map(Self::event_def).collect();
 
         Header {
             format: "spaa".to_string(),
@@ -498,69 +1156,31 @@ impl PerfConverter {
                 tool: "perf".to_string(),
                 command: None,
                 tool_version: None,
+                data_loss: (self.lost_records > 0).then_some(DataLoss {
+                    lost_events: self.lost_events,
+                    lost_records: self.lost_records,
+                }),
+                build: BTreeMap::new(),
             }),
             stack_id_mode: StackIdMode::ContentAddressable,
+            clock: Some(self.clock.clone()),
+            approximation: None,
         }
     }
 
-    fn aggregate_stacks(
-        &self,
-        frame_map: &HashMap<&PerfFrame, u64>,
-    ) -> HashMap<StackKey, StackData> {
-        let mut aggregated: HashMap<StackKey, StackData> = HashMap::new();
-
-        for sample in &self.samples {
-            let frame_ids: Vec<u64> = sample.frames.iter().map(|f| frame_map[f]).collect();
-
-            if frame_ids.is_empty() {
-                continue;
-            }
-
-            let stack_id = Self::compute_stack_id(&frame_ids);
-            let key = StackKey {
-                id: stack_id,
-                frame_ids,
-                event: sample.event.clone(),
-                pid: sample.pid,
-                tid: sample.tid,
-                comm: sample.comm.clone(),
-            };
-
-            let data = aggregated.entry(key).or_insert(StackData {
-                sample_count: 0,
-                total_period: 0,
-            });
-            data.sample_count += 1;
-            data.total_period += sample.period;
-        }
-
-        aggregated
-    }
-
-    fn compute_stack_id(frame_ids: &[u64]) -> String {
-        use std::collections::hash_map::DefaultHasher;
+    /// Content-addressed ID of the stack `key`, hashed over its SPAA frame
+    /// IDs and its context, so the same frames sampled in different threads
+    /// or events stay apart.
+    fn compute_stack_id(key: &StackKey) -> String {
         let mut hasher = DefaultHasher::new();
-        frame_ids.hash(&mut hasher);
+        FrameArena::ids(&key.frames).hash(&mut hasher);
+        key.event.hash(&mut hasher);
+        key.pid.hash(&mut hasher);
+        key.tid.hash(&mut hasher);
+        key.comm.hash(&mut hasher);
+        key.cgroup.hash(&mut hasher);
         format!("0x{:016x}", hasher.finish())
     }
-
-    fn write_record<W: Write, T: Serialize>(
-        &self,
-        writer: &mut W,
-        record_type: &str,
-        data: &T,
-    ) -> Result<()> {
-        // Create a combined structure with type field
-        let mut map = serde_json::to_value(data)?;
-        if let serde_json::Value::Object(ref mut obj) = map {
-            obj.insert(
-                "type".to_string(),
-                serde_json::Value::String(record_type.to_string()),
-            );
-        }
-        writeln!(writer, "{}", serde_json::to_string(&map)?)?;
-        Ok(())
-    }
 }
 
 impl Default for PerfConverter {
@@ -569,61 +1189,50 @@ impl Default for PerfConverter {
     }
 }
 
-// Serialization records (slightly different from spaa_parse types to control field order)
-#[derive(Serialize)]
-struct DsoRecord {
-    id: u64,
-    name: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    build_id: Option<String>,
-    is_kernel: bool,
-}
+impl crate::convert::Converter for PerfConverter {
+    fn name(&self) -> &str {
+        "perf"
+    }
 
-#[derive(Serialize)]
-struct FrameRecord {
-    id: u64,
-    func: String,
-    func_resolved: bool,
-    dso: u64,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    ip: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    symoff: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    srcline: Option<String>,
-    inlined: bool,
-    kind: FrameKind,
-}
+    /// `perf script` frames are indented `<ip> <symbol> (<dso>)` lines.
+    fn detect(&self, data: &[u8]) -> Option<crate::convert::Confidence> {
+        let text = crate::convert::text_prefix(data);
+        text.lines()
+            .filter(|line| line.starts_with([' ', '\t']))
+            .filter_map(Self::parse_frame)
+            .any(|frame| u64::from_str_radix(&frame.ip, 16).is_ok())
+            .then_some(crate::convert::Confidence::High)
+    }
 
-#[derive(Serialize)]
-struct ThreadRecord {
-    pid: u64,
-    tid: u64,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    comm: Option<String>,
-}
+    fn parse(
+        &mut self,
+        reader: &mut dyn Read,
+    ) -> std::result::Result<(), crate::convert::ConvertError> {
+        Ok(PerfConverter::parse(self, reader)?)
+    }
 
-#[derive(Serialize)]
-struct StackRecord {
-    id: String,
-    frames: Vec<u64>,
-    stack_type: StackType,
-    context: StackContext,
-    weights: Vec<Weight>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    exclusive: Option<ExclusiveWeights>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    related_stacks: Option<Vec<String>>,
+    fn to_spaa_file(&self) -> std::result::Result<SpaaFile, crate::convert::ConvertError> {
+        Ok(PerfConverter::to_spaa_file(self)?)
+    }
+
+    /// Samples without frames are dropped; unparsable frame lines and
+    /// indented lines outside a sample are unparsed.
+    fn fidelity(&self) -> Option<Fidelity> {
+        Some(Fidelity {
+            lost_events: self.lost_events,
+            ..self.fidelity.clone()
+        })
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct StackKey {
-    id: String,
-    frame_ids: Vec<u64>,
+    frames: Vec<FrameHandle>,
     event: String,
     pid: u64,
     tid: u64,
     comm: String,
+    cgroup: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -723,9 +1332,103 @@ myapp  1234 [000] 12345.680000:     100000 cycles:
         let mut converter = PerfConverter::new();
         converter.parse(cursor).unwrap();
 
-        assert_eq!(converter.samples.len(), 3);
+        assert_eq!(converter.sample_count(), 3);
         assert_eq!(converter.events.len(), 1);
-        assert!(converter.events.contains_key("cycles"));
+        assert_eq!(converter.events[0].name, "cycles");
+    }
+
+    #[test]
+    fn repeated_samples_are_aggregated_while_parsing() {
+        let mut converter = PerfConverter::new();
+        for _ in 0..100 {
+            converter.parse(Cursor::new(SAMPLE_PERF_OUTPUT)).unwrap();
+        }
+
+        assert_eq!(converter.sample_count(), 300);
+        assert_eq!(converter.stacks.len(), 2);
+        assert_eq!(
+            converter
+                .stacks
+                .values()
+                .map(|s| s.sample_count)
+                .sum::<u64>(),
+            300
+        );
+    }
+
+    #[test]
+    fn duplicate_frames_share_a_handle() {
+        let frame = || PerfFrame {
+            ip: "401234".to_string(),
+            symbol: "main".to_string(),
+            offset: Some("0x54".to_string()),
+            dso: "/usr/bin/myapp".to_string(),
+            srcline: None,
+        };
+        let mut arena = FrameArena::default();
+        let first = arena.intern(frame()).unwrap();
+        let other = arena
+            .intern(PerfFrame {
+                symbol: "helper".to_string(),
+                ..frame()
+            })
+            .unwrap();
+
+        assert_eq!(arena.intern(frame()), Some(first));
+        assert_ne!(other, first);
+        assert_eq!(arena.frames.len(), 2);
+    }
+
+    #[test]
+    fn output_is_reproducible() {
+        let convert = || {
+            let mut converter = PerfConverter::new();
+            converter.parse(Cursor::new(SAMPLE_PERF_OUTPUT)).unwrap();
+            let mut output = Vec::new();
+            converter.write_spaa(&mut output).unwrap();
+            output
+        };
+
+        assert_eq!(convert(), convert());
+    }
+
+    #[test]
+    fn in_memory_file_matches_written_output() {
+        let mut converter = PerfConverter::new();
+        converter.parse(Cursor::new(SAMPLE_PERF_OUTPUT)).unwrap();
+        let mut output = Vec::new();
+        converter.write_spaa(&mut output).unwrap();
+
+        let written = spaa_parse::SpaaFile::parse_slice(&output).unwrap();
+        assert_eq!(converter.to_spaa_file().unwrap(), written);
+    }
+
+    #[test]
+    fn streamed_totals_match_batch_conversion() {
+        let policy = WindowPolicy {
+            seconds: None,
+            samples: Some(1),
+            max_stacks: None,
+        };
+        let output = PerfConverter::new()
+            .stream(Cursor::new(SAMPLE_PERF_OUTPUT), Vec::new(), policy)
+            .unwrap();
+        let streamed = spaa_parse::SpaaFile::parse_slice(&output).unwrap();
+        let mut converter = PerfConverter::new();
+        converter.parse(Cursor::new(SAMPLE_PERF_OUTPUT)).unwrap();
+        let batch = converter.to_spaa_file().unwrap();
+
+        let period = |file: &spaa_parse::SpaaFile| -> u64 {
+            file.stacks
+                .values()
+                .flat_map(|s| &s.weights)
+                .filter(|w| w.metric == "period")
+                .map(|w| w.value)
+                .sum()
+        };
+        assert_eq!(period(&streamed), period(&batch));
+        assert_eq!(streamed.header.events, batch.header.events);
+        assert!(streamed.windows.len() > 1);
     }
 
     #[test]
@@ -771,6 +1474,18 @@ myapp  1234 [000] 12345.680000:     100000 cycles:
         assert_eq!(spaa.stacks.len(), 2);
     }
 
+    #[test]
+    fn known_events_are_described() {
+        let mut converter = PerfConverter::new();
+        converter.parse(Cursor::new(SAMPLE_PERF_OUTPUT)).unwrap();
+        let header = converter.build_header();
+
+        let cycles = &header.events[0];
+        assert_eq!(cycles.name, "cycles");
+        assert_eq!(cycles.unit.as_deref(), Some("cycles"));
+        assert!(cycles.description.is_some());
+    }
+
     #[test]
     fn empty_input_returns_error() {
         let cursor = Cursor::new("");
@@ -854,4 +1569,261 @@ app 100 [0] 3.0:     3000 cycles:
         assert_eq!(samples_weight.value, 3);
         assert_eq!(period_weight.value, 6000); // 1000 + 2000 + 3000
     }
+
+    #[test]
+    fn same_frames_in_different_threads_are_separate_stacks() {
+        let input = r#"
+app 100/100 [0] 1.0:     100000 cycles:
+	1000 func_a (/bin/app)
+
+app 100/101 [1] 2.0:     200000 cycles:
+	1000 func_a (/bin/app)
+"#;
+        let mut converter = PerfConverter::new().with_samples();
+        converter.parse(Cursor::new(input)).unwrap();
+        let spaa = converter.to_spaa_file().unwrap();
+
+        let mut periods: Vec<(u64, u64)> = spaa
+            .stacks
+            .values()
+            .map(|s| {
+                let period = s.weights.iter().find(|w| w.metric == "period").unwrap();
+                (s.context.tid.unwrap(), period.value)
+            })
+            .collect();
+        periods.sort();
+        assert_eq!(periods, [(100, 100000), (101, 200000)]);
+        // Each sample refers to its own thread's stack.
+        for sample in &spaa.samples {
+            assert_eq!(spaa.stacks[&sample.stack_id].context.tid, Some(sample.tid));
+        }
+    }
+
+    #[test]
+    fn max_stack_depth_truncates_deep_samples() {
+        let cursor = Cursor::new(SAMPLE_PERF_OUTPUT);
+        let mut converter = PerfConverter::new().with_max_stack_depth(2);
+        converter.parse(cursor).unwrap();
+
+        assert_eq!(converter.truncated_stacks(), 1);
+
+        let mut output = Vec::new();
+        converter.write_spaa(&mut output).unwrap();
+        let spaa = spaa_parse::SpaaFile::parse(Cursor::new(output)).unwrap();
+
+        let deep = spaa.stacks.values().find(|s| s.frames.len() == 3).unwrap();
+        assert_eq!(spaa.frames[&deep.frames[0]].func, "foo");
+        assert_eq!(spaa.frames[&deep.frames[2]].func, TRUNCATED_FRAME_NAME);
+    }
+
+    #[test]
+    fn lost_records_are_counted_and_recorded_in_header() {
+        let input = r#"
+perf  4031 [002] 0.5: PERF_RECORD_LOST lost 17
+app 100 [0] 1.0:     1000 cycles:
+	1000 func_a (/bin/app)
+	2000 [unknown] ([unknown])
+
+perf  4031 [002] 1.5: PERF_RECORD_LOST_SAMPLES lost 3
+Warning:
+Processed 48213 events and lost 2 chunks!
+"#;
+        let mut converter = PerfConverter::new();
+        converter.parse(Cursor::new(input)).unwrap();
+
+        assert_eq!(converter.lost_events(), 20);
+        assert_eq!(converter.lost_records(), 3);
+        assert_eq!(converter.unknown_frames(), 1);
+
+        let mut output = Vec::new();
+        converter.write_spaa(&mut output).unwrap();
+        let spaa = spaa_parse::SpaaFile::parse(Cursor::new(output)).unwrap();
+
+        assert_eq!(spaa.stacks.len(), 1);
+        let loss = spaa.header.source.unwrap().data_loss.unwrap();
+        assert_eq!(loss.lost_events, 20);
+        assert_eq!(loss.lost_records, 3);
+    }
+
+    #[test]
+    fn fidelity_counts_dropped_samples_and_unparsed_lines() {
+        use crate::convert::Converter;
+
+        let input = "app 100 [0] 1.0:     1000 cycles:
+\t1000 func_a (/bin/app)
+\tgarbled frame
+
+app 100 [0] 2.0:     1000 cycles:
+
+app 100 [0] 3.0:     1000 cycles:
+\t1000 func_a (/bin/app)
+";
+        let mut converter = PerfConverter::new();
+        PerfConverter::parse(&mut converter, Cursor::new(input)).unwrap();
+        let fidelity = converter.fidelity().unwrap();
+
+        assert_eq!(fidelity.samples_read, 3);
+        assert_eq!(fidelity.samples_dropped, 1);
+        assert_eq!(fidelity.unparsed_lines, 1);
+        assert_eq!(fidelity.unparsed_examples[0].line, 3);
+        assert_eq!(fidelity.unparsed_examples[0].text, "\tgarbled frame");
+    }
+
+    #[test]
+    fn reference_time_comment_sets_the_epoch_offset() {
+        let input = "# ========
+# clockid: CLOCK_MONOTONIC (1)
+# reference time: 2020-08-13 12:13:55.786 = 1597313635.786285 (TOD) = 17826.148296149 (CLOCK_MONOTONIC)
+# ========
+app 100 [0] 17830.0:     1000 cycles:
+\t1000 func_a (/bin/app)
+";
+        let mut converter = PerfConverter::new();
+        converter.parse(Cursor::new(input)).unwrap();
+        let mut spaa = converter.to_spaa_file().unwrap();
+
+        let clock = spaa.header.clock.clone().unwrap();
+        assert_eq!(clock.source, ClockSource::Monotonic);
+        let offset = clock.epoch_offset.unwrap();
+        assert!((offset - (1597313635.786285 - 17826.148296149)).abs() < 1e-6);
+        assert!(spaa.rebase_to_wall_clock());
+        assert!((spaa.header.time_range.unwrap().start - (17830.0 + offset)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn header_comments_describe_the_host() {
+        let input = "# ========
+# captured on    : Thu Aug 13 12:14:00 2020
+# hostname : build-07
+# os release : 6.8.0-45-generic
+# arch : x86_64
+# nrcpus online : 16
+# cpudesc : AMD EPYC 7B13
+# total memory : 65536000 kB
+# ========
+app 100 [0] 1.0:     1000 cycles:
+\t1000 func_a (/bin/app)
+";
+        let mut converter = PerfConverter::new();
+        converter.parse(Cursor::new(input)).unwrap();
+        let system = converter.to_spaa_file().unwrap().system.unwrap();
+
+        assert_eq!(system.hostname.as_deref(), Some("build-07"));
+        assert_eq!(system.os.as_deref(), Some("linux"));
+        assert_eq!(system.kernel_version.as_deref(), Some("6.8.0-45-generic"));
+        assert_eq!(system.cpu_model.as_deref(), Some("AMD EPYC 7B13"));
+        assert_eq!(system.cpu_cores, Some(16));
+        assert_eq!(system.memory_bytes, Some(65536000 * 1024));
+    }
+
+    #[test]
+    fn strict_mode_fails_on_unparsed_lines() {
+        let input = "app 100 [0] 1.0:     1000 cycles:
+\t1000 func_a (/bin/app)
+\tgarbled frame
+";
+        let mut converter = PerfConverter::new().with_strict();
+
+        assert!(matches!(
+            converter.parse(Cursor::new(input)),
+            Err(ConvertError::Parse { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn parse_sample_header_tracepoint() {
+        let line = "kworker/0:1  42 [000] 5.0: sched:sched_wakeup: comm=app pid=100 target_cpu=001";
+        let sample = PerfConverter::parse_sample_header(line).unwrap();
+
+        assert_eq!(sample.comm, "kworker/0:1");
+        assert_eq!(sample.event, "sched:sched_wakeup");
+        assert_eq!(sample.period, 1);
+        assert_eq!(
+            sample.trace_args.as_deref(),
+            Some("comm=app pid=100 target_cpu=001")
+        );
+    }
+
+    #[test]
+    fn sched_tracepoints_produce_thread_states() {
+        let input = r#"
+app   100 [000] 1.000: sched:sched_switch: prev_comm=app prev_pid=100 prev_prio=120 prev_state=S ==> next_comm=swapper/0 next_pid=0 next_prio=120
+	1000 schedule+0x10 ([kernel.kallsyms])
+	2000 read_input (/bin/app)
+
+swapper     0 [000] 1.004: sched:sched_wakeup: comm=app pid=100 prio=120 target_cpu=000
+swapper     0 [000] 1.005: sched:sched_switch: prev_comm=swapper/0 prev_pid=0 prev_prio=120 prev_state=R ==> next_comm=app next_pid=100 next_prio=120
+app   100 [000] 1.010: sched:sched_migrate_task: comm=app pid=100 prio=120 orig_cpu=0 dest_cpu=1
+"#;
+        let mut converter = PerfConverter::new();
+        converter.parse(Cursor::new(input)).unwrap();
+        assert_eq!(converter.migrations(), 1);
+
+        let mut output = Vec::new();
+        converter.write_spaa(&mut output).unwrap();
+        let spaa = spaa_parse::SpaaFile::parse(Cursor::new(output)).unwrap();
+
+        let states: Vec<_> = spaa
+            .states
+            .iter()
+            .map(|s| (s.state, s.start, s.end))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                (ThreadStateKind::Blocked, 1.0, 1.004),
+                (ThreadStateKind::Runnable, 1.004, 1.005),
+                (ThreadStateKind::Running, 1.005, 1.01),
+                (ThreadStateKind::Running, 1.01, 1.01),
+            ]
+        );
+
+        let blocked = &spaa.states[0];
+        assert_eq!(blocked.reason.as_deref(), Some("S"));
+        assert_eq!(blocked.pid, Some(100));
+        let stack = &spaa.stacks[blocked.stack_id.as_ref().unwrap()];
+        assert_eq!(spaa.frames[&stack.frames[0]].func, "schedule");
+        assert_eq!(spaa.states[3].cpu, Some(1));
+    }
+
+    #[test]
+    fn cgroup_populates_container_context() {
+        let input = r#"
+app  100 [000] 1.000 /kubepods/besteffort/pod6f1c2a3b-0000-4000-8000-000000000001/3f4e5d6c7b8a9f0e1d2c3b4a5f6e7d8c9b0a1f2e3d4c5b6a7f8e9d0c1b2a3f4e:     1000 cycles:
+	401234 main+0x54 (/usr/bin/app)
+"#;
+        let mut converter = PerfConverter::new();
+        converter.parse(Cursor::new(input)).unwrap();
+        let mut output = Vec::new();
+        converter.write_spaa(&mut output).unwrap();
+        let spaa = spaa_parse::SpaaFile::parse(Cursor::new(output)).unwrap();
+
+        let context = &spaa.stacks.values().next().unwrap().context;
+        assert!(context.cgroup.as_deref().unwrap().starts_with("/kubepods/"));
+        assert_eq!(
+            context.k8s_pod.as_deref(),
+            Some("6f1c2a3b-0000-4000-8000-000000000001")
+        );
+        assert_eq!(
+            context.container_id.as_deref(),
+            Some("3f4e5d6c7b8a9f0e1d2c3b4a5f6e7d8c9b0a1f2e3d4c5b6a7f8e9d0c1b2a3f4e")
+        );
+    }
+
+    #[test]
+    fn with_samples_emits_sample_records() {
+        let mut converter = PerfConverter::new().with_samples();
+        converter.parse(Cursor::new(SAMPLE_PERF_OUTPUT)).unwrap();
+
+        let mut output = Vec::new();
+        converter.write_spaa(&mut output).unwrap();
+        let spaa = spaa_parse::SpaaFile::parse(Cursor::new(output)).unwrap();
+
+        assert_eq!(spaa.samples.len() as u64, converter.sample_count());
+        assert!(
+            spaa.samples
+                .iter()
+                .all(|s| spaa.stacks.contains_key(&s.stack_id))
+        );
+    }
 }
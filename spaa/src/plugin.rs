@@ -0,0 +1,259 @@
+//! Converters for formats outside this crate, run as subprocesses.
+//!
+//! A plugin is any executable that speaks this protocol:
+//!
+//! - `<plugin> detect` receives up to [`DETECT_PREFIX`] bytes of input on
+//!   stdin and prints `high`, `medium`, `low` or `none`, like
+//!   [`Converter::detect`].
+//! - `<plugin> convert` receives the whole input on stdin and writes the
+//!   SPAA NDJSON profile to stdout. On failure it exits non-zero with a
+//!   message on stderr.
+//!
+//! Plugins are found on `PATH` as `spaa-convert-<name>` executables, or
+//! named in the `[plugins]` table of `spaa.toml`:
+//!
+//! ```toml
+//! [plugins]
+//! acme = "/opt/acme/bin/acme-to-spaa"
+//! ```
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::Converter;
+//! use spaa::plugin;
+//! use std::collections::BTreeMap;
+//!
+//! let mut plugins = plugin::discover(&BTreeMap::new());
+//! let acme = plugins.iter_mut().find(|p| p.name() == "acme").unwrap();
+//! acme.parse(&mut std::fs::File::open("app.acmeprof").unwrap()).unwrap();
+//! let file = acme.to_spaa_file().unwrap();
+//! ```
+
+use crate::convert::{Confidence, ConvertError, Converter, DETECT_PREFIX};
+use spaa_parse::SpaaFile;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+/// File name prefix of plugins found on `PATH`.
+pub const PLUGIN_PREFIX: &str = "spaa-convert-";
+
+/// A converter implemented by an external program.
+#[derive(Debug, Clone)]
+pub struct Plugin {
+    name: String,
+    program: PathBuf,
+    file: Option<SpaaFile>,
+}
+
+impl Plugin {
+    /// A plugin called `name` that runs `program`.
+    pub fn new(name: impl Into<String>, program: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            program: program.into(),
+            file: None,
+        }
+    }
+
+    /// The executable this plugin runs.
+    pub fn program(&self) -> &Path {
+        &self.program
+    }
+
+    /// Run `<program> <command>` with `input` on stdin.
+    fn run(&self, command: &str, input: &[u8]) -> std::io::Result<Output> {
+        let mut child = Command::new(&self.program)
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        thread::scope(|s| {
+            s.spawn(move || {
+                // The plugin may stop reading early, e.g. once it has
+                // recognized the input.
+                let _ = stdin.write_all(input);
+            });
+            child.wait_with_output()
+        })
+    }
+}
+
+impl Converter for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// A plugin that fails to run or answers anything unexpected does not
+    /// match.
+    fn detect(&self, data: &[u8]) -> Option<Confidence> {
+        let data = &data[..data.len().min(DETECT_PREFIX)];
+        let output = self.run("detect", data).ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        match String::from_utf8_lossy(&output.stdout).trim() {
+            "high" => Some(Confidence::High),
+            "medium" => Some(Confidence::Medium),
+            "low" => Some(Confidence::Low),
+            _ => None,
+        }
+    }
+
+    fn parse(&mut self, reader: &mut dyn Read) -> Result<(), ConvertError> {
+        let mut input = Vec::new();
+        reader.read_to_end(&mut input)?;
+        let output = self.run("convert", &input)?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(ConvertError::Invalid(format!(
+                "plugin '{}' failed ({}): {}",
+                self.name,
+                output.status,
+                stderr.trim()
+            )));
+        }
+        let file = SpaaFile::parse_slice(&output.stdout).map_err(|e| {
+            ConvertError::Invalid(format!("plugin '{}' wrote invalid SPAA: {}", self.name, e))
+        })?;
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn to_spaa_file(&self) -> Result<SpaaFile, ConvertError> {
+        self.file.clone().ok_or_else(|| {
+            ConvertError::Empty(format!(
+                "plugin '{}' has not converted any input",
+                self.name
+            ))
+        })
+    }
+}
+
+/// Plugins named in `configured`, then those found on `PATH`.
+///
+/// When two plugins share a name the first wins, so `spaa.toml` overrides
+/// `PATH` and earlier `PATH` entries override later ones.
+pub fn discover(configured: &BTreeMap<String, PathBuf>) -> Vec<Plugin> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    discover_in(configured, std::env::split_paths(&path))
+}
+
+/// Like [`discover`], searching `dirs` instead of `PATH`.
+pub fn discover_in(
+    configured: &BTreeMap<String, PathBuf>,
+    dirs: impl IntoIterator<Item = PathBuf>,
+) -> Vec<Plugin> {
+    let mut plugins: Vec<Plugin> = configured
+        .iter()
+        .map(|(name, program)| Plugin::new(name, program))
+        .collect();
+    let mut seen: HashSet<String> = configured.keys().cloned().collect();
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut found: Vec<(String, PathBuf)> = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let name = plugin_name(&path)?;
+                is_executable(&path).then_some((name, path))
+            })
+            .collect();
+        found.sort();
+        for (name, path) in found {
+            if seen.insert(name.clone()) {
+                plugins.push(Plugin::new(name, path));
+            }
+        }
+    }
+    plugins
+}
+
+/// `<name>` of a `spaa-convert-<name>` file, without any `.exe`.
+fn plugin_name(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    let file_name = file_name.strip_suffix(".exe").unwrap_or(file_name);
+    let name = file_name.strip_prefix(PLUGIN_PREFIX)?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"#!/bin/sh
+case "$1" in
+detect) grep -q '^ACME' && echo high || echo none ;;
+convert)
+  cat > /dev/null
+  echo '{"type":"header","format":"spaa","version":"1.0","source_tool":"acme","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}'
+  echo '{"type":"dso","id":1,"name":"app","is_kernel":false}'
+  echo '{"type":"frame","id":1,"func":"main","dso":1}'
+  echo '{"type":"stack","id":"0x1","frames":[1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":5}]}'
+  ;;
+esac
+"#;
+
+    fn plugin_dir(name: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("spaa-plugin-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("spaa-convert-acme");
+        std::fs::write(&script, SCRIPT).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(dir.join("spaa-convert-notes"), "not executable").unwrap();
+        dir
+    }
+
+    #[test]
+    fn discovers_executables_with_prefix() {
+        let dir = plugin_dir("discover");
+        let plugins = discover_in(&BTreeMap::new(), [dir.clone()]);
+
+        let names: Vec<&str> = plugins.iter().map(|p| p.name()).collect();
+        assert_eq!(names, ["acme"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn configured_plugins_take_precedence() {
+        let dir = plugin_dir("configured");
+        let configured = BTreeMap::from([("acme".to_string(), PathBuf::from("/opt/acme"))]);
+        let plugins = discover_in(&configured, [dir.clone()]);
+
+        assert_eq!(plugins.len(), 1);
+        assert_eq!(plugins[0].program(), Path::new("/opt/acme"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn plugin_detects_and_converts() {
+        let dir = plugin_dir("convert");
+        let mut plugin = Plugin::new("acme", dir.join("spaa-convert-acme"));
+
+        assert_eq!(plugin.detect(b"ACME profile v1\n"), Some(Confidence::High));
+        assert_eq!(plugin.detect(b"something else\n"), None);
+        plugin.parse(&mut &b"ACME profile v1\n"[..]).unwrap();
+        let file = plugin.to_spaa_file().unwrap();
+        assert_eq!(file.header.source_tool, "acme");
+        assert_eq!(file.stacks.len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
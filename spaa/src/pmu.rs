@@ -0,0 +1,323 @@
+//! Descriptions of common perf events.
+//!
+//! Event names such as `LLC-load-misses` mean little to most consumers of a
+//! profile. This catalog maps the generic perf hardware, cache and software
+//! events to a short description and the unit a single event counts, which
+//! converters attach to [`EventDef`]s.
+//!
+//! # Example
+//!
+//! ```
+//! use spaa::pmu;
+//!
+//! let event = pmu::lookup("cpu_core/LLC-load-misses/u").unwrap();
+//! assert_eq!(event.name, "LLC-load-misses");
+//! assert_eq!(event.unit, "misses");
+//! ```
+
+use spaa_parse::EventDef;
+
+/// Catalog entry for a perf event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PmuEvent {
+    /// Canonical perf event name.
+    pub name: &'static str,
+    /// Alternative names perf accepts for the same event.
+    pub aliases: &'static [&'static str],
+    pub description: &'static str,
+    /// Unit of what a single event counts.
+    pub unit: &'static str,
+}
+
+const fn event(
+    name: &'static str,
+    aliases: &'static [&'static str],
+    description: &'static str,
+    unit: &'static str,
+) -> PmuEvent {
+    PmuEvent {
+        name,
+        aliases,
+        description,
+        unit,
+    }
+}
+
+/// Generic perf events, as listed by `perf list`.
+pub const CATALOG: &[PmuEvent] = &[
+    // Hardware events
+    event(
+        "cycles",
+        &["cpu-cycles"],
+        "CPU core clock cycles; where time is spent on CPU",
+        "cycles",
+    ),
+    event(
+        "ref-cycles",
+        &[],
+        "Reference clock cycles, unaffected by frequency scaling",
+        "cycles",
+    ),
+    event("bus-cycles", &[], "Bus clock cycles", "cycles"),
+    event(
+        "instructions",
+        &[],
+        "Instructions retired; divide by cycles for IPC",
+        "instructions",
+    ),
+    event(
+        "branches",
+        &["branch-instructions"],
+        "Branch instructions retired",
+        "branches",
+    ),
+    event(
+        "branch-misses",
+        &[],
+        "Branch instructions the CPU mispredicted, each costing a pipeline flush",
+        "misses",
+    ),
+    event(
+        "cache-references",
+        &[],
+        "Last-level cache accesses",
+        "references",
+    ),
+    event(
+        "cache-misses",
+        &[],
+        "Last-level cache accesses that missed and went to memory",
+        "misses",
+    ),
+    event(
+        "stalled-cycles-frontend",
+        &["idle-cycles-frontend"],
+        "Cycles the front end could not deliver instructions (e.g. instruction cache or decode stalls)",
+        "cycles",
+    ),
+    event(
+        "stalled-cycles-backend",
+        &["idle-cycles-backend"],
+        "Cycles the back end could not retire instructions (e.g. waiting on memory)",
+        "cycles",
+    ),
+    // Hardware cache events
+    event(
+        "L1-dcache-loads",
+        &[],
+        "Loads from the L1 data cache",
+        "loads",
+    ),
+    event(
+        "L1-dcache-load-misses",
+        &[],
+        "Loads that missed the L1 data cache",
+        "misses",
+    ),
+    event(
+        "L1-dcache-stores",
+        &[],
+        "Stores to the L1 data cache",
+        "stores",
+    ),
+    event(
+        "L1-icache-load-misses",
+        &[],
+        "Instruction fetches that missed the L1 instruction cache",
+        "misses",
+    ),
+    event(
+        "LLC-loads",
+        &[],
+        "Loads that reached the last-level cache",
+        "loads",
+    ),
+    event(
+        "LLC-load-misses",
+        &[],
+        "Loads that missed the last-level cache and were served from memory",
+        "misses",
+    ),
+    event(
+        "LLC-stores",
+        &[],
+        "Stores that reached the last-level cache",
+        "stores",
+    ),
+    event(
+        "LLC-store-misses",
+        &[],
+        "Stores that missed the last-level cache",
+        "misses",
+    ),
+    event(
+        "dTLB-loads",
+        &[],
+        "Loads that looked up the data TLB",
+        "loads",
+    ),
+    event(
+        "dTLB-load-misses",
+        &[],
+        "Loads that missed the data TLB and required a page walk",
+        "misses",
+    ),
+    event(
+        "iTLB-load-misses",
+        &[],
+        "Instruction fetches that missed the instruction TLB",
+        "misses",
+    ),
+    event("branch-loads", &[], "Branch target buffer lookups", "loads"),
+    event(
+        "branch-load-misses",
+        &[],
+        "Branch target buffer lookups that missed",
+        "misses",
+    ),
+    // Software events
+    event(
+        "cpu-clock",
+        &[],
+        "CPU time sampled by a high-resolution timer",
+        "nanoseconds",
+    ),
+    event(
+        "task-clock",
+        &[],
+        "CPU time consumed by the profiled task",
+        "nanoseconds",
+    ),
+    event(
+        "page-faults",
+        &["faults"],
+        "Page faults, minor and major",
+        "faults",
+    ),
+    event(
+        "minor-faults",
+        &[],
+        "Page faults served without disk I/O",
+        "faults",
+    ),
+    event(
+        "major-faults",
+        &[],
+        "Page faults that required disk I/O",
+        "faults",
+    ),
+    event(
+        "context-switches",
+        &["cs"],
+        "Context switches; high counts suggest blocking or contention",
+        "switches",
+    ),
+    event(
+        "cpu-migrations",
+        &["migrations"],
+        "Task migrations between CPUs",
+        "migrations",
+    ),
+    event(
+        "alignment-faults",
+        &[],
+        "Unaligned memory accesses fixed up by the kernel",
+        "faults",
+    ),
+    event(
+        "emulation-faults",
+        &[],
+        "Instructions emulated by the kernel",
+        "faults",
+    ),
+];
+
+/// Look up a perf event by name.
+///
+/// Matching ignores case, treats `_` and `-` alike, and strips PMU wrappers
+/// (`cpu/cycles/`, `cpu_core/cycles,period=1000/`) and modifier suffixes
+/// (`cycles:u`, `cycles:ppp`).
+pub fn lookup(name: &str) -> Option<&'static PmuEvent> {
+    let key = normalize(name);
+    CATALOG
+        .iter()
+        .find(|e| normalize(e.name) == key || e.aliases.iter().any(|alias| normalize(alias) == key))
+}
+
+fn normalize(name: &str) -> String {
+    let mut name = name.trim();
+
+    // `pmu/event,term=value/modifiers`
+    if let Some((_, rest)) = name.split_once('/')
+        && let Some((inner, _)) = rest.rsplit_once('/')
+    {
+        name = inner.split(',').next().unwrap_or(inner);
+    }
+
+    // `event:modifiers`, leaving tracepoints like `sched:sched_switch` alone.
+    if let Some((event, modifiers)) = name.rsplit_once(':')
+        && !modifiers.is_empty()
+        && modifiers.chars().all(|c| "ukhIGHpPSDWe".contains(c))
+    {
+        name = event;
+    }
+
+    name.to_ascii_lowercase().replace('_', "-")
+}
+
+/// Fill in missing `description` and `unit` fields from the catalog.
+///
+/// Returns the number of events that were updated.
+pub fn describe_events(events: &mut [EventDef]) -> usize {
+    let mut updated = 0;
+    for event in events {
+        let Some(info) = lookup(&event.name) else {
+            continue;
+        };
+        let mut changed = false;
+        if event.description.is_none() {
+            event.description = Some(info.description.to_string());
+            changed = true;
+        }
+        if event.unit.is_none() {
+            event.unit = Some(info.unit.to_string());
+            changed = true;
+        }
+        updated += changed as usize;
+    }
+    updated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_handles_aliases_and_modifiers() {
+        assert_eq!(lookup("cpu-cycles").unwrap().name, "cycles");
+        assert_eq!(lookup("cycles:ppp").unwrap().name, "cycles");
+        assert_eq!(lookup("cpu/cycles,period=1000/u").unwrap().name, "cycles");
+        assert_eq!(lookup("llc_load_misses").unwrap().name, "LLC-load-misses");
+        assert_eq!(lookup("cs").unwrap().unit, "switches");
+    }
+
+    #[test]
+    fn unknown_events_are_not_matched() {
+        assert!(lookup("sched:sched_switch").is_none());
+        assert!(lookup("syscall::read:entry").is_none());
+        assert!(lookup("profile-997").is_none());
+    }
+
+    #[test]
+    fn catalog_names_are_unique() {
+        let mut names: Vec<String> = CATALOG
+            .iter()
+            .flat_map(|e| std::iter::once(e.name).chain(e.aliases.iter().copied()))
+            .map(normalize)
+            .collect();
+        let total = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), total);
+    }
+}
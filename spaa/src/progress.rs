@@ -0,0 +1,145 @@
+//! Progress reporting and cancellation for long-running operations.
+//!
+//! The [`ProgressSink`] API lives in `spaa_parse` so parsing can report
+//! progress too; it is re-exported here alongside [`TerminalProgress`], a
+//! sink that draws a progress bar for command-line tools.
+//!
+//! Services cancel work from another thread with a [`CancelToken`]. It is
+//! a sink, so it stops parsing, merging and symbol normalization through
+//! their `_with_progress` functions, and the heap snapshot analyses and the
+//! more expensive [`analysis`](crate::analysis) functions have `_limited`
+//! variants that check it while they run; see [`limits`](crate::limits).
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::progress::TerminalProgress;
+//! use spaa_parse::{ParseOptions, SpaaFile};
+//! use std::fs::File;
+//!
+//! let file = File::open("profile.spaa").unwrap();
+//! let options = ParseOptions {
+//!     size_hint: file.metadata().ok().map(|m| m.len()),
+//!     ..Default::default()
+//! };
+//! let mut progress = TerminalProgress::new();
+//! let (spaa, _) = SpaaFile::parse_with_progress(file, &options, &mut progress).unwrap();
+//! progress.finish();
+//! ```
+
+pub use spaa_parse::{CancelToken, Cancelled, Deadline, NoProgress, Progress, ProgressSink};
+
+use std::io::{IsTerminal, Write};
+use std::ops::ControlFlow;
+use std::time::{Duration, Instant};
+
+/// Minimum time between redraws.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Width of the bar, in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Draws a single-line progress bar on stderr.
+///
+/// Draws nothing when stderr is not a terminal, so logs and pipes stay
+/// clean. Never cancels.
+#[derive(Debug)]
+pub struct TerminalProgress {
+    enabled: bool,
+    last_draw: Option<Instant>,
+    drawn: bool,
+}
+
+impl TerminalProgress {
+    pub fn new() -> Self {
+        Self {
+            enabled: std::io::stderr().is_terminal(),
+            last_draw: None,
+            drawn: false,
+        }
+    }
+
+    /// Clear the progress line so subsequent output starts on a clean line.
+    pub fn finish(&mut self) {
+        if self.drawn {
+            eprint!("\r\x1b[2K");
+            self.drawn = false;
+        }
+    }
+}
+
+impl Default for TerminalProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressSink for TerminalProgress {
+    fn report(&mut self, progress: &Progress) -> ControlFlow<()> {
+        if !self.enabled {
+            return ControlFlow::Continue(());
+        }
+        let complete = progress.total.is_some_and(|t| progress.done >= t);
+        if !complete
+            && self
+                .last_draw
+                .is_some_and(|t| t.elapsed() < REDRAW_INTERVAL)
+        {
+            return ControlFlow::Continue(());
+        }
+        self.last_draw = Some(Instant::now());
+        self.drawn = true;
+
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K{}", render(progress));
+        let _ = stderr.flush();
+        ControlFlow::Continue(())
+    }
+}
+
+/// Render one progress line, e.g. `parse [#######.......]  50% 512/1024`.
+fn render(progress: &Progress) -> String {
+    match (progress.fraction(), progress.total) {
+        (Some(fraction), Some(total)) => {
+            let filled = (fraction * BAR_WIDTH as f64).round() as usize;
+            format!(
+                "{} [{}{}] {:>3.0}% {}/{}",
+                progress.stage,
+                "#".repeat(filled),
+                ".".repeat(BAR_WIDTH - filled),
+                fraction * 100.0,
+                progress.done,
+                total
+            )
+        }
+        _ => format!("{} {}", progress.stage, progress.done),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_bar_when_total_known() {
+        let line = render(&Progress {
+            stage: "parse",
+            done: 50,
+            total: Some(100),
+        });
+        assert_eq!(
+            line,
+            format!("parse [{}{}]  50% 50/100", "#".repeat(15), ".".repeat(15))
+        );
+    }
+
+    #[test]
+    fn renders_count_when_total_unknown() {
+        let line = render(&Progress {
+            stage: "merge",
+            done: 7,
+            total: None,
+        });
+        assert_eq!(line, "merge 7");
+    }
+}
@@ -0,0 +1,534 @@
+//! "What changed" narratives comparing a profile against a baseline.
+
+use super::{Renderer, ReportFormat, ReportOptions, leaf_frame, percent};
+use crate::analysis::stack_weight;
+use serde::Serialize;
+use spaa_parse::{FrameOrder, SpaaFile};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+/// Share change, in percentage points, below which a shift in the kernel
+/// split is not mentioned.
+const KERNEL_SHIFT_POINTS: f64 = 1.0;
+
+/// Differences between a baseline and a current profile.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Comparison {
+    pub baseline_tool: String,
+    pub current_tool: String,
+    /// Events recorded in both profiles, in the current profile's order.
+    pub events: Vec<EventComparison>,
+    /// Events recorded only in the current profile.
+    pub added_events: Vec<String>,
+    /// Events recorded only in the baseline.
+    pub removed_events: Vec<String>,
+    /// One sentence per notable change, grouped by event.
+    pub summary: Vec<String>,
+}
+
+/// Differences within one event.
+///
+/// Functions and stacks are matched by name, not ID, so profiles from
+/// different builds or converters compare cleanly. Shares are compared
+/// rather than raw weights, since two runs rarely collect the same number
+/// of samples.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EventComparison {
+    pub name: String,
+    /// Primary metric in the current profile.
+    pub metric: String,
+    pub baseline_total: u64,
+    pub current_total: u64,
+    /// Fraction of weight whose leaf frame is in a kernel DSO.
+    pub baseline_kernel_share: f64,
+    pub current_kernel_share: f64,
+    /// Functions whose self share changed most, largest change first.
+    pub functions: Vec<FunctionDelta>,
+    /// Function whose self share grew the most, even if `functions` was
+    /// cut off before it.
+    pub largest_increase: Option<FunctionDelta>,
+    /// Function whose self share shrank the most.
+    pub largest_decrease: Option<FunctionDelta>,
+    /// Call paths only in the current profile, heaviest first.
+    pub new_stacks: Vec<StackChange>,
+    /// Call paths only in the baseline, heaviest first.
+    pub removed_stacks: Vec<StackChange>,
+    /// Share of the current profile in new call paths.
+    pub new_share: f64,
+    /// Share of the baseline in removed call paths.
+    pub removed_share: f64,
+}
+
+impl EventComparison {
+    /// Relative change in total weight, or `None` if the baseline is empty.
+    pub fn total_change(&self) -> Option<f64> {
+        (self.baseline_total > 0)
+            .then(|| self.current_total as f64 / self.baseline_total as f64 - 1.0)
+    }
+}
+
+/// Change in one function's self weight.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FunctionDelta {
+    pub function: String,
+    pub baseline_weight: u64,
+    pub current_weight: u64,
+    pub baseline_share: f64,
+    pub current_share: f64,
+}
+
+impl FunctionDelta {
+    /// `current_share - baseline_share`.
+    pub fn share_delta(&self) -> f64 {
+        self.current_share - self.baseline_share
+    }
+}
+
+/// A call path present in only one of the profiles.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StackChange {
+    /// Function names from the outermost caller down to the leaf.
+    pub functions: Vec<String>,
+    pub weight: u64,
+    /// `weight` as a fraction of its own profile's event total.
+    pub share: f64,
+}
+
+/// Compare `current` against `baseline`.
+///
+/// `options.top` limits the functions listed per event and
+/// `options.stacks` the new and removed stacks.
+pub fn build_comparison(
+    baseline: &SpaaFile,
+    current: &SpaaFile,
+    options: &ReportOptions,
+) -> Comparison {
+    let baseline_events: HashSet<&str> = baseline
+        .header
+        .events
+        .iter()
+        .map(|e| e.name.as_str())
+        .collect();
+    let current_events: HashSet<&str> = current
+        .header
+        .events
+        .iter()
+        .map(|e| e.name.as_str())
+        .collect();
+
+    let events: Vec<EventComparison> = current
+        .header
+        .events
+        .iter()
+        .filter(|e| baseline_events.contains(e.name.as_str()))
+        .map(|event| {
+            let metric = &event.sampling.primary_metric;
+            let baseline_metric = baseline
+                .primary_metric_for_event(&event.name)
+                .unwrap_or(metric);
+            compare_event(
+                &event.name,
+                metric,
+                &Digest::new(baseline, &event.name, baseline_metric),
+                &Digest::new(current, &event.name, metric),
+                options,
+            )
+        })
+        .collect();
+
+    let mut comparison = Comparison {
+        baseline_tool: baseline.header.source_tool.clone(),
+        current_tool: current.header.source_tool.clone(),
+        events,
+        added_events: current
+            .header
+            .events
+            .iter()
+            .filter(|e| !baseline_events.contains(e.name.as_str()))
+            .map(|e| e.name.clone())
+            .collect(),
+        removed_events: baseline
+            .header
+            .events
+            .iter()
+            .filter(|e| !current_events.contains(e.name.as_str()))
+            .map(|e| e.name.clone())
+            .collect(),
+        summary: Vec::new(),
+    };
+    comparison.summary = summarize(&comparison);
+    comparison
+}
+
+/// Per-event weights keyed by name rather than ID.
+struct Digest<'a> {
+    total: u64,
+    kernel: u64,
+    /// Self weight by leaf function name.
+    functions: HashMap<&'a str, u64>,
+    /// Weight by root-to-leaf function names.
+    stacks: HashMap<Vec<&'a str>, u64>,
+}
+
+impl<'a> Digest<'a> {
+    fn new(file: &'a SpaaFile, event: &str, metric: &str) -> Self {
+        let mut digest = Digest {
+            total: 0,
+            kernel: 0,
+            functions: HashMap::new(),
+            stacks: HashMap::new(),
+        };
+        for stack in file.stacks_for_event(event) {
+            let weight = stack_weight(stack, metric);
+            if weight == 0 {
+                continue;
+            }
+            digest.total += weight;
+            if let Some(frame) = leaf_frame(file, stack) {
+                *digest.functions.entry(&frame.func).or_default() += weight;
+                if file.resolve_dso(frame.dso).is_some_and(|d| d.is_kernel) {
+                    digest.kernel += weight;
+                }
+            }
+            let mut path: Vec<&str> = stack
+                .frames
+                .iter()
+                .filter_map(|&id| file.resolve_frame(id))
+                .map(|f| f.func.as_str())
+                .collect();
+            if file.header.frame_order == FrameOrder::LeafToRoot {
+                path.reverse();
+            }
+            *digest.stacks.entry(path).or_default() += weight;
+        }
+        digest
+    }
+
+    fn share(&self, weight: u64) -> f64 {
+        weight as f64 / self.total.max(1) as f64
+    }
+}
+
+fn compare_event(
+    name: &str,
+    metric: &str,
+    baseline: &Digest,
+    current: &Digest,
+    options: &ReportOptions,
+) -> EventComparison {
+    let names: HashSet<&str> = baseline
+        .functions
+        .keys()
+        .chain(current.functions.keys())
+        .copied()
+        .collect();
+    let mut functions: Vec<FunctionDelta> = names
+        .into_iter()
+        .map(|function| {
+            let baseline_weight = baseline.functions.get(function).copied().unwrap_or(0);
+            let current_weight = current.functions.get(function).copied().unwrap_or(0);
+            FunctionDelta {
+                function: function.to_string(),
+                baseline_weight,
+                current_weight,
+                baseline_share: baseline.share(baseline_weight),
+                current_share: current.share(current_weight),
+            }
+        })
+        .filter(|d| d.share_delta() != 0.0)
+        .collect();
+    functions.sort_by(|a, b| {
+        b.share_delta()
+            .abs()
+            .total_cmp(&a.share_delta().abs())
+            .then_with(|| a.function.cmp(&b.function))
+    });
+    let largest_increase = functions.iter().find(|d| d.share_delta() > 0.0).cloned();
+    let largest_decrease = functions.iter().find(|d| d.share_delta() < 0.0).cloned();
+    functions.truncate(options.top);
+
+    let (new_stacks, new_weight) = only_in(current, baseline, options.stacks);
+    let (removed_stacks, removed_weight) = only_in(baseline, current, options.stacks);
+
+    EventComparison {
+        name: name.to_string(),
+        metric: metric.to_string(),
+        baseline_total: baseline.total,
+        current_total: current.total,
+        baseline_kernel_share: baseline.share(baseline.kernel),
+        current_kernel_share: current.share(current.kernel),
+        functions,
+        largest_increase,
+        largest_decrease,
+        new_stacks,
+        removed_stacks,
+        new_share: current.share(new_weight),
+        removed_share: baseline.share(removed_weight),
+    }
+}
+
+/// The heaviest `n` call paths of `a` missing from `b`, and the total
+/// weight of all of them.
+fn only_in(a: &Digest, b: &Digest, n: usize) -> (Vec<StackChange>, u64) {
+    let mut changes: Vec<StackChange> = a
+        .stacks
+        .iter()
+        .filter(|(path, _)| !b.stacks.contains_key(*path))
+        .map(|(path, &weight)| StackChange {
+            functions: path.iter().map(|f| f.to_string()).collect(),
+            weight,
+            share: a.share(weight),
+        })
+        .collect();
+    let total = changes.iter().map(|c| c.weight).sum();
+    changes.sort_by(|x, y| {
+        y.weight
+            .cmp(&x.weight)
+            .then_with(|| x.functions.cmp(&y.functions))
+    });
+    changes.truncate(n);
+    (changes, total)
+}
+
+fn points(delta: f64) -> String {
+    format!("{:+.1} pts", delta * 100.0)
+}
+
+fn summarize(comparison: &Comparison) -> Vec<String> {
+    let mut lines = Vec::new();
+    for event in &comparison.events {
+        match event.total_change() {
+            Some(change) => lines.push(format!(
+                "`{}` total went from {} to {} {} ({:+.1}%).",
+                event.name,
+                event.baseline_total,
+                event.current_total,
+                event.metric,
+                change * 100.0
+            )),
+            None => lines.push(format!(
+                "`{}` had no weight in the baseline and {} {} now.",
+                event.name, event.current_total, event.metric
+            )),
+        }
+        for (delta, verb) in [
+            (&event.largest_increase, "increase"),
+            (&event.largest_decrease, "decrease"),
+        ] {
+            if let Some(delta) = delta {
+                lines.push(format!(
+                    "Largest {verb} in `{}`: `{}` went from {} to {} of self time ({}).",
+                    event.name,
+                    delta.function,
+                    percent(delta.baseline_share),
+                    percent(delta.current_share),
+                    points(delta.share_delta())
+                ));
+            }
+        }
+        let kernel_shift = event.current_kernel_share - event.baseline_kernel_share;
+        if kernel_shift.abs() * 100.0 >= KERNEL_SHIFT_POINTS {
+            lines.push(format!(
+                "Kernel share of `{}` moved from {} to {} ({}); user space is now {}.",
+                event.name,
+                percent(event.baseline_kernel_share),
+                percent(event.current_kernel_share),
+                points(kernel_shift),
+                percent(1.0 - event.current_kernel_share)
+            ));
+        }
+        if event.new_share > 0.0 || event.removed_share > 0.0 {
+            lines.push(format!(
+                "New call paths carry {} of `{}`; paths that disappeared carried {} of the baseline.",
+                percent(event.new_share),
+                event.name,
+                percent(event.removed_share)
+            ));
+        }
+    }
+    for event in &comparison.added_events {
+        lines.push(format!(
+            "`{event}` is recorded only in the current profile."
+        ));
+    }
+    for event in &comparison.removed_events {
+        lines.push(format!("`{event}` is recorded only in the baseline."));
+    }
+    lines
+}
+
+impl Comparison {
+    /// Render the comparison in `format`.
+    pub fn write<W: Write>(&self, writer: W, format: ReportFormat) -> io::Result<()> {
+        let mut out = Renderer {
+            out: writer,
+            format,
+        };
+        out.comparison(self)
+    }
+}
+
+impl<W: Write> Renderer<W> {
+    fn comparison(&mut self, comparison: &Comparison) -> io::Result<()> {
+        self.heading(
+            1,
+            &format!(
+                "Profile comparison: {} (baseline) vs {} (current)",
+                comparison.baseline_tool, comparison.current_tool
+            ),
+        )?;
+        self.heading(2, "Summary")?;
+        for line in &comparison.summary {
+            let line = self.prose(line);
+            self.item(&line)?;
+        }
+        writeln!(self.out)?;
+
+        for event in &comparison.events {
+            self.heading(2, &event.name)?;
+            writeln!(
+                self.out,
+                "Total {}: {} -> {}. Kernel share: {} -> {}.",
+                self.code(&event.metric),
+                event.baseline_total,
+                event.current_total,
+                percent(event.baseline_kernel_share),
+                percent(event.current_kernel_share)
+            )?;
+            writeln!(self.out)?;
+
+            if !event.functions.is_empty() {
+                self.heading(3, "Function changes")?;
+                let rows: Vec<Vec<String>> = event
+                    .functions
+                    .iter()
+                    .map(|d| {
+                        vec![
+                            self.code(&d.function),
+                            percent(d.baseline_share),
+                            percent(d.current_share),
+                            points(d.share_delta()),
+                        ]
+                    })
+                    .collect();
+                self.table(&["Function", "Baseline", "Current", "Change"], &rows)?;
+            }
+            self.stack_changes("New stacks", &event.new_stacks)?;
+            self.stack_changes("Removed stacks", &event.removed_stacks)?;
+        }
+        Ok(())
+    }
+
+    fn stack_changes(&mut self, title: &str, changes: &[StackChange]) -> io::Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        self.heading(3, title)?;
+        for (i, change) in changes.iter().enumerate() {
+            let path = self.path(&change.functions);
+            writeln!(self.out, "{}. {} {}", i + 1, percent(change.share), path)?;
+        }
+        writeln!(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn profile(stacks: &[(&str, &str, u64)]) -> SpaaFile {
+        let mut lines = vec![
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#.to_string(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#.to_string(),
+            r#"{"type":"dso","id":2,"name":"[kernel.kallsyms]","is_kernel":true}"#.to_string(),
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#.to_string(),
+            r#"{"type":"frame","id":2,"func":"parse","dso":1}"#.to_string(),
+            r#"{"type":"frame","id":3,"func":"render","dso":1}"#.to_string(),
+            r#"{"type":"frame","id":4,"func":"do_syscall_64","dso":2}"#.to_string(),
+        ];
+        let id = |f: &str| match f {
+            "main" => 1,
+            "parse" => 2,
+            "render" => 3,
+            _ => 4,
+        };
+        for (i, (leaf, caller, weight)) in stacks.iter().enumerate() {
+            lines.push(format!(
+                r#"{{"type":"stack","id":"{i}","frames":[{},{}],"context":{{"event":"cycles"}},"weights":[{{"metric":"period","value":{weight}}}]}}"#,
+                id(leaf),
+                id(caller)
+            ));
+        }
+        SpaaFile::parse(Cursor::new(lines.join("\n"))).unwrap()
+    }
+
+    fn comparison() -> Comparison {
+        let baseline = profile(&[("parse", "main", 80), ("render", "main", 20)]);
+        let current = profile(&[
+            ("parse", "main", 50),
+            ("do_syscall_64", "render", 50),
+            ("do_syscall_64", "main", 100),
+        ]);
+        build_comparison(&baseline, &current, &ReportOptions::default())
+    }
+
+    #[test]
+    fn function_deltas_compare_shares() {
+        let comparison = comparison();
+        let event = &comparison.events[0];
+
+        assert_eq!(event.total_change(), Some(1.0));
+        let syscall = &event.functions[0];
+        assert_eq!(syscall.function, "do_syscall_64");
+        assert_eq!(syscall.share_delta(), 0.75);
+        let parse = event
+            .functions
+            .iter()
+            .find(|d| d.function == "parse")
+            .unwrap();
+        assert_eq!((parse.baseline_share, parse.current_share), (0.8, 0.25));
+    }
+
+    #[test]
+    fn new_and_removed_stacks_match_by_function_names() {
+        let comparison = comparison();
+        let event = &comparison.events[0];
+
+        let new: Vec<&[String]> = event.new_stacks.iter().map(|s| &s.functions[..]).collect();
+        assert_eq!(
+            new,
+            [
+                &["main".to_string(), "do_syscall_64".to_string()][..],
+                &["render".to_string(), "do_syscall_64".to_string()][..],
+            ]
+        );
+        assert_eq!(event.removed_stacks[0].functions, ["main", "render"]);
+        assert_eq!(event.removed_share, 0.2);
+    }
+
+    #[test]
+    fn summary_reports_kernel_shift() {
+        let comparison = comparison();
+
+        assert_eq!(comparison.events[0].current_kernel_share, 0.75);
+        assert!(
+            comparison
+                .summary
+                .iter()
+                .any(|s| s.starts_with("Kernel share of `cycles` moved from 0.0% to 75.0%"))
+        );
+    }
+
+    #[test]
+    fn markdown_lists_function_changes() {
+        let mut out = Vec::new();
+        comparison()
+            .write(&mut out, ReportFormat::Markdown)
+            .unwrap();
+        let markdown = String::from_utf8(out).unwrap();
+
+        assert!(markdown.contains("| `do_syscall_64` | 0.0% | 75.0% | +75.0 pts |"));
+        assert!(markdown.contains("### Removed stacks\n\n1. 20.0% `main` -> `render`"));
+    }
+}
@@ -0,0 +1,953 @@
+//! Narrative profile reports in Markdown or plain text.
+//!
+//! [`build_report`] digests a profile with the [`analysis`](crate::analysis)
+//! helpers into a [`Report`]: a summary, the top hotspots with their source
+//! lines, hot paths, per-thread notes, an allocation section for allocation
+//! events, and suggested next steps. [`Report::write`] renders it in a form
+//! that can be pasted into an issue or an agent prompt.
+//!
+//! [`build_comparison`] does the same for a profile against a baseline:
+//! per-function changes in self share, call paths that appeared or
+//! disappeared, and the shift between kernel and user time.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::report::{ReportFormat, ReportOptions, build_report};
+//! use spaa_parse::SpaaFile;
+//!
+//! let spaa = SpaaFile::open("profile.spaa").unwrap();
+//! let report = build_report(&spaa, &ReportOptions::default());
+//! report.write(std::io::stdout(), ReportFormat::Markdown).unwrap();
+//! ```
+
+mod compare;
+
+pub use compare::{Comparison, EventComparison, FunctionDelta, StackChange, build_comparison};
+
+use crate::analysis::{GroupKey, HotPath, group_by, hot_paths, stack_weight};
+use crate::leaks::{LeakSuspect, suspects_from_allocations};
+use spaa_parse::{DataLoss, EventKind, Frame, FrameOrder, SpaaFile, Stack, TimeRange, Weight};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Self share above which a single function is called out as the place to
+/// start.
+const DOMINANT_SHARE: f64 = 0.2;
+
+/// Share of weight in unresolved frames worth a warning.
+const UNRESOLVED_SHARE: f64 = 0.1;
+
+/// Share of weight in kernel frames worth pointing at system calls.
+const KERNEL_SHARE: f64 = 0.3;
+
+/// Share of weight on one thread that suggests the work is not spread out.
+const SINGLE_THREAD_SHARE: f64 = 0.5;
+
+/// Hot paths longer than this are shown as their outermost two and
+/// innermost frames around an ellipsis.
+const MAX_PATH_FRAMES: usize = 8;
+
+/// Output format for [`Report::write`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    #[default]
+    Markdown,
+    Text,
+}
+
+impl fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReportFormat::Markdown => "markdown",
+            ReportFormat::Text => "text",
+        })
+    }
+}
+
+/// Error returned when parsing an unknown [`ReportFormat`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseReportFormatError(String);
+
+impl fmt::Display for ParseReportFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown report format '{}' (expected markdown or text)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseReportFormatError {}
+
+impl FromStr for ReportFormat {
+    type Err = ParseReportFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" | "md" => Ok(ReportFormat::Markdown),
+            "text" | "txt" => Ok(ReportFormat::Text),
+            _ => Err(ParseReportFormatError(s.to_string())),
+        }
+    }
+}
+
+/// How much detail [`build_report`] collects.
+#[derive(Debug, Clone)]
+pub struct ReportOptions {
+    /// Hotspots listed per event.
+    pub top: usize,
+    /// Hot paths listed per event.
+    pub paths: usize,
+    /// Threads listed per event.
+    pub threads: usize,
+    /// New and removed stacks listed per event in a comparison.
+    pub stacks: usize,
+}
+
+impl Default for ReportOptions {
+    fn default() -> Self {
+        Self {
+            top: 10,
+            paths: 3,
+            threads: 5,
+            stacks: 5,
+        }
+    }
+}
+
+/// A digested profile, ready to render.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub source_tool: String,
+    /// Command line the profile was recorded with, if known.
+    pub command: Option<String>,
+    pub time_range: Option<TimeRange>,
+    pub data_loss: Option<DataLoss>,
+    /// One section per header event, in header order.
+    pub events: Vec<EventReport>,
+    pub next_steps: Vec<String>,
+}
+
+/// Report section for one event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventReport {
+    pub name: String,
+    pub kind: EventKind,
+    pub description: Option<String>,
+    pub unit: Option<String>,
+    /// Primary metric the section is weighted by.
+    pub metric: String,
+    pub total: u64,
+    pub stacks: usize,
+    /// Functions by self weight, heaviest first.
+    pub hotspots: Vec<Hotspot>,
+    pub hot_paths: Vec<HotPath>,
+    /// Threads by weight, heaviest first.
+    pub threads: Vec<ThreadNote>,
+    /// Present for allocation events.
+    pub allocation: Option<AllocationReport>,
+    /// Fraction of weight whose leaf frame is in a kernel DSO.
+    pub kernel_share: f64,
+    /// Fraction of weight whose leaf frame has an unresolved symbol.
+    pub unresolved_share: f64,
+}
+
+/// A function's weight within one event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hotspot {
+    pub function: String,
+    pub dso: Option<String>,
+    /// Source location of the function's first frame that has one.
+    pub srcline: Option<String>,
+    /// Weight of stacks whose leaf is this function.
+    pub self_weight: u64,
+    pub self_share: f64,
+    /// Weight of stacks this function appears anywhere in.
+    pub total_weight: u64,
+    pub total_share: f64,
+}
+
+/// Weight attributed to one thread.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadNote {
+    pub tid: u64,
+    pub comm: Option<String>,
+    pub weight: u64,
+    pub share: f64,
+    /// Function with the most self weight on this thread.
+    pub top_function: Option<String>,
+}
+
+/// Totals and top sites for an allocation event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllocationReport {
+    /// Sum of every metric the event's stacks record, by metric name.
+    pub totals: Vec<Weight>,
+    pub tracks_frees: bool,
+    /// Metric counting allocations (e.g. `alloc_count`), if recorded.
+    pub count_metric: Option<String>,
+    /// Allocation sites by primary weight, heaviest first.
+    pub sites: Vec<AllocationSite>,
+    /// Likely leaks, from the file's windows; empty without windows.
+    pub leaks: Vec<LeakSuspect>,
+}
+
+/// Allocations attributed to one function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllocationSite {
+    pub function: String,
+    pub srcline: Option<String>,
+    /// Primary metric (usually bytes).
+    pub weight: u64,
+    /// Value of the count metric, if recorded.
+    pub count: Option<u64>,
+}
+
+/// Digest `file` into a [`Report`].
+pub fn build_report(file: &SpaaFile, options: &ReportOptions) -> Report {
+    let source = file.header.source.as_ref();
+    let events: Vec<EventReport> = file
+        .header
+        .events
+        .iter()
+        .map(|event| {
+            let metric = event.sampling.primary_metric.clone();
+            let mut section = EventReport {
+                name: event.name.clone(),
+                kind: event.kind,
+                description: event.description.clone(),
+                unit: event.unit.clone(),
+                total: 0,
+                stacks: 0,
+                hotspots: Vec::new(),
+                hot_paths: hot_paths(file, &event.name, &metric, options.paths),
+                threads: Vec::new(),
+                allocation: None,
+                kernel_share: 0.0,
+                unresolved_share: 0.0,
+                metric,
+            };
+            digest_event(file, &mut section, options);
+            if event.kind == EventKind::Allocation || event.allocation_tracking.is_some() {
+                section.allocation = Some(allocation_report(
+                    file,
+                    &section,
+                    event
+                        .allocation_tracking
+                        .as_ref()
+                        .is_some_and(|a| a.tracks_frees),
+                    options.top,
+                ));
+            }
+            section
+        })
+        .collect();
+
+    let mut report = Report {
+        source_tool: file.header.source_tool.clone(),
+        command: source.and_then(|s| s.command.clone()),
+        time_range: file.header.time_range.clone(),
+        data_loss: source.and_then(|s| s.data_loss.clone()),
+        events,
+        next_steps: Vec::new(),
+    };
+    report.next_steps = next_steps(&report);
+    report
+}
+
+/// Function-level key: name plus DSO, so same-named functions in different
+/// binaries stay apart.
+type FunctionKey = (String, u64);
+
+/// Fill in totals, hotspots, threads and kernel/unresolved shares.
+fn digest_event(file: &SpaaFile, section: &mut EventReport, options: &ReportOptions) {
+    let mut self_weight: HashMap<FunctionKey, u64> = HashMap::new();
+    let mut total_weight: HashMap<FunctionKey, u64> = HashMap::new();
+    let mut srclines: HashMap<FunctionKey, String> = HashMap::new();
+    let mut thread_self: HashMap<u64, HashMap<&str, u64>> = HashMap::new();
+    let (mut kernel, mut unresolved) = (0, 0);
+
+    for stack in file.stacks_for_event(&section.name) {
+        section.stacks += 1;
+        let weight = stack_weight(stack, &section.metric);
+        if weight == 0 {
+            continue;
+        }
+        section.total += weight;
+
+        let mut seen = HashSet::new();
+        for frame in stack.frames.iter().filter_map(|&id| file.resolve_frame(id)) {
+            let key = (frame.func.clone(), frame.dso);
+            if let Some(srcline) = &frame.srcline {
+                srclines
+                    .entry(key.clone())
+                    .or_insert_with(|| srcline.clone());
+            }
+            if seen.insert(key.clone()) {
+                *total_weight.entry(key).or_default() += weight;
+            }
+        }
+        if let Some(frame) = leaf_frame(file, stack) {
+            *self_weight
+                .entry((frame.func.clone(), frame.dso))
+                .or_default() += weight;
+            if file.resolve_dso(frame.dso).is_some_and(|d| d.is_kernel) {
+                kernel += weight;
+            }
+            if !frame.func_resolved {
+                unresolved += weight;
+            }
+            if let Some(tid) = stack.context.tid {
+                *thread_self
+                    .entry(tid)
+                    .or_default()
+                    .entry(&frame.func)
+                    .or_default() += weight;
+            }
+        }
+    }
+
+    let total = section.total.max(1) as f64;
+    section.kernel_share = kernel as f64 / total;
+    section.unresolved_share = unresolved as f64 / total;
+
+    let mut hotspots: Vec<Hotspot> = total_weight
+        .into_iter()
+        .map(|(key, total_w)| {
+            let self_w = self_weight.get(&key).copied().unwrap_or(0);
+            Hotspot {
+                dso: file.resolve_dso(key.1).map(|d| d.name.clone()),
+                srcline: srclines.remove(&key),
+                function: key.0,
+                self_weight: self_w,
+                self_share: self_w as f64 / total,
+                total_weight: total_w,
+                total_share: total_w as f64 / total,
+            }
+        })
+        .collect();
+    hotspots.sort_by(|a, b| {
+        b.self_weight
+            .cmp(&a.self_weight)
+            .then(b.total_weight.cmp(&a.total_weight))
+            .then_with(|| a.function.cmp(&b.function))
+    });
+    hotspots.truncate(options.top);
+    section.hotspots = hotspots;
+
+    section.threads = group_by(file, &section.name, &section.metric, GroupKey::Tid)
+        .into_iter()
+        .filter_map(|group| {
+            let tid: u64 = group.key?.parse().ok()?;
+            let top_function = thread_self.get(&tid).and_then(|functions| {
+                functions
+                    .iter()
+                    .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)))
+                    .map(|(name, _)| name.to_string())
+            });
+            Some(ThreadNote {
+                tid,
+                comm: file.threads.get(&tid).and_then(|t| t.comm.clone()),
+                weight: group.weight,
+                share: group.share,
+                top_function,
+            })
+        })
+        .take(options.threads)
+        .collect();
+}
+
+fn allocation_report(
+    file: &SpaaFile,
+    section: &EventReport,
+    tracks_frees: bool,
+    top: usize,
+) -> AllocationReport {
+    let mut totals: BTreeMap<&str, u64> = BTreeMap::new();
+    for stack in file.stacks_for_event(&section.name) {
+        for weight in &stack.weights {
+            *totals.entry(&weight.metric).or_default() += weight.value;
+        }
+    }
+    let count_metric = totals
+        .keys()
+        .find(|m| **m != section.metric && m.ends_with("count"))
+        .map(|m| m.to_string());
+
+    // Count per allocation site, keyed by leaf function.
+    let mut counts: HashMap<&str, u64> = HashMap::new();
+    if let Some(count_metric) = &count_metric {
+        for stack in file.stacks_for_event(&section.name) {
+            if let Some(frame) = leaf_frame(file, stack) {
+                *counts.entry(&frame.func).or_default() += stack_weight(stack, count_metric);
+            }
+        }
+    }
+
+    AllocationReport {
+        totals: totals
+            .into_iter()
+            .map(|(metric, value)| Weight {
+                metric: metric.to_string(),
+                value,
+                unit: None,
+            })
+            .collect(),
+        tracks_frees,
+        sites: section
+            .hotspots
+            .iter()
+            .filter(|h| h.self_weight > 0)
+            .take(top)
+            .map(|h| AllocationSite {
+                function: h.function.clone(),
+                srcline: h.srcline.clone(),
+                weight: h.self_weight,
+                count: count_metric
+                    .as_ref()
+                    .map(|_| counts.get(h.function.as_str()).copied().unwrap_or(0)),
+            })
+            .collect(),
+        count_metric,
+        leaks: suspects_from_allocations(file, &section.name, top),
+    }
+}
+
+/// Heuristic suggestions, most actionable first.
+fn next_steps(report: &Report) -> Vec<String> {
+    let mut steps = Vec::new();
+    if let Some(loss) = &report.data_loss {
+        steps.push(format!(
+            "The profiler lost {} events; treat weights as lower bounds, and consider a lower sampling rate or larger buffers.",
+            loss.lost_events
+        ));
+    }
+    for event in &report.events {
+        if event.total == 0 {
+            continue;
+        }
+        if let Some(top) = event.hotspots.first()
+            && top.self_share >= DOMINANT_SHARE
+        {
+            let location = top
+                .srcline
+                .as_ref()
+                .map_or(String::new(), |s| format!(" ({s})"));
+            steps.push(format!(
+                "Start with `{}`{location}: it accounts for {} of `{}` on its own.",
+                top.function,
+                percent(top.self_share),
+                event.name
+            ));
+        }
+        if event.unresolved_share >= UNRESOLVED_SHARE {
+            steps.push(format!(
+                "{} of `{}` lands in unresolved frames; install debug symbols or rerun with symbolization to see where.",
+                percent(event.unresolved_share),
+                event.name
+            ));
+        }
+        if event.kernel_share >= KERNEL_SHARE {
+            steps.push(format!(
+                "{} of `{}` is spent in the kernel; look at which system calls or page faults lead there.",
+                percent(event.kernel_share),
+                event.name
+            ));
+        }
+        if event.threads.len() > 1
+            && let Some(thread) = event.threads.first()
+            && thread.share >= SINGLE_THREAD_SHARE
+        {
+            steps.push(format!(
+                "Thread {}{} carries {} of `{}`; check whether that work can be parallelized.",
+                thread.tid,
+                thread
+                    .comm
+                    .as_ref()
+                    .map_or(String::new(), |c| format!(" ({c})")),
+                percent(thread.share),
+                event.name
+            ));
+        }
+        if let Some(site) = event.allocation.as_ref().and_then(|a| a.sites.first()) {
+            steps.push(format!(
+                "The largest allocation site is `{}`; check whether its allocations can be reused, pooled or avoided.",
+                site.function
+            ));
+        }
+    }
+    if steps.is_empty() {
+        steps.push(
+            "No single hotspot dominates; compare against a baseline profile to find regressions."
+                .to_string(),
+        );
+    }
+    steps
+}
+
+/// The innermost frame of `stack`.
+fn leaf_frame<'a>(file: &'a SpaaFile, stack: &Stack) -> Option<&'a Frame> {
+    let leaf = match file.header.frame_order {
+        FrameOrder::LeafToRoot => stack.frames.first(),
+        FrameOrder::RootToLeaf => stack.frames.last(),
+    };
+    leaf.and_then(|&id| file.resolve_frame(id))
+}
+
+fn percent(share: f64) -> String {
+    format!("{:.1}%", share * 100.0)
+}
+
+impl Report {
+    /// Render the report in `format`.
+    pub fn write<W: Write>(&self, writer: W, format: ReportFormat) -> io::Result<()> {
+        let mut out = Renderer {
+            out: writer,
+            format,
+        };
+        out.report(self)
+    }
+}
+
+/// Writes report elements in either format.
+struct Renderer<W> {
+    out: W,
+    format: ReportFormat,
+}
+
+impl<W: Write> Renderer<W> {
+    fn report(&mut self, report: &Report) -> io::Result<()> {
+        self.heading(1, &format!("Profile report: {}", report.source_tool))?;
+        self.heading(2, "Summary")?;
+        if let Some(command) = &report.command {
+            self.item(&format!("Command: {}", self.code(command)))?;
+        }
+        if let Some(range) = &report.time_range {
+            self.item(&format!(
+                "Duration: {:.2} {}",
+                range.end - range.start,
+                range.unit
+            ))?;
+        }
+        for event in &report.events {
+            self.item(&format!(
+                "{}: {} {} across {} stacks",
+                self.code(&event.name),
+                event.total,
+                event.metric,
+                event.stacks
+            ))?;
+        }
+        if let Some(loss) = &report.data_loss {
+            self.item(&format!(
+                "Data loss: {} events in {} records were dropped during collection",
+                loss.lost_events, loss.lost_records
+            ))?;
+        }
+        writeln!(self.out)?;
+
+        for event in &report.events {
+            self.event(event)?;
+        }
+
+        self.heading(2, "Suggested next steps")?;
+        for (i, step) in report.next_steps.iter().enumerate() {
+            writeln!(self.out, "{}. {}", i + 1, self.prose(step))?;
+        }
+        Ok(())
+    }
+
+    fn event(&mut self, event: &EventReport) -> io::Result<()> {
+        self.heading(2, &event.name)?;
+        if let Some(description) = &event.description {
+            writeln!(self.out, "{description}")?;
+            writeln!(self.out)?;
+        }
+        let unit = event
+            .unit
+            .as_ref()
+            .map_or(String::new(), |u| format!(" (unit: {u})"));
+        writeln!(
+            self.out,
+            "Weighted by {}{unit}. Total: {} across {} stacks.",
+            self.code(&event.metric),
+            event.total,
+            event.stacks
+        )?;
+        writeln!(self.out)?;
+        if event.total == 0 {
+            return Ok(());
+        }
+
+        if !event.hotspots.is_empty() {
+            self.heading(3, "Top hotspots")?;
+            let rows: Vec<Vec<String>> = event
+                .hotspots
+                .iter()
+                .enumerate()
+                .map(|(i, h)| {
+                    vec![
+                        (i + 1).to_string(),
+                        self.code(&h.function),
+                        percent(h.self_share),
+                        percent(h.total_share),
+                        h.srcline.clone().or(h.dso.clone()).unwrap_or_default(),
+                    ]
+                })
+                .collect();
+            self.table(&["#", "Function", "Self", "Total", "Location"], &rows)?;
+        }
+
+        if !event.hot_paths.is_empty() {
+            self.heading(3, "Hot paths")?;
+            for (i, path) in event.hot_paths.iter().enumerate() {
+                let functions = self.path(&path.functions);
+                writeln!(self.out, "{}. {} {}", i + 1, percent(path.share), functions)?;
+            }
+            writeln!(self.out)?;
+        }
+
+        if !event.threads.is_empty() {
+            self.heading(3, "Threads")?;
+            for thread in &event.threads {
+                let comm = thread
+                    .comm
+                    .as_ref()
+                    .map_or(String::new(), |c| format!(" ({})", self.code(c)));
+                let top = thread
+                    .top_function
+                    .as_ref()
+                    .map_or(String::new(), |f| format!(", mostly in {}", self.code(f)));
+                self.item(&format!(
+                    "tid {}{comm}: {}{top}",
+                    thread.tid,
+                    percent(thread.share)
+                ))?;
+            }
+            writeln!(self.out)?;
+        }
+
+        if let Some(allocation) = &event.allocation {
+            self.allocation(event, allocation)?;
+        }
+        Ok(())
+    }
+
+    fn allocation(&mut self, event: &EventReport, allocation: &AllocationReport) -> io::Result<()> {
+        self.heading(3, "Allocations")?;
+        let totals: Vec<String> = allocation
+            .totals
+            .iter()
+            .map(|w| format!("{} {}", w.value, w.metric))
+            .collect();
+        self.item(&format!("Totals: {}", totals.join(", ")))?;
+        self.item(&format!(
+            "Frees tracked: {}",
+            if allocation.tracks_frees { "yes" } else { "no" }
+        ))?;
+        writeln!(self.out)?;
+
+        if allocation.sites.is_empty() {
+            return Ok(());
+        }
+        let mut header = vec!["#", "Site", event.metric.as_str()];
+        if let Some(count) = &allocation.count_metric {
+            header.extend([count.as_str(), "Average"]);
+        }
+        header.push("Location");
+        let rows: Vec<Vec<String>> = allocation
+            .sites
+            .iter()
+            .enumerate()
+            .map(|(i, site)| {
+                let mut row = vec![
+                    (i + 1).to_string(),
+                    self.code(&site.function),
+                    site.weight.to_string(),
+                ];
+                if let Some(count) = site.count {
+                    row.push(count.to_string());
+                    row.push(
+                        site.weight
+                            .checked_div(count)
+                            .map_or_else(|| "-".to_string(), |avg| avg.to_string()),
+                    );
+                }
+                row.push(site.srcline.clone().unwrap_or_default());
+                row
+            })
+            .collect();
+        self.table(&header, &rows)?;
+
+        if allocation.leaks.is_empty() {
+            return Ok(());
+        }
+        self.heading(3, "Likely leaks")?;
+        let rows: Vec<Vec<String>> = allocation
+            .leaks
+            .iter()
+            .enumerate()
+            .map(|(i, leak)| {
+                vec![
+                    (i + 1).to_string(),
+                    self.code(&leak.name),
+                    format!("{:.2}", leak.score),
+                    leak.confidence.to_string(),
+                    leak.bytes.to_string(),
+                    self.path(&leak.path),
+                ]
+            })
+            .collect();
+        self.table(
+            &[
+                "#",
+                "Site",
+                "Score",
+                "Confidence",
+                event.metric.as_str(),
+                "Path",
+            ],
+            &rows,
+        )
+    }
+
+    /// A call path, outermost caller first, with long paths elided in the
+    /// middle.
+    fn path(&self, functions: &[String]) -> String {
+        let mut parts: Vec<String> = functions.iter().map(|f| self.code(f)).collect();
+        if parts.len() > MAX_PATH_FRAMES {
+            let tail = parts.split_off(parts.len() - (MAX_PATH_FRAMES - 2));
+            parts.truncate(2);
+            parts.push("...".to_string());
+            parts.extend(tail);
+        }
+        parts.join(" -> ")
+    }
+
+    fn heading(&mut self, level: usize, text: &str) -> io::Result<()> {
+        match self.format {
+            ReportFormat::Markdown => writeln!(self.out, "{} {text}\n", "#".repeat(level)),
+            ReportFormat::Text => {
+                let underline = match level {
+                    1 => '=',
+                    2 => '-',
+                    _ => '~',
+                };
+                let rule: String = std::iter::repeat_n(underline, text.chars().count()).collect();
+                writeln!(self.out, "{text}\n{rule}\n")
+            }
+        }
+    }
+
+    fn item(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.out, "- {text}")
+    }
+
+    /// Inline code in Markdown, plain text otherwise.
+    fn code(&self, text: &str) -> String {
+        match self.format {
+            ReportFormat::Markdown => format!("`{}`", text.replace('`', "'")),
+            ReportFormat::Text => text.to_string(),
+        }
+    }
+
+    /// Text that marks code with backticks, stripped of them outside
+    /// Markdown.
+    fn prose(&self, text: &str) -> String {
+        match self.format {
+            ReportFormat::Markdown => text.to_string(),
+            ReportFormat::Text => text.replace('`', ""),
+        }
+    }
+
+    fn table(&mut self, header: &[&str], rows: &[Vec<String>]) -> io::Result<()> {
+        match self.format {
+            ReportFormat::Markdown => {
+                let cell = |s: &str| s.replace('|', "\\|");
+                writeln!(self.out, "| {} |", header.join(" | "))?;
+                writeln!(self.out, "|{}", "---|".repeat(header.len()))?;
+                for row in rows {
+                    let cells: Vec<String> = row.iter().map(|c| cell(c)).collect();
+                    writeln!(self.out, "| {} |", cells.join(" | "))?;
+                }
+            }
+            ReportFormat::Text => {
+                let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+                for row in rows {
+                    for (width, value) in widths.iter_mut().zip(row) {
+                        *width = (*width).max(value.chars().count());
+                    }
+                }
+                let line = |cells: Vec<&str>| {
+                    cells
+                        .iter()
+                        .zip(&widths)
+                        .map(|(c, &w)| format!("{c:<w$}"))
+                        .collect::<Vec<_>>()
+                        .join("  ")
+                        .trim_end()
+                        .to_string()
+                };
+                writeln!(self.out, "{}", line(header.to_vec()))?;
+                for row in rows {
+                    writeln!(
+                        self.out,
+                        "{}",
+                        line(row.iter().map(String::as_str).collect())
+                    )?;
+                }
+            }
+        }
+        writeln!(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn cpu_profile() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"},"description":"CPU cycles","unit":"cycles"}],"source":{"tool":"perf","data_loss":{"lost_events":12,"lost_records":1}}}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1,"srcline":"src/parse.rs:42"}"#,
+            r#"{"type":"frame","id":3,"func":"write","dso":1}"#,
+            r#"{"type":"thread","pid":10,"tid":10,"comm":"app"}"#,
+            r#"{"type":"thread","pid":10,"tid":11,"comm":"worker"}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles","tid":10},"weights":[{"metric":"period","value":70}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[3,1],"context":{"event":"cycles","tid":11},"weights":[{"metric":"period","value":20}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[1],"context":{"event":"cycles","tid":10},"weights":[{"metric":"period","value":10}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    fn heap_profile() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"chrome","frame_order":"leaf_to_root","events":[{"name":"allocation","kind":"allocation","sampling":{"mode":"event","primary_metric":"alloc_bytes"},"allocation_tracking":{"tracks_frees":false}}]}"#,
+            r#"{"type":"dso","id":1,"name":"app.js","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"allocateBuffer","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"allocation"},"weights":[{"metric":"alloc_bytes","value":4096},{"metric":"alloc_count","value":4}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    fn render(report: &Report, format: ReportFormat) -> String {
+        let mut out = Vec::new();
+        report.write(&mut out, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn format_parses_names_and_aliases() {
+        assert_eq!("markdown".parse(), Ok(ReportFormat::Markdown));
+        assert_eq!("txt".parse(), Ok(ReportFormat::Text));
+        assert!("html".parse::<ReportFormat>().is_err());
+    }
+
+    #[test]
+    fn hotspots_rank_by_self_weight_with_srclines() {
+        let report = build_report(&cpu_profile(), &ReportOptions::default());
+        let hotspots = &report.events[0].hotspots;
+
+        assert_eq!(hotspots[0].function, "parse");
+        assert_eq!(hotspots[0].srcline.as_deref(), Some("src/parse.rs:42"));
+        assert_eq!(hotspots[0].self_share, 0.7);
+        let main = hotspots.iter().find(|h| h.function == "main").unwrap();
+        assert_eq!((main.self_weight, main.total_weight), (10, 100));
+    }
+
+    #[test]
+    fn threads_note_their_top_function() {
+        let report = build_report(&cpu_profile(), &ReportOptions::default());
+        let thread = &report.events[0].threads[0];
+
+        assert_eq!(thread.tid, 10);
+        assert_eq!(thread.comm.as_deref(), Some("app"));
+        assert_eq!(thread.top_function.as_deref(), Some("parse"));
+    }
+
+    #[test]
+    fn next_steps_flag_data_loss_and_dominant_function() {
+        let report = build_report(&cpu_profile(), &ReportOptions::default());
+
+        assert!(report.next_steps[0].contains("lost 12 events"));
+        assert!(
+            report
+                .next_steps
+                .iter()
+                .any(|s| s.contains("`parse` (src/parse.rs:42)"))
+        );
+    }
+
+    #[test]
+    fn allocation_windows_add_likely_leaks() {
+        let mut file = heap_profile();
+        file.windows = (0..3)
+            .map(|i| spaa_parse::Window {
+                id: format!("w{}", i + 1),
+                start: i as f64,
+                end: i as f64 + 1.0,
+                unit: "seconds".to_string(),
+                by_stack: vec![spaa_parse::WindowStackWeight {
+                    stack_id: "0x1".to_string(),
+                    weights: vec![Weight {
+                        metric: "alloc_bytes".to_string(),
+                        value: 1024,
+                        unit: None,
+                    }],
+                }],
+            })
+            .collect();
+        let report = build_report(&file, &ReportOptions::default());
+
+        let allocation = report.events[0].allocation.as_ref().unwrap();
+        assert_eq!(allocation.leaks[0].name, "allocateBuffer");
+        assert!(render(&report, ReportFormat::Markdown).contains(
+            "| 1 | `allocateBuffer` | 1.00 | high | 3072 | `main` -> `allocateBuffer` |"
+        ));
+    }
+
+    #[test]
+    fn allocation_events_get_an_allocation_section() {
+        let report = build_report(&heap_profile(), &ReportOptions::default());
+        let allocation = report.events[0].allocation.as_ref().unwrap();
+
+        assert_eq!(allocation.count_metric.as_deref(), Some("alloc_count"));
+        assert_eq!(allocation.sites[0].function, "allocateBuffer");
+        assert_eq!(allocation.sites[0].count, Some(4));
+        assert!(
+            render(&report, ReportFormat::Markdown)
+                .contains("| 1 | `allocateBuffer` | 4096 | 4 | 1024 |")
+        );
+    }
+
+    #[test]
+    fn markdown_has_sections_and_tables() {
+        let report = build_report(&cpu_profile(), &ReportOptions::default());
+        let markdown = render(&report, ReportFormat::Markdown);
+
+        assert!(markdown.starts_with("# Profile report: perf\n"));
+        assert!(markdown.contains("CPU cycles\n"));
+        assert!(markdown.contains("| 1 | `parse` | 70.0% | 70.0% | src/parse.rs:42 |"));
+        assert!(markdown.contains("## Suggested next steps"));
+    }
+
+    #[test]
+    fn text_report_has_no_markdown_syntax() {
+        let report = build_report(&cpu_profile(), &ReportOptions::default());
+        let text = render(&report, ReportFormat::Text);
+
+        assert!(text.starts_with("Profile report: perf\n===================="));
+        assert!(!text.contains('`'));
+        assert!(!text.contains('|'));
+    }
+}
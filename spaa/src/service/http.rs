@@ -0,0 +1,139 @@
+//! REST front end for [`Service`] (requires the `http` feature).
+//!
+//! | Method | Path | Body | Response |
+//! |--------|------|------|----------|
+//! | `POST` | `/v1/profiles` | SPAA NDJSON | [`Uploaded`] |
+//! | `POST` | `/v1/convert?format=perf` | profiler output | SPAA NDJSON |
+//! | `POST` | `/v1/validate` | SPAA NDJSON | [`Validation`] |
+//! | `GET` | `/v1/profiles/{id}/summary?format=markdown` | | Markdown or text report |
+//! | `GET` | `/v1/profiles/{id}/query?event=&metric=&top=&rank=` | | [`QueryResult`] |
+//!
+//! Errors are returned as plain text with a 400 or 404 status. Parsing and
+//! conversion run on tokio's blocking thread pool.
+
+use super::{Query, QueryResult, Service, ServiceError, SourceFormat, Uploaded, Validation};
+use crate::report::ReportFormat;
+use axum::Json;
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Path, Query as QueryParams, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// Largest request body accepted, in bytes.
+pub const MAX_BODY_BYTES: usize = 512 * 1024 * 1024;
+
+impl IntoResponse for ServiceError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ServiceError::NotFound(_) => StatusCode::NOT_FOUND,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// Routes for `service`.
+pub fn router(service: Arc<Service>) -> Router {
+    Router::new()
+        .route("/v1/profiles", post(upload))
+        .route("/v1/convert", post(convert))
+        .route("/v1/validate", post(validate))
+        .route("/v1/profiles/{id}/summary", get(summarize))
+        .route("/v1/profiles/{id}/query", get(query))
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+        .with_state(service)
+}
+
+/// Serve [`router`] on `listener` until the process exits.
+pub async fn serve(listener: TcpListener, service: Arc<Service>) -> std::io::Result<()> {
+    axum::serve(listener, router(service)).await
+}
+
+/// Run `f` on the blocking pool; a panic there becomes a 500.
+async fn blocking<T, F>(f: F) -> Result<T, Response>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())
+}
+
+async fn upload(State(service): State<Arc<Service>>, body: Bytes) -> Response {
+    match blocking(move || service.upload(&body)).await {
+        Ok(Ok(uploaded)) => (StatusCode::CREATED, Json::<Uploaded>(uploaded)).into_response(),
+        Ok(Err(e)) => e.into_response(),
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize)]
+struct ConvertParams {
+    format: String,
+}
+
+async fn convert(
+    State(service): State<Arc<Service>>,
+    QueryParams(params): QueryParams<ConvertParams>,
+    body: Bytes,
+) -> Response {
+    let format: SourceFormat = match params.format.parse() {
+        Ok(format) => format,
+        Err(e) => return ServiceError::BadRequest(format!("{}", e)).into_response(),
+    };
+    match blocking(move || service.convert(format, &body)).await {
+        Ok(Ok(spaa)) => ([(header::CONTENT_TYPE, "application/x-ndjson")], spaa).into_response(),
+        Ok(Err(e)) => e.into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn validate(State(service): State<Arc<Service>>, body: Bytes) -> Response {
+    match blocking(move || service.validate(&body)).await {
+        Ok(validation) => Json::<Validation>(validation).into_response(),
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize)]
+struct SummaryParams {
+    format: Option<String>,
+}
+
+async fn summarize(
+    State(service): State<Arc<Service>>,
+    Path(id): Path<String>,
+    QueryParams(params): QueryParams<SummaryParams>,
+) -> Response {
+    let format: ReportFormat = match params.format.as_deref().map(str::parse).transpose() {
+        Ok(format) => format.unwrap_or_default(),
+        Err(e) => return ServiceError::BadRequest(format!("{}", e)).into_response(),
+    };
+    let content_type = match format {
+        ReportFormat::Markdown => "text/markdown; charset=utf-8",
+        ReportFormat::Text => "text/plain; charset=utf-8",
+    };
+    match blocking(move || service.summarize(&id, format)).await {
+        Ok(Ok(report)) => ([(header::CONTENT_TYPE, content_type)], report).into_response(),
+        Ok(Err(e)) => e.into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn query(
+    State(service): State<Arc<Service>>,
+    Path(id): Path<String>,
+    QueryParams(query): QueryParams<Query>,
+) -> Response {
+    match blocking(move || service.query(&id, &query)).await {
+        Ok(Ok(result)) => Json::<QueryResult>(result).into_response(),
+        Ok(Err(e)) => e.into_response(),
+        Err(response) => response,
+    }
+}
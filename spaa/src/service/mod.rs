@@ -0,0 +1,324 @@
+//! Engine for an internal profile analysis service.
+//!
+//! [`Service`] implements the operations a profile API needs: upload,
+//! convert, validate, summarize and query. It works on byte buffers and
+//! returns serializable types, so any transport can sit in front of it.
+//! With the `http` feature, [`http`] provides a ready-made REST front end
+//! built on axum.
+//!
+//! Uploaded profiles are kept in memory, keyed by a digest of their
+//! contents, so uploading the same file twice returns the same ID.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::service::{Query, Service};
+//!
+//! let service = Service::new();
+//! let data = std::fs::read("profile.spaa").unwrap();
+//! let uploaded = service.upload(&data).unwrap();
+//! let result = service.query(&uploaded.id, &Query::default()).unwrap();
+//! for f in result.functions {
+//!     println!("{:.1}% {}", f.self_share * 100.0, f.function);
+//! }
+//! ```
+
+#[cfg(feature = "http")]
+pub mod http;
+
+pub use crate::convert::SourceFormat;
+
+use crate::analysis::{FunctionWeight, RankBy, stack_weight, top_functions};
+use crate::convert::convert;
+use crate::report::{ReportFormat, ReportOptions, build_report};
+use serde::{Deserialize, Serialize};
+use spaa_parse::{ParseError, SpaaFile, SpaaSnapshot};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use thiserror::Error;
+
+/// Errors returned by [`Service`] operations.
+#[derive(Error, Debug)]
+pub enum ServiceError {
+    #[error("no profile with id '{0}'")]
+    NotFound(String),
+
+    #[error("invalid SPAA profile: {0}")]
+    InvalidProfile(#[from] ParseError),
+
+    #[error("conversion failed: {0}")]
+    Convert(String),
+
+    #[error("bad request: {0}")]
+    BadRequest(String),
+}
+
+pub type Result<T> = std::result::Result<T, ServiceError>;
+
+/// Weight and stack count for one event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EventSummary {
+    pub name: String,
+    pub metric: String,
+    pub total: u64,
+    pub stacks: usize,
+}
+
+/// Shape of a profile, as returned by upload and validate.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProfileSummary {
+    pub source_tool: String,
+    pub events: Vec<EventSummary>,
+    pub frames: usize,
+    pub stacks: usize,
+}
+
+impl ProfileSummary {
+    /// Summarize `file`.
+    pub fn of(file: &SpaaFile) -> Self {
+        let events = file
+            .header
+            .events
+            .iter()
+            .map(|event| {
+                let metric = &event.sampling.primary_metric;
+                let (mut total, mut stacks) = (0, 0);
+                for stack in file.stacks_for_event(&event.name) {
+                    total += stack_weight(stack, metric);
+                    stacks += 1;
+                }
+                EventSummary {
+                    name: event.name.clone(),
+                    metric: metric.clone(),
+                    total,
+                    stacks,
+                }
+            })
+            .collect();
+        Self {
+            source_tool: file.header.source_tool.clone(),
+            events,
+            frames: file.frames.len(),
+            stacks: file.stacks.len(),
+        }
+    }
+}
+
+/// Result of [`Service::upload`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Uploaded {
+    /// Content-derived ID to pass to later calls.
+    pub id: String,
+    pub profile: ProfileSummary,
+}
+
+/// Result of [`Service::validate`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Validation {
+    pub valid: bool,
+    /// Why the profile was rejected.
+    pub error: Option<String>,
+    /// Present when the profile is valid.
+    pub profile: Option<ProfileSummary>,
+}
+
+/// Parameters for [`Service::query`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Query {
+    /// Event to rank functions for; defaults to the first header event.
+    pub event: Option<String>,
+    /// Metric to weight by; defaults to the event's primary metric.
+    pub metric: Option<String>,
+    /// Number of functions to return.
+    pub top: usize,
+    pub rank: RankBy,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Self {
+            event: None,
+            metric: None,
+            top: 10,
+            rank: RankBy::SelfWeight,
+        }
+    }
+}
+
+/// Result of [`Service::query`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueryResult {
+    pub event: String,
+    pub metric: String,
+    pub functions: Vec<FunctionWeight>,
+}
+
+/// Profile service state: uploaded profiles by ID.
+///
+/// All methods take `&self`, so one `Service` can be shared between
+/// request handlers behind an [`Arc`](std::sync::Arc). Profiles are kept as
+/// [`SpaaSnapshot`]s, so queries hold the map's lock only long enough to
+/// clone one and run concurrently after that.
+#[derive(Debug, Default)]
+pub struct Service {
+    profiles: RwLock<HashMap<String, SpaaSnapshot>>,
+}
+
+impl Service {
+    /// A service with no profiles uploaded.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse and keep a SPAA profile.
+    pub fn upload(&self, data: &[u8]) -> Result<Uploaded> {
+        let file = SpaaFile::parse_slice(data)?;
+        let id = format!("{:016x}", fnv1a(data));
+        let profile = ProfileSummary::of(&file);
+        self.profiles
+            .write()
+            .expect("profile map poisoned")
+            .insert(id.clone(), SpaaSnapshot::new(file));
+        Ok(Uploaded { id, profile })
+    }
+
+    /// An uploaded profile.
+    pub fn profile(&self, id: &str) -> Result<SpaaSnapshot> {
+        self.profiles
+            .read()
+            .expect("profile map poisoned")
+            .get(id)
+            .cloned()
+            .ok_or_else(|| ServiceError::NotFound(id.to_string()))
+    }
+
+    /// Convert profiler output to SPAA NDJSON.
+    pub fn convert(&self, format: SourceFormat, data: &[u8]) -> Result<Vec<u8>> {
+        convert(format, data).map_err(|e| ServiceError::Convert(e.message))
+    }
+
+    /// Check that `data` is a well-formed SPAA profile without keeping it.
+    pub fn validate(&self, data: &[u8]) -> Validation {
+        match SpaaFile::parse_slice(data) {
+            Ok(file) => Validation {
+                valid: true,
+                error: None,
+                profile: Some(ProfileSummary::of(&file)),
+            },
+            Err(e) => Validation {
+                valid: false,
+                error: Some(e.to_string()),
+                profile: None,
+            },
+        }
+    }
+
+    /// Narrative report of an uploaded profile.
+    pub fn summarize(&self, id: &str, format: ReportFormat) -> Result<String> {
+        let file = self.profile(id)?;
+        let mut out = Vec::new();
+        build_report(&file, &ReportOptions::default())
+            .write(&mut out, format)
+            .expect("writing to a Vec cannot fail");
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    /// Top functions of an uploaded profile.
+    pub fn query(&self, id: &str, query: &Query) -> Result<QueryResult> {
+        let file = self.profile(id)?;
+        let event = match &query.event {
+            Some(name) => file.header.events.iter().find(|e| &e.name == name),
+            None => file.header.events.first(),
+        }
+        .ok_or_else(|| {
+            ServiceError::BadRequest(format!(
+                "no event '{}'",
+                query.event.as_deref().unwrap_or_default()
+            ))
+        })?;
+        let metric = query
+            .metric
+            .clone()
+            .unwrap_or_else(|| event.sampling.primary_metric.clone());
+        Ok(QueryResult {
+            functions: top_functions(&file, &event.name, &metric, query.top, query.rank),
+            event: event.name.clone(),
+            metric,
+        })
+    }
+}
+
+/// 64-bit FNV-1a, used for content-derived profile IDs.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = concat!(
+        r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+        "\n",
+        r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+        "\n",
+        r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+        "\n",
+        r#"{"type":"frame","id":2,"func":"parse","dso":1}"#,
+        "\n",
+        r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":70}]}"#,
+        "\n",
+        r#"{"type":"stack","id":"0x2","frames":[1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":30}]}"#,
+        "\n",
+    );
+
+    #[test]
+    fn upload_is_keyed_by_content() {
+        let service = Service::new();
+        let first = service.upload(PROFILE.as_bytes()).unwrap();
+        let second = service.upload(PROFILE.as_bytes()).unwrap();
+
+        assert_eq!(first.id, second.id);
+        assert_eq!(first.profile.events[0].total, 100);
+    }
+
+    #[test]
+    fn query_ranks_uploaded_profile() {
+        let service = Service::new();
+        let id = service.upload(PROFILE.as_bytes()).unwrap().id;
+        let result = service.query(&id, &Query::default()).unwrap();
+
+        assert_eq!(result.event, "cycles");
+        assert_eq!(result.functions[0].function, "parse");
+    }
+
+    #[test]
+    fn unknown_id_is_not_found() {
+        let err = Service::new()
+            .summarize("missing", ReportFormat::Text)
+            .unwrap_err();
+        assert!(matches!(err, ServiceError::NotFound(_)));
+    }
+
+    #[test]
+    fn validate_reports_parse_errors() {
+        let validation = Service::new().validate(b"{\"type\":\"stack\"}\n");
+
+        assert!(!validation.valid);
+        assert!(validation.error.is_some());
+    }
+
+    #[test]
+    fn convert_perf_output_produces_valid_spaa() {
+        let input = "app  1234 [000] 12345.678901:     100000 cycles:\n\t401234 main+0x54 (/usr/bin/app)\n\n";
+        let service = Service::new();
+        let spaa = service
+            .convert(SourceFormat::Perf, input.as_bytes())
+            .unwrap();
+
+        assert!(service.validate(&spaa).valid);
+    }
+}
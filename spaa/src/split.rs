@@ -0,0 +1,295 @@
+//! Split a SPAA file into smaller, self-contained files.
+//!
+//! Each part is a valid SPAA file on its own: DSO, frame and thread
+//! dictionaries are pruned to what the part's stacks reference, and header
+//! events are limited to those the part uses.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::split::{SplitBy, split};
+//! use spaa_parse::SpaaFile;
+//! use std::fs::File;
+//!
+//! let spaa = SpaaFile::parse(File::open("profile.spaa").unwrap()).unwrap();
+//! for part in split(&spaa, SplitBy::Event) {
+//!     let path = format!("profile.{}.spaa", part.key);
+//!     part.file.write(File::create(path).unwrap()).unwrap();
+//! }
+//! ```
+
+use spaa_parse::{SpaaFile, TimeRange};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// Dimension to split a file along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitBy {
+    /// One part per event.
+    Event,
+    /// One part per process. Stacks without a pid go to an `unknown` part.
+    Pid,
+    /// One part per time window record. Stack weights are replaced by the
+    /// window's weights, so each part describes only that slice of time.
+    Window,
+}
+
+impl fmt::Display for SplitBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SplitBy::Event => "event",
+            SplitBy::Pid => "pid",
+            SplitBy::Window => "window",
+        })
+    }
+}
+
+/// Error returned when parsing an unknown [`SplitBy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSplitByError(String);
+
+impl fmt::Display for ParseSplitByError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown split dimension '{}' (expected event, pid or window)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseSplitByError {}
+
+impl FromStr for SplitBy {
+    type Err = ParseSplitByError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "event" => Ok(SplitBy::Event),
+            "pid" => Ok(SplitBy::Pid),
+            "window" => Ok(SplitBy::Window),
+            _ => Err(ParseSplitByError(s.to_string())),
+        }
+    }
+}
+
+/// One output of [`split`].
+#[derive(Debug, Clone)]
+pub struct SplitPart {
+    /// Event name, pid (or `unknown`) or window ID.
+    pub key: String,
+    pub file: SpaaFile,
+}
+
+/// Split `file` along `by`. Parts are ordered by key.
+pub fn split(file: &SpaaFile, by: SplitBy) -> Vec<SplitPart> {
+    match by {
+        SplitBy::Event => split_by_event(file),
+        SplitBy::Pid => split_by_pid(file),
+        SplitBy::Window => split_by_window(file),
+    }
+}
+
+fn split_by_event(file: &SpaaFile) -> Vec<SplitPart> {
+    let mut groups: BTreeMap<String, HashSet<&str>> = BTreeMap::new();
+    for stack in file.stacks.values() {
+        groups
+            .entry(stack.context.event.clone())
+            .or_default()
+            .insert(&stack.id);
+    }
+
+    groups
+        .into_iter()
+        .map(|(event, ids)| {
+            let mut part = file.filter(|s| ids.contains(s.id.as_str()));
+            part.samples.retain(|s| s.event == event);
+            SplitPart {
+                key: event,
+                file: part,
+            }
+        })
+        .collect()
+}
+
+fn split_by_pid(file: &SpaaFile) -> Vec<SplitPart> {
+    let mut groups: BTreeMap<Option<u64>, HashSet<&str>> = BTreeMap::new();
+    for stack in file.stacks.values() {
+        groups
+            .entry(stack.context.pid)
+            .or_default()
+            .insert(&stack.id);
+    }
+
+    groups
+        .into_iter()
+        .map(|(pid, ids)| {
+            let mut part = file.filter(|s| ids.contains(s.id.as_str()));
+            if let Some(pid) = pid {
+                part.threads.retain(|_, t| t.pid == pid);
+                part.samples.retain(|s| s.pid == pid);
+                part.states = file
+                    .states
+                    .iter()
+                    .filter(|s| {
+                        s.pid == Some(pid)
+                            || s.stack_id
+                                .as_ref()
+                                .is_some_and(|id| ids.contains(id.as_str()))
+                    })
+                    .cloned()
+                    .collect();
+            }
+            SplitPart {
+                key: pid.map_or_else(|| "unknown".to_string(), |p| p.to_string()),
+                file: part,
+            }
+        })
+        .collect()
+}
+
+fn split_by_window(file: &SpaaFile) -> Vec<SplitPart> {
+    let mut windows: Vec<_> = file.windows.iter().collect();
+    windows.sort_by(|a, b| a.start.total_cmp(&b.start).then(a.id.cmp(&b.id)));
+
+    windows
+        .into_iter()
+        .map(|window| {
+            let ids: HashSet<&str> = window
+                .by_stack
+                .iter()
+                .map(|w| w.stack_id.as_str())
+                .collect();
+            let mut part = file.filter(|s| ids.contains(s.id.as_str()));
+            for entry in &window.by_stack {
+                if let Some(stack) = part.stacks.get_mut(&entry.stack_id) {
+                    stack.weights = entry.weights.clone();
+                    stack.exclusive = None;
+                }
+            }
+            part.samples
+                .retain(|s| s.timestamp >= window.start && s.timestamp < window.end);
+            part.states = file
+                .states
+                .iter()
+                .filter(|s| s.start < window.end && s.end > window.start)
+                .cloned()
+                .map(|mut s| {
+                    if s.stack_id
+                        .as_ref()
+                        .is_some_and(|id| !ids.contains(id.as_str()))
+                    {
+                        s.stack_id = None;
+                    }
+                    s
+                })
+                .collect();
+            part.windows = vec![window.clone()];
+            part.header.time_range = Some(TimeRange {
+                start: window.start,
+                end: window.end,
+                unit: window.unit.clone(),
+            });
+            SplitPart {
+                key: window.id.clone(),
+                file: part,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}},{"name":"cache-misses","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"dso","id":2,"name":"/usr/lib/libc.so.6","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"memcpy","dso":2}"#,
+            r#"{"type":"frame","id":3,"func":"worker","dso":1}"#,
+            r#"{"type":"thread","pid":10,"tid":10,"comm":"app"}"#,
+            r#"{"type":"thread","pid":20,"tid":21,"comm":"worker"}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles","pid":10,"tid":10},"weights":[{"metric":"period","value":100}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[3],"context":{"event":"cycles","pid":20,"tid":21},"weights":[{"metric":"period","value":50}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[2,1],"context":{"event":"cache-misses","pid":10,"tid":10},"weights":[{"metric":"period","value":7}]}"#,
+            r#"{"type":"sample","timestamp":1.5,"pid":10,"tid":10,"cpu":0,"event":"cycles","stack_id":"0x1"}"#,
+            r#"{"type":"sample","timestamp":2.5,"pid":20,"tid":21,"cpu":1,"event":"cycles","stack_id":"0x2"}"#,
+            r#"{"type":"window","id":"w1","start":1.0,"end":2.0,"unit":"seconds","by_stack":[{"stack_id":"0x1","weights":[{"metric":"period","value":60}]}]}"#,
+            r#"{"type":"window","id":"w2","start":2.0,"end":3.0,"unit":"seconds","by_stack":[{"stack_id":"0x1","weights":[{"metric":"period","value":40}]},{"stack_id":"0x2","weights":[{"metric":"period","value":50}]}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    fn round_trip(file: &SpaaFile) -> SpaaFile {
+        let mut buf = Vec::new();
+        file.write(&mut buf).unwrap();
+        SpaaFile::parse(Cursor::new(buf)).unwrap()
+    }
+
+    #[test]
+    fn split_by_event_prunes_dictionaries() {
+        let parts = split(&sample_file(), SplitBy::Event);
+        let keys: Vec<_> = parts.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, vec!["cache-misses", "cycles"]);
+
+        let misses = round_trip(&parts[0].file);
+        assert_eq!(misses.header.events.len(), 1);
+        assert_eq!(misses.stacks.len(), 1);
+        assert_eq!(misses.frames.len(), 2);
+        assert!(misses.frames.values().all(|f| f.func != "worker"));
+        assert!(misses.samples.is_empty());
+        assert!(misses.windows.is_empty());
+    }
+
+    #[test]
+    fn split_keeps_context_extensions() {
+        let mut file = sample_file();
+        let context = &mut file.stacks.get_mut("0x3").unwrap().context;
+        context.extra.insert("numa_node".to_string(), 1.into());
+
+        let parts = split(&file, SplitBy::Event);
+        let misses = round_trip(&parts[0].file);
+        assert_eq!(misses.stacks["0x3"].get_extra::<u32>("numa_node"), Some(1));
+    }
+
+    #[test]
+    fn split_by_pid_keeps_process_records() {
+        let parts = split(&sample_file(), SplitBy::Pid);
+        let keys: Vec<_> = parts.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, vec!["10", "20"]);
+
+        let worker = round_trip(&parts[1].file);
+        assert_eq!(worker.stacks.len(), 1);
+        assert_eq!(worker.dsos.len(), 1);
+        assert_eq!(worker.threads.len(), 1);
+        assert_eq!(worker.samples.len(), 1);
+        assert_eq!(worker.header.events.len(), 1);
+        assert_eq!(worker.windows.len(), 1);
+        assert_eq!(worker.windows[0].by_stack.len(), 1);
+    }
+
+    #[test]
+    fn split_by_window_uses_window_weights() {
+        let parts = split(&sample_file(), SplitBy::Window);
+        let keys: Vec<_> = parts.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, vec!["w1", "w2"]);
+
+        let first = round_trip(&parts[0].file);
+        assert_eq!(first.stacks.len(), 1);
+        assert_eq!(first.stacks["0x1"].weights[0].value, 60);
+        assert_eq!(first.samples.len(), 1);
+        assert_eq!(first.header.time_range.as_ref().unwrap().end, 2.0);
+    }
+
+    #[test]
+    fn parse_split_by() {
+        assert_eq!("pid".parse::<SplitBy>(), Ok(SplitBy::Pid));
+        assert!("tid".parse::<SplitBy>().is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! Build metadata stamps.
+//!
+//! Regression tracking needs to know which code a profile measured. A
+//! stamp is a set of key/value pairs, such as `git_sha` and
+//! `service_version`, stored in the header's `source.build` map. CI jobs
+//! can fill the common keys from the environment with [`from_env`].
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::stamp::{from_env, stamp};
+//! use spaa_parse::SpaaFile;
+//! use std::fs::File;
+//!
+//! let mut spaa = SpaaFile::parse(File::open("profile.spaa").unwrap()).unwrap();
+//! let mut metadata = from_env();
+//! metadata.insert("service_version".to_string(), "1.4.2".to_string());
+//! stamp(&mut spaa, metadata);
+//! ```
+
+use spaa_parse::{SourceInfo, SpaaFile};
+use std::collections::BTreeMap;
+
+/// Stamp keys and the CI environment variables they are read from, in
+/// order of preference: GitHub Actions, GitLab CI, Buildkite, CircleCI and
+/// Jenkins.
+pub const CI_VARIABLES: &[(&str, &[&str])] = &[
+    (
+        "git_sha",
+        &[
+            "GITHUB_SHA",
+            "CI_COMMIT_SHA",
+            "BUILDKITE_COMMIT",
+            "CIRCLE_SHA1",
+            "GIT_COMMIT",
+        ],
+    ),
+    (
+        "git_branch",
+        &[
+            "GITHUB_REF_NAME",
+            "CI_COMMIT_REF_NAME",
+            "BUILDKITE_BRANCH",
+            "CIRCLE_BRANCH",
+            "GIT_BRANCH",
+        ],
+    ),
+    (
+        "ci_job",
+        &[
+            "GITHUB_JOB",
+            "CI_JOB_NAME",
+            "BUILDKITE_LABEL",
+            "CIRCLE_JOB",
+            "JOB_NAME",
+        ],
+    ),
+    (
+        "ci_run",
+        &[
+            "GITHUB_RUN_ID",
+            "CI_PIPELINE_ID",
+            "BUILDKITE_BUILD_NUMBER",
+            "CIRCLE_BUILD_NUM",
+            "BUILD_NUMBER",
+        ],
+    ),
+];
+
+/// Add `metadata` to the build map in `file`'s header, replacing existing
+/// values for the same keys.
+///
+/// A header without source information gets one naming its `source_tool`.
+pub fn stamp(file: &mut SpaaFile, metadata: impl IntoIterator<Item = (String, String)>) {
+    let header = &mut file.header;
+    let source = header.source.get_or_insert_with(|| SourceInfo {
+        tool: header.source_tool.clone(),
+        command: None,
+        tool_version: None,
+        data_loss: None,
+        build: BTreeMap::new(),
+    });
+    source.build.extend(metadata);
+}
+
+/// Build metadata from the CI environment variables in [`CI_VARIABLES`].
+///
+/// Keys whose variables are all unset or empty are left out, so outside CI
+/// the result is empty.
+pub fn from_env() -> BTreeMap<String, String> {
+    from_vars(|name| std::env::var(name).ok())
+}
+
+fn from_vars(var: impl Fn(&str) -> Option<String>) -> BTreeMap<String, String> {
+    CI_VARIABLES
+        .iter()
+        .filter_map(|(key, names)| {
+            let value = names
+                .iter()
+                .find_map(|name| var(name).filter(|v| !v.is_empty()))?;
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ci_variables_fill_the_first_matching_key() {
+        let metadata = from_vars(|name| match name {
+            "CI_COMMIT_SHA" => Some("abc123".to_string()),
+            "GIT_COMMIT" => Some("ignored".to_string()),
+            "GITHUB_JOB" => Some(String::new()),
+            "CI_JOB_NAME" => Some("bench".to_string()),
+            _ => None,
+        });
+
+        assert_eq!(metadata.get("git_sha").map(String::as_str), Some("abc123"));
+        assert_eq!(metadata.get("ci_job").map(String::as_str), Some("bench"));
+        assert!(!metadata.contains_key("git_branch"));
+    }
+
+    #[test]
+    fn stamping_a_header_without_source_names_the_tool() {
+        let mut spaa = crate::synth::ProfileGenerator::default().generate();
+        spaa.header.source = None;
+        stamp(&mut spaa, [("git_sha".to_string(), "abc123".to_string())]);
+
+        let source = spaa.header.source.unwrap();
+        assert_eq!(source.tool, spaa.header.source_tool);
+        assert_eq!(source.build["git_sha"], "abc123");
+    }
+}
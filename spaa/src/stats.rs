@@ -0,0 +1,368 @@
+//! Summary statistics for SPAA files.
+//!
+//! Statistics are gathered from a [`SpaaReader`] one record at a time, so
+//! memory use is bounded by the dictionaries rather than the file size.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::stats::compute_stats;
+//! use std::fs::File;
+//! use std::io::BufReader;
+//!
+//! let stats = compute_stats(BufReader::new(File::open("profile.spaa").unwrap())).unwrap();
+//! println!("{} stacks, {} unique symbols", stats.records.stacks, stats.unique_symbols);
+//! ```
+
+use serde::Serialize;
+use spaa_parse::{FrameOrder, Record, SpaaReader};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::BufRead;
+
+/// Number of records of each type.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RecordCounts {
+    pub dsos: u64,
+    pub frames: u64,
+    pub threads: u64,
+    pub stacks: u64,
+    pub samples: u64,
+    pub windows: u64,
+    pub states: u64,
+    pub notes: u64,
+}
+
+/// How often a DSO appears in stacks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DsoUsage {
+    pub name: String,
+    /// Frame dictionary entries belonging to the DSO.
+    pub frames: u64,
+    /// Stack frames (across all stacks) pointing into the DSO.
+    pub stack_references: u64,
+    /// Stacks whose leaf frame is in the DSO.
+    pub leaf_stacks: u64,
+}
+
+/// Stacks with a depth in `min..=max` (`max` is `None` for the last bucket).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DepthBucket {
+    pub min: usize,
+    pub max: Option<usize>,
+    pub stacks: u64,
+}
+
+/// Sum of one metric over the stacks of one event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MetricTotal {
+    pub event: String,
+    pub metric: String,
+    pub total: u64,
+    /// Whether this is the event's primary metric.
+    pub primary: bool,
+}
+
+/// How well the frame and DSO dictionaries are used by stacks.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DictionaryUsage {
+    /// Distinct frames referenced by at least one stack.
+    pub frames_referenced: u64,
+    /// Total frame references across all stacks.
+    pub frame_references: u64,
+    /// Fraction of frame dictionary entries referenced by some stack.
+    pub frame_hit_rate: f64,
+    /// Average number of stacks sharing each referenced frame; higher
+    /// means the dictionary saves more space.
+    pub frame_reuse: f64,
+    /// Fraction of DSO dictionary entries referenced by some frame.
+    pub dso_hit_rate: f64,
+}
+
+/// Summary of a SPAA file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProfileStats {
+    pub source_tool: String,
+    pub bytes: u64,
+    pub records: RecordCounts,
+    pub unique_symbols: u64,
+    /// DSOs ordered by descending stack references.
+    pub top_dsos: Vec<DsoUsage>,
+    pub max_depth: usize,
+    pub depth_histogram: Vec<DepthBucket>,
+    pub weights: Vec<MetricTotal>,
+    pub dictionary: DictionaryUsage,
+}
+
+/// Options for [`compute_stats_with_options`].
+#[derive(Debug, Clone)]
+pub struct StatsOptions {
+    /// Number of DSOs to report in [`ProfileStats::top_dsos`].
+    pub top_dsos: usize,
+}
+
+impl Default for StatsOptions {
+    fn default() -> Self {
+        Self { top_dsos: 10 }
+    }
+}
+
+/// Upper bounds of the depth histogram buckets; deeper stacks fall into a
+/// final open-ended bucket.
+const DEPTH_BUCKETS: [usize; 8] = [1, 2, 4, 8, 16, 32, 64, 128];
+
+/// Compute statistics using [`StatsOptions::default`].
+pub fn compute_stats<R: BufRead>(reader: R) -> spaa_parse::Result<ProfileStats> {
+    compute_stats_with_options(reader, &StatsOptions::default())
+}
+
+/// Stream `reader` and compute statistics.
+pub fn compute_stats_with_options<R: BufRead>(
+    reader: R,
+    options: &StatsOptions,
+) -> spaa_parse::Result<ProfileStats> {
+    let mut reader = SpaaReader::new(reader);
+    let mut collector = StatsCollector::new();
+    for record in reader.by_ref() {
+        collector.add(&record?);
+    }
+    Ok(collector.finish(reader.bytes_read(), options))
+}
+
+/// Incremental statistics over a stream of records.
+#[derive(Debug, Default)]
+pub struct StatsCollector {
+    source_tool: String,
+    root_first: bool,
+    primary_metrics: HashMap<String, String>,
+    records: RecordCounts,
+    symbols: HashSet<String>,
+    dso_names: HashMap<u64, String>,
+    frame_dso: HashMap<u64, u64>,
+    dso_frames: HashMap<u64, u64>,
+    dso_references: HashMap<u64, u64>,
+    dso_leaves: HashMap<u64, u64>,
+    frames_referenced: HashSet<u64>,
+    frame_references: u64,
+    max_depth: usize,
+    depths: [u64; DEPTH_BUCKETS.len() + 1],
+    weights: BTreeMap<(String, String), u64>,
+}
+
+impl StatsCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Account for one record. Records must arrive in file order, with
+    /// frames before the stacks that use them.
+    pub fn add(&mut self, record: &Record) {
+        match record {
+            Record::Header(header) => {
+                self.source_tool = header.source_tool.clone();
+                self.root_first = header.frame_order == FrameOrder::RootToLeaf;
+                self.primary_metrics = header
+                    .events
+                    .iter()
+                    .map(|e| (e.name.clone(), e.sampling.primary_metric.clone()))
+                    .collect();
+            }
+            Record::Dso(dso) => {
+                self.records.dsos += 1;
+                self.dso_names.insert(dso.id, dso.name.clone());
+            }
+            Record::Frame(frame) => {
+                self.records.frames += 1;
+                if !self.symbols.contains(&frame.func) {
+                    self.symbols.insert(frame.func.clone());
+                }
+                self.frame_dso.insert(frame.id, frame.dso);
+                *self.dso_frames.entry(frame.dso).or_default() += 1;
+            }
+            Record::Thread(_) => self.records.threads += 1,
+            Record::Stack(stack) => {
+                self.records.stacks += 1;
+                let depth = stack.frames.len();
+                self.max_depth = self.max_depth.max(depth);
+                let bucket = DEPTH_BUCKETS
+                    .iter()
+                    .position(|max| depth <= *max)
+                    .unwrap_or(DEPTH_BUCKETS.len());
+                self.depths[bucket] += 1;
+
+                for frame in &stack.frames {
+                    self.frame_references += 1;
+                    self.frames_referenced.insert(*frame);
+                    if let Some(dso) = self.frame_dso.get(frame) {
+                        *self.dso_references.entry(*dso).or_default() += 1;
+                    }
+                }
+                let leaf = if self.root_first {
+                    stack.frames.last()
+                } else {
+                    stack.frames.first()
+                };
+                if let Some(dso) = leaf.and_then(|f| self.frame_dso.get(f)) {
+                    *self.dso_leaves.entry(*dso).or_default() += 1;
+                }
+
+                for weight in &stack.weights {
+                    *self
+                        .weights
+                        .entry((stack.context.event.clone(), weight.metric.clone()))
+                        .or_default() += weight.value;
+                }
+            }
+            Record::Sample(_) => self.records.samples += 1,
+            Record::Samples(batch) => self.records.samples += batch.len() as u64,
+            Record::Window(_) => self.records.windows += 1,
+            Record::State(_) => self.records.states += 1,
+            Record::Note(_) => self.records.notes += 1,
+            Record::System(_) | Record::Footer(_) => {}
+        }
+    }
+
+    /// Produce the final statistics. `bytes` is the size of the input.
+    pub fn finish(self, bytes: u64, options: &StatsOptions) -> ProfileStats {
+        let mut top_dsos: Vec<DsoUsage> = self
+            .dso_names
+            .iter()
+            .map(|(id, name)| DsoUsage {
+                name: name.clone(),
+                frames: self.dso_frames.get(id).copied().unwrap_or(0),
+                stack_references: self.dso_references.get(id).copied().unwrap_or(0),
+                leaf_stacks: self.dso_leaves.get(id).copied().unwrap_or(0),
+            })
+            .collect();
+        top_dsos.sort_by(|a, b| {
+            b.stack_references
+                .cmp(&a.stack_references)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        top_dsos.truncate(options.top_dsos);
+
+        let mut min = 0;
+        let depth_histogram = self
+            .depths
+            .iter()
+            .enumerate()
+            .map(|(i, &stacks)| {
+                let max = DEPTH_BUCKETS.get(i).copied();
+                let bucket = DepthBucket { min, max, stacks };
+                min = max.map_or(min, |m| m + 1);
+                bucket
+            })
+            .collect();
+
+        let weights = self
+            .weights
+            .into_iter()
+            .map(|((event, metric), total)| MetricTotal {
+                primary: self.primary_metrics.get(&event) == Some(&metric),
+                event,
+                metric,
+                total,
+            })
+            .collect();
+
+        let ratio = |a: u64, b: u64| if b > 0 { a as f64 / b as f64 } else { 0.0 };
+        let dsos_referenced = self
+            .dso_frames
+            .keys()
+            .filter(|id| self.dso_names.contains_key(id))
+            .count() as u64;
+        let frames_referenced = self.frames_referenced.len() as u64;
+        let dictionary = DictionaryUsage {
+            frames_referenced,
+            frame_references: self.frame_references,
+            frame_hit_rate: ratio(frames_referenced, self.records.frames),
+            frame_reuse: ratio(self.frame_references, frames_referenced),
+            dso_hit_rate: ratio(dsos_referenced, self.records.dsos),
+        };
+
+        ProfileStats {
+            source_tool: self.source_tool,
+            bytes,
+            records: self.records,
+            unique_symbols: self.symbols.len() as u64,
+            top_dsos,
+            max_depth: self.max_depth,
+            depth_histogram,
+            weights,
+            dictionary,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_data() -> String {
+        [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"dso","id":2,"name":"/usr/lib/libc.so.6","is_kernel":false}"#,
+            r#"{"type":"dso","id":3,"name":"/usr/lib/unused.so","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"memcpy","dso":2}"#,
+            r#"{"type":"frame","id":3,"func":"main","dso":2}"#,
+            r#"{"type":"frame","id":4,"func":"orphan","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":100},{"metric":"samples","value":3}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[2,3,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":50},{"metric":"samples","value":1}]}"#,
+            r#"{"type":"sample","timestamp":1.0,"pid":1,"tid":1,"cpu":0,"event":"cycles","stack_id":"0x1"}"#,
+        ]
+        .join("\n")
+    }
+
+    #[test]
+    fn counts_records_and_symbols() {
+        let data = sample_data();
+        let stats = compute_stats(Cursor::new(data.as_bytes())).unwrap();
+
+        assert_eq!(stats.bytes, data.len() as u64);
+        assert_eq!(stats.records.dsos, 3);
+        assert_eq!(stats.records.frames, 4);
+        assert_eq!(stats.records.stacks, 2);
+        assert_eq!(stats.records.samples, 1);
+        assert_eq!(stats.unique_symbols, 3);
+        assert_eq!(stats.max_depth, 3);
+    }
+
+    #[test]
+    fn reports_weights_and_dsos() {
+        let stats = compute_stats(Cursor::new(sample_data())).unwrap();
+
+        let period = stats.weights.iter().find(|w| w.metric == "period").unwrap();
+        assert_eq!(period.total, 150);
+        assert!(period.primary);
+        assert!(
+            !stats
+                .weights
+                .iter()
+                .find(|w| w.metric == "samples")
+                .unwrap()
+                .primary
+        );
+
+        assert_eq!(stats.top_dsos[0].name, "/usr/lib/libc.so.6");
+        assert_eq!(stats.top_dsos[0].stack_references, 3);
+        assert_eq!(stats.top_dsos[0].leaf_stacks, 2);
+    }
+
+    #[test]
+    fn depth_histogram_and_dictionary_usage() {
+        let stats = compute_stats(Cursor::new(sample_data())).unwrap();
+
+        let bucket = |min| stats.depth_histogram.iter().find(|b| b.min == min).unwrap();
+        assert_eq!(bucket(2).max, Some(2));
+        assert_eq!(bucket(2).stacks, 1);
+        assert_eq!(bucket(3).stacks, 1);
+        assert_eq!(stats.depth_histogram.last().unwrap().max, None);
+
+        assert_eq!(stats.dictionary.frames_referenced, 3);
+        assert_eq!(stats.dictionary.frame_references, 5);
+        assert!((stats.dictionary.frame_hit_rate - 0.75).abs() < 1e-9);
+        assert!((stats.dictionary.dso_hit_rate - 2.0 / 3.0).abs() < 1e-9);
+    }
+}
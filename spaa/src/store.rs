@@ -0,0 +1,600 @@
+//! On-disk catalog of SPAA profiles.
+//!
+//! A [`Store`] is a directory holding profiles named by the SHA-256 of
+//! their contents, plus a `catalog.json` index of their metadata: source
+//! tool, recorded time range, host, labels and when they were added.
+//! Adding the same bytes twice keeps one copy and merges the labels.
+//! [`RetentionPolicy`] prunes old entries by age, count or total size.
+//!
+//! The catalog also remembers which stored profile each converted input
+//! produced, keyed by the input's digest and the converter version, so
+//! [`Store::convert`] can skip conversions it has already done.
+//!
+//! The store assumes one writer at a time; concurrent `add`s from several
+//! processes can lose catalog updates.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::store::{AddOptions, Filter, Store};
+//!
+//! let mut store = Store::open("profiles").unwrap();
+//! let data = std::fs::read("nightly.spaa").unwrap();
+//! let options = AddOptions {
+//!     host: Some("build-01".to_string()),
+//!     labels: [("branch".to_string(), "main".to_string())].into(),
+//!     ..Default::default()
+//! };
+//! store.add(&data, options).unwrap();
+//!
+//! let filter = Filter { tool: Some("perf".to_string()), ..Default::default() };
+//! for entry in store.entries().iter().filter(|e| filter.matches(e)) {
+//!     println!("{} {}", entry.id, entry.source_tool);
+//! }
+//! ```
+
+use crate::convert::{ConversionError, ConvertOptions, SourceFormat, convert_with};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use spaa_parse::{ParseError, SpaaFile, TimeRange};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Name of the index file inside the store directory.
+pub const CATALOG_FILE: &str = "catalog.json";
+
+const PROFILES_DIR: &str = "profiles";
+const CATALOG_VERSION: u32 = 1;
+
+/// Errors that can occur while using a store.
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("catalog error: {0}")]
+    Catalog(#[from] serde_json::Error),
+
+    #[error("invalid SPAA profile: {0}")]
+    InvalidProfile(#[from] ParseError),
+
+    #[error("no profile matches '{0}'")]
+    NotFound(String),
+
+    #[error("'{0}' matches more than one profile")]
+    Ambiguous(String),
+
+    #[error(transparent)]
+    Convert(#[from] ConversionError),
+}
+
+pub type Result<T> = std::result::Result<T, StoreError>;
+
+/// Catalog metadata for one stored profile.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Hex SHA-256 of the profile's bytes.
+    pub id: String,
+    /// Seconds since the Unix epoch.
+    pub added: u64,
+    /// File name the profile was added from, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub source_tool: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_range: Option<TimeRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    pub events: Vec<String>,
+    pub bytes: u64,
+}
+
+/// A remembered conversion: converting `input` with `converter` produced
+/// the stored profile `output`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Conversion {
+    /// Hex SHA-256 of the converter's input.
+    pub input: String,
+    /// [`ConvertOptions::cache_key`] of the conversion that ran.
+    pub converter: String,
+    /// ID of the resulting entry.
+    pub output: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Catalog {
+    version: u32,
+    entries: Vec<Entry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    conversions: Vec<Conversion>,
+}
+
+/// Metadata supplied when adding a profile.
+#[derive(Debug, Clone, Default)]
+pub struct AddOptions {
+    pub name: Option<String>,
+    pub host: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    /// Time recorded as [`Entry::added`]; defaults to now. Set it when
+    /// backfilling older profiles so retention treats them correctly.
+    pub added: Option<SystemTime>,
+}
+
+/// Result of [`Store::add`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Added {
+    pub entry: Entry,
+    /// The same bytes were already stored; only the labels were merged.
+    pub duplicate: bool,
+}
+
+/// Result of [`Store::convert`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Converted {
+    pub entry: Entry,
+    /// The conversion was found in the catalog and did not run.
+    pub cached: bool,
+}
+
+/// Criteria for listing entries. Unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub tool: Option<String>,
+    pub host: Option<String>,
+    /// Every label must be present with the given value.
+    pub labels: Vec<(String, String)>,
+}
+
+impl Filter {
+    /// Whether `entry` meets every criterion.
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.tool.as_ref().is_none_or(|t| &entry.source_tool == t)
+            && self
+                .host
+                .as_ref()
+                .is_none_or(|h| entry.host.as_ref() == Some(h))
+            && self
+                .labels
+                .iter()
+                .all(|(k, v)| entry.labels.get(k) == Some(v))
+    }
+}
+
+/// Which entries [`Store::prune`] removes. Limits combine: an entry is
+/// removed if any of them excludes it.
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Remove entries added longer ago than this.
+    pub max_age: Option<Duration>,
+    /// Keep at most this many of the newest entries.
+    pub max_count: Option<usize>,
+    /// Remove the oldest entries until the stored profiles fit.
+    pub max_bytes: Option<u64>,
+}
+
+/// A directory of profiles and their catalog.
+#[derive(Debug)]
+pub struct Store {
+    root: PathBuf,
+    entries: Vec<Entry>,
+    conversions: Vec<Conversion>,
+}
+
+impl Store {
+    /// Open the store at `root`, creating it if needed.
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(root.join(PROFILES_DIR))?;
+        let catalog = match fs::read(root.join(CATALOG_FILE)) {
+            Ok(data) => serde_json::from_slice::<Catalog>(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Catalog {
+                version: CATALOG_VERSION,
+                entries: Vec::new(),
+                conversions: Vec::new(),
+            },
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            root,
+            entries: catalog.entries,
+            conversions: catalog.conversions,
+        })
+    }
+
+    /// Directory the store lives in.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Every entry, oldest first.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Validate and store a profile.
+    pub fn add(&mut self, data: &[u8], options: AddOptions) -> Result<Added> {
+        let file = SpaaFile::parse_slice(data)?;
+        let id = digest(data);
+
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.id == id) {
+            entry.labels.extend(options.labels);
+            if entry.host.is_none() {
+                entry.host = options.host;
+            }
+            let entry = entry.clone();
+            self.save()?;
+            return Ok(Added {
+                entry,
+                duplicate: true,
+            });
+        }
+
+        tracing::debug!(%id, bytes = data.len(), "storing profile");
+        fs::write(self.profile_path(&id), data)?;
+        let entry = Entry {
+            added: unix_seconds(options.added.unwrap_or_else(SystemTime::now)),
+            name: options.name,
+            source_tool: file.header.source_tool.clone(),
+            command: file.header.source.as_ref().and_then(|s| s.command.clone()),
+            time_range: file.header.time_range.clone(),
+            host: options.host,
+            labels: options.labels,
+            events: file.header.events.iter().map(|e| e.name.clone()).collect(),
+            bytes: data.len() as u64,
+            id,
+        };
+        let pos = self.entries.partition_point(|e| e.added <= entry.added);
+        self.entries.insert(pos, entry.clone());
+        self.save()?;
+        Ok(Added {
+            entry,
+            duplicate: false,
+        })
+    }
+
+    /// Convert `data` and store the result, unless the same input was
+    /// already converted by the same converter version with the same
+    /// options and its output is still stored.
+    ///
+    /// `options` only apply when the conversion runs or its output is new;
+    /// a cache hit returns the stored entry unchanged.
+    pub fn convert(
+        &mut self,
+        format: SourceFormat,
+        data: &[u8],
+        convert_options: &ConvertOptions,
+        options: AddOptions,
+    ) -> Result<Converted> {
+        let input = digest(data);
+        let converter = convert_options.cache_key(format);
+        if let Some(entry) = self.conversion(&input, &converter) {
+            tracing::debug!(%input, %converter, output = %entry.id, "conversion cache hit");
+            return Ok(Converted {
+                entry: entry.clone(),
+                cached: true,
+            });
+        }
+        let spaa = convert_with(format, data, convert_options)?;
+        let added = self.add(&spaa, options)?;
+        self.record_conversion(&input, &converter, &added.entry.id)?;
+        Ok(Converted {
+            entry: added.entry,
+            cached: false,
+        })
+    }
+
+    /// The stored output of converting the input with digest `input` using
+    /// `converter`, if it is still in the store.
+    pub fn conversion(&self, input: &str, converter: &str) -> Option<&Entry> {
+        let conversion = self
+            .conversions
+            .iter()
+            .find(|c| c.input == input && c.converter == converter)?;
+        self.entries.iter().find(|e| e.id == conversion.output)
+    }
+
+    /// Remember that converting `input` with `converter` produced the entry
+    /// `output`, replacing any earlier result for the same pair.
+    pub fn record_conversion(&mut self, input: &str, converter: &str, output: &str) -> Result<()> {
+        self.conversions
+            .retain(|c| c.input != input || c.converter != converter);
+        self.conversions.push(Conversion {
+            input: input.to_string(),
+            converter: converter.to_string(),
+            output: output.to_string(),
+        });
+        self.save()
+    }
+
+    /// Find the entry whose ID is `id` or starts with it.
+    pub fn find(&self, id: &str) -> Result<&Entry> {
+        let mut matches = self.entries.iter().filter(|e| e.id.starts_with(id));
+        match (matches.next(), matches.next()) {
+            (Some(entry), None) if !id.is_empty() => Ok(entry),
+            (Some(_), _) if !id.is_empty() => Err(StoreError::Ambiguous(id.to_string())),
+            _ => Err(StoreError::NotFound(id.to_string())),
+        }
+    }
+
+    /// Path of the stored profile for `entry`.
+    pub fn path(&self, entry: &Entry) -> PathBuf {
+        self.profile_path(&entry.id)
+    }
+
+    /// Read the raw bytes of a stored profile.
+    pub fn read(&self, id: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.path(self.find(id)?))?)
+    }
+
+    /// Remove a profile and its catalog entry.
+    pub fn remove(&mut self, id: &str) -> Result<Entry> {
+        let id = self.find(id)?.id.clone();
+        let pos = self.entries.iter().position(|e| e.id == id).unwrap();
+        let entry = self.entries.remove(pos);
+        self.conversions.retain(|c| c.output != entry.id);
+        if let Err(e) = fs::remove_file(self.profile_path(&entry.id))
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            return Err(e.into());
+        }
+        self.save()?;
+        Ok(entry)
+    }
+
+    /// Entries `policy` would remove as of `now`, oldest first.
+    pub fn expired(&self, policy: &RetentionPolicy, now: SystemTime) -> Vec<&Entry> {
+        let cutoff = policy
+            .max_age
+            .map(|age| unix_seconds(now).saturating_sub(age.as_secs()));
+        let newest_kept = policy.max_count.unwrap_or(usize::MAX);
+        // Walk newest to oldest, keeping entries while every limit allows.
+        let mut kept_bytes = 0;
+        let mut expired: Vec<&Entry> = self
+            .entries
+            .iter()
+            .rev()
+            .enumerate()
+            .filter(|(i, entry)| {
+                let too_old = cutoff.is_some_and(|c| entry.added < c);
+                let too_many = *i >= newest_kept;
+                let too_big = policy
+                    .max_bytes
+                    .is_some_and(|max| kept_bytes + entry.bytes > max);
+                let remove = too_old || too_many || too_big;
+                if !remove {
+                    kept_bytes += entry.bytes;
+                }
+                remove
+            })
+            .map(|(_, entry)| entry)
+            .collect();
+        expired.reverse();
+        expired
+    }
+
+    /// Remove the entries `policy` excludes as of `now`.
+    pub fn prune(&mut self, policy: &RetentionPolicy, now: SystemTime) -> Result<Vec<Entry>> {
+        let ids: Vec<String> = self
+            .expired(policy, now)
+            .into_iter()
+            .map(|e| e.id.clone())
+            .collect();
+        ids.iter().map(|id| self.remove(id)).collect()
+    }
+
+    fn profile_path(&self, id: &str) -> PathBuf {
+        self.root.join(PROFILES_DIR).join(format!("{}.spaa", id))
+    }
+
+    /// Write the catalog via a temporary file so a crash never leaves it
+    /// half-written.
+    fn save(&self) -> Result<()> {
+        let catalog = Catalog {
+            version: CATALOG_VERSION,
+            entries: self.entries.clone(),
+            conversions: self.conversions.clone(),
+        };
+        let tmp = self.root.join(format!("{}.tmp", CATALOG_FILE));
+        fs::write(&tmp, serde_json::to_vec_pretty(&catalog)?)?;
+        fs::rename(tmp, self.root.join(CATALOG_FILE))?;
+        Ok(())
+    }
+}
+
+/// Hex SHA-256 of `data`, as used for entry IDs and conversion inputs.
+pub fn digest(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(tool: &str) -> Vec<u8> {
+        format!(
+            concat!(
+                r#"{{"type":"header","format":"spaa","version":"1.0","source_tool":"{}","frame_order":"leaf_to_root","events":[{{"name":"cycles","kind":"hardware","sampling":{{"mode":"period","primary_metric":"period"}}}}]}}"#,
+                "\n",
+                r#"{{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}}"#,
+                "\n",
+                r#"{{"type":"frame","id":1,"func":"main","dso":1}}"#,
+                "\n",
+                r#"{{"type":"stack","id":"0x1","frames":[1],"context":{{"event":"cycles"}},"weights":[{{"metric":"period","value":10}}]}}"#,
+                "\n",
+            ),
+            tool
+        )
+        .into_bytes()
+    }
+
+    fn temp_store(name: &str) -> Store {
+        let dir = std::env::temp_dir().join(format!("spaa-store-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Store::open(dir).unwrap()
+    }
+
+    fn at(secs: u64) -> AddOptions {
+        AddOptions {
+            added: Some(UNIX_EPOCH + Duration::from_secs(secs)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn duplicate_add_merges_labels() {
+        let mut store = temp_store("dedupe");
+        let data = profile("perf");
+        store.add(&data, AddOptions::default()).unwrap();
+        let options = AddOptions {
+            labels: [("branch".to_string(), "main".to_string())].into(),
+            ..Default::default()
+        };
+        let added = store.add(&data, options).unwrap();
+
+        assert!(added.duplicate);
+        assert_eq!(store.entries().len(), 1);
+        assert_eq!(store.entries()[0].labels["branch"], "main");
+    }
+
+    #[test]
+    fn catalog_survives_reopen() {
+        let mut store = temp_store("reopen");
+        let id = store
+            .add(&profile("perf"), AddOptions::default())
+            .unwrap()
+            .entry
+            .id;
+        let reopened = Store::open(store.root()).unwrap();
+
+        assert_eq!(reopened.entries(), store.entries());
+        assert_eq!(reopened.read(&id[..8]).unwrap(), profile("perf"));
+    }
+
+    #[test]
+    fn filter_matches_tool_and_labels() {
+        let mut store = temp_store("filter");
+        let mut options = AddOptions::default();
+        options.labels.insert("env".to_string(), "ci".to_string());
+        store.add(&profile("perf"), options).unwrap();
+        store
+            .add(&profile("dtrace"), AddOptions::default())
+            .unwrap();
+        let filter = Filter {
+            tool: Some("perf".to_string()),
+            labels: vec![("env".to_string(), "ci".to_string())],
+            ..Default::default()
+        };
+
+        let matched: Vec<_> = store
+            .entries()
+            .iter()
+            .filter(|e| filter.matches(e))
+            .collect();
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].source_tool, "perf");
+    }
+
+    #[test]
+    fn prune_removes_old_and_excess_entries() {
+        let mut store = temp_store("prune");
+        store.add(&profile("a"), at(100)).unwrap();
+        store.add(&profile("b"), at(200)).unwrap();
+        store.add(&profile("c"), at(300)).unwrap();
+        store.add(&profile("d"), at(400)).unwrap();
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::from_secs(250)),
+            max_count: Some(2),
+            max_bytes: None,
+        };
+
+        let removed = store
+            .prune(&policy, UNIX_EPOCH + Duration::from_secs(450))
+            .unwrap();
+        let tools: Vec<&str> = removed.iter().map(|e| e.source_tool.as_str()).collect();
+        assert_eq!(tools, vec!["a", "b"]);
+        assert_eq!(store.entries().len(), 2);
+        assert!(!store.path(&removed[0]).exists());
+    }
+
+    const PERF_SCRIPT: &str =
+        "app  1234 [000] 12345.678901:     100000 cycles:\n\t401234 main+0x54 (/usr/bin/app)\n\n";
+
+    #[test]
+    fn repeated_conversion_is_cached() {
+        let mut store = temp_store("convert");
+        let first = store
+            .convert(
+                SourceFormat::Perf,
+                PERF_SCRIPT.as_bytes(),
+                &ConvertOptions::default(),
+                AddOptions::default(),
+            )
+            .unwrap();
+        let second = store
+            .convert(
+                SourceFormat::Perf,
+                PERF_SCRIPT.as_bytes(),
+                &ConvertOptions::default(),
+                AddOptions::default(),
+            )
+            .unwrap();
+
+        assert!(!first.cached);
+        assert!(second.cached);
+        assert_eq!(first.entry, second.entry);
+    }
+
+    #[test]
+    fn conversion_lookup_is_keyed_by_converter_version() {
+        let mut store = temp_store("converter-version");
+        let id = store
+            .add(&profile("perf"), AddOptions::default())
+            .unwrap()
+            .entry
+            .id;
+        store.record_conversion("abc", "perf@1.0.0", &id).unwrap();
+
+        assert!(store.conversion("abc", "perf@1.0.0").is_some());
+        assert!(store.conversion("abc", "perf@2.0.0").is_none());
+    }
+
+    #[test]
+    fn removing_output_forgets_conversion() {
+        let mut store = temp_store("convert-remove");
+        let converted = store
+            .convert(
+                SourceFormat::Perf,
+                PERF_SCRIPT.as_bytes(),
+                &ConvertOptions::default(),
+                AddOptions::default(),
+            )
+            .unwrap();
+        store.remove(&converted.entry.id).unwrap();
+        let reopened = Store::open(store.root()).unwrap();
+
+        let input = digest(PERF_SCRIPT.as_bytes());
+        let converter = crate::convert::converter_version(SourceFormat::Perf);
+        assert!(reopened.conversion(&input, &converter).is_none());
+        assert!(reopened.conversions.is_empty());
+    }
+
+    #[test]
+    fn empty_or_unknown_prefix_is_not_found() {
+        let mut store = temp_store("prefix");
+        store.add(&profile("a"), AddOptions::default()).unwrap();
+
+        assert!(matches!(store.find(""), Err(StoreError::NotFound(_))));
+        assert!(matches!(store.find("xyz"), Err(StoreError::NotFound(_))));
+    }
+}
@@ -0,0 +1,367 @@
+//! Symbol normalization for matching functions across builds.
+//!
+//! Compilers decorate function names with build-specific noise: Rust appends
+//! a hash (`::h1a2b3c4d5e6f7a8b`), C++ templates expand their arguments, and
+//! GCC emits clones such as `foo.isra.0` or `foo.constprop.3`. When two SPAA
+//! files come from different builds, exact name matching treats these as
+//! different functions. [`SymbolNormalizer`] strips the noise so the same
+//! logical function gets the same name.
+//!
+//! # Example
+//!
+//! ```
+//! use spaa::symbols::normalize_symbol;
+//!
+//! assert_eq!(
+//!     normalize_symbol("core::ptr::drop_in_place::h1a2b3c4d5e6f7a8b"),
+//!     "core::ptr::drop_in_place"
+//! );
+//! assert_eq!(normalize_symbol("std::vector<int>::push_back(int const&)"), "std::vector::push_back");
+//! assert_eq!(normalize_symbol("memcpy.isra.0"), "memcpy");
+//! ```
+
+use spaa_parse::{Cancelled, NoProgress, ProgressSink, SpaaFile};
+
+/// Frames normalized between progress updates.
+const PROGRESS_INTERVAL: usize = 1 << 16;
+
+/// GCC/LLVM clone and partitioning suffixes, as in `foo.isra.0`.
+const CLONE_SUFFIXES: &[&str] = &[
+    "isra",
+    "constprop",
+    "part",
+    "cold",
+    "clone",
+    "lto_priv",
+    "llvm",
+    "specialized",
+    "localalias",
+];
+
+/// Configurable symbol normalizer.
+///
+/// Every rule is enabled by default.
+#[derive(Debug, Clone)]
+pub struct SymbolNormalizer {
+    /// Strip Rust legacy hashes (`::h<16 hex digits>`) and v0 crate
+    /// disambiguators (`std[a1b2c3]`).
+    pub strip_hashes: bool,
+    /// Strip template/generic arguments that follow an identifier
+    /// (`Vec<T>` becomes `Vec`). Qualified-path brackets such as
+    /// `<T as Trait>::method` are kept, with their contents normalized.
+    pub strip_template_args: bool,
+    /// Strip compiler clone suffixes such as `.isra.0`, `.constprop.1`,
+    /// `.part.2` and `.cold`.
+    pub strip_clone_suffixes: bool,
+    /// Strip trailing C++ parameter lists and qualifiers (`(int) const`).
+    pub strip_parameters: bool,
+}
+
+impl Default for SymbolNormalizer {
+    fn default() -> Self {
+        Self {
+            strip_hashes: true,
+            strip_template_args: true,
+            strip_clone_suffixes: true,
+            strip_parameters: true,
+        }
+    }
+}
+
+impl SymbolNormalizer {
+    /// Normalize a single symbol name.
+    pub fn normalize(&self, symbol: &str) -> String {
+        let mut name = symbol.trim().to_string();
+
+        if self.strip_clone_suffixes {
+            name = strip_clone_suffixes(&name);
+        }
+        if self.strip_hashes {
+            name = strip_rust_hashes(&name);
+        }
+        if self.strip_parameters {
+            name = strip_parameters(&name);
+        }
+        if self.strip_template_args {
+            name = strip_template_args(&name);
+        }
+
+        if name.is_empty() {
+            symbol.to_string()
+        } else {
+            name
+        }
+    }
+
+    /// Returns true if two symbols normalize to the same name.
+    pub fn equivalent(&self, a: &str, b: &str) -> bool {
+        a == b || self.normalize(a) == self.normalize(b)
+    }
+
+    /// Rewrite every frame's function name in `file` to its normalized form.
+    ///
+    /// Returns the number of frames whose name changed.
+    pub fn normalize_file(&self, file: &mut SpaaFile) -> usize {
+        self.normalize_file_with_progress(file, &mut NoProgress)
+            .expect("NoProgress never cancels")
+    }
+
+    /// Like [`SymbolNormalizer::normalize_file`], reporting frames processed
+    /// to `progress` (stage `"symbols"`).
+    ///
+    /// If the sink cancels, frames already rewritten stay rewritten.
+    pub fn normalize_file_with_progress(
+        &self,
+        file: &mut SpaaFile,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<usize, Cancelled> {
+        let total = Some(file.frames.len() as u64);
+        let mut changed = 0;
+        for (i, frame) in file.frames.values_mut().enumerate() {
+            if i % PROGRESS_INTERVAL == 0 {
+                progress.update("symbols", i as u64, total)?;
+            }
+            let normalized = self.normalize(&frame.func);
+            if normalized != frame.func {
+                frame.func = normalized;
+                changed += 1;
+            }
+        }
+        progress.update("symbols", file.frames.len() as u64, total)?;
+        Ok(changed)
+    }
+}
+
+/// Normalize a symbol using the default [`SymbolNormalizer`].
+pub fn normalize_symbol(symbol: &str) -> String {
+    SymbolNormalizer::default().normalize(symbol)
+}
+
+/// Remove trailing `.suffix` and `.suffix.N` clone markers, repeatedly.
+fn strip_clone_suffixes(name: &str) -> String {
+    let mut parts: Vec<&str> = name.split('.').collect();
+    while parts.len() > 1 {
+        let last = parts[parts.len() - 1];
+        if !last.is_empty() && last.bytes().all(|b| b.is_ascii_digit()) {
+            // `foo.isra.0`: numeric index following a known suffix.
+            if parts.len() > 2 && CLONE_SUFFIXES.contains(&parts[parts.len() - 2]) {
+                parts.truncate(parts.len() - 2);
+                continue;
+            }
+            break;
+        }
+        if CLONE_SUFFIXES.contains(&last) {
+            parts.pop();
+            continue;
+        }
+        break;
+    }
+    parts.join(".")
+}
+
+/// Remove Rust legacy `::h<hash>` suffixes and v0 `[hash]` disambiguators.
+fn strip_rust_hashes(name: &str) -> String {
+    let mut name = name;
+    if let Some(pos) = name.rfind("::h") {
+        let hash = &name[pos + 3..];
+        if hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            name = &name[..pos];
+        }
+    }
+
+    let mut out = String::with_capacity(name.len());
+    let mut chars = name.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == '[' {
+            let rest = &name[i + 1..];
+            if let Some(end) = rest.find(']') {
+                let inner = &rest[..end];
+                if !inner.is_empty() && inner.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    for _ in 0..=end {
+                        chars.next();
+                    }
+                    continue;
+                }
+            }
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Remove a trailing C++ parameter list and any cv/ref qualifiers after it.
+fn strip_parameters(name: &str) -> String {
+    let trimmed = name.trim_end();
+    let mut end = trimmed.len();
+    for qualifier in [" const", " volatile", " &&", " &"] {
+        if trimmed[..end].ends_with(qualifier) {
+            end -= qualifier.len();
+        }
+    }
+    let head = &trimmed[..end];
+    if !head.ends_with(')') {
+        return name.to_string();
+    }
+
+    // Find the '(' that balances the trailing ')'.
+    let mut depth = 0;
+    for (i, c) in head.char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' => {
+                depth -= 1;
+                if depth == 0 {
+                    // Keep things like `(anonymous namespace)` that are not
+                    // parameter lists: a parameter list follows a name.
+                    let before = &head[..i];
+                    if before.is_empty() || before.ends_with("::") {
+                        return name.to_string();
+                    }
+                    return before.to_string();
+                }
+            }
+            _ => {}
+        }
+    }
+    name.to_string()
+}
+
+/// Remove `<...>` argument lists that directly follow an identifier.
+fn strip_template_args(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut out = String::with_capacity(name.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'<' && follows_identifier(&out) && !out.ends_with("operator") {
+            // Skip to the matching '>'.
+            let mut depth = 0;
+            let mut j = i;
+            while j < bytes.len() {
+                match bytes[j] {
+                    b'<' => depth += 1,
+                    b'>' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                j += 1;
+            }
+            if j == bytes.len() {
+                // Unbalanced; leave the rest alone.
+                out.push_str(&name[i..]);
+                break;
+            }
+            i = j + 1;
+            continue;
+        }
+        let ch = name[i..].chars().next().unwrap();
+        out.push(ch);
+        i += ch.len_utf8();
+    }
+    // Rust turbofish leaves a dangling `::` before the stripped arguments.
+    out.replace("::::", "::").trim_end_matches("::").to_string()
+}
+
+fn follows_identifier(out: &str) -> bool {
+    let trimmed = out.trim_end_matches("::");
+    trimmed
+        .chars()
+        .last()
+        .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn strips_rust_legacy_hash() {
+        assert_eq!(
+            normalize_symbol("alloc::raw_vec::finish_grow::h0f1e2d3c4b5a6978"),
+            "alloc::raw_vec::finish_grow"
+        );
+        // Not a hash: wrong length.
+        assert_eq!(normalize_symbol("foo::hello"), "foo::hello");
+    }
+
+    #[test]
+    fn strips_rust_v0_disambiguators() {
+        assert_eq!(
+            normalize_symbol("std[a1b2c3d4]::rt::lang_start"),
+            "std::rt::lang_start"
+        );
+    }
+
+    #[test]
+    fn strips_template_args_but_keeps_qualified_paths() {
+        assert_eq!(
+            normalize_symbol("std::vector<std::pair<int, int>>::push_back"),
+            "std::vector::push_back"
+        );
+        assert_eq!(
+            normalize_symbol("<alloc::vec::Vec<T> as core::ops::drop::Drop>::drop"),
+            "<alloc::vec::Vec as core::ops::drop::Drop>::drop"
+        );
+        assert_eq!(normalize_symbol("core::mem::swap::<u8>"), "core::mem::swap");
+        assert_eq!(normalize_symbol("operator<<"), "operator<<");
+    }
+
+    #[test]
+    fn strips_clone_suffixes() {
+        assert_eq!(normalize_symbol("memcpy.isra.0"), "memcpy");
+        assert_eq!(normalize_symbol("foo.constprop.2.isra.0"), "foo");
+        assert_eq!(normalize_symbol("bar.part.1.cold"), "bar");
+        assert_eq!(normalize_symbol("version.2"), "version.2");
+    }
+
+    #[test]
+    fn strips_parameters_and_qualifiers() {
+        assert_eq!(
+            normalize_symbol("Foo::bar(int, char const*) const"),
+            "Foo::bar"
+        );
+        assert_eq!(
+            normalize_symbol("(anonymous namespace)::helper()"),
+            "(anonymous namespace)::helper"
+        );
+    }
+
+    #[test]
+    fn rules_can_be_disabled() {
+        let normalizer = SymbolNormalizer {
+            strip_parameters: false,
+            ..Default::default()
+        };
+        assert_eq!(normalizer.normalize("foo(int)"), "foo(int)");
+    }
+
+    #[test]
+    fn equivalent_across_builds() {
+        let normalizer = SymbolNormalizer::default();
+        assert!(normalizer.equivalent(
+            "app::handler::h0123456789abcdef",
+            "app::handler::hfedcba9876543210"
+        ));
+        assert!(!normalizer.equivalent("app::handler", "app::other"));
+    }
+
+    #[test]
+    fn normalize_file_rewrites_frames() {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"app::run::h0123456789abcdef","dso":1}"#,
+        ]
+        .join("\n");
+        let mut file = SpaaFile::parse(Cursor::new(data)).unwrap();
+
+        assert_eq!(SymbolNormalizer::default().normalize_file(&mut file), 1);
+        assert_eq!(file.frames[&2].func, "app::run");
+    }
+}
@@ -0,0 +1,113 @@
+//! Configurable synthetic profile generation.
+//!
+//! [`ProfileGenerator`] produces deterministic SPAA files for testing agent
+//! pipelines and other consumers: the same settings and seed always yield
+//! the same file. It maps its settings onto [`spaa_parse::synth`], which
+//! does the generating, and describes the events from [`crate::pmu`].
+//!
+//! # Example
+//!
+//! ```
+//! use spaa::synth::{DepthDistribution, EventWeight, ProfileGenerator};
+//!
+//! let generator = ProfileGenerator {
+//!     stacks: 200,
+//!     depth: DepthDistribution::Geometric { mean: 12.0 },
+//!     events: vec!["cycles=3".parse().unwrap(), "cache-misses".parse().unwrap()],
+//!     ..Default::default()
+//! };
+//! let file = generator.generate();
+//! assert_eq!(file.stacks.len(), 200);
+//! assert_eq!(file.header.events.len(), 2);
+//! ```
+
+use spaa_parse::SpaaFile;
+use spaa_parse::synth::{SynthConfig, generate};
+
+pub use spaa_parse::synth::{
+    DepthDistribution, EventWeight, ParseDepthDistributionError, ParseEventWeightError, SymbolPool,
+};
+
+/// Settings for a generated profile.
+///
+/// Every event is declared as a hardware event sampled by `period`, with
+/// descriptions and units from [`crate::pmu`] where known.
+#[derive(Debug, Clone)]
+pub struct ProfileGenerator {
+    pub seed: u64,
+    /// Number of distinct stacks to generate.
+    pub stacks: usize,
+    pub depth: DepthDistribution,
+    pub symbols: SymbolPool,
+    /// Events and their share of stacks. Defaults to `cycles` alone.
+    pub events: Vec<EventWeight>,
+    pub threads: usize,
+    /// Raw sample records emitted per stack; 0 for none.
+    pub samples_per_stack: usize,
+    /// Time span, in seconds, that sample timestamps fall in. Recorded in
+    /// the header when set.
+    pub time_range: Option<(f64, f64)>,
+}
+
+impl Default for ProfileGenerator {
+    fn default() -> Self {
+        let config = SynthConfig::default();
+        Self {
+            seed: config.seed,
+            stacks: config.stacks,
+            depth: config.depth,
+            symbols: config.symbols,
+            events: config.events,
+            threads: config.threads,
+            samples_per_stack: config.samples_per_stack,
+            time_range: config.time_range,
+        }
+    }
+}
+
+impl ProfileGenerator {
+    /// Generate the profile.
+    ///
+    /// If the call graph cannot produce `stacks` distinct stacks (tiny
+    /// symbol pools or depths), the file has fewer.
+    pub fn generate(&self) -> SpaaFile {
+        let mut file = generate(&SynthConfig {
+            seed: self.seed,
+            stacks: self.stacks,
+            depth: self.depth,
+            symbols: self.symbols.clone(),
+            events: self.events.clone(),
+            threads: self.threads,
+            samples_per_stack: self.samples_per_stack,
+            time_range: self.time_range,
+        });
+        crate::pmu::describe_events(&mut file.header.events);
+        file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_mix_follows_shares() {
+        let file = ProfileGenerator {
+            stacks: 1000,
+            events: vec![
+                "cycles=3".parse().unwrap(),
+                "cache-misses=1".parse().unwrap(),
+            ],
+            ..Default::default()
+        }
+        .generate();
+
+        let misses = file.stacks_for_event("cache-misses").count();
+        assert!((150..350).contains(&misses), "{misses} cache-misses stacks");
+        assert_eq!(
+            file.header.events[1].unit.as_deref(),
+            Some("misses"),
+            "events are described from the PMU catalog"
+        );
+    }
+}
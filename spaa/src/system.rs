@@ -0,0 +1,70 @@
+//! Host metadata for the `system` record.
+//!
+//! Converters fill in [`SystemInfo`] from what the source tool records;
+//! [`local`] describes the machine the conversion runs on instead, for
+//! tools like DTrace whose output says nothing about the host. It is only
+//! right when the profile was recorded on the same machine, which is why
+//! `spaa convert` captures it only with `--system-info`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa_parse::SpaaFile;
+//! use std::fs::File;
+//!
+//! let mut spaa = SpaaFile::parse(File::open("profile.spaa").unwrap()).unwrap();
+//! spaa.system.get_or_insert_with(spaa::system::local);
+//! ```
+
+use spaa_parse::SystemInfo;
+use std::path::Path;
+
+/// Describe the local machine, leaving out whatever cannot be read.
+///
+/// The OS, architecture and core count come from the standard library; on
+/// Linux the hostname, kernel version, CPU model and memory size are read
+/// from `/proc`.
+pub fn local() -> SystemInfo {
+    let proc = Path::new("/proc");
+    let read = |path: &str| std::fs::read_to_string(proc.join(path)).ok();
+    let first_line = |path: &str| {
+        read(path)
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+    SystemInfo {
+        hostname: first_line("sys/kernel/hostname")
+            .or_else(|| std::env::var("HOSTNAME").ok().filter(|s| !s.is_empty())),
+        os: Some(std::env::consts::OS.to_string()),
+        kernel_version: first_line("sys/kernel/osrelease"),
+        arch: Some(std::env::consts::ARCH.to_string()),
+        cpu_model: read("cpuinfo").and_then(|s| field(&s, "model name")),
+        cpu_cores: std::thread::available_parallelism()
+            .ok()
+            .map(|n| n.get() as u32),
+        memory_bytes: read("meminfo")
+            .and_then(|s| field(&s, "MemTotal"))
+            .and_then(|v| v.trim_end_matches("kB").trim().parse::<u64>().ok())
+            .map(|kb| kb * 1024),
+    }
+}
+
+/// The value of the first `key : value` line for `key` in a `/proc` file.
+fn field(contents: &str, key: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let (k, v) = line.split_once(':')?;
+        (k.trim() == key).then(|| v.trim().to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proc_fields_are_read_by_key() {
+        let meminfo = "MemTotal:       16318360 kB\nMemFree:         1000 kB\n";
+        assert_eq!(field(meminfo, "MemTotal").as_deref(), Some("16318360 kB"));
+        assert_eq!(field(meminfo, "SwapTotal"), None);
+    }
+}
@@ -0,0 +1,555 @@
+//! Test helpers for converter authors.
+//!
+//! Comparing converter output as NDJSON text breaks whenever IDs are
+//! assigned differently or records come out in another order. The helpers
+//! here compare [`SpaaFile`]s semantically instead: frames, DSOs and stacks
+//! are matched by content, stacks are compared root to leaf regardless of
+//! `frame_order`, and duplicate stacks are summed.
+//!
+//! [`GoldenCorpus`] runs a converter over a directory of inputs and checks
+//! each result against a stored `.spaa` file.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::dtrace::{DtraceConverter, InputFormat};
+//! use spaa::testing::{GoldenCorpus, assert_equivalent};
+//! use spaa_parse::SpaaFile;
+//! use std::fs::File;
+//!
+//! // Compare two files directly...
+//! let expected = SpaaFile::parse(File::open("expected.spaa").unwrap()).unwrap();
+//! let actual = SpaaFile::parse(File::open("actual.spaa").unwrap()).unwrap();
+//! assert_equivalent(&expected, &actual);
+//!
+//! // ...or check every `*.stacks` file in a directory against its `.spaa`.
+//! // Set `SPAA_BLESS=1` to (re)write the expected files.
+//! GoldenCorpus::new("tests/corpus", "stacks").check(|path| {
+//!     let mut converter = DtraceConverter::new(InputFormat::AggregatedStack);
+//!     converter.parse(File::open(path)?)?;
+//!     Ok::<_, Box<dyn std::error::Error>>(converter.to_spaa_file()?)
+//! });
+//! ```
+
+use serde_json::{Value, json};
+use spaa_parse::{FrameOrder, SpaaFile, Stack, Weight};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Environment variable that makes [`GoldenCorpus`] write expected files
+/// instead of comparing against them.
+pub const BLESS_ENV: &str = "SPAA_BLESS";
+
+/// Differences listed in an assertion failure before the rest are elided.
+const MAX_REPORTED: usize = 20;
+
+/// One semantic difference between two files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// Part of the file that differs: `header`, `dso`, `frame`, `thread`,
+    /// `stack`, `sample`, `window` or `state`.
+    pub section: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.section, self.message)
+    }
+}
+
+/// Compare two files semantically, ignoring ID assignment, record order,
+/// frame order and stack-ID scheme.
+///
+/// Stacks are matched by their resolved frames, type and context; stacks
+/// that resolve to the same key have their weights summed. The
+/// `related_stacks` field and window IDs are not compared.
+///
+/// [`SpaaFile::semantically_eq`] gives the same kind of answer as a plain
+/// bool; this function exists to say *what* differs.
+pub fn compare(expected: &SpaaFile, actual: &SpaaFile) -> Vec<Difference> {
+    let expected = Canonical::new(expected);
+    let actual = Canonical::new(actual);
+    let mut diffs = Vec::new();
+
+    if expected.header != actual.header {
+        diffs.push(Difference {
+            section: "header",
+            message: format!("expected {}, found {}", expected.header, actual.header),
+        });
+    }
+    diff_maps("dso", &expected.dsos, &actual.dsos, &mut diffs);
+    diff_maps("frame", &expected.frames, &actual.frames, &mut diffs);
+    diff_maps("thread", &expected.threads, &actual.threads, &mut diffs);
+    diff_maps("stack", &expected.stacks, &actual.stacks, &mut diffs);
+    diff_maps("sample", &expected.samples, &actual.samples, &mut diffs);
+    diff_maps("window", &expected.windows, &actual.windows, &mut diffs);
+    diff_maps("state", &expected.states, &actual.states, &mut diffs);
+    diffs
+}
+
+/// Panic with a readable list of differences unless the files are
+/// semantically equal (see [`compare`]).
+#[track_caller]
+pub fn assert_equivalent(expected: &SpaaFile, actual: &SpaaFile) {
+    let diffs = compare(expected, actual);
+    if !diffs.is_empty() {
+        panic!("SPAA files differ:\n{}", format_differences(&diffs));
+    }
+}
+
+/// Write `file` as NDJSON, parse it back and assert nothing was lost.
+#[track_caller]
+pub fn assert_round_trip(file: &SpaaFile) {
+    let mut ndjson = Vec::new();
+    file.write(&mut ndjson).expect("writing SPAA failed");
+    let parsed = SpaaFile::parse_slice(&ndjson).expect("re-parsing written SPAA failed");
+    assert_equivalent(file, &parsed);
+}
+
+fn format_differences(diffs: &[Difference]) -> String {
+    let mut out: Vec<String> = diffs
+        .iter()
+        .take(MAX_REPORTED)
+        .map(|d| format!("  - {d}"))
+        .collect();
+    if diffs.len() > MAX_REPORTED {
+        out.push(format!("  ... and {} more", diffs.len() - MAX_REPORTED));
+    }
+    out.join("\n")
+}
+
+/// A directory of converter inputs and the SPAA files they should convert to.
+///
+/// Each `<name>.<extension>` input is paired with `<name>.spaa` in the same
+/// directory.
+#[derive(Debug, Clone)]
+pub struct GoldenCorpus {
+    dir: PathBuf,
+    extension: String,
+    bless: bool,
+}
+
+impl GoldenCorpus {
+    /// A corpus of `*.<extension>` files in `dir`. Blessing is enabled when
+    /// the [`BLESS_ENV`] environment variable is set.
+    pub fn new(dir: impl Into<PathBuf>, extension: &str) -> Self {
+        Self {
+            dir: dir.into(),
+            extension: extension.trim_start_matches('.').to_string(),
+            bless: std::env::var_os(BLESS_ENV).is_some(),
+        }
+    }
+
+    /// Write expected files from the converter's output instead of
+    /// comparing against them.
+    pub fn bless(mut self, bless: bool) -> Self {
+        self.bless = bless;
+        self
+    }
+
+    /// Input files in the corpus, sorted by name.
+    pub fn inputs(&self) -> std::io::Result<Vec<PathBuf>> {
+        let mut inputs = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|e| e == self.extension.as_str())
+            {
+                inputs.push(path);
+            }
+        }
+        inputs.sort();
+        Ok(inputs)
+    }
+
+    /// Run `convert` on every input and collect failures, one message per
+    /// failing input.
+    pub fn run<F, E>(&self, convert: F) -> std::io::Result<Vec<String>>
+    where
+        F: Fn(&Path) -> Result<SpaaFile, E>,
+        E: fmt::Display,
+    {
+        let mut failures = Vec::new();
+        for input in self.inputs()? {
+            let golden = input.with_extension("spaa");
+            let name = input.display();
+            let actual = match convert(&input) {
+                Ok(file) => file,
+                Err(e) => {
+                    failures.push(format!("{name}: conversion failed: {e}"));
+                    continue;
+                }
+            };
+
+            if self.bless {
+                let mut writer = std::io::BufWriter::new(std::fs::File::create(&golden)?);
+                actual.write(&mut writer).map_err(std::io::Error::other)?;
+                writer.flush()?;
+                continue;
+            }
+
+            let expected = match std::fs::read(&golden) {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    failures.push(format!(
+                        "{name}: missing {}; rerun with {BLESS_ENV}=1 to create it",
+                        golden.display()
+                    ));
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            match SpaaFile::parse_slice(&expected) {
+                Ok(expected) => {
+                    let diffs = compare(&expected, &actual);
+                    if !diffs.is_empty() {
+                        failures.push(format!("{name}:\n{}", format_differences(&diffs)));
+                    }
+                }
+                Err(e) => failures.push(format!("{}: invalid SPAA: {e}", golden.display())),
+            }
+        }
+        Ok(failures)
+    }
+
+    /// Like [`GoldenCorpus::run`], but panic if any input fails or the
+    /// corpus is empty.
+    #[track_caller]
+    pub fn check<F, E>(&self, convert: F)
+    where
+        F: Fn(&Path) -> Result<SpaaFile, E>,
+        E: fmt::Display,
+    {
+        let inputs = self.inputs().unwrap_or_else(|e| {
+            panic!("cannot read corpus {}: {e}", self.dir.display());
+        });
+        assert!(
+            !inputs.is_empty(),
+            "no *.{} files in {}",
+            self.extension,
+            self.dir.display()
+        );
+        let failures = self.run(convert).unwrap_or_else(|e| {
+            panic!("cannot read corpus {}: {e}", self.dir.display());
+        });
+        if !failures.is_empty() {
+            panic!(
+                "{} of {} corpus files failed:\n{}",
+                failures.len(),
+                inputs.len(),
+                failures.join("\n")
+            );
+        }
+    }
+}
+
+/// ID-free view of a file, with every record reduced to a JSON string key.
+struct Canonical {
+    header: Value,
+    dsos: BTreeMap<String, usize>,
+    frames: BTreeMap<String, usize>,
+    threads: BTreeMap<String, usize>,
+    /// Stack key to summed `{weights, exclusive}`.
+    stacks: BTreeMap<String, String>,
+    samples: BTreeMap<String, usize>,
+    windows: BTreeMap<String, usize>,
+    states: BTreeMap<String, usize>,
+}
+
+impl Canonical {
+    fn new(file: &SpaaFile) -> Self {
+        let mut header = to_value(&file.header);
+        if let Value::Object(map) = &mut header {
+            map.remove("frame_order");
+            map.remove("stack_id_mode");
+            if let Some(Value::Array(events)) = map.get_mut("events") {
+                events.sort_by_key(|e| e["name"].to_string());
+            }
+        }
+
+        let dsos = count(file.dsos.values().map(|d| dso_value(file, d.id)));
+        let frames = count(file.frames.keys().map(|id| frame_value(file, *id)));
+        let threads = count(file.threads.values().map(to_value));
+
+        let mut weights: BTreeMap<String, StackWeights> = BTreeMap::new();
+        for stack in file.stacks.values() {
+            weights
+                .entry(stack_key(file, stack))
+                .or_default()
+                .add(file, stack);
+        }
+        let stacks = weights
+            .into_iter()
+            .map(|(key, w)| (key, w.to_string()))
+            .collect();
+
+        let stack_ref = |id: &str| -> Value {
+            file.stacks
+                .get(id)
+                .map_or_else(|| json!(id), |s| json!(stack_key(file, s)))
+        };
+
+        let samples = count(file.samples.iter().map(|s| {
+            let mut v = to_value(s);
+            v["stack_id"] = stack_ref(&s.stack_id);
+            v
+        }));
+        let windows = count(file.windows.iter().map(|w| {
+            let mut by_stack: Vec<Value> = w
+                .by_stack
+                .iter()
+                .map(
+                    |s| json!({"stack": stack_ref(&s.stack_id), "weights": weight_map(&s.weights)}),
+                )
+                .collect();
+            by_stack.sort_by_key(|v| v.to_string());
+            json!({"start": w.start, "end": w.end, "unit": w.unit, "by_stack": by_stack})
+        }));
+        let states = count(file.states.iter().map(|s| {
+            let mut v = to_value(s);
+            if let Some(id) = &s.stack_id {
+                v["stack_id"] = stack_ref(id);
+            }
+            v
+        }));
+
+        Self {
+            header,
+            dsos,
+            frames,
+            threads,
+            stacks,
+            samples,
+            windows,
+            states,
+        }
+    }
+}
+
+/// Summed weights of all stacks sharing a key.
+#[derive(Default)]
+struct StackWeights {
+    weights: BTreeMap<String, u64>,
+    exclusive: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+impl StackWeights {
+    fn add(&mut self, file: &SpaaFile, stack: &Stack) {
+        for w in &stack.weights {
+            *self.weights.entry(w.metric.clone()).or_default() += w.value;
+        }
+        if let Some(ex) = &stack.exclusive {
+            let frame = frame_value(file, ex.frame).to_string();
+            let entry = self.exclusive.entry(frame).or_default();
+            for w in &ex.weights {
+                *entry.entry(w.metric.clone()).or_default() += w.value;
+            }
+        }
+    }
+}
+
+impl fmt::Display for StackWeights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            json!({"weights": self.weights, "exclusive": self.exclusive})
+        )
+    }
+}
+
+fn to_value<T: serde::Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+fn count(values: impl Iterator<Item = Value>) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for value in values {
+        *counts.entry(value.to_string()).or_default() += 1;
+    }
+    counts
+}
+
+fn dso_value(file: &SpaaFile, id: u64) -> Value {
+    match file.dsos.get(&id) {
+        Some(dso) => {
+            let mut v = to_value(dso);
+            if let Value::Object(map) = &mut v {
+                map.remove("id");
+            }
+            v
+        }
+        None => json!({ "missing_dso": id }),
+    }
+}
+
+fn frame_value(file: &SpaaFile, id: u64) -> Value {
+    match file.frames.get(&id) {
+        Some(frame) => {
+            let mut v = to_value(frame);
+            if let Value::Object(map) = &mut v {
+                map.remove("id");
+                map.insert("dso".to_string(), dso_value(file, frame.dso));
+            }
+            v
+        }
+        None => json!({ "missing_frame": id }),
+    }
+}
+
+fn weight_map(weights: &[Weight]) -> BTreeMap<&str, u64> {
+    let mut map = BTreeMap::new();
+    for w in weights {
+        *map.entry(w.metric.as_str()).or_default() += w.value;
+    }
+    map
+}
+
+/// Content key of a stack: root-to-leaf frames, type and context.
+fn stack_key(file: &SpaaFile, stack: &Stack) -> String {
+    let mut frames: Vec<Value> = stack
+        .frames
+        .iter()
+        .map(|id| frame_value(file, *id))
+        .collect();
+    if file.header.frame_order == FrameOrder::LeafToRoot {
+        frames.reverse();
+    }
+    json!({
+        "frames": frames,
+        "stack_type": stack.stack_type,
+        "context": stack.context,
+    })
+    .to_string()
+}
+
+/// Report keys present in only one map, or with different values.
+fn diff_maps<V: PartialEq + fmt::Display>(
+    section: &'static str,
+    expected: &BTreeMap<String, V>,
+    actual: &BTreeMap<String, V>,
+    diffs: &mut Vec<Difference>,
+) {
+    for (key, value) in expected {
+        match actual.get(key) {
+            None => diffs.push(Difference {
+                section,
+                message: format!("missing {key}"),
+            }),
+            Some(other) if other != value => diffs.push(Difference {
+                section,
+                message: format!("{key}: expected {value}, found {other}"),
+            }),
+            Some(_) => {}
+        }
+    }
+    for key in actual.keys().filter(|k| !expected.contains_key(*k)) {
+        diffs.push(Difference {
+            section,
+            message: format!("unexpected {key}"),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtrace::{DtraceConverter, InputFormat};
+    use std::io::Cursor;
+
+    const HEADER: &str = r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#;
+
+    fn parse(lines: &[&str]) -> SpaaFile {
+        SpaaFile::parse(Cursor::new(lines.join("\n"))).unwrap()
+    }
+
+    fn profile() -> SpaaFile {
+        parse(&[
+            HEADER,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"work","dso":1}"#,
+            r#"{"type":"stack","id":"0xa","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":70}]}"#,
+            r#"{"type":"stack","id":"0xb","frames":[1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":30}]}"#,
+            r#"{"type":"sample","timestamp":1.0,"pid":1,"tid":1,"cpu":0,"event":"cycles","stack_id":"0xa"}"#,
+        ])
+    }
+
+    #[test]
+    fn renumbered_and_reordered_files_are_equivalent() {
+        let renumbered = parse(&[
+            &HEADER.replace("leaf_to_root", "root_to_leaf"),
+            r#"{"type":"dso","id":7,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":40,"func":"work","dso":7}"#,
+            r#"{"type":"frame","id":41,"func":"main","dso":7}"#,
+            r#"{"type":"stack","id":"s1","frames":[41],"context":{"event":"cycles"},"weights":[{"metric":"period","value":30}]}"#,
+            r#"{"type":"stack","id":"s2","frames":[41,40],"context":{"event":"cycles"},"weights":[{"metric":"period","value":70}]}"#,
+            r#"{"type":"sample","timestamp":1.0,"pid":1,"tid":1,"cpu":0,"event":"cycles","stack_id":"s2"}"#,
+        ]);
+        assert_equivalent(&profile(), &renumbered);
+    }
+
+    #[test]
+    fn weight_changes_are_reported() {
+        let mut changed = profile();
+        changed.stacks.get_mut("0xb").unwrap().weights[0].value = 31;
+
+        let diffs = compare(&profile(), &changed);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].section, "stack");
+        assert!(diffs[0].message.contains("\"period\":30"));
+    }
+
+    #[test]
+    fn missing_stacks_are_reported() {
+        let mut changed = profile();
+        changed.stacks.remove("0xb");
+
+        let diffs = compare(&profile(), &changed);
+        assert_eq!(diffs.len(), 1);
+        assert!(diffs[0].message.starts_with("missing"));
+    }
+
+    #[test]
+    fn round_trip_preserves_profile() {
+        assert_round_trip(&profile());
+    }
+
+    #[test]
+    fn golden_corpus_blesses_then_checks() {
+        let dir = std::env::temp_dir().join(format!("spaa-corpus-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("basic.stacks"),
+            "\n  app`work+0x1\n  app`main+0x2\n  70\n",
+        )
+        .unwrap();
+        let convert = |path: &Path| -> Result<SpaaFile, Box<dyn std::error::Error>> {
+            let mut converter = DtraceConverter::new(InputFormat::AggregatedStack);
+            converter.parse(std::fs::File::open(path)?)?;
+            Ok(converter.to_spaa_file()?)
+        };
+
+        let missing = GoldenCorpus::new(&dir, "stacks")
+            .bless(false)
+            .run(convert)
+            .unwrap();
+        GoldenCorpus::new(&dir, "stacks")
+            .bless(true)
+            .run(convert)
+            .unwrap();
+        let after_bless = GoldenCorpus::new(&dir, "stacks")
+            .bless(false)
+            .run(convert)
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].contains("missing"));
+        assert!(after_bless.is_empty(), "{after_bless:?}");
+    }
+}
@@ -0,0 +1,359 @@
+//! CPU topology enrichment.
+//!
+//! Hardware-event profiles are far easier to interpret when each CPU number
+//! is known to belong to a particular socket, core and NUMA node. This
+//! module loads a topology description produced by `lscpu` and annotates
+//! stacks and raw samples that record a CPU with the following context keys:
+//!
+//! - `socket`: physical package ID
+//! - `core`: core ID
+//! - `numa_node`: NUMA node, when known
+//! - `smt_thread`: index of the hardware thread within its core
+//!
+//! Both the parsable (`lscpu -p=CPU,CORE,SOCKET,NODE`) and JSON
+//! (`lscpu -J -e=CPU,CORE,SOCKET,NODE`) outputs are accepted.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::topology::Topology;
+//! use spaa_parse::SpaaFile;
+//! use std::fs::File;
+//!
+//! let topology = Topology::parse(File::open("lscpu.txt").unwrap()).unwrap();
+//! let mut spaa = SpaaFile::parse(File::open("profile.spaa").unwrap()).unwrap();
+//! let annotated = topology.annotate(&mut spaa);
+//! println!("annotated {} records across {} sockets", annotated, topology.sockets());
+//! ```
+
+use serde::{Deserialize, Serialize};
+use spaa_parse::SpaaFile;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{BufRead, BufReader, Read};
+use thiserror::Error;
+
+/// Context key holding the socket ID.
+pub const SOCKET_KEY: &str = "socket";
+/// Context key holding the core ID.
+pub const CORE_KEY: &str = "core";
+/// Context key holding the NUMA node.
+pub const NUMA_NODE_KEY: &str = "numa_node";
+/// Context key holding the SMT thread index within a core.
+pub const SMT_THREAD_KEY: &str = "smt_thread";
+
+#[derive(Error, Debug)]
+pub enum TopologyError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("JSON parse error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+
+    #[error("topology describes no CPUs")]
+    Empty,
+}
+
+pub type Result<T> = std::result::Result<T, TopologyError>;
+
+/// Placement of a single logical CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CpuTopology {
+    pub cpu: u32,
+    pub socket: u32,
+    pub core: u32,
+    pub numa_node: Option<u32>,
+    /// Position among the hardware threads of `core`, ordered by CPU number.
+    pub smt_thread: u32,
+}
+
+/// Mapping from logical CPU number to its place in the machine.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Topology {
+    cpus: BTreeMap<u32, CpuTopology>,
+}
+
+impl Topology {
+    /// Build a topology from `(cpu, socket, core, numa_node)` tuples.
+    ///
+    /// SMT thread indices are derived by ordering the CPUs that share a
+    /// (socket, core) pair.
+    pub fn from_cpus(cpus: impl IntoIterator<Item = (u32, u32, u32, Option<u32>)>) -> Self {
+        let mut cpus: Vec<_> = cpus.into_iter().collect();
+        cpus.sort_unstable();
+
+        let mut siblings: HashMap<(u32, u32), u32> = HashMap::new();
+        let cpus = cpus
+            .into_iter()
+            .map(|(cpu, socket, core, numa_node)| {
+                let next = siblings.entry((socket, core)).or_default();
+                let smt_thread = *next;
+                *next += 1;
+                (
+                    cpu,
+                    CpuTopology {
+                        cpu,
+                        socket,
+                        core,
+                        numa_node,
+                        smt_thread,
+                    },
+                )
+            })
+            .collect();
+        Self { cpus }
+    }
+
+    /// Parse either `lscpu -p` or `lscpu -J -e` output.
+    pub fn parse<R: Read>(reader: R) -> Result<Self> {
+        let mut input = String::new();
+        BufReader::new(reader).read_to_string(&mut input)?;
+        if input.trim_start().starts_with('{') {
+            Self::from_json(input.as_bytes())
+        } else {
+            Self::from_lscpu(input.as_bytes())
+        }
+    }
+
+    /// Parse `lscpu -p` output.
+    ///
+    /// Column order is taken from the `# CPU,Core,Socket,...` comment line
+    /// when present, and defaults to `CPU,Core,Socket,Node` otherwise.
+    pub fn from_lscpu<R: Read>(reader: R) -> Result<Self> {
+        let mut columns: Vec<String> = ["cpu", "core", "socket", "node"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut cpus = Vec::new();
+
+        for (idx, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            let line_no = idx + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(comment) = line.strip_prefix('#') {
+                let comment = comment.trim();
+                if comment.to_ascii_lowercase().starts_with("cpu,") {
+                    columns = comment.split(',').map(|c| c.to_ascii_lowercase()).collect();
+                }
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split(',').collect();
+            let field = |name: &str| -> Result<Option<u32>> {
+                let Some(pos) = columns.iter().position(|c| c == name) else {
+                    return Ok(None);
+                };
+                match fields.get(pos).map(|f| f.trim()) {
+                    None | Some("") | Some("-") => Ok(None),
+                    Some(value) => value.parse().map(Some).map_err(|_| TopologyError::Parse {
+                        line: line_no,
+                        message: format!("invalid {} '{}'", name, value),
+                    }),
+                }
+            };
+            let required = |name: &str| -> Result<u32> {
+                field(name)?.ok_or_else(|| TopologyError::Parse {
+                    line: line_no,
+                    message: format!("missing {}", name),
+                })
+            };
+
+            cpus.push((
+                required("cpu")?,
+                field("socket")?.unwrap_or(0),
+                required("core")?,
+                field("node")?,
+            ));
+        }
+
+        Self::non_empty(Self::from_cpus(cpus))
+    }
+
+    /// Parse `lscpu -J -e=CPU,CORE,SOCKET,NODE` output.
+    ///
+    /// Older util-linux releases encode every value as a string; both forms
+    /// are accepted.
+    pub fn from_json<R: Read>(reader: R) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct LscpuJson {
+            cpus: Vec<HashMap<String, serde_json::Value>>,
+        }
+
+        fn number(value: Option<&serde_json::Value>) -> Option<u32> {
+            match value? {
+                serde_json::Value::Number(n) => n.as_u64().map(|n| n as u32),
+                serde_json::Value::String(s) => s.trim().parse().ok(),
+                _ => None,
+            }
+        }
+
+        let parsed: LscpuJson = serde_json::from_reader(reader)?;
+        let mut cpus = Vec::with_capacity(parsed.cpus.len());
+        for (idx, entry) in parsed.cpus.iter().enumerate() {
+            let missing = |name: &str| TopologyError::Parse {
+                line: idx + 1,
+                message: format!("cpu entry {} has no {}", idx, name),
+            };
+            cpus.push((
+                number(entry.get("cpu")).ok_or_else(|| missing("cpu"))?,
+                number(entry.get("socket")).unwrap_or(0),
+                number(entry.get("core")).ok_or_else(|| missing("core"))?,
+                number(entry.get("node")),
+            ));
+        }
+
+        Self::non_empty(Self::from_cpus(cpus))
+    }
+
+    fn non_empty(topology: Self) -> Result<Self> {
+        if topology.cpus.is_empty() {
+            Err(TopologyError::Empty)
+        } else {
+            Ok(topology)
+        }
+    }
+
+    /// Look up a logical CPU.
+    pub fn cpu(&self, cpu: u32) -> Option<&CpuTopology> {
+        self.cpus.get(&cpu)
+    }
+
+    /// Iterate over all CPUs in ascending order.
+    pub fn cpus(&self) -> impl Iterator<Item = &CpuTopology> {
+        self.cpus.values()
+    }
+
+    /// Number of distinct sockets.
+    pub fn sockets(&self) -> usize {
+        self.cpus
+            .values()
+            .map(|c| c.socket)
+            .collect::<BTreeSet<_>>()
+            .len()
+    }
+
+    /// Add topology context keys to every stack and sample whose CPU is
+    /// known. Returns the number of records annotated.
+    pub fn annotate(&self, file: &mut SpaaFile) -> usize {
+        let mut annotated = 0;
+        for stack in file.stacks.values_mut() {
+            if let Some(info) = stack.context.cpu.and_then(|cpu| self.cpu(cpu)) {
+                info.insert_into(&mut stack.context.extra);
+                annotated += 1;
+            }
+        }
+        for sample in &mut file.samples {
+            if let Some(info) = self.cpu(sample.cpu) {
+                info.insert_into(&mut sample.context);
+                annotated += 1;
+            }
+        }
+        annotated
+    }
+}
+
+impl CpuTopology {
+    fn insert_into(&self, context: &mut HashMap<String, serde_json::Value>) {
+        context.insert(SOCKET_KEY.to_string(), self.socket.into());
+        context.insert(CORE_KEY.to_string(), self.core.into());
+        if let Some(node) = self.numa_node {
+            context.insert(NUMA_NODE_KEY.to_string(), node.into());
+        }
+        context.insert(SMT_THREAD_KEY.to_string(), self.smt_thread.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Two sockets, two cores each, two hardware threads per core, with
+    // siblings numbered the way Linux usually enumerates them.
+    const LSCPU_P: &str = "\
+# The following is the parsable format, which can be fed to other
+# programs. Each different item in every column has an unique ID
+# starting usually from zero.
+# CPU,Core,Socket,Node,,L1d,L1i,L2,L3
+0,0,0,0,,0,0,0,0
+1,1,0,0,,1,1,1,0
+2,2,1,1,,2,2,2,1
+3,3,1,1,,3,3,3,1
+4,0,0,0,,0,0,0,0
+5,1,0,0,,1,1,1,0
+6,2,1,1,,2,2,2,1
+7,3,1,1,,3,3,3,1
+";
+
+    #[test]
+    fn parse_lscpu_parsable() {
+        let topology = Topology::parse(Cursor::new(LSCPU_P)).unwrap();
+
+        assert_eq!(topology.cpus().count(), 8);
+        assert_eq!(topology.sockets(), 2);
+        let cpu6 = topology.cpu(6).unwrap();
+        assert_eq!(cpu6.socket, 1);
+        assert_eq!(cpu6.core, 2);
+        assert_eq!(cpu6.numa_node, Some(1));
+        assert_eq!(cpu6.smt_thread, 1);
+        assert_eq!(topology.cpu(2).unwrap().smt_thread, 0);
+    }
+
+    #[test]
+    fn parse_lscpu_json_with_string_values() {
+        let json = r#"{"cpus": [
+            {"cpu": "0", "core": "0", "socket": "0", "node": "0"},
+            {"cpu": 1, "core": 0, "socket": 0, "node": null}
+        ]}"#;
+        let topology = Topology::parse(Cursor::new(json)).unwrap();
+
+        assert_eq!(topology.cpu(0).unwrap().numa_node, Some(0));
+        assert_eq!(topology.cpu(1).unwrap().numa_node, None);
+        assert_eq!(topology.cpu(1).unwrap().smt_thread, 1);
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() {
+        assert!(matches!(
+            Topology::from_lscpu(Cursor::new("# CPU,Core\n0,x\n")),
+            Err(TopologyError::Parse { line: 2, .. })
+        ));
+        assert!(matches!(
+            Topology::from_lscpu(Cursor::new("# nothing here\n")),
+            Err(TopologyError::Empty)
+        ));
+    }
+
+    #[test]
+    fn annotates_stacks_and_samples() {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[1],"context":{"event":"cycles","cpu":6},"weights":[{"metric":"period","value":100}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":100}]}"#,
+            r#"{"type":"sample","timestamp":1.0,"pid":1,"tid":1,"cpu":3,"event":"cycles","stack_id":"0x2"}"#,
+        ]
+        .join("\n");
+        let mut file = SpaaFile::parse(Cursor::new(data)).unwrap();
+        let topology = Topology::parse(Cursor::new(LSCPU_P)).unwrap();
+
+        assert_eq!(topology.annotate(&mut file), 2);
+        let extra = &file.stacks["0x1"].context.extra;
+        assert_eq!(extra[SOCKET_KEY], 1);
+        assert_eq!(extra[SMT_THREAD_KEY], 1);
+        assert!(file.stacks["0x2"].context.extra.is_empty());
+        assert_eq!(file.samples[0].context[CORE_KEY], 3);
+
+        // Annotations survive a write/parse round trip.
+        let mut buf = Vec::new();
+        file.write(&mut buf).unwrap();
+        let reparsed = SpaaFile::parse(Cursor::new(buf)).unwrap();
+        assert_eq!(reparsed.stacks["0x1"].context.extra[NUMA_NODE_KEY], 1);
+    }
+}
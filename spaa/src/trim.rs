@@ -0,0 +1,414 @@
+//! Trim a SPAA file to fit an LLM context budget.
+//!
+//! The lowest-weight stacks are dropped until the written file fits, along
+//! with any frames, DSOs and threads only they referenced. The weight of
+//! dropped stacks is folded into one `[other]` stack per event, so event
+//! totals are unchanged and percentages computed from the trimmed file stay
+//! correct. Stacks are ranked by their share of their own event's total, so
+//! a rare event keeps its hottest stacks next to a common one.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::trim::{Budget, trim};
+//! use spaa_parse::SpaaFile;
+//!
+//! let spaa = SpaaFile::open("profile.spaa").unwrap();
+//! let trimmed = trim(&spaa, "50000-tokens".parse::<Budget>().unwrap());
+//! eprintln!("dropped {} stacks", trimmed.dropped_stacks);
+//! ```
+
+use spaa_parse::{
+    BYTES_PER_TOKEN, Dso, ExclusiveWeights, Frame, FrameKind, SpaaFile, Stack, StackContext,
+    StackType, ThreadState, Weight, Window, WindowStackWeight,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// Function and DSO name of the frame standing in for dropped stacks.
+pub const OTHER_NAME: &str = "[other]";
+
+/// Size limit for a trimmed file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Budget {
+    /// Estimated LLM tokens (see [`SpaaFile::estimate_tokens`]).
+    Tokens(u64),
+    /// Bytes of NDJSON.
+    Bytes(u64),
+}
+
+impl Budget {
+    /// The budget in bytes.
+    pub fn bytes(self) -> u64 {
+        match self {
+            Budget::Tokens(n) => n.saturating_mul(BYTES_PER_TOKEN),
+            Budget::Bytes(n) => n,
+        }
+    }
+}
+
+impl fmt::Display for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Budget::Tokens(n) => write!(f, "{n}-tokens"),
+            Budget::Bytes(n) => write!(f, "{n}-bytes"),
+        }
+    }
+}
+
+/// Error returned when parsing an invalid [`Budget`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBudgetError(String);
+
+impl fmt::Display for ParseBudgetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid budget '{}' (expected N-tokens or N-bytes, e.g. 50000-tokens or 200k-bytes)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseBudgetError {}
+
+impl FromStr for Budget {
+    type Err = ParseBudgetError;
+
+    /// Parse `N`, `N-tokens` or `N-bytes`, where `N` may end in `k` or `m`.
+    /// A bare number is a token count.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseBudgetError(s.to_string());
+        let (number, unit): (&str, fn(u64) -> Budget) = if let Some(n) = s.strip_suffix("-tokens") {
+            (n, Budget::Tokens)
+        } else if let Some(n) = s.strip_suffix("-bytes") {
+            (n, Budget::Bytes)
+        } else {
+            (s, Budget::Tokens)
+        };
+        let (digits, scale) = match number.as_bytes().last() {
+            Some(b'k' | b'K') => (&number[..number.len() - 1], 1_000),
+            Some(b'm' | b'M') => (&number[..number.len() - 1], 1_000_000),
+            _ => (number, 1),
+        };
+        let value: u64 = digits.parse().map_err(|_| err())?;
+        Ok(unit(value.checked_mul(scale).ok_or_else(err)?))
+    }
+}
+
+/// Result of [`trim`].
+#[derive(Debug, Clone)]
+pub struct Trimmed {
+    pub file: SpaaFile,
+    /// Stacks folded into `[other]`.
+    pub dropped_stacks: usize,
+    /// Raw sample records removed. Their weight is already counted in the
+    /// stacks, so samples are the first thing to go.
+    pub dropped_samples: usize,
+    /// Whether the trimmed file fits the budget. False only when the
+    /// header, windows, thread states and `[other]` stacks alone exceed it.
+    pub fits: bool,
+}
+
+/// Trim `file` to fit `budget`, keeping as many of the heaviest stacks as
+/// possible. Files that already fit are returned unchanged.
+pub fn trim(file: &SpaaFile, budget: Budget) -> Trimmed {
+    let limit = budget.bytes();
+    if file.estimate_bytes_per_section().total() <= limit {
+        return Trimmed {
+            file: file.clone(),
+            dropped_stacks: 0,
+            dropped_samples: 0,
+            fits: true,
+        };
+    }
+
+    let ranked = rank(file);
+    let fits = |k: usize| {
+        keep_top(file, &ranked[..k])
+            .estimate_bytes_per_section()
+            .total()
+            <= limit
+    };
+
+    // Largest number of stacks that fits; size grows with every stack kept.
+    let (mut lo, mut hi) = (0, ranked.len());
+    while lo < hi {
+        let mid = lo + (hi - lo).div_ceil(2);
+        if fits(mid) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+
+    let trimmed = keep_top(file, &ranked[..lo]);
+    Trimmed {
+        fits: trimmed.estimate_bytes_per_section().total() <= limit,
+        file: trimmed,
+        dropped_stacks: ranked.len() - lo,
+        dropped_samples: file.samples.len(),
+    }
+}
+
+/// Stack IDs, heaviest first by share of their event's primary metric.
+fn rank(file: &SpaaFile) -> Vec<&str> {
+    let primary = |stack: &Stack| {
+        let metric = file.primary_metric_for_event(&stack.context.event);
+        stack
+            .weights
+            .iter()
+            .find(|w| Some(w.metric.as_str()) == metric)
+            .map_or(0, |w| w.value)
+    };
+    let mut totals: HashMap<&str, u64> = HashMap::new();
+    for stack in file.stacks.values() {
+        *totals.entry(&stack.context.event).or_default() += primary(stack);
+    }
+
+    let mut ranked: Vec<(f64, &str)> = file
+        .stacks
+        .values()
+        .map(|stack| {
+            let total = totals[stack.context.event.as_str()].max(1);
+            (primary(stack) as f64 / total as f64, stack.id.as_str())
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(b.1)));
+    ranked.into_iter().map(|(_, id)| id).collect()
+}
+
+/// `file` with only the `kept` stacks, the rest folded into `[other]`.
+fn keep_top(file: &SpaaFile, kept: &[&str]) -> SpaaFile {
+    let ids: HashSet<&str> = kept.iter().copied().collect();
+    let mut part = file.filter(|s| ids.contains(s.id.as_str()));
+    part.samples.clear();
+
+    // One `[other]` stack per event, in event order.
+    let mut others: BTreeMap<&str, (Vec<Weight>, Vec<Weight>)> = BTreeMap::new();
+    for stack in file.stacks.values() {
+        if ids.contains(stack.id.as_str()) {
+            continue;
+        }
+        let (weights, exclusive) = others.entry(&stack.context.event).or_default();
+        add_weights(weights, &stack.weights);
+        if let Some(ex) = &stack.exclusive {
+            add_weights(exclusive, &ex.weights);
+        }
+    }
+    if !others.is_empty() {
+        let frame = add_other_frame(file, &mut part);
+        for (event, (weights, exclusive)) in others {
+            let id = other_stack_id(event);
+            part.stacks.insert(
+                id.clone(),
+                Stack {
+                    id,
+                    frames: vec![frame],
+                    stack_type: StackType::Unified,
+                    context: other_context(event),
+                    weights,
+                    exclusive: (!exclusive.is_empty()).then_some(ExclusiveWeights {
+                        frame,
+                        weights: exclusive,
+                    }),
+                    related_stacks: None,
+                },
+            );
+        }
+    }
+
+    let remap = |id: &str| -> String {
+        if ids.contains(id) {
+            id.to_string()
+        } else {
+            file.stacks
+                .get(id)
+                .map_or_else(|| id.to_string(), |s| other_stack_id(&s.context.event))
+        }
+    };
+    part.windows = file
+        .windows
+        .iter()
+        .map(|window| {
+            let mut by_stack: Vec<WindowStackWeight> = Vec::new();
+            for entry in &window.by_stack {
+                let stack_id = remap(&entry.stack_id);
+                match by_stack.iter_mut().find(|e| e.stack_id == stack_id) {
+                    Some(existing) => add_weights(&mut existing.weights, &entry.weights),
+                    None => by_stack.push(WindowStackWeight {
+                        stack_id,
+                        weights: entry.weights.clone(),
+                    }),
+                }
+            }
+            Window {
+                by_stack,
+                ..window.clone()
+            }
+        })
+        .collect();
+    part.states = file
+        .states
+        .iter()
+        .map(|state| ThreadState {
+            stack_id: state.stack_id.as_deref().map(remap),
+            ..state.clone()
+        })
+        .collect();
+
+    let events: HashSet<&str> = part
+        .stacks
+        .values()
+        .map(|s| s.context.event.as_str())
+        .collect();
+    part.header = file.header.clone();
+    part.header
+        .events
+        .retain(|e| events.contains(e.name.as_str()));
+    part
+}
+
+/// Add the `[other]` frame and DSO to `part`, with IDs unused in `file`.
+fn add_other_frame(file: &SpaaFile, part: &mut SpaaFile) -> u64 {
+    let dso = file.dsos.keys().max().map_or(1, |max| max + 1);
+    let frame = file.frames.keys().max().map_or(1, |max| max + 1);
+    part.dsos.insert(
+        dso,
+        Dso {
+            id: dso,
+            name: OTHER_NAME.to_string(),
+            build_id: None,
+            is_kernel: false,
+        },
+    );
+    part.frames.insert(
+        frame,
+        Frame {
+            id: frame,
+            func: OTHER_NAME.to_string(),
+            dso,
+            func_resolved: true,
+            ip: None,
+            symoff: None,
+            srcline: None,
+            srcline_resolved: false,
+            inlined: false,
+            inline_depth: None,
+            kind: FrameKind::Unknown,
+        },
+    );
+    frame
+}
+
+fn other_stack_id(event: &str) -> String {
+    format!("{OTHER_NAME}:{event}")
+}
+
+fn other_context(event: &str) -> StackContext {
+    StackContext {
+        event: event.to_string(),
+        pid: None,
+        tid: None,
+        cpu: None,
+        comm: None,
+        probe: None,
+        execname: None,
+        uid: None,
+        zonename: None,
+        cgroup: None,
+        container_id: None,
+        k8s_pod: None,
+        trace_fields: None,
+        extra: HashMap::new(),
+    }
+}
+
+fn add_weights(into: &mut Vec<Weight>, from: &[Weight]) {
+    for weight in from {
+        match into.iter_mut().find(|w| w.metric == weight.metric) {
+            Some(existing) => existing.value += weight.value,
+            None => into.push(weight.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spaa_parse::synth::{SynthConfig, generate};
+
+    fn total(file: &SpaaFile) -> u64 {
+        file.stacks.values().map(|s| s.weights[0].value).sum()
+    }
+
+    #[test]
+    fn budget_parses_units_and_suffixes() {
+        assert_eq!("50000-tokens".parse(), Ok(Budget::Tokens(50_000)));
+        assert_eq!("200k-bytes".parse(), Ok(Budget::Bytes(200_000)));
+        assert_eq!("2m".parse(), Ok(Budget::Tokens(2_000_000)));
+        assert!("lots-tokens".parse::<Budget>().is_err());
+    }
+
+    #[test]
+    fn file_within_budget_is_unchanged() {
+        let file = generate(&SynthConfig {
+            stacks: 10,
+            ..Default::default()
+        });
+        let trimmed = trim(&file, Budget::Tokens(1_000_000));
+
+        assert_eq!(trimmed.dropped_stacks, 0);
+        assert_eq!(trimmed.file, file);
+    }
+
+    #[test]
+    fn trimmed_file_fits_and_keeps_totals() {
+        let file = generate(&SynthConfig {
+            stacks: 500,
+            samples_per_stack: 1,
+            ..Default::default()
+        });
+        let budget = Budget::Tokens(5_000);
+        let trimmed = trim(&file, budget);
+
+        assert!(trimmed.fits);
+        assert!(trimmed.file.estimate_tokens() <= 5_000);
+        assert!(trimmed.dropped_stacks > 0);
+        assert!(trimmed.file.samples.is_empty());
+        assert_eq!(total(&trimmed.file), total(&file));
+        assert!(trimmed.file.stacks.contains_key("[other]:cycles"));
+    }
+
+    #[test]
+    fn trimmed_file_keeps_heaviest_stacks() {
+        let file = generate(&SynthConfig {
+            stacks: 200,
+            ..Default::default()
+        });
+        let trimmed = trim(&file, Budget::Tokens(2_000));
+
+        let heaviest = file
+            .stacks
+            .values()
+            .max_by_key(|s| s.weights[0].value)
+            .unwrap();
+        assert!(trimmed.file.stacks.contains_key(&heaviest.id));
+    }
+
+    #[test]
+    fn trimmed_file_is_valid() {
+        let file = generate(&SynthConfig {
+            stacks: 200,
+            ..Default::default()
+        });
+        let mut ndjson = Vec::new();
+        trim(&file, Budget::Tokens(2_000))
+            .file
+            .write(&mut ndjson)
+            .unwrap();
+
+        assert!(SpaaFile::parse_slice(&ndjson).is_ok());
+    }
+}
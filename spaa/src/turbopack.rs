@@ -32,13 +32,13 @@
 //! ```
 
 use spaa_parse::{
-    AllocationTracking, Dso, EventDef, EventKind, ExclusiveWeights, Frame, FrameKind, FrameOrder,
-    Header, Sampling, SamplingMode, SourceInfo, SpaaWriter, Stack, StackContext, StackIdMode,
-    StackType, Thread, TimeRange, Weight,
+    AllocationTracking, ClockInfo, ClockSource, Dso, EventDef, EventKind, ExclusiveWeights, Frame,
+    FrameKind, FrameOrder, Header, Sampling, SamplingMode, SourceInfo, SpaaFile, Stack,
+    StackContext, StackIdMode, StackType, Thread, TimeRange, Weight,
 };
 use std::borrow::Cow;
-use std::collections::{HashMap, HashSet};
-use std::io::{self, BufReader, Read, Write};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::path::Path;
 use thiserror::Error;
 
@@ -144,16 +144,22 @@ pub enum ConvertError {
 
 // ── Internal span model ────────────────────────────────────────────────────
 
+/// String args attached to a span: `(key, value)` pairs.
+type SpanArgs = Vec<(String, String)>;
+
+/// Numeric metrics attached to a span: `(metric, value)` pairs.
+type SpanMetrics = Vec<(String, u64)>;
+
 struct Span {
     name: String,
     target: String,
     parent: Option<u64>,
     /// String args from Start `values` — used as identity qualifiers.
     /// e.g., [("name", "src/app/page.tsx"), ("lookup_path", "...")]
-    args: Vec<(String, String)>,
+    args: SpanArgs,
     /// Numeric metrics from Record events — aggregated as weights.
     /// e.g., [("module_count", 42), ("total_size", 1024)]
-    metrics: Vec<(String, u64)>,
+    metrics: SpanMetrics,
     self_time_us: u64,
     self_allocations: u64,
     self_allocation_count: u64,
@@ -243,11 +249,10 @@ impl TurbopackConverter {
         let reader = BufReader::with_capacity((1 << 17) + 7, file);
         let path_str = path.as_ref().to_string_lossy();
 
-        if path_str.ends_with(".zst") || magic == [0x28, 0xb5, 0x2f, 0xfd] {
-            let decoder = zstd::Decoder::with_buffer(reader)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if path_str.ends_with(".zst") || magic == ZSTD_MAGIC {
+            let decoder = zstd::Decoder::with_buffer(reader).map_err(io::Error::other)?;
             self.parse_reader(decoder)
-        } else if path_str.ends_with(".gz") || magic[..2] == [0x1f, 0x8b] {
+        } else if path_str.ends_with(".gz") || magic[..2] == GZIP_MAGIC {
             let decoder = flate2::bufread::GzDecoder::new(reader);
             self.parse_reader(decoder)
         } else {
@@ -285,10 +290,7 @@ impl TurbopackConverter {
                 Err(e) => {
                     if self.row_count > 0 {
                         // Partial read is OK — trace may still be in progress
-                        eprintln!(
-                            "Warning: parse error after {} events: {e}",
-                            self.row_count
-                        );
+                        tracing::warn!("parse error after {} events: {e}", self.row_count);
                         break;
                     }
                     return Err(e.into());
@@ -314,9 +316,7 @@ impl TurbopackConverter {
     }
 
     /// Classify trace values into string args and numeric metrics.
-    fn classify_values(
-        values: &[(Cow<'_, str>, TraceValue<'_>)],
-    ) -> (Vec<(String, String)>, Vec<(String, u64)>) {
+    fn classify_values(values: &[(Cow<'_, str>, TraceValue<'_>)]) -> (SpanArgs, SpanMetrics) {
         let mut args = Vec::new();
         let mut metrics = Vec::new();
         for (k, v) in values {
@@ -368,10 +368,10 @@ impl TurbopackConverter {
                     children: Vec::new(),
                 };
                 self.spans.insert(id, span);
-                if let Some(pid) = parent {
-                    if let Some(parent_span) = self.spans.get_mut(&pid) {
-                        parent_span.children.push(id);
-                    }
+                if let Some(pid) = parent
+                    && let Some(parent_span) = self.spans.get_mut(&pid)
+                {
+                    parent_span.children.push(id);
                 }
             }
             TraceRow::End { ts, .. } => {
@@ -381,16 +381,13 @@ impl TurbopackConverter {
                 self.update_ts(ts);
                 self.thread_ids.insert(thread_id);
                 let stack = self.thread_stacks.entry(thread_id).or_default();
-                if let Some(&parent_id) = stack.last() {
-                    if let Some(parent_start) =
+                if let Some(&parent_id) = stack.last()
+                    && let Some(parent_start) =
                         self.self_time_started.remove(&(parent_id, thread_id))
-                    {
-                        if ts > parent_start {
-                            if let Some(parent_span) = self.spans.get_mut(&parent_id) {
-                                parent_span.self_time_us += ts - parent_start;
-                            }
-                        }
-                    }
+                    && ts > parent_start
+                    && let Some(parent_span) = self.spans.get_mut(&parent_id)
+                {
+                    parent_span.self_time_us += ts - parent_start;
                 }
                 stack.push(id);
                 self.self_time_started.insert((id, thread_id), ts);
@@ -406,19 +403,14 @@ impl TurbopackConverter {
                         self.self_time_started.insert((parent_id, thread_id), ts);
                     }
                 }
-                if let Some(start) = self.self_time_started.remove(&(id, thread_id)) {
-                    if ts > start {
-                        if let Some(span) = self.spans.get_mut(&id) {
-                            span.self_time_us += ts - start;
-                        }
-                    }
+                if let Some(start) = self.self_time_started.remove(&(id, thread_id))
+                    && ts > start
+                    && let Some(span) = self.spans.get_mut(&id)
+                {
+                    span.self_time_us += ts - start;
                 }
             }
-            TraceRow::Event {
-                ts,
-                parent,
-                values,
-            } => {
+            TraceRow::Event { ts, parent, values } => {
                 self.update_ts(ts);
                 let mut name = String::from("event");
                 let mut duration = 0u64;
@@ -455,10 +447,10 @@ impl TurbopackConverter {
                     children: Vec::new(),
                 };
                 self.spans.insert(synthetic_id, span);
-                if let Some(pid) = parent {
-                    if let Some(parent_span) = self.spans.get_mut(&pid) {
-                        parent_span.children.push(synthetic_id);
-                    }
+                if let Some(pid) = parent
+                    && let Some(parent_span) = self.spans.get_mut(&pid)
+                {
+                    parent_span.children.push(synthetic_id);
                 }
             }
             TraceRow::Record { id, values } => {
@@ -478,13 +470,13 @@ impl TurbopackConverter {
             } => {
                 self.thread_ids.insert(thread_id);
                 let stack = self.thread_stacks.entry(thread_id).or_default();
-                if let Some(&id) = stack.last() {
-                    if let Some(span) = self.spans.get_mut(&id) {
-                        span.self_allocations += allocations;
-                        span.self_allocation_count += allocation_count;
-                        span.self_deallocations += deallocations;
-                        span.self_deallocation_count += deallocation_count;
-                    }
+                if let Some(&id) = stack.last()
+                    && let Some(span) = self.spans.get_mut(&id)
+                {
+                    span.self_allocations += allocations;
+                    span.self_allocation_count += allocation_count;
+                    span.self_deallocations += deallocations;
+                    span.self_deallocation_count += deallocation_count;
                 }
             }
             TraceRow::AllocationCounters {
@@ -500,21 +492,20 @@ impl TurbopackConverter {
                 let diff_alloc = allocations.saturating_sub(prev.allocations);
                 let diff_alloc_count = allocation_count.saturating_sub(prev.allocation_count);
                 let diff_dealloc = deallocations.saturating_sub(prev.deallocations);
-                let diff_dealloc_count =
-                    deallocation_count.saturating_sub(prev.deallocation_count);
+                let diff_dealloc_count = deallocation_count.saturating_sub(prev.deallocation_count);
                 prev.allocations = allocations;
                 prev.allocation_count = allocation_count;
                 prev.deallocations = deallocations;
                 prev.deallocation_count = deallocation_count;
 
                 let stack = self.thread_stacks.entry(thread_id).or_default();
-                if let Some(&id) = stack.last() {
-                    if let Some(span) = self.spans.get_mut(&id) {
-                        span.self_allocations += diff_alloc;
-                        span.self_allocation_count += diff_alloc_count;
-                        span.self_deallocations += diff_dealloc;
-                        span.self_deallocation_count += diff_dealloc_count;
-                    }
+                if let Some(&id) = stack.last()
+                    && let Some(span) = self.spans.get_mut(&id)
+                {
+                    span.self_allocations += diff_alloc;
+                    span.self_allocation_count += diff_alloc_count;
+                    span.self_deallocations += diff_dealloc;
+                    span.self_deallocation_count += diff_dealloc_count;
                 }
             }
         }
@@ -539,12 +530,17 @@ impl TurbopackConverter {
 
     /// Write the parsed trace as SPAA format.
     pub fn write_spaa<W: Write>(&self, writer: W) -> Result<(), ConvertError> {
+        self.to_spaa_file()?.write(writer)?;
+        Ok(())
+    }
+
+    /// Build the SPAA profile in memory.
+    pub fn to_spaa_file(&self) -> Result<SpaaFile, ConvertError> {
         if self.spans.is_empty() {
             return Err(ConvertError::NoSpans);
         }
 
         let has_allocs = self.has_allocations();
-        let mut w = SpaaWriter::new(writer);
 
         // ── Header ─────────────────────────────────────────────────────
         let mut events = vec![EventDef {
@@ -557,6 +553,8 @@ impl TurbopackConverter {
                 frequency_hz: None,
             },
             allocation_tracking: None,
+            description: None,
+            unit: None,
         }];
         if has_allocs {
             events.push(EventDef {
@@ -572,6 +570,8 @@ impl TurbopackConverter {
                     tracks_frees: true,
                     has_timestamps: false,
                 }),
+                description: None,
+                unit: None,
             });
         }
 
@@ -590,10 +590,13 @@ impl TurbopackConverter {
                 tool: "turbopack_to_spaa".to_string(),
                 command: Some("turbopack_to_spaa <trace-file>".to_string()),
                 tool_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                data_loss: None,
+                build: BTreeMap::new(),
             }),
             stack_id_mode: StackIdMode::ContentAddressable,
+            clock: Some(ClockInfo::new(ClockSource::Monotonic)),
+            approximation: None,
         };
-        w.write_header(&header)?;
 
         // ── Build frame + DSO dictionaries ─────────────────────────────
         //
@@ -609,7 +612,11 @@ impl TurbopackConverter {
         // We need per-span frame IDs, so also build a span->frame lookup.
         let mut span_frame_ids: HashMap<u64, u64> = HashMap::new();
 
-        for (&span_id, span) in &self.spans {
+        // Spans are visited in ID order so IDs are reproducible.
+        let mut span_ids: Vec<u64> = self.spans.keys().copied().collect();
+        span_ids.sort_unstable();
+        for &span_id in &span_ids {
+            let span = &self.spans[&span_id];
             if !dso_map.contains_key(&span.target) {
                 dso_map.insert(span.target.clone(), next_dso_id);
                 next_dso_id += 1;
@@ -624,47 +631,54 @@ impl TurbopackConverter {
             span_frame_ids.insert(span_id, frame_id);
         }
 
-        // ── Write DSOs ─────────────────────────────────────────────────
-        let mut dsos: Vec<_> = dso_map.iter().collect();
-        dsos.sort_by_key(|(_, id)| **id);
-        for (name, id) in &dsos {
-            w.write_dso(&Dso {
-                id: **id,
-                name: name.to_string(),
-                build_id: None,
-                is_kernel: false,
-            })?;
-        }
-
-        // ── Write frames ───────────────────────────────────────────────
-        let mut frames: Vec<_> = frame_map.iter().collect();
-        frames.sort_by_key(|(_, id)| **id);
-        for ((name, target), id) in &frames {
-            w.write_frame(&Frame {
-                id: **id,
-                func: name.clone(),
-                dso: dso_map[target],
-                func_resolved: true,
-                ip: None,
-                symoff: None,
-                srcline: None,
-                srcline_resolved: true,
-                inlined: false,
-                inline_depth: None,
-                kind: FrameKind::User,
-            })?;
+        // ── DSOs ───────────────────────────────────────────────────────
+        let dsos = dso_map
+            .iter()
+            .map(|(name, &id)| {
+                let dso = Dso {
+                    id,
+                    name: name.clone(),
+                    build_id: None,
+                    is_kernel: false,
+                };
+                (id, dso)
+            })
+            .collect();
+
+        // ── Frames ─────────────────────────────────────────────────────
+        let mut frames = HashMap::new();
+        for ((name, target), &id) in &frame_map {
+            frames.insert(
+                id,
+                Frame {
+                    id,
+                    func: name.clone(),
+                    dso: dso_map[target],
+                    func_resolved: true,
+                    ip: None,
+                    symoff: None,
+                    srcline: None,
+                    srcline_resolved: true,
+                    inlined: false,
+                    inline_depth: None,
+                    kind: FrameKind::User,
+                },
+            );
         }
 
-        // ── Write threads ──────────────────────────────────────────────
-        let mut tids: Vec<_> = self.thread_ids.iter().copied().collect();
-        tids.sort();
-        for tid in &tids {
-            w.write_thread(&Thread {
-                pid: 0,
-                tid: *tid,
-                comm: Some(format!("thread-{tid}")),
-            })?;
-        }
+        // ── Threads ────────────────────────────────────────────────────
+        let threads = self
+            .thread_ids
+            .iter()
+            .map(|&tid| {
+                let thread = Thread {
+                    pid: 0,
+                    tid,
+                    comm: Some(format!("thread-{tid}")),
+                };
+                (tid, thread)
+            })
+            .collect();
 
         // ── Build and aggregate stacks ─────────────────────────────────
         struct StackAgg {
@@ -711,11 +725,8 @@ impl TurbopackConverter {
             }
         }
 
-        // Sort by self_time descending
-        let mut stacks: Vec<_> = stack_agg.into_iter().collect();
-        stacks.sort_by(|a, b| b.1.self_time_us.cmp(&a.1.self_time_us));
-
-        for (call_stack, agg) in &stacks {
+        let mut stack_records = HashMap::new();
+        for (call_stack, agg) in &stack_agg {
             let stack_id = hash_stack(call_stack);
             let leaf_frame = call_stack[0];
 
@@ -785,42 +796,55 @@ impl TurbopackConverter {
                 "turbopack-allocations"
             };
 
-            w.write_stack(&Stack {
-                id: stack_id,
-                frames: call_stack.clone(),
-                stack_type: StackType::Unified,
-                context: StackContext {
-                    event: event.to_string(),
-                    pid: None,
-                    tid: None,
-                    cpu: None,
-                    comm: None,
-                    probe: None,
-                    execname: None,
-                    uid: None,
-                    zonename: None,
-                    trace_fields: None,
-                    extra: HashMap::new(),
+            stack_records.insert(
+                stack_id.clone(),
+                Stack {
+                    id: stack_id,
+                    frames: call_stack.clone(),
+                    stack_type: StackType::Unified,
+                    context: StackContext {
+                        event: event.to_string(),
+                        pid: None,
+                        tid: None,
+                        cpu: None,
+                        comm: None,
+                        probe: None,
+                        execname: None,
+                        uid: None,
+                        zonename: None,
+                        cgroup: None,
+                        container_id: None,
+                        k8s_pod: None,
+                        trace_fields: None,
+                        extra: HashMap::new(),
+                    },
+                    weights: weights.clone(),
+                    exclusive: Some(ExclusiveWeights {
+                        frame: leaf_frame,
+                        weights,
+                    }),
+                    related_stacks: None,
                 },
-                weights: weights.clone(),
-                exclusive: Some(ExclusiveWeights {
-                    frame: leaf_frame,
-                    weights,
-                }),
-                related_stacks: None,
-            })?;
+            );
         }
 
-        Ok(())
+        Ok(SpaaFile {
+            header,
+            system: None,
+            dsos,
+            frames,
+            threads,
+            stacks: stack_records,
+            samples: Vec::new(),
+            windows: Vec::new(),
+            states: Vec::new(),
+            notes: Vec::new(),
+        })
     }
 
     /// Walk the parent chain from a span to the root, collecting frame IDs.
     /// Returns frames in leaf-to-root order.
-    fn build_call_stack(
-        &self,
-        span_id: u64,
-        span_frame_ids: &HashMap<u64, u64>,
-    ) -> Vec<u64> {
+    fn build_call_stack(&self, span_id: u64, span_frame_ids: &HashMap<u64, u64>) -> Vec<u64> {
         let mut stack = Vec::new();
         let mut current = Some(span_id);
         let mut visited = HashSet::new();
@@ -847,6 +871,51 @@ impl Default for TurbopackConverter {
     }
 }
 
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+impl crate::convert::Converter for TurbopackConverter {
+    fn name(&self) -> &str {
+        "turbopack"
+    }
+
+    /// Traces start with `TRACEv0`; compressed ones cannot be told apart
+    /// from any other compressed file without decompressing them.
+    fn detect(&self, data: &[u8]) -> Option<crate::convert::Confidence> {
+        use crate::convert::Confidence;
+        if data.starts_with(b"TRACEv0") {
+            Some(Confidence::High)
+        } else if data.starts_with(&ZSTD_MAGIC) || data.starts_with(&GZIP_MAGIC) {
+            Some(Confidence::Low)
+        } else {
+            None
+        }
+    }
+
+    /// Like [`parse_file`](TurbopackConverter::parse_file), decompresses
+    /// zstd and gzip input, recognized by its magic bytes.
+    fn parse(
+        &mut self,
+        reader: &mut dyn Read,
+    ) -> std::result::Result<(), crate::convert::ConvertError> {
+        let mut reader = BufReader::with_capacity((1 << 17) + 7, reader);
+        let magic = reader.fill_buf()?;
+        if magic.starts_with(&ZSTD_MAGIC) {
+            let decoder = zstd::Decoder::with_buffer(reader).map_err(io::Error::other)?;
+            self.parse_reader(decoder)?;
+        } else if magic.starts_with(&GZIP_MAGIC) {
+            self.parse_reader(flate2::bufread::GzDecoder::new(reader))?;
+        } else {
+            self.parse_reader(reader)?;
+        }
+        Ok(())
+    }
+
+    fn to_spaa_file(&self) -> std::result::Result<SpaaFile, crate::convert::ConvertError> {
+        Ok(TurbopackConverter::to_spaa_file(self)?)
+    }
+}
+
 /// FNV-1a hash of frame IDs for content-addressable stack IDs.
 fn hash_stack(frames: &[u64]) -> String {
     let mut h: u64 = 0xcbf29ce484222325;
@@ -0,0 +1,537 @@
+//! Interactive profile viewer.
+//!
+//! [`Viewer`] holds the navigation state of a call-tree and flame view over
+//! a [`SpaaFile`]: which nodes are expanded, the selection, the search query
+//! and the event and thread being shown. It has no terminal dependency; with
+//! the `tui` feature enabled, [`run`] drives it with a ratatui front end.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa::view::Viewer;
+//! use spaa_parse::SpaaFile;
+//! use std::fs::File;
+//!
+//! let spaa = SpaaFile::parse(File::open("profile.spaa").unwrap()).unwrap();
+//! let mut viewer = Viewer::new(&spaa);
+//! viewer.search("malloc");
+//! viewer.next_match();
+//! println!("{}", viewer.function(viewer.selected_node().unwrap()));
+//! ```
+
+#[cfg(feature = "tui")]
+mod ui;
+
+#[cfg(feature = "tui")]
+pub use ui::run;
+
+use crate::analysis::{CallTree, TreeMode};
+use spaa_parse::SpaaFile;
+use std::collections::{BTreeSet, HashSet};
+
+/// Which view of the call tree is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pane {
+    /// Indented, expandable call tree.
+    Tree,
+    /// Icicle-style flame graph of the selected node's subtree.
+    Flame,
+}
+
+/// A visible line of the tree pane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Row {
+    pub node: usize,
+    /// Nesting depth; outermost frames are at depth 0.
+    pub depth: usize,
+}
+
+/// A block of the flame pane, spanning columns `start..start + width`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlameCell {
+    pub node: usize,
+    pub start: u16,
+    pub width: u16,
+}
+
+/// Navigation state over one SPAA file.
+pub struct Viewer<'a> {
+    file: &'a SpaaFile,
+    events: Vec<String>,
+    event: usize,
+    threads: Vec<u64>,
+    thread: Option<usize>,
+    tree: CallTree,
+    mode: TreeMode,
+    expanded: HashSet<usize>,
+    rows: Vec<Row>,
+    selected: usize,
+    query: String,
+    matches: Vec<usize>,
+    pane: Pane,
+}
+
+impl<'a> Viewer<'a> {
+    /// Open `file`, showing its first event with the hottest path expanded.
+    pub fn new(file: &'a SpaaFile) -> Self {
+        let mut viewer = Self {
+            file,
+            events: file.header.events.iter().map(|e| e.name.clone()).collect(),
+            event: 0,
+            threads: Vec::new(),
+            thread: None,
+            tree: CallTree::build(file, "", ""),
+            mode: TreeMode::TopDown,
+            expanded: HashSet::new(),
+            rows: Vec::new(),
+            selected: 0,
+            query: String::new(),
+            matches: Vec::new(),
+            pane: Pane::Tree,
+        };
+        viewer.rebuild();
+        viewer
+    }
+
+    pub fn file(&self) -> &SpaaFile {
+        self.file
+    }
+
+    pub fn tree(&self) -> &CallTree {
+        &self.tree
+    }
+
+    /// Name of the event being shown, if the file has any.
+    pub fn event(&self) -> Option<&str> {
+        self.events.get(self.event).map(String::as_str)
+    }
+
+    /// Metric the tree is weighted by (the event's primary metric).
+    pub fn metric(&self) -> &str {
+        self.event()
+            .and_then(|e| self.file.primary_metric_for_event(e))
+            .unwrap_or_default()
+    }
+
+    /// Thread the tree is restricted to, or `None` for all threads.
+    pub fn thread(&self) -> Option<u64> {
+        self.thread.map(|i| self.threads[i])
+    }
+
+    pub fn pane(&self) -> Pane {
+        self.pane
+    }
+
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    /// Index into [`Viewer::rows`] of the selection.
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected_node(&self) -> Option<usize> {
+        self.rows.get(self.selected).map(|r| r.node)
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Nodes whose function matches the query, in display order.
+    pub fn matches(&self) -> &[usize] {
+        &self.matches
+    }
+
+    pub fn is_expanded(&self, node: usize) -> bool {
+        self.expanded.contains(&node)
+    }
+
+    /// Function name of a node.
+    pub fn function(&self, node: usize) -> &str {
+        match self.tree.node(node).frame {
+            None => "<root>",
+            Some(id) => self
+                .file
+                .resolve_frame(id)
+                .map_or("<unknown>", |f| f.func.as_str()),
+        }
+    }
+
+    /// Inclusive weight of `node` as a fraction of the tree total.
+    pub fn share(&self, node: usize) -> f64 {
+        let total = self.tree.total();
+        if total == 0 {
+            0.0
+        } else {
+            self.tree.node(node).inclusive as f64 / total as f64
+        }
+    }
+
+    /// Whether the tree is shown top-down or bottom-up.
+    pub fn mode(&self) -> TreeMode {
+        self.mode
+    }
+
+    /// Switch between the top-down and bottom-up trees.
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            TreeMode::TopDown => TreeMode::BottomUp,
+            TreeMode::BottomUp => TreeMode::TopDown,
+        };
+        self.rebuild();
+    }
+
+    pub fn toggle_pane(&mut self) {
+        self.pane = match self.pane {
+            Pane::Tree => Pane::Flame,
+            Pane::Flame => Pane::Tree,
+        };
+    }
+
+    /// Move the selection by `delta` rows, clamped to the visible rows.
+    pub fn move_by(&mut self, delta: isize) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let max = self.rows.len() as isize - 1;
+        self.selected = (self.selected as isize + delta).clamp(0, max) as usize;
+    }
+
+    /// Expand the selected node.
+    pub fn expand(&mut self) {
+        if let Some(node) = self.selected_node()
+            && !self.tree.node(node).children.is_empty()
+        {
+            self.expanded.insert(node);
+            self.refresh_rows(Some(node));
+        }
+    }
+
+    /// Collapse the selected node, or select its parent if it is already
+    /// collapsed.
+    pub fn collapse(&mut self) {
+        let Some(node) = self.selected_node() else {
+            return;
+        };
+        if self.expanded.remove(&node) {
+            self.refresh_rows(Some(node));
+        } else if let Some(parent) = self.tree.node(node).parent.filter(|p| *p != 0) {
+            self.select_node(parent);
+        }
+    }
+
+    /// Expand or collapse the selected node.
+    pub fn toggle(&mut self) {
+        match self.selected_node() {
+            Some(node) if self.expanded.contains(&node) => self.collapse(),
+            Some(_) => self.expand(),
+            None => {}
+        }
+    }
+
+    /// Show the next event in the header.
+    pub fn next_event(&mut self) {
+        if !self.events.is_empty() {
+            self.event = (self.event + 1) % self.events.len();
+            self.thread = None;
+            self.rebuild();
+        }
+    }
+
+    /// Cycle the thread filter: all threads, then each thread in turn.
+    pub fn next_thread(&mut self) {
+        self.thread = match self.thread {
+            None if !self.threads.is_empty() => Some(0),
+            Some(i) if i + 1 < self.threads.len() => Some(i + 1),
+            _ => None,
+        };
+        self.rebuild();
+    }
+
+    /// Highlight nodes whose function contains `query` (case-insensitive).
+    /// An empty query clears the search.
+    pub fn search(&mut self, query: &str) {
+        self.query = query.to_string();
+        self.matches.clear();
+        if query.is_empty() {
+            return;
+        }
+        let needle = query.to_lowercase();
+        let mut stack: Vec<usize> = self.sorted_children(0).into_iter().rev().collect();
+        while let Some(node) = stack.pop() {
+            if self.function(node).to_lowercase().contains(&needle) {
+                self.matches.push(node);
+            }
+            stack.extend(self.sorted_children(node).into_iter().rev());
+        }
+    }
+
+    /// Select the first match after the current selection, expanding its
+    /// ancestors so it is visible. Wraps around.
+    pub fn next_match(&mut self) -> bool {
+        if self.matches.is_empty() {
+            return false;
+        }
+        let current = self.selected_node();
+        let pos = current
+            .and_then(|n| self.matches.iter().position(|m| *m == n))
+            .map_or(0, |p| (p + 1) % self.matches.len());
+        let target = self.matches[pos];
+        self.select_node(target);
+        true
+    }
+
+    /// Lay out the flame graph of the selected node's subtree in `width`
+    /// columns, one vector of cells per depth, at most `max_depth` deep.
+    ///
+    /// Children are placed left to right by descending weight, each taking
+    /// columns in proportion to its inclusive weight; nodes narrower than
+    /// one column are omitted.
+    pub fn flame(&self, width: u16, max_depth: usize) -> Vec<Vec<FlameCell>> {
+        let focus = self.selected_node().unwrap_or(0);
+        let mut levels: Vec<Vec<FlameCell>> = Vec::new();
+        let mut current = vec![FlameCell {
+            node: focus,
+            start: 0,
+            width,
+        }];
+        while !current.is_empty() && levels.len() < max_depth {
+            let mut next = Vec::new();
+            for cell in &current {
+                let parent = self.tree.node(cell.node);
+                if parent.inclusive == 0 {
+                    continue;
+                }
+                let mut start = cell.start;
+                for child in self.sorted_children(cell.node) {
+                    let w = (self.tree.node(child).inclusive as u128 * cell.width as u128
+                        / parent.inclusive as u128) as u16;
+                    if w == 0 {
+                        break;
+                    }
+                    next.push(FlameCell {
+                        node: child,
+                        start,
+                        width: w,
+                    });
+                    start += w;
+                }
+            }
+            levels.push(current);
+            current = next;
+        }
+        levels
+    }
+
+    /// Children of `node`, heaviest first.
+    fn sorted_children(&self, node: usize) -> Vec<usize> {
+        let mut children = self.tree.node(node).children.clone();
+        children.sort_by(|a, b| {
+            self.tree
+                .node(*b)
+                .inclusive
+                .cmp(&self.tree.node(*a).inclusive)
+                .then(a.cmp(b))
+        });
+        children
+    }
+
+    /// Rebuild the tree for the current event, thread filter and mode.
+    fn rebuild(&mut self) {
+        let event = self.event().unwrap_or_default().to_string();
+        let metric = self.metric().to_string();
+
+        self.threads = self
+            .file
+            .stacks_for_event(&event)
+            .filter_map(|s| s.context.tid)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        let tid = self.thread();
+        self.tree = CallTree::build_with(self.file, &event, &metric, self.mode, |s| {
+            tid.is_none_or(|t| s.context.tid == Some(t))
+        });
+
+        // Start with the hottest path open.
+        self.expanded.clear();
+        let mut node = 0;
+        self.expanded.insert(node);
+        while let Some(child) = self.tree.heaviest_child(node) {
+            self.expanded.insert(child);
+            node = child;
+        }
+
+        self.refresh_rows(None);
+        self.selected = 0;
+        let query = std::mem::take(&mut self.query);
+        self.search(&query);
+    }
+
+    fn refresh_rows(&mut self, keep: Option<usize>) {
+        self.rows.clear();
+        let mut stack: Vec<Row> = self
+            .sorted_children(0)
+            .into_iter()
+            .rev()
+            .map(|node| Row { node, depth: 0 })
+            .collect();
+        while let Some(row) = stack.pop() {
+            self.rows.push(row);
+            if self.expanded.contains(&row.node) {
+                stack.extend(
+                    self.sorted_children(row.node)
+                        .into_iter()
+                        .rev()
+                        .map(|node| Row {
+                            node,
+                            depth: row.depth + 1,
+                        }),
+                );
+            }
+        }
+        if let Some(pos) = keep.and_then(|n| self.rows.iter().position(|r| r.node == n)) {
+            self.selected = pos;
+        }
+        self.selected = self.selected.min(self.rows.len().saturating_sub(1));
+    }
+
+    fn select_node(&mut self, node: usize) {
+        let mut ancestor = self.tree.node(node).parent;
+        while let Some(a) = ancestor {
+            self.expanded.insert(a);
+            ancestor = self.tree.node(a).parent;
+        }
+        self.refresh_rows(Some(node));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_file() -> SpaaFile {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}},{"name":"cache-misses","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"render","dso":1}"#,
+            r#"{"type":"frame","id":4,"func":"malloc","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[4,2,1],"context":{"event":"cycles","tid":1},"weights":[{"metric":"period","value":60}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[4,3,1],"context":{"event":"cycles","tid":2},"weights":[{"metric":"period","value":30}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[3,1],"context":{"event":"cycles","tid":2},"weights":[{"metric":"period","value":10}]}"#,
+            r#"{"type":"stack","id":"0x4","frames":[3,1],"context":{"event":"cache-misses","tid":2},"weights":[{"metric":"period","value":5}]}"#,
+        ]
+        .join("\n");
+        SpaaFile::parse(Cursor::new(data)).unwrap()
+    }
+
+    fn functions(viewer: &Viewer) -> Vec<String> {
+        viewer
+            .rows()
+            .iter()
+            .map(|r| format!("{}{}", " ".repeat(r.depth), viewer.function(r.node)))
+            .collect()
+    }
+
+    #[test]
+    fn opens_with_hot_path_expanded() {
+        let file = sample_file();
+        let viewer = Viewer::new(&file);
+
+        assert_eq!(viewer.event(), Some("cycles"));
+        assert_eq!(
+            functions(&viewer),
+            vec!["main", " parse", "  malloc", " render"]
+        );
+    }
+
+    #[test]
+    fn expand_and_collapse() {
+        let file = sample_file();
+        let mut viewer = Viewer::new(&file);
+
+        viewer.move_by(3);
+        viewer.expand();
+        assert_eq!(viewer.rows().len(), 5);
+        assert_eq!(viewer.function(viewer.selected_node().unwrap()), "render");
+
+        viewer.collapse();
+        assert_eq!(viewer.rows().len(), 4);
+        viewer.collapse();
+        assert_eq!(viewer.function(viewer.selected_node().unwrap()), "main");
+    }
+
+    #[test]
+    fn search_cycles_through_matches() {
+        let file = sample_file();
+        let mut viewer = Viewer::new(&file);
+
+        viewer.search("MALLOC");
+        assert_eq!(viewer.matches().len(), 2);
+        assert!(viewer.next_match());
+        let first = viewer.selected_node().unwrap();
+        assert!(viewer.next_match());
+        let second = viewer.selected_node().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(viewer.function(second), "malloc");
+        // The second match lives under the collapsed "render" node.
+        assert!(viewer.is_expanded(viewer.tree().node(second).parent.unwrap()));
+    }
+
+    #[test]
+    fn thread_filter_rebuilds_tree() {
+        let file = sample_file();
+        let mut viewer = Viewer::new(&file);
+
+        viewer.next_thread();
+        assert_eq!(viewer.thread(), Some(1));
+        assert_eq!(viewer.tree().total(), 60);
+        viewer.next_thread();
+        assert_eq!(viewer.thread(), Some(2));
+        assert_eq!(viewer.tree().total(), 40);
+        viewer.next_thread();
+        assert_eq!(viewer.thread(), None);
+        assert_eq!(viewer.tree().total(), 100);
+    }
+
+    #[test]
+    fn next_event_switches_tree() {
+        let file = sample_file();
+        let mut viewer = Viewer::new(&file);
+
+        viewer.next_event();
+        assert_eq!(viewer.event(), Some("cache-misses"));
+        assert_eq!(viewer.tree().total(), 5);
+    }
+
+    #[test]
+    fn bottom_up_mode_lists_callers_under_leaves() {
+        let file = sample_file();
+        let mut viewer = Viewer::new(&file);
+
+        viewer.toggle_mode();
+        assert_eq!(viewer.mode(), TreeMode::BottomUp);
+        assert_eq!(
+            functions(&viewer),
+            vec!["malloc", " parse", "  main", " render", "render"]
+        );
+    }
+
+    #[test]
+    fn flame_layout_is_proportional() {
+        let file = sample_file();
+        let viewer = Viewer::new(&file);
+
+        let levels = viewer.flame(100, 10);
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[0][0].width, 100);
+        let widths: Vec<_> = levels[1].iter().map(|c| (c.start, c.width)).collect();
+        assert_eq!(widths, vec![(0, 60), (60, 40)]);
+    }
+}
@@ -0,0 +1,217 @@
+//! ratatui front end for [`Viewer`].
+
+use super::{Pane, Viewer};
+use crate::analysis::TreeMode;
+use ratatui::DefaultTerminal;
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use spaa_parse::SpaaFile;
+use std::io;
+
+const HELP: &str = "j/k move  h/l fold  enter toggle  f flame  b bottom-up  / search  n next  t thread  e event  q quit";
+
+/// Run the interactive viewer over `file` until the user quits.
+///
+/// Takes over the terminal (alternate screen, raw mode) and restores it on
+/// return.
+pub fn run(file: &SpaaFile) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, Viewer::new(file));
+    ratatui::restore();
+    result
+}
+
+/// Text being typed after `/`, if the search prompt is open.
+type Prompt = Option<String>;
+
+fn event_loop(terminal: &mut DefaultTerminal, mut viewer: Viewer) -> io::Result<()> {
+    let mut prompt: Prompt = None;
+    let mut list = ListState::default();
+    loop {
+        terminal.draw(|frame| draw(frame, &viewer, &prompt, &mut list))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        if let Some(query) = prompt.as_mut() {
+            match key.code {
+                KeyCode::Enter => {
+                    viewer.search(query);
+                    viewer.next_match();
+                    prompt = None;
+                }
+                KeyCode::Esc => prompt = None,
+                KeyCode::Backspace => {
+                    query.pop();
+                }
+                KeyCode::Char(c) => query.push(c),
+                _ => {}
+            }
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Char('j') | KeyCode::Down => viewer.move_by(1),
+            KeyCode::Char('k') | KeyCode::Up => viewer.move_by(-1),
+            KeyCode::PageDown => viewer.move_by(20),
+            KeyCode::PageUp => viewer.move_by(-20),
+            KeyCode::Char('l') | KeyCode::Right => viewer.expand(),
+            KeyCode::Char('h') | KeyCode::Left => viewer.collapse(),
+            KeyCode::Enter | KeyCode::Char(' ') => viewer.toggle(),
+            KeyCode::Char('f') => viewer.toggle_pane(),
+            KeyCode::Char('b') => viewer.toggle_mode(),
+            KeyCode::Char('/') => prompt = Some(String::new()),
+            KeyCode::Char('n') => {
+                viewer.next_match();
+            }
+            KeyCode::Char('t') => viewer.next_thread(),
+            KeyCode::Char('e') => viewer.next_event(),
+            _ => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, viewer: &Viewer, prompt: &Prompt, list: &mut ListState) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(1),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let thread = viewer
+        .thread()
+        .map_or_else(|| "all".to_string(), |t| t.to_string());
+    let title = format!(
+        " {} | event: {} ({}) | thread: {} | {} | total: {}",
+        viewer.file().header.source_tool,
+        viewer.event().unwrap_or("-"),
+        viewer.metric(),
+        thread,
+        match viewer.mode() {
+            TreeMode::TopDown => "top-down",
+            TreeMode::BottomUp => "bottom-up",
+        },
+        viewer.tree().total(),
+    );
+    frame.render_widget(
+        Paragraph::new(title).style(Style::default().add_modifier(Modifier::REVERSED)),
+        header,
+    );
+
+    match viewer.pane() {
+        Pane::Tree => draw_tree(frame, viewer, list, body),
+        Pane::Flame => draw_flame(frame, viewer, body),
+    }
+
+    let status = match prompt {
+        Some(query) => format!("/{query}"),
+        None if !viewer.query().is_empty() => {
+            format!(
+                "/{} ({} matches)  {HELP}",
+                viewer.query(),
+                viewer.matches().len()
+            )
+        }
+        None => HELP.to_string(),
+    };
+    frame.render_widget(Paragraph::new(status), footer);
+}
+
+fn draw_tree(frame: &mut Frame, viewer: &Viewer, list: &mut ListState, area: Rect) {
+    let tree = viewer.tree();
+    let total = tree.total().max(1) as f64;
+    let items: Vec<ListItem> = viewer
+        .rows()
+        .iter()
+        .map(|row| {
+            let node = tree.node(row.node);
+            let marker = if node.children.is_empty() {
+                ' '
+            } else if viewer.is_expanded(row.node) {
+                '▾'
+            } else {
+                '▸'
+            };
+            let text = format!(
+                "{:>6.2}% {:>6.2}% {}{} {}",
+                node.inclusive as f64 * 100.0 / total,
+                node.exclusive as f64 * 100.0 / total,
+                "  ".repeat(row.depth),
+                marker,
+                viewer.function(row.node),
+            );
+            let style = if viewer.matches().contains(&row.node) {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default()
+            };
+            ListItem::new(text).style(style)
+        })
+        .collect();
+
+    list.select(Some(viewer.selected()));
+    let widget = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" incl%  self%  call tree "),
+        )
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(widget, area, list);
+}
+
+fn draw_flame(frame: &mut Frame, viewer: &Viewer, area: Rect) {
+    let block = Block::default().borders(Borders::ALL).title(" flame ");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let levels = viewer.flame(inner.width, inner.height as usize);
+    let lines: Vec<Line> = levels
+        .iter()
+        .map(|cells| {
+            let mut spans = Vec::new();
+            let mut column = 0;
+            for cell in cells {
+                if cell.start > column {
+                    spans.push(Span::raw(" ".repeat((cell.start - column) as usize)));
+                }
+                let width = cell.width as usize;
+                let name: String = viewer.function(cell.node).chars().take(width).collect();
+                let mut style = Style::default()
+                    .bg(color_for(viewer.function(cell.node)))
+                    .fg(Color::Black);
+                if viewer.matches().contains(&cell.node) {
+                    style = style.add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+                }
+                spans.push(Span::styled(format!("{name:<width$}"), style));
+                column = cell.start + cell.width;
+            }
+            Line::from(spans)
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines), inner);
+}
+
+/// Warm colour derived from the function name, so a function keeps its
+/// colour as the view changes.
+fn color_for(name: &str) -> Color {
+    let hash = name
+        .bytes()
+        .fold(0u32, |h, b| h.wrapping_mul(31).wrapping_add(b as u32));
+    Color::Rgb(
+        200 + (hash % 55) as u8,
+        80 + ((hash >> 8) % 140) as u8,
+        40 + ((hash >> 16) % 50) as u8,
+    )
+}
@@ -0,0 +1,80 @@
+//! Parse and write throughput on synthetic files of increasing size.
+//!
+//! ```bash
+//! cargo bench --bench parse
+//! ```
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use spaa_parse::synth::{SymbolPool, SynthConfig, generate};
+use spaa_parse::{Record, SpaaFile, SpaaReader, SpaaWriter};
+use std::hint::black_box;
+use std::io::Cursor;
+
+/// Stack counts for the small, medium and large inputs.
+const SIZES: &[usize] = &[1_000, 10_000, 100_000];
+
+fn ndjson(stacks: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    generate(&SynthConfig {
+        stacks,
+        symbols: SymbolPool::synthetic(stacks.max(500) / 2, 8),
+        ..Default::default()
+    })
+    .write(&mut out)
+    .unwrap();
+    out
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for &stacks in SIZES {
+        let data = ndjson(stacks);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(BenchmarkId::new("reader", stacks), &data, |b, data| {
+            b.iter(|| SpaaFile::parse(Cursor::new(black_box(data))).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("slice", stacks), &data, |b, data| {
+            b.iter(|| SpaaFile::parse_slice(black_box(data)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write");
+    for &stacks in SIZES {
+        let data = ndjson(stacks);
+        let file = SpaaFile::parse_slice(&data).unwrap();
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(stacks), &file, |b, file| {
+            b.iter(|| {
+                let mut out = Vec::with_capacity(data.len());
+                file.write(&mut out).unwrap();
+                out
+            })
+        });
+    }
+    group.finish();
+}
+
+fn write_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write_batch");
+    for &stacks in SIZES {
+        let data = ndjson(stacks);
+        let records: Vec<Record> = SpaaReader::new(Cursor::new(&data))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(BenchmarkId::new("batch", stacks), &records, |b, records| {
+            b.iter(|| {
+                let mut writer = SpaaWriter::new(Vec::with_capacity(data.len()));
+                writer.write_batch(black_box(records)).unwrap();
+                writer.into_inner()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, write, write_batch);
+criterion_main!(benches);
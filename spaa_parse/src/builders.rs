@@ -0,0 +1,460 @@
+//! Fluent builders for headers, frames and stacks.
+//!
+//! [`Header`], [`Frame`] and [`Stack`] have many optional fields that most
+//! converters leave unset. Their builders take the required fields up
+//! front, default the rest, and set anything else by name:
+//!
+//! ```
+//! use spaa_parse::{EventDef, EventKind, Frame, Header, SamplingMode, Stack};
+//!
+//! let header = Header::builder("my-converter")
+//!     .event(EventDef::new("cycles", EventKind::Hardware, SamplingMode::Period, "period"))
+//!     .build();
+//! let frame = Frame::builder(1, "main", 1).srcline("src/main.rs:3").build();
+//! let stack = Stack::builder("0x1", "cycles")
+//!     .frames([1])
+//!     .tid(1234)
+//!     .comm("myapp")
+//!     .weight("period", 1_000_000)
+//!     .build();
+//!
+//! assert_eq!(header.format, "spaa");
+//! assert!(frame.func_resolved);
+//! assert_eq!(stack.context.pid, None);
+//! ```
+
+use crate::{
+    Approximation, ClockInfo, EventDef, EventKind, ExclusiveWeights, Frame, FrameKind, FrameOrder,
+    Header, ProbeContext, Sampling, SamplingMode, SourceInfo, Stack, StackContext, StackIdMode,
+    StackType, TimeRange, Weight,
+};
+use std::collections::HashMap;
+
+impl Header {
+    /// A builder for a header written by `source_tool`.
+    ///
+    /// Defaults to format `spaa` version `1.0`, leaf-to-root frames,
+    /// content-addressable stack IDs and no events.
+    pub fn builder(source_tool: impl Into<String>) -> HeaderBuilder {
+        HeaderBuilder {
+            header: Header {
+                format: "spaa".to_string(),
+                version: "1.0".to_string(),
+                source_tool: source_tool.into(),
+                frame_order: FrameOrder::LeafToRoot,
+                events: Vec::new(),
+                time_range: None,
+                source: None,
+                stack_id_mode: StackIdMode::ContentAddressable,
+                clock: None,
+                approximation: None,
+            },
+        }
+    }
+}
+
+impl EventDef {
+    /// An event named `name`, weighted by `primary_metric`, with no
+    /// sampling period or frequency and no description.
+    pub fn new(
+        name: impl Into<String>,
+        kind: EventKind,
+        mode: SamplingMode,
+        primary_metric: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            sampling: Sampling {
+                mode,
+                primary_metric: primary_metric.into(),
+                sample_period: None,
+                frequency_hz: None,
+            },
+            allocation_tracking: None,
+            description: None,
+            unit: None,
+        }
+    }
+}
+
+/// Builds a [`Header`]; see [`Header::builder`].
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct HeaderBuilder {
+    header: Header,
+}
+
+impl HeaderBuilder {
+    pub fn format(mut self, format: impl Into<String>) -> Self {
+        self.header.format = format.into();
+        self
+    }
+
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.header.version = version.into();
+        self
+    }
+
+    pub fn frame_order(mut self, frame_order: FrameOrder) -> Self {
+        self.header.frame_order = frame_order;
+        self
+    }
+
+    /// Add an event after those already added.
+    pub fn event(mut self, event: EventDef) -> Self {
+        self.header.events.push(event);
+        self
+    }
+
+    /// Set the time range covered, in `unit` (such as `seconds`).
+    pub fn time_range(mut self, start: f64, end: f64, unit: impl Into<String>) -> Self {
+        self.header.time_range = Some(TimeRange {
+            start,
+            end,
+            unit: unit.into(),
+        });
+        self
+    }
+
+    pub fn source(mut self, source: SourceInfo) -> Self {
+        self.header.source = Some(source);
+        self
+    }
+
+    pub fn stack_id_mode(mut self, mode: StackIdMode) -> Self {
+        self.header.stack_id_mode = mode;
+        self
+    }
+
+    pub fn clock(mut self, clock: ClockInfo) -> Self {
+        self.header.clock = Some(clock);
+        self
+    }
+
+    /// Mark the stack weights as approximated, such as by a converter
+    /// tracking a bounded number of stacks.
+    pub fn approximation(mut self, approximation: Approximation) -> Self {
+        self.header.approximation = Some(approximation);
+        self
+    }
+
+    pub fn build(self) -> Header {
+        self.header
+    }
+}
+
+impl Frame {
+    /// A builder for frame `id`, function `func` in DSO `dso`.
+    ///
+    /// Defaults to a resolved, non-inlined user frame with no address or
+    /// source line.
+    pub fn builder(id: u64, func: impl Into<String>, dso: u64) -> FrameBuilder {
+        FrameBuilder {
+            frame: Frame {
+                id,
+                func: func.into(),
+                dso,
+                func_resolved: true,
+                ip: None,
+                symoff: None,
+                srcline: None,
+                srcline_resolved: true,
+                inlined: false,
+                inline_depth: None,
+                kind: FrameKind::User,
+            },
+        }
+    }
+}
+
+/// Builds a [`Frame`]; see [`Frame::builder`].
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct FrameBuilder {
+    frame: Frame,
+}
+
+impl FrameBuilder {
+    /// Mark the function name as not a resolved symbol, such as a raw
+    /// address.
+    pub fn unresolved(mut self) -> Self {
+        self.frame.func_resolved = false;
+        self
+    }
+
+    pub fn ip(mut self, ip: impl Into<String>) -> Self {
+        self.frame.ip = Some(ip.into());
+        self
+    }
+
+    pub fn symoff(mut self, symoff: impl Into<String>) -> Self {
+        self.frame.symoff = Some(symoff.into());
+        self
+    }
+
+    pub fn srcline(mut self, srcline: impl Into<String>) -> Self {
+        self.frame.srcline = Some(srcline.into());
+        self
+    }
+
+    pub fn srcline_resolved(mut self, resolved: bool) -> Self {
+        self.frame.srcline_resolved = resolved;
+        self
+    }
+
+    /// Mark the frame as inlined, `depth` levels into its caller.
+    pub fn inlined(mut self, depth: u32) -> Self {
+        self.frame.inlined = true;
+        self.frame.inline_depth = Some(depth);
+        self
+    }
+
+    pub fn kind(mut self, kind: FrameKind) -> Self {
+        self.frame.kind = kind;
+        self
+    }
+
+    pub fn build(self) -> Frame {
+        self.frame
+    }
+}
+
+impl StackContext {
+    /// A context for `event` with nothing else known.
+    pub fn new(event: impl Into<String>) -> Self {
+        Self {
+            event: event.into(),
+            pid: None,
+            tid: None,
+            cpu: None,
+            comm: None,
+            probe: None,
+            execname: None,
+            uid: None,
+            zonename: None,
+            cgroup: None,
+            container_id: None,
+            k8s_pod: None,
+            trace_fields: None,
+            extra: HashMap::new(),
+        }
+    }
+}
+
+impl Stack {
+    /// A builder for stack `id` of `event`.
+    ///
+    /// Defaults to a unified stack with no frames, weights or context
+    /// beyond the event.
+    pub fn builder(id: impl Into<String>, event: impl Into<String>) -> StackBuilder {
+        StackBuilder {
+            stack: Stack {
+                id: id.into(),
+                frames: Vec::new(),
+                stack_type: StackType::Unified,
+                context: StackContext::new(event),
+                weights: Vec::new(),
+                exclusive: None,
+                related_stacks: None,
+            },
+        }
+    }
+}
+
+/// Builds a [`Stack`]; see [`Stack::builder`].
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct StackBuilder {
+    stack: Stack,
+}
+
+impl StackBuilder {
+    /// Set the frame IDs, in the header's frame order.
+    pub fn frames(mut self, frames: impl IntoIterator<Item = u64>) -> Self {
+        self.stack.frames = frames.into_iter().collect();
+        self
+    }
+
+    pub fn stack_type(mut self, stack_type: StackType) -> Self {
+        self.stack.stack_type = stack_type;
+        self
+    }
+
+    /// Add a weight of `value` in `metric`.
+    pub fn weight(mut self, metric: impl Into<String>, value: u64) -> Self {
+        self.stack.weights.push(Weight {
+            metric: metric.into(),
+            value,
+            unit: None,
+        });
+        self
+    }
+
+    /// Add a weight of `value` in `metric`, measured in `unit`.
+    pub fn weight_with_unit(
+        mut self,
+        metric: impl Into<String>,
+        value: u64,
+        unit: impl Into<String>,
+    ) -> Self {
+        self.stack.weights.push(Weight {
+            metric: metric.into(),
+            value,
+            unit: Some(unit.into()),
+        });
+        self
+    }
+
+    /// Attribute `weights` exclusively to leaf frame `frame`.
+    pub fn exclusive(mut self, frame: u64, weights: Vec<Weight>) -> Self {
+        self.stack.exclusive = Some(ExclusiveWeights { frame, weights });
+        self
+    }
+
+    /// Add the ID of a related stack, such as the kernel half of a user
+    /// stack.
+    pub fn related(mut self, id: impl Into<String>) -> Self {
+        self.stack
+            .related_stacks
+            .get_or_insert_with(Vec::new)
+            .push(id.into());
+        self
+    }
+
+    pub fn pid(mut self, pid: u64) -> Self {
+        self.stack.context.pid = Some(pid);
+        self
+    }
+
+    pub fn tid(mut self, tid: u64) -> Self {
+        self.stack.context.tid = Some(tid);
+        self
+    }
+
+    pub fn cpu(mut self, cpu: u32) -> Self {
+        self.stack.context.cpu = Some(cpu);
+        self
+    }
+
+    pub fn comm(mut self, comm: impl Into<String>) -> Self {
+        self.stack.context.comm = Some(comm.into());
+        self
+    }
+
+    pub fn probe(mut self, probe: ProbeContext) -> Self {
+        self.stack.context.probe = Some(probe);
+        self
+    }
+
+    pub fn execname(mut self, execname: impl Into<String>) -> Self {
+        self.stack.context.execname = Some(execname.into());
+        self
+    }
+
+    pub fn uid(mut self, uid: u64) -> Self {
+        self.stack.context.uid = Some(uid);
+        self
+    }
+
+    pub fn zonename(mut self, zonename: impl Into<String>) -> Self {
+        self.stack.context.zonename = Some(zonename.into());
+        self
+    }
+
+    pub fn cgroup(mut self, cgroup: impl Into<String>) -> Self {
+        self.stack.context.cgroup = Some(cgroup.into());
+        self
+    }
+
+    pub fn container_id(mut self, container_id: impl Into<String>) -> Self {
+        self.stack.context.container_id = Some(container_id.into());
+        self
+    }
+
+    pub fn k8s_pod(mut self, k8s_pod: impl Into<String>) -> Self {
+        self.stack.context.k8s_pod = Some(k8s_pod.into());
+        self
+    }
+
+    /// Set trace field `key`, such as a request ID.
+    pub fn trace_field(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<serde_json::Value>,
+    ) -> Self {
+        self.stack
+            .context
+            .trace_fields
+            .get_or_insert_with(HashMap::new)
+            .insert(key.into(), value.into());
+        self
+    }
+
+    /// Set extension field `key` of the context. See
+    /// [`extensions`](crate::extensions) for namespaced keys.
+    pub fn extra(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.stack.context.extra.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn build(self) -> Stack {
+        self.stack
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dso, SpaaFile, SpaaWriter};
+
+    #[test]
+    fn built_records_round_trip() {
+        let mut writer = SpaaWriter::new(Vec::new());
+        let header = Header::builder("test")
+            .frame_order(FrameOrder::RootToLeaf)
+            .event(EventDef::new(
+                "cycles",
+                EventKind::Hardware,
+                SamplingMode::Period,
+                "period",
+            ))
+            .time_range(0.0, 2.5, "seconds")
+            .build();
+        writer.write_header(&header).unwrap();
+        writer
+            .write_dso(&Dso {
+                id: 1,
+                name: "/usr/bin/app".to_string(),
+                build_id: None,
+                is_kernel: false,
+            })
+            .unwrap();
+        let frames = [
+            Frame::builder(1, "main", 1).build(),
+            Frame::builder(2, "0x4010a0", 1)
+                .unresolved()
+                .ip("0x4010a0")
+                .inlined(1)
+                .build(),
+        ];
+        for frame in &frames {
+            writer.write_frame(frame).unwrap();
+        }
+        let stack = Stack::builder("0x1", "cycles")
+            .frames([1, 2])
+            .pid(7)
+            .tid(8)
+            .weight_with_unit("period", 500, "events")
+            .trace_field("request_id", "abc")
+            .extra("numa_node", 1)
+            .build();
+        writer.write_stack(&stack).unwrap();
+
+        let spaa = SpaaFile::parse(&writer.into_inner()[..]).unwrap();
+        assert_eq!(spaa.header, header);
+        assert_eq!(spaa.frames[&2], frames[1]);
+        assert_eq!(spaa.stacks["0x1"], stack);
+        assert_eq!(stack.get_extra::<u32>("numa_node"), Some(1));
+    }
+}
@@ -0,0 +1,163 @@
+//! Checkpoints for resuming interrupted writes.
+//!
+//! A long conversion calls [`SpaaWriter::checkpoint`] now and then, saving
+//! how much of the output is complete together with its own progress (such
+//! as the input offset it has read up to) in a small sidecar file. After a
+//! crash, [`SpaaWriter::resume`] cuts the output back to the last
+//! checkpoint, which always ends on a record boundary, and hands back that
+//! progress so the conversion can carry on from there. [`SpaaWriter::finish`]
+//! ends the file with a [`Footer`](crate::Footer), so a file that was never
+//! finished is detectably incomplete.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa_parse::SpaaWriter;
+//! use std::path::Path;
+//!
+//! let output = Path::new("profile.spaa");
+//! let sidecar = Path::new("profile.spaa.checkpoint");
+//! let (mut writer, mut next_input) = if sidecar.exists() {
+//!     SpaaWriter::resume(output, sidecar).unwrap()
+//! } else {
+//!     (SpaaWriter::new(std::fs::File::create(output).unwrap()), 0u64)
+//! };
+//! // ... write records, and every so often:
+//! writer.checkpoint(sidecar, &next_input).unwrap();
+//! # next_input += 1;
+//! writer.finish().unwrap();
+//! std::fs::remove_file(sidecar).unwrap();
+//! ```
+
+use crate::{SpaaWriter, WriteResult};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Contents of a checkpoint sidecar file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint<S> {
+    /// Bytes of the output that hold complete records.
+    pub bytes: u64,
+    /// Records in those bytes.
+    pub records: u64,
+    /// The caller's progress at the checkpoint.
+    pub state: S,
+}
+
+impl<W: Write> SpaaWriter<W> {
+    /// Flush the output and save a checkpoint with the caller's `state` to
+    /// `sidecar`.
+    ///
+    /// The sidecar is replaced atomically, so a crash while checkpointing
+    /// leaves the previous checkpoint in place.
+    pub fn checkpoint<S: Serialize>(&mut self, sidecar: &Path, state: &S) -> WriteResult<()> {
+        self.flush()?;
+        let checkpoint = Checkpoint {
+            bytes: self.bytes_written(),
+            records: self.records_written(),
+            state,
+        };
+        let mut tmp = sidecar.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = File::create(&tmp)?;
+        serde_json::to_writer(&mut file, &checkpoint)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, sidecar)?;
+        Ok(())
+    }
+}
+
+impl SpaaWriter<File> {
+    /// Reopen `output` at the checkpoint saved in `sidecar`, dropping
+    /// anything written after it, and return the caller's state from the
+    /// checkpoint.
+    pub fn resume<S: DeserializeOwned>(output: &Path, sidecar: &Path) -> WriteResult<(Self, S)> {
+        let checkpoint: Checkpoint<S> = serde_json::from_reader(File::open(sidecar)?)?;
+        let mut file = OpenOptions::new().write(true).open(output)?;
+        file.set_len(checkpoint.bytes)?;
+        file.seek(SeekFrom::End(0))?;
+
+        let mut writer = SpaaWriter::new(file);
+        writer.bytes = checkpoint.bytes;
+        writer.records = checkpoint.records;
+        Ok((writer, checkpoint.state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Dso, ParseError, SpaaFile, SpaaWriter};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn header() -> crate::Header {
+        serde_json::from_str(
+            r#"{"format":"spaa","version":"1.0","source_tool":"test","frame_order":"leaf_to_root","events":[]}"#,
+        )
+        .unwrap()
+    }
+
+    fn dso(id: u64) -> Dso {
+        Dso {
+            id,
+            name: format!("/lib/{id}.so"),
+            build_id: None,
+            is_kernel: false,
+        }
+    }
+
+    fn paths(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir();
+        let output = dir.join(format!("spaa-{name}-{}.spaa", std::process::id()));
+        let sidecar = dir.join(format!("spaa-{name}-{}.checkpoint", std::process::id()));
+        (output, sidecar)
+    }
+
+    #[test]
+    fn resume_drops_records_after_the_checkpoint() {
+        let (output, sidecar) = paths("resume");
+        let mut writer = SpaaWriter::new(std::fs::File::create(&output).unwrap());
+        writer.write_header(&header()).unwrap();
+        writer.write_dso(&dso(1)).unwrap();
+        writer.checkpoint(&sidecar, &2u64).unwrap();
+        writer.write_dso(&dso(2)).unwrap();
+        writer.get_mut().write_all(b"{\"type\":\"ds").unwrap();
+        drop(writer);
+
+        let (mut writer, next): (_, u64) = SpaaWriter::resume(&output, &sidecar).unwrap();
+        assert_eq!(next, 2);
+        writer.write_dso(&dso(2)).unwrap();
+        writer.finish().unwrap();
+
+        let (file, diagnostics) = SpaaFile::parse_with_options(
+            std::fs::File::open(&output).unwrap(),
+            &Default::default(),
+        )
+        .unwrap();
+        assert!(diagnostics.has_footer);
+        assert_eq!(file.dsos.len(), 2);
+        std::fs::remove_file(&output).unwrap();
+        std::fs::remove_file(&sidecar).unwrap();
+    }
+
+    #[test]
+    fn a_footer_with_the_wrong_count_is_rejected() {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"test","frame_order":"leaf_to_root","events":[]}"#,
+            r#"{"type":"footer","records":2}"#,
+        ]
+        .join("\n");
+
+        assert!(matches!(
+            SpaaFile::parse(data.as_bytes()),
+            Err(ParseError::FooterMismatch {
+                line: 2,
+                expected: 2,
+                found: 1
+            })
+        ));
+    }
+}
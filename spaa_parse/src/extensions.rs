@@ -0,0 +1,240 @@
+//! Namespaced extension fields in a stack context.
+//!
+//! [`StackContext::extra`] holds every context field the schema does not
+//! define. [`Extensions`] reads and writes it with `<namespace>.<field>`
+//! keys, so fields added by different tools cannot collide, and refuses
+//! keys that would shadow a standard context field when written.
+//!
+//! # Example
+//!
+//! ```
+//! use spaa_parse::StackContext;
+//! # let mut context: StackContext =
+//! #     serde_json::from_str(r#"{"event":"cycles"}"#).unwrap();
+//!
+//! context.extensions_mut().set("acme", "build", "a1b2").unwrap();
+//! assert!(context.extra.contains_key("acme.build"));
+//!
+//! let build: Option<String> = context.extensions().get("acme", "build");
+//! assert_eq!(build.as_deref(), Some("a1b2"));
+//! ```
+
+use crate::StackContext;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use thiserror::Error;
+
+/// Standard [`StackContext`] field names, which an extension key must not
+/// use.
+pub const RESERVED_KEYS: &[&str] = &[
+    "event",
+    "pid",
+    "tid",
+    "cpu",
+    "comm",
+    "probe",
+    "execname",
+    "uid",
+    "zonename",
+    "cgroup",
+    "container_id",
+    "k8s_pod",
+    "trace_fields",
+];
+
+/// Errors from [`Extensions`].
+#[derive(Error, Debug)]
+pub enum ExtensionError {
+    /// Namespaces are non-empty ASCII letters, digits, `_` and `-`.
+    #[error("invalid extension namespace '{0}'")]
+    InvalidNamespace(String),
+
+    #[error("extension field name is empty in namespace '{0}'")]
+    EmptyField(String),
+
+    /// The key is a standard context field, which it would shadow.
+    #[error("extension key '{0}' shadows a standard context field")]
+    Reserved(String),
+
+    #[error("extension value for '{key}' is not serializable: {source}")]
+    Json {
+        key: String,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+/// The extension key for `field` in `namespace`, i.e. `namespace.field`.
+pub fn key(namespace: &str, field: &str) -> Result<String, ExtensionError> {
+    if !valid_namespace(namespace) {
+        return Err(ExtensionError::InvalidNamespace(namespace.to_string()));
+    }
+    if field.is_empty() {
+        return Err(ExtensionError::EmptyField(namespace.to_string()));
+    }
+    Ok(format!("{namespace}.{field}"))
+}
+
+fn valid_namespace(namespace: &str) -> bool {
+    !namespace.is_empty()
+        && namespace
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+/// Typed, namespaced access to an extension map.
+///
+/// `M` is `&HashMap` for reading or `&mut HashMap` for writing; see
+/// [`StackContext::extensions`] and [`StackContext::extensions_mut`].
+#[derive(Debug)]
+pub struct Extensions<M> {
+    map: M,
+}
+
+impl<M: Deref<Target = HashMap<String, Value>>> Extensions<M> {
+    /// Wrap the extension map `map`.
+    pub fn new(map: M) -> Self {
+        Self { map }
+    }
+
+    /// `namespace.field` as a `T`, or `None` if it is missing, not a `T`,
+    /// or not a valid key.
+    pub fn get<T: DeserializeOwned>(&self, namespace: &str, field: &str) -> Option<T> {
+        let value = self.map.get(&key(namespace, field).ok()?)?;
+        T::deserialize(value).ok()
+    }
+
+    /// Fields of `namespace` and their values, without the namespace
+    /// prefix.
+    pub fn namespace<'a>(
+        &'a self,
+        namespace: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a Value)> {
+        self.map.iter().filter_map(move |(key, value)| {
+            let (ns, field) = key.split_once('.')?;
+            (ns == namespace).then_some((field, value))
+        })
+    }
+
+    /// Check that no key shadows a standard context field and that every
+    /// namespaced key has a valid namespace.
+    ///
+    /// Keys without a `.` are allowed unless reserved, for fields written
+    /// before namespacing, such as those from `spaa`'s topology enrichment.
+    pub fn validate(&self) -> Result<(), ExtensionError> {
+        let mut keys: Vec<&String> = self.map.keys().collect();
+        keys.sort();
+        for key in keys {
+            if RESERVED_KEYS.contains(&key.as_str()) {
+                return Err(ExtensionError::Reserved(key.clone()));
+            }
+            if let Some((namespace, _)) = key.split_once('.')
+                && !valid_namespace(namespace)
+            {
+                return Err(ExtensionError::InvalidNamespace(namespace.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<M: DerefMut<Target = HashMap<String, Value>>> Extensions<M> {
+    /// Set `namespace.field` to `value`, returning the previous value.
+    pub fn set<T: Serialize>(
+        &mut self,
+        namespace: &str,
+        field: &str,
+        value: T,
+    ) -> Result<Option<Value>, ExtensionError> {
+        let key = key(namespace, field)?;
+        let value = serde_json::to_value(value).map_err(|source| ExtensionError::Json {
+            key: key.clone(),
+            source,
+        })?;
+        Ok(self.map.insert(key, value))
+    }
+
+    /// Remove `namespace.field`, returning its value.
+    pub fn remove(&mut self, namespace: &str, field: &str) -> Option<Value> {
+        self.map.remove(&key(namespace, field).ok()?)
+    }
+}
+
+impl StackContext {
+    /// Read-only namespaced access to [`StackContext::extra`].
+    pub fn extensions(&self) -> Extensions<&HashMap<String, Value>> {
+        Extensions::new(&self.extra)
+    }
+
+    /// Namespaced access to [`StackContext::extra`].
+    pub fn extensions_mut(&mut self) -> Extensions<&mut HashMap<String, Value>> {
+        Extensions::new(&mut self.extra)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> StackContext {
+        serde_json::from_str(r#"{"event":"cycles"}"#).unwrap()
+    }
+
+    #[test]
+    fn set_and_get_round_trip_through_json() {
+        let mut context = context();
+        context
+            .extensions_mut()
+            .set("acme", "retries", 3u32)
+            .unwrap();
+        let json = serde_json::to_string(&context).unwrap();
+        let parsed: StackContext = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(parsed.extensions().get::<u32>("acme", "retries"), Some(3));
+        assert_eq!(parsed.extensions().get::<String>("acme", "retries"), None);
+        assert_eq!(parsed.extensions().get::<u32>("other", "retries"), None);
+    }
+
+    #[test]
+    fn namespace_lists_its_fields_only() {
+        let mut context = context();
+        let mut ext = context.extensions_mut();
+        ext.set("acme", "build", "a1b2").unwrap();
+        ext.set("other", "build", "c3d4").unwrap();
+
+        let ext = context.extensions();
+        let fields: Vec<_> = ext.namespace("acme").collect();
+        assert_eq!(fields, vec![("build", &Value::from("a1b2"))]);
+    }
+
+    #[test]
+    fn invalid_keys_are_refused() {
+        let mut context = context();
+        let mut ext = context.extensions_mut();
+
+        assert!(matches!(
+            ext.set("ac.me", "build", 1),
+            Err(ExtensionError::InvalidNamespace(_))
+        ));
+        assert!(matches!(
+            ext.set("acme", "", 1),
+            Err(ExtensionError::EmptyField(_))
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_shadowed_fields() {
+        let mut context = context();
+        context.extra.insert("numa_node".to_string(), 1.into());
+        assert!(context.extensions().validate().is_ok());
+
+        context.extra.insert("pid".to_string(), 1.into());
+        assert!(matches!(
+            context.extensions().validate(),
+            Err(ExtensionError::Reserved(key)) if key == "pid"
+        ));
+    }
+}
@@ -30,6 +30,32 @@
 //! let spaa = SpaaFile::parse(Cursor::new(data)).unwrap();
 //! ```
 //!
+//! For files too large to hold in memory, [`SpaaReader`] yields one
+//! [`Record`] at a time without validating cross-record references.
+//! [`SpaaSliceReader`] does the same over an in-memory buffer without
+//! copying lines; with the `mmap` feature, `SpaaFile::open_mmap` uses it
+//! over a memory-mapped file.
+//!
+//! Long-running operations accept a [`ProgressSink`], which receives
+//! [`Progress`] updates and can cancel the operation:
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::ops::ControlFlow;
+//! use spaa_parse::{ParseOptions, Progress, SpaaFile};
+//!
+//! let file = File::open("profile.spaa").unwrap();
+//! let options = ParseOptions {
+//!     size_hint: Some(file.metadata().unwrap().len()),
+//!     ..Default::default()
+//! };
+//! let mut sink = |p: &Progress| {
+//!     eprint!("\r{}: {:.0}%", p.stage, p.fraction().unwrap_or(0.0) * 100.0);
+//!     ControlFlow::Continue(())
+//! };
+//! let (spaa, _) = SpaaFile::parse_with_progress(file, &options, &mut sink).unwrap();
+//! ```
+//!
 //! # Accessing Parsed Data
 //!
 //! The [`SpaaFile`] struct provides access to all parsed records:
@@ -55,6 +81,10 @@
 //! }
 //! ```
 //!
+//! To query one loaded profile from many threads, wrap it in a
+//! [`SpaaSnapshot`]: an indexed, immutable view that is cheap to clone and
+//! `Send + Sync`.
+//!
 //! # Writing SPAA Files
 //!
 //! ## Writing a Complete SpaaFile
@@ -77,37 +107,15 @@
 //!
 //! ```no_run
 //! use std::fs::File;
-//! use std::collections::HashMap;
-//! use spaa_parse::{
-//!     SpaaWriter, Header, Dso, Frame, Stack, StackContext, Weight,
-//!     FrameOrder, StackIdMode, EventDef, EventKind, Sampling, SamplingMode,
-//!     FrameKind, StackType,
-//! };
+//! use spaa_parse::{SpaaWriter, Header, Dso, Frame, Stack, EventDef, EventKind, SamplingMode};
 //!
 //! let file = File::create("output.spaa").unwrap();
 //! let mut writer = SpaaWriter::new(file);
 //!
 //! // 1. Write header first (required)
-//! let header = Header {
-//!     format: "spaa".to_string(),
-//!     version: "1.0".to_string(),
-//!     source_tool: "my-converter".to_string(),
-//!     frame_order: FrameOrder::LeafToRoot,
-//!     events: vec![EventDef {
-//!         name: "cycles".to_string(),
-//!         kind: EventKind::Hardware,
-//!         sampling: Sampling {
-//!             mode: SamplingMode::Period,
-//!             primary_metric: "period".to_string(),
-//!             sample_period: None,
-//!             frequency_hz: None,
-//!         },
-//!         allocation_tracking: None,
-//!     }],
-//!     time_range: None,
-//!     source: None,
-//!     stack_id_mode: StackIdMode::ContentAddressable,
-//! };
+//! let header = Header::builder("my-converter")
+//!     .event(EventDef::new("cycles", EventKind::Hardware, SamplingMode::Period, "period"))
+//!     .build();
 //! writer.write_header(&header).unwrap();
 //!
 //! // 2. Write dictionary records (DSOs, frames, threads)
@@ -119,50 +127,24 @@
 //! };
 //! writer.write_dso(&dso).unwrap();
 //!
-//! let frame = Frame {
-//!     id: 1,
-//!     func: "main".to_string(),
-//!     dso: 1,  // References the DSO above
-//!     func_resolved: true,
-//!     ip: Some("0x401000".to_string()),
-//!     symoff: Some("0x0".to_string()),
-//!     srcline: None,
-//!     srcline_resolved: true,
-//!     inlined: false,
-//!     inline_depth: None,
-//!     kind: FrameKind::User,
-//! };
+//! // References the DSO above
+//! let frame = Frame::builder(1, "main", 1).ip("0x401000").symoff("0x0").build();
 //! writer.write_frame(&frame).unwrap();
 //!
 //! // 3. Write stack records
-//! let stack = Stack {
-//!     id: "0x123abc".to_string(),
-//!     frames: vec![1],  // References frame IDs
-//!     stack_type: StackType::Unified,
-//!     context: StackContext {
-//!         event: "cycles".to_string(),
-//!         pid: Some(1234),
-//!         tid: Some(1234),
-//!         cpu: None,
-//!         comm: Some("myapp".to_string()),
-//!         probe: None,
-//!         execname: None,
-//!         uid: None,
-//!         zonename: None,
-//!         trace_fields: None,
-//!         extra: HashMap::new(),
-//!     },
-//!     weights: vec![Weight {
-//!         metric: "period".to_string(),
-//!         value: 1000000,
-//!         unit: Some("events".to_string()),
-//!     }],
-//!     exclusive: None,
-//!     related_stacks: None,
-//! };
+//! let stack = Stack::builder("0x123abc", "cycles")
+//!     .frames([1]) // References frame IDs
+//!     .pid(1234)
+//!     .tid(1234)
+//!     .comm("myapp")
+//!     .weight_with_unit("period", 1000000, "events")
+//!     .build();
 //! writer.write_stack(&stack).unwrap();
 //! ```
 //!
+//! The [`builders`] default every optional field; the records can also be
+//! written out as struct literals.
+//!
 //! # Record Ordering
 //!
 //! SPAA files must follow this ordering:
@@ -174,20 +156,44 @@
 //!
 //! The parser validates references and will return errors for invalid files.
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::io::{BufRead, BufReader, Read, Write};
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+pub mod builders;
+pub mod checkpoint;
+pub mod extensions;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod snapshot;
+pub mod srcline;
+pub mod synth;
+pub mod watch;
+pub mod windows;
+
+pub use builders::{FrameBuilder, HeaderBuilder, StackBuilder};
+pub use snapshot::SpaaSnapshot;
+pub use watch::SpaaWatcher;
+pub use windows::WindowBuilder;
+
 /// Errors that can occur during SPAA parsing.
 #[derive(Error, Debug)]
 pub enum ParseError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
-    #[error("JSON parse error at line {line}: {source}")]
+    #[error("JSON parse error at line {line} (byte {offset}): {source}")]
     Json {
         line: usize,
+        /// Byte offset of the start of the line.
+        offset: u64,
         #[source]
         source: serde_json::Error,
     },
@@ -195,26 +201,194 @@ pub enum ParseError {
     #[error("missing header record")]
     MissingHeader,
 
+    /// The input looks like UTF-16, as some Windows tools write by default.
+    #[error("input is UTF-16 encoded; SPAA files must be UTF-8")]
+    Utf16Input,
+
     #[error("header must be first record, found at line {0}")]
     HeaderNotFirst(usize),
 
     #[error("duplicate header at line {0}")]
     DuplicateHeader(usize),
 
-    #[error("frame {frame_id} references non-existent DSO {dso_id}")]
-    InvalidDsoReference { frame_id: u64, dso_id: u64 },
+    #[error("frame {frame_id} at {location} references non-existent DSO {dso_id}")]
+    InvalidDsoReference {
+        frame_id: u64,
+        dso_id: u64,
+        location: Location,
+    },
+
+    #[error("stack {stack_id} at {location} references non-existent frame {frame_id}")]
+    InvalidFrameReference {
+        stack_id: String,
+        frame_id: u64,
+        location: Location,
+    },
 
-    #[error("stack {stack_id} references non-existent frame {frame_id}")]
-    InvalidFrameReference { stack_id: String, frame_id: u64 },
+    #[error("stack {stack_id} at {location} missing primary metric '{metric}'")]
+    MissingPrimaryMetric {
+        stack_id: String,
+        metric: String,
+        location: Location,
+    },
 
-    #[error("stack {stack_id} missing primary metric '{metric}'")]
-    MissingPrimaryMetric { stack_id: String, metric: String },
+    /// A `sample`, `samples` or `state` record (named by `record`) refers to
+    /// a stack that is not defined.
+    #[error("{record} record at {location} references non-existent stack {stack_id}")]
+    InvalidStackReference {
+        record: &'static str,
+        stack_id: String,
+        location: Location,
+    },
 
-    #[error("sample references non-existent stack {0}")]
-    InvalidStackReference(String),
+    /// A `note` record refers to a frame that is not defined.
+    #[error("note at {location} references non-existent frame {frame_id}")]
+    InvalidNoteFrameReference { frame_id: u64, location: Location },
+
+    #[error(
+        "stack {stack_id} at {location} has exclusive {metric} {exclusive} greater than inclusive {inclusive}"
+    )]
+    ExclusiveExceedsInclusive {
+        stack_id: String,
+        metric: String,
+        exclusive: u64,
+        inclusive: u64,
+        location: Location,
+    },
 
     #[error("unknown record type '{0}' at line {1}")]
     UnknownRecordType(String, usize),
+
+    #[error("invalid sample batch at line {line} (byte {offset}): {reason}")]
+    InvalidSampleBatch {
+        line: usize,
+        offset: u64,
+        reason: String,
+    },
+
+    /// The footer's record count is wrong, so records are missing or
+    /// duplicated.
+    #[error("footer at line {line} counts {expected} records but {found} precede it")]
+    FooterMismatch {
+        line: usize,
+        expected: u64,
+        found: u64,
+    },
+
+    #[error("record at line {0} follows the footer")]
+    RecordAfterFooter(usize),
+
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+}
+
+impl ParseError {
+    /// Where in the input the offending record is, for errors found while
+    /// validating references and weights.
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            ParseError::InvalidDsoReference { location, .. }
+            | ParseError::InvalidFrameReference { location, .. }
+            | ParseError::MissingPrimaryMetric { location, .. }
+            | ParseError::InvalidStackReference { location, .. }
+            | ParseError::InvalidNoteFrameReference { location, .. }
+            | ParseError::ExclusiveExceedsInclusive { location, .. } => Some(*location),
+            ParseError::Json { line, offset, .. }
+            | ParseError::InvalidSampleBatch { line, offset, .. } => Some(Location {
+                line: *line,
+                offset: *offset,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Class of the failure, for choosing a command-line exit code.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ParseError::Io(_) => ErrorKind::Io,
+            ParseError::Cancelled(_) => ErrorKind::Failure,
+            ParseError::Json { .. }
+            | ParseError::MissingHeader
+            | ParseError::Utf16Input
+            | ParseError::HeaderNotFirst(_)
+            | ParseError::DuplicateHeader(_)
+            | ParseError::UnknownRecordType(..) => ErrorKind::Parse,
+            _ => ErrorKind::Validation,
+        }
+    }
+
+    /// Whether the error concerns a single record, which lenient parsing
+    /// can skip.
+    fn is_record_error(&self) -> bool {
+        !matches!(
+            self,
+            ParseError::Io(_)
+                | ParseError::MissingHeader
+                | ParseError::Utf16Input
+                | ParseError::Cancelled(_)
+        )
+    }
+}
+
+/// Class of failure of a SPAA command-line tool, each with its own exit
+/// code, so scripts can tell a missing file from a malformed profile
+/// without parsing messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Any other error.
+    Failure,
+    /// Invalid command-line arguments.
+    Usage,
+    /// A file could not be read or written.
+    Io,
+    /// Input is not well-formed.
+    Parse,
+    /// Input is well-formed but inconsistent.
+    Validation,
+    /// Input format is not recognized.
+    UnsupportedFormat,
+}
+
+impl ErrorKind {
+    /// Process exit code for this kind.
+    pub fn code(self) -> u8 {
+        match self {
+            ErrorKind::Failure => 1,
+            ErrorKind::Usage => 2,
+            ErrorKind::Io => 3,
+            ErrorKind::Parse => 4,
+            ErrorKind::Validation => 5,
+            ErrorKind::UnsupportedFormat => 6,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorKind::Failure => "failure",
+            ErrorKind::Usage => "usage",
+            ErrorKind::Io => "io",
+            ErrorKind::Parse => "parse",
+            ErrorKind::Validation => "validation",
+            ErrorKind::UnsupportedFormat => "unsupported_format",
+        })
+    }
+}
+
+/// Position of a record in SPAA input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Location {
+    /// Line number, 1-indexed.
+    pub line: usize,
+    /// Byte offset of the start of the line.
+    pub offset: u64,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {} (byte {})", self.line, self.offset)
+    }
 }
 
 /// Result type for SPAA parsing operations.
@@ -239,6 +413,7 @@ pub type WriteResult<T> = std::result::Result<T, WriteError>;
 
 /// Frame ordering within stacks.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum FrameOrder {
     LeafToRoot,
@@ -247,6 +422,7 @@ pub enum FrameOrder {
 
 /// Stack ID mode for the file.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum StackIdMode {
     ContentAddressable,
@@ -255,6 +431,7 @@ pub enum StackIdMode {
 
 /// Sampling mode for an event.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum SamplingMode {
     Period,
@@ -264,6 +441,7 @@ pub enum SamplingMode {
 
 /// Event kind classification.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum EventKind {
     Hardware,
@@ -276,6 +454,7 @@ pub enum EventKind {
 
 /// Sampling configuration for an event.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Sampling {
     pub mode: SamplingMode,
     pub primary_metric: String,
@@ -287,6 +466,7 @@ pub struct Sampling {
 
 /// Allocation tracking metadata.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct AllocationTracking {
     #[serde(default)]
     pub tracks_frees: bool,
@@ -296,16 +476,24 @@ pub struct AllocationTracking {
 
 /// Event definition in the header.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct EventDef {
     pub name: String,
     pub kind: EventKind,
     pub sampling: Sampling,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub allocation_tracking: Option<AllocationTracking>,
+    /// Human-readable explanation of what the event counts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Unit of what a single event counts (e.g. `"cycles"`, `"misses"`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
 }
 
 /// Time range for the profile.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct TimeRange {
     pub start: f64,
     pub end: f64,
@@ -314,16 +502,109 @@ pub struct TimeRange {
 
 /// Source tool information.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SourceInfo {
     pub tool: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub command: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_version: Option<String>,
+    /// Data the source tool reported as dropped during collection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_loss: Option<DataLoss>,
+    /// Build and CI metadata, such as the git commit and service version,
+    /// tying the profile to the code it measured.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub build: BTreeMap<String, String>,
+}
+
+/// Collection-time data loss reported by the source tool.
+///
+/// Profiles with lost data under-count some stacks, so consumers should
+/// treat weights as lower bounds when this is present.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DataLoss {
+    /// Number of events or samples the tool reported as lost.
+    #[serde(default)]
+    pub lost_events: u64,
+    /// Number of lost-data records or warnings encountered.
+    #[serde(default)]
+    pub lost_records: u64,
+}
+
+/// Clock a profile's timestamps were read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ClockSource {
+    /// A clock counting from an arbitrary point, such as boot
+    /// (`CLOCK_MONOTONIC`, Chrome's `TimeTicks`).
+    Monotonic,
+    /// Wall-clock time since the Unix epoch (`CLOCK_REALTIME`).
+    Wall,
+}
+
+/// How a converter approximated stack weights to bound its memory, as
+/// streaming converters may for inputs that never end.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Approximation {
+    /// Algorithm used, such as [`SPACE_SAVING`].
+    pub algorithm: String,
+    /// Most distinct stacks tracked at once.
+    pub capacity: u64,
+}
+
+/// [`Approximation::algorithm`] for the Space-Saving top-k algorithm:
+/// while a stack is tracked its weights never fall below its true weights,
+/// and every stack with more than `1 / capacity` of an event's weight is
+/// tracked.
+pub const SPACE_SAVING: &str = "space_saving";
+
+/// How a profile's timestamps relate to wall-clock time.
+///
+/// With an `epoch_offset`, [`SpaaFile::rebase_to_wall_clock`] turns
+/// monotonic timestamps into Unix time, so samples can be lined up with
+/// logs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ClockInfo {
+    pub source: ClockSource,
+    /// Added to a timestamp, gives the time since the Unix epoch, in the
+    /// unit of the header's `time_range`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub epoch_offset: Option<f64>,
+    /// Timezone of the host the profile was recorded on, as an IANA name
+    /// (`Europe/Berlin`) or a UTC offset (`+02:00`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+impl ClockInfo {
+    /// A `source` clock with no known offset or timezone.
+    pub fn new(source: ClockSource) -> Self {
+        Self {
+            source,
+            epoch_offset: None,
+            timezone: None,
+        }
+    }
+
+    /// Offset from timestamps to Unix time, if known: the `epoch_offset`,
+    /// or zero for a wall clock without one.
+    pub fn wall_clock_offset(&self) -> Option<f64> {
+        match (self.epoch_offset, self.source) {
+            (Some(offset), _) => Some(offset),
+            (None, ClockSource::Wall) => Some(0.0),
+            (None, ClockSource::Monotonic) => None,
+        }
+    }
 }
 
 /// SPAA file header record.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Header {
     pub format: String,
     pub version: String,
@@ -336,6 +617,13 @@ pub struct Header {
     pub source: Option<SourceInfo>,
     #[serde(default = "default_stack_id_mode")]
     pub stack_id_mode: StackIdMode,
+    /// Clock the timestamps come from, when the converter knows it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<ClockInfo>,
+    /// Set when stack weights were aggregated approximately, in bounded
+    /// memory, rather than exactly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approximation: Option<Approximation>,
 }
 
 fn default_stack_id_mode() -> StackIdMode {
@@ -348,6 +636,7 @@ fn default_stack_id_mode() -> StackIdMode {
 
 /// DSO (Dynamic Shared Object) record.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Dso {
     pub id: u64,
     pub name: String,
@@ -358,7 +647,8 @@ pub struct Dso {
 }
 
 /// Frame kind classification.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum FrameKind {
     User,
@@ -368,6 +658,7 @@ pub enum FrameKind {
 
 /// Stack frame record.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Frame {
     pub id: u64,
     pub func: String,
@@ -400,6 +691,7 @@ fn default_frame_kind() -> FrameKind {
 
 /// Thread information record.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Thread {
     pub pid: u64,
     pub tid: u64,
@@ -412,22 +704,19 @@ pub struct Thread {
 // ============================================================================
 
 /// Stack type classification.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum StackType {
+    #[default]
     Unified,
     User,
     Kernel,
 }
 
-impl Default for StackType {
-    fn default() -> Self {
-        StackType::Unified
-    }
-}
-
 /// Weight measurement for a stack.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Weight {
     pub metric: String,
     pub value: u64,
@@ -437,6 +726,7 @@ pub struct Weight {
 
 /// DTrace probe context information.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ProbeContext {
     pub provider: String,
     #[serde(default)]
@@ -447,6 +737,7 @@ pub struct ProbeContext {
 
 /// Stack context metadata.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct StackContext {
     pub event: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -465,15 +756,26 @@ pub struct StackContext {
     pub uid: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub zonename: Option<String>,
+    /// Control group path, e.g. `/system.slice/docker-<id>.scope`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<String>,
+    /// Container ID, typically derived from the cgroup path.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_id: Option<String>,
+    /// Kubernetes pod identifier (the pod UID when derived from a cgroup).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_pod: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub trace_fields: Option<HashMap<String, serde_json::Value>>,
-    /// Extension fields not covered by standard schema.
+    /// Extension fields not covered by standard schema. See
+    /// [`extensions`] for namespaced, typed access.
     #[serde(flatten)]
     pub extra: HashMap<String, serde_json::Value>,
 }
 
 /// Exclusive weight attribution to leaf frame.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ExclusiveWeights {
     pub frame: u64,
     pub weights: Vec<Weight>,
@@ -481,6 +783,7 @@ pub struct ExclusiveWeights {
 
 /// Aggregated stack record.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Stack {
     pub id: String,
     pub frames: Vec<u64>,
@@ -494,12 +797,63 @@ pub struct Stack {
     pub related_stacks: Option<Vec<String>>,
 }
 
+impl Stack {
+    /// Add the weights of `other`, a stack with the same frames, to this
+    /// stack's.
+    ///
+    /// Exclusive weights are summed per frame. A stack without an
+    /// `exclusive` record contributes nothing to the other's, rather than
+    /// clearing it; exclusive weights attributed to a different frame than
+    /// this stack's are dropped, since a stack has only one leaf.
+    pub fn merge_weights(&mut self, other: &Stack) {
+        sum_weights(&mut self.weights, &other.weights);
+        if let Some(exclusive) = &other.exclusive {
+            let target = self.exclusive.get_or_insert_with(|| ExclusiveWeights {
+                frame: exclusive.frame,
+                weights: Vec::new(),
+            });
+            if target.frame == exclusive.frame {
+                sum_weights(&mut target.weights, &exclusive.weights);
+            }
+        }
+    }
+
+    /// The extension field `key` of this stack's context as a `T`, or
+    /// `None` if it is missing or not a `T`.
+    ///
+    /// ```
+    /// # use spaa_parse::SpaaFile;
+    /// # let data = r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}
+    /// # {"type":"dso","id":1,"name":"app","is_kernel":false}
+    /// # {"type":"frame","id":1,"func":"main","dso":1}
+    /// # {"type":"stack","id":"0x1","frames":[1],"context":{"event":"cycles","numa_node":1},"weights":[{"metric":"period","value":5}]}"#;
+    /// # let spaa = SpaaFile::parse(data.as_bytes()).unwrap();
+    /// let stack = &spaa.stacks["0x1"];
+    /// assert_eq!(stack.get_extra::<u32>("numa_node"), Some(1));
+    /// assert_eq!(stack.get_extra::<String>("numa_node"), None);
+    /// ```
+    pub fn get_extra<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        typed(self.context.extra.get(key)?)
+    }
+
+    /// The trace field `key` of this stack's context as a `T`, or `None` if
+    /// it is missing or not a `T`.
+    pub fn get_trace_field<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        typed(self.context.trace_fields.as_ref()?.get(key)?)
+    }
+}
+
+fn typed<T: DeserializeOwned>(value: &serde_json::Value) -> Option<T> {
+    T::deserialize(value).ok()
+}
+
 // ============================================================================
 // Optional record types
 // ============================================================================
 
 /// Raw sample record.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Sample {
     pub timestamp: f64,
     pub pid: u64,
@@ -513,8 +867,219 @@ pub struct Sample {
     pub context: HashMap<String, serde_json::Value>,
 }
 
+/// A run of consecutive samples from one thread, in a [`SampleBatch`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ThreadRun {
+    pub pid: u64,
+    pub tid: u64,
+    /// Number of consecutive samples taken on this thread.
+    pub count: usize,
+}
+
+/// Delta-encoded batch of samples of one event, the compact alternative to
+/// one [`Sample`] record per sample.
+///
+/// Sample `i` is taken at `start + (deltas[0] + ... + deltas[i]) *
+/// resolution`, on the thread of the [`ThreadRun`] covering it. Samples
+/// with a `context` cannot be batched.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SampleBatch {
+    pub event: String,
+    /// Timestamp the deltas are counted from.
+    pub start: f64,
+    /// Length of one delta tick, in the unit of sample timestamps.
+    pub resolution: f64,
+    /// Ticks since the previous sample, or since `start` for the first.
+    pub deltas: Vec<i64>,
+    pub stack_ids: Vec<String>,
+    pub threads: Vec<ThreadRun>,
+    pub cpus: Vec<u32>,
+    /// Per-sample periods; omitted when no sample has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub periods: Option<Vec<u64>>,
+}
+
+impl SampleBatch {
+    /// Encode `samples` as one batch, rounding timestamps to multiples of
+    /// `resolution` from the first sample.
+    ///
+    /// Returns `None` if `samples` is empty, mixes events, has a sample
+    /// with a `context`, or gives a period to some samples but not others.
+    pub fn encode(samples: &[Sample], resolution: f64) -> Option<Self> {
+        let first = samples.first()?;
+        let batchable = samples.iter().all(|s| {
+            s.event == first.event
+                && s.context.is_empty()
+                && s.period.is_some() == first.period.is_some()
+        });
+        if !batchable {
+            return None;
+        }
+
+        let mut threads: Vec<ThreadRun> = Vec::new();
+        let mut deltas = Vec::with_capacity(samples.len());
+        let mut previous = 0;
+        for sample in samples {
+            let ticks = ((sample.timestamp - first.timestamp) / resolution).round() as i64;
+            deltas.push(ticks - previous);
+            previous = ticks;
+            match threads.last_mut() {
+                Some(run) if run.pid == sample.pid && run.tid == sample.tid => run.count += 1,
+                _ => threads.push(ThreadRun {
+                    pid: sample.pid,
+                    tid: sample.tid,
+                    count: 1,
+                }),
+            }
+        }
+
+        Some(SampleBatch {
+            event: first.event.clone(),
+            start: first.timestamp,
+            resolution,
+            deltas,
+            stack_ids: samples.iter().map(|s| s.stack_id.clone()).collect(),
+            threads,
+            cpus: samples.iter().map(|s| s.cpu).collect(),
+            periods: first
+                .period
+                .map(|_| samples.iter().filter_map(|s| s.period).collect()),
+        })
+    }
+
+    /// Number of samples in the batch.
+    pub fn len(&self) -> usize {
+        self.stack_ids.len()
+    }
+
+    /// Whether the batch has no samples.
+    pub fn is_empty(&self) -> bool {
+        self.stack_ids.is_empty()
+    }
+
+    /// Check that every per-sample array, and the thread runs, cover the
+    /// same number of samples.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        let len = self.len();
+        let mut lengths = vec![
+            ("deltas", self.deltas.len()),
+            ("cpus", self.cpus.len()),
+            ("threads", self.threads.iter().map(|r| r.count).sum()),
+        ];
+        if let Some(periods) = &self.periods {
+            lengths.push(("periods", periods.len()));
+        }
+        for (field, field_len) in lengths {
+            if field_len != len {
+                return Err(format!(
+                    "{field} covers {field_len} samples but stack_ids has {len}"
+                ));
+            }
+        }
+        if !(self.resolution.is_finite() && self.resolution > 0.0) {
+            return Err(format!(
+                "resolution must be positive, got {}",
+                self.resolution
+            ));
+        }
+        Ok(())
+    }
+
+    /// The batch's samples, in order.
+    pub fn samples(&self) -> impl Iterator<Item = Sample> + '_ {
+        let threads = self
+            .threads
+            .iter()
+            .flat_map(|run| std::iter::repeat_n((run.pid, run.tid), run.count));
+        let mut ticks = 0i64;
+        self.deltas
+            .iter()
+            .zip(&self.stack_ids)
+            .zip(threads)
+            .zip(&self.cpus)
+            .enumerate()
+            .map(move |(i, (((&delta, stack_id), (pid, tid)), &cpu))| {
+                ticks += delta;
+                Sample {
+                    timestamp: self.start + ticks as f64 * self.resolution,
+                    pid,
+                    tid,
+                    cpu,
+                    event: self.event.clone(),
+                    period: self.periods.as_ref().and_then(|p| p.get(i).copied()),
+                    stack_id: stack_id.clone(),
+                    context: HashMap::new(),
+                }
+            })
+    }
+}
+
+/// Last record of a file written to completion, counting the records
+/// before it.
+///
+/// Footers are optional, but a writer that emits them lets readers tell a
+/// complete file from one cut short by a crash: a file that ends without
+/// one, or whose footer count is wrong, is incomplete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Footer {
+    /// Number of records before the footer, including the header.
+    pub records: u64,
+}
+
+/// The machine a profile was recorded on.
+///
+/// Hardware event counts only mean something for a known CPU, so
+/// converters fill in what the source tool records about the host.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SystemInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Operating system, e.g. `linux` or `macos`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel_version: Option<String>,
+    /// CPU architecture, e.g. `x86_64`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_model: Option<String>,
+    /// Online logical CPUs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_cores: Option<u32>,
+    /// Physical memory in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_bytes: Option<u64>,
+}
+
+/// A sample with its thread, stack and frames looked up, from
+/// [`SpaaFile::resolve_sample`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedSample<'a> {
+    pub timestamp: f64,
+    pub pid: u64,
+    pub tid: u64,
+    pub cpu: u32,
+    pub event: &'a str,
+    /// The sample's thread record, if the dictionary has one for its `pid`
+    /// and `tid`.
+    pub thread: Option<&'a Thread>,
+    /// Thread name, from the thread record or the stack's context.
+    pub comm: Option<&'a str>,
+    /// The sample's stack, or `None` if its ID is unknown.
+    pub stack: Option<&'a Stack>,
+    /// The stack's frames in the file's frame order, `None` where an ID is
+    /// not in the frame dictionary.
+    pub frames: Vec<Option<&'a Frame>>,
+}
+
 /// Stack weight within a time window.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct WindowStackWeight {
     pub stack_id: String,
     pub weights: Vec<Weight>,
@@ -522,6 +1087,7 @@ pub struct WindowStackWeight {
 
 /// Time window record.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Window {
     pub id: String,
     pub start: f64,
@@ -530,6 +1096,58 @@ pub struct Window {
     pub by_stack: Vec<WindowStackWeight>,
 }
 
+/// Scheduling state of a thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadStateKind {
+    /// On a CPU.
+    Running,
+    /// Ready to run but waiting for a CPU (run-queue latency).
+    Runnable,
+    /// Sleeping or waiting on I/O, locks or other events.
+    Blocked,
+}
+
+/// Thread state interval record.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ThreadState {
+    pub tid: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u64>,
+    pub state: ThreadStateKind,
+    pub start: f64,
+    pub end: f64,
+    /// CPU the thread ran on or was queued on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<u32>,
+    /// Tool-specific reason for the state, such as a Linux task state
+    /// letter (`S`, `D`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Stack the thread was in when it entered this state.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack_id: Option<String>,
+}
+
+/// A finding or comment attached to a profile, so investigation notes
+/// travel with the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Note {
+    pub author: String,
+    /// When the note was written, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub text: String,
+    /// Stack the note is about.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack_id: Option<String>,
+    /// Frame the note is about.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_id: Option<u64>,
+}
+
 // ============================================================================
 // Internal parsing types
 // ============================================================================
@@ -548,6 +1166,13 @@ struct HeaderRecord {
     header: Header,
 }
 
+/// System record with type field for parsing.
+#[derive(Debug, Deserialize)]
+struct SystemRecord {
+    #[serde(flatten)]
+    system: SystemInfo,
+}
+
 /// DSO record with type field for parsing.
 #[derive(Debug, Deserialize)]
 struct DsoRecord {
@@ -583,6 +1208,27 @@ struct SampleRecord {
     sample: Sample,
 }
 
+/// Sample batch record with type field for parsing.
+#[derive(Debug, Deserialize)]
+struct SamplesRecord {
+    #[serde(flatten)]
+    batch: SampleBatch,
+}
+
+/// Note record with type field for parsing.
+#[derive(Debug, Deserialize)]
+struct NoteRecord {
+    #[serde(flatten)]
+    note: Note,
+}
+
+/// Footer record with type field for parsing.
+#[derive(Debug, Deserialize)]
+struct FooterRecord {
+    #[serde(flatten)]
+    footer: Footer,
+}
+
 /// Window record with type field for parsing.
 #[derive(Debug, Deserialize)]
 struct WindowRecord {
@@ -590,197 +1236,1670 @@ struct WindowRecord {
     window: Window,
 }
 
+/// Thread state record with type field for parsing.
+#[derive(Debug, Deserialize)]
+struct StateRecord {
+    #[serde(flatten)]
+    state: ThreadState,
+}
+
 // ============================================================================
-// Main SpaaFile type
+// Streaming reader
 // ============================================================================
 
-/// A parsed SPAA file containing all profiling data.
-#[derive(Debug, Clone)]
-pub struct SpaaFile {
-    /// File header with metadata and event definitions.
-    pub header: Header,
-    /// DSO dictionary, keyed by DSO ID.
-    pub dsos: HashMap<u64, Dso>,
-    /// Frame dictionary, keyed by frame ID.
-    pub frames: HashMap<u64, Frame>,
-    /// Thread dictionary, keyed by thread ID.
-    pub threads: HashMap<u64, Thread>,
-    /// Stack records, keyed by stack ID.
-    pub stacks: HashMap<String, Stack>,
-    /// Raw sample records (optional).
-    pub samples: Vec<Sample>,
-    /// Time window records (optional).
-    pub windows: Vec<Window>,
+/// A single SPAA record, as yielded by [`SpaaReader`].
+// Stacks dominate most files, so keep them unboxed rather than paying an
+// extra allocation per record to shrink the rare variants.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    Header(Header),
+    System(SystemInfo),
+    Dso(Dso),
+    Frame(Frame),
+    Thread(Thread),
+    Stack(Stack),
+    Sample(Sample),
+    /// A delta-encoded `samples` batch, kept encoded; see
+    /// [`SampleBatch::samples`].
+    Samples(SampleBatch),
+    Window(Window),
+    State(ThreadState),
+    Note(Note),
+    Footer(Footer),
 }
 
-impl SpaaFile {
-    /// Parse a SPAA file from any `Read`-able source.
-    pub fn parse<R: Read>(reader: R) -> Result<Self> {
-        let buf_reader = BufReader::new(reader);
-        let mut header: Option<Header> = None;
-        let mut dsos: HashMap<u64, Dso> = HashMap::new();
-        let mut frames: HashMap<u64, Frame> = HashMap::new();
-        let mut threads: HashMap<u64, Thread> = HashMap::new();
-        let mut stacks: HashMap<String, Stack> = HashMap::new();
-        let mut samples: Vec<Sample> = Vec::new();
-        let mut windows: Vec<Window> = Vec::new();
+/// Streaming, record-at-a-time SPAA reader.
+///
+/// Unlike [`SpaaFile::parse`], the reader holds only the current line in
+/// memory, so it can scan files far larger than RAM. It enforces the header
+/// rules (exactly one header, on the first line) but does not validate
+/// cross-record references.
+///
+/// ```
+/// use std::io::Cursor;
+/// use spaa_parse::{Record, SpaaReader};
+///
+/// let data = r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[]}
+/// {"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#;
+/// let mut dsos = 0;
+/// for record in SpaaReader::new(Cursor::new(data)) {
+///     if let Record::Dso(_) = record.unwrap() {
+///         dsos += 1;
+///     }
+/// }
+/// assert_eq!(dsos, 1);
+/// ```
+pub struct SpaaReader<R> {
+    reader: R,
+    line: Vec<u8>,
+    decoder: LineDecoder,
+    bytes_read: u64,
+    done: bool,
+}
 
-        for (line_num, line_result) in buf_reader.lines().enumerate() {
-            let line_num = line_num + 1; // 1-indexed for error messages
-            let line = line_result?;
+impl<R: BufRead> SpaaReader<R> {
+    /// Create a reader over buffered input.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: Vec::new(),
+            decoder: LineDecoder::default(),
+            bytes_read: 0,
+            done: false,
+        }
+    }
 
-            // Skip empty lines
-            if line.trim().is_empty() {
-                continue;
-            }
+    /// Line number (1-indexed) of the most recently read line.
+    pub fn line_number(&self) -> usize {
+        self.decoder.line_num
+    }
 
-            // First, determine the record type
-            let raw: RawRecord = serde_json::from_str(&line).map_err(|e| ParseError::Json {
-                line: line_num,
-                source: e,
-            })?;
+    /// Line number and starting byte offset of the most recently read
+    /// line.
+    pub fn location(&self) -> Location {
+        self.decoder.location()
+    }
 
-            match raw.record_type.as_str() {
-                "header" => {
-                    if header.is_some() {
-                        return Err(ParseError::DuplicateHeader(line_num));
-                    }
-                    if line_num != 1 {
-                        return Err(ParseError::HeaderNotFirst(line_num));
-                    }
-                    let record: HeaderRecord =
-                        serde_json::from_str(&line).map_err(|e| ParseError::Json {
-                            line: line_num,
-                            source: e,
-                        })?;
-                    header = Some(record.header);
-                }
-                _ if header.is_none() => {
-                    // First non-empty line must be a header
-                    return Err(ParseError::HeaderNotFirst(line_num));
-                }
-                "dso" => {
-                    let record: DsoRecord =
-                        serde_json::from_str(&line).map_err(|e| ParseError::Json {
-                            line: line_num,
-                            source: e,
-                        })?;
-                    dsos.insert(record.dso.id, record.dso);
-                }
-                "frame" => {
-                    let record: FrameRecord =
-                        serde_json::from_str(&line).map_err(|e| ParseError::Json {
-                            line: line_num,
-                            source: e,
-                        })?;
-                    frames.insert(record.frame.id, record.frame);
-                }
-                "thread" => {
-                    let record: ThreadRecord =
-                        serde_json::from_str(&line).map_err(|e| ParseError::Json {
-                            line: line_num,
-                            source: e,
-                        })?;
-                    threads.insert(record.thread.tid, record.thread);
-                }
-                "stack" => {
-                    let record: StackRecord =
-                        serde_json::from_str(&line).map_err(|e| ParseError::Json {
-                            line: line_num,
-                            source: e,
-                        })?;
-                    stacks.insert(record.stack.id.clone(), record.stack);
-                }
-                "sample" => {
-                    let record: SampleRecord =
-                        serde_json::from_str(&line).map_err(|e| ParseError::Json {
-                            line: line_num,
-                            source: e,
-                        })?;
-                    samples.push(record.sample);
-                }
-                "window" => {
-                    let record: WindowRecord =
-                        serde_json::from_str(&line).map_err(|e| ParseError::Json {
-                            line: line_num,
-                            source: e,
-                        })?;
-                    windows.push(record.window);
-                }
-                other => {
-                    return Err(ParseError::UnknownRecordType(other.to_string(), line_num));
-                }
+    /// Number of bytes consumed so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn read_record(&mut self) -> Result<Option<Record>> {
+        loop {
+            // Reuse one buffer for every line; serde_json validates UTF-8
+            // only inside strings, so no up-front String conversion.
+            self.line.clear();
+            let n = self.reader.read_until(b'\n', &mut self.line)?;
+            if n == 0 {
+                return self.decoder.finish().map(|()| None);
+            }
+            self.bytes_read += n as u64;
+            if let Some(record) = self.decoder.decode(&self.line)? {
+                return Ok(Some(record));
             }
         }
+    }
+}
 
-        let header = header.ok_or(ParseError::MissingHeader)?;
+impl<R: BufRead> Iterator for SpaaReader<R> {
+    type Item = Result<Record>;
 
-        let file = SpaaFile {
-            header,
-            dsos,
-            frames,
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_record().transpose();
+        // Stop at the end of input, or after the first error; the stream
+        // position is no longer meaningful.
+        self.done = !matches!(result, Some(Ok(_)));
+        result
+    }
+}
+
+/// Zero-copy SPAA reader over an in-memory buffer.
+///
+/// Yields the same records as [`SpaaReader`], but decodes each line in
+/// place with [`serde_json::from_slice`] instead of copying it into a
+/// line buffer first. Pair it with a memory-mapped file (see
+/// `SpaaFile::open_mmap`, behind the `mmap` feature) to scan large files
+/// without reading them into memory.
+///
+/// ```
+/// use spaa_parse::{Record, SpaaSliceReader};
+///
+/// let data = br#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[]}
+/// {"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#;
+/// let records: Vec<_> = SpaaSliceReader::new(data).collect::<Result<_, _>>().unwrap();
+/// assert!(matches!(records[1], Record::Dso(_)));
+/// ```
+pub struct SpaaSliceReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    decoder: LineDecoder,
+    done: bool,
+}
+
+impl<'a> SpaaSliceReader<'a> {
+    /// Create a reader over `data`.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            decoder: LineDecoder::default(),
+            done: false,
+        }
+    }
+
+    /// Line number (1-indexed) of the most recently read line.
+    pub fn line_number(&self) -> usize {
+        self.decoder.line_num
+    }
+
+    /// Line number and starting byte offset of the most recently read
+    /// line.
+    pub fn location(&self) -> Location {
+        self.decoder.location()
+    }
+
+    /// Number of bytes consumed so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.pos as u64
+    }
+
+    fn read_record(&mut self) -> Result<Option<Record>> {
+        while self.pos < self.data.len() {
+            let rest = &self.data[self.pos..];
+            let len = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(rest.len(), |i| i + 1);
+            self.pos += len;
+            if let Some(record) = self.decoder.decode(&rest[..len])? {
+                return Ok(Some(record));
+            }
+        }
+        self.decoder.finish().map(|()| None)
+    }
+}
+
+impl Iterator for SpaaSliceReader<'_> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_record().transpose();
+        self.done = !matches!(result, Some(Ok(_)));
+        result
+    }
+}
+
+/// A record iterator that knows how far into its input it is.
+trait RecordSource: Iterator<Item = Result<Record>> {
+    fn bytes_read(&self) -> u64;
+
+    /// Location of the record last returned.
+    fn location(&self) -> Location;
+
+    /// Carry on past a record that failed to decode, whose line has been
+    /// consumed.
+    fn resume(&mut self);
+}
+
+impl<R: BufRead> RecordSource for SpaaReader<R> {
+    fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    fn location(&self) -> Location {
+        self.decoder.location()
+    }
+
+    fn resume(&mut self) {
+        self.done = false;
+    }
+}
+
+impl RecordSource for SpaaSliceReader<'_> {
+    fn bytes_read(&self) -> u64 {
+        self.pos as u64
+    }
+
+    fn location(&self) -> Location {
+        self.decoder.location()
+    }
+
+    fn resume(&mut self) {
+        self.done = false;
+    }
+}
+
+/// Line-by-line record decoding shared by the readers, enforcing the
+/// header rules.
+#[derive(Debug, Clone, Default)]
+struct LineDecoder {
+    line_num: usize,
+    /// Byte offset of the start of the current line.
+    line_start: u64,
+    /// Bytes decoded so far.
+    bytes: u64,
+    seen_header: bool,
+    /// Records decoded so far, to check a footer against.
+    records: u64,
+    seen_footer: bool,
+}
+
+impl LineDecoder {
+    /// Decode one line (with or without its trailing newline). Returns
+    /// `None` for blank lines.
+    ///
+    /// A UTF-8 byte order mark before the first line is skipped, and CRLF
+    /// line endings and trailing whitespace are allowed, since JSON treats
+    /// `\r` as whitespace.
+    fn decode(&mut self, mut line: &[u8]) -> Result<Option<Record>> {
+        self.line_num += 1;
+        self.line_start = self.bytes;
+        self.bytes += line.len() as u64;
+        if self.line_num == 1 {
+            // UTF-16 has a byte order mark or, for ASCII text, a NUL byte
+            // next to every character.
+            if line.starts_with(&[0xFF, 0xFE])
+                || line.starts_with(&[0xFE, 0xFF])
+                || line.contains(&0)
+            {
+                return Err(ParseError::Utf16Input);
+            }
+            line = line.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(line);
+        }
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(None);
+        }
+        let line_num = self.line_num;
+        let location = self.location();
+        if self.seen_footer {
+            return Err(ParseError::RecordAfterFooter(line_num));
+        }
+
+        // First, determine the record type
+        let raw: RawRecord = decode(line, location)?;
+
+        let record = match raw.record_type.as_str() {
+            "header" => {
+                if self.seen_header {
+                    return Err(ParseError::DuplicateHeader(line_num));
+                }
+                if line_num != 1 {
+                    return Err(ParseError::HeaderNotFirst(line_num));
+                }
+                self.seen_header = true;
+                Record::Header(decode::<HeaderRecord>(line, location)?.header)
+            }
+            _ if !self.seen_header => {
+                // First non-empty line must be a header
+                return Err(ParseError::HeaderNotFirst(line_num));
+            }
+            "system" => Record::System(decode::<SystemRecord>(line, location)?.system),
+            "dso" => Record::Dso(decode::<DsoRecord>(line, location)?.dso),
+            "frame" => Record::Frame(decode::<FrameRecord>(line, location)?.frame),
+            "thread" => Record::Thread(decode::<ThreadRecord>(line, location)?.thread),
+            "stack" => Record::Stack(decode::<StackRecord>(line, location)?.stack),
+            "sample" => Record::Sample(decode::<SampleRecord>(line, location)?.sample),
+            "samples" => {
+                let batch = decode::<SamplesRecord>(line, location)?.batch;
+                batch
+                    .validate()
+                    .map_err(|reason| ParseError::InvalidSampleBatch {
+                        line: line_num,
+                        offset: location.offset,
+                        reason,
+                    })?;
+                Record::Samples(batch)
+            }
+            "window" => Record::Window(decode::<WindowRecord>(line, location)?.window),
+            "state" => Record::State(decode::<StateRecord>(line, location)?.state),
+            "note" => Record::Note(decode::<NoteRecord>(line, location)?.note),
+            "footer" => {
+                let footer = decode::<FooterRecord>(line, location)?.footer;
+                if footer.records != self.records {
+                    return Err(ParseError::FooterMismatch {
+                        line: line_num,
+                        expected: footer.records,
+                        found: self.records,
+                    });
+                }
+                self.seen_footer = true;
+                Record::Footer(footer)
+            }
+            other => {
+                return Err(ParseError::UnknownRecordType(other.to_string(), line_num));
+            }
+        };
+        self.records += 1;
+        Ok(Some(record))
+    }
+
+    /// Location of the most recently decoded line.
+    fn location(&self) -> Location {
+        Location {
+            line: self.line_num,
+            offset: self.line_start,
+        }
+    }
+
+    /// Check the input as a whole once it is exhausted.
+    fn finish(&self) -> Result<()> {
+        if self.seen_header {
+            Ok(())
+        } else {
+            Err(ParseError::MissingHeader)
+        }
+    }
+}
+
+fn decode<T: serde::de::DeserializeOwned>(line: &[u8], location: Location) -> Result<T> {
+    serde_json::from_slice(line).map_err(|e| ParseError::Json {
+        line: location.line,
+        offset: location.offset,
+        source: e,
+    })
+}
+
+/// Check a stack's weights: exclusive weights never exceed inclusive ones,
+/// and the primary metric of its event is present.
+fn check_stack(
+    stack: &Stack,
+    primary_metrics: &HashMap<String, String>,
+    location: Location,
+) -> Result<()> {
+    if let Some(exclusive) = &stack.exclusive {
+        for weight in &exclusive.weights {
+            if let Some(inclusive) = stack.weights.iter().find(|w| w.metric == weight.metric)
+                && weight.value > inclusive.value
+            {
+                return Err(ParseError::ExclusiveExceedsInclusive {
+                    stack_id: stack.id.clone(),
+                    metric: weight.metric.clone(),
+                    exclusive: weight.value,
+                    inclusive: inclusive.value,
+                    location,
+                });
+            }
+        }
+    }
+
+    if let Some(primary_metric) = primary_metrics.get(&stack.context.event)
+        && !stack.weights.iter().any(|w| w.metric == *primary_metric)
+    {
+        return Err(ParseError::MissingPrimaryMetric {
+            stack_id: stack.id.clone(),
+            metric: primary_metric.clone(),
+            location,
+        });
+    }
+    Ok(())
+}
+
+/// References to records that had not been read yet when the referring
+/// record was, to check once the whole file is in. Writers put dictionaries
+/// first, so these are normally empty.
+#[derive(Debug, Default)]
+struct PendingReferences {
+    /// Frame ID, DSO ID.
+    dsos: Vec<(u64, u64, Location)>,
+    /// Stack ID, frame ID.
+    frames: Vec<(String, u64, Location)>,
+    /// Record type, stack ID.
+    stacks: Vec<(&'static str, String, Location)>,
+    /// Frame IDs of notes.
+    note_frames: Vec<(u64, Location)>,
+}
+
+impl PendingReferences {
+    fn check(self, file: &SpaaFile) -> Result<()> {
+        if let Some((frame_id, dso_id, location)) = self
+            .dsos
+            .into_iter()
+            .find(|(_, dso_id, _)| !file.dsos.contains_key(dso_id))
+        {
+            return Err(ParseError::InvalidDsoReference {
+                frame_id,
+                dso_id,
+                location,
+            });
+        }
+        if let Some((stack_id, frame_id, location)) = self
+            .frames
+            .into_iter()
+            .find(|(_, frame_id, _)| !file.frames.contains_key(frame_id))
+        {
+            return Err(ParseError::InvalidFrameReference {
+                stack_id,
+                frame_id,
+                location,
+            });
+        }
+        if let Some((record, stack_id, location)) = self
+            .stacks
+            .into_iter()
+            .find(|(_, stack_id, _)| !file.stacks.contains_key(stack_id))
+        {
+            return Err(ParseError::InvalidStackReference {
+                record,
+                stack_id,
+                location,
+            });
+        }
+        if let Some((frame_id, location)) = self
+            .note_frames
+            .into_iter()
+            .find(|(frame_id, _)| !file.frames.contains_key(frame_id))
+        {
+            return Err(ParseError::InvalidNoteFrameReference { frame_id, location });
+        }
+        Ok(())
+    }
+
+    /// Drop the records [`PendingReferences::check`] would fail on, and the
+    /// records that depended on them, with a warning for each.
+    fn prune(self, file: &mut SpaaFile, warnings: &mut Vec<ParseWarning>) {
+        for (frame_id, dso_id, location) in self.dsos {
+            if !file.dsos.contains_key(&dso_id) && file.frames.remove(&frame_id).is_some() {
+                warnings.push(ParseWarning::from(&ParseError::InvalidDsoReference {
+                    frame_id,
+                    dso_id,
+                    location,
+                }));
+            }
+        }
+
+        // Stacks may also refer to frames dropped above, which were never
+        // pending; those are reported without a location.
+        let locations: HashMap<&str, Location> = self
+            .frames
+            .iter()
+            .map(|(stack_id, _, location)| (stack_id.as_str(), *location))
+            .collect();
+        let mut dangling: Vec<(String, u64)> = file
+            .stacks
+            .values()
+            .filter_map(|stack| {
+                let missing = stack
+                    .frames
+                    .iter()
+                    .find(|id| !file.frames.contains_key(id))?;
+                Some((stack.id.clone(), *missing))
+            })
+            .collect();
+        dangling.sort();
+        let mut removed: HashSet<String> = HashSet::new();
+        for (stack_id, frame_id) in dangling {
+            file.stacks.remove(&stack_id);
+            removed.insert(stack_id.clone());
+            warnings.push(match locations.get(stack_id.as_str()) {
+                Some(&location) => ParseWarning::from(&ParseError::InvalidFrameReference {
+                    stack_id,
+                    frame_id,
+                    location,
+                }),
+                None => ParseWarning {
+                    location: None,
+                    message: format!(
+                        "stack {stack_id} references frame {frame_id}, which was skipped"
+                    ),
+                },
+            });
+        }
+
+        for (record, stack_id, location) in self.stacks {
+            if !file.stacks.contains_key(&stack_id) {
+                warnings.push(ParseWarning::from(&ParseError::InvalidStackReference {
+                    record,
+                    stack_id,
+                    location,
+                }));
+            }
+        }
+        let stacks = &file.stacks;
+        let mut orphans: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut keep = |stack_id: &str| {
+            let found = stacks.contains_key(stack_id);
+            if let Some(skipped) = removed.get(stack_id) {
+                *orphans.entry(skipped.as_str()).or_default() += 1;
+            }
+            found
+        };
+        file.samples.retain(|sample| keep(&sample.stack_id));
+        file.states
+            .retain(|state| state.stack_id.as_deref().is_none_or(&mut keep));
+        file.notes
+            .retain(|note| note.stack_id.as_deref().is_none_or(&mut keep));
+        for (stack_id, count) in orphans {
+            warnings.push(ParseWarning {
+                location: None,
+                message: format!(
+                    "{count} samples, thread states or notes refer to stack {stack_id}, which was skipped"
+                ),
+            });
+        }
+
+        for (frame_id, location) in self.note_frames {
+            if !file.frames.contains_key(&frame_id) {
+                warnings.push(ParseWarning::from(&ParseError::InvalidNoteFrameReference {
+                    frame_id,
+                    location,
+                }));
+            }
+        }
+        let frames = &file.frames;
+        file.notes
+            .retain(|note| note.frame_id.is_none_or(|id| frames.contains_key(&id)));
+    }
+}
+
+// ============================================================================
+// Progress reporting
+// ============================================================================
+
+/// A progress update from a long-running operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// What is being done, such as `"parse"` or `"merge"`.
+    pub stage: &'static str,
+    /// Units of work completed: bytes when reading input, items otherwise.
+    pub done: u64,
+    /// Total units of work, when known.
+    pub total: Option<u64>,
+}
+
+impl Progress {
+    /// Completed fraction in `0.0..=1.0`, when the total is known.
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total {
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.done as f64 / total as f64).min(1.0)),
+            None => None,
+        }
+    }
+}
+
+/// Error returned when a [`ProgressSink`] cancels an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("operation cancelled")]
+pub struct Cancelled;
+
+/// Receiver of [`Progress`] updates.
+///
+/// Operations report periodically, not per item, so sinks can render
+/// directly. Returning [`ControlFlow::Break`] cancels the operation, which
+/// then fails with a [`Cancelled`] error. Closures taking `&Progress` are
+/// sinks.
+pub trait ProgressSink {
+    /// Receive an update; break to cancel the operation.
+    fn report(&mut self, progress: &Progress) -> ControlFlow<()>;
+
+    /// Report progress, turning a cancellation into an error.
+    fn update(
+        &mut self,
+        stage: &'static str,
+        done: u64,
+        total: Option<u64>,
+    ) -> std::result::Result<(), Cancelled> {
+        match self.report(&Progress { stage, done, total }) {
+            ControlFlow::Continue(()) => Ok(()),
+            ControlFlow::Break(()) => Err(Cancelled),
+        }
+    }
+}
+
+impl<F: FnMut(&Progress) -> ControlFlow<()>> ProgressSink for F {
+    fn report(&mut self, progress: &Progress) -> ControlFlow<()> {
+        self(progress)
+    }
+}
+
+/// A sink that ignores progress and never cancels.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn report(&mut self, _progress: &Progress) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+/// A sink that cancels the operation once a time limit has passed, for
+/// services that must not block on oversized inputs.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    at: Instant,
+}
+
+impl Deadline {
+    /// Cancel operations still running `timeout` from now.
+    pub fn after(timeout: Duration) -> Self {
+        Self {
+            at: Instant::now() + timeout,
+        }
+    }
+}
+
+impl ProgressSink for Deadline {
+    fn report(&mut self, _progress: &Progress) -> ControlFlow<()> {
+        if Instant::now() >= self.at {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+}
+
+/// A handle for cancelling operations from another thread, such as a
+/// request handler whose client has gone away.
+///
+/// Clones share one flag: once [`CancelToken::cancel`] is called on any
+/// of them, operations passed the token stop at their next check and fail
+/// with [`Cancelled`]. A token is also a [`ProgressSink`], so it can be
+/// handed to every `_with_progress` function.
+///
+/// ```
+/// use spaa_parse::{CancelToken, ParseError, ParseOptions, SpaaFile};
+///
+/// let token = CancelToken::new();
+/// token.clone().cancel();
+/// let data = r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[]}"#;
+/// let result = SpaaFile::parse_with_progress(data.as_bytes(), &ParseOptions::default(), &mut token.clone());
+/// assert!(matches!(result, Err(ParseError::Cancelled(_))));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every operation using this token or a clone of it.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fail with [`Cancelled`] if the token has been cancelled.
+    pub fn check(&self) -> std::result::Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl ProgressSink for CancelToken {
+    fn report(&mut self, _progress: &Progress) -> ControlFlow<()> {
+        if self.is_cancelled() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+}
+
+/// Bytes of input between progress updates while parsing.
+const PARSE_PROGRESS_INTERVAL: u64 = 1 << 20;
+
+// ============================================================================
+// Parse options and diagnostics
+// ============================================================================
+
+/// Function name (and DSO name) of the synthetic frame that stands in for
+/// frames removed by stack truncation.
+pub const TRUNCATED_FRAME_NAME: &str = "[truncated]";
+
+/// Options controlling how [`SpaaFile::parse_with_options`] processes a file.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Maximum number of real frames to keep per stack.
+    ///
+    /// Stacks deeper than this keep their `max_stack_depth` leaf-most frames,
+    /// followed by a synthetic [`TRUNCATED_FRAME_NAME`] frame in place of the
+    /// dropped root-side frames. Deeply recursive stacks (10k+ frames) are
+    /// otherwise expensive for downstream call-tree building.
+    pub max_stack_depth: Option<usize>,
+
+    /// Total input size in bytes, if known. Only used as the `total` of
+    /// progress updates from [`SpaaFile::parse_with_progress`].
+    pub size_hint: Option<u64>,
+
+    /// Fill in thread names missing from thread records or stack contexts
+    /// from each other, joined on `pid` and `tid`.
+    ///
+    /// See [`SpaaFile::fill_thread_comms`] and
+    /// [`SpaaFile::fill_stack_comms`].
+    pub enrich_threads: bool,
+
+    /// Skip records that fail to decode or validate, such as stacks missing
+    /// their primary metric or referencing undefined frames, instead of
+    /// failing on the first one. Each skipped record is reported as a
+    /// [`ParseWarning`] in [`ParseDiagnostics::warnings`].
+    ///
+    /// I/O errors, UTF-16 input and a missing header still fail.
+    pub lenient: bool,
+}
+
+/// Non-fatal information collected while parsing a file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseDiagnostics {
+    /// Number of stacks that were truncated to `max_stack_depth`.
+    pub truncated_stacks: usize,
+
+    /// Number of thread records added or named by `enrich_threads`.
+    pub enriched_threads: usize,
+
+    /// Number of stack contexts named by `enrich_threads`.
+    pub enriched_stacks: usize,
+
+    /// Whether the file ends with a [`Footer`], so it was written to
+    /// completion.
+    pub has_footer: bool,
+
+    /// Records skipped by [`ParseOptions::lenient`] parsing, in the order
+    /// they were found.
+    pub warnings: Vec<ParseWarning>,
+}
+
+/// A record skipped by [`ParseOptions::lenient`] parsing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseWarning {
+    /// Where the record is, when known. Records dropped because a record
+    /// they refer to was skipped have no location.
+    pub location: Option<Location>,
+    /// What was wrong with the record.
+    pub message: String,
+}
+
+impl From<&ParseError> for ParseWarning {
+    fn from(error: &ParseError) -> Self {
+        Self {
+            location: error.location(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Truncate a frame sequence to its `max_depth` leaf-most entries.
+///
+/// The dropped root-side entries are replaced by a single `sentinel`, placed
+/// at the root end according to `frame_order`. Returns `true` if the frames
+/// were truncated.
+///
+/// ```
+/// use spaa_parse::{FrameOrder, truncate_frames};
+///
+/// let mut frames = vec![1, 2, 3, 4, 5];
+/// assert!(truncate_frames(&mut frames, 2, FrameOrder::LeafToRoot, 0));
+/// assert_eq!(frames, vec![1, 2, 0]);
+/// ```
+pub fn truncate_frames<T>(
+    frames: &mut Vec<T>,
+    max_depth: usize,
+    frame_order: FrameOrder,
+    sentinel: T,
+) -> bool {
+    if frames.len() <= max_depth {
+        return false;
+    }
+    match frame_order {
+        FrameOrder::LeafToRoot => {
+            frames.truncate(max_depth);
+            frames.push(sentinel);
+        }
+        FrameOrder::RootToLeaf => {
+            frames.drain(..frames.len() - max_depth);
+            frames.insert(0, sentinel);
+        }
+    }
+    true
+}
+
+/// Rough bytes of NDJSON per LLM token, used by
+/// [`SpaaFile::estimate_tokens`].
+///
+/// JSON with short keys and hex IDs tokenizes at roughly three to four bytes
+/// per token across common tokenizers; four errs towards under-counting.
+pub const BYTES_PER_TOKEN: u64 = 4;
+
+/// A function's total weight across a profile, from
+/// [`SpaaFile::function_weights`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FunctionWeights {
+    pub func: String,
+    /// Weight of the stacks the function appears in.
+    pub inclusive: u64,
+    /// Weight attributed to the function as the leaf of a stack.
+    pub exclusive: u64,
+}
+
+/// Serialized size of each section of a file, in bytes of NDJSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SectionSizes {
+    pub header: u64,
+    pub dsos: u64,
+    pub frames: u64,
+    pub threads: u64,
+    pub stacks: u64,
+    pub samples: u64,
+    pub windows: u64,
+    pub states: u64,
+    pub notes: u64,
+}
+
+impl SectionSizes {
+    /// Size of the whole file.
+    pub fn total(&self) -> u64 {
+        self.header
+            + self.dsos
+            + self.frames
+            + self.threads
+            + self.stacks
+            + self.samples
+            + self.windows
+            + self.states
+            + self.notes
+    }
+}
+
+// ============================================================================
+// Main SpaaFile type
+// ============================================================================
+
+/// A parsed SPAA file containing all profiling data.
+///
+/// `==` compares IDs and record order exactly as stored; use
+/// [`SpaaFile::semantically_eq`] to ignore them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpaaFile {
+    /// File header with metadata and event definitions.
+    pub header: Header,
+    /// The machine the profile was recorded on (optional).
+    pub system: Option<SystemInfo>,
+    /// DSO dictionary, keyed by DSO ID.
+    pub dsos: HashMap<u64, Dso>,
+    /// Frame dictionary, keyed by frame ID.
+    pub frames: HashMap<u64, Frame>,
+    /// Thread dictionary, keyed by thread ID.
+    pub threads: HashMap<u64, Thread>,
+    /// Stack records, keyed by stack ID.
+    pub stacks: HashMap<String, Stack>,
+    /// Raw sample records (optional).
+    pub samples: Vec<Sample>,
+    /// Time window records (optional).
+    pub windows: Vec<Window>,
+    /// Thread state intervals (optional).
+    pub states: Vec<ThreadState>,
+    /// Investigation notes, in the order they were written (optional).
+    pub notes: Vec<Note>,
+}
+
+impl SpaaFile {
+    /// Parse a SPAA file from any `Read`-able source.
+    pub fn parse<R: Read>(reader: R) -> Result<Self> {
+        Self::parse_with_options(reader, &ParseOptions::default()).map(|(file, _)| file)
+    }
+
+    /// Parse a SPAA file with custom [`ParseOptions`].
+    ///
+    /// Returns the parsed file along with diagnostics describing any
+    /// adjustments made while parsing.
+    pub fn parse_with_options<R: Read>(
+        reader: R,
+        options: &ParseOptions,
+    ) -> Result<(Self, ParseDiagnostics)> {
+        Self::parse_with_progress(reader, options, &mut NoProgress)
+    }
+
+    /// Parse a SPAA file, reporting bytes read to `progress` (stage
+    /// `"parse"`) as it goes.
+    ///
+    /// Fails with [`ParseError::Cancelled`] if the sink cancels.
+    pub fn parse_with_progress<R: Read>(
+        reader: R,
+        options: &ParseOptions,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<(Self, ParseDiagnostics)> {
+        Self::build(
+            SpaaReader::new(BufReader::new(reader)),
+            options.size_hint,
+            options,
+            progress,
+        )
+    }
+
+    /// Parse a SPAA file held in memory.
+    ///
+    /// Lines are decoded in place, without the per-line copy that
+    /// [`SpaaFile::parse`] makes.
+    pub fn parse_slice(data: &[u8]) -> Result<Self> {
+        Self::parse_slice_with_progress(data, &ParseOptions::default(), &mut NoProgress)
+            .map(|(file, _)| file)
+    }
+
+    /// Parse a SPAA file held in memory with custom [`ParseOptions`],
+    /// reporting progress as [`SpaaFile::parse_with_progress`] does.
+    pub fn parse_slice_with_progress(
+        data: &[u8],
+        options: &ParseOptions,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<(Self, ParseDiagnostics)> {
+        let total = options.size_hint.or(Some(data.len() as u64));
+        Self::build(SpaaSliceReader::new(data), total, options, progress)
+    }
+
+    /// Read and parse the SPAA file at `path`.
+    ///
+    /// The file is read through a buffer, so it is safe to open one that
+    /// another process is still writing.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        Self::open_with_progress(path, &ParseOptions::default(), &mut NoProgress)
+            .map(|(file, _)| file)
+    }
+
+    /// Read and parse the SPAA file at `path` with custom [`ParseOptions`],
+    /// reporting progress against the file's size as it goes.
+    pub fn open_with_progress<P: AsRef<std::path::Path>>(
+        path: P,
+        options: &ParseOptions,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<(Self, ParseDiagnostics)> {
+        let file = std::fs::File::open(path)?;
+        let options = ParseOptions {
+            size_hint: options.size_hint.or(Some(file.metadata()?.len())),
+            ..options.clone()
+        };
+        Self::parse_with_progress(file, &options, progress)
+    }
+
+    /// Memory-map and parse the SPAA file at `path`.
+    ///
+    /// Avoids reading the file through a buffer, which is noticeably faster
+    /// for large files.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be truncated or modified, by this or any other
+    /// process, until this returns; otherwise reading the map is undefined
+    /// behavior. Use [`SpaaFile::open`] for files that may still be
+    /// growing, such as the output of a streaming converter.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn open_mmap<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        // SAFETY: passed on to the caller.
+        unsafe { Self::open_mmap_with_progress(path, &ParseOptions::default(), &mut NoProgress) }
+            .map(|(file, _)| file)
+    }
+
+    /// Memory-map and parse the SPAA file at `path` with custom
+    /// [`ParseOptions`], reporting progress as it goes.
+    ///
+    /// # Safety
+    ///
+    /// As for [`SpaaFile::open_mmap`].
+    #[cfg(feature = "mmap")]
+    pub unsafe fn open_mmap_with_progress<P: AsRef<std::path::Path>>(
+        path: P,
+        options: &ParseOptions,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<(Self, ParseDiagnostics)> {
+        let file = std::fs::File::open(path)?;
+        if file.metadata()?.len() == 0 {
+            return Err(ParseError::MissingHeader);
+        }
+        // SAFETY: the map is only read while parsing and is dropped before
+        // returning; the caller guarantees the file is unchanged until then.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::parse_slice_with_progress(&map, options, progress)
+    }
+
+    /// Collect and validate the records of `records`.
+    ///
+    /// Checks run as each record is read, so errors can say where the
+    /// record is. References to records not yet seen are checked once the
+    /// input is exhausted.
+    fn build(
+        mut records: impl RecordSource,
+        total: Option<u64>,
+        options: &ParseOptions,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<(Self, ParseDiagnostics)> {
+        let mut header: Option<Header> = None;
+        let mut system: Option<SystemInfo> = None;
+        let mut dsos: HashMap<u64, Dso> = HashMap::new();
+        let mut frames: HashMap<u64, Frame> = HashMap::new();
+        let mut threads: HashMap<u64, Thread> = HashMap::new();
+        let mut stacks: HashMap<String, Stack> = HashMap::new();
+        let mut samples: Vec<Sample> = Vec::new();
+        let mut windows: Vec<Window> = Vec::new();
+        let mut states: Vec<ThreadState> = Vec::new();
+        let mut notes: Vec<Note> = Vec::new();
+        let mut has_footer = false;
+        let mut primary_metrics: HashMap<String, String> = HashMap::new();
+        let mut pending = PendingReferences::default();
+        let mut warnings: Vec<ParseWarning> = Vec::new();
+
+        let mut next_report = 0;
+        while let Some(record) = records.next() {
+            if records.bytes_read() >= next_report {
+                progress.update("parse", records.bytes_read(), total)?;
+                next_report = records.bytes_read() + PARSE_PROGRESS_INTERVAL;
+            }
+            let location = records.location();
+            let record = match record {
+                Err(e) if options.lenient && e.is_record_error() => {
+                    warnings.push(ParseWarning {
+                        location: Some(location),
+                        message: e.to_string(),
+                    });
+                    records.resume();
+                    continue;
+                }
+                record => record?,
+            };
+            match record {
+                Record::Header(h) => {
+                    primary_metrics = h
+                        .events
+                        .iter()
+                        .map(|e| (e.name.clone(), e.sampling.primary_metric.clone()))
+                        .collect();
+                    header = Some(h);
+                }
+                Record::System(s) => system = Some(s),
+                Record::Dso(dso) => {
+                    dsos.insert(dso.id, dso);
+                }
+                Record::Frame(frame) => {
+                    if !dsos.contains_key(&frame.dso) {
+                        pending.dsos.push((frame.id, frame.dso, location));
+                    }
+                    frames.insert(frame.id, frame);
+                }
+                Record::Thread(thread) => {
+                    threads.insert(thread.tid, thread);
+                }
+                Record::Stack(stack) => {
+                    if let Err(e) = check_stack(&stack, &primary_metrics, location) {
+                        if !options.lenient {
+                            return Err(e);
+                        }
+                        warnings.push(ParseWarning::from(&e));
+                        continue;
+                    }
+                    for &frame_id in &stack.frames {
+                        if !frames.contains_key(&frame_id) {
+                            pending.frames.push((stack.id.clone(), frame_id, location));
+                        }
+                    }
+                    stacks.insert(stack.id.clone(), stack);
+                }
+                Record::Sample(sample) => {
+                    if !stacks.contains_key(&sample.stack_id) {
+                        pending
+                            .stacks
+                            .push(("sample", sample.stack_id.clone(), location));
+                    }
+                    samples.push(sample);
+                }
+                Record::Samples(batch) => {
+                    for stack_id in &batch.stack_ids {
+                        if !stacks.contains_key(stack_id) {
+                            pending.stacks.push(("samples", stack_id.clone(), location));
+                        }
+                    }
+                    samples.extend(batch.samples());
+                }
+                Record::Window(window) => windows.push(window),
+                Record::State(state) => {
+                    if let Some(stack_id) = &state.stack_id
+                        && !stacks.contains_key(stack_id)
+                    {
+                        pending.stacks.push(("state", stack_id.clone(), location));
+                    }
+                    states.push(state);
+                }
+                Record::Note(note) => {
+                    if let Some(stack_id) = &note.stack_id
+                        && !stacks.contains_key(stack_id)
+                    {
+                        pending.stacks.push(("note", stack_id.clone(), location));
+                    }
+                    if let Some(frame_id) = note.frame_id
+                        && !frames.contains_key(&frame_id)
+                    {
+                        pending.note_frames.push((frame_id, location));
+                    }
+                    notes.push(note);
+                }
+                Record::Footer(_) => has_footer = true,
+            }
+        }
+
+        let header = header.ok_or(ParseError::MissingHeader)?;
+        progress.update("parse", records.bytes_read(), total)?;
+
+        let mut file = SpaaFile {
+            header,
+            system,
+            dsos,
+            frames,
             threads,
             stacks,
             samples,
             windows,
+            states,
+            notes,
         };
 
-        file.validate()?;
+        if options.lenient {
+            pending.prune(&mut file, &mut warnings);
+        } else {
+            pending.check(&file)?;
+        }
+
+        let mut diagnostics = ParseDiagnostics {
+            has_footer,
+            warnings,
+            ..Default::default()
+        };
+        if let Some(max_depth) = options.max_stack_depth {
+            diagnostics.truncated_stacks = file.truncate_stacks(max_depth);
+        }
+        if options.enrich_threads {
+            diagnostics.enriched_threads = file.fill_thread_comms();
+            diagnostics.enriched_stacks = file.fill_stack_comms();
+        }
 
-        Ok(file)
+        Ok((file, diagnostics))
     }
 
-    /// Validate the parsed file according to SPAA spec rules.
-    fn validate(&self) -> Result<()> {
-        // Validate frame DSO references
-        for frame in self.frames.values() {
-            if !self.dsos.contains_key(&frame.dso) {
-                return Err(ParseError::InvalidDsoReference {
-                    frame_id: frame.id,
-                    dso_id: frame.dso,
-                });
+    /// Shift every timestamp onto wall-clock time (Unix time, in the
+    /// header's time unit) using the header's clock offset.
+    ///
+    /// Rebases the time range, samples, windows and thread states, and marks
+    /// the clock as a wall clock. Returns `false`, changing nothing, if the
+    /// offset is not known.
+    pub fn rebase_to_wall_clock(&mut self) -> bool {
+        let Some(clock) = &mut self.header.clock else {
+            return false;
+        };
+        let Some(offset) = clock.wall_clock_offset() else {
+            return false;
+        };
+        clock.source = ClockSource::Wall;
+        clock.epoch_offset = None;
+        if offset == 0.0 {
+            return true;
+        }
+
+        if let Some(range) = &mut self.header.time_range {
+            range.start += offset;
+            range.end += offset;
+        }
+        for sample in &mut self.samples {
+            sample.timestamp += offset;
+        }
+        for window in &mut self.windows {
+            window.start += offset;
+            window.end += offset;
+        }
+        for state in &mut self.states {
+            state.start += offset;
+            state.end += offset;
+        }
+        true
+    }
+
+    /// Rename event `old` to `new` in the header, stacks and samples.
+    ///
+    /// If `new` is already declared, `old`'s declaration is dropped and its
+    /// stacks and samples join `new`'s; stack IDs are unchanged, so stacks
+    /// that become identical stay separate until [`SpaaFile::normalized`].
+    /// Returns the number of stacks and samples rewritten.
+    pub fn rename_event(&mut self, old: &str, new: &str) -> usize {
+        if old == new {
+            return 0;
+        }
+        if self.header.events.iter().any(|e| e.name == new) {
+            self.header.events.retain(|e| e.name != old);
+        } else if let Some(event) = self.header.events.iter_mut().find(|e| e.name == old) {
+            event.name = new.to_string();
+        }
+
+        let mut renamed = 0;
+        let events = self
+            .stacks
+            .values_mut()
+            .map(|s| &mut s.context.event)
+            .chain(self.samples.iter_mut().map(|s| &mut s.event));
+        for event in events.filter(|e| *e == old) {
+            *event = new.to_string();
+            renamed += 1;
+        }
+        renamed
+    }
+
+    /// Rename weight metric `old` to `new` in event declarations, stacks
+    /// (including exclusive weights) and windows.
+    ///
+    /// Where a record already has a `new` weight, the `old` weight is added
+    /// to it. Returns the number of stacks and windows rewritten.
+    pub fn alias_metric(&mut self, old: &str, new: &str) -> usize {
+        if old == new {
+            return 0;
+        }
+        for event in &mut self.header.events {
+            if event.sampling.primary_metric == old {
+                event.sampling.primary_metric = new.to_string();
             }
         }
 
-        // Build event primary metrics map
-        let event_metrics: HashMap<&str, &str> = self
-            .header
-            .events
-            .iter()
-            .map(|e| (e.name.as_str(), e.sampling.primary_metric.as_str()))
-            .collect();
+        let mut aliased = 0;
+        for stack in self.stacks.values_mut() {
+            let mut changed = alias_weights(&mut stack.weights, old, new);
+            if let Some(exclusive) = &mut stack.exclusive {
+                changed |= alias_weights(&mut exclusive.weights, old, new);
+            }
+            aliased += changed as usize;
+        }
+        for window in &mut self.windows {
+            let mut changed = false;
+            for entry in &mut window.by_stack {
+                changed |= alias_weights(&mut entry.weights, old, new);
+            }
+            aliased += changed as usize;
+        }
+        aliased
+    }
 
-        // Validate stack frame references and primary metrics
-        for stack in self.stacks.values() {
-            for &frame_id in &stack.frames {
-                if !self.frames.contains_key(&frame_id) {
-                    return Err(ParseError::InvalidFrameReference {
-                        stack_id: stack.id.clone(),
-                        frame_id,
-                    });
+    /// Multiply the `metric` weights of `event`'s stacks by `factor`,
+    /// rounding to the nearest integer.
+    ///
+    /// Exclusive weights and the stacks' window entries are scaled too.
+    /// Returns the number of stacks scaled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `factor` is negative or not finite.
+    pub fn scale_weights(&mut self, event: &str, metric: &str, factor: f64) -> usize {
+        assert!(
+            factor.is_finite() && factor >= 0.0,
+            "scale factor must be finite and non-negative, got {factor}"
+        );
+        let scale = |weights: &mut [Weight]| {
+            let mut scaled = false;
+            for weight in weights.iter_mut().filter(|w| w.metric == metric) {
+                weight.value = (weight.value as f64 * factor).round() as u64;
+                scaled = true;
+            }
+            scaled
+        };
+
+        let mut scaled_stacks = 0;
+        for stack in self.stacks.values_mut() {
+            if stack.context.event != event {
+                continue;
+            }
+            let mut scaled = scale(&mut stack.weights);
+            if let Some(exclusive) = &mut stack.exclusive {
+                scaled |= scale(&mut exclusive.weights);
+            }
+            scaled_stacks += scaled as usize;
+        }
+        for window in &mut self.windows {
+            for entry in &mut window.by_stack {
+                if self
+                    .stacks
+                    .get(&entry.stack_id)
+                    .is_some_and(|s| s.context.event == event)
+                {
+                    scale(&mut entry.weights);
                 }
             }
+        }
+        scaled_stacks
+    }
 
-            // Check primary metric is present
-            if let Some(primary_metric) = event_metrics.get(stack.context.event.as_str()) {
-                let has_primary = stack.weights.iter().any(|w| w.metric == *primary_metric);
-                if !has_primary {
-                    return Err(ParseError::MissingPrimaryMetric {
-                        stack_id: stack.id.clone(),
-                        metric: primary_metric.to_string(),
-                    });
+    /// Add a `name` weight computed by `f` to every stack, replacing any
+    /// existing `name` weight.
+    ///
+    /// Window entries and exclusive weights are left alone, since `f` sees
+    /// only the stack's totals.
+    ///
+    /// ```
+    /// # use spaa_parse::SpaaFile;
+    /// # let mut spaa = SpaaFile::parse(&br#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[]}"#[..]).unwrap();
+    /// // Estimated CPU time at 1ms per sample.
+    /// spaa.add_derived_metric("cpu_ns", |stack| {
+    ///     let samples = stack.weights.iter().find(|w| w.metric == "samples");
+    ///     samples.map_or(0, |w| w.value * 1_000_000)
+    /// });
+    /// ```
+    pub fn add_derived_metric(&mut self, name: &str, mut f: impl FnMut(&Stack) -> u64) {
+        for stack in self.stacks.values_mut() {
+            let value = f(stack);
+            match stack.weights.iter_mut().find(|w| w.metric == name) {
+                Some(weight) => weight.value = value,
+                None => stack.weights.push(Weight {
+                    metric: name.to_string(),
+                    value,
+                    unit: None,
+                }),
+            }
+        }
+    }
+
+    /// Truncate every stack deeper than `max_depth` frames.
+    ///
+    /// See [`ParseOptions::max_stack_depth`] for the truncation rules. The
+    /// synthetic [`TRUNCATED_FRAME_NAME`] frame and DSO are added to the
+    /// dictionaries on first use. Returns the number of truncated stacks.
+    pub fn truncate_stacks(&mut self, max_depth: usize) -> usize {
+        let frame_order = self.header.frame_order;
+        let mut sentinel: Option<u64> = None;
+        let mut truncated = 0;
+
+        let mut ids: Vec<String> = self
+            .stacks
+            .values()
+            .filter(|s| s.frames.len() > max_depth)
+            .map(|s| s.id.clone())
+            .collect();
+        ids.sort();
+
+        for id in ids {
+            let sentinel_id = *sentinel.get_or_insert_with(|| self.truncated_frame_id());
+            if let Some(stack) = self.stacks.get_mut(&id)
+                && truncate_frames(&mut stack.frames, max_depth, frame_order, sentinel_id)
+            {
+                truncated += 1;
+            }
+        }
+
+        truncated
+    }
+
+    /// Find or create the synthetic frame used to mark truncated stacks.
+    fn truncated_frame_id(&mut self) -> u64 {
+        if let Some(frame) = self
+            .frames
+            .values()
+            .find(|f| f.func == TRUNCATED_FRAME_NAME)
+        {
+            return frame.id;
+        }
+
+        let dso_id = match self.dsos.values().find(|d| d.name == TRUNCATED_FRAME_NAME) {
+            Some(dso) => dso.id,
+            None => {
+                let id = self.dsos.keys().max().map_or(1, |max| max + 1);
+                self.dsos.insert(
+                    id,
+                    Dso {
+                        id,
+                        name: TRUNCATED_FRAME_NAME.to_string(),
+                        build_id: None,
+                        is_kernel: false,
+                    },
+                );
+                id
+            }
+        };
+
+        let id = self.frames.keys().max().map_or(1, |max| max + 1);
+        self.frames.insert(
+            id,
+            Frame {
+                id,
+                func: TRUNCATED_FRAME_NAME.to_string(),
+                dso: dso_id,
+                func_resolved: true,
+                ip: None,
+                symoff: None,
+                srcline: None,
+                srcline_resolved: false,
+                inlined: false,
+                inline_depth: None,
+                kind: FrameKind::Unknown,
+            },
+        );
+        id
+    }
+
+    /// Derive every stack's exclusive weights from the call tree.
+    ///
+    /// Stack weights are treated as inclusive: a stack's exclusive weight is
+    /// its own weight minus the weights of its direct children, i.e. stacks
+    /// with the same event and context whose frames extend it by exactly one
+    /// frame on the leaf side. This is the right model for aggregated
+    /// call-tree views that emit one stack per tree node. Existing
+    /// `exclusive` records are replaced. Returns the number of stacks whose
+    /// exclusive weights changed.
+    ///
+    /// Sample-aggregated profiles (perf, DTrace) already attribute each
+    /// stack's weight to its leaf and should not be recomputed.
+    pub fn recompute_exclusive(&mut self) -> usize {
+        let frame_order = self.header.frame_order;
+        let root_to_leaf = |frames: &[u64]| -> Vec<u64> {
+            match frame_order {
+                FrameOrder::RootToLeaf => frames.to_vec(),
+                FrameOrder::LeafToRoot => frames.iter().rev().copied().collect(),
+            }
+        };
+        let context_key = |stack: &Stack| content_key(&stack.context);
+
+        let mut by_path: HashMap<(String, Vec<u64>), String> = HashMap::new();
+        for stack in self.stacks.values() {
+            by_path.insert(
+                (context_key(stack), root_to_leaf(&stack.frames)),
+                stack.id.clone(),
+            );
+        }
+
+        // Sum each stack's direct children, per metric.
+        let mut child_sums: HashMap<String, HashMap<String, u64>> = HashMap::new();
+        for stack in self.stacks.values() {
+            let mut path = root_to_leaf(&stack.frames);
+            if path.pop().is_none() {
+                continue;
+            }
+            if let Some(parent) = by_path.get(&(context_key(stack), path)) {
+                let sums = child_sums.entry(parent.clone()).or_default();
+                for weight in &stack.weights {
+                    *sums.entry(weight.metric.clone()).or_default() += weight.value;
                 }
             }
         }
 
-        // Validate sample stack references
-        for sample in &self.samples {
-            if !self.stacks.contains_key(&sample.stack_id) {
-                return Err(ParseError::InvalidStackReference(sample.stack_id.clone()));
+        let mut changed = 0;
+        for stack in self.stacks.values_mut() {
+            let leaf = match frame_order {
+                FrameOrder::LeafToRoot => stack.frames.first(),
+                FrameOrder::RootToLeaf => stack.frames.last(),
+            };
+            let exclusive = leaf.map(|&frame| {
+                let sums = child_sums.get(&stack.id);
+                ExclusiveWeights {
+                    frame,
+                    weights: stack
+                        .weights
+                        .iter()
+                        .map(|w| Weight {
+                            value: w.value.saturating_sub(
+                                sums.and_then(|s| s.get(&w.metric)).copied().unwrap_or(0),
+                            ),
+                            ..w.clone()
+                        })
+                        .collect(),
+                }
+            });
+            if stack.exclusive != exclusive {
+                stack.exclusive = exclusive;
+                changed += 1;
             }
         }
+        changed
+    }
 
-        Ok(())
+    /// Copy of the file with only the stacks `keep` accepts, and every
+    /// record pruned to what those stacks reference.
+    ///
+    /// DSOs, frames and threads no other kept record uses are dropped, as
+    /// are header events without stacks. Samples, thread states and window
+    /// entries are kept only where they reference a kept stack, windows
+    /// left empty are dropped, and related-stack links to dropped stacks
+    /// are removed. Notes about a dropped stack or frame are dropped; notes
+    /// about the whole profile are kept.
+    ///
+    /// ```
+    /// # let spaa = spaa_parse::SpaaFile::parse_slice(br#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#).unwrap();
+    /// let worker = spaa.filter(|stack| stack.context.tid == Some(42));
+    /// assert!(worker.stacks.values().all(|s| s.context.tid == Some(42)));
+    /// ```
+    pub fn filter(&self, mut keep: impl FnMut(&Stack) -> bool) -> SpaaFile {
+        let ids: HashSet<&str> = self
+            .stacks
+            .values()
+            .filter(|s| keep(s))
+            .map(|s| s.id.as_str())
+            .collect();
+        let stacks: HashMap<String, Stack> = self
+            .stacks
+            .iter()
+            .filter(|(id, _)| ids.contains(id.as_str()))
+            .map(|(id, stack)| {
+                let mut stack = stack.clone();
+                if let Some(related) = &mut stack.related_stacks {
+                    related.retain(|r| ids.contains(r.as_str()));
+                }
+                (id.clone(), stack)
+            })
+            .collect();
+
+        let frame_ids: HashSet<u64> = stacks
+            .values()
+            .flat_map(|s| {
+                s.frames
+                    .iter()
+                    .copied()
+                    .chain(s.exclusive.as_ref().map(|e| e.frame))
+            })
+            .collect();
+        let frames: HashMap<_, _> = self
+            .frames
+            .iter()
+            .filter(|(id, _)| frame_ids.contains(id))
+            .map(|(id, f)| (*id, f.clone()))
+            .collect();
+        let dso_ids: HashSet<u64> = frames.values().map(|f| f.dso).collect();
+        let dsos = self
+            .dsos
+            .iter()
+            .filter(|(id, _)| dso_ids.contains(id))
+            .map(|(id, d)| (*id, d.clone()))
+            .collect();
+
+        let events: HashSet<&str> = stacks.values().map(|s| s.context.event.as_str()).collect();
+        let mut header = self.header.clone();
+        header.events.retain(|e| events.contains(e.name.as_str()));
+
+        let tids: HashSet<u64> = stacks
+            .values()
+            .filter_map(|s| s.context.tid)
+            .chain(
+                self.samples
+                    .iter()
+                    .filter(|s| ids.contains(s.stack_id.as_str()))
+                    .map(|s| s.tid),
+            )
+            .collect();
+        let threads = self
+            .threads
+            .iter()
+            .filter(|(tid, _)| tids.contains(tid))
+            .map(|(tid, t)| (*tid, t.clone()))
+            .collect();
+
+        // Notes about other stacks or frames stay out; notes about the whole
+        // profile are kept.
+        let notes = self
+            .notes
+            .iter()
+            .filter(|n| {
+                n.stack_id
+                    .as_ref()
+                    .is_none_or(|id| ids.contains(id.as_str()))
+                    && n.frame_id.is_none_or(|id| frames.contains_key(&id))
+            })
+            .cloned()
+            .collect();
+
+        SpaaFile {
+            header,
+            system: self.system.clone(),
+            dsos,
+            frames,
+            threads,
+            samples: self
+                .samples
+                .iter()
+                .filter(|s| ids.contains(s.stack_id.as_str()))
+                .cloned()
+                .collect(),
+            windows: self
+                .windows
+                .iter()
+                .filter_map(|w| {
+                    let by_stack: Vec<_> = w
+                        .by_stack
+                        .iter()
+                        .filter(|e| ids.contains(e.stack_id.as_str()))
+                        .cloned()
+                        .collect();
+                    (!by_stack.is_empty()).then(|| Window {
+                        by_stack,
+                        ..w.clone()
+                    })
+                })
+                .collect(),
+            states: self
+                .states
+                .iter()
+                .filter(|s| {
+                    s.stack_id
+                        .as_ref()
+                        .is_some_and(|id| ids.contains(id.as_str()))
+                })
+                .cloned()
+                .collect(),
+            stacks,
+            notes,
+        }
     }
 
     /// Get the primary metric name for a given event.
@@ -799,32 +2918,477 @@ impl SpaaFile {
             .filter(move |s| s.context.event == event_name)
     }
 
-    /// Resolve a frame ID to its Frame record.
-    pub fn resolve_frame(&self, frame_id: u64) -> Option<&Frame> {
-        self.frames.get(&frame_id)
-    }
+    /// Inclusive and exclusive `metric` weight of every function, across
+    /// the stacks of `event`, heaviest inclusive weight first.
+    ///
+    /// Functions are matched by name, so the same function in several DSOs
+    /// or inlined at several sites is counted once, and a function appearing
+    /// more than once in a stack, as under recursion, counts that stack's
+    /// weight once. Exclusive weight comes from each stack's `exclusive`
+    /// record, or is the whole stack weight on its leaf frame, per
+    /// `frame_order`, when it has none. Stacks without `metric` are skipped.
+    ///
+    /// ```
+    /// # let spaa = spaa_parse::SpaaFile::parse_slice(br#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#).unwrap();
+    /// for function in spaa.function_weights("cycles", "period").iter().take(10) {
+    ///     println!("{} {} {}", function.func, function.inclusive, function.exclusive);
+    /// }
+    /// ```
+    pub fn function_weights(&self, event: &str, metric: &str) -> Vec<FunctionWeights> {
+        let value_of =
+            |weights: &[Weight]| weights.iter().find(|w| w.metric == metric).map(|w| w.value);
+        let mut totals: HashMap<&str, FunctionWeights> = HashMap::new();
+        for stack in self.stacks_for_event(event) {
+            let Some(weight) = value_of(&stack.weights) else {
+                continue;
+            };
+            let mut seen = HashSet::new();
+            for frame in stack.frames.iter().filter_map(|&id| self.frames.get(&id)) {
+                if seen.insert(frame.func.as_str()) {
+                    let function = totals.entry(&frame.func).or_default();
+                    function.inclusive = function.inclusive.saturating_add(weight);
+                }
+            }
+
+            let (leaf, exclusive) = match &stack.exclusive {
+                Some(exclusive) => (
+                    Some(exclusive.frame),
+                    value_of(&exclusive.weights).unwrap_or(0),
+                ),
+                None => {
+                    let leaf = match self.header.frame_order {
+                        FrameOrder::LeafToRoot => stack.frames.first(),
+                        FrameOrder::RootToLeaf => stack.frames.last(),
+                    };
+                    (leaf.copied(), weight)
+                }
+            };
+            if let Some(frame) = leaf.and_then(|id| self.frames.get(&id)) {
+                let function = totals.entry(&frame.func).or_default();
+                function.exclusive = function.exclusive.saturating_add(exclusive);
+            }
+        }
+
+        let mut functions: Vec<FunctionWeights> = totals
+            .into_iter()
+            .map(|(func, weights)| FunctionWeights {
+                func: func.to_string(),
+                ..weights
+            })
+            .collect();
+        functions.sort_by(|a, b| {
+            b.inclusive
+                .cmp(&a.inclusive)
+                .then(b.exclusive.cmp(&a.exclusive))
+                .then_with(|| a.func.cmp(&b.func))
+        });
+        functions
+    }
+
+    /// Resolve a frame ID to its Frame record.
+    pub fn resolve_frame(&self, frame_id: u64) -> Option<&Frame> {
+        self.frames.get(&frame_id)
+    }
+
+    /// Resolve a DSO ID to its DSO record.
+    pub fn resolve_dso(&self, dso_id: u64) -> Option<&Dso> {
+        self.dsos.get(&dso_id)
+    }
+
+    /// The thread record for `tid`, if it belongs to process `pid`.
+    pub fn thread_for(&self, pid: u64, tid: u64) -> Option<&Thread> {
+        self.threads.get(&tid).filter(|t| t.pid == pid)
+    }
+
+    /// The thread record for a stack context's `tid`, if the context has
+    /// one and its `pid`, when set, matches.
+    pub fn thread_for_context(&self, context: &StackContext) -> Option<&Thread> {
+        let thread = self.threads.get(&context.tid?)?;
+        context
+            .pid
+            .is_none_or(|pid| pid == thread.pid)
+            .then_some(thread)
+    }
+
+    /// Name threads from the `comm` of their stacks' contexts, adding a
+    /// thread record for any `pid` and `tid` that stacks name but the
+    /// dictionary lacks.
+    ///
+    /// Where stacks disagree, the name from the lowest stack ID wins.
+    /// Existing names are kept. Returns the number of threads added or
+    /// named.
+    pub fn fill_thread_comms(&mut self) -> usize {
+        let mut named: Vec<(&str, u64, u64, &str)> = self
+            .stacks
+            .values()
+            .filter_map(|s| {
+                let c = &s.context;
+                Some((s.id.as_str(), c.pid?, c.tid?, c.comm.as_deref()?))
+            })
+            .collect();
+        named.sort_unstable();
+
+        let mut filled = 0;
+        for (_, pid, tid, comm) in named {
+            match self.threads.get_mut(&tid) {
+                Some(thread) if thread.pid == pid && thread.comm.is_none() => {
+                    thread.comm = Some(comm.to_string());
+                    filled += 1;
+                }
+                Some(_) => {}
+                None => {
+                    self.threads.insert(
+                        tid,
+                        Thread {
+                            pid,
+                            tid,
+                            comm: Some(comm.to_string()),
+                        },
+                    );
+                    filled += 1;
+                }
+            }
+        }
+        filled
+    }
+
+    /// Set the `comm` of stack contexts that lack one from their thread
+    /// record, found with [`SpaaFile::thread_for_context`]. Returns the
+    /// number of stacks named.
+    pub fn fill_stack_comms(&mut self) -> usize {
+        let mut filled = 0;
+        for stack in self.stacks.values_mut() {
+            if stack.context.comm.is_some() {
+                continue;
+            }
+            let Some(tid) = stack.context.tid else {
+                continue;
+            };
+            if let Some(thread) = self.threads.get(&tid)
+                && stack.context.pid.is_none_or(|pid| pid == thread.pid)
+                && let Some(comm) = &thread.comm
+            {
+                stack.context.comm = Some(comm.clone());
+                filled += 1;
+            }
+        }
+        filled
+    }
+
+    /// Get the fully resolved stack frames for a stack.
+    pub fn resolve_stack_frames(&self, stack: &Stack) -> Vec<Option<&Frame>> {
+        stack
+            .frames
+            .iter()
+            .map(|&id| self.resolve_frame(id))
+            .collect()
+    }
+
+    /// Resolve a sample's thread, stack and frames in one call.
+    ///
+    /// The thread is found with [`SpaaFile::thread_for`]; `comm` falls back
+    /// to the stack context's when the thread record has none.
+    pub fn resolve_sample<'a>(&'a self, sample: &'a Sample) -> ResolvedSample<'a> {
+        let thread = self.thread_for(sample.pid, sample.tid);
+        let stack = self.stacks.get(&sample.stack_id);
+        let comm = thread
+            .and_then(|t| t.comm.as_deref())
+            .or_else(|| stack.and_then(|s| s.context.comm.as_deref()));
+        ResolvedSample {
+            timestamp: sample.timestamp,
+            pid: sample.pid,
+            tid: sample.tid,
+            cpu: sample.cpu,
+            event: &sample.event,
+            thread,
+            comm,
+            stack,
+            frames: stack.map_or_else(Vec::new, |s| self.resolve_stack_frames(s)),
+        }
+    }
+
+    /// Size of each section as [`SpaaFile::write`] would write it, without
+    /// writing anything.
+    pub fn estimate_bytes_per_section(&self) -> SectionSizes {
+        SectionSizes {
+            header: record_size("header", &self.header),
+            dsos: self.dsos.values().map(|d| record_size("dso", d)).sum(),
+            frames: self.frames.values().map(|f| record_size("frame", f)).sum(),
+            threads: self
+                .threads
+                .values()
+                .map(|t| record_size("thread", t))
+                .sum(),
+            stacks: self.stacks.values().map(|s| record_size("stack", s)).sum(),
+            samples: self.samples.iter().map(|s| record_size("sample", s)).sum(),
+            windows: self.windows.iter().map(|w| record_size("window", w)).sum(),
+            states: self.states.iter().map(|s| record_size("state", s)).sum(),
+            notes: self.notes.iter().map(|n| record_size("note", n)).sum(),
+        }
+    }
+
+    /// Approximate number of LLM tokens the written file would take up,
+    /// at [`BYTES_PER_TOKEN`] bytes per token.
+    pub fn estimate_tokens(&self) -> u64 {
+        self.estimate_bytes_per_section()
+            .total()
+            .div_ceil(BYTES_PER_TOKEN)
+    }
+
+    /// Return a copy with IDs and record order in canonical form.
+    ///
+    /// DSOs and frames are deduplicated by content and renumbered from 1 in
+    /// content order. Stacks are stored leaf to root under content-addressed
+    /// IDs, identical stacks have their weights summed, and every reference
+    /// to a stack is remapped. Events, samples, windows (renumbered `w1`,
+    /// `w2`, ...), thread states and notes are sorted. Two files describing the same
+    /// profile normalize to equal values however their IDs were assigned, so
+    /// the result also works as a dedupe key.
+    pub fn normalized(&self) -> SpaaFile {
+        let mut header = self.header.clone();
+        header.frame_order = FrameOrder::LeafToRoot;
+        header.stack_id_mode = StackIdMode::ContentAddressable;
+        header.events.sort_by(|a, b| a.name.cmp(&b.name));
+
+        // DSOs, numbered in order of content.
+        let dso_content = |dso: &Dso| content_key(&(&dso.name, &dso.build_id, dso.is_kernel));
+        let dso_ids = dense_ids(self.dsos.values().map(dso_content));
+        let dso_map: HashMap<u64, u64> = self
+            .dsos
+            .values()
+            .map(|dso| (dso.id, dso_ids[&dso_content(dso)]))
+            .collect();
+        let mut dsos = HashMap::new();
+        for dso in self.dsos.values() {
+            let id = dso_map[&dso.id];
+            dsos.entry(id).or_insert_with(|| Dso { id, ..dso.clone() });
+        }
+
+        // Frames, numbered in order of content once their DSO is renumbered.
+        let frame_content = |frame: &Frame| Frame {
+            id: 0,
+            dso: dso_map.get(&frame.dso).copied().unwrap_or(frame.dso),
+            ..frame.clone()
+        };
+        let frame_ids = dense_ids(self.frames.values().map(|f| content_key(&frame_content(f))));
+        let mut frame_map = HashMap::new();
+        let mut frames = HashMap::new();
+        for frame in self.frames.values() {
+            let content = frame_content(frame);
+            let id = frame_ids[&content_key(&content)];
+            frame_map.insert(frame.id, id);
+            frames.entry(id).or_insert(Frame { id, ..content });
+        }
+        let frame_id = |id: u64| frame_map.get(&id).copied().unwrap_or(id);
+
+        // Stacks, addressed by content; identical stacks merge.
+        let mut originals: Vec<&Stack> = self.stacks.values().collect();
+        originals.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut stack_map: HashMap<&str, String> = HashMap::new();
+        let mut stacks: HashMap<String, Stack> = HashMap::new();
+        for stack in &originals {
+            let mut path: Vec<u64> = stack.frames.iter().map(|&id| frame_id(id)).collect();
+            if self.header.frame_order == FrameOrder::RootToLeaf {
+                path.reverse();
+            }
+            let key = content_key(&(&path, stack.stack_type, &stack.context));
+            let id = format!("0x{:016x}", fnv1a(key.as_bytes()));
+            stack_map.insert(&stack.id, id.clone());
+
+            let merged = stacks.entry(id.clone()).or_insert_with(|| Stack {
+                id,
+                frames: path,
+                stack_type: stack.stack_type,
+                context: stack.context.clone(),
+                weights: Vec::new(),
+                exclusive: None,
+                related_stacks: None,
+            });
+            add_weights(&mut merged.weights, &stack.weights);
+            if let Some(exclusive) = &stack.exclusive {
+                let frame = frame_id(exclusive.frame);
+                let target = merged.exclusive.get_or_insert_with(|| ExclusiveWeights {
+                    frame,
+                    weights: Vec::new(),
+                });
+                if target.frame == frame {
+                    add_weights(&mut target.weights, &exclusive.weights);
+                }
+            }
+        }
+        let stack_id = |id: &str| stack_map.get(id).cloned().unwrap_or_else(|| id.to_string());
+        for stack in &originals {
+            if let Some(related) = &stack.related_stacks {
+                let merged = stacks.get_mut(&stack_map[stack.id.as_str()]).unwrap();
+                let list = merged.related_stacks.get_or_insert_with(Vec::new);
+                list.extend(related.iter().map(|id| stack_id(id)));
+                list.sort();
+                list.dedup();
+            }
+        }
+
+        let mut samples: Vec<Sample> = self
+            .samples
+            .iter()
+            .map(|sample| Sample {
+                stack_id: stack_id(&sample.stack_id),
+                ..sample.clone()
+            })
+            .collect();
+        samples.sort_by_cached_key(|s| (s.timestamp.to_bits(), content_key(s)));
+
+        let mut windows: Vec<Window> = self
+            .windows
+            .iter()
+            .map(|window| {
+                let mut by_stack: Vec<WindowStackWeight> = Vec::new();
+                for entry in &window.by_stack {
+                    let id = stack_id(&entry.stack_id);
+                    let index = match by_stack.iter().position(|e| e.stack_id == id) {
+                        Some(index) => index,
+                        None => {
+                            by_stack.push(WindowStackWeight {
+                                stack_id: id,
+                                weights: Vec::new(),
+                            });
+                            by_stack.len() - 1
+                        }
+                    };
+                    add_weights(&mut by_stack[index].weights, &entry.weights);
+                }
+                by_stack.sort_by(|a, b| a.stack_id.cmp(&b.stack_id));
+                Window {
+                    id: String::new(),
+                    by_stack,
+                    ..window.clone()
+                }
+            })
+            .collect();
+        windows.sort_by(|a, b| {
+            a.start
+                .total_cmp(&b.start)
+                .then(a.end.total_cmp(&b.end))
+                .then_with(|| content_key(a).cmp(&content_key(b)))
+        });
+        for (i, window) in windows.iter_mut().enumerate() {
+            window.id = format!("w{}", i + 1);
+        }
+
+        let mut states: Vec<ThreadState> = self
+            .states
+            .iter()
+            .map(|state| ThreadState {
+                stack_id: state.stack_id.as_deref().map(stack_id),
+                ..state.clone()
+            })
+            .collect();
+        states.sort_by(|a, b| {
+            a.tid
+                .cmp(&b.tid)
+                .then(a.start.total_cmp(&b.start))
+                .then_with(|| content_key(a).cmp(&content_key(b)))
+        });
+
+        let mut notes: Vec<Note> = self
+            .notes
+            .iter()
+            .map(|note| Note {
+                stack_id: note.stack_id.as_deref().map(stack_id),
+                frame_id: note.frame_id.map(frame_id),
+                ..note.clone()
+            })
+            .collect();
+        notes.sort_by_cached_key(|n| (n.timestamp, content_key(n)));
 
-    /// Resolve a DSO ID to its DSO record.
-    pub fn resolve_dso(&self, dso_id: u64) -> Option<&Dso> {
-        self.dsos.get(&dso_id)
+        SpaaFile {
+            header,
+            system: self.system.clone(),
+            dsos,
+            frames,
+            threads: self.threads.clone(),
+            stacks,
+            samples,
+            windows,
+            states,
+            notes,
+        }
     }
 
-    /// Get the fully resolved stack frames for a stack.
-    pub fn resolve_stack_frames(&self, stack: &Stack) -> Vec<Option<&Frame>> {
-        stack
-            .frames
-            .iter()
-            .map(|&id| self.resolve_frame(id))
-            .collect()
+    /// Whether `self` and `other` describe the same profile.
+    ///
+    /// Compares [`normalized`](SpaaFile::normalized) forms, so differences
+    /// in ID numbering, record order, frame order and stack-ID scheme are
+    /// ignored.
+    pub fn semantically_eq(&self, other: &SpaaFile) -> bool {
+        self.normalized() == other.normalized()
     }
 
     /// Write this SPAA file to a writer in NDJSON format.
     ///
     /// Records are written in the correct order: header first, then dictionaries
-    /// (DSOs, frames, threads), then stacks, samples, and windows.
+    /// (DSOs, frames, threads), then stacks, samples, windows, thread states
+    /// and notes.
     pub fn write<W: Write>(&self, writer: W) -> WriteResult<()> {
+        self.write_with_options(writer, &WriteOptions::default())
+    }
+
+    /// Every stack, in `order`.
+    pub fn stacks_in_order(&self, order: StackOrder) -> Vec<&Stack> {
+        let mut stacks: Vec<&Stack> = self.stacks.values().collect();
+        stacks.sort_by(|a, b| a.id.cmp(&b.id));
+        match order {
+            StackOrder::ById => {}
+            StackOrder::ByWeightDesc => {
+                let events: HashMap<&str, (usize, &str)> = self
+                    .header
+                    .events
+                    .iter()
+                    .enumerate()
+                    .map(|(i, e)| (e.name.as_str(), (i, e.sampling.primary_metric.as_str())))
+                    .collect();
+                let key = |stack: &Stack| {
+                    let (rank, metric) = events
+                        .get(stack.context.event.as_str())
+                        .copied()
+                        .unwrap_or((usize::MAX, ""));
+                    let weight = stack.weights.iter().find(|w| w.metric == metric);
+                    (rank, std::cmp::Reverse(weight.map_or(0, |w| w.value)))
+                };
+                stacks.sort_by_cached_key(|s| key(s));
+            }
+            StackOrder::FirstReference => {
+                let mut first_use: HashMap<&str, usize> = HashMap::new();
+                let referenced = self
+                    .samples
+                    .iter()
+                    .map(|s| s.stack_id.as_str())
+                    .chain(
+                        self.windows
+                            .iter()
+                            .flat_map(|w| w.by_stack.iter().map(|s| s.stack_id.as_str())),
+                    )
+                    .chain(self.states.iter().filter_map(|s| s.stack_id.as_deref()));
+                for id in referenced {
+                    let next = first_use.len();
+                    first_use.entry(id).or_insert(next);
+                }
+                stacks.sort_by_key(|s| first_use.get(s.id.as_str()).copied().unwrap_or(usize::MAX));
+            }
+        }
+        stacks
+    }
+
+    /// Write the SPAA file with custom [`WriteOptions`].
+    pub fn write_with_options<W: Write>(
+        &self,
+        writer: W,
+        options: &WriteOptions,
+    ) -> WriteResult<()> {
         let mut spaa_writer = SpaaWriter::new(writer);
         spaa_writer.write_header(&self.header)?;
+        if let Some(system) = &self.system {
+            spaa_writer.write_system(system)?;
+        }
 
         // Write dictionaries in deterministic order
         let mut dsos: Vec<_> = self.dsos.values().collect();
@@ -845,26 +3409,93 @@ impl SpaaFile {
             spaa_writer.write_thread(thread)?;
         }
 
-        // Write stacks in deterministic order
-        let mut stacks: Vec<_> = self.stacks.values().collect();
-        stacks.sort_by(|a, b| a.id.cmp(&b.id));
-        for stack in stacks {
+        for stack in self.stacks_in_order(options.stack_order) {
             spaa_writer.write_stack(stack)?;
         }
 
         // Write samples and windows
-        for sample in &self.samples {
-            spaa_writer.write_sample(sample)?;
+        match &options.sample_batching {
+            Some(batching) => write_sample_batches(&mut spaa_writer, &self.samples, batching)?,
+            None => {
+                for sample in &self.samples {
+                    spaa_writer.write_sample(sample)?;
+                }
+            }
         }
 
         for window in &self.windows {
             spaa_writer.write_window(window)?;
         }
 
+        for state in &self.states {
+            spaa_writer.write_state(state)?;
+        }
+
+        for note in &self.notes {
+            spaa_writer.write_note(note)?;
+        }
+
         Ok(())
     }
 }
 
+/// Order-independent string key for a record's content.
+///
+/// Goes through `serde_json::Value`, whose maps are sorted, so records with
+/// `HashMap` fields produce the same key regardless of iteration order.
+fn content_key<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .map(|v| v.to_string())
+        .unwrap_or_default()
+}
+
+/// Map each distinct key to its 1-based position in sorted order.
+fn dense_ids(keys: impl Iterator<Item = String>) -> HashMap<String, u64> {
+    let mut keys: Vec<String> = keys.collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter().zip(1..).collect()
+}
+
+/// 64-bit FNV-1a hash.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Add `weights` into `into` by metric, appending metrics it lacks.
+fn sum_weights(into: &mut Vec<Weight>, weights: &[Weight]) {
+    for weight in weights {
+        match into.iter_mut().find(|w| w.metric == weight.metric) {
+            Some(existing) => existing.value = existing.value.saturating_add(weight.value),
+            None => into.push(weight.clone()),
+        }
+    }
+}
+
+/// Rename metric `old` to `new` in `weights`, summing it into an existing
+/// `new` weight. Returns whether `weights` had an `old` weight.
+fn alias_weights(weights: &mut Vec<Weight>, old: &str, new: &str) -> bool {
+    if !weights.iter().any(|w| w.metric == old) {
+        return false;
+    }
+    let original = std::mem::take(weights);
+    for mut weight in original {
+        if weight.metric == old {
+            weight.metric = new.to_string();
+        }
+        sum_weights(weights, std::slice::from_ref(&weight));
+    }
+    true
+}
+
+/// Add `weights` into `into` by metric, keeping `into` sorted by metric.
+fn add_weights(into: &mut Vec<Weight>, weights: &[Weight]) {
+    sum_weights(into, weights);
+    into.sort_by(|a, b| a.metric.cmp(&b.metric));
+}
+
 // ============================================================================
 // Writer types
 // ============================================================================
@@ -878,6 +3509,106 @@ struct TypedRecord<'a, T: Serialize> {
     data: &'a T,
 }
 
+/// Options controlling how [`SpaaFile::write_with_options`] writes a file.
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Order of the stack records.
+    pub stack_order: StackOrder,
+
+    /// Write samples as delta-encoded `samples` batches instead of one
+    /// `sample` record each. Samples that cannot be batched, such as those
+    /// with a `context`, are still written one per record.
+    pub sample_batching: Option<SampleBatching>,
+}
+
+/// Order of stack records in a written file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StackOrder {
+    /// By stack ID, so the same file is always written the same way.
+    #[default]
+    ById,
+    /// Heaviest first by the primary metric, grouping stacks by event in
+    /// header order, so readers that stop early see the hottest stacks.
+    /// Ties are broken by ID.
+    ByWeightDesc,
+    /// In the order samples, then windows, then thread states first
+    /// reference each stack, which for converted files is usually the order
+    /// the stacks were recorded in. Unreferenced stacks, which is every
+    /// stack in an aggregated-only file, follow by ID.
+    FirstReference,
+}
+
+/// How [`WriteOptions::sample_batching`] groups samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleBatching {
+    /// Most samples per batch record.
+    pub max_samples: usize,
+    /// Timestamp resolution, in the unit of sample timestamps; timestamps
+    /// are rounded to it. See [`SampleBatch::resolution`].
+    pub resolution: f64,
+}
+
+impl Default for SampleBatching {
+    /// Batches of 1024 samples at nanosecond resolution, for timestamps in
+    /// seconds.
+    fn default() -> Self {
+        Self {
+            max_samples: 1024,
+            resolution: 1e-9,
+        }
+    }
+}
+
+/// Write `samples` in order as batches of consecutive samples of one event,
+/// falling back to `sample` records for samples that cannot be batched.
+fn write_sample_batches<W: Write>(
+    writer: &mut SpaaWriter<W>,
+    samples: &[Sample],
+    batching: &SampleBatching,
+) -> WriteResult<()> {
+    let max_samples = batching.max_samples.max(1);
+    let mut rest = samples;
+    while let Some(first) = rest.first() {
+        let run = rest
+            .iter()
+            .take(max_samples)
+            .take_while(|s| {
+                s.event == first.event
+                    && s.context.is_empty()
+                    && s.period.is_some() == first.period.is_some()
+            })
+            .count();
+        match SampleBatch::encode(&rest[..run], batching.resolution) {
+            Some(batch) => writer.write_sample_batch(&batch)?,
+            None => writer.write_sample(first)?,
+        }
+        rest = &rest[run.max(1)..];
+    }
+    Ok(())
+}
+
+/// Bytes [`SpaaWriter`] writes for one record, including the newline.
+fn record_size<T: Serialize>(record_type: &str, data: &T) -> u64 {
+    let mut counter = ByteCounter(0);
+    // Counting cannot fail, and none of the record types fail to serialize.
+    let _ = serde_json::to_writer(&mut counter, &TypedRecord { record_type, data });
+    counter.0 + 1
+}
+
+/// `Write` sink that only counts bytes.
+struct ByteCounter(u64);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 /// Writer for creating SPAA files incrementally.
 ///
 /// This is useful for converters that build SPAA output without first
@@ -908,12 +3639,23 @@ struct TypedRecord<'a, T: Serialize> {
 /// ```
 pub struct SpaaWriter<W: Write> {
     writer: W,
+    /// Serialized records waiting to be written, reused between calls so
+    /// that writing a record does not allocate.
+    scratch: Vec<u8>,
+    /// Bytes and records written so far.
+    bytes: u64,
+    records: u64,
 }
 
 impl<W: Write> SpaaWriter<W> {
     /// Create a new SPAA writer.
     pub fn new(writer: W) -> Self {
-        Self { writer }
+        Self {
+            writer,
+            scratch: Vec::new(),
+            bytes: 0,
+            records: 0,
+        }
     }
 
     /// Write a header record. This should be called first.
@@ -921,6 +3663,11 @@ impl<W: Write> SpaaWriter<W> {
         self.write_record("header", header)
     }
 
+    /// Write a system record.
+    pub fn write_system(&mut self, system: &SystemInfo) -> WriteResult<()> {
+        self.write_record("system", system)
+    }
+
     /// Write a DSO dictionary record.
     pub fn write_dso(&mut self, dso: &Dso) -> WriteResult<()> {
         self.write_record("dso", dso)
@@ -946,16 +3693,101 @@ impl<W: Write> SpaaWriter<W> {
         self.write_record("sample", sample)
     }
 
+    /// Write a delta-encoded `samples` batch record.
+    pub fn write_sample_batch(&mut self, batch: &SampleBatch) -> WriteResult<()> {
+        self.write_record("samples", batch)
+    }
+
     /// Write a window record.
     pub fn write_window(&mut self, window: &Window) -> WriteResult<()> {
         self.write_record("window", window)
     }
 
+    /// Write a thread state record.
+    pub fn write_state(&mut self, state: &ThreadState) -> WriteResult<()> {
+        self.write_record("state", state)
+    }
+
+    /// Write a note record.
+    pub fn write_note(&mut self, note: &Note) -> WriteResult<()> {
+        self.write_record("note", note)
+    }
+
+    /// Write `records` in order with a single write to the underlying
+    /// writer.
+    ///
+    /// Cheaper than writing the records one at a time when the underlying
+    /// writer is unbuffered, such as a `File`.
+    pub fn write_batch(&mut self, records: &[Record]) -> WriteResult<()> {
+        self.scratch.clear();
+        for record in records {
+            match record {
+                Record::Header(header) => self.encode("header", header)?,
+                Record::System(system) => self.encode("system", system)?,
+                Record::Dso(dso) => self.encode("dso", dso)?,
+                Record::Frame(frame) => self.encode("frame", frame)?,
+                Record::Thread(thread) => self.encode("thread", thread)?,
+                Record::Stack(stack) => self.encode("stack", stack)?,
+                Record::Sample(sample) => self.encode("sample", sample)?,
+                Record::Samples(batch) => self.encode("samples", batch)?,
+                Record::Window(window) => self.encode("window", window)?,
+                Record::State(state) => self.encode("state", state)?,
+                Record::Note(note) => self.encode("note", note)?,
+                Record::Footer(footer) => self.encode("footer", footer)?,
+            }
+        }
+        self.write_scratch(records.len())
+    }
+
+    /// Write a footer record counting every record written so far, then
+    /// flush and return the underlying writer.
+    ///
+    /// Files without a footer may have been cut short; see [`Footer`].
+    pub fn finish(mut self) -> WriteResult<W> {
+        let footer = Footer {
+            records: self.records,
+        };
+        self.write_record("footer", &footer)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Number of records written so far.
+    pub fn records_written(&self) -> u64 {
+        self.records
+    }
+
+    /// Number of bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> WriteResult<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
     /// Write a record with the given type tag.
     fn write_record<T: Serialize>(&mut self, record_type: &str, data: &T) -> WriteResult<()> {
-        let typed = TypedRecord { record_type, data };
-        let json = serde_json::to_string(&typed)?;
-        writeln!(self.writer, "{}", json)?;
+        self.scratch.clear();
+        self.encode(record_type, data)?;
+        self.write_scratch(1)
+    }
+
+    /// Write `scratch`, holding `records` records, to the underlying writer.
+    fn write_scratch(&mut self, records: usize) -> WriteResult<()> {
+        self.writer.write_all(&self.scratch)?;
+        self.bytes += self.scratch.len() as u64;
+        self.records += records as u64;
+        Ok(())
+    }
+
+    /// Append a record with the given type tag, and its newline, to
+    /// `scratch`.
+    fn encode<T: Serialize>(&mut self, record_type: &str, data: &T) -> WriteResult<()> {
+        serde_json::to_writer(&mut self.scratch, &TypedRecord { record_type, data })?;
+        self.scratch.push(b'\n');
         Ok(())
     }
 
@@ -1068,7 +3900,8 @@ mod tests {
             result,
             Err(ParseError::InvalidDsoReference {
                 frame_id: 101,
-                dso_id: 999
+                dso_id: 999,
+                ..
             })
         ));
     }
@@ -1088,11 +3921,45 @@ mod tests {
             result,
             Err(ParseError::InvalidFrameReference {
                 stack_id,
-                frame_id: 999
+                frame_id: 999,
+                ..
             }) if stack_id == "0xabc"
         ));
     }
 
+    #[test]
+    fn validation_errors_report_the_record_location() {
+        let dso = r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#;
+        let data = format!(
+            "{}\n{}\n{}",
+            minimal_spaa(),
+            dso,
+            r#"{"type":"stack","id":"0xabc","frames":[999],"context":{"event":"cycles"},"weights":[{"metric":"period","value":1}]}"#
+        );
+        let error = SpaaFile::parse(Cursor::new(&data)).unwrap_err();
+
+        let offset = (minimal_spaa().len() + dso.len() + 2) as u64;
+        assert_eq!(error.location(), Some(Location { line: 3, offset }));
+        assert!(
+            error
+                .to_string()
+                .contains(&format!("line 3 (byte {offset})"))
+        );
+    }
+
+    #[test]
+    fn references_to_records_defined_later_are_accepted() {
+        let data = format!(
+            "{}\n{}\n{}\n{}",
+            minimal_spaa(),
+            r#"{"type":"stack","id":"0xabc","frames":[101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":1}]}"#,
+            r#"{"type":"frame","id":101,"func":"main","dso":1,"kind":"user"}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#
+        );
+
+        assert!(SpaaFile::parse(Cursor::new(data)).is_ok());
+    }
+
     #[test]
     fn missing_primary_metric_fails() {
         let data = format!(
@@ -1107,11 +3974,55 @@ mod tests {
 
         assert!(matches!(
             result,
-            Err(ParseError::MissingPrimaryMetric { stack_id, metric })
+            Err(ParseError::MissingPrimaryMetric { stack_id, metric, .. })
                 if stack_id == "0xabc" && metric == "period"
         ));
     }
 
+    #[test]
+    fn lenient_parsing_skips_bad_records_with_warnings() {
+        let data = [
+            minimal_spaa().as_str(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":101,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":102,"func":"lost","dso":9}"#,
+            r#"{"type":"stack","id":"0x1","frames":[101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":5}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[101],"context":{"event":"cycles"},"weights":[{"metric":"samples","value":1}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[102,101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":1}]}"#,
+            r#"{"type":"mystery"}"#,
+            r#"{"type":"sample","timestamp":1.0,"pid":1,"tid":1,"cpu":0,"event":"cycles","stack_id":"0x3"}"#,
+            r#"{"type":"sample","timestamp":2.0,"pid":1,"tid":1,"cpu":0,"event":"cycles","stack_id":"0x1"}"#,
+        ]
+        .join("\n");
+
+        assert!(SpaaFile::parse(Cursor::new(&data)).is_err());
+        let options = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let (file, diagnostics) =
+            SpaaFile::parse_with_options(Cursor::new(&data), &options).unwrap();
+
+        assert_eq!(file.stacks.keys().collect::<Vec<_>>(), vec!["0x1"]);
+        assert!(!file.frames.contains_key(&102));
+        assert_eq!(file.samples.len(), 1);
+        let warnings: Vec<_> = diagnostics
+            .warnings
+            .iter()
+            .map(|w| (w.location.map(|l| l.line), w.message.as_str()))
+            .collect();
+        assert_eq!(warnings.len(), 5);
+        assert_eq!(warnings[0].0, Some(6));
+        assert!(warnings[0].1.contains("missing primary metric"));
+        assert_eq!(warnings[1].0, Some(8));
+        assert!(warnings[2].1.contains("non-existent DSO 9"));
+        assert_eq!(
+            warnings[3],
+            (None, "stack 0x3 references frame 102, which was skipped")
+        );
+        assert!(warnings[4].1.starts_with("1 samples"));
+    }
+
     #[test]
     fn parse_thread_record() {
         let data = format!(
@@ -1122,46 +4033,246 @@ mod tests {
         let cursor = Cursor::new(data);
         let spaa = SpaaFile::parse(cursor).unwrap();
 
-        assert_eq!(spaa.threads.len(), 1);
-        let thread = &spaa.threads[&5678];
-        assert_eq!(thread.pid, 1234);
-        assert_eq!(thread.comm, Some("myapp".to_string()));
+        assert_eq!(spaa.threads.len(), 1);
+        let thread = &spaa.threads[&5678];
+        assert_eq!(thread.pid, 1234);
+        assert_eq!(thread.comm, Some("myapp".to_string()));
+    }
+
+    #[test]
+    fn parse_sample_record() {
+        let data = format!(
+            "{}\n{}\n{}\n{}\n{}",
+            minimal_spaa(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":101,"func":"main","dso":1,"kind":"user"}"#,
+            r#"{"type":"stack","id":"0xabc","frames":[101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":1}]}"#,
+            r#"{"type":"sample","timestamp":123.456,"pid":1000,"tid":1001,"cpu":0,"event":"cycles","stack_id":"0xabc"}"#
+        );
+        let cursor = Cursor::new(data);
+        let spaa = SpaaFile::parse(cursor).unwrap();
+
+        assert_eq!(spaa.samples.len(), 1);
+        assert_eq!(spaa.samples[0].timestamp, 123.456);
+        assert_eq!(spaa.samples[0].stack_id, "0xabc");
+    }
+
+    #[test]
+    fn parse_window_record() {
+        let data = format!(
+            "{}\n{}\n{}\n{}\n{}",
+            minimal_spaa(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":101,"func":"main","dso":1,"kind":"user"}"#,
+            r#"{"type":"stack","id":"0xabc","frames":[101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":1}]}"#,
+            r#"{"type":"window","id":"w1","start":0.0,"end":1.0,"unit":"seconds","by_stack":[{"stack_id":"0xabc","weights":[{"metric":"period","value":1}]}]}"#
+        );
+        let cursor = Cursor::new(data);
+        let spaa = SpaaFile::parse(cursor).unwrap();
+
+        assert_eq!(spaa.windows.len(), 1);
+        assert_eq!(spaa.windows[0].id, "w1");
+        assert_eq!(spaa.windows[0].by_stack.len(), 1);
+    }
+
+    #[test]
+    fn reader_streams_records_in_order() {
+        let data = format!(
+            "{}\n\n{}\n{}\n",
+            minimal_spaa(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#
+        );
+        let mut reader = SpaaReader::new(Cursor::new(data.as_bytes()));
+
+        assert!(matches!(reader.next(), Some(Ok(Record::Header(_)))));
+        assert!(matches!(reader.next(), Some(Ok(Record::Dso(_)))));
+        assert!(matches!(reader.next(), Some(Ok(Record::Frame(f))) if f.func == "main"));
+        assert!(reader.next().is_none());
+        assert_eq!(reader.line_number(), 4);
+        assert_eq!(reader.bytes_read(), data.len() as u64);
+    }
+
+    #[test]
+    fn slice_reader_matches_stream_reader() {
+        let data = format!(
+            "{}\r\n\n{}\n{}",
+            minimal_spaa(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#
+        );
+        let streamed: Vec<_> = SpaaReader::new(Cursor::new(data.as_bytes()))
+            .collect::<Result<_>>()
+            .unwrap();
+        let mut reader = SpaaSliceReader::new(data.as_bytes());
+        let sliced: Vec<_> = reader.by_ref().collect::<Result<_>>().unwrap();
+
+        assert_eq!(sliced, streamed);
+        assert_eq!(reader.line_number(), 4);
+        assert_eq!(reader.bytes_read(), data.len() as u64);
+    }
+
+    #[test]
+    fn slice_reader_requires_header() {
+        let mut reader = SpaaSliceReader::new(b"\n\n");
+        assert!(matches!(
+            reader.next(),
+            Some(Err(ParseError::MissingHeader))
+        ));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn open_parses_file() {
+        let path = std::env::temp_dir().join(format!("spaa-open-{}.spaa", std::process::id()));
+        std::fs::write(&path, deep_stack_spaa("leaf_to_root")).unwrap();
+        let result = SpaaFile::open(&path);
+        #[cfg(feature = "mmap")]
+        // SAFETY: nothing else writes the file.
+        let mapped = unsafe { SpaaFile::open_mmap(&path) };
+        std::fs::remove_file(&path).unwrap();
+
+        let file = result.unwrap();
+        assert_eq!(file.stacks["0xabc"].frames, vec![101, 102, 103]);
+        #[cfg(feature = "mmap")]
+        assert_eq!(mapped.unwrap(), file);
+    }
+
+    #[test]
+    fn reader_stops_after_error() {
+        let data = format!(
+            "{}\n{{\"type\":\"bogus\"}}\n{}\n",
+            minimal_spaa(),
+            minimal_spaa()
+        );
+        let mut reader = SpaaReader::new(Cursor::new(data));
+
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(
+            reader.next(),
+            Some(Err(ParseError::UnknownRecordType(_, 2)))
+        ));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn parse_state_record() {
+        let data = format!(
+            "{}\n{}\n{}\n{}\n{}",
+            minimal_spaa(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":101,"func":"main","dso":1,"kind":"user"}"#,
+            r#"{"type":"stack","id":"0xabc","frames":[101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":1}]}"#,
+            r#"{"type":"state","tid":1001,"state":"blocked","start":1.0,"end":1.5,"reason":"D","stack_id":"0xabc"}"#
+        );
+        let cursor = Cursor::new(data);
+        let spaa = SpaaFile::parse(cursor).unwrap();
+
+        assert_eq!(spaa.states.len(), 1);
+        assert_eq!(spaa.states[0].state, ThreadStateKind::Blocked);
+        assert_eq!(spaa.states[0].reason.as_deref(), Some("D"));
+    }
+
+    #[test]
+    fn state_with_invalid_stack_fails() {
+        let data = format!(
+            "{}\n{}",
+            minimal_spaa(),
+            r#"{"type":"state","tid":1,"state":"runnable","start":1.0,"end":1.5,"stack_id":"0xmissing"}"#
+        );
+        let result = SpaaFile::parse(Cursor::new(data));
+
+        assert!(matches!(
+            result,
+            Err(ParseError::InvalidStackReference {
+                record: "state",
+                ..
+            })
+        ));
+    }
+
+    /// Two events, `cpu-profile` counting `samples` and `cycles` counting
+    /// `period`, each with a stack, sample and window entry.
+    fn two_event_spaa() -> String {
+        [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}},{"name":"cpu-profile","kind":"timer","sampling":{"mode":"period","primary_metric":"samples"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":100},{"metric":"samples","value":2}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[1],"context":{"event":"cpu-profile"},"weights":[{"metric":"samples","value":5}],"exclusive":{"frame":1,"weights":[{"metric":"samples","value":5}]}}"#,
+            r#"{"type":"sample","timestamp":1.0,"pid":1,"tid":1,"cpu":0,"event":"cpu-profile","stack_id":"0x2"}"#,
+            r#"{"type":"window","id":"w1","start":0.0,"end":2.0,"unit":"seconds","by_stack":[{"stack_id":"0x2","weights":[{"metric":"samples","value":5}]}]}"#,
+        ]
+        .join("\n")
+    }
+
+    #[test]
+    fn rename_event_rewrites_stacks_and_samples() {
+        let mut spaa = SpaaFile::parse(Cursor::new(two_event_spaa())).unwrap();
+
+        assert_eq!(spaa.rename_event("cpu-profile", "cpu"), 2);
+        assert_eq!(spaa.header.events[1].name, "cpu");
+        assert_eq!(spaa.stacks["0x2"].context.event, "cpu");
+        assert_eq!(spaa.samples[0].event, "cpu");
+    }
+
+    #[test]
+    fn rename_event_onto_declared_event_merges_declarations() {
+        let mut spaa = SpaaFile::parse(Cursor::new(two_event_spaa())).unwrap();
+        spaa.rename_event("cpu-profile", "cycles");
+
+        assert_eq!(spaa.header.events.len(), 1);
+        assert_eq!(spaa.stacks_for_event("cycles").count(), 2);
     }
 
     #[test]
-    fn parse_sample_record() {
-        let data = format!(
-            "{}\n{}\n{}\n{}\n{}",
-            minimal_spaa(),
-            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
-            r#"{"type":"frame","id":101,"func":"main","dso":1,"kind":"user"}"#,
-            r#"{"type":"stack","id":"0xabc","frames":[101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":1}]}"#,
-            r#"{"type":"sample","timestamp":123.456,"pid":1000,"tid":1001,"cpu":0,"event":"cycles","stack_id":"0xabc"}"#
-        );
-        let cursor = Cursor::new(data);
-        let spaa = SpaaFile::parse(cursor).unwrap();
+    fn alias_metric_sums_into_existing_metric() {
+        let mut spaa = SpaaFile::parse(Cursor::new(two_event_spaa())).unwrap();
+
+        assert_eq!(spaa.alias_metric("samples", "count"), 3);
+        assert_eq!(spaa.header.events[1].sampling.primary_metric, "count");
+        let stack = &spaa.stacks["0x2"];
+        assert_eq!(stack.weights[0].metric, "count");
+        assert_eq!(stack.exclusive.as_ref().unwrap().weights[0].metric, "count");
+        assert_eq!(spaa.windows[0].by_stack[0].weights[0].metric, "count");
+
+        spaa.alias_metric("count", "period");
+        let weights = &spaa.stacks["0x1"].weights;
+        assert_eq!(weights.len(), 1);
+        assert_eq!(weights[0].value, 102);
+    }
 
-        assert_eq!(spaa.samples.len(), 1);
-        assert_eq!(spaa.samples[0].timestamp, 123.456);
-        assert_eq!(spaa.samples[0].stack_id, "0xabc");
+    #[test]
+    fn scale_weights_touches_one_event_and_metric() {
+        let mut spaa = SpaaFile::parse(Cursor::new(two_event_spaa())).unwrap();
+
+        assert_eq!(spaa.scale_weights("cpu-profile", "samples", 1.5), 1);
+        let stack = &spaa.stacks["0x2"];
+        assert_eq!(stack.weights[0].value, 8);
+        assert_eq!(stack.exclusive.as_ref().unwrap().weights[0].value, 8);
+        assert_eq!(spaa.windows[0].by_stack[0].weights[0].value, 8);
+        // `cycles` also has a `samples` weight, which is left alone.
+        assert_eq!(spaa.stacks["0x1"].weights[1].value, 2);
     }
 
     #[test]
-    fn parse_window_record() {
-        let data = format!(
-            "{}\n{}\n{}\n{}\n{}",
-            minimal_spaa(),
-            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
-            r#"{"type":"frame","id":101,"func":"main","dso":1,"kind":"user"}"#,
-            r#"{"type":"stack","id":"0xabc","frames":[101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":1}]}"#,
-            r#"{"type":"window","id":"w1","start":0.0,"end":1.0,"unit":"seconds","by_stack":[{"stack_id":"0xabc","weights":[{"metric":"period","value":1}]}]}"#
-        );
-        let cursor = Cursor::new(data);
-        let spaa = SpaaFile::parse(cursor).unwrap();
+    #[should_panic(expected = "scale factor")]
+    fn scale_weights_rejects_negative_factor() {
+        let mut spaa = SpaaFile::parse(Cursor::new(two_event_spaa())).unwrap();
+        spaa.scale_weights("cycles", "period", -1.0);
+    }
 
-        assert_eq!(spaa.windows.len(), 1);
-        assert_eq!(spaa.windows[0].id, "w1");
-        assert_eq!(spaa.windows[0].by_stack.len(), 1);
+    #[test]
+    fn derived_metric_is_added_or_replaced() {
+        let mut spaa = SpaaFile::parse(Cursor::new(two_event_spaa())).unwrap();
+        spaa.add_derived_metric("frames", |stack| stack.frames.len() as u64);
+        spaa.add_derived_metric("samples", |_| 7);
+
+        let weights = &spaa.stacks["0x1"].weights;
+        assert_eq!(weights.len(), 3);
+        assert_eq!(weights[1].value, 7);
+        assert_eq!(weights[2].metric, "frames");
+        assert_eq!(weights[2].value, 1);
     }
 
     #[test]
@@ -1241,6 +4352,468 @@ mod tests {
         ));
     }
 
+    fn thread_spaa() -> String {
+        [
+            minimal_spaa(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#.to_string(),
+            r#"{"type":"frame","id":101,"func":"main","dso":1}"#.to_string(),
+            r#"{"type":"thread","pid":1,"tid":10,"comm":"worker"}"#.to_string(),
+            r#"{"type":"thread","pid":1,"tid":11}"#.to_string(),
+            r#"{"type":"stack","id":"0x1","frames":[101],"context":{"event":"cycles","pid":1,"tid":10},"weights":[{"metric":"period","value":1}]}"#.to_string(),
+            r#"{"type":"stack","id":"0x2","frames":[101],"context":{"event":"cycles","pid":1,"tid":11,"comm":"io"},"weights":[{"metric":"period","value":1}]}"#.to_string(),
+            r#"{"type":"stack","id":"0x3","frames":[101],"context":{"event":"cycles","pid":1,"tid":12,"comm":"gc"},"weights":[{"metric":"period","value":1}]}"#.to_string(),
+        ]
+        .join("\n")
+    }
+
+    #[test]
+    fn enrich_threads_fills_comms_both_ways() {
+        let options = ParseOptions {
+            enrich_threads: true,
+            ..Default::default()
+        };
+        let (spaa, diagnostics) =
+            SpaaFile::parse_with_options(Cursor::new(thread_spaa()), &options).unwrap();
+
+        assert_eq!(diagnostics.enriched_threads, 2);
+        assert_eq!(diagnostics.enriched_stacks, 1);
+        assert_eq!(spaa.stacks["0x1"].context.comm.as_deref(), Some("worker"));
+        assert_eq!(spaa.threads[&11].comm.as_deref(), Some("io"));
+        assert_eq!(spaa.thread_for(1, 12).unwrap().comm.as_deref(), Some("gc"));
+    }
+
+    #[test]
+    fn thread_for_requires_a_matching_pid() {
+        let spaa = SpaaFile::parse(Cursor::new(thread_spaa())).unwrap();
+
+        assert!(spaa.thread_for(1, 10).is_some());
+        assert!(spaa.thread_for(2, 10).is_none());
+        assert!(spaa.thread_for(1, 12).is_none());
+        assert_eq!(
+            spaa.thread_for_context(&spaa.stacks["0x1"].context)
+                .and_then(|t| t.comm.as_deref()),
+            Some("worker")
+        );
+    }
+
+    #[test]
+    fn resolve_sample_looks_up_thread_stack_and_frames() {
+        let data = format!(
+            "{}\n{}",
+            thread_spaa(),
+            r#"{"type":"sample","timestamp":1.5,"pid":1,"tid":11,"cpu":0,"event":"cycles","stack_id":"0x2"}"#
+        );
+        let spaa = SpaaFile::parse(Cursor::new(data)).unwrap();
+        let resolved = spaa.resolve_sample(&spaa.samples[0]);
+
+        assert_eq!(resolved.timestamp, 1.5);
+        assert_eq!(resolved.thread, spaa.threads.get(&11));
+        assert_eq!(resolved.comm, Some("io"));
+        assert_eq!(resolved.stack.map(|s| s.id.as_str()), Some("0x2"));
+        let funcs: Vec<_> = resolved
+            .frames
+            .iter()
+            .map(|f| f.unwrap().func.as_str())
+            .collect();
+        assert_eq!(funcs, vec!["main"]);
+    }
+
+    #[test]
+    fn byte_order_mark_and_crlf_line_endings_are_accepted() {
+        let data = format!("\u{feff}{}  \r\n\r\n", thread_spaa().replace('\n', "\r\n"));
+
+        let spaa = SpaaFile::parse(Cursor::new(&data)).unwrap();
+        assert_eq!(spaa.stacks.len(), 3);
+        let spaa = SpaaFile::parse_slice(data.as_bytes()).unwrap();
+        assert_eq!(spaa.threads.len(), 2);
+    }
+
+    #[test]
+    fn utf16_input_is_rejected() {
+        let utf16: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain(minimal_spaa().encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+
+        assert!(matches!(
+            SpaaFile::parse(Cursor::new(utf16)),
+            Err(ParseError::Utf16Input)
+        ));
+    }
+
+    fn deep_stack_spaa(frame_order: &str) -> String {
+        let header = minimal_spaa().replace("leaf_to_root", frame_order);
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            header,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":101,"func":"leaf","dso":1,"kind":"user"}"#,
+            r#"{"type":"frame","id":102,"func":"middle","dso":1,"kind":"user"}"#,
+            r#"{"type":"frame","id":103,"func":"root","dso":1,"kind":"user"}"#,
+            r#"{"type":"stack","id":"0xabc","frames":[101,102,103],"context":{"event":"cycles"},"weights":[{"metric":"period","value":1}]}"#
+        )
+    }
+
+    #[test]
+    fn max_stack_depth_truncates_leaf_to_root() {
+        let options = ParseOptions {
+            max_stack_depth: Some(2),
+            ..Default::default()
+        };
+        let (spaa, diagnostics) =
+            SpaaFile::parse_with_options(Cursor::new(deep_stack_spaa("leaf_to_root")), &options)
+                .unwrap();
+
+        assert_eq!(diagnostics.truncated_stacks, 1);
+        let frames = &spaa.stacks["0xabc"].frames;
+        assert_eq!(&frames[..2], &[101, 102]);
+        let sentinel = &spaa.frames[&frames[2]];
+        assert_eq!(sentinel.func, TRUNCATED_FRAME_NAME);
+        assert_eq!(spaa.dsos[&sentinel.dso].name, TRUNCATED_FRAME_NAME);
+    }
+
+    #[test]
+    fn max_stack_depth_truncates_root_to_leaf() {
+        let options = ParseOptions {
+            max_stack_depth: Some(2),
+            ..Default::default()
+        };
+        let (spaa, _) =
+            SpaaFile::parse_with_options(Cursor::new(deep_stack_spaa("root_to_leaf")), &options)
+                .unwrap();
+
+        let frames = &spaa.stacks["0xabc"].frames;
+        assert_eq!(spaa.frames[&frames[0]].func, TRUNCATED_FRAME_NAME);
+        assert_eq!(&frames[1..], &[102, 103]);
+    }
+
+    #[test]
+    fn max_stack_depth_leaves_shallow_stacks_alone() {
+        let options = ParseOptions {
+            max_stack_depth: Some(3),
+            ..Default::default()
+        };
+        let (spaa, diagnostics) =
+            SpaaFile::parse_with_options(Cursor::new(deep_stack_spaa("leaf_to_root")), &options)
+                .unwrap();
+
+        assert_eq!(diagnostics.truncated_stacks, 0);
+        assert_eq!(spaa.stacks["0xabc"].frames, vec![101, 102, 103]);
+        assert_eq!(spaa.frames.len(), 3);
+    }
+
+    #[test]
+    fn parse_reports_progress() {
+        let data = deep_stack_spaa("leaf_to_root");
+        let options = ParseOptions {
+            size_hint: Some(data.len() as u64),
+            ..Default::default()
+        };
+        let mut updates = Vec::new();
+        let mut sink = |p: &Progress| {
+            updates.push(*p);
+            ControlFlow::Continue(())
+        };
+        SpaaFile::parse_with_progress(Cursor::new(data.clone()), &options, &mut sink).unwrap();
+
+        let last = updates.last().unwrap();
+        assert_eq!(last.stage, "parse");
+        assert_eq!(last.done, data.len() as u64);
+        assert_eq!(last.fraction(), Some(1.0));
+    }
+
+    #[test]
+    fn parse_can_be_cancelled() {
+        let mut sink = |_: &Progress| ControlFlow::Break(());
+        let result = SpaaFile::parse_with_progress(
+            Cursor::new(deep_stack_spaa("leaf_to_root")),
+            &ParseOptions::default(),
+            &mut sink,
+        );
+        assert!(matches!(result, Err(ParseError::Cancelled(Cancelled))));
+    }
+
+    fn inclusive_tree_spaa() -> String {
+        [
+            minimal_spaa().as_str(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"foo","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"bar","dso":1}"#,
+            r#"{"type":"frame","id":4,"func":"baz","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":100}],"exclusive":{"frame":1,"weights":[{"metric":"period","value":100}]}}"#,
+            r#"{"type":"stack","id":"0x2","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":60}]}"#,
+            r#"{"type":"stack","id":"0x3","frames":[3,2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":20}]}"#,
+            r#"{"type":"stack","id":"0x4","frames":[4,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":10}]}"#,
+        ]
+        .join("\n")
+    }
+
+    #[test]
+    fn recompute_exclusive_subtracts_children() {
+        let mut spaa = SpaaFile::parse(Cursor::new(inclusive_tree_spaa())).unwrap();
+
+        assert_eq!(spaa.recompute_exclusive(), 4);
+
+        let exclusive = |id: &str| {
+            let ex = spaa.stacks[id].exclusive.as_ref().unwrap();
+            (ex.frame, ex.weights[0].value)
+        };
+        assert_eq!(exclusive("0x1"), (1, 30));
+        assert_eq!(exclusive("0x2"), (2, 40));
+        assert_eq!(exclusive("0x3"), (3, 20));
+        assert_eq!(exclusive("0x4"), (4, 10));
+    }
+
+    #[test]
+    fn recompute_exclusive_is_idempotent() {
+        let mut spaa = SpaaFile::parse(Cursor::new(inclusive_tree_spaa())).unwrap();
+        spaa.recompute_exclusive();
+
+        assert_eq!(spaa.recompute_exclusive(), 0);
+    }
+
+    #[test]
+    fn function_weights_count_recursion_once() {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}},{"name":"instructions","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":101,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":102,"func":"walk","dso":1}"#,
+            r#"{"type":"frame","id":103,"func":"visit","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[103,102,102,101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":10}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[102,101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":5}],"exclusive":{"frame":102,"weights":[{"metric":"period","value":2}]}}"#,
+            r#"{"type":"stack","id":"0x3","frames":[101],"context":{"event":"instructions"},"weights":[{"metric":"period","value":1000}]}"#,
+        ]
+        .join("\n");
+        let spaa = SpaaFile::parse(Cursor::new(data)).unwrap();
+
+        let functions = spaa.function_weights("cycles", "period");
+        let weights: Vec<(&str, u64, u64)> = functions
+            .iter()
+            .map(|f| (f.func.as_str(), f.inclusive, f.exclusive))
+            .collect();
+        assert_eq!(
+            weights,
+            [("walk", 15, 2), ("main", 15, 0), ("visit", 10, 10)]
+        );
+    }
+
+    #[test]
+    fn filter_prunes_unreferenced_records() {
+        let data = [
+            minimal_spaa().as_str(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"dso","id":2,"name":"/usr/lib/libc.so.6","is_kernel":false}"#,
+            r#"{"type":"frame","id":101,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":102,"func":"memcpy","dso":2}"#,
+            r#"{"type":"thread","pid":1,"tid":1,"comm":"main"}"#,
+            r#"{"type":"thread","pid":1,"tid":2,"comm":"copier"}"#,
+            r#"{"type":"stack","id":"0x1","frames":[101],"context":{"event":"cycles","tid":1},"weights":[{"metric":"period","value":10}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[102,101],"context":{"event":"cycles","tid":2},"weights":[{"metric":"period","value":20}]}"#,
+            r#"{"type":"sample","timestamp":1.0,"pid":1,"tid":1,"cpu":0,"event":"cycles","stack_id":"0x1"}"#,
+            r#"{"type":"sample","timestamp":2.0,"pid":1,"tid":2,"cpu":0,"event":"cycles","stack_id":"0x2"}"#,
+            r#"{"type":"window","id":"w1","start":0.0,"end":1.0,"unit":"seconds","by_stack":[{"stack_id":"0x2","weights":[{"metric":"period","value":20}]}]}"#,
+            r#"{"type":"window","id":"w2","start":1.0,"end":2.0,"unit":"seconds","by_stack":[{"stack_id":"0x1","weights":[{"metric":"period","value":10}]},{"stack_id":"0x2","weights":[{"metric":"period","value":20}]}]}"#,
+        ]
+        .join("\n");
+        let spaa = SpaaFile::parse(Cursor::new(data)).unwrap();
+
+        let main = spaa.filter(|s| s.context.tid == Some(1));
+        assert_eq!(main.stacks.keys().collect::<Vec<_>>(), ["0x1"]);
+        assert_eq!(main.frames.keys().collect::<Vec<_>>(), [&101]);
+        assert_eq!(main.dsos.keys().collect::<Vec<_>>(), [&1]);
+        assert_eq!(main.threads.keys().collect::<Vec<_>>(), [&1]);
+        assert_eq!(main.samples.len(), 1);
+        assert_eq!(main.windows.len(), 1);
+        assert_eq!(main.windows[0].by_stack.len(), 1);
+
+        // The pruned file is consistent on its own.
+        let mut out = Vec::new();
+        main.write(&mut out).unwrap();
+        assert!(
+            SpaaFile::parse(Cursor::new(out))
+                .unwrap()
+                .semantically_eq(&main)
+        );
+    }
+
+    #[test]
+    fn merge_weights_sums_exclusive_per_frame() {
+        let period = |value| Weight {
+            metric: "period".to_string(),
+            value,
+            unit: None,
+        };
+        let spaa = SpaaFile::parse(Cursor::new(inclusive_tree_spaa())).unwrap();
+        let without = Stack {
+            exclusive: None,
+            ..spaa.stacks.values().next().unwrap().clone()
+        };
+        let with = Stack {
+            weights: vec![period(10)],
+            exclusive: Some(ExclusiveWeights {
+                frame: without.frames[0],
+                weights: vec![period(10)],
+            }),
+            ..without.clone()
+        };
+        let mut merged = without.clone();
+        merged.merge_weights(&with);
+        merged.merge_weights(&with);
+        merged.merge_weights(&without);
+
+        let exclusive = merged.exclusive.unwrap();
+        assert_eq!(exclusive.frame, without.frames[0]);
+        assert_eq!(exclusive.weights, vec![period(20)]);
+        assert_eq!(merged.weights[0].value, 2 * without.weights[0].value + 20);
+    }
+
+    /// `inclusive_tree_spaa` with different IDs, frame order and record order.
+    fn renumbered_tree_spaa() -> String {
+        [
+            minimal_spaa().replace("leaf_to_root", "root_to_leaf").as_str(),
+            r#"{"type":"dso","id":7,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":40,"func":"baz","dso":7}"#,
+            r#"{"type":"frame","id":30,"func":"bar","dso":7}"#,
+            r#"{"type":"frame","id":20,"func":"foo","dso":7}"#,
+            r#"{"type":"frame","id":10,"func":"main","dso":7}"#,
+            r#"{"type":"stack","id":"d","frames":[10,40],"context":{"event":"cycles"},"weights":[{"metric":"period","value":10}]}"#,
+            r#"{"type":"stack","id":"c","frames":[10,20,30],"context":{"event":"cycles"},"weights":[{"metric":"period","value":20}]}"#,
+            r#"{"type":"stack","id":"b","frames":[10,20],"context":{"event":"cycles"},"weights":[{"metric":"period","value":60}]}"#,
+            r#"{"type":"stack","id":"a","frames":[10],"context":{"event":"cycles"},"weights":[{"metric":"period","value":100}],"exclusive":{"frame":10,"weights":[{"metric":"period","value":100}]}}"#,
+        ]
+        .join("\n")
+    }
+
+    #[test]
+    fn renumbered_files_are_semantically_equal() {
+        let a = SpaaFile::parse(Cursor::new(inclusive_tree_spaa())).unwrap();
+        let b = SpaaFile::parse(Cursor::new(renumbered_tree_spaa())).unwrap();
+
+        assert_ne!(a, b);
+        assert!(a.semantically_eq(&b));
+    }
+
+    #[test]
+    fn changed_weight_is_not_semantically_equal() {
+        let a = SpaaFile::parse(Cursor::new(inclusive_tree_spaa())).unwrap();
+        let b = SpaaFile::parse(Cursor::new(
+            renumbered_tree_spaa().replace("\"value\":20", "\"value\":21"),
+        ))
+        .unwrap();
+
+        assert!(!a.semantically_eq(&b));
+    }
+
+    #[test]
+    fn normalized_merges_identical_stacks() {
+        let data = inclusive_tree_spaa().replace(r#""frames":[4,1]"#, r#""frames":[2,1]"#);
+        let normalized = SpaaFile::parse(Cursor::new(data)).unwrap().normalized();
+
+        assert_eq!(normalized.stacks.len(), 3);
+        let foo = normalized
+            .stacks
+            .values()
+            .find(|s| s.frames.len() == 2)
+            .unwrap();
+        assert_eq!(foo.weights[0].value, 70);
+    }
+
+    /// A stack whose context carries a probe, trace fields and extension
+    /// fields.
+    fn extended_context_spaa() -> String {
+        [
+            minimal_spaa().as_str(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[1],"context":{"event":"cycles","probe":{"provider":"syscall","module":"","function":"read","name":"entry"},"trace_fields":{"fd":3,"count":4096},"numa_node":1,"acme.build":"a1b2"},"weights":[{"metric":"period","value":100}]}"#,
+        ]
+        .join("\n")
+    }
+
+    #[test]
+    fn context_extensions_survive_write() {
+        let spaa = SpaaFile::parse(Cursor::new(extended_context_spaa())).unwrap();
+        let mut buf = Vec::new();
+        spaa.write(&mut buf).unwrap();
+        let reparsed = SpaaFile::parse(Cursor::new(buf)).unwrap();
+
+        assert_eq!(reparsed.stacks["0x1"].context, spaa.stacks["0x1"].context);
+        assert_eq!(reparsed.stacks["0x1"].context.extra.len(), 2);
+    }
+
+    #[test]
+    fn normalized_keeps_context_extensions() {
+        let spaa = SpaaFile::parse(Cursor::new(extended_context_spaa())).unwrap();
+        let normalized = spaa.normalized();
+
+        let stack = normalized.stacks.values().next().unwrap();
+        assert_eq!(stack.context, spaa.stacks["0x1"].context);
+    }
+
+    #[test]
+    fn typed_accessors_read_trace_fields_and_extra() {
+        let spaa = SpaaFile::parse(Cursor::new(extended_context_spaa())).unwrap();
+        let stack = &spaa.stacks["0x1"];
+
+        assert_eq!(stack.get_trace_field::<u64>("count"), Some(4096));
+        assert_eq!(
+            stack.get_extra::<String>("acme.build").as_deref(),
+            Some("a1b2")
+        );
+        assert_eq!(stack.get_extra::<u32>("acme.build"), None);
+        assert_eq!(stack.get_extra::<u32>("missing"), None);
+    }
+
+    #[test]
+    fn normalized_is_idempotent() {
+        let normalized = SpaaFile::parse(Cursor::new(renumbered_tree_spaa()))
+            .unwrap()
+            .normalized();
+
+        assert_eq!(normalized.normalized(), normalized);
+    }
+
+    #[test]
+    fn section_sizes_match_written_output() {
+        let spaa = SpaaFile::parse(Cursor::new(inclusive_tree_spaa())).unwrap();
+        let mut output = Vec::new();
+        spaa.write(&mut output).unwrap();
+
+        let sizes = spaa.estimate_bytes_per_section();
+        assert_eq!(sizes.total(), output.len() as u64);
+        assert_eq!(
+            sizes.dsos,
+            output.split(|&b| b == b'\n').nth(1).unwrap().len() as u64 + 1
+        );
+        assert_eq!(
+            spaa.estimate_tokens(),
+            sizes.total().div_ceil(BYTES_PER_TOKEN)
+        );
+    }
+
+    #[test]
+    fn exclusive_greater_than_inclusive_fails() {
+        let data = format!(
+            "{}\n{}\n{}\n{}",
+            minimal_spaa(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":10}],"exclusive":{"frame":1,"weights":[{"metric":"period","value":11}]}}"#
+        );
+        let result = SpaaFile::parse(Cursor::new(data));
+
+        assert!(matches!(
+            result,
+            Err(ParseError::ExclusiveExceedsInclusive {
+                exclusive: 11,
+                inclusive: 10,
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn write_and_read_roundtrip() {
         // Parse a file
@@ -1272,6 +4845,195 @@ mod tests {
         );
     }
 
+    fn sampled_spaa() -> String {
+        [
+            thread_spaa(),
+            r#"{"type":"sample","timestamp":1.0,"pid":1,"tid":10,"cpu":0,"event":"cycles","period":5,"stack_id":"0x1"}"#.to_string(),
+            r#"{"type":"sample","timestamp":1.25,"pid":1,"tid":10,"cpu":1,"event":"cycles","period":6,"stack_id":"0x2"}"#.to_string(),
+            r#"{"type":"sample","timestamp":2.0,"pid":1,"tid":11,"cpu":0,"event":"cycles","period":7,"stack_id":"0x1"}"#.to_string(),
+            r#"{"type":"sample","timestamp":2.5,"pid":1,"tid":11,"cpu":0,"event":"cycles","stack_id":"0x1","context":{"tag":1}}"#.to_string(),
+        ]
+        .join("\n")
+    }
+
+    #[test]
+    fn system_record_round_trips() {
+        let data = format!(
+            "{}\n{}",
+            minimal_spaa(),
+            r#"{"type":"system","hostname":"build-7","os":"linux","cpu_model":"AMD EPYC 7B13","cpu_cores":64,"memory_bytes":270582939648}"#
+        );
+        let spaa = SpaaFile::parse(Cursor::new(data)).unwrap();
+        let system = spaa.system.clone().unwrap();
+        assert_eq!(system.hostname.as_deref(), Some("build-7"));
+        assert_eq!(system.cpu_cores, Some(64));
+
+        let mut out = Vec::new();
+        spaa.write(&mut out).unwrap();
+        assert_eq!(
+            SpaaFile::parse(Cursor::new(out)).unwrap().system,
+            Some(system)
+        );
+    }
+
+    #[test]
+    fn note_records_round_trip_and_are_checked() {
+        let stack = r#"{"type":"stack","id":"0x1","frames":[101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":1}]}"#;
+        let data = [
+            minimal_spaa().as_str(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":101,"func":"main","dso":1}"#,
+            stack,
+            r#"{"type":"note","author":"sam","timestamp":1700000000,"text":"hot loop","stack_id":"0x1","frame_id":101}"#,
+        ]
+        .join("\n");
+        let spaa = SpaaFile::parse(Cursor::new(&data)).unwrap();
+        assert_eq!(spaa.notes.len(), 1);
+        assert_eq!(spaa.notes[0].frame_id, Some(101));
+
+        let mut out = Vec::new();
+        spaa.write(&mut out).unwrap();
+        assert_eq!(SpaaFile::parse(Cursor::new(out)).unwrap().notes, spaa.notes);
+
+        let dangling = data.replace(r#""frame_id":101"#, r#""frame_id":7"#);
+        assert!(matches!(
+            SpaaFile::parse(Cursor::new(dangling)),
+            Err(ParseError::InvalidNoteFrameReference { frame_id: 7, location }) if location.line == 5
+        ));
+    }
+
+    #[test]
+    fn rebase_to_wall_clock_adds_the_epoch_offset() {
+        let mut spaa = SpaaFile::parse(Cursor::new(sampled_spaa())).unwrap();
+        spaa.header.clock = Some(ClockInfo {
+            source: ClockSource::Monotonic,
+            epoch_offset: Some(1_700_000_000.0),
+            timezone: None,
+        });
+
+        assert!(spaa.rebase_to_wall_clock());
+        assert_eq!(spaa.samples[0].timestamp, 1_700_000_001.0);
+        let clock = spaa.header.clock.unwrap();
+        assert_eq!(
+            (clock.source, clock.epoch_offset),
+            (ClockSource::Wall, None)
+        );
+    }
+
+    #[test]
+    fn rebase_without_an_offset_changes_nothing() {
+        let mut spaa = SpaaFile::parse(Cursor::new(sampled_spaa())).unwrap();
+        spaa.header.clock = Some(ClockInfo {
+            source: ClockSource::Monotonic,
+            epoch_offset: None,
+            timezone: Some("UTC".to_string()),
+        });
+
+        assert!(!spaa.rebase_to_wall_clock());
+        assert_eq!(spaa.samples[0].timestamp, 1.0);
+    }
+
+    #[test]
+    fn batched_samples_round_trip() {
+        let original = SpaaFile::parse(Cursor::new(sampled_spaa())).unwrap();
+        let options = WriteOptions {
+            sample_batching: Some(SampleBatching {
+                max_samples: 2,
+                resolution: 0.25,
+            }),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        original.write_with_options(&mut output, &options).unwrap();
+        let text = String::from_utf8(output).unwrap();
+
+        assert_eq!(text.matches(r#""type":"samples""#).count(), 2);
+        assert_eq!(text.matches(r#""type":"sample""#).count(), 1);
+        let roundtrip = SpaaFile::parse(Cursor::new(text)).unwrap();
+        assert_eq!(roundtrip.samples, original.samples);
+    }
+
+    #[test]
+    fn sample_batch_with_mismatched_lengths_fails() {
+        let data = format!(
+            "{}\n{}",
+            thread_spaa(),
+            r#"{"type":"samples","event":"cycles","start":1.0,"resolution":0.001,"deltas":[0,1],"stack_ids":["0x1","0x2"],"threads":[{"pid":1,"tid":10,"count":1}],"cpus":[0,0]}"#
+        );
+        let result = SpaaFile::parse(Cursor::new(data));
+
+        assert!(matches!(
+            result,
+            Err(ParseError::InvalidSampleBatch { line: 9, .. })
+        ));
+    }
+
+    #[test]
+    fn stack_order_by_weight_puts_hottest_stacks_first() {
+        let data = format!(
+            "{}\n{}\n{}\n{}\n{}",
+            minimal_spaa(),
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"frame","id":101,"func":"main","dso":1}"#,
+            r#"{"type":"stack","id":"0x1","frames":[101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":10}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[101],"context":{"event":"cycles"},"weights":[{"metric":"period","value":30}]}"#
+        );
+        let spaa = SpaaFile::parse(Cursor::new(data)).unwrap();
+        let options = WriteOptions {
+            stack_order: StackOrder::ByWeightDesc,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        spaa.write_with_options(&mut output, &options).unwrap();
+
+        let ids: Vec<String> = SpaaReader::new(Cursor::new(output))
+            .filter_map(|r| match r.unwrap() {
+                Record::Stack(stack) => Some(stack.id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, vec!["0x2", "0x1"]);
+    }
+
+    #[test]
+    fn first_reference_order_follows_samples() {
+        let data = format!(
+            "{}\n{}\n{}",
+            thread_spaa(),
+            r#"{"type":"sample","timestamp":1.0,"pid":1,"tid":12,"cpu":0,"event":"cycles","stack_id":"0x3"}"#,
+            r#"{"type":"sample","timestamp":2.0,"pid":1,"tid":10,"cpu":0,"event":"cycles","stack_id":"0x1"}"#
+        );
+        let spaa = SpaaFile::parse(Cursor::new(data)).unwrap();
+        let ids: Vec<&str> = spaa
+            .stacks_in_order(StackOrder::FirstReference)
+            .iter()
+            .map(|s| s.id.as_str())
+            .collect();
+
+        assert_eq!(ids, vec!["0x3", "0x1", "0x2"]);
+    }
+
+    #[test]
+    fn write_batch_matches_writing_records_one_at_a_time() {
+        let records: Vec<Record> = SpaaReader::new(Cursor::new(thread_spaa()))
+            .collect::<Result<_>>()
+            .unwrap();
+
+        let mut batched = SpaaWriter::new(Vec::new());
+        batched.write_batch(&records).unwrap();
+        let mut single = SpaaWriter::new(Vec::new());
+        for record in &records {
+            single.write_batch(std::slice::from_ref(record)).unwrap();
+        }
+
+        let batched = batched.into_inner();
+        assert_eq!(batched, single.into_inner());
+        let reread: Vec<Record> = SpaaReader::new(Cursor::new(batched))
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(reread, records);
+    }
+
     #[test]
     fn spaa_writer_creates_valid_output() {
         let mut output = Vec::new();
@@ -1293,10 +5055,14 @@ mod tests {
                         frequency_hz: None,
                     },
                     allocation_tracking: None,
+                    description: None,
+                    unit: None,
                 }],
                 time_range: None,
                 source: None,
                 stack_id_mode: StackIdMode::ContentAddressable,
+                clock: None,
+                approximation: None,
             };
             writer.write_header(&header).unwrap();
 
@@ -1337,6 +5103,9 @@ mod tests {
                     execname: None,
                     uid: None,
                     zonename: None,
+                    cgroup: None,
+                    container_id: None,
+                    k8s_pod: None,
                     trace_fields: None,
                     extra: HashMap::new(),
                 },
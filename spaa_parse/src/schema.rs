@@ -0,0 +1,227 @@
+//! JSON Schema for SPAA records (requires the `schema` feature).
+//!
+//! The schemas are generated from the same types the parser deserializes
+//! into, so they accept exactly what [`SpaaFile::parse`](crate::SpaaFile::parse)
+//! accepts record by record. Reference checks (stack frames pointing at
+//! known frame IDs, and so on) are beyond what JSON Schema can express and
+//! are left to the parser.
+//!
+//! Each schema carries an `$id` of the form `urn:spaa:schema:<version>:<type>`
+//! so producers can pin the format version they validate against.
+//!
+//! # Example
+//!
+//! ```
+//! use spaa_parse::schema::{RecordType, record_schema};
+//!
+//! let schema = record_schema(RecordType::Stack);
+//! assert_eq!(schema["properties"]["type"]["const"], "stack");
+//! ```
+
+use crate::{
+    Dso, Footer, Frame, Header, Note, Sample, SampleBatch, Stack, SystemInfo, Thread, ThreadState,
+    Window,
+};
+use schemars::{JsonSchema, SchemaGenerator, generate::SchemaSettings};
+use serde_json::{Value, json};
+use std::fmt;
+use std::str::FromStr;
+
+/// SPAA format version the schemas describe.
+pub const FORMAT_VERSION: &str = "1.0";
+
+/// Kind of NDJSON record, as named by its `type` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordType {
+    Header,
+    System,
+    Dso,
+    Frame,
+    Thread,
+    Stack,
+    Sample,
+    Samples,
+    Window,
+    State,
+    Note,
+    Footer,
+}
+
+impl RecordType {
+    /// Every record type, in the order records appear in a file.
+    pub const ALL: [RecordType; 12] = [
+        RecordType::Header,
+        RecordType::System,
+        RecordType::Dso,
+        RecordType::Frame,
+        RecordType::Thread,
+        RecordType::Stack,
+        RecordType::Sample,
+        RecordType::Samples,
+        RecordType::Window,
+        RecordType::State,
+        RecordType::Note,
+        RecordType::Footer,
+    ];
+
+    /// Value of the record's `type` field.
+    pub fn name(self) -> &'static str {
+        match self {
+            RecordType::Header => "header",
+            RecordType::System => "system",
+            RecordType::Dso => "dso",
+            RecordType::Frame => "frame",
+            RecordType::Thread => "thread",
+            RecordType::Stack => "stack",
+            RecordType::Sample => "sample",
+            RecordType::Samples => "samples",
+            RecordType::Window => "window",
+            RecordType::State => "state",
+            RecordType::Note => "note",
+            RecordType::Footer => "footer",
+        }
+    }
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Error returned when parsing an unknown [`RecordType`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseRecordTypeError(String);
+
+impl fmt::Display for ParseRecordTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown record type '{}' (expected header, system, dso, frame, thread, stack, sample, samples, window, state, note or footer)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseRecordTypeError {}
+
+impl FromStr for RecordType {
+    type Err = ParseRecordTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RecordType::ALL
+            .into_iter()
+            .find(|t| t.name() == s)
+            .ok_or_else(|| ParseRecordTypeError(s.to_string()))
+    }
+}
+
+/// JSON Schema (draft 2020-12) for one record type, including its `type`
+/// discriminator.
+pub fn record_schema(record: RecordType) -> Value {
+    let generator = SchemaSettings::draft2020_12()
+        .for_deserialize()
+        .into_generator();
+    let schema = match record {
+        RecordType::Header => root::<Header>(generator),
+        RecordType::System => root::<SystemInfo>(generator),
+        RecordType::Dso => root::<Dso>(generator),
+        RecordType::Frame => root::<Frame>(generator),
+        RecordType::Thread => root::<Thread>(generator),
+        RecordType::Stack => root::<Stack>(generator),
+        RecordType::Sample => root::<Sample>(generator),
+        RecordType::Samples => root::<SampleBatch>(generator),
+        RecordType::Window => root::<Window>(generator),
+        RecordType::State => root::<ThreadState>(generator),
+        RecordType::Note => root::<Note>(generator),
+        RecordType::Footer => root::<Footer>(generator),
+    };
+    tag(schema, record)
+}
+
+/// JSON Schema matching any single line of a SPAA file.
+///
+/// Each alternative is the corresponding [`record_schema`], embedded with
+/// its own `$id`.
+pub fn line_schema() -> Value {
+    let records: Vec<Value> = RecordType::ALL.into_iter().map(record_schema).collect();
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": format!("urn:spaa:schema:{FORMAT_VERSION}"),
+        "title": format!("SPAA {FORMAT_VERSION} record"),
+        "oneOf": records,
+    })
+}
+
+fn root<T: JsonSchema>(generator: SchemaGenerator) -> Value {
+    generator.into_root_schema_for::<T>().to_value()
+}
+
+/// Add the `type` discriminator, `$id` and title to a generated schema.
+fn tag(mut schema: Value, record: RecordType) -> Value {
+    let name = record.name();
+    let object = schema.as_object_mut().expect("struct schemas are objects");
+    object.insert(
+        "$id".to_string(),
+        json!(format!("urn:spaa:schema:{FORMAT_VERSION}:{name}")),
+    );
+    object.insert(
+        "title".to_string(),
+        json!(format!("SPAA {FORMAT_VERSION} {name} record")),
+    );
+
+    let properties = object
+        .entry("properties")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .expect("properties is an object");
+    properties.insert("type".to_string(), json!({ "const": name }));
+
+    let required = object
+        .entry("required")
+        .or_insert_with(|| json!([]))
+        .as_array_mut()
+        .expect("required is an array");
+    required.insert(0, json!("type"));
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_type_round_trips_through_str() {
+        for record in RecordType::ALL {
+            assert_eq!(record.name().parse::<RecordType>(), Ok(record));
+        }
+        assert!("stacks".parse::<RecordType>().is_err());
+    }
+
+    #[test]
+    fn stack_schema_requires_discriminator_and_fields() {
+        let schema = record_schema(RecordType::Stack);
+
+        assert_eq!(schema["$id"], "urn:spaa:schema:1.0:stack");
+        let required: Vec<&str> = schema["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(Value::as_str)
+            .collect();
+        assert_eq!(required[0], "type");
+        for field in ["id", "frames", "context", "weights"] {
+            assert!(required.contains(&field), "{field} not required");
+        }
+        assert!(!required.contains(&"stack_type"));
+    }
+
+    #[test]
+    fn line_schema_covers_every_record() {
+        let schema = line_schema();
+        let alternatives = schema["oneOf"].as_array().unwrap();
+
+        assert_eq!(alternatives.len(), RecordType::ALL.len());
+        assert_eq!(alternatives[9]["properties"]["type"]["const"], "state");
+    }
+}
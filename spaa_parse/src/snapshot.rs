@@ -0,0 +1,219 @@
+//! Immutable, shareable views of a parsed profile.
+//!
+//! A [`SpaaSnapshot`] owns a [`SpaaFile`] behind an [`Arc`] together with
+//! lookup indexes built once up front. Cloning one is a reference count
+//! increment, it is `Send + Sync`, and every lookup is a plain read, so a
+//! server can load a profile once and answer queries over it from many
+//! threads without locking. It dereferences to the [`SpaaFile`], so every
+//! function taking `&SpaaFile` accepts `&snapshot` too.
+//!
+//! # Example
+//!
+//! ```
+//! use spaa_parse::{SpaaFile, SpaaSnapshot};
+//! use std::thread;
+//!
+//! # let data = br#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#;
+//! let snapshot = SpaaSnapshot::new(SpaaFile::parse_slice(data).unwrap());
+//! let workers: Vec<_> = (0..4)
+//!     .map(|_| {
+//!         let snapshot = snapshot.clone();
+//!         thread::spawn(move || snapshot.stacks_for_event("cycles").count())
+//!     })
+//!     .collect();
+//! for worker in workers {
+//!     assert_eq!(worker.join().unwrap(), 0);
+//! }
+//! ```
+
+use crate::{Frame, SpaaFile, Stack};
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// A read-only, cheaply cloned profile with prebuilt indexes.
+#[derive(Debug, Clone)]
+pub struct SpaaSnapshot {
+    inner: Arc<Indexed>,
+}
+
+#[derive(Debug)]
+struct Indexed {
+    file: SpaaFile,
+    /// Stack IDs by event, sorted.
+    by_event: HashMap<String, Vec<String>>,
+    /// Frame IDs by function name, sorted.
+    by_function: HashMap<String, Vec<u64>>,
+    /// IDs of the stacks each frame appears in, sorted.
+    by_frame: HashMap<u64, Vec<String>>,
+}
+
+impl SpaaSnapshot {
+    /// Index `file` and freeze it.
+    pub fn new(file: SpaaFile) -> Self {
+        let mut by_event: HashMap<String, Vec<String>> = HashMap::new();
+        let mut by_frame: HashMap<u64, Vec<String>> = HashMap::new();
+        for stack in file.stacks.values() {
+            by_event
+                .entry(stack.context.event.clone())
+                .or_default()
+                .push(stack.id.clone());
+            let mut frames = stack.frames.clone();
+            frames.sort_unstable();
+            frames.dedup();
+            for frame in frames {
+                by_frame.entry(frame).or_default().push(stack.id.clone());
+            }
+        }
+        let mut by_function: HashMap<String, Vec<u64>> = HashMap::new();
+        for frame in file.frames.values() {
+            by_function
+                .entry(frame.func.clone())
+                .or_default()
+                .push(frame.id);
+        }
+        by_event.values_mut().for_each(|ids| ids.sort());
+        by_frame.values_mut().for_each(|ids| ids.sort());
+        by_function.values_mut().for_each(|ids| ids.sort());
+
+        Self {
+            inner: Arc::new(Indexed {
+                file,
+                by_event,
+                by_function,
+                by_frame,
+            }),
+        }
+    }
+
+    /// The profile.
+    pub fn file(&self) -> &SpaaFile {
+        &self.inner.file
+    }
+
+    /// Stacks of `event`, in ID order.
+    ///
+    /// Unlike [`SpaaFile::stacks_for_event`], this visits only the event's
+    /// stacks.
+    pub fn stacks_for_event<'a>(&'a self, event: &str) -> impl Iterator<Item = &'a Stack> {
+        self.stacks_by_id(self.inner.by_event.get(event))
+    }
+
+    /// Frames whose function is named `func`, in ID order.
+    pub fn frames_named<'a>(&'a self, func: &str) -> impl Iterator<Item = &'a Frame> {
+        self.inner
+            .by_function
+            .get(func)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.inner.file.frames.get(id))
+    }
+
+    /// Stacks that frame `frame_id` appears in, in ID order.
+    pub fn stacks_with_frame(&self, frame_id: u64) -> impl Iterator<Item = &Stack> {
+        self.stacks_by_id(self.inner.by_frame.get(&frame_id))
+    }
+
+    /// Stacks that a frame of function `func` appears in, in ID order.
+    pub fn stacks_with_function<'a>(&'a self, func: &str) -> impl Iterator<Item = &'a Stack> {
+        let mut ids: Vec<&String> = self
+            .inner
+            .by_function
+            .get(func)
+            .into_iter()
+            .flatten()
+            .filter_map(|frame| self.inner.by_frame.get(frame))
+            .flatten()
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.into_iter()
+            .filter_map(|id| self.inner.file.stacks.get(id))
+    }
+
+    /// Unwrap the profile, cloning it if other snapshots still share it.
+    pub fn into_file(self) -> SpaaFile {
+        match Arc::try_unwrap(self.inner) {
+            Ok(indexed) => indexed.file,
+            Err(shared) => shared.file.clone(),
+        }
+    }
+
+    fn stacks_by_id<'a>(&'a self, ids: Option<&'a Vec<String>>) -> impl Iterator<Item = &'a Stack> {
+        ids.into_iter()
+            .flatten()
+            .filter_map(|id| self.inner.file.stacks.get(id))
+    }
+}
+
+impl Deref for SpaaSnapshot {
+    type Target = SpaaFile;
+
+    fn deref(&self) -> &SpaaFile {
+        &self.inner.file
+    }
+}
+
+impl From<SpaaFile> for SpaaSnapshot {
+    fn from(file: SpaaFile) -> Self {
+        Self::new(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> SpaaSnapshot {
+        let data = [
+            r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}},{"name":"cache-misses","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#,
+            r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#,
+            r#"{"type":"dso","id":2,"name":"/usr/bin/app.debug","is_kernel":false}"#,
+            r#"{"type":"frame","id":1,"func":"main","dso":1}"#,
+            r#"{"type":"frame","id":2,"func":"parse","dso":1}"#,
+            r#"{"type":"frame","id":3,"func":"parse","dso":2}"#,
+            r#"{"type":"stack","id":"0x3","frames":[3,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":5}]}"#,
+            r#"{"type":"stack","id":"0x1","frames":[2,1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":10}]}"#,
+            r#"{"type":"stack","id":"0x2","frames":[1],"context":{"event":"cache-misses"},"weights":[{"metric":"period","value":1}]}"#,
+        ]
+        .join("\n");
+        SpaaSnapshot::new(SpaaFile::parse_slice(data.as_bytes()).unwrap())
+    }
+
+    fn ids<'a>(stacks: impl Iterator<Item = &'a Stack>) -> Vec<&'a str> {
+        stacks.map(|s| s.id.as_str()).collect()
+    }
+
+    #[test]
+    fn indexes_answer_lookups_in_id_order() {
+        let snapshot = snapshot();
+
+        assert_eq!(ids(snapshot.stacks_for_event("cycles")), ["0x1", "0x3"]);
+        assert_eq!(ids(snapshot.stacks_with_frame(1)), ["0x1", "0x2", "0x3"]);
+        assert_eq!(ids(snapshot.stacks_with_function("parse")), ["0x1", "0x3"]);
+        let parse: Vec<u64> = snapshot.frames_named("parse").map(|f| f.id).collect();
+        assert_eq!(parse, [2, 3]);
+        assert_eq!(snapshot.stacks_for_event("instructions").count(), 0);
+        // Everything else reads through to the file.
+        assert_eq!(snapshot.primary_metric_for_event("cycles"), Some("period"));
+    }
+
+    #[test]
+    fn clones_share_one_file_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SpaaSnapshot>();
+
+        let snapshot = snapshot();
+        let counts: Vec<usize> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| {
+                    let snapshot = snapshot.clone();
+                    scope.spawn(move || snapshot.stacks_with_function("main").count())
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+        assert_eq!(counts, [3; 4]);
+        assert!(std::ptr::eq(snapshot.file(), snapshot.clone().file()));
+    }
+}
@@ -0,0 +1,159 @@
+//! Parsing of frame `srcline` values.
+//!
+//! Converters record source locations as `file:line` or `file:line:col`,
+//! but the file part may itself contain colons (`https://` URLs, Windows
+//! drive letters) and perf appends notes such as `(discriminator 3)`.
+//! [`SrcLine::parse`] splits a srcline from the right so the file path
+//! comes out intact.
+//!
+//! # Example
+//!
+//! ```
+//! use spaa_parse::srcline::SrcLine;
+//!
+//! let src = SrcLine::parse("https://example.com/app.js:11:5").unwrap();
+//! assert_eq!(src.file, "https://example.com/app.js");
+//! assert_eq!((src.line, src.column), (Some(11), Some(5)));
+//! assert_eq!(src.directory(Some(1)), "https://example.com");
+//! ```
+
+/// A srcline split into its parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrcLine<'a> {
+    /// Source file path or URL.
+    pub file: &'a str,
+    /// 1-based line, if recorded and known.
+    pub line: Option<u32>,
+    /// 1-based column, if recorded and known.
+    pub column: Option<u32>,
+}
+
+impl<'a> SrcLine<'a> {
+    /// Split `srcline` into file, line and column.
+    ///
+    /// Returns `None` when the file is unknown, as in perf's `??:0`.
+    pub fn parse(srcline: &'a str) -> Option<Self> {
+        let mut rest = srcline.trim();
+        // perf appends `(discriminator N)` and similar notes.
+        if let Some(open) = rest.rfind(" (")
+            && rest.ends_with(')')
+        {
+            rest = rest[..open].trim_end();
+        }
+
+        let mut numbers = Vec::new();
+        while numbers.len() < 2 {
+            let Some((head, tail)) = rest.rsplit_once(':') else {
+                break;
+            };
+            let number = match tail {
+                "?" => None,
+                _ if !tail.is_empty() && tail.bytes().all(|b| b.is_ascii_digit()) => {
+                    tail.parse().ok()
+                }
+                _ => break,
+            };
+            numbers.push(number);
+            rest = head;
+        }
+        // Numbers were read from the right: column first when there are two.
+        let (line, column) = match numbers.as_slice() {
+            [] => (None, None),
+            [line] => (*line, None),
+            [column, line] => (*line, *column),
+            _ => unreachable!(),
+        };
+
+        let file = rest.strip_prefix("./").unwrap_or(rest);
+        if file.is_empty() || file == "??" {
+            return None;
+        }
+        Some(Self {
+            file,
+            // perf writes line 0 for unknown lines.
+            line: line.filter(|&l| l > 0),
+            column: column.filter(|&c| c > 0),
+        })
+    }
+
+    /// The directory holding the file, cut to its first `depth` components
+    /// when given. Backslashes count as separators, and a URL's scheme and
+    /// host count as one component.
+    ///
+    /// A file without a directory is in `.`.
+    pub fn directory(&self, depth: Option<usize>) -> String {
+        let file = self.file.replace('\\', "/");
+        let (root, path) = match file.find("://") {
+            Some(i) => {
+                let host_end = file[i + 3..].find('/').map_or(file.len(), |j| i + 3 + j);
+                (&file[..host_end], &file[host_end..])
+            }
+            None => ("", file.as_str()),
+        };
+        let absolute = path.starts_with('/');
+        let mut components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        // Drop the file name.
+        components.pop();
+        let mut kept = depth.unwrap_or(usize::MAX);
+        if !root.is_empty() {
+            kept = kept.saturating_sub(1);
+        }
+        components.truncate(kept);
+
+        let mut dir = root.to_string();
+        if absolute && (!components.is_empty() || root.is_empty()) {
+            dir.push('/');
+        }
+        dir.push_str(&components.join("/"));
+        if dir.is_empty() {
+            dir.push('.');
+        }
+        dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(srcline: &str) -> Option<(&str, Option<u32>, Option<u32>)> {
+        SrcLine::parse(srcline).map(|s| (s.file, s.line, s.column))
+    }
+
+    #[test]
+    fn file_line_and_column_split_from_the_right() {
+        assert_eq!(parts("parse.c:10"), Some(("parse.c", Some(10), None)));
+        assert_eq!(
+            parts("/src/net/io.rs:42:7"),
+            Some(("/src/net/io.rs", Some(42), Some(7)))
+        );
+        assert_eq!(
+            parts(r"C:\src\main.cpp:12"),
+            Some((r"C:\src\main.cpp", Some(12), None))
+        );
+        assert_eq!(
+            parts("./lib/a.c:5 (discriminator 3)"),
+            Some(("lib/a.c", Some(5), None))
+        );
+        assert_eq!(parts("lib/a.c:?"), Some(("lib/a.c", None, None)));
+        assert_eq!(parts("Makefile"), Some(("Makefile", None, None)));
+        assert_eq!(parts("??:0"), None);
+    }
+
+    #[test]
+    fn directories_are_cut_to_a_depth() {
+        let src = SrcLine::parse("/home/ci/repo/src/net/io.rs:1").unwrap();
+        assert_eq!(src.directory(None), "/home/ci/repo/src/net");
+        assert_eq!(src.directory(Some(3)), "/home/ci/repo");
+
+        let src = SrcLine::parse("crates/core/src/lib.rs:1").unwrap();
+        assert_eq!(src.directory(Some(2)), "crates/core");
+        assert_eq!(SrcLine::parse("main.c:3").unwrap().directory(None), ".");
+        assert_eq!(
+            SrcLine::parse("https://cdn.example.com/js/app.js:1:1")
+                .unwrap()
+                .directory(None),
+            "https://cdn.example.com/js"
+        );
+    }
+}
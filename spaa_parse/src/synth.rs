@@ -0,0 +1,640 @@
+//! Deterministic synthetic SPAA files.
+//!
+//! [`generate`] builds a [`SpaaFile`] of any size from a [`SynthConfig`].
+//! Stacks are drawn from a fixed pseudo-random call graph, so they share
+//! prefixes the way real profiles do, and the same seed always produces the
+//! same file. The stack-depth distribution, symbol pool, event mix and time
+//! range are configurable. Useful as fixtures for benchmarks and for testing
+//! tools that consume SPAA.
+//!
+//! # Example
+//!
+//! ```
+//! use spaa_parse::synth::{SynthConfig, generate};
+//!
+//! let file = generate(&SynthConfig {
+//!     stacks: 100,
+//!     ..Default::default()
+//! });
+//! assert_eq!(file.stacks.len(), 100);
+//!
+//! let mut ndjson = Vec::new();
+//! file.write(&mut ndjson).unwrap();
+//! ```
+
+use crate::{
+    Dso, EventDef, EventKind, Frame, FrameKind, FrameOrder, Header, Sample, Sampling, SamplingMode,
+    SpaaFile, Stack, StackContext, StackIdMode, StackType, Thread, TimeRange, Weight,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Small, fast, seedable PRNG (SplitMix64).
+///
+/// Not suitable for anything but generating test data.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// A generator whose output is determined by `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Next uniformly distributed value.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `0..n`. Returns 0 when `n` is 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        if n == 0 { 0 } else { self.next_u64() % n }
+    }
+
+    /// Uniform value in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Upper bound on generated stack depth, whatever the distribution.
+const MAX_DEPTH: usize = 1024;
+
+/// Distribution stack depths are drawn from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthDistribution {
+    /// Every stack has the same depth.
+    Fixed(usize),
+    /// Depths uniform in `min..=max`.
+    Uniform { min: usize, max: usize },
+    /// Many shallow stacks and a long tail of deep ones, with the given
+    /// mean depth.
+    Geometric { mean: f64 },
+}
+
+impl Default for DepthDistribution {
+    fn default() -> Self {
+        Self::Uniform { min: 1, max: 32 }
+    }
+}
+
+impl DepthDistribution {
+    fn sample(&self, rng: &mut Rng) -> usize {
+        let depth = match *self {
+            Self::Fixed(depth) => depth,
+            Self::Uniform { min, max } => {
+                let (lo, hi) = (min.min(max), min.max(max));
+                lo + rng.below((hi - lo + 1) as u64) as usize
+            }
+            Self::Geometric { mean } => {
+                if mean <= 1.0 {
+                    1
+                } else {
+                    let p = 1.0 / mean;
+                    let u = 1.0 - rng.next_f64();
+                    1 + (u.ln() / (1.0 - p).ln()) as usize
+                }
+            }
+        };
+        depth.clamp(1, MAX_DEPTH)
+    }
+}
+
+impl fmt::Display for DepthDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fixed(depth) => write!(f, "fixed:{depth}"),
+            Self::Uniform { min, max } => write!(f, "uniform:{min}-{max}"),
+            Self::Geometric { mean } => write!(f, "geometric:{mean}"),
+        }
+    }
+}
+
+/// Error returned when parsing a [`DepthDistribution`] fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDepthDistributionError(String);
+
+impl fmt::Display for ParseDepthDistributionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid depth distribution '{}': expected fixed:N, uniform:MIN-MAX or geometric:MEAN",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseDepthDistributionError {}
+
+impl FromStr for DepthDistribution {
+    type Err = ParseDepthDistributionError;
+
+    /// Parse `fixed:N`, `uniform:MIN-MAX` or `geometric:MEAN`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseDepthDistributionError(s.to_string());
+        let (kind, value) = s.split_once(':').ok_or_else(err)?;
+        match kind.trim() {
+            "fixed" => value.trim().parse().map(Self::Fixed).map_err(|_| err()),
+            "uniform" => {
+                let (min, max) = value.split_once('-').ok_or_else(err)?;
+                Ok(Self::Uniform {
+                    min: min.trim().parse().map_err(|_| err())?,
+                    max: max.trim().parse().map_err(|_| err())?,
+                })
+            }
+            "geometric" => match value.trim().parse::<f64>() {
+                Ok(mean) if mean.is_finite() && mean >= 1.0 => Ok(Self::Geometric { mean }),
+                _ => Err(err()),
+            },
+            _ => Err(err()),
+        }
+    }
+}
+
+/// Function and DSO names stacks are built from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolPool {
+    pub functions: Vec<String>,
+    /// DSOs functions are spread over, round-robin.
+    pub dsos: Vec<String>,
+}
+
+impl SymbolPool {
+    /// `functions` generated names spread over `dsos` generated libraries.
+    pub fn synthetic(functions: usize, dsos: usize) -> Self {
+        Self {
+            functions: (1..=functions.max(1) as u64)
+                .map(|id| format!("synth::module{}::function{}", id % 16, id))
+                .collect(),
+            dsos: (1..=dsos.max(1))
+                .map(|id| format!("/usr/lib/libsynth{id}.so"))
+                .collect(),
+        }
+    }
+
+    /// A pool of the given function names in a single DSO.
+    pub fn from_names<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            functions: names.into_iter().map(Into::into).collect(),
+            dsos: vec!["/usr/bin/app".to_string()],
+        }
+    }
+}
+
+impl Default for SymbolPool {
+    fn default() -> Self {
+        Self::synthetic(500, 8)
+    }
+}
+
+/// An event and its relative share of the generated stacks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventWeight {
+    pub name: String,
+    /// Relative share; shares need not sum to one.
+    pub share: f64,
+}
+
+/// Error returned when parsing an [`EventWeight`] fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseEventWeightError(String);
+
+impl fmt::Display for ParseEventWeightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid event '{}': expected NAME or NAME=SHARE with a positive share",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseEventWeightError {}
+
+impl FromStr for EventWeight {
+    type Err = ParseEventWeightError;
+
+    /// Parse `NAME` (share 1) or `NAME=SHARE`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseEventWeightError(s.to_string());
+        // Raw PMU events such as `cpu/event=0x3c/` contain `=` too, so only
+        // a numeric suffix is a share.
+        let (name, share) = s
+            .rsplit_once('=')
+            .and_then(|(name, share)| Some((name, share.trim().parse::<f64>().ok()?)))
+            .unwrap_or((s, 1.0));
+        let name = name.trim();
+        if name.is_empty() || !share.is_finite() || share <= 0.0 {
+            return Err(err());
+        }
+        Ok(Self {
+            name: name.to_string(),
+            share,
+        })
+    }
+}
+
+/// Shape of a generated file.
+///
+/// Every event is declared as a hardware event sampled by `period`.
+#[derive(Debug, Clone)]
+pub struct SynthConfig {
+    pub seed: u64,
+    /// Number of distinct stacks.
+    pub stacks: usize,
+    pub depth: DepthDistribution,
+    pub symbols: SymbolPool,
+    /// Events and their share of stacks. Defaults to `cycles` alone.
+    pub events: Vec<EventWeight>,
+    pub threads: usize,
+    /// Raw sample records emitted per stack; 0 for none.
+    pub samples_per_stack: usize,
+    /// Time span, in seconds, that sample timestamps fall in. Recorded in
+    /// the header when set.
+    pub time_range: Option<(f64, f64)>,
+}
+
+impl Default for SynthConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            stacks: 1_000,
+            depth: DepthDistribution::default(),
+            symbols: SymbolPool::default(),
+            events: vec![EventWeight {
+                name: "cycles".to_string(),
+                share: 1.0,
+            }],
+            threads: 4,
+            samples_per_stack: 0,
+            time_range: None,
+        }
+    }
+}
+
+/// Calls each function can make; low fan-out gives stacks shared prefixes.
+const FANOUT: u64 = 4;
+
+/// Functions stacks start from.
+const ENTRY_POINTS: u64 = 4;
+
+/// First thread (and process) ID.
+const BASE_PID: u64 = 1000;
+
+/// A path of `depth` calls through the synthetic call graph of `functions`
+/// functions, as frame IDs in `1..=functions` from root to leaf.
+///
+/// The graph is fixed: function `f`'s callees depend only on `f`, so paths
+/// drawn with different seeds share prefixes the way real stacks do.
+fn call_path(rng: &mut Rng, functions: u64, depth: u64) -> Vec<u64> {
+    let functions = functions.max(1);
+    let mut frames = Vec::with_capacity(depth as usize);
+    let mut func = 1 + rng.below(ENTRY_POINTS.min(functions));
+    frames.push(func);
+    for _ in 1..depth {
+        func = 1 + callee(func, rng.below(FANOUT)) % functions;
+        frames.push(func);
+    }
+    frames
+}
+
+/// Generate a profile shaped by `config`.
+///
+/// If the call graph cannot produce `config.stacks` distinct stacks (tiny
+/// symbol pools or depths), the file has fewer.
+pub fn generate(config: &SynthConfig) -> SpaaFile {
+    let mut rng = Rng::new(config.seed);
+    let functions = config.symbols.functions.len().max(1);
+    let dso_count = config.symbols.dsos.len().max(1);
+    let threads = config.threads.max(1) as u64;
+    let events: Vec<EventWeight> = if config.events.is_empty() {
+        SynthConfig::default().events
+    } else {
+        config.events.clone()
+    };
+    let total_share: f64 = events.iter().map(|e| e.share).sum();
+
+    let mut file = SpaaFile {
+        header: header(&events, config.time_range),
+        system: None,
+        dsos: HashMap::new(),
+        frames: HashMap::new(),
+        threads: HashMap::new(),
+        stacks: HashMap::new(),
+        samples: Vec::new(),
+        windows: Vec::new(),
+        states: Vec::new(),
+        notes: Vec::new(),
+    };
+
+    for i in 0..dso_count {
+        let id = i as u64 + 1;
+        let name = config
+            .symbols
+            .dsos
+            .get(i)
+            .cloned()
+            .unwrap_or_else(|| "/usr/bin/app".to_string());
+        file.dsos.insert(
+            id,
+            Dso {
+                id,
+                name,
+                build_id: None,
+                is_kernel: false,
+            },
+        );
+    }
+    for i in 0..functions {
+        let id = i as u64 + 1;
+        let func = config
+            .symbols
+            .functions
+            .get(i)
+            .cloned()
+            .unwrap_or_else(|| "main".to_string());
+        file.frames.insert(
+            id,
+            Frame {
+                id,
+                func,
+                dso: 1 + (i % dso_count) as u64,
+                func_resolved: true,
+                ip: Some(format!("0x{:x}", 0x40_0000 + id * 0x40)),
+                symoff: None,
+                srcline: None,
+                srcline_resolved: true,
+                inlined: false,
+                inline_depth: None,
+                kind: FrameKind::User,
+            },
+        );
+    }
+    for i in 0..threads {
+        let tid = BASE_PID + i;
+        file.threads.insert(
+            tid,
+            Thread {
+                pid: BASE_PID,
+                tid,
+                comm: Some(format!("worker-{i}")),
+            },
+        );
+    }
+
+    let mut attempts = config.stacks * 4;
+    while file.stacks.len() < config.stacks && attempts > 0 {
+        attempts -= 1;
+        let event = pick_event(&events, total_share, &mut rng);
+        let depth = config.depth.sample(&mut rng);
+        let tid = BASE_PID + rng.below(threads);
+
+        // Walk the call graph from an entry point, then store leaf first.
+        let mut frames = call_path(&mut rng, functions as u64, depth as u64);
+        frames.reverse();
+
+        let id = stack_id(event, &frames, tid);
+        if file.stacks.contains_key(&id) {
+            continue;
+        }
+        file.stacks.insert(
+            id.clone(),
+            Stack {
+                id,
+                frames,
+                stack_type: StackType::Unified,
+                context: context(event, tid),
+                weights: vec![Weight {
+                    metric: "period".to_string(),
+                    value: 1 + rng.below(10_000),
+                    unit: None,
+                }],
+                exclusive: None,
+                related_stacks: None,
+            },
+        );
+    }
+
+    if config.samples_per_stack > 0 {
+        add_samples(&mut file, config, &mut rng);
+    }
+    file
+}
+
+fn header(events: &[EventWeight], time_range: Option<(f64, f64)>) -> Header {
+    Header {
+        format: "spaa".to_string(),
+        version: "1.0".to_string(),
+        source_tool: "synth".to_string(),
+        frame_order: FrameOrder::LeafToRoot,
+        events: events
+            .iter()
+            .map(|e| EventDef {
+                name: e.name.clone(),
+                kind: EventKind::Hardware,
+                sampling: Sampling {
+                    mode: SamplingMode::Period,
+                    primary_metric: "period".to_string(),
+                    sample_period: None,
+                    frequency_hz: None,
+                },
+                allocation_tracking: None,
+                description: None,
+                unit: None,
+            })
+            .collect(),
+        time_range: time_range.map(|(start, end)| TimeRange {
+            start,
+            end,
+            unit: "seconds".to_string(),
+        }),
+        source: None,
+        stack_id_mode: StackIdMode::ContentAddressable,
+        clock: None,
+        approximation: None,
+    }
+}
+
+/// Spread `config.samples_per_stack` samples of each stack over the time
+/// range (one second when unset), in timestamp order.
+fn add_samples(file: &mut SpaaFile, config: &SynthConfig, rng: &mut Rng) {
+    let (start, end) = config.time_range.unwrap_or((0.0, 1.0));
+    let mut stacks: Vec<&Stack> = file.stacks.values().collect();
+    stacks.sort_by(|a, b| a.id.cmp(&b.id));
+
+    for stack in stacks {
+        let tid = stack.context.tid.unwrap_or(BASE_PID);
+        let period = stack.weights[0].value / config.samples_per_stack as u64;
+        for _ in 0..config.samples_per_stack {
+            file.samples.push(Sample {
+                timestamp: start + rng.next_f64() * (end - start),
+                pid: BASE_PID,
+                tid,
+                cpu: (tid - BASE_PID) as u32,
+                event: stack.context.event.clone(),
+                period: Some(period),
+                stack_id: stack.id.clone(),
+                context: HashMap::new(),
+            });
+        }
+    }
+    file.samples
+        .sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+}
+
+fn pick_event<'a>(events: &'a [EventWeight], total_share: f64, rng: &mut Rng) -> &'a str {
+    let mut target = rng.next_f64() * total_share;
+    for event in events {
+        if target < event.share {
+            return &event.name;
+        }
+        target -= event.share;
+    }
+    &events[events.len() - 1].name
+}
+
+fn context(event: &str, tid: u64) -> StackContext {
+    StackContext {
+        event: event.to_string(),
+        pid: Some(BASE_PID),
+        tid: Some(tid),
+        cpu: None,
+        comm: Some(format!("worker-{}", tid - BASE_PID)),
+        probe: None,
+        execname: None,
+        uid: None,
+        zonename: None,
+        cgroup: None,
+        container_id: None,
+        k8s_pod: None,
+        trace_fields: None,
+        extra: HashMap::new(),
+    }
+}
+
+/// The `n`th function called by `caller` in the synthetic call graph.
+fn callee(caller: u64, n: u64) -> u64 {
+    let mut rng = Rng::new(caller.wrapping_mul(FANOUT).wrapping_add(n));
+    rng.next_u64()
+}
+
+/// Content-addressed stack ID (FNV-1a over the event, frames and thread).
+fn stack_id(event: &str, frames: &[u64], tid: u64) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let bytes = event
+        .bytes()
+        .chain(frames.iter().chain([&tid]).flat_map(|v| v.to_le_bytes()));
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("0x{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn same_seed_same_file() {
+        let config = SynthConfig {
+            stacks: 50,
+            samples_per_stack: 2,
+            ..Default::default()
+        };
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        generate(&config).write(&mut a).unwrap();
+        generate(&config).write(&mut b).unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn generated_file_round_trips() {
+        let file = generate(&SynthConfig {
+            stacks: 200,
+            samples_per_stack: 1,
+            ..Default::default()
+        });
+        let mut ndjson = Vec::new();
+        file.write(&mut ndjson).unwrap();
+        let parsed = SpaaFile::parse(Cursor::new(ndjson)).unwrap();
+
+        assert_eq!(parsed.stacks.len(), 200);
+        assert_eq!(parsed.samples.len(), 200);
+        assert!(parsed.stacks.values().all(|s| s.frames.len() <= 32));
+    }
+
+    #[test]
+    fn parses_depth_distributions() {
+        assert_eq!("fixed:8".parse(), Ok(DepthDistribution::Fixed(8)));
+        assert_eq!(
+            "uniform:2-16".parse(),
+            Ok(DepthDistribution::Uniform { min: 2, max: 16 })
+        );
+        assert_eq!(
+            "geometric:12".parse(),
+            Ok(DepthDistribution::Geometric { mean: 12.0 })
+        );
+        assert!("normal:3".parse::<DepthDistribution>().is_err());
+        assert!("geometric:0.5".parse::<DepthDistribution>().is_err());
+    }
+
+    #[test]
+    fn parses_event_weights() {
+        let event: EventWeight = "cache-misses=0.25".parse().unwrap();
+        assert_eq!(event.name, "cache-misses");
+        assert_eq!(event.share, 0.25);
+        assert_eq!("cycles".parse::<EventWeight>().unwrap().share, 1.0);
+        assert!("cycles=0".parse::<EventWeight>().is_err());
+        assert_eq!(
+            "cpu/event=0x3c/".parse::<EventWeight>().unwrap().name,
+            "cpu/event=0x3c/"
+        );
+    }
+
+    #[test]
+    fn fixed_depth_is_respected() {
+        let file = generate(&SynthConfig {
+            stacks: 50,
+            depth: DepthDistribution::Fixed(7),
+            ..Default::default()
+        });
+        assert!(file.stacks.values().all(|s| s.frames.len() == 7));
+    }
+
+    #[test]
+    fn samples_fall_in_time_range() {
+        let file = generate(&SynthConfig {
+            stacks: 20,
+            samples_per_stack: 3,
+            time_range: Some((10.0, 20.0)),
+            ..Default::default()
+        });
+
+        assert_eq!(file.samples.len(), 60);
+        assert!(
+            file.samples
+                .iter()
+                .all(|s| (10.0..20.0).contains(&s.timestamp))
+        );
+        assert!(
+            file.samples
+                .windows(2)
+                .all(|w| w[0].timestamp <= w[1].timestamp)
+        );
+        assert_eq!(file.header.time_range.as_ref().unwrap().end, 20.0);
+    }
+}
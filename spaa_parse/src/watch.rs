@@ -0,0 +1,307 @@
+//! Follow a SPAA file as a streaming converter appends to it.
+//!
+//! A [`SpaaWatcher`] remembers how far into a file it has read. Each
+//! [`poll`](SpaaWatcher::poll) reads only the complete lines appended since
+//! the last one, adds their records to its in-memory [`SpaaFile`] and
+//! returns them, so a live dashboard can update its own aggregations from
+//! the new records instead of re-reading the whole profile. A line still
+//! being written is left for the next poll.
+//!
+//! Polling is left to the caller, on a timer or when a file system
+//! notification arrives. If the file shrinks, as when a resumed writer cuts
+//! it back to a checkpoint or a converter starts over, the watcher starts
+//! again from the beginning and says so in the [`Update`].
+//!
+//! Cross-record references are not checked, since a writer may still be
+//! about to write the records they refer to; parse the finished file to
+//! validate it.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spaa_parse::{Record, SpaaWatcher};
+//! use std::collections::HashMap;
+//! use std::time::Duration;
+//!
+//! let mut watcher = SpaaWatcher::new("live.spaa");
+//! let mut samples_per_event: HashMap<String, u64> = HashMap::new();
+//! while !watcher.finished() {
+//!     let update = watcher.poll().unwrap();
+//!     if update.reset {
+//!         samples_per_event.clear();
+//!     }
+//!     for record in &update.records {
+//!         if let Record::Sample(sample) = record {
+//!             *samples_per_event.entry(sample.event.clone()).or_default() += 1;
+//!         }
+//!     }
+//!     std::thread::sleep(Duration::from_millis(500));
+//! }
+//! ```
+
+use crate::{LineDecoder, Record, Result, SpaaFile, check_stack};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Incrementally reads a growing SPAA file.
+#[derive(Debug)]
+pub struct SpaaWatcher {
+    path: PathBuf,
+    /// Bytes of the file consumed, always at a line boundary.
+    offset: u64,
+    decoder: LineDecoder,
+    file: Option<SpaaFile>,
+    primary_metrics: HashMap<String, String>,
+    finished: bool,
+}
+
+/// What one [`SpaaWatcher::poll`] read.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Update {
+    /// The file shrank, so everything read before was discarded and
+    /// `records` starts from the beginning of the file.
+    pub reset: bool,
+    /// Records appended since the previous poll, in file order. Sample
+    /// batches are passed on as written.
+    pub records: Vec<Record>,
+}
+
+impl SpaaWatcher {
+    /// A watcher for the file at `path`, which need not exist yet.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            offset: 0,
+            decoder: LineDecoder::default(),
+            file: None,
+            primary_metrics: HashMap::new(),
+            finished: false,
+        }
+    }
+
+    /// The file being watched.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The records read so far, once the header has been read.
+    pub fn file(&self) -> Option<&SpaaFile> {
+        self.file.as_ref()
+    }
+
+    /// Bytes of the file read so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Whether the writer has finished the file with a footer.
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Forget everything read, so the next poll starts from the beginning.
+    pub fn reset(&mut self) {
+        *self = Self::new(std::mem::take(&mut self.path));
+    }
+
+    /// Read the records appended since the last poll.
+    ///
+    /// Returns an empty update if the file does not exist yet or nothing
+    /// new has been written. Fails on a record that does not parse, with
+    /// its line number in the whole file; the watcher stops before that
+    /// record, so the error repeats until [`reset`](Self::reset). Records
+    /// read before a bad one are returned first, and the error is reported
+    /// by the next poll.
+    pub fn poll(&mut self) -> Result<Update> {
+        let mut input = match File::open(&self.path) {
+            Ok(input) => input,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Update::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let len = input.metadata()?.len();
+        let mut update = Update::default();
+        if len < self.offset {
+            self.reset();
+            update.reset = true;
+        }
+        if len == self.offset {
+            return Ok(update);
+        }
+
+        input.seek(SeekFrom::Start(self.offset))?;
+        let mut appended = Vec::new();
+        input.take(len - self.offset).read_to_end(&mut appended)?;
+        let complete = appended
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+
+        for line in appended[..complete].split_inclusive(|&b| b == b'\n') {
+            // Decode a copy so a failing line is decoded afresh, with the
+            // same line number, on the next poll.
+            let mut decoder = self.decoder.clone();
+            let decoded = decoder.decode(line).and_then(|record| {
+                if let Some(record) = &record {
+                    self.apply(record.clone(), decoder.location())?;
+                }
+                Ok(record)
+            });
+            let record = match decoded {
+                Ok(record) => record,
+                Err(_) if update.reset || !update.records.is_empty() => break,
+                Err(e) => return Err(e),
+            };
+            self.decoder = decoder;
+            self.offset += line.len() as u64;
+            update.records.extend(record);
+        }
+        Ok(update)
+    }
+
+    fn apply(&mut self, record: Record, location: crate::Location) -> Result<()> {
+        if let Record::Header(header) = record {
+            self.primary_metrics = header
+                .events
+                .iter()
+                .map(|e| (e.name.clone(), e.sampling.primary_metric.clone()))
+                .collect();
+            self.file = Some(SpaaFile {
+                header,
+                system: None,
+                dsos: HashMap::new(),
+                frames: HashMap::new(),
+                threads: HashMap::new(),
+                stacks: HashMap::new(),
+                samples: Vec::new(),
+                windows: Vec::new(),
+                states: Vec::new(),
+                notes: Vec::new(),
+            });
+            return Ok(());
+        }
+        let file = self
+            .file
+            .as_mut()
+            .expect("the decoder requires a header first");
+        match record {
+            Record::Header(_) => unreachable!(),
+            Record::System(system) => file.system = Some(system),
+            Record::Dso(dso) => {
+                file.dsos.insert(dso.id, dso);
+            }
+            Record::Frame(frame) => {
+                file.frames.insert(frame.id, frame);
+            }
+            Record::Thread(thread) => {
+                file.threads.insert(thread.tid, thread);
+            }
+            Record::Stack(stack) => {
+                check_stack(&stack, &self.primary_metrics, location)?;
+                file.stacks.insert(stack.id.clone(), stack);
+            }
+            Record::Sample(sample) => file.samples.push(sample),
+            Record::Samples(batch) => file.samples.extend(batch.samples()),
+            Record::Window(window) => file.windows.push(window),
+            Record::State(state) => file.states.push(state),
+            Record::Note(note) => file.notes.push(note),
+            Record::Footer(_) => self.finished = true,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParseError;
+    use std::io::Write;
+
+    const HEADER: &str = r#"{"type":"header","format":"spaa","version":"1.0","source_tool":"perf","frame_order":"leaf_to_root","events":[{"name":"cycles","kind":"hardware","sampling":{"mode":"period","primary_metric":"period"}}]}"#;
+    const DSO: &str = r#"{"type":"dso","id":1,"name":"/usr/bin/app","is_kernel":false}"#;
+    const FRAME: &str = r#"{"type":"frame","id":1,"func":"main","dso":1}"#;
+    const STACK: &str = r#"{"type":"stack","id":"0x1","frames":[1],"context":{"event":"cycles"},"weights":[{"metric":"period","value":10}]}"#;
+
+    fn path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("spaa-watch-{name}-{}.spaa", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn polls_read_only_complete_appended_lines() {
+        let path = path("append");
+        let mut watcher = SpaaWatcher::new(&path);
+        assert_eq!(watcher.poll().unwrap(), Update::default());
+
+        append(&path, &format!("{HEADER}\n{DSO}\n{}", &FRAME[..10]));
+        let update = watcher.poll().unwrap();
+        assert_eq!(update.records.len(), 2);
+        assert!(watcher.file().unwrap().frames.is_empty());
+
+        append(&path, &format!("{}\n{STACK}\n", &FRAME[10..]));
+        let update = watcher.poll().unwrap();
+        assert!(matches!(
+            update.records[..],
+            [Record::Frame(_), Record::Stack(_)]
+        ));
+        let file = watcher.file().unwrap();
+        assert_eq!(file.frames[&1].func, "main");
+        assert_eq!(file.stacks["0x1"].weights[0].value, 10);
+        assert!(watcher.poll().unwrap().records.is_empty());
+
+        // A bad record is reported with its line in the whole file.
+        append(&path, "{\"type\":\"dso\"}\n");
+        let Err(ParseError::Json { line, .. }) = watcher.poll() else {
+            panic!("expected a JSON error");
+        };
+        assert_eq!(line, 5);
+        assert!(watcher.poll().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn records_before_a_bad_line_are_returned_first() {
+        let path = path("bad");
+        let mut watcher = SpaaWatcher::new(&path);
+        append(
+            &path,
+            &format!("{HEADER}\n{DSO}\n{FRAME}\n{{\"type\":\"dso\"}}\n{STACK}\n"),
+        );
+
+        let update = watcher.poll().unwrap();
+        assert_eq!(update.records.len(), 3);
+        assert_eq!(watcher.file().unwrap().frames.len(), 1);
+        let Err(ParseError::Json { line, .. }) = watcher.poll() else {
+            panic!("expected a JSON error");
+        };
+        assert_eq!(line, 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn shrinking_file_starts_over() {
+        let path = path("shrink");
+        append(&path, &format!("{HEADER}\n{DSO}\n{FRAME}\n{STACK}\n"));
+        let mut watcher = SpaaWatcher::new(&path);
+        watcher.poll().unwrap();
+
+        std::fs::write(&path, format!("{HEADER}\n{DSO}\n")).unwrap();
+        let update = watcher.poll().unwrap();
+        assert!(update.reset);
+        assert_eq!(update.records.len(), 2);
+        assert!(watcher.file().unwrap().stacks.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
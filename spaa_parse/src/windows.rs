@@ -0,0 +1,184 @@
+//! Streaming construction of `window` records.
+//!
+//! [`WindowBuilder`] sums per-stack weights for one fixed-width time bucket
+//! at a time and writes each bucket's [`Window`] to a [`SpaaWriter`] as soon
+//! as a later timestamp closes it, so a converter never holds more than one
+//! window in memory.
+//!
+//! # Example
+//!
+//! ```
+//! use spaa_parse::{SpaaWriter, Weight, WindowBuilder};
+//!
+//! let mut writer = SpaaWriter::new(Vec::new());
+//! let mut windows = WindowBuilder::new(1.0, "seconds");
+//! let samples = [(0.2, "0x1"), (0.7, "0x2"), (1.4, "0x1")];
+//! for (timestamp, stack_id) in samples {
+//!     let weight = Weight { metric: "samples".to_string(), value: 1, unit: None };
+//!     windows.add(&mut writer, timestamp, stack_id, &[weight]).unwrap();
+//! }
+//! windows.flush(&mut writer).unwrap();
+//! assert_eq!(windows.windows_written(), 2);
+//! ```
+
+use crate::{SpaaWriter, Weight, Window, WindowStackWeight, WriteResult, add_weights};
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Accumulates weights into fixed-width windows and writes each one when
+/// it is complete.
+///
+/// Bucket `n` covers `[n * width, (n + 1) * width)`. Buckets without
+/// weight are skipped. Timestamps must be mostly increasing: one that falls
+/// before the current bucket is counted in it, since earlier windows have
+/// already been written.
+#[derive(Debug, Clone)]
+pub struct WindowBuilder {
+    width: f64,
+    unit: String,
+    bucket: Option<i64>,
+    by_stack: Vec<WindowStackWeight>,
+    /// Index into `by_stack` by stack ID.
+    index: HashMap<String, usize>,
+    written: u64,
+}
+
+impl WindowBuilder {
+    /// A builder for windows `width` long, in `unit` (such as `seconds`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `width` is not positive and finite.
+    pub fn new(width: f64, unit: impl Into<String>) -> Self {
+        assert!(
+            width.is_finite() && width > 0.0,
+            "window width must be positive and finite, got {width}"
+        );
+        Self {
+            width,
+            unit: unit.into(),
+            bucket: None,
+            by_stack: Vec::new(),
+            index: HashMap::new(),
+            written: 0,
+        }
+    }
+
+    /// Add `weights` for `stack_id` at `timestamp`, first writing the
+    /// current window if `timestamp` is past its end.
+    pub fn add<W: Write>(
+        &mut self,
+        writer: &mut SpaaWriter<W>,
+        timestamp: f64,
+        stack_id: &str,
+        weights: &[Weight],
+    ) -> WriteResult<()> {
+        let bucket = (timestamp / self.width).floor() as i64;
+        match self.bucket {
+            Some(current) if bucket > current => {
+                self.flush(writer)?;
+                self.bucket = Some(bucket);
+            }
+            Some(_) => {}
+            None => self.bucket = Some(bucket),
+        }
+
+        let idx = *self.index.entry(stack_id.to_string()).or_insert_with(|| {
+            self.by_stack.push(WindowStackWeight {
+                stack_id: stack_id.to_string(),
+                weights: Vec::new(),
+            });
+            self.by_stack.len() - 1
+        });
+        add_weights(&mut self.by_stack[idx].weights, weights);
+        Ok(())
+    }
+
+    /// Write the current window, if it has any weight. Call once after the
+    /// last [`WindowBuilder::add`].
+    pub fn flush<W: Write>(&mut self, writer: &mut SpaaWriter<W>) -> WriteResult<()> {
+        let Some(bucket) = self.bucket.take() else {
+            return Ok(());
+        };
+        let mut by_stack = std::mem::take(&mut self.by_stack);
+        self.index.clear();
+        if by_stack.is_empty() {
+            return Ok(());
+        }
+        by_stack.sort_by(|a, b| a.stack_id.cmp(&b.stack_id));
+
+        self.written += 1;
+        let start = bucket as f64 * self.width;
+        writer.write_window(&Window {
+            id: format!("w{}", self.written),
+            start,
+            end: start + self.width,
+            unit: self.unit.clone(),
+            by_stack,
+        })
+    }
+
+    /// Number of windows written so far.
+    pub fn windows_written(&self) -> u64 {
+        self.written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(value: u64) -> Weight {
+        Weight {
+            metric: "samples".to_string(),
+            value,
+            unit: None,
+        }
+    }
+
+    fn windows(writer: SpaaWriter<Vec<u8>>) -> Vec<Window> {
+        String::from_utf8(writer.into_inner())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn each_bucket_is_written_once_a_later_timestamp_closes_it() {
+        let mut writer = SpaaWriter::new(Vec::new());
+        let mut builder = WindowBuilder::new(1.0, "seconds");
+        builder.add(&mut writer, 0.2, "0x2", &[samples(1)]).unwrap();
+        builder.add(&mut writer, 0.7, "0x1", &[samples(2)]).unwrap();
+        builder.add(&mut writer, 0.9, "0x2", &[samples(3)]).unwrap();
+        assert!(writer.get_ref().is_empty());
+
+        builder.add(&mut writer, 3.5, "0x1", &[samples(1)]).unwrap();
+        builder.flush(&mut writer).unwrap();
+        let windows = windows(writer);
+
+        assert_eq!(windows.len(), 2);
+        assert_eq!((windows[0].start, windows[0].end), (0.0, 1.0));
+        let first: Vec<_> = windows[0]
+            .by_stack
+            .iter()
+            .map(|s| (s.stack_id.as_str(), s.weights[0].value))
+            .collect();
+        assert_eq!(first, vec![("0x1", 2), ("0x2", 4)]);
+        assert_eq!((windows[1].id.as_str(), windows[1].start), ("w2", 3.0));
+    }
+
+    #[test]
+    fn late_timestamps_count_in_the_current_window() {
+        let mut writer = SpaaWriter::new(Vec::new());
+        let mut builder = WindowBuilder::new(1.0, "seconds");
+        builder.add(&mut writer, 1.5, "0x1", &[samples(1)]).unwrap();
+        builder.add(&mut writer, 0.5, "0x1", &[samples(1)]).unwrap();
+        builder.flush(&mut writer).unwrap();
+        let windows = windows(writer);
+
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].start, 1.0);
+        assert_eq!(windows[0].by_stack[0].weights[0].value, 2);
+    }
+}